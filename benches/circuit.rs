@@ -0,0 +1,314 @@
+//! Witness synthesis, keygen and proving of circuits hashing `n` two-element messages, the
+//! size of a Merkle node, under each form of the S-box of the main gate, under each round
+//! layout and with Poseidon2.
+//!
+//! Synthesis runs the `MockProver`, which assigns every row, and reports rows per second.
+//! Proofs are created at the smallest `k` that fits the hashes and at twice as many rows,
+//! to separate the cost of the hashes from the cost of the domain. The S-box forms share the
+//! rows of the narrow layout; the wide and folded layouts fit the same hashes in a smaller
+//! `k`, for more fixed columns, which the ids of the proving benchmarks show.
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use ff::Field;
+use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner},
+    dev::MockProver,
+    plonk::{Circuit, Column, ConstraintSystem, Error, Instance},
+    poly::kzg::commitment::ParamsKZG,
+};
+use halo2curves::bn256::{Bn256, Fr};
+use poseidon::Spec;
+use poseidon_circuit::{
+    main_gate::{GateLayout, MainGate, MainGateConfig, RegionCtx, RoundLayout},
+    poseidon2::{self, Poseidon2Spec},
+    poseidon2_circuit::{Poseidon2Chip, Poseidon2Config},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash,
+    prover::ProverContext,
+    specs::BN256_T4_R3,
+};
+use rand_core::OsRng;
+
+const T: usize = BN256_T4_R3.width;
+const RATE: usize = BN256_T4_R3.rate;
+/// Elements per message
+const LEN: usize = 2;
+/// Hashes per circuit
+const HASHES: [usize; 3] = [1, 4, 16];
+/// Rows reserved for blinding at the end of the circuit.
+const UNUSABLE_ROWS: usize = 6;
+/// Round layouts of [`PoseidonCircuit`], by index
+const LAYOUTS: [RoundLayout; 3] = [RoundLayout::Narrow, RoundLayout::Wide, RoundLayout::Folded];
+
+fn spec() -> Spec<Fr, T, RATE> {
+    Spec::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p)
+}
+
+fn messages(hashes: usize) -> Vec<Vec<Fr>> {
+    (0..hashes)
+        .map(|i| (0..LEN).map(|j| Fr::from((i * LEN + j) as u64)).collect())
+        .collect()
+}
+
+fn min_k(rows: usize) -> u32 {
+    (rows + UNUSABLE_ROWS).next_power_of_two().trailing_zeros()
+}
+
+/// A circuit of the benchmarks: its rows and public inputs for the messages it hashes.
+trait Bench: Circuit<Fr> + Sized {
+    const NAME: &'static str;
+
+    fn new(messages: Vec<Vec<Fr>>) -> Self;
+
+    fn rows(&self) -> usize;
+
+    fn instance(&self) -> Vec<Fr>;
+}
+
+#[derive(Clone, Debug)]
+struct HashesConfig<C> {
+    config: C,
+    instance: Column<Instance>,
+}
+
+/// The digests of the messages, hashed in one region by [`PoseidonChip::hash_many`] on a
+/// main gate of degree at most `MAX_DEGREE`, with the rounds laid out as `LAYOUTS[LAYOUT]`.
+struct PoseidonCircuit<const MAX_DEGREE: usize, const LAYOUT: usize = 0> {
+    messages: Vec<Vec<Fr>>,
+}
+
+impl<const MAX_DEGREE: usize, const LAYOUT: usize> PoseidonCircuit<MAX_DEGREE, LAYOUT> {
+    fn layout() -> GateLayout {
+        GateLayout::for_max_degree(MAX_DEGREE)
+            .unwrap()
+            .with_rounds(LAYOUTS[LAYOUT])
+    }
+}
+
+impl<const MAX_DEGREE: usize, const LAYOUT: usize> Circuit<Fr>
+    for PoseidonCircuit<MAX_DEGREE, LAYOUT>
+{
+    type Config = HashesConfig<MainGateConfig<T>>;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            messages: vec![vec![Fr::ZERO; LEN]; self.messages.len()],
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let instance = meta.instance_column();
+        meta.enable_equality(instance);
+        let layout = Self::layout();
+        let mut adv_cols = (0..layout.advice_columns(T))
+            .map(|_| meta.advice_column())
+            .collect::<Vec<_>>()
+            .into_iter();
+        let mut fix_cols = (0..layout.fixed_columns(T))
+            .map(|_| meta.fixed_column())
+            .collect::<Vec<_>>()
+            .into_iter();
+        let config = MainGate::configure_with_layout(meta, &mut adv_cols, &mut fix_cols, layout);
+        HashesConfig { config, instance }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let chip = PoseidonChip::new(config.config, spec());
+        let hashes = layouter.assign_region(
+            || "poseidon hashes",
+            |region| {
+                let ctx = &mut RegionCtx::new(region, 0);
+                chip.hash_many(ctx, &self.messages)
+            },
+        )?;
+        for (row, (_, digest)) in hashes.iter().enumerate() {
+            layouter.constrain_instance(digest.cell(), config.instance, row)?;
+        }
+        Ok(())
+    }
+}
+
+impl<const MAX_DEGREE: usize, const LAYOUT: usize> Bench for PoseidonCircuit<MAX_DEGREE, LAYOUT> {
+    const NAME: &'static str = match (MAX_DEGREE, LAYOUT) {
+        (_, 1) => "poseidon wide",
+        (_, 2) => "poseidon folded",
+        (3, _) => "poseidon staged",
+        (4 | 5, _) => "poseidon squared",
+        _ => "poseidon direct",
+    };
+
+    fn new(messages: Vec<Vec<Fr>>) -> Self {
+        Self { messages }
+    }
+
+    /// The wide layouts lay the first absorb round out as the narrow one, so they share the
+    /// same rows of it across the hashes, and save the same rows in every hash after it.
+    fn rows(&self) -> usize {
+        let spec = spec();
+        let saved = PoseidonChip::num_rows(&spec, LEN)
+            - PoseidonChip::num_rows_with_layout(&spec, LEN, LAYOUTS[LAYOUT]);
+        PoseidonChip::num_rows_many(&spec, &vec![LEN; self.messages.len()])
+            - self.messages.len() * saved
+    }
+
+    fn instance(&self) -> Vec<Fr> {
+        self.messages.iter().map(|m| hash(&spec(), m)).collect()
+    }
+}
+
+/// The digests of the messages, hashed one after the other by [`Poseidon2Chip`] of width 3.
+struct Poseidon2Circuit {
+    messages: Vec<Vec<Fr>>,
+}
+
+fn poseidon2_spec() -> Poseidon2Spec<Fr, 3> {
+    Poseidon2Spec::new(poseidon2::R_F, poseidon2::R_P)
+}
+
+impl Circuit<Fr> for Poseidon2Circuit {
+    type Config = HashesConfig<Poseidon2Config<3, 2>>;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            messages: vec![vec![Fr::ZERO; LEN]; self.messages.len()],
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let instance = meta.instance_column();
+        meta.enable_equality(instance);
+        let mut adv_cols = [(); 3 + 2].map(|_| meta.advice_column()).into_iter();
+        let mut fix_cols = [(); 3 + 1 + 2].map(|_| meta.fixed_column()).into_iter();
+        let config = Poseidon2Chip::configure(meta, &mut adv_cols, &mut fix_cols);
+        HashesConfig { config, instance }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let digests = layouter.assign_region(
+            || "poseidon2 hashes",
+            |region| {
+                let ctx = &mut RegionCtx::new(region, 0);
+                self.messages
+                    .iter()
+                    .map(|message| {
+                        let mut chip =
+                            Poseidon2Chip::<Fr, 3, 2>::new(config.config.clone(), poseidon2_spec());
+                        chip.update(message.clone());
+                        chip.squeeze(ctx)
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            },
+        )?;
+        for (row, digest) in digests.iter().enumerate() {
+            layouter.constrain_instance(digest.cell(), config.instance, row)?;
+        }
+        Ok(())
+    }
+}
+
+impl Bench for Poseidon2Circuit {
+    const NAME: &'static str = "poseidon2";
+
+    fn new(messages: Vec<Vec<Fr>>) -> Self {
+        Self { messages }
+    }
+
+    fn rows(&self) -> usize {
+        self.messages.len() * Poseidon2Chip::<Fr, 3, 2>::num_rows(&poseidon2_spec(), LEN)
+    }
+
+    fn instance(&self) -> Vec<Fr> {
+        let spec = poseidon2_spec();
+        self.messages
+            .iter()
+            .map(|m| poseidon2::hash(&spec, m))
+            .collect()
+    }
+}
+
+fn synthesis_of<C: Bench>(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("synthesis/{}", C::NAME));
+    for hashes in HASHES {
+        let circuit = C::new(messages(hashes));
+        let k = min_k(circuit.rows());
+        let instance = circuit.instance();
+        group.throughput(Throughput::Elements(circuit.rows() as u64));
+        group.bench_function(BenchmarkId::from_parameter(hashes), |b| {
+            b.iter(|| MockProver::run(k, black_box(&circuit), vec![instance.clone()]).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn keygen_of<C: Bench>(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("keygen/{}", C::NAME));
+    group.sample_size(10);
+    for hashes in HASHES {
+        let circuit = C::new(messages(hashes));
+        let k = min_k(circuit.rows());
+        let params = ParamsKZG::<Bn256>::setup(k, OsRng);
+        group.bench_function(BenchmarkId::new(format!("k={k}"), hashes), |b| {
+            b.iter_batched(
+                || params.clone(),
+                |params| ProverContext::new(params, &circuit).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn prove_of<C: Bench>(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("prove/{}", C::NAME));
+    group.sample_size(10);
+    for hashes in HASHES {
+        let circuit = C::new(messages(hashes));
+        let instance = circuit.instance();
+        let min_k = min_k(circuit.rows());
+        for k in [min_k, min_k + 1] {
+            let ctx = ProverContext::setup(k, &circuit).unwrap();
+            group.bench_function(BenchmarkId::new(format!("k={k}"), hashes), |b| {
+                b.iter(|| ctx.prove(&circuit, &[instance.as_slice()]).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn synthesis(c: &mut Criterion) {
+    synthesis_of::<PoseidonCircuit<6>>(c);
+    synthesis_of::<PoseidonCircuit<4>>(c);
+    synthesis_of::<PoseidonCircuit<3>>(c);
+    synthesis_of::<PoseidonCircuit<6, 1>>(c);
+    synthesis_of::<PoseidonCircuit<6, 2>>(c);
+    synthesis_of::<Poseidon2Circuit>(c);
+}
+
+fn keygen(c: &mut Criterion) {
+    keygen_of::<PoseidonCircuit<6>>(c);
+    keygen_of::<PoseidonCircuit<6, 2>>(c);
+    keygen_of::<Poseidon2Circuit>(c);
+}
+
+fn prove(c: &mut Criterion) {
+    prove_of::<PoseidonCircuit<6>>(c);
+    prove_of::<PoseidonCircuit<4>>(c);
+    prove_of::<PoseidonCircuit<3>>(c);
+    prove_of::<PoseidonCircuit<6, 1>>(c);
+    prove_of::<PoseidonCircuit<6, 2>>(c);
+    prove_of::<Poseidon2Circuit>(c);
+}
+
+criterion_group!(benches, synthesis, keygen, prove);
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+//! Native throughput: permutations per second of Poseidon at the widths the service and the
+//! Merkle gadgets use and of Poseidon2, and elements per second of the sponge.
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use halo2curves::bn256::Fr;
+use poseidon::Spec;
+use poseidon_circuit::{
+    poseidon2::{self, Poseidon2Spec},
+    poseidon_hash::hash,
+    specs::BN256_T4_R3,
+};
+use poseidon_circuit_native::Spec as NativeSpec;
+
+fn state<const T: usize>() -> [Fr; T] {
+    [(); T].map(|_| Fr::from(7))
+}
+
+fn permutation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("permutation");
+    group.throughput(Throughput::Elements(1));
+
+    let spec = NativeSpec::<Fr, 3, 2>::new(8, 57);
+    group.bench_function("poseidon t=3", |b| {
+        let mut state = state::<3>();
+        b.iter(|| spec.permute(black_box(&mut state)))
+    });
+    let spec = NativeSpec::<Fr, 4, 3>::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p);
+    group.bench_function("poseidon t=4", |b| {
+        let mut state = state::<4>();
+        b.iter(|| spec.permute(black_box(&mut state)))
+    });
+    let spec = Poseidon2Spec::<Fr, 3>::new(poseidon2::R_F, poseidon2::R_P);
+    group.bench_function("poseidon2 t=3", |b| {
+        let mut state = state::<3>();
+        b.iter(|| spec.permute(black_box(&mut state)))
+    });
+    group.finish();
+}
+
+fn sponge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hash");
+    let spec = Spec::<Fr, 4, 3>::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p);
+    for len in [1, 8, 64] {
+        let message = (0..len).map(Fr::from).collect::<Vec<_>>();
+        group.throughput(Throughput::Elements(len));
+        group.bench_with_input(BenchmarkId::new("poseidon t=4", len), &message, |b, m| {
+            b.iter(|| hash(&spec, black_box(m)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, permutation, sponge);
+criterion_main!(benches);
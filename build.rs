@@ -0,0 +1,5 @@
+fn main() {
+    // the service and messages of `grpc`, from `proto/prover.proto`
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/prover.proto").expect("proto/prover.proto compiles");
+}
@@ -0,0 +1,50 @@
+#![no_main]
+
+use std::sync::OnceLock;
+
+use base64::{engine::general_purpose::STANDARD as BS64, Engine};
+use libfuzzer_sys::fuzz_target;
+use poseidon_circuit::{
+    state::ProverState,
+    task::{ProofDetail, ProofType, Task},
+};
+
+/// A prover and a valid preimage proof, built once per run.
+fn proven() -> &'static (ProverState, ProofDetail, Vec<u8>) {
+    static PROVEN: OnceLock<(ProverState, ProofDetail, Vec<u8>)> = OnceLock::new();
+    PROVEN.get_or_init(|| {
+        let state = ProverState::new(10).expect("test params");
+        let task = Task {
+            id: "fuzz".to_string(),
+            task_type: ProofType::Preimage,
+            task_data: "[42]".to_string(),
+            ..Default::default()
+        };
+        let proof = state.prove(&task).expect("the preimage task proves");
+        let detail = proof.detail(&task);
+        (state, detail, proof.proof)
+    })
+}
+
+// Proofs come from clients: the verifier rejects any corruption of a valid proof with an
+// error. The first two bytes give the length the proof is cut or padded to, the rest is
+// xored into it.
+fuzz_target!(|data: &[u8]| {
+    let (state, detail, valid) = proven();
+    let Some((len, masks)) = data.split_first_chunk::<2>() else {
+        return;
+    };
+    let mut proof = valid.clone();
+    proof.resize(u16::from_le_bytes(*len) as usize, 0);
+    for (byte, mask) in proof.iter_mut().zip(masks) {
+        *byte ^= mask;
+    }
+    let corrupted = ProofDetail {
+        proof_data: BS64.encode(&proof),
+        ..detail.clone()
+    };
+    let verified = state.verify(&corrupted);
+    if proof != *valid {
+        assert!(verified.is_err(), "a corrupted proof verified");
+    }
+});
@@ -0,0 +1,34 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use poseidon_circuit::{
+    field_encoding::to_canonical,
+    halo2curves::bn256::Fr,
+    preprocess::Preprocessors,
+    task::Task,
+    task_data::{parse_array, HashWitness},
+};
+
+// task_data is untrusted: every reader either returns the elements or an error, and a
+// message read as JSON reads the same once written back in canonical form.
+fuzz_target!(|data: &[u8]| {
+    let Ok(task_data) = std::str::from_utf8(data) else {
+        return;
+    };
+    let _ = parse_array::<Fr>(task_data);
+    if let Ok(witness) = HashWitness::<Fr>::parse(task_data) {
+        let inputs = witness.inputs.iter().map(to_canonical).collect::<Vec<_>>();
+        let encoded = serde_json::to_string(&inputs).expect("strings serialize");
+        let decoded = HashWitness::<Fr>::parse(&encoded).expect("canonical elements parse");
+        assert_eq!(decoded.inputs, witness.inputs);
+    }
+    let preprocessors = Preprocessors::default();
+    for name in preprocessors.names() {
+        let task = Task {
+            task_data: task_data.to_string(),
+            preprocessor: Some(name.to_string()),
+            ..Default::default()
+        };
+        let _ = preprocessors.preprocess(&task);
+    }
+});
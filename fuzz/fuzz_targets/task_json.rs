@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use poseidon_circuit::task::Task;
+
+// Queue payloads are untrusted: they must either parse within the limits or be
+// rejected with an error, and whatever parses must survive a round trip.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(task) = Task::from_json(data) {
+        let encoded = serde_json::to_vec(&task).expect("a parsed task always serializes");
+        let decoded = Task::from_json(&encoded).expect("a re-encoded task always parses");
+        assert_eq!(decoded, task);
+    }
+});
@@ -0,0 +1,87 @@
+//! The Grain LFSR the Poseidon reference scripts derive round constants from.
+//!
+//! The PSE spec draws its constants from a private copy of the same generator; this one
+//! derives those of [`crate::Spec`], and serves the permutations whose constants the PSE
+//! crate does not provide.
+use alloc::collections::VecDeque;
+
+use ff::{FromUniformBytes, PrimeField};
+
+/// A self-shrinking generator seeded with the field size, width and rounds of an instance.
+pub struct Grain {
+    bits: VecDeque<bool>,
+}
+
+impl Grain {
+    pub fn new(field_bits: u32, t: usize, r_f: usize, r_p: usize) -> Self {
+        fn append(bits: &mut VecDeque<bool>, value: u64, width: usize) {
+            bits.extend((0..width).rev().map(|i| value >> i & 1 == 1));
+        }
+        let mut bits = VecDeque::with_capacity(80);
+        // a prime field and an x^alpha S-box
+        append(&mut bits, 1, 2);
+        append(&mut bits, 0, 4);
+        append(&mut bits, field_bits.into(), 12);
+        append(&mut bits, t as u64, 12);
+        append(&mut bits, r_f as u64, 10);
+        append(&mut bits, r_p as u64, 10);
+        append(&mut bits, (1 << 30) - 1, 30);
+        let mut grain = Self { bits };
+        for _ in 0..160 {
+            grain.step();
+        }
+        grain
+    }
+
+    fn step(&mut self) -> bool {
+        let b = &self.bits;
+        let bit = b[62] ^ b[51] ^ b[38] ^ b[23] ^ b[13] ^ b[0];
+        self.bits.pop_front();
+        self.bits.push_back(bit);
+        bit
+    }
+
+    /// Outputs the second bit of every pair whose first bit is set.
+    fn next_bit(&mut self) -> bool {
+        loop {
+            let keep = self.step();
+            let bit = self.step();
+            if keep {
+                return bit;
+            }
+        }
+    }
+
+    /// The next `NUM_BITS` bits, most significant first, that are smaller than the modulus.
+    ///
+    /// Assumes a little-endian representation, as the PSE poseidon crate does.
+    pub fn next_element<F: PrimeField>(&mut self) -> F {
+        loop {
+            let mut repr = F::Repr::default();
+            for i in (0..F::NUM_BITS as usize).rev() {
+                if self.next_bit() {
+                    repr.as_mut()[i / 8] |= 1 << (i % 8);
+                }
+            }
+            if let Some(element) = Option::from(F::from_repr(repr)) {
+                return element;
+            }
+        }
+    }
+
+    pub fn next_elements<F: PrimeField, const T: usize>(&mut self) -> [F; T] {
+        [(); T].map(|_| self.next_element())
+    }
+
+    /// The next `NUM_BITS` bits, most significant first, reduced modulo the field; the
+    /// reference scripts sample the MDS matrix this way.
+    pub fn next_element_reduced<F: PrimeField + FromUniformBytes<64>>(&mut self) -> F {
+        let mut bytes = [0u8; 64];
+        for i in (0..F::NUM_BITS as usize).rev() {
+            if self.next_bit() {
+                bytes[i / 8] |= 1 << (i % 8);
+            }
+        }
+        F::from_uniform_bytes(&bytes)
+    }
+}
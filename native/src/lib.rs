@@ -0,0 +1,146 @@
+//! The native Poseidon permutation, its constants and the sponge of `poseidon_circuit`, for
+//! targets without `std`, such as embedded devices and zkVM guests.
+//!
+//! Digests are those of `poseidon_circuit::poseidon_hash` and of the circuits: [`Spec::new`]
+//! derives the round constants and the MDS matrix from [`grain::Grain`] as the PSE spec does,
+//! and [`hash_with_capacity`] absorbs and pads like the chip. The permutation runs as the
+//! paper writes it rather than in the optimized form of the PSE spec, which takes the same
+//! rounds to the same output with fewer multiplications; that form needs matrix inversions
+//! this crate leaves to the prover.
+//!
+//! Only `alloc` and the traits of `ff` are needed; fields come from the caller.
+#![no_std]
+
+extern crate alloc;
+
+pub mod grain;
+
+use alloc::vec::Vec;
+
+use ff::{FromUniformBytes, PrimeField};
+
+use crate::grain::Grain;
+
+/// The round constants and MDS matrix of a permutation of width `T` absorbing `RATE`
+/// elements.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Spec<F, const T: usize, const RATE: usize> {
+    /// Added to the state at the start of every round
+    round_constants: Vec<[F; T]>,
+    mds: [[F; T]; T],
+    r_f: usize,
+    r_p: usize,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> Spec<F, T, RATE> {
+    /// The spec of `r_f` full and `r_p` partial rounds, with the constants of
+    /// `poseidon::Spec::new(r_f, r_p)`.
+    pub fn new(r_f: usize, r_p: usize) -> Self {
+        assert!(T == RATE + 1, "the sponge has a capacity of one element");
+        assert!(
+            r_f % 2 == 0,
+            "full rounds are split around the partial ones"
+        );
+        let mut grain = Grain::new(F::NUM_BITS, T, r_f, r_p);
+        let round_constants = (0..r_f + r_p).map(|_| grain.next_elements()).collect();
+        // a Cauchy matrix `1 / (x_i + y_j)` of the elements drawn after the constants
+        let xs: [F; T] = [(); T].map(|_| grain.next_element_reduced());
+        let ys: [F; T] = [(); T].map(|_| grain.next_element_reduced());
+        let mds = xs.map(|x| {
+            ys.map(|y| {
+                Option::from((x + y).invert()).expect("the reference scripts draw x_i + y_j != 0")
+            })
+        });
+        Self {
+            round_constants,
+            mds,
+            r_f,
+            r_p,
+        }
+    }
+}
+
+impl<F: PrimeField, const T: usize, const RATE: usize> Spec<F, T, RATE> {
+    /// Full rounds, half of them before the partial rounds and half after.
+    pub fn r_f(&self) -> usize {
+        self.r_f
+    }
+
+    pub fn r_p(&self) -> usize {
+        self.r_p
+    }
+
+    pub fn round_constants(&self) -> &[[F; T]] {
+        &self.round_constants
+    }
+
+    pub fn mds(&self) -> &[[F; T]; T] {
+        &self.mds
+    }
+
+    /// Permutes `state`: every round adds its constants, raises the whole state to the fifth
+    /// power in full rounds and its first element in partial ones, and multiplies by the
+    /// MDS matrix.
+    pub fn permute(&self, state: &mut [F; T]) {
+        let pow5 = |v: F| v.square().square() * v;
+        let partial = self.r_f / 2..self.r_f / 2 + self.r_p;
+        for (round, constants) in self.round_constants.iter().enumerate() {
+            for (s, c) in state.iter_mut().zip(constants) {
+                *s += c;
+            }
+            if partial.contains(&round) {
+                state[0] = pow5(state[0]);
+            } else {
+                *state = state.map(pow5);
+            }
+            *state = self.mds.map(|row| {
+                row.iter()
+                    .zip(state.iter())
+                    .fold(F::ZERO, |acc, (m, s)| acc + *m * s)
+            });
+        }
+    }
+}
+
+/// The capacity the PSE sponge starts from, `2^64`, that of `Domain::Pse` in
+/// `poseidon_circuit`.
+pub fn pse_capacity<F: PrimeField>() -> F {
+    F::from_u128(1 << 64)
+}
+
+/// Hashes `inputs` as `poseidon_circuit::poseidon_hash::hash` does.
+pub fn hash<F: PrimeField, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    inputs: &[F],
+) -> F {
+    hash_with_capacity(spec, pse_capacity(), inputs)
+}
+
+/// Hashes `inputs` from `capacity`, the `Domain::capacity` of a domain of `poseidon_circuit`:
+/// every `RATE` elements are added to the rate part of the state, the last chunk padded with
+/// a one, and permuted; a message filling its last chunk is followed by a chunk of padding.
+/// The digest is the first rate element.
+pub fn hash_with_capacity<F: PrimeField, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    capacity: F,
+    inputs: &[F],
+) -> F {
+    let mut state = [F::ZERO; T];
+    state[0] = capacity;
+    let mut absorb = |chunk: &[F]| {
+        for (s, input) in state[1..].iter_mut().zip(chunk) {
+            *s += input;
+        }
+        if chunk.len() < RATE {
+            state[1 + chunk.len()] += F::ONE;
+        }
+        spec.permute(&mut state);
+    };
+    for chunk in inputs.chunks(RATE) {
+        absorb(chunk);
+    }
+    if inputs.len() % RATE == 0 {
+        absorb(&[]);
+    }
+    state[1]
+}
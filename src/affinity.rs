@@ -0,0 +1,360 @@
+//! Placement of prover threads on cores and NUMA nodes.
+//!
+//! Proving is memory bound: on a dual-socket machine, threads migrating across sockets read
+//! the proving key and the polynomials from the other node's memory, which measurably slows
+//! every proof down. A [`Placement`] restricts a rayon pool to a set of CPUs, e.g. those of
+//! one node, and optionally pins every thread of the pool to a single core of the set.
+//! Allocations are served from the node of the thread that first touches them, so pinned
+//! threads keep their data local without an explicit memory policy.
+//!
+//! halo2 parallelizes over the global rayon pool; [`Placement::build_global`] configures it
+//! for a process running one worker, [`Placement::build`] gives every worker of a process
+//! its own pool to `install` its proofs into.
+use std::{fmt, fs, io, str::FromStr};
+
+/// Largest CPU number [`CpuSet`] accepts, that of the kernel's default `cpu_set_t`.
+pub const MAX_CPUS: usize = 1024;
+
+/// An ordered set of CPU numbers, written as a Linux cpu list such as `0-3,8,10-11`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CpuSet(Vec<usize>);
+
+impl CpuSet {
+    pub fn cpus(&self) -> &[usize] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The CPUs of NUMA node `node`, from sysfs.
+    pub fn of_node(node: usize) -> Result<Self, AffinityError> {
+        let path = format!("/sys/devices/system/node/node{node}/cpulist");
+        let list = fs::read_to_string(path).map_err(|err| AffinityError::Node { node, err })?;
+        list.trim().parse()
+    }
+
+    /// The CPUs the calling thread may run on.
+    pub fn allowed() -> io::Result<Self> {
+        sys::get_affinity().map(Self)
+    }
+}
+
+impl FromStr for CpuSet {
+    type Err = AffinityError;
+
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        let invalid = || AffinityError::InvalidCpuList(list.to_string());
+        let cpu = |s: &str| {
+            s.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|cpu| *cpu < MAX_CPUS)
+                .ok_or_else(invalid)
+        };
+        let mut cpus = Vec::new();
+        for range in list.split(',') {
+            match range.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (cpu(first)?, cpu(last)?);
+                    if first > last {
+                        return Err(invalid());
+                    }
+                    cpus.extend(first..=last);
+                }
+                None => cpus.push(cpu(range)?),
+            }
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(Self(cpus))
+    }
+}
+
+impl fmt::Display for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut cpus = self.0.iter().copied().peekable();
+        let mut first = true;
+        while let Some(start) = cpus.next() {
+            let mut end = start;
+            while cpus.peek() == Some(&(end + 1)) {
+                end = cpus.next().expect("peeked");
+            }
+            if !first {
+                write!(f, ",")?;
+            }
+            first = false;
+            if start == end {
+                write!(f, "{start}")?;
+            } else {
+                write!(f, "{start}-{end}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How the threads of a pool are bound to the CPUs of a [`Placement`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pinning {
+    /// Every thread may run on any CPU of the set; the scheduler balances within it
+    #[default]
+    Set,
+    /// Thread `i` runs on CPU `i` of the set only, wrapping around
+    Core,
+}
+
+impl FromStr for Pinning {
+    type Err = AffinityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "set" => Ok(Self::Set),
+            "core" => Ok(Self::Core),
+            _ => Err(AffinityError::InvalidPinning(s.to_string())),
+        }
+    }
+}
+
+/// Where the threads of a prover pool run, and how many there are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    pub cpus: CpuSet,
+    /// Defaults to one thread per CPU of the set
+    pub threads: Option<usize>,
+    pub pinning: Pinning,
+}
+
+#[derive(Debug)]
+pub enum AffinityError {
+    InvalidCpuList(String),
+    InvalidPinning(String),
+    /// The CPUs of a NUMA node cannot be read, e.g. because the node does not exist
+    Node {
+        node: usize,
+        err: io::Error,
+    },
+    /// The placement names CPUs this process may not run on, e.g. outside its cgroup
+    Unavailable(CpuSet),
+    Io(io::Error),
+    Pool(rayon::ThreadPoolBuildError),
+}
+
+impl fmt::Display for AffinityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCpuList(list) => write!(f, "invalid cpu list {list:?}"),
+            Self::InvalidPinning(pinning) => {
+                write!(f, "invalid pinning {pinning:?}, expected set or core")
+            }
+            Self::Node { node, err } => write!(f, "cannot read the cpus of node {node}: {err}"),
+            Self::Unavailable(cpus) => write!(f, "cpus {cpus} are not available to the process"),
+            Self::Io(err) => write!(f, "cannot set the cpu affinity: {err}"),
+            Self::Pool(err) => write!(f, "cannot build the thread pool: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for AffinityError {}
+
+impl Placement {
+    /// All threads on the CPUs of `cpus`, one per CPU.
+    pub fn new(cpus: CpuSet) -> Self {
+        Self {
+            cpus,
+            threads: None,
+            pinning: Pinning::Set,
+        }
+    }
+
+    /// Parses `spec`, a cpu list or `node:<n>` for the CPUs of a NUMA node.
+    pub fn parse(spec: &str) -> Result<Self, AffinityError> {
+        let cpus = match spec.strip_prefix("node:") {
+            Some(node) => {
+                let node = node
+                    .parse()
+                    .map_err(|_| AffinityError::InvalidCpuList(spec.to_string()))?;
+                CpuSet::of_node(node)?
+            }
+            None => spec.parse()?,
+        };
+        Ok(Self::new(cpus))
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    pub fn with_pinning(mut self, pinning: Pinning) -> Self {
+        self.pinning = pinning;
+        self
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.threads.unwrap_or(self.cpus.len()).max(1)
+    }
+
+    /// The CPUs thread `index` of the pool may run on.
+    pub fn cpus_of_thread(&self, index: usize) -> Vec<usize> {
+        match self.pinning {
+            Pinning::Set => self.cpus.cpus().to_vec(),
+            Pinning::Core => vec![self.cpus.cpus()[index % self.cpus.len()]],
+        }
+    }
+
+    /// Checks that the process may run on every CPU of the placement.
+    fn check(&self) -> Result<(), AffinityError> {
+        if self.cpus.is_empty() {
+            return Err(AffinityError::InvalidCpuList(String::new()));
+        }
+        let allowed = CpuSet::allowed().map_err(AffinityError::Io)?;
+        let missing = self
+            .cpus
+            .cpus()
+            .iter()
+            .filter(|cpu| !allowed.cpus().contains(cpu))
+            .copied()
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(AffinityError::Unavailable(CpuSet(missing)))
+        }
+    }
+
+    fn builder(&self) -> Result<rayon::ThreadPoolBuilder, AffinityError> {
+        self.check()?;
+        let placement = self.clone();
+        Ok(rayon::ThreadPoolBuilder::new()
+            .num_threads(self.num_threads())
+            .start_handler(move |index| {
+                // the CPUs were checked above; a failure leaves the thread unpinned
+                let _ = sys::set_affinity(&placement.cpus_of_thread(index));
+            }))
+    }
+
+    /// A pool of its own, for one of several workers in a process.
+    pub fn build(&self) -> Result<rayon::ThreadPool, AffinityError> {
+        self.builder()?.build().map_err(AffinityError::Pool)
+    }
+
+    /// Configures the global pool, which halo2 and every `par_iter` of the crate run on.
+    ///
+    /// Only possible before anything used the global pool; the caller's thread is
+    /// restricted to the set too, so the work it does itself stays on the node.
+    pub fn build_global(&self) -> Result<(), AffinityError> {
+        self.builder()?
+            .build_global()
+            .map_err(AffinityError::Pool)?;
+        sys::set_affinity(self.cpus.cpus()).map_err(AffinityError::Io)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::{io, mem};
+
+    pub fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+        // SAFETY: cpu_set_t is a plain bitmask, CPU_SET is given numbers below MAX_CPUS
+        // and the set outlives the call reading it
+        unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            for &cpu in cpus {
+                libc::CPU_SET(cpu, &mut set);
+            }
+            if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_affinity() -> io::Result<Vec<usize>> {
+        // SAFETY: as above, the kernel writes at most size_of::<cpu_set_t>() bytes
+        unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok((0..super::MAX_CPUS)
+                .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+                .collect())
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+
+    pub fn set_affinity(_cpus: &[usize]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "cpu affinity is only supported on linux",
+        ))
+    }
+
+    pub fn get_affinity() -> io::Result<Vec<usize>> {
+        set_affinity(&[]).map(|()| Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_list() {
+        let set = "8,0-3,2,10-11".parse::<CpuSet>().unwrap();
+        assert_eq!(set.cpus(), [0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(set.to_string(), "0-3,8,10-11");
+        for list in ["", "3-1", "a", "0-", "1,,2", &MAX_CPUS.to_string()] {
+            assert!(list.parse::<CpuSet>().is_err(), "{list:?}");
+        }
+        assert!(matches!(
+            Placement::parse("node:x"),
+            Err(AffinityError::InvalidCpuList(_))
+        ));
+    }
+
+    #[test]
+    fn test_thread_cpus() {
+        let placement = Placement::parse("4-5").unwrap().with_threads(3);
+        assert_eq!(placement.num_threads(), 3);
+        assert_eq!(placement.cpus_of_thread(2), [4, 5]);
+        let placement = placement.with_pinning("core".parse().unwrap());
+        assert_eq!(
+            (0..3)
+                .map(|i| placement.cpus_of_thread(i))
+                .collect::<Vec<_>>(),
+            [[4], [5], [4]]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pinned_pool() {
+        let allowed = CpuSet::allowed().unwrap();
+        let first = allowed.cpus()[0];
+        let placement = Placement::new(CpuSet(vec![first]))
+            .with_threads(2)
+            .with_pinning(Pinning::Core);
+        let pool = placement.build().unwrap();
+        let pinned = pool.broadcast(|_| CpuSet::allowed().unwrap());
+        assert!(pinned.iter().all(|set| set.cpus() == [first]));
+
+        let unavailable = Placement::new(CpuSet(vec![MAX_CPUS - 1]));
+        if !allowed.cpus().contains(&(MAX_CPUS - 1)) {
+            assert!(matches!(
+                unavailable.build(),
+                Err(AffinityError::Unavailable(_))
+            ));
+        }
+    }
+}
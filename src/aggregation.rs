@@ -0,0 +1,569 @@
+//! Aggregation of chunk proofs into a single batch proof, with the halo2 loader of
+//! snark-verifier.
+//!
+//! A chunk proof to aggregate is created in snark-verifier's Poseidon transcript instead of
+//! Blake2b, see [`prove_chunk`], since replaying that transcript in a circuit is cheap. The
+//! [`AggregationCircuit`] of `n` chunks verifies every chunk proof up to its final pairing,
+//! folds the pairings into one KZG accumulator and exposes the accumulator, as
+//! [`ACCUMULATOR_INSTANCES`] limbs, followed by the public inputs of every chunk in order.
+//!
+//! A batch proof is valid when the aggregation proof verifies and its accumulator passes the
+//! pairing check on the params of the chunks; [`Aggregator::verify`] does both. The
+//! aggregation circuit needs params of about `2^21` rows or more, depending on `n`.
+//!
+//! Chunk contexts with a salt or protocol label are not supported: their transcripts absorb
+//! both ahead of the verifying key, which the aggregation circuit does not replay.
+use std::{
+    collections::HashMap,
+    fmt,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use halo2_proofs::{
+    arithmetic::CurveAffine,
+    circuit::{Layouter, SimpleFloorPlanner, Value},
+    plonk::{create_proof, verify_proof, Circuit, ConstraintSystem, Error, VerifyingKey},
+    poly::kzg::{
+        commitment::{KZGCommitmentScheme, ParamsKZG},
+        multiopen::{ProverGWC, VerifierGWC},
+        strategy::SingleStrategy,
+    },
+    transcript::{TranscriptReadBuffer, TranscriptWriterBuffer},
+};
+use halo2curves::bn256::{Bn256, Fq, Fr, G1Affine};
+use rand_core::OsRng;
+use snark_verifier::{
+    loader::{
+        self,
+        halo2::halo2_wrong_ecc::{
+            self,
+            integer::rns::Rns,
+            maingate::{
+                MainGate, MainGateConfig, MainGateInstructions, RangeChip, RangeConfig,
+                RangeInstructions, RegionCtx,
+            },
+            EccConfig,
+        },
+        native::NativeLoader,
+    },
+    pcs::{
+        kzg::{
+            Gwc19, KzgAccumulator, KzgAs, KzgDecidingKey, KzgSuccinctVerifyingKey, LimbsEncoding,
+        },
+        AccumulationDecider, AccumulationScheme, AccumulationSchemeProver,
+    },
+    system::halo2::{compile, transcript::halo2, Config},
+    util::arithmetic::{fe_from_limbs, fe_to_limbs},
+    verifier::{
+        plonk::{self, PlonkProtocol},
+        SnarkVerifier,
+    },
+};
+
+use crate::prover::{ProverContext, TranscriptHash};
+
+/// Limbs of a base field coordinate of the accumulator
+pub const LIMBS: usize = 4;
+/// Bits per limb
+pub const BITS: usize = 68;
+/// Public inputs of the accumulator: the coordinates of both of its points, as limbs
+pub const ACCUMULATOR_INSTANCES: usize = 4 * LIMBS;
+
+pub(crate) type As = KzgAs<Bn256, Gwc19>;
+pub(crate) type SuccinctVerifier = plonk::PlonkSuccinctVerifier<As, LimbsEncoding<LIMBS, BITS>>;
+pub(crate) type Svk = KzgSuccinctVerifyingKey<G1Affine>;
+pub(crate) type BaseFieldEccChip = halo2_wrong_ecc::BaseFieldEccChip<G1Affine, LIMBS, BITS>;
+pub(crate) type Halo2Loader<'a> = loader::halo2::Halo2Loader<'a, G1Affine, BaseFieldEccChip>;
+/// The transcript of chunk proofs, with the Poseidon parameters of snark-verifier's chip
+pub(crate) type PoseidonTranscript<L, S> = halo2::PoseidonTranscript<G1Affine, L, S, 5, 4, 8, 60>;
+
+#[derive(Debug)]
+pub enum AggregationError {
+    /// The chunk context absorbs a salt or protocol label the circuit does not replay
+    Unsupported(&'static str),
+    /// A chunk proof does not verify against the chunk key
+    InvalidChunk {
+        index: usize,
+        reason: String,
+    },
+    /// The instances of a batch proof are too few or do not split into chunks
+    Instances(usize),
+    /// The accumulator of a batch proof fails the pairing check
+    Accumulator,
+    Plonk(Error),
+}
+
+impl fmt::Display for AggregationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported(what) => write!(f, "aggregation does not support a {what}"),
+            Self::InvalidChunk { index, reason } => {
+                write!(f, "chunk proof {index} does not verify: {reason}")
+            }
+            Self::Instances(len) => write!(f, "{len} instances are not those of a batch proof"),
+            Self::Accumulator => write!(f, "the accumulator fails the pairing check"),
+            Self::Plonk(err) => write!(f, "{err:?}"),
+        }
+    }
+}
+
+impl std::error::Error for AggregationError {}
+
+impl From<Error> for AggregationError {
+    fn from(err: Error) -> Self {
+        Self::Plonk(err)
+    }
+}
+
+fn check_plain<C: Circuit<Fr>>(ctx: &ProverContext<C>) -> Result<(), AggregationError> {
+    if ctx.salt().is_some() {
+        return Err(AggregationError::Unsupported("deployment salt"));
+    }
+    if ctx.protocol_label().is_some() {
+        return Err(AggregationError::Unsupported("protocol label"));
+    }
+    Ok(())
+}
+
+/// A chunk proof in the transcript of [`prove_chunk`] and its public inputs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkSnark {
+    pub instances: Vec<Fr>,
+    pub proof: Vec<u8>,
+}
+
+/// Proves `circuit` in the transcript the aggregation circuit replays.
+pub fn prove_chunk<C: Circuit<Fr>>(
+    ctx: &ProverContext<C>,
+    circuit: &C,
+    instances: &[Fr],
+) -> Result<ChunkSnark, AggregationError> {
+    check_plain(ctx)?;
+    let mut transcript = PoseidonTranscript::<NativeLoader, _>::init(Vec::new());
+    create_proof::<KZGCommitmentScheme<_>, ProverGWC<'_, _>, _, _, _, _>(
+        ctx.params(),
+        ctx.pk(),
+        std::slice::from_ref(circuit),
+        &[&[instances]],
+        OsRng,
+        &mut transcript,
+    )?;
+    Ok(ChunkSnark {
+        instances: instances.to_vec(),
+        proof: transcript.finalize(),
+    })
+}
+
+pub(crate) fn verify_chunk(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    chunk: &ChunkSnark,
+) -> Result<(), Error> {
+    let mut transcript = PoseidonTranscript::<NativeLoader, _>::init(chunk.proof.as_slice());
+    verify_proof::<KZGCommitmentScheme<Bn256>, VerifierGWC<'_, Bn256>, _, _, _>(
+        params,
+        vk,
+        SingleStrategy::new(params),
+        &[&[&chunk.instances]],
+        &mut transcript,
+    )?;
+    Ok(())
+}
+
+#[derive(Clone)]
+pub(crate) struct SnarkWitness {
+    pub(crate) protocol: PlonkProtocol<G1Affine>,
+    pub(crate) instances: Vec<Vec<Value<Fr>>>,
+    pub(crate) proof: Value<Vec<u8>>,
+}
+
+impl SnarkWitness {
+    /// The witness of `chunk`, proven with the key `protocol` was compiled from.
+    pub(crate) fn new(protocol: &PlonkProtocol<G1Affine>, chunk: &ChunkSnark) -> Self {
+        Self {
+            protocol: protocol.clone(),
+            instances: vec![chunk.instances.iter().copied().map(Value::known).collect()],
+            proof: Value::known(chunk.proof.clone()),
+        }
+    }
+
+    pub(crate) fn without_witnesses(&self) -> Self {
+        Self {
+            protocol: self.protocol.clone(),
+            instances: self
+                .instances
+                .iter()
+                .map(|column| vec![Value::unknown(); column.len()])
+                .collect(),
+            proof: Value::unknown(),
+        }
+    }
+
+    pub(crate) fn proof(&self) -> Value<&[u8]> {
+        self.proof.as_ref().map(Vec::as_slice)
+    }
+}
+
+/// Verifies `snarks` up to their pairings in the circuit and folds them with `as_proof`;
+/// returns the accumulator and the cells of the public inputs of every snark.
+fn aggregate<'a>(
+    svk: &Svk,
+    loader: &Rc<Halo2Loader<'a>>,
+    snarks: &[SnarkWitness],
+    as_proof: Value<&'_ [u8]>,
+) -> Result<
+    (
+        KzgAccumulator<G1Affine, Rc<Halo2Loader<'a>>>,
+        Vec<loader::halo2::Scalar<'a, G1Affine, BaseFieldEccChip>>,
+    ),
+    Error,
+> {
+    let mut accumulators = Vec::new();
+    let mut public = Vec::new();
+    for snark in snarks {
+        let protocol = snark.protocol.loaded(loader);
+        let instances = snark
+            .instances
+            .iter()
+            .map(|column| {
+                column
+                    .iter()
+                    .map(|value| loader.assign_scalar(*value))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let mut transcript = PoseidonTranscript::<Rc<Halo2Loader>, _>::new(loader, snark.proof());
+        let proof = SuccinctVerifier::read_proof(svk, &protocol, &instances, &mut transcript)
+            .map_err(|_| Error::Synthesis)?;
+        accumulators.extend(
+            SuccinctVerifier::verify(svk, &protocol, &instances, &proof)
+                .map_err(|_| Error::Synthesis)?,
+        );
+        public.extend(instances.into_iter().flatten());
+    }
+    let mut transcript = PoseidonTranscript::<Rc<Halo2Loader>, _>::new(loader, as_proof);
+    let proof = As::read_proof(&Default::default(), &accumulators, &mut transcript)
+        .map_err(|_| Error::Synthesis)?;
+    let accumulator =
+        As::verify(&Default::default(), &accumulators, &proof).map_err(|_| Error::Synthesis)?;
+    Ok((accumulator, public))
+}
+
+#[derive(Clone, Debug)]
+pub struct AggregationConfig {
+    main_gate: MainGateConfig,
+    range: RangeConfig,
+}
+
+/// Verifies `n` chunk proofs of one chunk key and exposes their accumulator and public
+/// inputs, see the [module documentation](self).
+#[derive(Clone)]
+pub struct AggregationCircuit {
+    svk: Svk,
+    snarks: Vec<SnarkWitness>,
+    instances: Vec<Fr>,
+    as_proof: Value<Vec<u8>>,
+}
+
+impl AggregationCircuit {
+    /// Folds `chunks`, all proven with the key `protocol` was compiled from on params whose
+    /// first generator `svk` is.
+    fn new(
+        svk: Svk,
+        protocol: &PlonkProtocol<G1Affine>,
+        chunks: &[ChunkSnark],
+    ) -> Result<Self, AggregationError> {
+        let mut accumulators = Vec::new();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let invalid = |err: snark_verifier::Error| AggregationError::InvalidChunk {
+                index,
+                reason: format!("{err:?}"),
+            };
+            let instances = vec![chunk.instances.clone()];
+            let mut transcript = PoseidonTranscript::<NativeLoader, _>::new(chunk.proof.as_slice());
+            let proof = SuccinctVerifier::read_proof(&svk, protocol, &instances, &mut transcript)
+                .map_err(invalid)?;
+            accumulators.extend(
+                SuccinctVerifier::verify(&svk, protocol, &instances, &proof).map_err(invalid)?,
+            );
+        }
+        let mut transcript = PoseidonTranscript::<NativeLoader, _>::new(Vec::new());
+        let accumulator =
+            As::create_proof(&Default::default(), &accumulators, &mut transcript, OsRng)
+                .map_err(|_| AggregationError::Accumulator)?;
+        let KzgAccumulator { lhs, rhs } = accumulator;
+        let mut instances = [lhs.x, lhs.y, rhs.x, rhs.y]
+            .map(fe_to_limbs::<Fq, Fr, LIMBS, BITS>)
+            .concat();
+        instances.extend(
+            chunks
+                .iter()
+                .flat_map(|chunk| chunk.instances.iter().copied()),
+        );
+        Ok(Self {
+            svk,
+            snarks: chunks
+                .iter()
+                .map(|chunk| SnarkWitness::new(protocol, chunk))
+                .collect(),
+            instances,
+            as_proof: Value::known(transcript.finalize()),
+        })
+    }
+
+    /// The circuit of `n` chunks of `num_instances` public inputs each, without witnesses,
+    /// for keygen.
+    fn shape(svk: Svk, protocol: &PlonkProtocol<G1Affine>, n: usize, num_instances: usize) -> Self {
+        let snark = SnarkWitness {
+            protocol: protocol.clone(),
+            instances: vec![vec![Value::unknown(); num_instances]],
+            proof: Value::unknown(),
+        };
+        Self {
+            svk,
+            snarks: vec![snark; n],
+            instances: Vec::new(),
+            as_proof: Value::unknown(),
+        }
+    }
+
+    /// The accumulator limbs, then the public inputs of every chunk.
+    pub fn instances(&self) -> Vec<Fr> {
+        self.instances.clone()
+    }
+}
+
+impl Circuit<Fr> for AggregationCircuit {
+    type Config = AggregationConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            svk: self.svk,
+            snarks: self
+                .snarks
+                .iter()
+                .map(SnarkWitness::without_witnesses)
+                .collect(),
+            instances: Vec::new(),
+            as_proof: Value::unknown(),
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let main_gate = MainGate::<Fr>::configure(meta);
+        let overflow_bits = Rns::<Fq, Fr, LIMBS, BITS>::construct().overflow_lengths();
+        let range = RangeChip::<Fr>::configure(meta, &main_gate, vec![BITS / LIMBS], overflow_bits);
+        AggregationConfig { main_gate, range }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let main_gate = MainGate::<Fr>::new(config.main_gate.clone());
+        RangeChip::<Fr>::new(config.range.clone()).load_table(&mut layouter)?;
+
+        let public = layouter.assign_region(
+            || "aggregation",
+            |region| {
+                let ctx = RegionCtx::new(region, 0);
+                let ecc_chip = BaseFieldEccChip::new(EccConfig::new(
+                    config.range.clone(),
+                    config.main_gate.clone(),
+                ));
+                let loader = Halo2Loader::new(ecc_chip, ctx);
+                let as_proof = self.as_proof.as_ref().map(Vec::as_slice);
+                let (accumulator, instances) =
+                    aggregate(&self.svk, &loader, &self.snarks, as_proof)?;
+                let mut public = Vec::new();
+                for point in [accumulator.lhs, accumulator.rhs] {
+                    let limbs = loader
+                        .ecc_chip()
+                        .assign_ec_point_to_limbs(&mut loader.ctx_mut(), point.assigned())?;
+                    public.extend(limbs);
+                }
+                public.extend(instances.into_iter().map(|scalar| scalar.into_assigned()));
+                Ok(public)
+            },
+        )?;
+        for (row, cell) in public.into_iter().enumerate() {
+            main_gate.expose_public(layouter.namespace(|| "public input"), cell, row)?;
+        }
+        Ok(())
+    }
+}
+
+/// Aggregates chunk proofs of one chunk key, keeping the aggregation keys of every batch
+/// size it has seen.
+pub struct Aggregator {
+    /// Params of the aggregation circuit
+    params: ParamsKZG<Bn256>,
+    /// Params and key of the chunks
+    chunk_params: ParamsKZG<Bn256>,
+    chunk_vk: VerifyingKey<G1Affine>,
+    protocol: PlonkProtocol<G1Affine>,
+    num_instances: usize,
+    contexts: Mutex<HashMap<usize, Arc<ProverContext<AggregationCircuit>>>>,
+}
+
+impl Aggregator {
+    /// Aggregates proofs of `chunk` with `num_instances` public inputs each, on `params` of
+    /// the size the aggregation circuit needs.
+    pub fn new<C: Circuit<Fr>>(
+        params: ParamsKZG<Bn256>,
+        chunk: &ProverContext<C>,
+        num_instances: usize,
+    ) -> Result<Self, AggregationError> {
+        check_plain(chunk)?;
+        let chunk_vk = chunk.pk().get_vk().clone();
+        let config = Config::kzg().with_num_instance(vec![num_instances]);
+        let protocol = compile(chunk.params(), &chunk_vk, config);
+        Ok(Self {
+            params,
+            chunk_params: chunk.params().clone(),
+            chunk_vk,
+            protocol,
+            num_instances,
+            contexts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn svk(&self) -> Svk {
+        self.chunk_params.get_g()[0].into()
+    }
+
+    /// The keys of batches of `n` chunks, generated on first use.
+    pub fn context(&self, n: usize) -> Result<Arc<ProverContext<AggregationCircuit>>, Error> {
+        let mut contexts = self
+            .contexts
+            .lock()
+            .expect("aggregation keys are not poisoned");
+        if let Some(ctx) = contexts.get(&n) {
+            return Ok(ctx.clone());
+        }
+        let shape = AggregationCircuit::shape(self.svk(), &self.protocol, n, self.num_instances);
+        let ctx = Arc::new(ProverContext::new(self.params.clone(), &shape)?);
+        contexts.insert(n, ctx.clone());
+        Ok(ctx)
+    }
+
+    /// Checks every chunk proof natively, then proves their aggregation in the transcript of
+    /// `hash`; returns the proof and its public inputs.
+    pub fn aggregate(
+        &self,
+        chunks: &[ChunkSnark],
+        hash: TranscriptHash,
+    ) -> Result<(Vec<u8>, Vec<Fr>), AggregationError> {
+        for (index, chunk) in chunks.iter().enumerate() {
+            if chunk.instances.len() != self.num_instances {
+                return Err(AggregationError::InvalidChunk {
+                    index,
+                    reason: format!(
+                        "{} public inputs, the chunk key has {}",
+                        chunk.instances.len(),
+                        self.num_instances
+                    ),
+                });
+            }
+            verify_chunk(&self.chunk_params, &self.chunk_vk, chunk).map_err(|err| {
+                AggregationError::InvalidChunk {
+                    index,
+                    reason: format!("{err:?}"),
+                }
+            })?;
+        }
+        let circuit = AggregationCircuit::new(self.svk(), &self.protocol, chunks)?;
+        let instances = circuit.instances();
+        let ctx = self.context(chunks.len())?;
+        let proof = ctx.prove_in(hash, &circuit, &[&instances])?;
+        Ok((proof, instances))
+    }
+
+    /// Checks a batch proof of [`Aggregator::aggregate`]: the proof against the key of its
+    /// batch size, then the pairing of the accumulator it exposes.
+    pub fn verify(
+        &self,
+        proof: &[u8],
+        instances: &[Fr],
+        hash: TranscriptHash,
+    ) -> Result<(), AggregationError> {
+        let chunk_instances = instances.len().wrapping_sub(ACCUMULATOR_INSTANCES);
+        if instances.len() <= ACCUMULATOR_INSTANCES || chunk_instances % self.num_instances != 0 {
+            return Err(AggregationError::Instances(instances.len()));
+        }
+        let ctx = self.context(chunk_instances / self.num_instances)?;
+        ctx.verify_in(hash, proof, &[instances])?;
+        decide(&self.chunk_params, instances)
+    }
+}
+
+/// The pairing check of the accumulator exposed by the first [`ACCUMULATOR_INSTANCES`] of
+/// `instances`, on the params of the proofs it folds.
+pub(crate) fn decide(params: &ParamsKZG<Bn256>, instances: &[Fr]) -> Result<(), AggregationError> {
+    let coordinate = |i: usize| {
+        let limbs: [Fr; LIMBS] = instances[i * LIMBS..(i + 1) * LIMBS].try_into().unwrap();
+        fe_from_limbs::<Fq, Fr, LIMBS, BITS>(limbs)
+    };
+    let point = |x: Fq, y: Fq| {
+        Option::<G1Affine>::from(G1Affine::from_xy(x, y)).ok_or(AggregationError::Accumulator)
+    };
+    let lhs = point(coordinate(0), coordinate(1))?;
+    let rhs = point(coordinate(2), coordinate(3))?;
+    let dk: KzgDecidingKey<Bn256> = (params.get_g()[0], params.g2(), params.s_g2()).into();
+    As::decide(&dk, KzgAccumulator::new(lhs, rhs)).map_err(|_| AggregationError::Accumulator)
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::dev::MockProver;
+    use poseidon::Spec;
+
+    use super::*;
+    use crate::{poseidon_hash::hash, test_circuit::TestCircuit};
+
+    #[test]
+    fn test_aggregate_chunks() {
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        let template = TestCircuit::new(vec![Fr::from(0); 5]);
+        let chunk_ctx = ProverContext::setup(10, &template).unwrap();
+        let chunks = (0..2u64)
+            .map(|i| {
+                let inputs = (0..5).map(|j| Fr::from(10 * i + j)).collect::<Vec<_>>();
+                let digest = hash(&spec, &inputs);
+                prove_chunk(&chunk_ctx, &TestCircuit::new(inputs), &[digest]).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let svk: Svk = chunk_ctx.params().get_g()[0].into();
+        let protocol = compile(
+            chunk_ctx.params(),
+            chunk_ctx.pk().get_vk(),
+            Config::kzg().with_num_instance(vec![1]),
+        );
+        let circuit = AggregationCircuit::new(svk, &protocol, &chunks).unwrap();
+        let instances = circuit.instances();
+        assert_eq!(instances.len(), ACCUMULATOR_INSTANCES + 2);
+        assert_eq!(
+            instances[ACCUMULATOR_INSTANCES..],
+            [chunks[0].instances[0], chunks[1].instances[0]]
+        );
+        let prover = MockProver::run(21, &circuit, vec![instances.clone()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // another digest does not verify, natively or in the circuit
+        let mut forged = chunks.clone();
+        forged[1].instances[0] += Fr::from(1);
+        let aggregator = Aggregator::new(ParamsKZG::setup(21, OsRng), &chunk_ctx, 1).unwrap();
+        assert!(matches!(
+            aggregator.aggregate(&forged, TranscriptHash::Blake2b),
+            Err(AggregationError::InvalidChunk { index: 1, .. })
+        ));
+        let mut public = instances;
+        public[ACCUMULATOR_INSTANCES + 1] = forged[1].instances[0];
+        let prover = MockProver::run(21, &circuit, vec![public]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
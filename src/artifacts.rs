@@ -0,0 +1,625 @@
+//! Where params, keys and keygen manifests are kept, shared by every prover of a fleet.
+//!
+//! An [`ArtifactStore`] maps keys such as `<vk hash>.vk` to bytes. [`LocalStore`] keeps them
+//! in a directory, [`MemoryStore`] in memory for tests, and `S3Store` (feature `s3`) in a
+//! bucket of any S3-compatible object store, so keygen can run once and every prover read
+//! the same keys. Keys are the file names written by [`crate::keygen`]: see [`vk_key`],
+//! [`pk_key`], [`crate::keygen::MANIFEST_FILE`] and [`crate::keygen::PARAMS_FILE`].
+//!
+//! [`LocalStore`] writes every file aside as a [`TempFile`] and renames it into place. A
+//! failed write removes its temp file, and [`LocalStore::sweep`] removes those of writes a
+//! crash interrupted, which would otherwise pile up next to multi-gigabyte keys.
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+/// Longest accepted key.
+pub const MAX_KEY_LEN: usize = 512;
+
+/// Prefix of the names of [`TempFile`]s, which no key segment may start with.
+pub const TEMP_PREFIX: &str = ".tmp-";
+
+/// Age after which [`LocalStore::sweep`] takes an untouched temp file for the leftover of a
+/// crash rather than a write in progress, possibly by another prover sharing the directory.
+pub const STALE_TEMP_AGE: Duration = Duration::from_secs(15 * 60);
+
+pub trait ArtifactStore: fmt::Debug + Send + Sync {
+    fn get(&self, key: &str) -> Result<Vec<u8>, ArtifactError>;
+
+    /// Stores `bytes` under `key`, replacing what was there; readers see either the old or
+    /// the new object, never a partial one.
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<(), ArtifactError>;
+}
+
+/// Key of the verifying key whose [`crate::prover::vk_hash`] is `hash`.
+pub fn vk_key(hash: &str) -> String {
+    format!("{hash}.vk")
+}
+
+/// Key of the proving key whose verifying key hashes to `hash`.
+pub fn pk_key(hash: &str) -> String {
+    format!("{hash}.pk")
+}
+
+#[derive(Debug)]
+pub enum ArtifactError {
+    InvalidKey(String),
+    NotFound(String),
+    Io(io::Error),
+    /// The object store answered with an error status
+    Http {
+        status: u16,
+        body: String,
+    },
+}
+
+impl fmt::Display for ArtifactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidKey(key) => write!(f, "invalid artifact key {key:?}"),
+            Self::NotFound(key) => write!(f, "artifact {key} not found"),
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Http { status, body } => write!(f, "object store answered {status}: {body}"),
+        }
+    }
+}
+
+impl std::error::Error for ArtifactError {}
+
+impl From<ArtifactError> for io::Error {
+    fn from(err: ArtifactError) -> Self {
+        match err {
+            ArtifactError::Io(err) => err,
+            ArtifactError::NotFound(_) => io::Error::new(io::ErrorKind::NotFound, err),
+            ArtifactError::InvalidKey(_) => io::Error::new(io::ErrorKind::InvalidInput, err),
+            _ => io::Error::other(err),
+        }
+    }
+}
+
+/// Checks `key` is `/`-separated segments of ASCII letters, digits, `.`, `_` and `-`, none of
+/// them `.` or `..`, so it names the same object in a directory and in a bucket. No segment
+/// starts with [`TEMP_PREFIX`] either, so no object is taken for a temp file.
+pub fn check_key(key: &str) -> Result<(), ArtifactError> {
+    let valid_segment = |segment: &str| {
+        !segment.is_empty()
+            && segment != "."
+            && segment != ".."
+            && !segment.starts_with(TEMP_PREFIX)
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+    };
+    if key.len() <= MAX_KEY_LEN && key.split('/').all(valid_segment) {
+        Ok(())
+    } else {
+        Err(ArtifactError::InvalidKey(key.to_string()))
+    }
+}
+
+/// Artifacts as files under a directory, the key being the relative path.
+#[derive(Debug, Clone)]
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn path(&self, key: &str) -> Result<PathBuf, ArtifactError> {
+        check_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+impl ArtifactStore for LocalStore {
+    fn get(&self, key: &str) -> Result<Vec<u8>, ArtifactError> {
+        fs::read(self.path(key)?).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => ArtifactError::NotFound(key.to_string()),
+            _ => ArtifactError::Io(err),
+        })
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<(), ArtifactError> {
+        // written aside and renamed, so concurrent writers of one key never interleave
+        let path = self.path(key)?;
+        let dir = path.parent().expect("keys are relative to the root");
+        fs::create_dir_all(dir).map_err(ArtifactError::Io)?;
+        let mut tmp = TempFile::create(dir).map_err(ArtifactError::Io)?;
+        tmp.file()
+            .write_all(bytes)
+            .and_then(|()| tmp.persist(&path))
+            .map_err(ArtifactError::Io)
+    }
+}
+
+/// A file written aside, removed when dropped unless [`TempFile::persist`]ed into place.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    /// `None` once persisted
+    file: Option<File>,
+}
+
+impl TempFile {
+    /// A new empty file in `dir`, named with [`TEMP_PREFIX`].
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        static SEQ: AtomicU64 = AtomicU64::new(0);
+        let path = dir.join(format!(
+            "{TEMP_PREFIX}{}-{}",
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::options().write(true).create_new(true).open(&path)?;
+        Ok(Self {
+            path,
+            file: Some(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file(&mut self) -> &mut File {
+        self.file.as_mut().expect("only persist takes the file")
+    }
+
+    /// Flushes the file to disk and renames it to `to`, in the same file system.
+    pub fn persist(mut self, to: &Path) -> io::Result<()> {
+        let file = self.file.take().expect("only persist takes the file");
+        let renamed = file.sync_all().and_then(|()| fs::rename(&self.path, to));
+        if renamed.is_err() {
+            let _ = fs::remove_file(&self.path);
+        }
+        renamed
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            drop(file);
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl LocalStore {
+    /// Removes the temp files under the root that were last written `older_than` ago or
+    /// earlier, left over by writes a crash interrupted, and returns how many it removed.
+    ///
+    /// Meant for startup: younger ones may be written by another process right now.
+    pub fn sweep(&self, older_than: Duration) -> io::Result<usize> {
+        sweep_dir(&self.root, older_than, SystemTime::now())
+    }
+}
+
+fn sweep_dir(dir: &Path, older_than: Duration, now: SystemTime) -> io::Result<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            removed += sweep_dir(&entry.path(), older_than, now)?;
+            continue;
+        }
+        if !file_type.is_file() || !entry.file_name().to_string_lossy().starts_with(TEMP_PREFIX) {
+            continue;
+        }
+        let modified = entry.metadata()?.modified()?;
+        if now.duration_since(modified).unwrap_or_default() >= older_than {
+            match fs::remove_file(entry.path()) {
+                Ok(()) => removed += 1,
+                // swept by another process meanwhile
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Artifacts in memory, e.g. for tests.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    objects: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ArtifactStore for MemoryStore {
+    fn get(&self, key: &str) -> Result<Vec<u8>, ArtifactError> {
+        check_key(key)?;
+        self.objects
+            .lock()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .ok_or_else(|| ArtifactError::NotFound(key.to_string()))
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<(), ArtifactError> {
+        check_key(key)?;
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), bytes.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "s3")]
+pub use s3::{S3Config, S3Store};
+
+#[cfg(feature = "s3")]
+mod s3 {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::{http::HttpUrl, limits::MAX_ARTIFACT_BYTES};
+
+    /// Location of and credentials for a bucket.
+    #[derive(Clone, PartialEq, Eq)]
+    pub struct S3Config {
+        /// `http://` endpoint of the store; TLS is left to a sidecar as for [`crate::http`]
+        pub endpoint: HttpUrl,
+        pub bucket: String,
+        /// Prepended to every key, e.g. `mainnet/`; empty for none
+        pub prefix: String,
+        pub region: String,
+        pub access_key: String,
+        pub secret_key: String,
+    }
+
+    impl fmt::Debug for S3Config {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("S3Config")
+                .field("endpoint", &self.endpoint)
+                .field("bucket", &self.bucket)
+                .field("prefix", &self.prefix)
+                .field("region", &self.region)
+                .field("access_key", &self.access_key)
+                .finish_non_exhaustive()
+        }
+    }
+
+    /// Artifacts as objects of a bucket, addressed path-style and signed with SigV4.
+    #[derive(Debug, Clone)]
+    pub struct S3Store {
+        config: S3Config,
+        timeout: Duration,
+    }
+
+    impl S3Store {
+        pub fn new(config: S3Config) -> Self {
+            Self {
+                config,
+                timeout: Duration::from_secs(60),
+            }
+        }
+
+        pub fn with_timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        fn send(&self, method: &str, key: &str, body: &[u8]) -> Result<Vec<u8>, ArtifactError> {
+            check_key(key)?;
+            let mut url = self.config.endpoint.clone();
+            url.path = format!(
+                "{}/{}/{}{key}",
+                url.path.trim_end_matches('/'),
+                self.config.bucket,
+                self.config.prefix
+            );
+            let payload_hash = hex(&Sha256::digest(body));
+            let amz_date = amz_date(SystemTime::now());
+            let authorization = authorization(
+                &self.config,
+                method,
+                &url.path,
+                &url.host_header(),
+                &payload_hash,
+                &amz_date,
+            );
+            let headers = [
+                ("x-amz-content-sha256", payload_hash.as_str()),
+                ("x-amz-date", amz_date.as_str()),
+                ("Authorization", authorization.as_str()),
+            ];
+            let response = url
+                .request(method, body, &headers, self.timeout, MAX_ARTIFACT_BYTES)
+                .map_err(ArtifactError::Io)?;
+            match response.status {
+                404 => Err(ArtifactError::NotFound(key.to_string())),
+                _ if response.is_success() => Ok(response.body),
+                status => Err(ArtifactError::Http {
+                    status,
+                    body: String::from_utf8_lossy(&response.body).into_owned(),
+                }),
+            }
+        }
+    }
+
+    impl ArtifactStore for S3Store {
+        fn get(&self, key: &str) -> Result<Vec<u8>, ArtifactError> {
+            self.send("GET", key, &[])
+        }
+
+        fn put(&self, key: &str, bytes: &[u8]) -> Result<(), ArtifactError> {
+            self.send("PUT", key, bytes).map(drop)
+        }
+    }
+
+    /// The SigV4 `Authorization` header of a request signing `host`, the payload hash and the
+    /// date; `path` must not need percent-encoding, which [`check_key`] ensures for keys.
+    pub(super) fn authorization(
+        config: &S3Config,
+        method: &str,
+        path: &str,
+        host: &str,
+        payload_hash: &str,
+        amz_date: &str,
+    ) -> String {
+        const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{method}\n{path}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{SIGNED_HEADERS}\n{payload_hash}"
+        );
+        let date = &amz_date[..8];
+        let scope = format!("{date}/{}/s3/aws4_request", config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&config.secret_key, date, &config.region, "s3");
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={SIGNED_HEADERS}, Signature={}",
+            config.access_key,
+            hex(&hmac(&key, string_to_sign.as_bytes()))
+        )
+    }
+
+    pub(super) fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+        let key = hmac(format!("AWS4{secret}").as_bytes(), date.as_bytes());
+        let key = hmac(&key, region.as_bytes());
+        let key = hmac(&key, service.as_bytes());
+        hmac(&key, b"aws4_request")
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    pub(super) fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// `YYYYMMDDTHHMMSSZ` in UTC.
+    pub(super) fn amz_date(time: SystemTime) -> String {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let (days, secs) = (secs / 86400, secs % 86400);
+        // days since the epoch to a proleptic Gregorian date, after Howard Hinnant
+        let z = days as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        format!(
+            "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        for key in ["a.vk", "mainnet/params.bin", "x_y-z.0"] {
+            assert!(check_key(key).is_ok(), "{key}");
+        }
+        for key in ["", "/a", "a/", "a//b", "../a", "a/./b", "a b", "a\\b", "ä"] {
+            assert!(check_key(key).is_err(), "{key}");
+        }
+        assert!(check_key(&"a".repeat(MAX_KEY_LEN + 1)).is_err());
+        assert!(check_key("a/.tmp-1-2").is_err());
+    }
+
+    #[test]
+    fn test_stores() {
+        let dir = std::env::temp_dir().join(format!("artifacts-{}", std::process::id()));
+        let local = LocalStore::new(&dir);
+        let memory = MemoryStore::new();
+        for store in [&local as &dyn ArtifactStore, &memory] {
+            assert!(matches!(store.get("a.vk"), Err(ArtifactError::NotFound(_))));
+            store.put("a.vk", b"one").unwrap();
+            store.put("a.vk", b"two").unwrap();
+            store.put("nested/b.pk", b"three").unwrap();
+            assert_eq!(store.get("a.vk").unwrap(), b"two");
+            assert_eq!(store.get("nested/b.pk").unwrap(), b"three");
+            assert!(matches!(
+                store.put("../c", b""),
+                Err(ArtifactError::InvalidKey(_))
+            ));
+        }
+        assert_eq!(memory.len(), 2);
+        // nothing is left over from replacing an object
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_temp_files() {
+        let dir = std::env::temp_dir().join(format!("temp-files-{}", std::process::id()));
+        let nested = dir.join("nested");
+        fs::create_dir_all(&nested).unwrap();
+        let store = LocalStore::new(&dir);
+        assert_eq!(store.sweep(Duration::ZERO).unwrap(), 0);
+
+        // a dropped temp file is removed, a persisted one is renamed
+        let mut dropped = TempFile::create(&dir).unwrap();
+        dropped.file().write_all(b"partial").unwrap();
+        let path = dropped.path().to_path_buf();
+        assert!(path.exists());
+        drop(dropped);
+        assert!(!path.exists());
+        let mut kept = TempFile::create(&dir).unwrap();
+        kept.file().write_all(b"whole").unwrap();
+        kept.persist(&dir.join("a.pk")).unwrap();
+        assert_eq!(store.get("a.pk").unwrap(), b"whole");
+
+        // the leftovers of a crash, in the root and below it
+        for dir in [&dir, &nested] {
+            let mut orphan = TempFile::create(dir).unwrap();
+            orphan.file().write_all(b"partial").unwrap();
+            std::mem::forget(orphan);
+        }
+        assert_eq!(store.sweep(STALE_TEMP_AGE).unwrap(), 0);
+        assert_eq!(store.sweep(Duration::ZERO).unwrap(), 2);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        assert_eq!(fs::read_dir(&nested).unwrap().count(), 0);
+        assert_eq!(
+            LocalStore::new(dir.join("missing"))
+                .sweep(Duration::ZERO)
+                .unwrap(),
+            0
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "s3")]
+    #[test]
+    fn test_sigv4() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        // the derivation example of the AWS documentation
+        let key = s3::signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            s3::hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+        assert_eq!(s3::amz_date(UNIX_EPOCH), "19700101T000000Z");
+        assert_eq!(
+            s3::amz_date(UNIX_EPOCH + Duration::from_secs(1369353600 + 3723)),
+            "20130524T010203Z"
+        );
+        assert_eq!(
+            s3::amz_date(UNIX_EPOCH + Duration::from_secs(951782400)),
+            "20000229T000000Z"
+        );
+    }
+
+    #[cfg(feature = "s3")]
+    #[test]
+    fn test_s3_requests() {
+        use std::{
+            io::{Read, Write},
+            net::TcpListener,
+        };
+
+        use crate::http::HttpUrl;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status, body) in [("200 OK", ""), ("200 OK", "keys"), ("404 Not Found", "")] {
+                let (mut stream, _) = listener.accept().unwrap();
+                // the head and the body may arrive in separate reads
+                let mut request = String::new();
+                let mut chunk = [0u8; 4096];
+                let complete = |request: &str| {
+                    request.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                        head.lines().any(|line| {
+                            line.strip_prefix("Content-Length: ")
+                                == Some(body.len().to_string().as_str())
+                        })
+                    })
+                };
+                while !complete(&request) {
+                    let n = stream.read(&mut chunk).unwrap();
+                    request.push_str(&String::from_utf8_lossy(&chunk[..n]));
+                }
+                requests.push(request);
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+            requests
+        });
+        let store = S3Store::new(S3Config {
+            endpoint: HttpUrl::parse(&format!("http://127.0.0.1:{port}")).unwrap(),
+            bucket: "artifacts".to_string(),
+            prefix: "mainnet/".to_string(),
+            region: "us-east-1".to_string(),
+            access_key: "AKIDEXAMPLE".to_string(),
+            secret_key: "secret".to_string(),
+        });
+        store.put("a.vk", b"keys").unwrap();
+        assert_eq!(store.get("a.vk").unwrap(), b"keys");
+        assert!(matches!(store.get("b.vk"), Err(ArtifactError::NotFound(_))));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("PUT /artifacts/mainnet/a.vk HTTP/1.1\r\n"));
+        assert!(requests[0].ends_with("\r\n\r\nkeys"));
+        assert!(requests[0].contains("Authorization: AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+        assert!(requests[1].starts_with("GET /artifacts/mainnet/a.vk HTTP/1.1\r\n"));
+    }
+}
@@ -0,0 +1,342 @@
+//! Batches of chunk proofs collected by the service itself, for deployments without an
+//! orchestrator.
+//!
+//! Chunk tasks name their batch in [`Task::batch`]. A [`BatchCollector`] keeps the digest of
+//! every chunk proven, and closes a batch once it has all of its chunks or its timer fires,
+//! a fixed time after its first chunk. Closing yields the batch task: a
+//! [`ProofType::Batch`] task with the id of the batch, hashing the chunk digests in index
+//! order, which the service proves like any other task. It takes the hard fork, EVM flag,
+//! instance layout and callback of the batch's first chunk.
+//!
+//! A batch without a size only closes on its timer, with whatever chunks it has. A batch
+//! of a known size that times out incomplete is closed too, with the chunks it lacks listed
+//! in [`ClosedBatch::missing`]; it should be reported rather than proven.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use halo2curves::bn256::Fr;
+
+use crate::{
+    field_encoding::to_canonical,
+    task::{ProofType, Task},
+};
+
+/// A batch taken out of the collector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClosedBatch {
+    /// The batch task, over the chunks the batch has
+    pub task: Task,
+    /// Indexes of the chunks a batch of known size did not get before its timer fired
+    pub missing: Vec<usize>,
+}
+
+impl ClosedBatch {
+    /// The batch task if the batch has all of its chunks.
+    pub fn complete(self) -> Result<Task, BatchError> {
+        if self.missing.is_empty() {
+            Ok(self.task)
+        } else {
+            Err(BatchError::Incomplete {
+                batch: self.task.id,
+                missing: self.missing,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchError {
+    /// The chunk is past the size of its batch
+    InvalidIndex {
+        batch: String,
+        index: usize,
+        size: usize,
+    },
+    /// Another digest was recorded for the same chunk
+    Conflict {
+        batch: String,
+        index: usize,
+    },
+    /// Chunks of one batch disagree on a field that has to be the same for all of them
+    Mismatch {
+        batch: String,
+        field: &'static str,
+    },
+    Incomplete {
+        batch: String,
+        missing: Vec<usize>,
+    },
+}
+
+impl BatchError {
+    /// Id of the batch the error is about.
+    pub fn batch(&self) -> &str {
+        match self {
+            Self::InvalidIndex { batch, .. }
+            | Self::Conflict { batch, .. }
+            | Self::Mismatch { batch, .. }
+            | Self::Incomplete { batch, .. } => batch,
+        }
+    }
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidIndex { batch, index, size } => {
+                write!(f, "chunk {index} is out of batch {batch} of {size} chunks")
+            }
+            Self::Conflict { batch, index } => {
+                write!(
+                    f,
+                    "chunk {index} of batch {batch} has another digest already"
+                )
+            }
+            Self::Mismatch { batch, field } => {
+                write!(f, "chunks of batch {batch} disagree on {field}")
+            }
+            Self::Incomplete { batch, missing } => {
+                write!(f, "batch {batch} timed out without chunks {missing:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+/// A batch with some of its chunks proven.
+struct PendingBatch {
+    size: Option<usize>,
+    opened: Instant,
+    /// The first chunk, whose settings the batch task takes
+    first: Task,
+    digests: BTreeMap<usize, Fr>,
+}
+
+impl PendingBatch {
+    fn close(self, id: String) -> ClosedBatch {
+        let missing = match self.size {
+            Some(size) => (0..size)
+                .filter(|index| !self.digests.contains_key(index))
+                .collect(),
+            None => Vec::new(),
+        };
+        let digests = self.digests.values().map(to_canonical).collect::<Vec<_>>();
+        let task = Task {
+            uuid: id.clone(),
+            id,
+            task_type: ProofType::Batch,
+            task_data: serde_json::to_string(&digests).expect("strings serialize"),
+            hard_fork_name: self.first.hard_fork_name,
+            evm: self.first.evm,
+            instance_layout: self.first.instance_layout,
+            transcript: self.first.transcript,
+            callback_url: self.first.callback_url,
+            compression: self.first.compression,
+            schema_version: self.first.schema_version,
+            ..Default::default()
+        };
+        ClosedBatch { task, missing }
+    }
+}
+
+/// Open batches, keyed by batch id.
+pub struct BatchCollector {
+    timeout: Duration,
+    pending: Mutex<HashMap<String, PendingBatch>>,
+}
+
+impl BatchCollector {
+    /// Closes every batch `timeout` after its first chunk at the latest.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Open batches.
+    pub fn pending(&self) -> usize {
+        self.pending.lock().expect("not poisoned").len()
+    }
+
+    /// Records `digest`, the public input of the proof of the chunk task `task`, returning
+    /// the batch if the chunk completes it; tasks without a batch are ignored.
+    ///
+    /// Recording the same digest twice, e.g. for a retried chunk, is not an error.
+    pub fn add(
+        &self,
+        task: &Task,
+        digest: Fr,
+        now: Instant,
+    ) -> Result<Option<ClosedBatch>, BatchError> {
+        let Some(batch) = &task.batch else {
+            return Ok(None);
+        };
+        let (id, index) = (&batch.id, batch.index);
+        if let Some(size) = batch.size.filter(|size| index >= *size) {
+            return Err(BatchError::InvalidIndex {
+                batch: id.clone(),
+                index,
+                size,
+            });
+        }
+        let mut pending = self.pending.lock().expect("not poisoned");
+        let open = pending.entry(id.clone()).or_insert_with(|| PendingBatch {
+            size: batch.size,
+            opened: now,
+            first: task.clone(),
+            digests: BTreeMap::new(),
+        });
+        let mismatch = |field| BatchError::Mismatch {
+            batch: id.clone(),
+            field,
+        };
+        if open.size != batch.size {
+            return Err(mismatch("size"));
+        }
+        if open.first.hard_fork_name != task.hard_fork_name {
+            return Err(mismatch("hard_fork_name"));
+        }
+        match open.digests.insert(index, digest) {
+            Some(recorded) if recorded != digest => {
+                open.digests.insert(index, recorded);
+                return Err(BatchError::Conflict {
+                    batch: id.clone(),
+                    index,
+                });
+            }
+            _ => {}
+        }
+        if open.size != Some(open.digests.len()) {
+            return Ok(None);
+        }
+        let open = pending.remove(id).expect("entry exists");
+        Ok(Some(open.close(id.clone())))
+    }
+
+    /// Closes the batches whose timer fired by `now`, oldest first.
+    pub fn expire(&self, now: Instant) -> Vec<ClosedBatch> {
+        let mut pending = self.pending.lock().expect("not poisoned");
+        let mut expired = pending
+            .iter()
+            .filter(|(_, open)| now.saturating_duration_since(open.opened) >= self.timeout)
+            .map(|(id, open)| (open.opened, id.clone()))
+            .collect::<Vec<_>>();
+        expired.sort();
+        expired
+            .into_iter()
+            .map(|(_, id)| {
+                let open = pending.remove(&id).expect("entry exists");
+                open.close(id)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{task::BatchRef, task_data::HashWitness};
+
+    fn chunk(batch: &str, index: usize, size: Option<usize>) -> Task {
+        Task {
+            id: format!("{batch}-{index}"),
+            task_type: ProofType::Chunk,
+            hard_fork_name: "bernoulli".to_string(),
+            evm: true,
+            batch: Some(BatchRef {
+                id: batch.to_string(),
+                index,
+                size,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_complete_batch() {
+        let collector = BatchCollector::new(Duration::from_secs(60));
+        let now = Instant::now();
+        let add =
+            |index, digest: u64| collector.add(&chunk("b", index, Some(3)), digest.into(), now);
+
+        assert_eq!(add(2, 12), Ok(None));
+        assert_eq!(add(0, 10), Ok(None));
+        // a retried chunk
+        assert_eq!(add(0, 10), Ok(None));
+        assert_eq!(
+            add(0, 11),
+            Err(BatchError::Conflict {
+                batch: "b".to_string(),
+                index: 0
+            })
+        );
+        assert!(matches!(add(3, 13), Err(BatchError::InvalidIndex { .. })));
+        let other_fork = Task {
+            hard_fork_name: "curie".to_string(),
+            ..chunk("b", 1, Some(3))
+        };
+        assert_eq!(
+            collector.add(&other_fork, Fr::from(11), now),
+            Err(BatchError::Mismatch {
+                batch: "b".to_string(),
+                field: "hard_fork_name"
+            })
+        );
+        assert!(collector
+            .add(&Task::default(), Fr::from(1), now)
+            .unwrap()
+            .is_none());
+
+        let task = add(1, 11).unwrap().unwrap().complete().unwrap();
+        assert_eq!(collector.pending(), 0);
+        assert_eq!((task.id.as_str(), task.task_type), ("b", ProofType::Batch));
+        assert!(task.evm);
+        assert_eq!(task.batch, None);
+        let witness = HashWitness::<Fr>::parse(&task.task_data).unwrap();
+        assert_eq!(witness.inputs, [10u64, 11, 12].map(Fr::from));
+    }
+
+    #[test]
+    fn test_timer() {
+        let collector = BatchCollector::new(Duration::from_secs(60));
+        let start = Instant::now();
+        collector
+            .add(&chunk("open", 1, None), Fr::from(1), start)
+            .unwrap();
+        collector
+            .add(&chunk("sized", 0, Some(3)), Fr::from(2), start)
+            .unwrap();
+        assert!(collector.expire(start + Duration::from_secs(59)).is_empty());
+
+        let closed = collector.expire(start + Duration::from_secs(60));
+        assert_eq!(collector.pending(), 0);
+        let (open, sized): (Vec<_>, Vec<_>) = closed
+            .into_iter()
+            .partition(|batch| batch.task.id == "open");
+        let task = open[0].clone().complete().unwrap();
+        assert_eq!(
+            HashWitness::<Fr>::parse(&task.task_data).unwrap().inputs,
+            [Fr::from(1)]
+        );
+        assert_eq!(sized[0].missing, [1, 2]);
+        let err = sized[0].clone().complete().unwrap_err();
+        assert_eq!(
+            err,
+            BatchError::Incomplete {
+                batch: "sized".to_string(),
+                missing: vec![1, 2]
+            }
+        );
+        assert_eq!(err.batch(), "sized");
+    }
+}
@@ -0,0 +1,51 @@
+//! Produces the witness of a membership proof from a file of leaves.
+//!
+//! Usage: `membership_witness <leaf-file> <secret> [depth]`
+//!
+//! The leaf file holds one field element per line, in tree order; blank lines are skipped.
+//! The witness is printed to stdout as JSON.
+use std::process::ExitCode;
+
+use halo2curves::bn256::Fr;
+use poseidon_circuit::{membership::MembershipWitness, state::MEMBERSHIP_DEPTH};
+
+fn main() -> ExitCode {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() < 3 || args.len() > 4 {
+        eprintln!("usage: {} <leaf-file> <secret> [depth]", args[0]);
+        return ExitCode::FAILURE;
+    }
+    let depth = match args.get(3).map(|d| d.parse::<usize>()) {
+        None => MEMBERSHIP_DEPTH,
+        Some(Ok(depth)) => depth,
+        Some(Err(err)) => {
+            eprintln!("invalid depth: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let leaves = match std::fs::read_to_string(&args[1]) {
+        Ok(leaves) => leaves,
+        Err(err) => {
+            eprintln!("cannot read {}: {err}", args[1]);
+            return ExitCode::FAILURE;
+        }
+    };
+    let leaves = leaves
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>();
+    match MembershipWitness::from_leaves::<Fr, _>(&leaves, &args[2], depth) {
+        Ok(witness) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&witness).expect("witness serializes")
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
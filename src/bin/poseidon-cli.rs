@@ -0,0 +1,273 @@
+//! The proving pipeline of the service's hash circuit, run locally without the service.
+//!
+//! ```text
+//! poseidon-cli setup --out <dir> [--inputs <n>] [--k <k>] [--params <file | http://url>]
+//! poseidon-cli prove --keys <dir> --witness <witness.json> --out <proof.json>
+//! poseidon-cli verify --keys <dir> --proof <proof.json>
+//! poseidon-cli hash --input <a,b,...>
+//! ```
+//!
+//! `setup` writes the params, the keys of [`TestCircuit`] for messages of `n` elements and
+//! their manifest into `<dir>`, as `snarkify keygen-all` does for one `hash` variant. Without
+//! `--params` it generates insecure test params. A witness is `{"inputs": ["1", "0x02"]}` in
+//! any encoding of [`poseidon_circuit::field_encoding`]; a proof file holds the base64
+//! Blake2b-transcript proof and its digest, and `verify` needs only the params and the
+//! verifying key of the keys directory.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD as BS64, Engine};
+use halo2_proofs::poly::{commitment::Params, kzg::commitment::ParamsKZG};
+use halo2curves::bn256::{Bn256, Fr};
+use poseidon::Spec;
+use poseidon_circuit::{
+    artifacts::LocalStore,
+    field_encoding::{parse_field, parse_fields, to_canonical},
+    http::HttpUrl,
+    keygen::{self, CircuitKind, KeygenConfig, Manifest, Variant, VariantKeys},
+    limits::{MessageLimits, MAX_ARTIFACT_BYTES},
+    poseidon_hash::hash,
+    prover::{read_params, read_pk, read_vk, verify_detached, ProverContext},
+    specs::BN256_T4_R3,
+    state::HASH_INPUTS,
+    test_circuit::TestCircuit,
+};
+use serde::{Deserialize, Serialize};
+
+/// Degree of the circuit when `setup` is not given one, that of the service.
+const DEFAULT_K: u32 = 10;
+
+/// How long downloading params may take.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(600);
+
+const USAGE: &str = "usage: poseidon-cli <setup|prove|verify|hash> [options]";
+
+fn spec() -> Spec<Fr, { BN256_T4_R3.width }, { BN256_T4_R3.rate }> {
+    Spec::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p)
+}
+
+fn error(msg: impl Into<String>) -> std::io::Error {
+    std::io::Error::other(msg.into())
+}
+
+fn error_of(err: impl std::fmt::Display) -> std::io::Error {
+    error(err.to_string())
+}
+
+/// Names the keys by the message length they prove, which their manifest records nowhere else.
+fn variant_name(inputs: usize) -> String {
+    format!("hash-{inputs}")
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Witness {
+    inputs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProofFile {
+    vk_hash: String,
+    /// The digest, the only instance
+    digest: String,
+    /// Base64 of the proof bytes
+    proof: String,
+}
+
+/// Parses `--flag value` pairs of `flags` in any order.
+fn options<'a, const N: usize>(
+    args: &'a [String],
+    flags: [&str; N],
+    usage: &str,
+) -> Result<[Option<&'a str>; N], std::io::Error> {
+    let mut values = [None; N];
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let i = flags
+            .iter()
+            .position(|f| f == flag)
+            .ok_or_else(|| error(usage))?;
+        values[i] = Some(args.next().ok_or_else(|| error(usage))?.as_str());
+    }
+    Ok(values)
+}
+
+fn number<T: std::str::FromStr>(
+    flag: &str,
+    value: Option<&str>,
+    default: T,
+) -> Result<T, std::io::Error> {
+    value.map_or(Ok(default), |v| {
+        v.parse()
+            .map_err(|_| error(format!("{flag} is {v:?}, not a number")))
+    })
+}
+
+/// Copies a params file, or downloads one over plain HTTP, to `to`.
+fn fetch_params(from: &str, to: &Path) -> Result<(), std::io::Error> {
+    if from.starts_with("http://") {
+        let url = HttpUrl::parse(from).ok_or_else(|| error(format!("invalid URL {from:?}")))?;
+        let response = url.request("GET", &[], &[], DOWNLOAD_TIMEOUT, MAX_ARTIFACT_BYTES)?;
+        if !response.is_success() {
+            return Err(error(format!("{from}: HTTP {}", response.status)));
+        }
+        fs::write(to, response.body)
+    } else if from.contains("://") {
+        Err(error(format!(
+            "{from}: only files and http:// URLs, download over TLS first"
+        )))
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+/// `poseidon-cli setup`: writes params, keys and their manifest into a directory.
+fn run_setup(args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str = "usage: poseidon-cli setup --out <dir> [--inputs <n>] [--k <k>] \
+                         [--params <file | http://url>]";
+    let [out, inputs, k, params] = options(args, ["--out", "--inputs", "--k", "--params"], USAGE)?;
+    let out = PathBuf::from(out.ok_or_else(|| error(USAGE))?);
+    let inputs = number("--inputs", inputs, HASH_INPUTS)?;
+    let k = number("--k", k, DEFAULT_K)?;
+    MessageLimits::for_k(&spec(), k)
+        .check([inputs])
+        .map_err(|err| error(format!("{err}, pick a larger --k")))?;
+
+    fs::create_dir_all(&out)?;
+    let params = match params {
+        Some(from) => {
+            let path = out.join(keygen::PARAMS_FILE);
+            fetch_params(from, &path)?;
+            Some(path)
+        }
+        None => {
+            eprintln!("warning: no --params, generating insecure test params");
+            None
+        }
+    };
+    let config = KeygenConfig {
+        params,
+        variants: vec![Variant {
+            name: Some(variant_name(inputs)),
+            circuit: CircuitKind::Hash,
+            k,
+            spec: Some(BN256_T4_R3.id.to_string()),
+            fork: None,
+            inputs: Some(inputs),
+            bits: None,
+            depth: None,
+        }],
+    };
+    let manifest = keygen::keygen_all(&config, &LocalStore::new(&out)).map_err(error_of)?;
+    let keys = &manifest.variants[0];
+    println!("{}: vk {}", out.display(), keys.vk_hash);
+    Ok(())
+}
+
+/// The params and the keys of the only variant of a directory written by `setup`.
+fn read_keys(dir: &Path) -> Result<(ParamsKZG<Bn256>, VariantKeys), std::io::Error> {
+    let manifest: Manifest =
+        serde_json::from_slice(&fs::read(dir.join(keygen::MANIFEST_FILE))?).map_err(error_of)?;
+    let keys = match &manifest.variants[..] {
+        [keys] if keys.circuit == CircuitKind::Hash => keys.clone(),
+        _ => return Err(error(format!("{}: not written by setup", dir.display()))),
+    };
+    let mut params = read_params(dir.join(keygen::PARAMS_FILE))?;
+    params.downsize(keys.k);
+    Ok((params, keys))
+}
+
+/// `poseidon-cli prove`: proves the digest of the inputs of a witness file.
+fn run_prove(args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str =
+        "usage: poseidon-cli prove --keys <dir> --witness <witness.json> --out <proof.json>";
+    let [dir, witness, out] = options(args, ["--keys", "--witness", "--out"], USAGE)?;
+    let (dir, witness, out) = match (dir, witness, out) {
+        (Some(dir), Some(witness), Some(out)) => (Path::new(dir), witness, out),
+        _ => return Err(error(USAGE)),
+    };
+    let witness: Witness = serde_json::from_slice(&fs::read(witness)?).map_err(error_of)?;
+    let inputs = parse_fields::<Fr, _>(&witness.inputs).map_err(error_of)?;
+    let (params, keys) = read_keys(dir)?;
+
+    // the proof of a message of another length than the keys' would not verify
+    if keys.name != variant_name(inputs.len()) {
+        return Err(error(format!(
+            "the keys in {} are {}, not for {} inputs",
+            dir.display(),
+            keys.name,
+            inputs.len()
+        )));
+    }
+    let pk = read_pk::<TestCircuit<Fr>>(&fs::read(dir.join(&keys.pk.file))?)?;
+    let ctx = ProverContext::from_pk(params, Arc::new(pk));
+    let digest = hash(&spec(), &inputs);
+    let proof = ctx
+        .prove(&TestCircuit::new(inputs), &[&[digest]])
+        .map_err(|e| error(format!("proving failed: {e:?}")))?;
+    let file = ProofFile {
+        vk_hash: keys.vk_hash,
+        digest: to_canonical(&digest),
+        proof: BS64.encode(proof),
+    };
+    fs::write(out, serde_json::to_vec_pretty(&file).map_err(error_of)?)?;
+    println!("{}", file.digest);
+    Ok(())
+}
+
+/// `poseidon-cli verify`: checks a proof file against the verifying key of a directory.
+fn run_verify(args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str = "usage: poseidon-cli verify --keys <dir> --proof <proof.json>";
+    let [dir, proof] = options(args, ["--keys", "--proof"], USAGE)?;
+    let (dir, proof) = match (dir, proof) {
+        (Some(dir), Some(proof)) => (Path::new(dir), proof),
+        _ => return Err(error(USAGE)),
+    };
+    let file: ProofFile = serde_json::from_slice(&fs::read(proof)?).map_err(error_of)?;
+    let (params, keys) = read_keys(dir)?;
+    if file.vk_hash != keys.vk_hash {
+        return Err(error(format!(
+            "{proof} is of vk {}, {} holds {}",
+            file.vk_hash,
+            dir.display(),
+            keys.vk_hash
+        )));
+    }
+    let vk = read_vk::<TestCircuit<Fr>>(&fs::read(dir.join(&keys.vk.file))?)?;
+    let digest = parse_field::<Fr>(&file.digest).map_err(error_of)?;
+    let bytes = BS64.decode(&file.proof).map_err(error_of)?;
+    verify_detached(&params, &vk, None, &bytes, &[&[digest]])
+        .map_err(|e| error(format!("{proof}: invalid: {e:?}")))?;
+    println!("{proof}: valid");
+    Ok(())
+}
+
+/// `poseidon-cli hash`: prints the digest of the inputs.
+fn run_hash(args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str = "usage: poseidon-cli hash --input <a,b,...>";
+    let [input] = options(args, ["--input"], USAGE)?;
+    let items = input
+        .ok_or_else(|| error(USAGE))?
+        .split(',')
+        .filter(|item| !item.trim().is_empty())
+        .collect::<Vec<_>>();
+    let inputs = parse_fields::<Fr, _>(&items).map_err(error_of)?;
+    println!("{}", to_canonical(&hash(&spec(), &inputs)));
+    Ok(())
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.split_first() {
+        Some((cmd, rest)) if cmd == "setup" => run_setup(rest),
+        Some((cmd, rest)) if cmd == "prove" => run_prove(rest),
+        Some((cmd, rest)) if cmd == "verify" => run_verify(rest),
+        Some((cmd, rest)) if cmd == "hash" => run_hash(rest),
+        _ => Err(error(USAGE)),
+    }
+}
@@ -1,175 +1,1387 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    net::TcpListener,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
 use async_trait::async_trait;
-use base64::{engine::general_purpose::STANDARD as BS64, Engine};
-use ff::PrimeField;
-use halo2_proofs::{
-    plonk::{self, create_proof, keygen_pk, keygen_vk, verify_proof},
-    poly::kzg::{
-        commitment::{KZGCommitmentScheme, ParamsKZG},
-        multiopen::{ProverGWC, VerifierGWC},
-        strategy::SingleStrategy,
-    },
-    transcript::{
-        Blake2bRead, Blake2bWrite, Challenge255, TranscriptReadBuffer, TranscriptWriterBuffer,
-    },
+use halo2curves::bn256::Fr;
+use poseidon::Spec;
+#[cfg(feature = "s3")]
+use poseidon_circuit::artifacts::{S3Config, S3Store};
+#[cfg(feature = "grpc")]
+use poseidon_circuit::grpc::GrpcProver;
+use poseidon_circuit::{
+    affinity::{Pinning, Placement},
+    artifacts::{ArtifactError, ArtifactStore, LocalStore, STALE_TEMP_AGE},
+    batching::{BatchCollector, ClosedBatch},
+    callback::{Callback, RetryPolicy},
+    capabilities::Capabilities,
+    cost,
+    error::Error,
+    field_encoding::parse_fields,
+    http::{self, HttpUrl},
+    jobs::{JobConfig, JobQueue},
+    key_cache::DEFAULT_KEY_CACHE_CAPACITY,
+    keygen::{self, KeygenConfig},
+    limits::MessageLimits,
+    loadtest::{self, parse_duration, LoadConfig},
+    mem_stats::MemoryProfiler,
+    payload::{LocalFiles, PayloadSource},
+    proof_store::ProofStore,
+    prover::{deployment_salt, verify_all, VerifyOutcome},
+    replay, schema,
+    service_config::{check_backend, parse_proof_types, ServiceConfig},
+    spec_bench,
+    specs::{SpecParams, BN256_T4_R3, SPECS},
+    stage::Stage,
+    state::{LatencyProfile, ProverState},
+    task::{ParseMode, ProofDetail, ProofType, Task},
+    trace,
+    vk_cache::DEFAULT_VK_CACHE_CAPACITY,
+    witness_bench,
 };
-use halo2curves::bn256::{Bn256, Fr, G1Affine};
-use poseidon_circuit::test_circuit;
-use rand_core::OsRng;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use snarkify_sdk::prover::ProofHandler;
 
+#[cfg(feature = "mem-stats")]
+#[global_allocator]
+static GLOBAL: poseidon_circuit::mem_stats::TrackingAllocator =
+    poseidon_circuit::mem_stats::TrackingAllocator;
+
+/// Degree of the circuits served by this binary, unless [`PROVER_K_ENV`] sets another.
+const K: u32 = 10;
+
+/// Service config file read at startup, see [`poseidon_circuit::service_config`]; overridden
+/// by a leading `--config <path>`. Every environment variable below overrides the setting of
+/// the file it corresponds to.
+const SERVICE_CONFIG_ENV: &str = "SERVICE_CONFIG";
+
+/// Degree of the hash, preimage and range circuits; `k` in the config, [`K`] if neither.
+const PROVER_K_ENV: &str = "PROVER_K";
+
+/// Proving backend, one of [`poseidon_circuit::capabilities::BACKENDS`]; `backend` in the
+/// config.
+const BACKEND_ENV: &str = "PROVING_BACKEND";
+
+/// Comma-separated proof types answered, e.g. `chunk,batch`; `proof_types` in the config.
+/// Unset with neither answers all of them.
+const PROOF_TYPES_ENV: &str = "PROOF_TYPES";
+
+/// Address to serve the capability document on, e.g. `0.0.0.0:8081`; unset disables it.
+const CAPABILITIES_ADDR_ENV: &str = "CAPABILITIES_ADDR";
+
+/// Address serving `GET /metrics` in the Prometheus text format, e.g. `0.0.0.0:9090`; unset
+/// disables it. See [`poseidon_circuit::telemetry`].
+const METRICS_ADDR_ENV: &str = "METRICS_ADDR";
+
+/// Filter of the spans and events logged to stderr as JSON lines, e.g. `info`; unset logs
+/// nothing.
+const LOG_FILTER_ENV: &str = "RUST_LOG";
+
+/// CPUs the prover threads run on: a cpu list such as `0-15,32-47`, or `node:<n>` for the
+/// CPUs of a NUMA node; unset leaves placement to the scheduler.
+const PROVER_CPUS_ENV: &str = "PROVER_CPUS";
+
+/// Number of prover threads; unset runs one per CPU of [`PROVER_CPUS_ENV`], or per core.
+const PROVER_THREADS_ENV: &str = "PROVER_THREADS";
+
+/// `core` pins every prover thread to one CPU of [`PROVER_CPUS_ENV`]; unset or `set` lets
+/// threads move between them.
+const PROVER_PINNING_ENV: &str = "PROVER_PINNING";
+
+/// Params file to load at startup, e.g. a `.srs` written by `ParamsKZG::write`; overridden
+/// by a leading `--params <path>` and, with neither, insecure test params are generated.
+const PARAMS_PATH_ENV: &str = "PARAMS_PATH";
+
+/// Directory of `<vk hash>.vk` files verifying proofs of earlier circuit versions.
+const VK_STORE_DIR_ENV: &str = "VK_STORE_DIR";
+
+/// Directory persisting the keys of message lengths the service is not keyed for at startup,
+/// read back on the next start; unset keeps them in memory only.
+const KEY_CACHE_DIR_ENV: &str = "KEY_CACHE_DIR";
+
+/// Where keys and params are shared across the fleet: a directory, `file:///<dir>`, or
+/// `s3://<bucket>[/<prefix>]` with the `s3` feature; keys of earlier circuit versions are
+/// read from it like from [`VK_STORE_DIR_ENV`].
+const ARTIFACT_STORE_ENV: &str = "ARTIFACT_STORE";
+
+/// Key of the params in the artifact store, read at startup instead of [`PARAMS_PATH_ENV`].
+const PARAMS_KEY_ENV: &str = "PARAMS_KEY";
+
+/// `http://` endpoint of the S3-compatible store of `s3://` artifact stores.
+#[cfg(feature = "s3")]
+const S3_ENDPOINT_ENV: &str = "S3_ENDPOINT";
+
+/// `false` returns proofs without verifying them first; unset or `true` verifies every proof
+/// and records the outcome in its proof detail.
+const SANITY_CHECK_ENV: &str = "SANITY_CHECK";
+
+/// `interactive` proves preimage tasks on params trimmed to their circuit, under a preimage
+/// key of its own; unset or `standard` keeps the defaults. Either keeps [`SANITY_CHECK_ENV`].
+const LATENCY_PROFILE_ENV: &str = "LATENCY_PROFILE";
+
+/// Most bytes a proof may take, or its calldata for EVM proofs; tasks estimated to take
+/// more are rejected before proving. Unset proves tasks of any size.
+const MAX_PROOF_SIZE_ENV: &str = "MAX_PROOF_SIZE";
+
+/// Most rows, as `k` of `2^k`, a task's circuit may take; tasks needing more fail with
+/// `resource_limit_exceeded` before keygen. Unset allows what the params hold.
+const MAX_K_ENV: &str = "MAX_K";
+
+/// Most elements of a chunk or batch message, e.g. 4096; longer ones fail with
+/// `resource_limit_exceeded` before synthesis. Unset allows what the params hold.
+const MAX_MESSAGE_LEN_ENV: &str = "MAX_MESSAGE_LEN";
+
+/// How long a task may run, e.g. `10m`; tasks still running then fail with
+/// `resource_limit_exceeded` at the end of the stage they are in. Unset never stops them.
+const TASK_TIMEOUT_ENV: &str = "TASK_TIMEOUT";
+
+/// Permutations of message chunks kept for tasks sharing a prefix, see
+/// [`ProverState::with_trace_cache`], e.g. 4096 taking 32 MiB; unset computes every one.
+const TRACE_CACHE_ENTRIES_ENV: &str = "TRACE_CACHE_ENTRIES";
+
+/// Protocol label absorbed into every transcript and recorded in every proof detail.
+const PROTOCOL_LABEL_ENV: &str = "PROTOCOL_LABEL";
+
+/// Transcript of tasks that do not name one: `blake2b`, `keccak256` or `poseidon`; unset
+/// proves them in Blake2b. `--transcript` overrides it.
+const TRANSCRIPT_ENV: &str = "TRANSCRIPT";
+
+/// Deployment name whose salt is absorbed into every transcript; unset leaves proofs unsalted.
+const DEPLOYMENT_SALT_ENV: &str = "DEPLOYMENT_SALT";
+
+/// `strict` rejects tasks with unknown or missing fields; unset or `lenient` defaults them.
+const TASK_PARSE_MODE_ENV: &str = "TASK_PARSE_MODE";
+
+/// Directory serving the `file:///` payloads of tasks sent with `task_data_ref`; unset
+/// rejects such tasks.
+const PAYLOAD_DIR_ENV: &str = "PAYLOAD_DIR";
+
+/// Comma-separated hosts that task `callback_url`s may point to; unset rejects callbacks.
+const CALLBACK_HOSTS_ENV: &str = "CALLBACK_HOSTS";
+
+/// Key signing callback bodies; unset sends them unsigned.
+const CALLBACK_SECRET_ENV: &str = "CALLBACK_SECRET";
+
+/// Collects chunk tasks naming a batch and proves each batch once it is complete or this
+/// long, e.g. `5m`, after its first chunk; see [`poseidon_circuit::batching`]. Unset proves
+/// chunks alone.
+const BATCH_TIMEOUT_ENV: &str = "BATCH_TIMEOUT";
+
+/// Regenerates the keys of the most frequent message lengths once the service has been
+/// idle this long, e.g. `30s`, if keys of rarer lengths evicted them; unset only generates
+/// keys when a task needs them.
+const PREWARM_IDLE_ENV: &str = "PREWARM_IDLE";
+
+/// Params of the aggregation circuit, e.g. of degree 22, for batch tasks sending chunk
+/// proofs; see [`poseidon_circuit::aggregation`]. Unset rejects such tasks.
+#[cfg(feature = "aggregation")]
+const AGGREGATION_PARAMS_PATH_ENV: &str = "AGGREGATION_PARAMS_PATH";
+
+/// Number of workers proving tasks in the background: tasks are then answered at once with
+/// the `uuid` to poll on [`JOBS_ADDR_ENV`], see [`poseidon_circuit::jobs`]. Unset proves
+/// every task within its request.
+const PROVER_WORKERS_ENV: &str = "PROVER_WORKERS";
+
+/// Tasks waiting for a worker of [`PROVER_WORKERS_ENV`] before new ones are rejected; 64 if
+/// unset.
+const JOB_QUEUE_CAPACITY_ENV: &str = "JOB_QUEUE_CAPACITY";
+
+/// Address answering `GET /jobs/<uuid>` with the status of a queued task, e.g.
+/// `0.0.0.0:8082`; unset disables it.
+const JOBS_ADDR_ENV: &str = "JOBS_ADDR";
+
+/// Address serving the prover over gRPC, e.g. `0.0.0.0:50051`, see
+/// [`poseidon_circuit::grpc`]; its tasks are proven by the workers of [`PROVER_WORKERS_ENV`],
+/// or by a worker of its own if that is unset. Unset disables it.
+#[cfg(feature = "grpc")]
+const GRPC_ADDR_ENV: &str = "GRPC_ADDR";
+
+/// Where proof details and the verifying keys of their proofs are kept across restarts: a
+/// directory, `file:///<dir>` or `s3://<bucket>[/<prefix>]` as for [`ARTIFACT_STORE_ENV`].
+/// Tasks of the same content as one proven before are answered from it, see
+/// [`poseidon_circuit::proof_store`]; unset keeps nothing.
+const PROOF_STORE_ENV: &str = "PROOF_STORE";
+
+/// Address answering `GET /proofs/<uuid>` and `GET /proofs/digest/<task digest>` with the
+/// kept proof detail of a task, e.g. `0.0.0.0:8083`; needs [`PROOF_STORE_ENV`].
+const PROOFS_ADDR_ENV: &str = "PROOFS_ADDR";
+
+/// Hosts and signing key of callbacks, set once in [`main`].
+static CALLBACKS: OnceLock<(Vec<String>, Option<Vec<u8>>)> = OnceLock::new();
+
+/// Where referenced payloads are read from, set once in [`main`].
+static PAYLOAD_SOURCE: OnceLock<LocalFiles> = OnceLock::new();
+
+/// How incoming tasks are parsed, set once in [`main`].
+static PARSE_MODE: OnceLock<ParseMode> = OnceLock::new();
+
+/// Open batches, set once in [`main`] if [`BATCH_TIMEOUT_ENV`] is.
+static BATCHES: OnceLock<BatchCollector> = OnceLock::new();
+
+/// Proofs kept across restarts, opened once in [`main`] if [`PROOF_STORE_ENV`] is set.
+static PROOFS: OnceLock<ProofStore> = OnceLock::new();
+
+/// Service state shared by all requests, built once in [`main`] before serving.
+static STATE: OnceLock<Arc<ProverState>> = OnceLock::new();
+
+/// Workers proving queued tasks, started in [`main`] if [`PROVER_WORKERS_ENV`] is set.
+static JOBS: OnceLock<JobQueue> = OnceLock::new();
+
 /// A prover for Poseidon hashes using the Halo2 proving system.
 struct PoseidonProver;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum ProofType {
-    Undefined,
-    Chunk,
-    Batch,
+#[async_trait]
+impl ProofHandler for PoseidonProver {
+    /// Raw task JSON, parsed in the configured [`ParseMode`]
+    type Input = serde_json::Value;
+    type Output = ProofDetail;
+    type Error = Error;
+
+    /// Generates a zk-SNARK proof for the Poseidon hash function.
+    ///
+    /// Given a task whose `task_data` holds the hashed message, this function proves it with
+    /// the keys generated at startup and then verifies that proof, ultimately returning
+    /// the proof in the form of a Base64-encoded string along with its public inputs.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The task JSON, see [`Task`] and [`ProverState::prove`] for the
+    ///   `task_data` of every proof type; or `{"id": ..., "verify": <proof detail>}` to
+    ///   verify a proof instead, see [`verify_request`].
+    ///
+    /// # Returns
+    ///
+    /// A [`ProofDetail`] in every case: with the proof if all stages succeeded, otherwise
+    /// with `error`, `error_code` and `failed_stage` conveying the nature and stage of the
+    /// failure, see [`Error`]. With [`PROVER_WORKERS_ENV`], one with `job` set instead, see
+    /// [`enqueue`].
+    ///
+    /// Fetching payloads, proving and verifying block, so they run on the blocking pool of
+    /// the runtime rather than on the thread serving requests, see [`respond`].
+    async fn prove(input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let id = input["id"].as_str().unwrap_or_default().to_string();
+        let detail = tokio::task::spawn_blocking(move || respond(input))
+            .await
+            .unwrap_or_else(|_| {
+                ProofDetail {
+                    id,
+                    ..Default::default()
+                }
+                .with_error(
+                    Stage::Prove,
+                    &Error::new(Stage::Prove, "the prover panicked"),
+                )
+            });
+        Ok(detail)
+    }
 }
 
-impl ProofType {
-    fn from_u8(v: u8) -> Self {
-        match v {
-            1 => ProofType::Chunk,
-            2 => ProofType::Batch,
-            _ => ProofType::Undefined,
+/// The answer to the request `input` of [`PoseidonProver::prove`], computed on the calling
+/// thread.
+fn respond(input: serde_json::Value) -> ProofDetail {
+    let id = input["id"].as_str().unwrap_or_default().to_string();
+    if let Some(detail) = input.get("verify") {
+        return verify_request(id, detail.clone());
+    }
+    match read_task(input) {
+        Ok(task) => match JOBS.get() {
+            Some(jobs) => enqueue(jobs, task),
+            None => answer(&task),
+        },
+        Err(error) => {
+            let state = STATE.get().expect("state is set");
+            state.metrics().record_task(Some(Stage::Parse));
+            parse_failure(id, error)
         }
     }
 }
 
-impl Serialize for ProofType {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match *self {
-            ProofType::Undefined => serializer.serialize_i8(0),
-            ProofType::Chunk => serializer.serialize_i8(1),
-            ProofType::Batch => serializer.serialize_i8(2),
+/// Parses a task in the configured [`ParseMode`], refusing callbacks outside of
+/// [`CALLBACK_HOSTS_ENV`] and fetching a referenced payload.
+fn read_task(input: serde_json::Value) -> Result<Task, String> {
+    let mode = PARSE_MODE.get().copied().unwrap_or_default();
+    let source = PAYLOAD_SOURCE.get().map(|s| s as &dyn PayloadSource);
+    let mut task = Task::from_value(input, mode).map_err(|err| err.to_string())?;
+    callback(&task)?;
+    task.resolve_task_data(source)
+        .map_err(|err| err.to_string())?;
+    Ok(task)
+}
+
+/// Checks the proof of the proof detail `detail`, of any schema version: its `proof_data`,
+/// `instances`, and `proof_type` and `vk_hash` naming the circuit and key. Answers with the
+/// detail under `id`, see [`ProverState::check`].
+fn verify_request(id: String, detail: serde_json::Value) -> ProofDetail {
+    let state = STATE.get().expect("state is set");
+    let detail = schema::upgrade_proof_detail(detail)
+        .map_err(|err| err.to_string())
+        .and_then(|value| {
+            serde_json::from_value::<ProofDetail>(value).map_err(|err| err.to_string())
+        });
+    match detail {
+        Ok(detail) => ProofDetail {
+            id,
+            ..state.check(&detail)
+        },
+        Err(error) => parse_failure(id, error),
+    }
+}
+
+/// The answer to a request that could not be read.
+fn parse_failure(id: String, error: String) -> ProofDetail {
+    let error = Error::Deserialization { message: error };
+    ProofDetail {
+        id,
+        ..Default::default()
+    }
+    .with_error(Stage::Parse, &error)
+}
+
+/// The callback `task` names, if any, refusing hosts outside of [`CALLBACK_HOSTS_ENV`].
+fn callback(task: &Task) -> Result<Option<Callback>, String> {
+    task.callback_url
+        .as_deref()
+        .map(|url| {
+            let hosts = CALLBACKS.get().map(|(hosts, _)| &hosts[..]);
+            Callback::parse(url, hosts.unwrap_or_default())
+        })
+        .transpose()
+        .map_err(|err| err.to_string())
+}
+
+/// Proves `task`, delivering the proof detail to its callback and recording it in its
+/// batch.
+fn answer(task: &Task) -> ProofDetail {
+    let detail = match PROOFS.get() {
+        Some(proofs) => prove_once(proofs, task),
+        None => handle(task),
+    };
+    // the callback was checked when the task was parsed
+    if let Ok(Some(callback)) = callback(task) {
+        notify(callback, detail.clone());
+    }
+    collect(task, &detail);
+    detail
+}
+
+/// Answers `task` with the kept proof of a task of the same content and protocol label, or
+/// proves it and keeps the proof, see [`PROOF_STORE_ENV`].
+fn prove_once(proofs: &ProofStore, task: &Task) -> ProofDetail {
+    let state = STATE.get().expect("state is set");
+    let label = state.protocol_label().unwrap_or_default();
+    match proofs.lookup(task) {
+        Ok(Some(detail)) if detail.protocol_label == label => return detail,
+        Ok(_) => {}
+        Err(err) => eprintln!("task {}: {err}", task.id),
+    }
+    let detail = handle(task);
+    if let Err(err) = keep(proofs, state, task, &detail) {
+        eprintln!("task {}: keeping the proof failed: {err}", task.id);
+    }
+    detail
+}
+
+/// Keeps the proof of `detail` with the hash of its key, and the key if it is a current one.
+fn keep(
+    proofs: &ProofStore,
+    state: &ProverState,
+    task: &Task,
+    detail: &ProofDetail,
+) -> Result<(), ArtifactError> {
+    // an empty `vk_hash` names the current key, which need not be current once read back
+    let circuit = detail.circuit.as_ref().map(|circuit| circuit.name.as_str());
+    let current = circuit
+        .filter(|_| detail.vk_hash.is_empty())
+        .and_then(|name| state.vk_hash_of(name));
+    let kept = ProofDetail {
+        vk_hash: current.map_or_else(|| detail.vk_hash.clone(), str::to_string),
+        ..detail.clone()
+    };
+    if !proofs.record(task, &kept)? {
+        return Ok(());
+    }
+    match (circuit, current) {
+        (Some(name), Some(vk_hash)) => {
+            proofs.record_vk(vk_hash, || state.export_vk(name).map(|export| export.vk))
         }
+        _ => Ok(()),
     }
 }
 
-impl<'de> Deserialize<'de> for ProofType {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let v: u8 = u8::deserialize(deserializer)?;
-        Ok(ProofType::from_u8(v))
+/// Queues `task` for the workers of [`PROVER_WORKERS_ENV`], who [`answer`] it. Answers with
+/// the `uuid` to poll as `job`, or why the queue refused the task.
+fn enqueue(jobs: &JobQueue, task: Task) -> ProofDetail {
+    let (id, proof_type, uuid) = (task.id.clone(), task.task_type, task.uuid.clone());
+    let queued = if uuid.is_empty() {
+        Err("queued tasks need a uuid to poll".to_string())
+    } else {
+        jobs.submit(task).map_err(|err| err.to_string())
+    };
+    match queued {
+        Ok(()) => ProofDetail {
+            id,
+            proof_type,
+            job: Some(uuid),
+            ..Default::default()
+        },
+        Err(message) => ProofDetail {
+            id,
+            proof_type,
+            ..Default::default()
+        }
+        .with_error(Stage::Parse, &Error::InvalidTask { message }),
     }
 }
 
-impl Default for ProofType {
-    fn default() -> Self {
-        Self::Undefined
+/// Delivers `detail` to `callback` in the background, so retries do not hold the request.
+fn notify(callback: Callback, detail: ProofDetail) {
+    std::thread::spawn(move || {
+        let secret = CALLBACKS.get().and_then(|(_, secret)| secret.as_deref());
+        if let Err(err) = callback.deliver(&detail, secret, &RetryPolicy::default()) {
+            eprintln!("task {}: {err}", detail.id);
+        }
+    });
+}
+
+/// Records a proven chunk in its batch, proving the batch in the background if the chunk
+/// completes it.
+fn collect(task: &Task, detail: &ProofDetail) {
+    let Some(batches) = BATCHES.get() else {
+        return;
+    };
+    if task.task_type != ProofType::Chunk || task.batch.is_none() || !detail.error.is_empty() {
+        return;
+    }
+    let Some(digest) = parse_fields::<Fr, _>(&detail.instances)
+        .ok()
+        .and_then(|instances| instances.first().copied())
+    else {
+        return;
+    };
+    match batches.add(task, digest, Instant::now()) {
+        Ok(Some(batch)) => {
+            std::thread::spawn(move || emit(batch));
+        }
+        Ok(None) => {}
+        Err(err) => eprintln!("task {}: {err}", task.id),
     }
 }
 
-/// Represents the inputs to the Poseidon Circuit
-///
-/// This struct is designed to capture the necessary inputs for the
-/// Poseidon hash circuit.
-#[derive(Serialize, Deserialize, Default)]
-pub struct Task {
-    /// The private_input vector, representing the hash input
-    ///
-    /// These inputs are part of the witness
-    pub uuid: String,
-    pub id: String,
-    #[serde(rename = "type", default)]
-    pub task_type: ProofType,
-    pub task_data: String,
-    #[serde(default)]
-    pub hard_fork_name: String,
+/// Proves a closed batch, or reports it if it is incomplete, and delivers the proof detail
+/// to the callback of the batch, or prints it if there is none.
+fn emit(batch: ClosedBatch) {
+    let callback_url = batch.task.callback_url.clone();
+    let detail = match batch.complete() {
+        Ok(task) => handle(&task),
+        Err(err) => ProofDetail {
+            id: err.batch().to_string(),
+            proof_type: ProofType::Batch,
+            ..Default::default()
+        }
+        .with_error(Stage::Witness, &Error::new(Stage::Witness, err)),
+    };
+    let hosts = CALLBACKS.get().map(|(hosts, _)| &hosts[..]);
+    match callback_url.map(|url| Callback::parse(&url, hosts.unwrap_or_default())) {
+        Some(Ok(callback)) => notify(callback, detail),
+        Some(Err(err)) => eprintln!("batch {}: {err}", detail.id),
+        None => match serde_json::to_string(&detail) {
+            Ok(json) => println!("{json}"),
+            Err(err) => eprintln!("batch {}: {err}", detail.id),
+        },
+    }
 }
 
-#[derive(Serialize, Deserialize, Default)]
-pub struct ProofDetail {
-    pub id: String,
-    #[serde(rename = "type", default)]
-    pub proof_type: ProofType,
-    pub proof_data: String,
-    pub error: String,
+/// Closes the batches whose timer fired, every tenth of [`BATCH_TIMEOUT_ENV`].
+fn run_batch_timer(batches: &'static BatchCollector) {
+    let tick = (batches.timeout() / 10).max(Duration::from_millis(100));
+    std::thread::spawn(move || loop {
+        std::thread::sleep(tick);
+        for batch in batches.expire(Instant::now()) {
+            emit(batch);
+        }
+    });
 }
 
-#[async_trait]
-impl ProofHandler for PoseidonProver {
-    type Input = Task;
-    type Output = ProofDetail;
-    type Error = Error;
+/// Prewarms keys whenever the service has been idle for `idle`, see
+/// [`ProverState::prewarm`].
+fn run_prewarmer(state: Arc<ProverState>, idle: Duration) {
+    let tick = (idle / 10).max(Duration::from_millis(100));
+    std::thread::spawn(move || loop {
+        std::thread::sleep(tick);
+        if !state.idle_for().is_some_and(|idle_for| idle_for >= idle) {
+            continue;
+        }
+        match state.prewarm(DEFAULT_KEY_CACHE_CAPACITY) {
+            Ok(shapes) => {
+                for shape in shapes {
+                    eprintln!(
+                        "prewarmed keys of {} elements under {:?} for {:?}",
+                        shape.len, shape.domain, shape.hard_fork
+                    );
+                }
+            }
+            Err(err) => eprintln!("prewarming failed: {}", err.error),
+        }
+    });
+}
 
-    /// Generates a zk-SNARK proof for the Poseidon hash function.
-    ///
-    /// Given an [`Input`] instance containing the private and public inputs,
-    /// this function goes through the steps of setting up the proving parameters,
-    /// generating a proof, and then verifying that proof, ultimately returning
-    /// a serialized proof in the form of a Base64-encoded string.
-    ///
-    /// # Arguments
-    ///
-    /// * `input` - An `Input` struct containing:
-    ///   - `private_input`: A `Vec<u64>` representing the private part of the input to the hash function.
-    ///   - `public_input`: A `String` representing the expected hash output in the field `Fp`.
-    ///
-    /// # Returns
-    ///
-    /// If successful, it returns `Ok(String)` where the string is the Base64-encoded
-    /// representation of the generated zk-SNARK proof. If any step in the proof generation
-    /// or verification fails, it returns an `Err(Error)`, which captures and conveys
-    /// the specific stage and nature of the failure.
-    async fn prove(input: Self::Input) -> Result<Self::Output, Self::Error> {
-        Ok(ProofDetail {
+/// Answers one task; shared by the service handler and `replay`.
+///
+/// Proves with the keys generated at startup and verifies the proof before answering, see
+/// [`ProverState::prove`]; `error` is only set if a stage failed.
+fn handle(input: &Task) -> ProofDetail {
+    let state = STATE.get().expect("state is set");
+    let mut profiler = MemoryProfiler::new();
+    let proven = profiler.measure(Stage::Prove, || state.prove(input));
+    let detail = match proven {
+        Ok(proof) => proof.detail(input),
+        Err(err) => ProofDetail {
             id: input.id.clone(),
             proof_type: input.task_type,
-            proof_data: "proof".to_string(),
-            error: "error".to_string(),
-        })
+            constraint_failures: err.constraint_failures,
+            instance_layout: input.instance_layout,
+            verification: err.verification,
+            ..Default::default()
+        }
+        .with_error(err.stage, &err.error),
+    };
+    ProofDetail {
+        protocol_label: state.protocol_label().unwrap_or_default().to_string(),
+        memory: cfg!(feature = "mem-stats").then(|| profiler.finish()),
+        metadata: Some(state.metadata().clone()),
+        ..detail
     }
 }
 
-/// Enumerates the potential errors that can occur within the [`PoseidonProver`].
-///
-/// This error enum captures the various points of failure that could occur
-/// during the setup, proof generation, and verification steps of the Poseidon
-/// proving process.
-///
-/// Note: The [`plonk::Error`] type is not serializable, hence we convert it to a string
-/// to capture the error information. This workaround allows us to include `plonk::Error`
-/// information in a serializable format.
-#[derive(Serialize)]
-pub enum Error {
-    WhileKeygenVk { plonk_error: String },
-    WhileKeygenPk { plonk_error: String },
-    PubInputOutOfField { public_input: String },
-    WhileProve { plonk_error: String },
-    WhileVerify { plonk_error: String },
+/// `snarkify replay --log <tasks.jsonl>`: re-executes recorded tasks and diffs the results.
+fn run_replay(args: &[String]) -> Result<(), std::io::Error> {
+    let log = match args {
+        [flag, path] if flag == "--log" => path,
+        _ => {
+            return Err(std::io::Error::other(
+                "usage: snarkify replay --log <tasks.jsonl>",
+            ))
+        }
+    };
+    let log = BufReader::new(File::open(log)?);
+    let report = replay::replay(log, handle).map_err(std::io::Error::other)?;
+    println!("{report}");
+    match report.regressions().count() {
+        0 => Ok(()),
+        n => Err(std::io::Error::other(format!("{n} regressions"))),
+    }
+}
+
+/// `snarkify loadtest --rps <n> --duration <10m> --profile <chunk> [--target <url>]`: submits
+/// synthetic tasks to the prover at `url`, or to this process without one, and prints
+/// latency percentiles and error rates.
+fn run_loadtest(args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str =
+        "usage: snarkify loadtest --rps <n> --duration <10m> --profile <chunk> [--target <url>]";
+    let usage = || std::io::Error::other(USAGE);
+    let (mut rps, mut duration, mut profile, mut target) = (None, None, None, None);
+    for pair in args.chunks(2) {
+        let [flag, value] = pair else {
+            return Err(usage());
+        };
+        match flag.as_str() {
+            "--rps" => rps = value.parse::<f64>().ok().filter(|rps| *rps > 0.0),
+            "--duration" => duration = loadtest::parse_duration(value).ok(),
+            "--profile" => profile = value.parse().ok(),
+            "--target" => target = Some(HttpUrl::parse(value).ok_or_else(usage)?),
+            _ => return Err(usage()),
+        }
+    }
+    let config = LoadConfig {
+        rps: rps.ok_or_else(usage)?,
+        duration: duration.ok_or_else(usage)?,
+        profile: profile.ok_or_else(usage)?,
+    };
+    let report = match target {
+        Some(url) => loadtest::run(&config, |task| {
+            let body = serde_json::to_vec(task).map_err(|err| err.to_string())?;
+            let response = url
+                .post_json(&body, &[], Duration::from_secs(600))
+                .map_err(|err| err.kind().to_string())?;
+            if !response.is_success() {
+                return Err(format!("status {}", response.status));
+            }
+            let detail = serde_json::from_slice::<serde_json::Value>(&response.body)
+                .map_err(|_| "malformed response".to_string())?;
+            match detail["error"].as_str() {
+                Some(error) if !error.is_empty() => Err(error.to_string()),
+                _ => Ok(()),
+            }
+        }),
+        None => loadtest::run(&config, |task| match handle(task).error {
+            error if error.is_empty() => Ok(()),
+            error => Err(error),
+        }),
+    };
+    println!("{report}");
+    Ok(())
+}
+
+/// `snarkify trace --input 1,2,3 [--spec default] [--json]`: prints the state after every
+/// round of every permutation of the hash of the inputs, see [`trace::hash_trace`].
+fn run_trace(args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str = "usage: snarkify trace --input <a,b,...> [--spec <id>] [--json]";
+    let usage = || std::io::Error::other(USAGE);
+    let (mut input, mut spec, mut json) = (None, "default", false);
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--input" => input = Some(args.next().ok_or_else(usage)?),
+            "--spec" => spec = args.next().ok_or_else(usage)?,
+            "--json" => json = true,
+            _ => return Err(usage()),
+        }
+    }
+    let input = input.ok_or_else(usage)?;
+    let items = input.split(',').filter(|item| !item.trim().is_empty());
+    let inputs =
+        parse_fields::<Fr, _>(&items.collect::<Vec<_>>()).map_err(std::io::Error::other)?;
+    let trace = match spec {
+        spec if spec == "default" || spec == BN256_T4_R3.id => {
+            const SPEC: SpecParams = BN256_T4_R3;
+            let spec = Spec::<Fr, { SPEC.width }, { SPEC.rate }>::new(SPEC.r_f, SPEC.r_p);
+            trace::hash_trace(&spec, &inputs)
+        }
+        _ => {
+            let ids = SPECS.iter().map(|spec| spec.id).collect::<Vec<_>>();
+            return Err(std::io::Error::other(format!(
+                "unknown spec {spec:?}, expected default or one of {}",
+                ids.join(", ")
+            )));
+        }
+    };
+    if json {
+        println!("{}", trace.to_json());
+    } else {
+        print!("{trace}");
+    }
+    Ok(())
+}
+
+/// `snarkify bench-specs [--len <n>] [--json]`: proves the same message under every vetted
+/// spec and with Poseidon2, and prints rows, proof size and timings side by side.
+fn run_bench_specs(args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str = "usage: snarkify bench-specs [--len <n>] [--json]";
+    let usage = || std::io::Error::other(USAGE);
+    let (mut len, mut json) = (8, false);
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--len" => len = args.next().and_then(|n| n.parse().ok()).ok_or_else(usage)?,
+            "--json" => json = true,
+            _ => return Err(usage()),
+        }
+    }
+    let report = spec_bench::benchmark_all(len).map_err(std::io::Error::other)?;
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        print!("{report}");
+    }
+    Ok(())
+}
+
+/// `snarkify bench-witness [--hashes <n>] [--len <n>] [--json]`: times the witness of many
+/// hashes serially and on the rayon pool, see [`poseidon_circuit::witness_bench`].
+fn run_bench_witness(args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str = "usage: snarkify bench-witness [--hashes <n>] [--len <n>] [--json]";
+    let usage = || std::io::Error::other(USAGE);
+    let (mut hashes, mut len, mut json) = (4096, 8, false);
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let mut number = || args.next().and_then(|n| n.parse().ok()).ok_or_else(usage);
+        match flag.as_str() {
+            "--hashes" => hashes = number()?,
+            "--len" => len = number()?,
+            "--json" => json = true,
+            _ => return Err(usage()),
+        }
+    }
+    let result = witness_bench::benchmark(hashes, len);
+    if json {
+        println!("{}", result.to_json());
+    } else {
+        print!("{result}");
+    }
+    Ok(())
+}
+
+/// `snarkify cost --len <n> [--hashes <n>] [--width <t>] [--json]`: prints the columns, rows
+/// and smallest `k` of a circuit hashing `hashes` messages of `len` elements, see
+/// [`poseidon_circuit::cost`].
+fn run_cost(args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str = "usage: snarkify cost --len <n> [--hashes <n>] [--width <t>] [--json]";
+    let usage = || std::io::Error::other(USAGE);
+    let (mut len, mut hashes, mut width, mut json) = (None, 1, 4, false);
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let mut number = || args.next().and_then(|n| n.parse().ok()).ok_or_else(usage);
+        match flag.as_str() {
+            "--len" => len = Some(number()?),
+            "--hashes" => hashes = number()?,
+            "--width" => width = number()?,
+            "--json" => json = true,
+            _ => return Err(usage()),
+        }
+    }
+    let cost = cost::estimate_width(width, hashes, len.ok_or_else(usage)?).ok_or_else(|| {
+        std::io::Error::other(format!("no vetted spec of width {width} is enabled"))
+    })?;
+    if json {
+        println!("{}", cost.to_json());
+    } else {
+        print!("{cost}");
+    }
+    Ok(())
 }
 
-impl Error {
-    fn while_keygen_vk(err: plonk::Error) -> Self {
-        Self::WhileKeygenVk {
-            plonk_error: format!("{err:?}"),
+/// `snarkify evm-verifier --circuit <chunk|preimage> --out <file.yul>`: writes the Yul
+/// verifier contract of a circuit of the service, see [`poseidon_circuit::evm`].
+fn run_evm_verifier(state: &ProverState, args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str = "usage: snarkify evm-verifier --circuit <chunk|preimage> --out <file.yul>";
+    let usage = || std::io::Error::other(USAGE);
+    let (mut circuit, mut out) = ("chunk", None);
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--circuit" => circuit = args.next().ok_or_else(usage)?,
+            "--out" => out = Some(args.next().ok_or_else(usage)?),
+            _ => return Err(usage()),
         }
     }
-    fn while_keygen_pk(err: plonk::Error) -> Self {
-        Self::WhileKeygenPk {
-            plonk_error: format!("{err:?}"),
+    let out = out.ok_or_else(usage)?;
+    #[cfg(feature = "evm-verifier")]
+    {
+        use poseidon_circuit::evm::verifier_yul;
+
+        // both expose a single digest
+        let yul = match circuit {
+            "chunk" => verifier_yul(state.test_circuit(), 1),
+            "preimage" => verifier_yul(state.preimage(), 1),
+            _ => return Err(usage()),
         }
+        .map_err(std::io::Error::other)?;
+        std::fs::write(out, yul)?;
+        println!("wrote the {circuit} verifier to {out}");
+        Ok(())
+    }
+    #[cfg(not(feature = "evm-verifier"))]
+    {
+        let _ = (state, circuit, out);
+        Err(std::io::Error::other(
+            "snarkify evm-verifier needs the evm-verifier feature",
+        ))
     }
-    fn while_prove(err: plonk::Error) -> Self {
-        Self::WhileProve {
-            plonk_error: format!("{err:?}"),
+}
+
+/// `snarkify export-vk --circuit <name> --out <file>`: writes the verifying key of a circuit
+/// of the capability document in the format of [`poseidon_circuit::vk_export`].
+fn run_export_vk(state: &ProverState, args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str = "usage: snarkify export-vk --circuit <name> --out <file>";
+    let usage = || std::io::Error::other(USAGE);
+    let (mut circuit, mut out) = (None, None);
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--circuit" => circuit = Some(args.next().ok_or_else(usage)?),
+            "--out" => out = Some(args.next().ok_or_else(usage)?),
+            _ => return Err(usage()),
         }
     }
-    fn while_verify(err: plonk::Error) -> Self {
-        Self::WhileProve {
-            plonk_error: format!("{err:?}"),
+    let (circuit, out) = (circuit.ok_or_else(usage)?, out.ok_or_else(usage)?);
+    let export = state
+        .export_vk(circuit)
+        .ok_or_else(|| std::io::Error::other(format!("no circuit named {circuit:?}")))?;
+    std::fs::write(out, export.encode())?;
+    println!(
+        "wrote the {circuit} verifying key {} for params {} to {out}",
+        export.vk_hash(),
+        export.params_digest
+    );
+    Ok(())
+}
+
+/// Opens the artifact store named by `uri`, see [`ARTIFACT_STORE_ENV`].
+fn open_store(uri: &str) -> Result<Arc<dyn ArtifactStore>, std::io::Error> {
+    if let Some(location) = uri.strip_prefix("s3://") {
+        #[cfg(feature = "s3")]
+        {
+            let env = |name: &str| {
+                std::env::var(name)
+                    .map_err(|_| std::io::Error::other(format!("{uri} needs {name} to be set")))
+            };
+            let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+            let endpoint = env(S3_ENDPOINT_ENV)?;
+            let config = S3Config {
+                endpoint: HttpUrl::parse(&endpoint).ok_or_else(|| {
+                    std::io::Error::other(format!("invalid {S3_ENDPOINT_ENV} {endpoint:?}"))
+                })?,
+                bucket: bucket.to_string(),
+                prefix: match prefix.trim_matches('/') {
+                    "" => String::new(),
+                    prefix => format!("{prefix}/"),
+                },
+                region: env("AWS_REGION")?,
+                access_key: env("AWS_ACCESS_KEY_ID")?,
+                secret_key: env("AWS_SECRET_ACCESS_KEY")?,
+            };
+            return Ok(Arc::new(S3Store::new(config)));
         }
+        #[cfg(not(feature = "s3"))]
+        return Err(std::io::Error::other(format!(
+            "{location}: s3:// artifact stores need the s3 feature"
+        )));
+    }
+    let dir = uri.strip_prefix("file://").unwrap_or(uri);
+    let store = LocalStore::new(dir);
+    let swept = store.sweep(STALE_TEMP_AGE)?;
+    if swept > 0 {
+        eprintln!("{uri}: removed {swept} temp files left over by interrupted writes");
+    }
+    Ok(Arc::new(store))
+}
+
+/// `snarkify keygen-all --config <config.toml> --out <store>`: generates the keys of every
+/// configured circuit variant into a directory or artifact store, see [`open_store`].
+fn run_keygen_all(args: &[String]) -> Result<(), std::io::Error> {
+    const USAGE: &str =
+        "usage: snarkify keygen-all --config <config.toml> --out <dir|s3://bucket/prefix>";
+    let usage = || std::io::Error::other(USAGE);
+    let (mut config, mut out) = (None, None);
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--config" => config = Some(args.next().ok_or_else(usage)?),
+            "--out" => out = Some(args.next().ok_or_else(usage)?),
+            _ => return Err(usage()),
+        }
+    }
+    let (config, out) = (config.ok_or_else(usage)?, out.ok_or_else(usage)?);
+    let config = KeygenConfig::read(config).map_err(std::io::Error::other)?;
+    let store = open_store(out)?;
+    let manifest = keygen::keygen_all(&config, store.as_ref()).map_err(std::io::Error::other)?;
+    for keys in &manifest.variants {
+        println!(
+            "{}: {} (k = {}, {})",
+            keys.name, keys.vk_hash, keys.k, keys.spec
+        );
+    }
+    Ok(())
+}
+
+/// Answers every HTTP request on `addr` with the capability document.
+fn serve_capabilities(addr: &str, capabilities: &Capabilities) -> Result<(), std::io::Error> {
+    let listener = TcpListener::bind(addr)?;
+    let body = capabilities.to_json();
+    std::thread::spawn(move || {
+        // every path gets the same document
+        http::serve(listener, "application/json", move |_| {
+            ("200 OK", body.clone())
+        })
+    });
+    Ok(())
+}
+
+/// Answers `GET /jobs/<uuid>` on `addr` with the [`poseidon_circuit::jobs::JobStatus`] of
+/// the task, and with 404 for tasks the queue does not know.
+fn serve_jobs(addr: &str, jobs: &'static JobQueue) -> Result<(), std::io::Error> {
+    let listener = TcpListener::bind(addr)?;
+    std::thread::spawn(move || {
+        http::serve(listener, "application/json", move |request| {
+            let status = request
+                .strip_prefix("GET /jobs/")
+                .and_then(|rest| rest.split_whitespace().next())
+                .and_then(|uuid| jobs.status(uuid));
+            match status {
+                Some(status) => (
+                    "200 OK",
+                    serde_json::to_string(&status).expect("job statuses serialize"),
+                ),
+                None => ("404 Not Found", r#"{"status":"unknown"}"#.to_string()),
+            }
+        })
+    });
+    Ok(())
+}
+
+/// Serves the prover over gRPC on `addr` from a runtime of its own, proving tasks on `jobs`.
+#[cfg(feature = "grpc")]
+fn serve_grpc(addr: &str, jobs: JobQueue) -> Result<(), std::io::Error> {
+    let addr: std::net::SocketAddr = addr
+        .parse()
+        .map_err(|err| std::io::Error::other(format!("{GRPC_ADDR_ENV} is {addr:?}: {err}")))?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let prover = GrpcProver::new(jobs, |json| {
+        let task = serde_json::from_str(json)
+            .map_err(|err| err.to_string())
+            .and_then(read_task);
+        if task.is_err() {
+            let state = STATE.get().expect("state is set");
+            state.metrics().record_task(Some(Stage::Parse));
+        }
+        task
+    });
+    std::thread::spawn(move || {
+        if let Err(err) = runtime.block_on(prover.serve(addr)) {
+            eprintln!("gRPC server on {addr}: {err}");
+        }
+    });
+    Ok(())
+}
+
+/// Answers `GET /proofs/<uuid>` and `GET /proofs/digest/<task digest>` on `addr` with the
+/// proof detail kept in `proofs`, and with 404 for tasks it does not hold.
+fn serve_proofs(addr: &str, proofs: &'static ProofStore) -> Result<(), std::io::Error> {
+    let listener = TcpListener::bind(addr)?;
+    std::thread::spawn(move || {
+        http::serve(listener, "application/json", move |request| {
+            let path = request
+                .strip_prefix("GET /proofs/")
+                .and_then(|rest| rest.split_whitespace().next());
+            let found = match path {
+                Some(path) => match path.strip_prefix("digest/") {
+                    Some(digest) => proofs.by_digest(digest),
+                    None => proofs.by_uuid(path),
+                },
+                None => Ok(None),
+            };
+            match found {
+                Ok(Some(detail)) => (
+                    "200 OK",
+                    serde_json::to_string(&detail).expect("proof details serialize"),
+                ),
+                Ok(None) | Err(ArtifactError::NotFound(_) | ArtifactError::InvalidKey(_)) => {
+                    ("404 Not Found", r#"{"error":"unknown task"}"#.to_string())
+                }
+                Err(err) => (
+                    "500 Internal Server Error",
+                    serde_json::json!({ "error": err.to_string() }).to_string(),
+                ),
+            }
+        })
+    });
+    Ok(())
+}
+
+/// Answers `GET /metrics` on `addr` with the metrics of the service and the depth of the
+/// job queue, and every other path with 404.
+fn serve_metrics(addr: &str, state: Arc<ProverState>) -> Result<(), std::io::Error> {
+    let listener = TcpListener::bind(addr)?;
+    std::thread::spawn(move || {
+        http::serve(listener, "text/plain; version=0.0.4", move |request| {
+            if request.starts_with("GET /metrics ") {
+                let metrics = state.metrics();
+                metrics.set_queue_depth(JOBS.get().map_or(0, JobQueue::waiting));
+                ("200 OK", metrics.render())
+            } else {
+                ("404 Not Found", String::new())
+            }
+        })
+    });
+    Ok(())
+}
+
+/// `snarkify verify --proofs <details.jsonl> [--keep-going]`: checks recorded proofs in
+/// parallel and reports every one; stops at the first failure unless `--keep-going`.
+fn run_verify(state: &ProverState, args: &[String]) -> Result<(), std::io::Error> {
+    let (path, keep_going) = match args {
+        [flag, path] if flag == "--proofs" => (path, false),
+        [flag, path, keep] if flag == "--proofs" && keep == "--keep-going" => (path, true),
+        _ => {
+            return Err(std::io::Error::other(
+                "usage: snarkify verify --proofs <details.jsonl> [--keep-going]",
+            ))
+        }
+    };
+    let details = BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| {
+            let value = serde_json::from_str(&line?).map_err(std::io::Error::other)?;
+            let value = schema::upgrade_proof_detail(value).map_err(std::io::Error::other)?;
+            serde_json::from_value::<ProofDetail>(value).map_err(std::io::Error::other)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let outcomes = verify_all(&details, !keep_going, |detail| state.verify(detail));
+    let mut failures = 0;
+    for (detail, outcome) in details.iter().zip(&outcomes) {
+        for mismatch in state.metadata_mismatches(detail) {
+            eprintln!(
+                "{}: warning: produced by another build: {mismatch}",
+                detail.id
+            );
+        }
+        match outcome {
+            VerifyOutcome::Valid => println!("{}: valid", detail.id),
+            VerifyOutcome::Invalid(err) => {
+                failures += 1;
+                println!("{}: invalid: {err}", detail.id);
+            }
+            VerifyOutcome::Skipped => println!("{}: skipped", detail.id),
+        }
+    }
+    match failures {
+        0 => Ok(()),
+        n => Err(std::io::Error::other(format!("{n} invalid proofs"))),
+    }
+}
+
+/// The value of `env`, or of the setting of the config file it overrides.
+fn setting<T: ToString>(env: &str, config: Option<T>) -> Option<String> {
+    std::env::var(env)
+        .ok()
+        .or_else(|| config.map(|value| value.to_string()))
+}
+
+/// Sizes the global rayon pool and places its threads, before anything runs on it.
+fn configure_threads(config: &ServiceConfig) -> Result<(), std::io::Error> {
+    let threads = setting(PROVER_THREADS_ENV, config.threads)
+        .map(|threads| {
+            threads
+                .parse::<usize>()
+                .map_err(|_| std::io::Error::other(format!("{PROVER_THREADS_ENV} is {threads:?}")))
+        })
+        .transpose()?;
+    let placement = match std::env::var(PROVER_CPUS_ENV) {
+        Ok(cpus) => Placement::parse(&cpus).map_err(std::io::Error::other)?,
+        Err(_) => {
+            if let Some(threads) = threads {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build_global()
+                    .map_err(std::io::Error::other)?;
+            }
+            return Ok(());
+        }
+    };
+    let pinning = std::env::var(PROVER_PINNING_ENV)
+        .map(|pinning| pinning.parse().map_err(std::io::Error::other))
+        .unwrap_or(Ok(Pinning::Set))?;
+    let placement = match threads {
+        Some(threads) => placement.with_threads(threads),
+        None => placement,
+    }
+    .with_pinning(pinning);
+    placement.build_global().map_err(std::io::Error::other)
+}
+
+/// The flags in front of the command, which override the service config and its
+/// environment.
+#[derive(Default)]
+struct LeadingFlags {
+    config: Option<String>,
+    params: Option<String>,
+    transcript: Option<String>,
+}
+
+impl LeadingFlags {
+    /// Takes the flags off the front of `args`, in any order, up to the command. A flag it
+    /// does not know, a repeated one or one without its value is an error.
+    fn take(args: &mut Vec<String>) -> Result<Self, std::io::Error> {
+        let usage = || {
+            std::io::Error::other(
+                "usage: snarkify [--config <path>] [--params <path>] [--transcript <hash>] \
+                 [command]",
+            )
+        };
+        let mut flags = Self::default();
+        while args.first().is_some_and(|arg| arg.starts_with("--")) {
+            let slot = match args[0].as_str() {
+                "--config" => &mut flags.config,
+                "--params" => &mut flags.params,
+                "--transcript" => &mut flags.transcript,
+                flag => return Err(std::io::Error::other(format!("unknown flag {flag}"))),
+            };
+            let value = args.get(1).cloned().ok_or_else(usage)?;
+            if slot.replace(value).is_some() {
+                return Err(std::io::Error::other(format!("{} is given twice", args[0])));
+            }
+            args.drain(..2);
+        }
+        Ok(flags)
     }
 }
 
 fn main() -> Result<(), std::io::Error> {
-    snarkify_sdk::run::<PoseidonProver>()
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let flags = LeadingFlags::take(&mut args)?;
+    let config_path = flags
+        .config
+        .or_else(|| std::env::var(SERVICE_CONFIG_ENV).ok());
+    let config = match config_path {
+        Some(path) => ServiceConfig::read(path).map_err(std::io::Error::other)?,
+        None => ServiceConfig::default(),
+    };
+    if let Ok(filter) = std::env::var(LOG_FILTER_ENV) {
+        tracing_subscriber::fmt()
+            .json()
+            .with_env_filter(tracing_subscriber::EnvFilter::new(filter))
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .with_writer(std::io::stderr)
+            .init();
+    }
+    configure_threads(&config)?;
+    if let Ok(mode) = std::env::var(TASK_PARSE_MODE_ENV) {
+        let _ = PARSE_MODE.set(mode.parse().map_err(std::io::Error::other)?);
+    }
+    let callback_hosts = std::env::var(CALLBACK_HOSTS_ENV)
+        .map(|hosts| {
+            hosts
+                .split(',')
+                .map(|host| host.trim().to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    let callback_secret = std::env::var(CALLBACK_SECRET_ENV)
+        .ok()
+        .map(String::into_bytes);
+    let _ = CALLBACKS.set((callback_hosts, callback_secret));
+    if let Ok(dir) = std::env::var(PAYLOAD_DIR_ENV) {
+        let _ = PAYLOAD_SOURCE.set(LocalFiles::new(dir));
+    }
+    if let Ok(timeout) = std::env::var(BATCH_TIMEOUT_ENV) {
+        let timeout = parse_duration(&timeout)
+            .map_err(|err| std::io::Error::other(format!("{BATCH_TIMEOUT_ENV}: {err}")))?;
+        let _ = BATCHES.set(BatchCollector::new(timeout));
+    }
+    let number = |env: &str, value: String| {
+        value
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| {
+                std::io::Error::other(format!("{env} is {value:?}, not a positive number"))
+            })
+    };
+    let job_config = match setting(PROVER_WORKERS_ENV, config.workers) {
+        Some(workers) => Some(JobConfig {
+            workers: number(PROVER_WORKERS_ENV, workers)?,
+            capacity: match std::env::var(JOB_QUEUE_CAPACITY_ENV) {
+                Ok(capacity) => number(JOB_QUEUE_CAPACITY_ENV, capacity)?,
+                Err(_) => JobConfig::default().capacity,
+            },
+            ..JobConfig::default()
+        }),
+        None => None,
+    };
+    let k = match setting(PROVER_K_ENV, config.k) {
+        Some(k) => k
+            .parse::<u32>()
+            .ok()
+            .filter(|k| *k > 0)
+            .ok_or_else(|| std::io::Error::other(format!("{PROVER_K_ENV} is {k:?}")))?,
+        None => K,
+    };
+    if let Some(backend) = setting(BACKEND_ENV, config.backend.as_ref()) {
+        check_backend(&backend).map_err(std::io::Error::other)?;
+    }
+    let proof_types = match std::env::var(PROOF_TYPES_ENV) {
+        Ok(names) => Some(
+            parse_proof_types(&names)
+                .map_err(|err| std::io::Error::other(format!("{PROOF_TYPES_ENV}: {err}")))?,
+        ),
+        Err(_) => config.proof_types.clone(),
+    };
+    let params_path = flags
+        .params
+        .or_else(|| setting(PARAMS_PATH_ENV, config.params.as_ref().map(|p| p.display())));
+    let transcript = flags
+        .transcript
+        .or_else(|| setting(TRANSCRIPT_ENV, config.transcript.map(|hash| hash.as_str())));
+    let artifacts = std::env::var(ARTIFACT_STORE_ENV)
+        .ok()
+        .map(|uri| open_store(&uri))
+        .transpose()?;
+    let state = match (params_path, std::env::var(PARAMS_KEY_ENV)) {
+        (Some(path), _) => ProverState::prefetch(path, k).map_err(std::io::Error::other),
+        (None, Ok(key)) => {
+            let store = artifacts.as_deref().ok_or_else(|| {
+                std::io::Error::other(format!("{PARAMS_KEY_ENV} needs {ARTIFACT_STORE_ENV}"))
+            })?;
+            ProverState::prefetch_from(store, &key, k).map_err(std::io::Error::other)
+        }
+        _ => {
+            eprintln!(
+                "warning: neither --params, {PARAMS_PATH_ENV} nor {PARAMS_KEY_ENV} is set, \
+                 generating insecure test params; proofs will not verify against other provers"
+            );
+            ProverState::new(k).map_err(|err| std::io::Error::other(format!("{err:?}")))
+        }
+    };
+    let mut state = state
+        .map_err(|err| std::io::Error::other(format!("failed to set up prover state: {err}")))?;
+    if let Ok(name) = std::env::var(DEPLOYMENT_SALT_ENV) {
+        state = state.with_salt(deployment_salt(&name));
+    }
+    if let Ok(on) = std::env::var(SANITY_CHECK_ENV) {
+        let on = on.parse().map_err(|_| {
+            std::io::Error::other(format!("{SANITY_CHECK_ENV} is {on:?}, not true or false"))
+        })?;
+        state = state.with_sanity_check(on);
+    }
+    if let Ok(bytes) = std::env::var(MAX_PROOF_SIZE_ENV) {
+        let bytes = bytes.parse().map_err(|_| {
+            std::io::Error::other(format!(
+                "{MAX_PROOF_SIZE_ENV} is {bytes:?}, not a byte count"
+            ))
+        })?;
+        state = state.with_max_proof_size(bytes);
+    }
+    if let Ok(k) = std::env::var(MAX_K_ENV) {
+        let k = k.parse().map_err(|_| {
+            std::io::Error::other(format!("{MAX_K_ENV} is {k:?}, not a number of rows as k"))
+        })?;
+        state = state.with_max_k(k);
+    }
+    if let Ok(len) = std::env::var(MAX_MESSAGE_LEN_ENV) {
+        let len: usize = len.parse().map_err(|_| {
+            std::io::Error::other(format!(
+                "{MAX_MESSAGE_LEN_ENV} is {len:?}, not a number of elements"
+            ))
+        })?;
+        let limits = *state.limits();
+        state = state.with_limits(MessageLimits {
+            max_hash_len: limits.max_hash_len.min(len),
+            max_task_len: limits.max_task_len.min(len),
+        });
+    }
+    if let Ok(timeout) = std::env::var(TASK_TIMEOUT_ENV) {
+        let timeout = parse_duration(&timeout)
+            .map_err(|err| std::io::Error::other(format!("{TASK_TIMEOUT_ENV}: {err}")))?;
+        state = state.with_timeout(timeout);
+    }
+    if let Ok(entries) = std::env::var(TRACE_CACHE_ENTRIES_ENV) {
+        let entries = entries.parse().map_err(|_| {
+            std::io::Error::other(format!(
+                "{TRACE_CACHE_ENTRIES_ENV} is {entries:?}, not a number of permutations"
+            ))
+        })?;
+        state = state.with_trace_cache(entries);
+    }
+    if let Some(hash) = transcript {
+        state = state.with_transcript(hash.parse().map_err(std::io::Error::other)?);
+    }
+    if let Ok(label) = std::env::var(PROTOCOL_LABEL_ENV) {
+        state = state.with_protocol_label(&label);
+    }
+    if let Ok(profile) = std::env::var(LATENCY_PROFILE_ENV) {
+        let profile = match profile.as_str() {
+            "standard" => LatencyProfile::Standard,
+            "interactive" => LatencyProfile::Interactive,
+            _ => {
+                return Err(std::io::Error::other(format!(
+                    "{LATENCY_PROFILE_ENV} is {profile:?}, not standard or interactive"
+                )))
+            }
+        };
+        state = state
+            .with_latency_profile(profile)
+            .map_err(|err| std::io::Error::other(format!("{err:?}")))?;
+    }
+    if let Some(types) = proof_types {
+        state = state.with_proof_types(types);
+    }
+    if let Some(dir) = setting(
+        VK_STORE_DIR_ENV,
+        config.vk_store_dir.as_ref().map(|d| d.display()),
+    ) {
+        state = state.with_vk_store(dir, DEFAULT_VK_CACHE_CAPACITY);
+    }
+    if let Some(dir) = setting(
+        KEY_CACHE_DIR_ENV,
+        config.key_cache_dir.as_ref().map(|d| d.display()),
+    ) {
+        state = state.with_key_dir(dir, DEFAULT_KEY_CACHE_CAPACITY)?;
+    }
+    #[cfg(feature = "aggregation")]
+    if let Ok(path) = std::env::var(AGGREGATION_PARAMS_PATH_ENV) {
+        let params = poseidon_circuit::prover::read_params(path)?;
+        state = state
+            .with_aggregation(params)
+            .map_err(std::io::Error::other)?;
+    }
+    if let Some(store) = artifacts {
+        state = state.with_artifact_store(store, DEFAULT_VK_CACHE_CAPACITY);
+    }
+    let prewarm_idle = std::env::var(PREWARM_IDLE_ENV)
+        .ok()
+        .map(|idle| {
+            parse_duration(&idle)
+                .map_err(|err| std::io::Error::other(format!("{PREWARM_IDLE_ENV}: {err}")))
+        })
+        .transpose()?;
+    if let Ok(uri) = std::env::var(PROOF_STORE_ENV) {
+        let _ = PROOFS.set(ProofStore::new(open_store(&uri)?, state.proof_generation()));
+    }
+    let capabilities = state.capabilities();
+    let _ = STATE.set(Arc::new(state));
+    match args.split_first() {
+        Some((cmd, rest)) if cmd == "replay" => run_replay(rest),
+        Some((cmd, rest)) if cmd == "loadtest" => run_loadtest(rest),
+        Some((cmd, rest)) if cmd == "trace" => run_trace(rest),
+        Some((cmd, rest)) if cmd == "keygen-all" => run_keygen_all(rest),
+        Some((cmd, rest)) if cmd == "bench-specs" => run_bench_specs(rest),
+        Some((cmd, rest)) if cmd == "bench-witness" => run_bench_witness(rest),
+        Some((cmd, rest)) if cmd == "cost" => run_cost(rest),
+        Some((cmd, rest)) if cmd == "evm-verifier" => {
+            run_evm_verifier(STATE.get().expect("state is set"), rest)
+        }
+        Some((cmd, rest)) if cmd == "export-vk" => {
+            run_export_vk(STATE.get().expect("state is set"), rest)
+        }
+        Some((cmd, rest)) if cmd == "verify" => {
+            run_verify(STATE.get().expect("state is set"), rest)
+        }
+        Some((cmd, _)) if cmd == "capabilities" => {
+            println!("{}", capabilities.to_json());
+            Ok(())
+        }
+        _ => {
+            println!("{}", capabilities.to_json());
+            if let Ok(addr) = std::env::var(CAPABILITIES_ADDR_ENV) {
+                serve_capabilities(&addr, &capabilities)?;
+            }
+            if let Ok(addr) = std::env::var(METRICS_ADDR_ENV) {
+                serve_metrics(&addr, STATE.get().expect("state is set").clone())?;
+            }
+            if let Ok(addr) = std::env::var(PROOFS_ADDR_ENV) {
+                let proofs = PROOFS.get().ok_or_else(|| {
+                    std::io::Error::other(format!("{PROOFS_ADDR_ENV} needs {PROOF_STORE_ENV}"))
+                })?;
+                serve_proofs(&addr, proofs)?;
+            }
+            if let Some(batches) = BATCHES.get() {
+                run_batch_timer(batches);
+            }
+            if let Some(config) = job_config {
+                let jobs = JOBS.get_or_init(|| JobQueue::start(config, answer));
+                if let Ok(addr) = std::env::var(JOBS_ADDR_ENV) {
+                    serve_jobs(&addr, jobs)?;
+                }
+            }
+            #[cfg(feature = "grpc")]
+            if let Ok(addr) = std::env::var(GRPC_ADDR_ENV) {
+                let jobs = JOBS.get().cloned();
+                serve_grpc(
+                    &addr,
+                    jobs.unwrap_or_else(|| JobQueue::start(JobConfig::default(), answer)),
+                )?;
+            }
+            if let Some(idle) = prewarm_idle {
+                run_prewarmer(STATE.get().expect("state is set").clone(), idle);
+            }
+            snarkify_sdk::run::<PoseidonProver>()
+        }
+    }
 }
@@ -0,0 +1,393 @@
+use ff::PrimeField;
+use halo2_proofs::{
+    circuit::Value,
+    plonk::{Advice, Column, ConstraintSystem, Error, Expression, Fixed, VirtualCells},
+    poly::Rotation,
+};
+
+use crate::main_gate::{AssignedValue, RegionCtx};
+
+/// Columns of a bus binding the preimage of a Poseidon digest to a commitment computed by
+/// another sub-circuit of the same constraint system.
+///
+/// An entry takes one row per word of the message, holding
+/// `(tag, index, len, word, digest, commitment)`. `tag`, `index` and `len` are fixed, so they
+/// are part of the verifying key; `word` is copy-constrained to the cell the
+/// [`PoseidonChip`](crate::poseidon_circuit::PoseidonChip) absorbed the word from, `digest`
+/// to the squeezed output, and the `commitment` cells of an entry to each other.
+///
+/// The foreign circuit (e.g. a zkEVM Keccak circuit producing `commitment` over the same data)
+/// looks up `(tag, index, len, word, digest, commitment)` for every word it hashed with
+/// [`DigestBusConfig::lookup_words`]. If it looks up each of its `len` words, satisfied
+/// lookups prove that the data it committed to under `tag` is word for word the message
+/// Poseidon hashed to `digest`. Covering every index is up to the foreign circuit: the bus
+/// cannot tell a word that was never looked up.
+///
+/// The digest is what keeps entries apart. The commitment is a witness of the foreign
+/// circuit, so two entries under the same tag may well carry the same one; without the
+/// digest, a lookup could take some words from one entry and the rest from the other.
+#[derive(Clone, Copy, Debug)]
+pub struct DigestBusConfig {
+    pub q_enable: Column<Fixed>,
+    pub tag: Column<Fixed>,
+    pub index: Column<Fixed>,
+    pub len: Column<Fixed>,
+    pub word: Column<Advice>,
+    pub digest: Column<Advice>,
+    pub commitment: Column<Advice>,
+}
+
+impl DigestBusConfig {
+    pub fn configure<F: PrimeField>(meta: &mut ConstraintSystem<F>) -> Self {
+        let q_enable = meta.fixed_column();
+        let tag = meta.fixed_column();
+        let index = meta.fixed_column();
+        let len = meta.fixed_column();
+        let word = meta.advice_column();
+        let digest = meta.advice_column();
+        let commitment = meta.advice_column();
+        meta.enable_equality(word);
+        meta.enable_equality(digest);
+        meta.enable_equality(commitment);
+        meta.annotate_lookup_any_column(q_enable, || "digest bus: q_enable");
+        meta.annotate_lookup_any_column(tag, || "digest bus: tag");
+        meta.annotate_lookup_any_column(index, || "digest bus: index");
+        meta.annotate_lookup_any_column(len, || "digest bus: len");
+        meta.annotate_lookup_any_column(word, || "digest bus: word");
+        meta.annotate_lookup_any_column(digest, || "digest bus: digest");
+        meta.annotate_lookup_any_column(commitment, || "digest bus: commitment");
+        Self {
+            q_enable,
+            tag,
+            index,
+            len,
+            word,
+            digest,
+            commitment,
+        }
+    }
+
+    /// Registers a lookup of the words a foreign circuit hashed into the bus.
+    ///
+    /// `foreign` returns `[q, tag, index, len, word, digest, commitment]` queried from the
+    /// foreign circuit's columns, one row per word, with the same `digest` on every row of a
+    /// message; rows where `q` is zero are not constrained. `q` itself is part of the lookup
+    /// so that an enabled all-zero row cannot match the unused rows of the bus.
+    pub fn lookup_words<F: PrimeField>(
+        &self,
+        meta: &mut ConstraintSystem<F>,
+        name: &'static str,
+        foreign: impl FnOnce(&mut VirtualCells<'_, F>) -> [Expression<F>; 7],
+    ) {
+        meta.lookup_any(name, |meta| {
+            let [q, tag, index, len, word, digest, commitment] = foreign(meta);
+            let q_bus = meta.query_fixed(self.q_enable, Rotation::cur());
+            let bus = [
+                meta.query_fixed(self.tag, Rotation::cur()),
+                meta.query_fixed(self.index, Rotation::cur()),
+                meta.query_fixed(self.len, Rotation::cur()),
+                meta.query_advice(self.word, Rotation::cur()),
+                meta.query_advice(self.digest, Rotation::cur()),
+                meta.query_advice(self.commitment, Rotation::cur()),
+            ];
+            std::iter::once((q.clone(), q_bus.clone()))
+                .chain(
+                    [tag, index, len, word, digest, commitment]
+                        .into_iter()
+                        .zip(bus)
+                        .map(|(foreign, bus)| (q.clone() * foreign, q_bus.clone() * bus)),
+                )
+                .collect()
+        });
+    }
+
+    /// Writes an entry for the message absorbed from `words` and squeezed to `digest`, one row
+    /// per word starting at the current offset, and moves past it.
+    ///
+    /// `words` are the input cells returned by [`PoseidonChip::squeeze_with_inputs`].
+    ///
+    /// # Panics
+    ///
+    /// If `words` is empty: an entry without rows binds nothing.
+    ///
+    /// [`PoseidonChip::squeeze_with_inputs`]:
+    ///     crate::poseidon_circuit::PoseidonChip::squeeze_with_inputs
+    pub fn assign_entry<F: PrimeField>(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        tag: F,
+        words: &[AssignedValue<F>],
+        digest: &AssignedValue<F>,
+        commitment: Value<F>,
+    ) -> Result<(), Error> {
+        assert!(
+            !words.is_empty(),
+            "a digest bus entry needs at least one word"
+        );
+        let len = F::from(words.len() as u64);
+        let mut first_commitment: Option<AssignedValue<F>> = None;
+        for (i, word) in words.iter().enumerate() {
+            ctx.assign_fixed(|| "bus: q_enable", self.q_enable, F::ONE)?;
+            ctx.assign_fixed(|| "bus: tag", self.tag, tag)?;
+            ctx.assign_fixed(|| "bus: index", self.index, F::from(i as u64))?;
+            ctx.assign_fixed(|| "bus: len", self.len, len)?;
+            let w = ctx.assign_advice(|| "bus: word", self.word, word.value().copied())?;
+            ctx.constrain_equal(w.cell(), word.cell())?;
+            let d = ctx.assign_advice(|| "bus: digest", self.digest, digest.value().copied())?;
+            ctx.constrain_equal(d.cell(), digest.cell())?;
+            let c = ctx.assign_advice(|| "bus: commitment", self.commitment, commitment)?;
+            match &first_commitment {
+                Some(first) => ctx.constrain_equal(c.cell(), first.cell())?,
+                None => first_commitment = Some(c),
+            }
+            ctx.next();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::Circuit,
+    };
+    use halo2curves::pasta::Fp;
+    use poseidon::Spec;
+
+    use super::*;
+    use crate::{
+        main_gate::{MainGate, MainGateConfig},
+        poseidon_circuit::PoseidonChip,
+    };
+
+    const T: usize = 3;
+    const RATE: usize = 2;
+    const R_F: usize = 4;
+    const R_P: usize = 3;
+    const TAG: u64 = 7;
+
+    #[derive(Clone, Debug)]
+    struct BridgeCircuitConfig {
+        main_gate: MainGateConfig<T>,
+        bus: DigestBusConfig,
+        q_foreign: Column<Fixed>,
+        foreign_index: Column<Fixed>,
+        foreign_len: Column<Fixed>,
+        foreign_word: Column<Advice>,
+        foreign_digest: Column<Advice>,
+        foreign_commitment: Column<Advice>,
+    }
+
+    /// Hashes every message of `messages` with Poseidon onto the bus, each an entry under
+    /// `TAG` and `bus_commitment`, and plays the foreign circuit claiming `foreign_commitment`
+    /// over `foreign_words` with the digest of `messages[foreign_entry]`.
+    struct BridgeCircuit {
+        messages: Vec<Vec<Fp>>,
+        bus_commitment: Fp,
+        foreign_words: Vec<Fp>,
+        foreign_entry: usize,
+        foreign_commitment: Fp,
+    }
+
+    impl BridgeCircuit {
+        fn honest(inputs: Vec<Fp>, commitment: Fp) -> Self {
+            Self {
+                foreign_words: inputs.clone(),
+                messages: vec![inputs],
+                bus_commitment: commitment,
+                foreign_entry: 0,
+                foreign_commitment: commitment,
+            }
+        }
+    }
+
+    impl Circuit<Fp> for BridgeCircuit {
+        type Config = BridgeCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                messages: self
+                    .messages
+                    .iter()
+                    .map(|m| vec![Fp::ZERO; m.len()])
+                    .collect(),
+                bus_commitment: Fp::ZERO,
+                foreign_words: vec![Fp::ZERO; self.foreign_words.len()],
+                foreign_entry: self.foreign_entry,
+                foreign_commitment: Fp::ZERO,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let main_gate = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            let bus = DigestBusConfig::configure(meta);
+            let q_foreign = meta.fixed_column();
+            let foreign_index = meta.fixed_column();
+            let foreign_len = meta.fixed_column();
+            let foreign_word = meta.advice_column();
+            let foreign_digest = meta.advice_column();
+            let foreign_commitment = meta.advice_column();
+            bus.lookup_words(meta, "foreign words on bus", |meta| {
+                [
+                    meta.query_fixed(q_foreign, Rotation::cur()),
+                    Expression::Constant(Fp::from(TAG)),
+                    meta.query_fixed(foreign_index, Rotation::cur()),
+                    meta.query_fixed(foreign_len, Rotation::cur()),
+                    meta.query_advice(foreign_word, Rotation::cur()),
+                    meta.query_advice(foreign_digest, Rotation::cur()),
+                    meta.query_advice(foreign_commitment, Rotation::cur()),
+                ]
+            });
+            BridgeCircuitConfig {
+                main_gate,
+                bus,
+                q_foreign,
+                foreign_index,
+                foreign_len,
+                foreign_word,
+                foreign_digest,
+                foreign_commitment,
+            }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = Spec::<Fp, T, RATE>::new(R_F, R_P);
+            let pchip = PoseidonChip::new(config.main_gate, spec);
+            let hashes = layouter.assign_region(
+                || "poseidon hashes",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    pchip.hash_many(ctx, &self.messages)
+                },
+            )?;
+            layouter.assign_region(
+                || "digest bus",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    for (words, digest) in &hashes {
+                        config.bus.assign_entry(
+                            ctx,
+                            Fp::from(TAG),
+                            words,
+                            digest,
+                            Value::known(self.bus_commitment),
+                        )?;
+                    }
+                    Ok(())
+                },
+            )?;
+            let foreign_digest = hashes[self.foreign_entry].1.value().copied();
+            layouter.assign_region(
+                || "foreign circuit",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let len = Fp::from(self.foreign_words.len() as u64);
+                    for (i, word) in self.foreign_words.iter().enumerate() {
+                        ctx.assign_fixed(|| "q_foreign", config.q_foreign, Fp::ONE)?;
+                        ctx.assign_fixed(|| "index", config.foreign_index, Fp::from(i as u64))?;
+                        ctx.assign_fixed(|| "len", config.foreign_len, len)?;
+                        ctx.assign_advice(|| "word", config.foreign_word, Value::known(*word))?;
+                        ctx.assign_advice(|| "digest", config.foreign_digest, foreign_digest)?;
+                        ctx.assign_advice(
+                            || "commitment",
+                            config.foreign_commitment,
+                            Value::known(self.foreign_commitment),
+                        )?;
+                        ctx.next();
+                    }
+                    Ok(())
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn test_bus_lookup() {
+        const K: u32 = 10;
+        let inputs = (0..5).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let commitment = Fp::from(0xdead_beef_u64);
+
+        let circuit = BridgeCircuit::honest(inputs.clone(), commitment);
+        let prover = MockProver::run(K, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let circuit = BridgeCircuit {
+            foreign_commitment: commitment + Fp::ONE,
+            ..BridgeCircuit::honest(inputs, commitment)
+        };
+        let prover = MockProver::run(K, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_bus_binds_preimage() {
+        const K: u32 = 10;
+        let inputs = (0..5).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let commitment = Fp::from(0xdead_beef_u64);
+
+        // The commitment on the bus matches the foreign one, but the foreign circuit hashed
+        // other data than Poseidon did.
+        let mut foreign_words = inputs.clone();
+        foreign_words[2] += Fp::ONE;
+        let circuit = BridgeCircuit {
+            foreign_words,
+            ..BridgeCircuit::honest(inputs.clone(), commitment)
+        };
+        let prover = MockProver::run(K, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err());
+
+        // A prefix of the message does not match either.
+        let circuit = BridgeCircuit {
+            foreign_words: inputs[..4].to_vec(),
+            ..BridgeCircuit::honest(inputs.clone(), commitment)
+        };
+        let prover = MockProver::run(K, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err());
+
+        // Nor does the same data in another order.
+        let mut foreign_words = inputs.clone();
+        foreign_words.swap(0, 1);
+        let circuit = BridgeCircuit {
+            foreign_words,
+            ..BridgeCircuit::honest(inputs, commitment)
+        };
+        let prover = MockProver::run(K, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_bus_keeps_entries_apart() {
+        const K: u32 = 10;
+        let a = (0..5).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let b = (10..15).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let commitment = Fp::from(0xdead_beef_u64);
+        // two entries of the same length under the same tag and commitment
+        let circuit = |foreign_words: Vec<Fp>, foreign_entry| BridgeCircuit {
+            messages: vec![a.clone(), b.clone()],
+            bus_commitment: commitment,
+            foreign_words,
+            foreign_entry,
+            foreign_commitment: commitment,
+        };
+
+        let prover = MockProver::run(K, &circuit(b.clone(), 1), vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // the first words of one entry and the last ones of the other match neither digest
+        let mixed = [&a[..3], &b[3..]].concat();
+        for entry in [0, 1] {
+            let prover = MockProver::run(K, &circuit(mixed.clone(), entry), vec![]).unwrap();
+            assert!(prover.verify().is_err());
+        }
+    }
+}
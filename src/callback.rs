@@ -0,0 +1,229 @@
+//! Pushing a finished [`ProofDetail`] to the `callback_url` of its task.
+//!
+//! The detail is POSTed as JSON with [`crate::http`] and retried with exponential backoff on
+//! connection errors, `429` and `5xx`. With a secret configured, the body is signed with
+//! keyed Blake2b-256 and the hex MAC sent as `X-Poseidon-Signature: blake2b=<mac>`, which
+//! the receiver recomputes with [`sign`] over the raw body. Only hosts on an allow list are
+//! called, since the URL comes from the untrusted task queue.
+use std::{fmt, io, time::Duration};
+
+use crate::{http::HttpUrl, task::ProofDetail};
+
+/// Longest accepted callback URL.
+pub const MAX_CALLBACK_URL_LEN: usize = 2048;
+
+/// Header carrying the signature of the body.
+pub const SIGNATURE_HEADER: &str = "X-Poseidon-Signature";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Callback {
+    url: HttpUrl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    /// Wait before the second attempt, doubled before every next one
+    pub backoff: Duration,
+    /// Timeout of every connect, read and write
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CallbackError {
+    InvalidUrl(String),
+    HostNotAllowed(String),
+    Io(io::Error),
+    /// The receiver answered with a status that retrying will not change
+    Rejected(u16),
+    /// Every attempt failed; the last status, if the receiver answered at all
+    GaveUp {
+        attempts: u32,
+        status: Option<u16>,
+    },
+}
+
+impl fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl(url) => write!(f, "invalid callback url {url:?}"),
+            Self::HostNotAllowed(host) => write!(f, "callback host {host:?} is not allowed"),
+            Self::Io(err) => write!(f, "callback failed: {err}"),
+            Self::Rejected(status) => write!(f, "callback rejected with status {status}"),
+            Self::GaveUp { attempts, status } => {
+                write!(f, "callback failed after {attempts} attempts")?;
+                match status {
+                    Some(status) => write!(f, ", last status {status}"),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for CallbackError {}
+
+/// Hex keyed Blake2b-256 of `body`.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    blake2b_simd::Params::new()
+        .hash_length(32)
+        .key(secret)
+        .hash(body)
+        .to_hex()
+        .to_string()
+}
+
+impl Callback {
+    /// Parses an `http://host[:port][/path]` URL whose host is in `allowed_hosts`.
+    pub fn parse(url: &str, allowed_hosts: &[String]) -> Result<Self, CallbackError> {
+        if url.len() > MAX_CALLBACK_URL_LEN {
+            return Err(CallbackError::InvalidUrl(url.to_string()));
+        }
+        let url = HttpUrl::parse(url).ok_or_else(|| CallbackError::InvalidUrl(url.to_string()))?;
+        if !allowed_hosts.iter().any(|allowed| allowed == &url.host) {
+            return Err(CallbackError::HostNotAllowed(url.host));
+        }
+        Ok(Self { url })
+    }
+
+    /// POSTs `detail`, retrying as `policy` says, and returns the final `2xx` status.
+    pub fn deliver(
+        &self,
+        detail: &ProofDetail,
+        secret: Option<&[u8]>,
+        policy: &RetryPolicy,
+    ) -> Result<u16, CallbackError> {
+        let body = serde_json::to_vec(detail).map_err(|err| CallbackError::Io(err.into()))?;
+        let signature = secret.map(|secret| format!("blake2b={}", sign(secret, &body)));
+        let headers = signature
+            .as_deref()
+            .map(|signature| vec![(SIGNATURE_HEADER, signature)])
+            .unwrap_or_default();
+        let mut backoff = policy.backoff;
+        let mut status = None;
+        for attempt in 0..policy.attempts {
+            if attempt > 0 {
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+            match self.url.post_json(&body, &headers, policy.timeout) {
+                Ok(response) if response.is_success() => return Ok(response.status),
+                Ok(response) if response.status == 429 || response.status >= 500 => {
+                    status = Some(response.status)
+                }
+                Ok(response) => return Err(CallbackError::Rejected(response.status)),
+                Err(_) => {}
+            }
+        }
+        Err(CallbackError::GaveUp {
+            attempts: policy.attempts,
+            status,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    fn hosts() -> Vec<String> {
+        vec!["127.0.0.1".to_string(), "hooks.example.com".to_string()]
+    }
+
+    #[test]
+    fn test_parse() {
+        let callback = Callback::parse("http://Hooks.example.com:8080/done?x=1", &hosts()).unwrap();
+        assert_eq!(callback.url.host, "hooks.example.com");
+        assert_eq!(callback.url.port, 8080);
+        assert_eq!(callback.url.path, "/done?x=1");
+        assert_eq!(
+            Callback::parse("http://hooks.example.com", &hosts())
+                .unwrap()
+                .url
+                .path,
+            "/"
+        );
+        assert!(matches!(
+            Callback::parse("http://10.0.0.1/", &hosts()),
+            Err(CallbackError::HostNotAllowed(_))
+        ));
+        for url in [
+            "https://hooks.example.com/",
+            "http://user@hooks.example.com/",
+            "http://hooks.example.com:port/",
+            "http://hooks.example.com/a b",
+            "http://hooks.example.com/\r\nX-Injected: 1",
+        ] {
+            assert!(
+                matches!(
+                    Callback::parse(url, &hosts()),
+                    Err(CallbackError::InvalidUrl(_))
+                ),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_deliver_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in ["503 Service Unavailable", "204 No Content"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                stream
+                    .set_read_timeout(Some(Duration::from_millis(200)))
+                    .unwrap();
+                let mut request = Vec::new();
+                let _ = stream.read_to_end(&mut request);
+                write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        let callback = Callback::parse(&format!("http://127.0.0.1:{port}/hook"), &hosts()).unwrap();
+        let detail = ProofDetail {
+            id: "7".to_string(),
+            ..Default::default()
+        };
+        let policy = RetryPolicy {
+            attempts: 3,
+            backoff: Duration::ZERO,
+            timeout: Duration::from_secs(5),
+        };
+        assert_eq!(
+            callback.deliver(&detail, Some(b"secret"), &policy).unwrap(),
+            204
+        );
+
+        let requests = server.join().unwrap();
+        let body = serde_json::to_vec(&detail).unwrap();
+        let signature = format!("{SIGNATURE_HEADER}: blake2b={}", sign(b"secret", &body));
+        assert!(requests[1].starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(requests[1].contains(&signature));
+        assert!(requests[1].ends_with(std::str::from_utf8(&body).unwrap()));
+    }
+
+    #[test]
+    fn test_sign() {
+        assert_ne!(sign(b"a", b"body"), sign(b"b", b"body"));
+        assert_eq!(sign(b"a", b"body").len(), 64);
+    }
+}
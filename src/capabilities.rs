@@ -0,0 +1,181 @@
+//! Machine-readable description of what a prover binary can do.
+//!
+//! The service logs [`Capabilities`] as a single JSON line at startup, and can serve it over
+//! HTTP, so the coordinator routes tasks by capability instead of by deployment name. Fields
+//! are only ever added, never renamed, and lists keep their order across releases.
+use serde::Serialize;
+
+use crate::{
+    instance_layout::InstanceLayout,
+    specs::{self, SpecInfo},
+    state::ProverState,
+    task::ProofType,
+};
+
+/// Version of the capability document layout, bumped on incompatible changes.
+pub const SCHEMA_VERSION: u32 = 1;
+/// Transcripts a proof can be requested with, see [`crate::task::Task::evm`].
+pub const TRANSCRIPTS: &[&str] = &["blake2b", "keccak256"];
+/// Proving backends compiled into this crate.
+pub const BACKENDS: &[&str] = &["halo2-kzg-gwc"];
+
+/// One circuit with keys loaded in the service.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CircuitCapability {
+    pub name: &'static str,
+    /// Proof type of the tasks answered by this circuit, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof_type: Option<ProofType>,
+    pub k: u32,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    pub schema_version: u32,
+    pub version: &'static str,
+    pub proof_types: Vec<ProofType>,
+    pub circuits: Vec<CircuitCapability>,
+    /// Hard forks whose tasks can be proven, oldest first
+    pub forks: Vec<&'static str>,
+    /// Distinct `k` of the circuits, ascending
+    pub k: Vec<u32>,
+    pub transcripts: Vec<&'static str>,
+    pub instance_layouts: Vec<InstanceLayout>,
+    pub backends: Vec<&'static str>,
+    pub specs: Vec<SpecInfo>,
+    pub features: Vec<&'static str>,
+}
+
+impl Capabilities {
+    pub fn new(circuits: Vec<CircuitCapability>) -> Self {
+        let proof_types = circuits.iter().filter_map(|c| c.proof_type).collect();
+        let mut k = circuits.iter().map(|c| c.k).collect::<Vec<_>>();
+        k.sort_unstable();
+        k.dedup();
+        let mut forks = Vec::new();
+        for spec in specs::SPECS {
+            if !forks.contains(&spec.introduced_in) {
+                forks.push(spec.introduced_in);
+            }
+        }
+        Self {
+            schema_version: SCHEMA_VERSION,
+            version: env!("CARGO_PKG_VERSION"),
+            proof_types,
+            circuits,
+            forks,
+            k,
+            transcripts: TRANSCRIPTS.to_vec(),
+            instance_layouts: vec![InstanceLayout::RowMajor, InstanceLayout::ColumnMajor],
+            backends: BACKENDS.to_vec(),
+            specs: specs::available(),
+            features: enabled_features(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("capabilities serialize")
+    }
+}
+
+impl ProverState {
+    /// The capabilities of a service answering tasks with this state.
+    pub fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::new(vec![
+            CircuitCapability {
+                name: "test_circuit",
+                proof_type: None,
+                k: self.test_circuit().k(),
+            },
+            CircuitCapability {
+                name: "preimage",
+                proof_type: Some(ProofType::Preimage),
+                k: self.preimage().k(),
+            },
+            CircuitCapability {
+                name: "range_proof",
+                proof_type: Some(ProofType::Range),
+                k: self.range_proof().k(),
+            },
+            CircuitCapability {
+                name: "membership",
+                proof_type: Some(ProofType::Membership),
+                k: self.membership().k(),
+            },
+        ]);
+        capabilities
+            .proof_types
+            .retain(|proof_type| self.accepts(*proof_type));
+        capabilities
+    }
+}
+
+fn enabled_features() -> Vec<&'static str> {
+    [
+        ("aggregation", cfg!(feature = "aggregation")),
+        ("bn256-t2", cfg!(feature = "bn256-t2")),
+        ("bn256-t3", cfg!(feature = "bn256-t3")),
+        ("bn256-t5", cfg!(feature = "bn256-t5")),
+        ("bn256-t6", cfg!(feature = "bn256-t6")),
+        ("bn256-t7", cfg!(feature = "bn256-t7")),
+        ("bn256-t8", cfg!(feature = "bn256-t8")),
+        ("bn256-t9", cfg!(feature = "bn256-t9")),
+        ("cbor", cfg!(feature = "cbor")),
+        ("experimental", cfg!(feature = "experimental")),
+        ("gzip", cfg!(feature = "gzip")),
+        ("mem-stats", cfg!(feature = "mem-stats")),
+        ("msgpack", cfg!(feature = "msgpack")),
+        ("pasta", cfg!(feature = "pasta")),
+        ("pse-compat", cfg!(feature = "pse-compat")),
+        ("rlp", cfg!(feature = "rlp")),
+        ("ssz", cfg!(feature = "ssz")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = Capabilities::new(vec![
+            CircuitCapability {
+                name: "a",
+                proof_type: None,
+                k: 13,
+            },
+            CircuitCapability {
+                name: "b",
+                proof_type: Some(ProofType::Range),
+                k: 10,
+            },
+            CircuitCapability {
+                name: "c",
+                proof_type: Some(ProofType::Preimage),
+                k: 10,
+            },
+        ]);
+        assert_eq!(
+            capabilities.proof_types,
+            vec![ProofType::Range, ProofType::Preimage]
+        );
+        assert_eq!(capabilities.k, vec![10, 13]);
+        assert_eq!(capabilities.forks, vec!["genesis"]);
+
+        let json: serde_json::Value = serde_json::from_str(&capabilities.to_json()).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["proof_types"], serde_json::json!([4, 3]));
+        assert_eq!(
+            json["circuits"][0],
+            serde_json::json!({"name": "a", "k": 13})
+        );
+        assert_eq!(
+            json["transcripts"],
+            serde_json::json!(["blake2b", "keccak256"])
+        );
+        assert_eq!(json["specs"][0]["id"], "bn256-t4-r3");
+    }
+}
@@ -0,0 +1,390 @@
+//! A PRF and authenticated encryption from the Poseidon permutation, for encrypting notes to
+//! their recipients in privacy protocols.
+//!
+//! [`prf`] is Poseidon of a key and an input under [`PRF_DOMAIN`]. [`encrypt`] runs the
+//! permutation as a duplex sponge, as in "Encryption with Poseidon" (Khovratovich, 2019):
+//! the state starts from [`ENCRYPTION_DOMAIN`] and absorbs the key, the nonce and the length
+//! of the plaintext; every chunk of `RATE` plaintext elements is then added to the rate
+//! part of the state, the sums are the ciphertext, and the state is permuted. The first rate
+//! element of the final state is the tag, which [`decrypt`] checks. The last chunk is padded
+//! with zeros, whose keystream is not part of the ciphertext.
+//!
+//! A nonce must never repeat under one key. [`CipherChip`] encrypts in-circuit, proving that
+//! a ciphertext is the encryption of the plaintext it constrains.
+use std::sync::Arc;
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::{circuit::Value, plonk::Error};
+use poseidon::Spec;
+
+use crate::{
+    main_gate::{AssignedValue, MainGateConfig, RegionCtx},
+    optimized_constants::OptimizedConstants,
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::{hash_with_domain, State},
+    specs::Domain,
+};
+
+/// `2^128 - 2`, next to [`crate::commitment::COMMITMENT_DOMAIN`]
+pub const PRF_DOMAIN: Domain = Domain::Constant(u128::MAX - 1);
+
+/// `2^128 - 3`
+pub const ENCRYPTION_DOMAIN: Domain = Domain::Constant(u128::MAX - 2);
+
+/// The PRF of `key` at `input`.
+pub fn prf<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    key: F,
+    input: F,
+) -> F {
+    hash_with_domain(spec, PRF_DOMAIN, &[key, input])
+}
+
+/// The key, the nonce and the plaintext length, in chunks of `RATE` padded with zeros.
+fn header<F: PrimeField, const RATE: usize>(key: F, nonce: F, len: usize) -> Vec<F> {
+    let mut header = vec![key, nonce, F::from(len as u64)];
+    header.resize(header.len().div_ceil(RATE) * RATE, F::ZERO);
+    header
+}
+
+fn padded<F: PrimeField, const RATE: usize>(chunk: &[F]) -> Vec<F> {
+    let mut padded = chunk.to_vec();
+    padded.resize(RATE, F::ZERO);
+    padded
+}
+
+fn start<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    key: F,
+    nonce: F,
+    len: usize,
+) -> State<F, T, RATE> {
+    let mut state = [F::ZERO; T];
+    state[0] = ENCRYPTION_DOMAIN.capacity();
+    let mut state = State::new(state);
+    for chunk in header::<F, RATE>(key, nonce, len).chunks(RATE) {
+        state.permute(spec, chunk);
+    }
+    state
+}
+
+/// Encrypts `plaintext` under `key` and `nonce`; the ciphertext is as long as the plaintext,
+/// followed by the tag.
+pub fn encrypt<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    key: F,
+    nonce: F,
+    plaintext: &[F],
+) -> Vec<F> {
+    let mut state = start(spec, key, nonce, plaintext.len());
+    let mut ciphertext = Vec::with_capacity(plaintext.len() + 1);
+    for chunk in plaintext.chunks(RATE) {
+        let words = state.words();
+        ciphertext.extend(chunk.iter().zip(&words[1..]).map(|(p, s)| *p + s));
+        state.permute(spec, &padded::<F, RATE>(chunk));
+    }
+    ciphertext.push(state.words()[1]);
+    ciphertext
+}
+
+/// Decrypts a ciphertext of [`encrypt`], or `None` if it was not encrypted under `key` and
+/// `nonce` or was altered.
+pub fn decrypt<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    key: F,
+    nonce: F,
+    ciphertext: &[F],
+) -> Option<Vec<F>> {
+    let (tag, ciphertext) = ciphertext.split_last()?;
+    let mut state = start(spec, key, nonce, ciphertext.len());
+    let mut plaintext = Vec::with_capacity(ciphertext.len());
+    for chunk in ciphertext.chunks(RATE) {
+        let words = state.words();
+        let chunk: Vec<F> = chunk.iter().zip(&words[1..]).map(|(c, s)| *c - s).collect();
+        state.permute(spec, &padded::<F, RATE>(&chunk));
+        plaintext.extend(chunk);
+    }
+    (state.words()[1] == *tag).then_some(plaintext)
+}
+
+/// The cells of an encryption by [`CipherChip::encrypt`].
+#[derive(Clone, Debug)]
+pub struct AssignedEncryption<F: PrimeField> {
+    pub key: AssignedValue<F>,
+    pub nonce: AssignedValue<F>,
+    pub plaintext: Vec<AssignedValue<F>>,
+    /// As long as the plaintext, followed by the tag
+    pub ciphertext: Vec<AssignedValue<F>>,
+}
+
+pub struct CipherChip<F: PrimeField, const T: usize, const RATE: usize> {
+    config: MainGateConfig<T>,
+    constants: Arc<OptimizedConstants<F, T, RATE>>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    CipherChip<F, T, RATE>
+{
+    pub fn new(config: MainGateConfig<T>, spec: &Spec<F, T, RATE>) -> Self {
+        Self {
+            config,
+            constants: Arc::new(OptimizedConstants::from_spec(spec)),
+        }
+    }
+
+    fn poseidon(&self) -> PoseidonChip<F, T, RATE> {
+        PoseidonChip::from_constants(self.config.clone(), self.constants.clone())
+    }
+
+    /// Rows used by [`CipherChip::prf`].
+    pub fn num_rows_prf(spec: &Spec<F, T, RATE>) -> usize {
+        PoseidonChip::num_rows(spec, 2)
+    }
+
+    /// Rows used by [`CipherChip::encrypt`] for `len` plaintext elements.
+    pub fn num_rows(spec: &Spec<F, T, RATE>, len: usize) -> usize {
+        let header = 3usize.div_ceil(RATE);
+        let chunks = len.div_ceil(RATE);
+        // a permutation per chunk, a constant row per padding element and for the length,
+        // and a row per ciphertext element
+        (header + chunks) * PoseidonChip::num_rows(spec, 0)
+            + (header * RATE - 2)
+            + (chunks * RATE - len)
+            + len
+    }
+
+    /// The PRF of `key` at `input`; returns the key and input cells and the output.
+    pub fn prf(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        key: F,
+        input: F,
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        let mut chip = self.poseidon().with_domain(PRF_DOMAIN);
+        chip.update(vec![key, input]);
+        chip.squeeze_with_inputs(ctx)
+    }
+
+    /// Constrains `cell` to `value`, on one row.
+    fn constrain_constant(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        cell: &AssignedValue<F>,
+        value: F,
+    ) -> Result<(), Error> {
+        // x - c = 0
+        let x = ctx.assign_advice(
+            || "cipher: constant",
+            self.config.state[0],
+            cell.value().copied(),
+        )?;
+        ctx.constrain_equal(x.cell(), cell.cell())?;
+        ctx.assign_fixed(|| "cipher: q_1", self.config.q_1[0], F::ONE)?;
+        ctx.assign_fixed(|| "cipher: rc", self.config.rc, -value)?;
+        ctx.next();
+        Ok(())
+    }
+
+    /// Encrypts `plaintext` as [`encrypt`] does.
+    pub fn encrypt(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        key: F,
+        nonce: F,
+        plaintext: &[F],
+    ) -> Result<AssignedEncryption<F>, Error> {
+        let config = &self.config;
+        let chip = self.poseidon().with_domain(ENCRYPTION_DOMAIN);
+
+        let header = header::<F, RATE>(key, nonce, plaintext.len());
+        let mut state: Option<[AssignedValue<F>; T]> = None;
+        let mut header_cells = Vec::with_capacity(header.len());
+        for chunk in header.chunks(RATE) {
+            let (next, inputs) = chip.duplex(ctx, chunk.to_vec(), state.as_ref())?;
+            header_cells.extend(inputs);
+            state = Some(next);
+        }
+        // the length and the padding are constants
+        for (cell, value) in header_cells.iter().zip(&header).skip(2) {
+            self.constrain_constant(ctx, cell, *value)?;
+        }
+
+        let mut plaintext_cells = Vec::with_capacity(plaintext.len());
+        let mut ciphertext = Vec::with_capacity(plaintext.len() + 1);
+        for chunk in plaintext.chunks(RATE) {
+            let rate = state.as_ref().expect("the header is absorbed")[1..].to_vec();
+            let (next, inputs) = chip.duplex(ctx, padded::<F, RATE>(chunk), state.as_ref())?;
+            for (p, s) in inputs.iter().zip(&rate).take(chunk.len()) {
+                // s + p - c = 0
+                let s_copy =
+                    ctx.assign_advice(|| "cipher: s", config.state[0], s.value().copied())?;
+                ctx.constrain_equal(s_copy.cell(), s.cell())?;
+                let p_copy =
+                    ctx.assign_advice(|| "cipher: p", config.state[1], p.value().copied())?;
+                ctx.constrain_equal(p_copy.cell(), p.cell())?;
+                ctx.assign_fixed(|| "cipher: q_1", config.q_1[0], F::ONE)?;
+                ctx.assign_fixed(|| "cipher: q_1", config.q_1[1], F::ONE)?;
+                ctx.assign_fixed(|| "cipher: q_o", config.q_o, -F::ONE)?;
+                let c_val: Value<F> = s.value().copied() + p.value().copied();
+                ciphertext.push(ctx.assign_advice(|| "cipher: c", config.out, c_val)?);
+                ctx.next();
+            }
+            for padding in &inputs[chunk.len()..] {
+                self.constrain_constant(ctx, padding, F::ZERO)?;
+            }
+            plaintext_cells.extend(inputs.into_iter().take(chunk.len()));
+            state = Some(next);
+        }
+        let state = state.expect("the header is absorbed");
+        ciphertext.push(state[1].clone());
+
+        let mut header_cells = header_cells.into_iter();
+        Ok(AssignedEncryption {
+            key: header_cells.next().expect("the key is absorbed"),
+            nonce: header_cells.next().expect("the nonce is absorbed"),
+            plaintext: plaintext_cells,
+            ciphertext,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+    };
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::main_gate::MainGate;
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+    const K: u32 = 12;
+
+    fn spec() -> Spec<Fr, T, RATE> {
+        Spec::new(8, 56)
+    }
+
+    /// Key, nonce and plaintext of each vector, checked natively and in-circuit
+    fn vectors() -> Vec<(Fr, Fr, Vec<Fr>)> {
+        [0, 1, RATE, RATE + 1, 2 * RATE]
+            .into_iter()
+            .map(|len| {
+                let plaintext = (0..len as u64).map(|i| Fr::from(100 + i)).collect();
+                (Fr::from(7), Fr::from(len as u64), plaintext)
+            })
+            .collect()
+    }
+
+    /// Encrypts a private plaintext and exposes `[nonce, ciphertext.., tag, prf(key, nonce)]`.
+    struct EncryptCircuit {
+        key: Fr,
+        nonce: Fr,
+        plaintext: Vec<Fr>,
+    }
+
+    impl Circuit<Fr> for EncryptCircuit {
+        type Config = (MainGateConfig<T>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                key: Fr::ZERO,
+                nonce: Fr::ZERO,
+                plaintext: vec![Fr::ZERO; self.plaintext.len()],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let config = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            (config, instance)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = CipherChip::new(config, &spec());
+            let public = layouter.assign_region(
+                || "encryption",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let encryption = chip.encrypt(ctx, self.key, self.nonce, &self.plaintext)?;
+                    let (inputs, prf) = chip.prf(ctx, self.key, self.nonce)?;
+                    ctx.constrain_equal(inputs[0].cell(), encryption.key.cell())?;
+                    ctx.constrain_equal(inputs[1].cell(), encryption.nonce.cell())?;
+                    let mut public = vec![encryption.nonce];
+                    public.extend(encryption.ciphertext);
+                    public.push(prf);
+                    Ok(public)
+                },
+            )?;
+            for (i, cell) in public.iter().enumerate() {
+                layouter.constrain_instance(cell.cell(), instance, i)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn public(key: Fr, nonce: Fr, plaintext: &[Fr]) -> Vec<Fr> {
+        let mut public = vec![nonce];
+        public.extend(encrypt(&spec(), key, nonce, plaintext));
+        public.push(prf(&spec(), key, nonce));
+        public
+    }
+
+    #[test]
+    fn test_native() {
+        let spec = spec();
+        for (key, nonce, plaintext) in vectors() {
+            let ciphertext = encrypt(&spec, key, nonce, &plaintext);
+            assert_eq!(ciphertext.len(), plaintext.len() + 1);
+            assert_eq!(
+                decrypt(&spec, key, nonce, &ciphertext),
+                Some(plaintext.clone())
+            );
+            assert_eq!(decrypt(&spec, key + Fr::ONE, nonce, &ciphertext), None);
+            assert_eq!(decrypt(&spec, key, nonce + Fr::ONE, &ciphertext), None);
+            for i in 0..ciphertext.len() {
+                let mut altered = ciphertext.clone();
+                altered[i] += Fr::ONE;
+                assert_eq!(decrypt(&spec, key, nonce, &altered), None);
+            }
+            assert_eq!(decrypt(&spec, key, nonce, &ciphertext[1..]), None);
+        }
+        assert_eq!(decrypt(&spec, Fr::ONE, Fr::ONE, &[]), None);
+        assert_ne!(
+            prf(&spec, Fr::ONE, Fr::ONE),
+            prf(&spec, Fr::ONE, Fr::from(2))
+        );
+    }
+
+    #[test]
+    fn test_encrypt_in_circuit() {
+        for (key, nonce, plaintext) in vectors() {
+            assert!(CipherChip::num_rows(&spec(), plaintext.len()) < 1 << (K - 1));
+            let expected = public(key, nonce, &plaintext);
+            let circuit = EncryptCircuit {
+                key,
+                nonce,
+                plaintext: plaintext.clone(),
+            };
+            let prover = MockProver::run(K, &circuit, vec![expected.clone()]).unwrap();
+            assert_eq!(prover.verify(), Ok(()));
+
+            // the tag and the prf bind the key
+            let other = public(key + Fr::ONE, nonce, &plaintext);
+            let prover = MockProver::run(K, &circuit, vec![other]).unwrap();
+            assert!(prover.verify().is_err());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Hiding commitments to a field element, `Poseidon(value, blinding)` under
+//! [`COMMITMENT_DOMAIN`].
+//!
+//! The commitment of a value under a random blinding factor reveals nothing about the value,
+//! and opening it to another value means finding a Poseidon collision. The domain keeps
+//! commitments apart from every other hash of two elements, so that a commitment made for
+//! one circuit opens in any other using [`CommitmentChip`], but never passes for the digest
+//! of a preimage or a Merkle node. [`crate::range_proof`] predates this module and commits
+//! with the PSE domain.
+use std::sync::Arc;
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::plonk::Error;
+use poseidon::Spec;
+use rand_core::RngCore;
+
+use crate::{
+    main_gate::{AssignedValue, MainGateConfig, RegionCtx},
+    optimized_constants::OptimizedConstants,
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash_with_domain,
+    specs::Domain,
+};
+
+/// `2^128 - 1`: above the capacity of every constant length domain of fewer than `2^64 - 1`
+/// elements and below every user tag.
+pub const COMMITMENT_DOMAIN: Domain = Domain::Constant(u128::MAX);
+
+/// The commitment to `value` under `blinding`.
+pub fn commit<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    value: F,
+    blinding: F,
+) -> F {
+    hash_with_domain(spec, COMMITMENT_DOMAIN, &[value, blinding])
+}
+
+/// What opens a commitment: the value and its blinding factor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Opening<F> {
+    pub value: F,
+    pub blinding: F,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> Opening<F> {
+    /// Opens `value` under a blinding factor drawn from `rng`, which must be secret and
+    /// uniform for the commitment to hide the value.
+    pub fn random(value: F, rng: impl RngCore) -> Self {
+        Self {
+            value,
+            blinding: F::random(rng),
+        }
+    }
+
+    pub fn commit<const T: usize, const RATE: usize>(&self, spec: &Spec<F, T, RATE>) -> F {
+        commit(spec, self.value, self.blinding)
+    }
+
+    /// Checks the opening natively against a commitment.
+    pub fn verify<const T: usize, const RATE: usize>(
+        &self,
+        spec: &Spec<F, T, RATE>,
+        commitment: F,
+    ) -> bool {
+        self.commit(spec) == commitment
+    }
+}
+
+pub struct CommitmentChip<F: PrimeField, const T: usize, const RATE: usize> {
+    config: MainGateConfig<T>,
+    constants: Arc<OptimizedConstants<F, T, RATE>>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    CommitmentChip<F, T, RATE>
+{
+    pub fn new(config: MainGateConfig<T>, spec: &Spec<F, T, RATE>) -> Self {
+        Self {
+            config,
+            constants: Arc::new(OptimizedConstants::from_spec(spec)),
+        }
+    }
+
+    /// Rows used by [`CommitmentChip::commit`] and by [`CommitmentChip::open`].
+    pub fn num_rows(spec: &Spec<F, T, RATE>) -> usize {
+        PoseidonChip::num_rows(spec, 2)
+    }
+
+    /// Commits to the value of `opening`; returns the value cell and the commitment cell.
+    pub fn commit(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        opening: &Opening<F>,
+    ) -> Result<(AssignedValue<F>, AssignedValue<F>), Error> {
+        let mut pchip = PoseidonChip::from_constants(self.config.clone(), self.constants.clone())
+            .with_domain(COMMITMENT_DOMAIN);
+        pchip.update(vec![opening.value, opening.blinding]);
+        let (inputs, commitment) = pchip.squeeze_with_inputs(ctx)?;
+        Ok((inputs[0].clone(), commitment))
+    }
+
+    /// Constrains `commitment` to open to `value` with the blinding factor of `opening`.
+    pub fn open(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        commitment: &AssignedValue<F>,
+        value: &AssignedValue<F>,
+        opening: &Opening<F>,
+    ) -> Result<(), Error> {
+        let (opened, recomputed) = self.commit(ctx, opening)?;
+        ctx.constrain_equal(opened.cell(), value.cell())?;
+        ctx.constrain_equal(recomputed.cell(), commitment.cell())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner, Value},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+    };
+    use halo2curves::bn256::Fr;
+    use rand_core::OsRng;
+
+    use super::*;
+    use crate::{main_gate::MainGate, poseidon_hash::hash};
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+    const K: u32 = 10;
+
+    fn spec() -> Spec<Fr, T, RATE> {
+        Spec::new(8, 56)
+    }
+
+    /// Opens the public commitment to the public value, `[commitment, value]`.
+    struct OpenCircuit {
+        opening: Opening<Fr>,
+    }
+
+    impl Circuit<Fr> for OpenCircuit {
+        type Config = (MainGateConfig<T>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                opening: Opening {
+                    value: Fr::ZERO,
+                    blinding: Fr::ZERO,
+                },
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let config = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            (config, instance)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = CommitmentChip::new(config.clone(), &spec());
+            let cells = layouter.assign_region(
+                || "commitment opening",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    // free cells: every selector of the main gate is zero on this row
+                    let commitment = ctx.assign_advice(
+                        || "opening: commitment",
+                        config.state[0],
+                        Value::known(self.opening.commit(&spec())),
+                    )?;
+                    let value = ctx.assign_advice(
+                        || "opening: value",
+                        config.state[1],
+                        Value::known(self.opening.value),
+                    )?;
+                    ctx.next();
+                    chip.open(ctx, &commitment, &value, &self.opening)?;
+                    Ok([commitment, value])
+                },
+            )?;
+            for (i, cell) in cells.iter().enumerate() {
+                layouter.constrain_instance(cell.cell(), instance, i)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_commit() {
+        let opening = Opening::random(Fr::from(42), OsRng);
+        let commitment = opening.commit(&spec());
+        assert!(opening.verify(&spec(), commitment));
+        assert!(!Opening {
+            value: Fr::from(43),
+            ..opening
+        }
+        .verify(&spec(), commitment));
+        // hiding: another blinding factor, another commitment
+        assert_ne!(
+            Opening::random(Fr::from(42), OsRng).commit(&spec()),
+            commitment
+        );
+        // not the digest of the same elements
+        assert_ne!(
+            hash(&spec(), &[opening.value, opening.blinding]),
+            commitment
+        );
+    }
+
+    #[test]
+    fn test_open() {
+        let opening = Opening::random(Fr::from(42), OsRng);
+        let commitment = opening.commit(&spec());
+        let circuit = OpenCircuit { opening };
+        let prover = MockProver::run(K, &circuit, vec![vec![commitment, opening.value]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // the circuit assigns the commitment of its opening; a public one of another value
+        // or blinding factor does not open
+        let other = commit(&spec(), Fr::from(42), opening.blinding + Fr::ONE);
+        let prover = MockProver::run(K, &circuit, vec![vec![other, opening.value]]).unwrap();
+        assert!(prover.verify().is_err());
+        let prover = MockProver::run(K, &circuit, vec![vec![commitment, Fr::from(43)]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
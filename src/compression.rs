@@ -0,0 +1,143 @@
+//! Compression of the proofs of proof details, asked for per task, behind the `gzip`
+//! feature.
+//!
+//! A halo2 proof is mostly compressed curve points and field elements, so gzip rarely saves
+//! more than a few percent; it is meant for queues billing by the byte. Proof details record
+//! the size of the uncompressed proof next to the compressed one, see
+//! [`crate::task::ProofDetail::proof_size`], so clients can tell what it saved.
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::task::MAX_TASK_BYTES;
+
+/// Largest decompressed proof, bounding what a corrupt or hostile proof detail can inflate
+/// to.
+pub const MAX_PROOF_BYTES: usize = MAX_TASK_BYTES;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofCompression {
+    Gzip,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressionError {
+    /// The compression needs a feature this build lacks
+    Disabled(&'static str),
+    Corrupt(String),
+    TooLarge,
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disabled(feature) => write!(f, "built without the {feature} feature"),
+            Self::Corrupt(msg) => write!(f, "corrupt compressed proof: {msg}"),
+            Self::TooLarge => write!(f, "proof decompresses to over {MAX_PROOF_BYTES} bytes"),
+        }
+    }
+}
+
+impl std::error::Error for CompressionError {}
+
+impl ProofCompression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// Whether this build can compress and decompress with it.
+    pub fn check(&self) -> Result<(), CompressionError> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => Ok(()),
+            #[cfg(not(feature = "gzip"))]
+            Self::Gzip => Err(CompressionError::Disabled("gzip")),
+        }
+    }
+
+    pub fn compress(&self, proof: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => {
+                use std::io::Write;
+
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                let vec_write = "writing to a Vec cannot fail";
+                encoder.write_all(proof).expect(vec_write);
+                Ok(encoder.finish().expect(vec_write))
+            }
+            #[cfg(not(feature = "gzip"))]
+            Self::Gzip => {
+                let _ = proof;
+                Err(CompressionError::Disabled("gzip"))
+            }
+        }
+    }
+
+    pub fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => {
+                use std::io::Read;
+
+                let mut proof = Vec::new();
+                flate2::read::GzDecoder::new(bytes)
+                    .take(MAX_PROOF_BYTES as u64 + 1)
+                    .read_to_end(&mut proof)
+                    .map_err(|err| CompressionError::Corrupt(err.to_string()))?;
+                if proof.len() > MAX_PROOF_BYTES {
+                    return Err(CompressionError::TooLarge);
+                }
+                Ok(proof)
+            }
+            #[cfg(not(feature = "gzip"))]
+            Self::Gzip => {
+                let _ = bytes;
+                Err(CompressionError::Disabled("gzip"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_round_trip() {
+        let proof = (0..4096u32).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+        let compressed = ProofCompression::Gzip.compress(&proof).unwrap();
+        assert!(compressed.len() < proof.len());
+        assert_eq!(
+            ProofCompression::Gzip.decompress(&compressed).unwrap(),
+            proof
+        );
+        assert!(matches!(
+            ProofCompression::Gzip.decompress(&proof),
+            Err(CompressionError::Corrupt(_))
+        ));
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn test_gzip_disabled() {
+        assert_eq!(
+            ProofCompression::Gzip.check(),
+            Err(CompressionError::Disabled("gzip"))
+        );
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(
+            serde_json::to_string(&ProofCompression::Gzip).unwrap(),
+            "\"gzip\""
+        );
+        assert_eq!(ProofCompression::Gzip.as_str(), "gzip");
+    }
+}
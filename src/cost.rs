@@ -0,0 +1,157 @@
+//! What a hash circuit costs before proving it: its columns, rows and the smallest `k` it
+//! fits into, so integrators can size their SRS without running keygen.
+//!
+//! Costs are those of [`TestCircuit`]'s layout, the main gate with one instance column, for
+//! `hashes` messages of the same length hashed in one region. Nothing is synthesized: the
+//! columns come from configuring a constraint system and the rows from
+//! [`PoseidonChip::num_rows_many`].
+use std::fmt;
+
+use halo2_proofs::plonk::{Circuit, ConstraintSystem};
+use halo2curves::bn256::Fr;
+use poseidon::Spec;
+use serde::Serialize;
+
+use crate::{
+    limits::UNUSABLE_ROWS, poseidon_circuit::PoseidonChip, prover::estimate_proof_size,
+    specs::vetted_spec, test_circuit::TestCircuit,
+};
+
+/// Layout of a circuit hashing `hashes` messages of `len` elements.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitCost {
+    pub width: usize,
+    pub rate: usize,
+    pub hashes: usize,
+    pub len: usize,
+    pub advice_columns: usize,
+    pub fixed_columns: usize,
+    pub instance_columns: usize,
+    /// Lookup arguments; the main gate has none
+    pub lookups: usize,
+    /// Maximum degree of the gates
+    pub degree: usize,
+    /// Rows the hashes take, without those reserved for blinding
+    pub rows: usize,
+    /// Smallest `k` whose `2^k` rows hold the hashes and the blinding rows
+    pub k: u32,
+    /// Bytes of every proof, see [`estimate_proof_size`]
+    pub proof_bytes: usize,
+}
+
+impl CircuitCost {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("costs serialize")
+    }
+}
+
+impl fmt::Display for CircuitCost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "width {}, rate {}: {} hashes of {} elements",
+            self.width, self.rate, self.hashes, self.len
+        )?;
+        writeln!(
+            f,
+            "columns: {} advice, {} fixed, {} instance, {} lookups, degree {}",
+            self.advice_columns,
+            self.fixed_columns,
+            self.instance_columns,
+            self.lookups,
+            self.degree
+        )?;
+        writeln!(
+            f,
+            "rows: {}, k: {}, proof: {} bytes",
+            self.rows, self.k, self.proof_bytes
+        )
+    }
+}
+
+/// The cost of hashing `hashes` messages of `len` elements with `spec`.
+pub fn estimate<const T: usize, const RATE: usize>(
+    spec: &Spec<Fr, T, RATE>,
+    hashes: usize,
+    len: usize,
+) -> CircuitCost {
+    let mut cs = ConstraintSystem::<Fr>::default();
+    TestCircuit::<Fr, T, RATE>::configure(&mut cs);
+    let rows = PoseidonChip::num_rows_many(spec, &vec![len; hashes]);
+    CircuitCost {
+        width: T,
+        rate: RATE,
+        hashes,
+        len,
+        advice_columns: cs.num_advice_columns(),
+        fixed_columns: cs.num_fixed_columns(),
+        instance_columns: cs.num_instance_columns(),
+        lookups: cs.lookups().len(),
+        degree: cs.degree(),
+        rows,
+        k: (rows + UNUSABLE_ROWS).next_power_of_two().trailing_zeros(),
+        // the main gate has no selectors, so keygen leaves the system as configured
+        proof_bytes: estimate_proof_size(&cs),
+    }
+}
+
+/// [`estimate`] with the vetted bn256 spec of `width`, for widths only known at runtime;
+/// `None` for widths whose spec is not enabled.
+pub fn estimate_width(width: usize, hashes: usize, len: usize) -> Option<CircuitCost> {
+    fn vetted<const T: usize, const RATE: usize>() -> Spec<Fr, T, RATE> {
+        vetted_spec().expect("enabled widths have vetted specs")
+    }
+
+    // only the widths of the enabled specs are instantiated
+    match width {
+        #[cfg(feature = "bn256-t2")]
+        2 => Some(estimate(&vetted::<2, 1>(), hashes, len)),
+        #[cfg(feature = "bn256-t3")]
+        3 => Some(estimate(&vetted::<3, 2>(), hashes, len)),
+        4 => Some(estimate(&vetted::<4, 3>(), hashes, len)),
+        #[cfg(feature = "bn256-t5")]
+        5 => Some(estimate(&vetted::<5, 4>(), hashes, len)),
+        #[cfg(feature = "bn256-t6")]
+        6 => Some(estimate(&vetted::<6, 5>(), hashes, len)),
+        #[cfg(feature = "bn256-t7")]
+        7 => Some(estimate(&vetted::<7, 6>(), hashes, len)),
+        #[cfg(feature = "bn256-t8")]
+        8 => Some(estimate(&vetted::<8, 7>(), hashes, len)),
+        #[cfg(feature = "bn256-t9")]
+        9 => Some(estimate(&vetted::<9, 8>(), hashes, len)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::dev::MockProver;
+
+    use super::*;
+    use crate::{poseidon_hash::hash, prover::ProverContext, specs::BN256_T4_R3};
+
+    #[test]
+    fn test_estimate() {
+        let spec = Spec::<Fr, 4, 3>::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p);
+        let inputs = (0..5).map(Fr::from).collect::<Vec<_>>();
+        let cost = estimate(&spec, 1, inputs.len());
+        assert_eq!(cost.rows, PoseidonChip::num_rows(&spec, inputs.len()));
+        assert_eq!((cost.instance_columns, cost.lookups), (1, 0));
+        assert_eq!(estimate_width(4, 1, 5).unwrap(), cost);
+
+        // the recommended k is the smallest the circuit fits into
+        let circuit = TestCircuit::new(inputs.clone());
+        let instance = vec![vec![hash(&spec, &inputs)]];
+        let prover = MockProver::run(cost.k, &circuit, instance.clone()).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        assert!(MockProver::run(cost.k - 1, &circuit, instance).is_err());
+        let ctx = ProverContext::setup(cost.k, &circuit).unwrap();
+        assert_eq!(ctx.proof_size(), cost.proof_bytes);
+
+        // batched hashes share rows
+        let batch = estimate(&spec, 4, 5);
+        assert!(batch.rows < 4 * cost.rows);
+        assert!(batch.k >= cost.k);
+        assert!(estimate_width(10, 1, 5).is_none());
+    }
+}
@@ -0,0 +1,270 @@
+//! Witnesses from raw RLP or SSZ blobs, behind the `rlp` and `ssz` features.
+//!
+//! A blob is split into byte-string items, every item is packed with
+//! [`crate::packing::pack_bytes`] and becomes one message to hash:
+//!
+//! * RLP: any RLP value; its byte strings are the items, in encoding order, at any depth
+//! * SSZ: a `List[ByteList[N]]`, i.e. 4-byte little-endian offsets followed by the items
+use std::fmt;
+
+use base64::{engine::general_purpose::STANDARD as BS64, Engine};
+use ff::{FromUniformBytes, PrimeField};
+use poseidon::Spec;
+use serde::{Deserialize, Serialize};
+
+use crate::{packing::pack_bytes, poseidon_hash::hash};
+
+/// Deepest RLP nesting accepted, to bound recursion on untrusted input.
+pub const MAX_RLP_DEPTH: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlobEncoding {
+    Rlp,
+    Ssz,
+}
+
+/// `task_data` of a blob task.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlobTask {
+    pub encoding: BlobEncoding,
+    /// The blob, `0x`-prefixed hex or base64
+    pub data: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidData,
+    /// The blob ends inside the item starting at `offset`
+    Truncated {
+        offset: usize,
+    },
+    TrailingBytes {
+        offset: usize,
+    },
+    TooDeep,
+    /// SSZ offset `index` points outside the blob or before the previous item
+    InvalidOffset {
+        index: usize,
+    },
+    /// The binary was built without the feature decoding this encoding
+    Disabled(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidData => write!(f, "blob is neither 0x-hex nor base64"),
+            Self::Truncated { offset } => write!(f, "blob ends inside the item at byte {offset}"),
+            Self::TrailingBytes { offset } => write!(f, "unexpected bytes after byte {offset}"),
+            Self::TooDeep => write!(f, "RLP nesting exceeds {MAX_RLP_DEPTH} levels"),
+            Self::InvalidOffset { index } => write!(f, "SSZ offset {index} is out of order"),
+            Self::Disabled(feature) => write!(f, "built without the `{feature}` feature"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl BlobTask {
+    pub fn bytes(&self) -> Result<Vec<u8>, DecodeError> {
+        match self.data.strip_prefix("0x") {
+            Some(hex) if hex.len().is_multiple_of(2) => (0..hex.len())
+                .step_by(2)
+                .map(|i| {
+                    u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| DecodeError::InvalidData)
+                })
+                .collect(),
+            Some(_) => Err(DecodeError::InvalidData),
+            None => BS64
+                .decode(&self.data)
+                .map_err(|_| DecodeError::InvalidData),
+        }
+    }
+
+    /// One packed message per item of the blob.
+    pub fn messages<F: PrimeField>(&self) -> Result<Vec<Vec<F>>, DecodeError> {
+        let items = decode_items(self.encoding, &self.bytes()?)?;
+        Ok(items.iter().map(|item| pack_bytes(item)).collect())
+    }
+
+    /// The digest of every item of the blob, in order.
+    pub fn digests<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+        &self,
+        spec: &Spec<F, T, RATE>,
+    ) -> Result<Vec<F>, DecodeError> {
+        Ok(self.messages()?.iter().map(|msg| hash(spec, msg)).collect())
+    }
+}
+
+/// Splits a blob into its byte-string items.
+pub fn decode_items(encoding: BlobEncoding, blob: &[u8]) -> Result<Vec<Vec<u8>>, DecodeError> {
+    match encoding {
+        #[cfg(feature = "rlp")]
+        BlobEncoding::Rlp => {
+            let mut items = Vec::new();
+            let end = rlp_item(blob, 0, 0, &mut items)?;
+            if end != blob.len() {
+                return Err(DecodeError::TrailingBytes { offset: end });
+            }
+            Ok(items)
+        }
+        #[cfg(not(feature = "rlp"))]
+        BlobEncoding::Rlp => Err(DecodeError::Disabled("rlp")),
+        #[cfg(feature = "ssz")]
+        BlobEncoding::Ssz => ssz_byte_lists(blob),
+        #[cfg(not(feature = "ssz"))]
+        BlobEncoding::Ssz => Err(DecodeError::Disabled("ssz")),
+    }
+}
+
+/// Decodes the RLP item at `offset` into `items` and returns the offset after it.
+#[cfg(feature = "rlp")]
+fn rlp_item(
+    blob: &[u8],
+    offset: usize,
+    depth: usize,
+    items: &mut Vec<Vec<u8>>,
+) -> Result<usize, DecodeError> {
+    if depth > MAX_RLP_DEPTH {
+        return Err(DecodeError::TooDeep);
+    }
+    let truncated = DecodeError::Truncated { offset };
+    let prefix = *blob.get(offset).ok_or(truncated.clone())?;
+    // (is_list, header length, payload length)
+    let (is_list, header, len) = match prefix {
+        0x00..=0x7f => {
+            items.push(vec![prefix]);
+            return Ok(offset + 1);
+        }
+        0x80..=0xb7 => (false, 1, (prefix - 0x80) as usize),
+        0xb8..=0xbf => {
+            let len_len = (prefix - 0xb7) as usize;
+            (false, 1 + len_len, rlp_len(blob, offset + 1, len_len)?)
+        }
+        0xc0..=0xf7 => (true, 1, (prefix - 0xc0) as usize),
+        0xf8..=0xff => {
+            let len_len = (prefix - 0xf7) as usize;
+            (true, 1 + len_len, rlp_len(blob, offset + 1, len_len)?)
+        }
+    };
+    let start = offset + header;
+    let end = start.checked_add(len).ok_or(truncated.clone())?;
+    if end > blob.len() {
+        return Err(truncated);
+    }
+    if !is_list {
+        items.push(blob[start..end].to_vec());
+        return Ok(end);
+    }
+    let mut cur = start;
+    while cur < end {
+        cur = rlp_item(&blob[..end], cur, depth + 1, items)?;
+    }
+    Ok(end)
+}
+
+#[cfg(feature = "rlp")]
+fn rlp_len(blob: &[u8], offset: usize, len_len: usize) -> Result<usize, DecodeError> {
+    let bytes = blob
+        .get(offset..offset + len_len)
+        .ok_or(DecodeError::Truncated { offset })?;
+    if len_len > std::mem::size_of::<usize>() {
+        return Err(DecodeError::Truncated { offset });
+    }
+    Ok(bytes.iter().fold(0, |acc, b| (acc << 8) | *b as usize))
+}
+
+/// Decodes an SSZ `List[ByteList[N]]`.
+#[cfg(feature = "ssz")]
+fn ssz_byte_lists(blob: &[u8]) -> Result<Vec<Vec<u8>>, DecodeError> {
+    if blob.is_empty() {
+        return Ok(Vec::new());
+    }
+    let offset_at = |index: usize| {
+        blob.get(4 * index..4 * index + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or(DecodeError::Truncated { offset: 4 * index })
+    };
+    let first = offset_at(0)?;
+    if first == 0 || !first.is_multiple_of(4) || first > blob.len() {
+        return Err(DecodeError::InvalidOffset { index: 0 });
+    }
+    let count = first / 4;
+    let mut offsets = (0..count).map(offset_at).collect::<Result<Vec<_>, _>>()?;
+    offsets.push(blob.len());
+    offsets
+        .windows(2)
+        .enumerate()
+        .map(|(index, w)| {
+            if w[0] > w[1] {
+                return Err(DecodeError::InvalidOffset { index });
+            }
+            Ok(blob[w[0]..w[1]].to_vec())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "rlp")]
+    #[test]
+    fn test_rlp() {
+        // ["cat", ["dog"], "", 0x0f]
+        let blob = [
+            0xcb, 0x83, b'c', b'a', b't', 0xc4, 0x83, b'd', b'o', b'g', 0x80, 0x0f,
+        ];
+        let items = decode_items(BlobEncoding::Rlp, &blob).unwrap();
+        assert_eq!(
+            items,
+            vec![b"cat".to_vec(), b"dog".to_vec(), vec![], vec![0x0f]]
+        );
+        assert_eq!(
+            decode_items(BlobEncoding::Rlp, &blob[..6]),
+            Err(DecodeError::Truncated { offset: 0 })
+        );
+        assert_eq!(
+            decode_items(BlobEncoding::Rlp, &[0x80, 0x80]),
+            Err(DecodeError::TrailingBytes { offset: 1 })
+        );
+        let deep = [0xc1; MAX_RLP_DEPTH + 2];
+        assert!(decode_items(BlobEncoding::Rlp, &deep).is_err());
+    }
+
+    #[cfg(feature = "ssz")]
+    #[test]
+    fn test_ssz() {
+        // [0xaabb, "", 0xcc]
+        let blob = [12, 0, 0, 0, 14, 0, 0, 0, 14, 0, 0, 0, 0xaa, 0xbb, 0xcc];
+        let items = decode_items(BlobEncoding::Ssz, &blob).unwrap();
+        assert_eq!(items, vec![vec![0xaa, 0xbb], vec![], vec![0xcc]]);
+        assert_eq!(
+            decode_items(BlobEncoding::Ssz, &[8, 0, 0, 0, 4, 0, 0, 0]),
+            Err(DecodeError::InvalidOffset { index: 0 })
+        );
+    }
+
+    #[cfg(feature = "rlp")]
+    #[test]
+    fn test_blob_task() {
+        use halo2curves::bn256::Fr;
+
+        let task: BlobTask =
+            serde_json::from_str(r#"{"encoding": "rlp", "data": "0xc20102"}"#).unwrap();
+        let messages = task.messages::<Fr>().unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                vec![Fr::from(1), Fr::from(1)],
+                vec![Fr::from(1), Fr::from(2)]
+            ]
+        );
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        assert_eq!(
+            task.digests(&spec).unwrap(),
+            messages.iter().map(|m| hash(&spec, m)).collect::<Vec<_>>()
+        );
+    }
+}
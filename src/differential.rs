@@ -0,0 +1,403 @@
+//! Differential checks of the native Poseidon permutation against other implementations.
+//!
+//! Every entry of [`COMPATIBILITY`] pairs a spec of this crate with the implementation of a
+//! reference crate it is compared to. [`CompatSpec::check`] permutes the same state with
+//! both and reports the first round after which they differ; [`check_all`] guards every
+//! entry at once and is what the `differential` fuzz target runs on arbitrary seeds.
+//!
+//! Neither reference exposes its intermediate states, and the native permutation of this
+//! crate runs the optimized decomposition described in [`crate::trace`]. Both sides are
+//! therefore replayed as textbook [`Permutation`]s from their own constants, and each
+//! replay is checked against the final output of the implementation it stands for before
+//! any round is compared, so a reported round is one of the actual permutations.
+use std::{fmt, sync::OnceLock};
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2curves::{bn256::Fr, pasta::Fp};
+use poseidon::Spec;
+use poseidon_circuit_native::grain::Grain;
+
+use crate::{field_encoding::to_canonical, poseidon_hash::State, trace::RoundKind};
+
+/// A permutation as the Poseidon paper writes it: every round adds its constants, applies
+/// its S-boxes and multiplies by the MDS matrix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Permutation<F, const T: usize> {
+    pub round_constants: Vec<[F; T]>,
+    pub mds: [[F; T]; T],
+    pub r_f: usize,
+    pub r_p: usize,
+}
+
+impl<F: PrimeField, const T: usize> Permutation<F, T> {
+    /// The permutation of `spec`, with the constants the PSE crate draws from Grain before
+    /// its MDS matrix.
+    pub fn of_spec<const RATE: usize>(spec: &Spec<F, T, RATE>) -> Self
+    where
+        F: FromUniformBytes<64>,
+    {
+        let (r_f, r_p) = (spec.r_f(), spec.constants().partial().len());
+        let mut grain = Grain::new(F::NUM_BITS, T, r_f, r_p);
+        Self {
+            round_constants: (0..r_f + r_p).map(|_| grain.next_elements()).collect(),
+            mds: spec.mds_matrices().mds().rows(),
+            r_f,
+            r_p,
+        }
+    }
+
+    pub fn kind(&self, round: usize) -> RoundKind {
+        if (self.r_f / 2..self.r_f / 2 + self.r_p).contains(&round) {
+            RoundKind::Partial
+        } else {
+            RoundKind::Full
+        }
+    }
+
+    /// The state after every round.
+    pub fn trace(&self, mut state: [F; T]) -> Vec<[F; T]> {
+        let pow5 = |v: F| v.square().square() * v;
+        (0..self.r_f + self.r_p)
+            .map(|round| {
+                for (s, c) in state.iter_mut().zip(&self.round_constants[round]) {
+                    *s += c;
+                }
+                match self.kind(round) {
+                    RoundKind::Partial => state[0] = pow5(state[0]),
+                    _ => state = state.map(pow5),
+                }
+                state = self.mds.map(|row| {
+                    row.iter()
+                        .zip(&state)
+                        .fold(F::ZERO, |acc, (m, s)| acc + *m * s)
+                });
+                state
+            })
+            .collect()
+    }
+
+    pub fn permute(&self, state: [F; T]) -> [F; T] {
+        *self.trace(state).last().expect("at least one round")
+    }
+}
+
+/// The first round after which two permutations of the same state differ.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub round: usize,
+    pub kind: RoundKind,
+    /// The states after the round, in the canonical encoding of [`crate::field_encoding`]
+    pub ours: Vec<String>,
+    pub reference: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "round {} ({}): ours [{}], reference [{}]",
+            self.round,
+            self.kind.as_str(),
+            self.ours.join(", "),
+            self.reference.join(", ")
+        )
+    }
+}
+
+/// Compares two permutations of the same rounds on `state`, round by round.
+pub fn first_divergence<F: PrimeField, const T: usize>(
+    ours: &Permutation<F, T>,
+    reference: &Permutation<F, T>,
+    state: [F; T],
+) -> Option<Divergence> {
+    assert_eq!(
+        (ours.r_f, ours.r_p),
+        (reference.r_f, reference.r_p),
+        "permutations of different rounds"
+    );
+    let state_hex = |state: &[F; T]| state.iter().map(to_canonical).collect();
+    ours.trace(state)
+        .iter()
+        .zip(reference.trace(state).iter())
+        .enumerate()
+        .find(|(_, (a, b))| a != b)
+        .map(|(round, (a, b))| Divergence {
+            round,
+            kind: ours.kind(round),
+            ours: state_hex(a),
+            reference: state_hex(b),
+        })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffError {
+    /// The permutations part, where they must agree or at another round than expected
+    Diverged {
+        spec: &'static str,
+        expected: Option<usize>,
+        divergence: Divergence,
+    },
+    /// The permutations agree although they are known to part at `expected`
+    Agreed { spec: &'static str, expected: usize },
+    /// The textbook replay of `implementation` disagrees with its output: the harness is
+    /// wrong, not the permutation
+    Replay {
+        spec: &'static str,
+        implementation: &'static str,
+    },
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Diverged {
+                spec,
+                expected: None,
+                divergence,
+            } => write!(f, "{spec}: the permutations part at {divergence}"),
+            Self::Diverged {
+                spec,
+                expected: Some(expected),
+                divergence,
+            } => write!(
+                f,
+                "{spec}: the permutations part at {divergence}, not at round {expected}"
+            ),
+            Self::Agreed { spec, expected } => write!(
+                f,
+                "{spec}: the permutations agree, but are known to part at round {expected}"
+            ),
+            Self::Replay {
+                spec,
+                implementation,
+            } => write!(
+                f,
+                "{spec}: the replay of {implementation} does not reproduce its output"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DiffError {}
+
+/// A spec of this crate and the reference implementation it is compared to.
+#[derive(Clone, Copy, Debug)]
+pub struct CompatSpec {
+    pub name: &'static str,
+    /// Round at which the permutations are known to part; `None` where they must agree
+    pub known_divergence: Option<usize>,
+    compare: fn(&[u8]) -> Result<Option<Divergence>, DiffError>,
+}
+
+impl CompatSpec {
+    /// Compares the permutations on a state derived from `seed`.
+    pub fn check(&self, seed: &[u8]) -> Result<(), DiffError> {
+        match ((self.compare)(seed)?, self.known_divergence) {
+            (None, None) => Ok(()),
+            (Some(divergence), Some(expected)) if divergence.round == expected => Ok(()),
+            (Some(divergence), expected) => Err(DiffError::Diverged {
+                spec: self.name,
+                expected,
+                divergence,
+            }),
+            (None, Some(expected)) => Err(DiffError::Agreed {
+                spec: self.name,
+                expected,
+            }),
+        }
+    }
+}
+
+/// `P128Pow5T3` of halo2_gadgets over the pallas base field, the permutation of
+/// `Spec::<Fp, 3, 2>::new(8, 56)`.
+pub const HALO2_GADGETS: CompatSpec = CompatSpec {
+    name: "halo2_gadgets P128Pow5T3",
+    known_divergence: None,
+    compare: compare_halo2_gadgets,
+};
+
+/// The arity 2 Merkle hash of neptune over bn256, against the spec of the same rounds.
+///
+/// neptune multiplies by the Cauchy matrix of `x_i = i` and `y_j = t + j`, where the
+/// reference scripts and this crate draw the points from Grain after the round constants,
+/// so the permutations share their constants and part at the first linear layer.
+pub const NEPTUNE: CompatSpec = CompatSpec {
+    name: "neptune arity 2",
+    known_divergence: Some(0),
+    compare: compare_neptune,
+};
+
+pub const COMPATIBILITY: &[CompatSpec] = &[HALO2_GADGETS, NEPTUNE];
+
+/// Checks every entry of [`COMPATIBILITY`] on a state derived from `seed`.
+pub fn check_all(seed: &[u8]) -> Result<(), DiffError> {
+    COMPATIBILITY.iter().try_for_each(|spec| spec.check(seed))
+}
+
+/// A state of uniformly distributed elements, deterministic in `seed`.
+pub fn state_from_seed<F: FromUniformBytes<64>, const T: usize>(seed: &[u8]) -> [F; T] {
+    let mut index = 0u8;
+    [(); T].map(|_| {
+        let mut hasher = blake2b_simd::Params::new().hash_length(64).to_state();
+        hasher.update(&[index]).update(seed);
+        index += 1;
+        let bytes = hasher.finalize();
+        F::from_uniform_bytes(bytes.as_bytes().try_into().expect("64 bytes"))
+    })
+}
+
+/// Checks the replay of a spec against [`State::permute_traced`], the optimized permutation
+/// the hashes and circuits of this crate run.
+fn check_ours<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    name: &'static str,
+    spec: &Spec<F, T, RATE>,
+    ours: &Permutation<F, T>,
+    state: [F; T],
+) -> Result<(), DiffError> {
+    let mut capacity = [F::ZERO; T];
+    capacity[0] = state[0];
+    let mut native = State::<F, T, RATE>::new(capacity);
+    // absorbing a whole chunk adds it to the rate without padding
+    native.permute_traced(spec, &state[1..], |_, _, _| {});
+    if native.words() == ours.permute(state) {
+        Ok(())
+    } else {
+        Err(DiffError::Replay {
+            spec: name,
+            implementation: "poseidon_circuit",
+        })
+    }
+}
+
+/// Converts between the types of one field in different crates.
+fn convert<A: PrimeField<Repr = [u8; 32]>, B: PrimeField<Repr = [u8; 32]>>(a: A) -> B {
+    Option::from(B::from_repr(a.to_repr())).expect("the same field")
+}
+
+#[allow(clippy::type_complexity)]
+fn compare_halo2_gadgets(seed: &[u8]) -> Result<Option<Divergence>, DiffError> {
+    use halo2_gadgets::poseidon::primitives::{
+        ConstantLength, Hash, P128Pow5T3, Spec as GadgetSpec,
+    };
+    use pasta_curves::pallas;
+    type S = P128Pow5T3;
+    const NAME: &str = HALO2_GADGETS.name;
+
+    static PERMUTATIONS: OnceLock<(Spec<Fp, 3, 2>, Permutation<Fp, 3>, Permutation<Fp, 3>)> =
+        OnceLock::new();
+    let (spec, ours, reference) = PERMUTATIONS.get_or_init(|| {
+        let (round_constants, mds, _) = <S as GadgetSpec<pallas::Base, 3, 2>>::constants();
+        let reference = Permutation {
+            round_constants: round_constants.iter().map(|rc| rc.map(convert)).collect(),
+            mds: mds.map(|row| row.map(convert)),
+            r_f: <S as GadgetSpec<pallas::Base, 3, 2>>::full_rounds(),
+            r_p: <S as GadgetSpec<pallas::Base, 3, 2>>::partial_rounds(),
+        };
+        let spec = Spec::new(reference.r_f, reference.r_p);
+        let ours = Permutation::of_spec(&spec);
+        (spec, ours, reference)
+    });
+
+    let state = state_from_seed::<Fp, 3>(seed);
+    check_ours(NAME, spec, ours, state)?;
+    // a message of two elements is absorbed into [m0, m1, 2 * 2^64] and squeezed from the
+    // first element, with the capacity last and no padding
+    let message = [state[0], state[1]];
+    let digest: Fp = convert(
+        Hash::<pallas::Base, S, ConstantLength<2>, 3, 2>::init().hash(message.map(convert)),
+    );
+    if digest != reference.permute([message[0], message[1], Fp::from_u128(2 << 64)])[0] {
+        return Err(DiffError::Replay {
+            spec: NAME,
+            implementation: "halo2_gadgets",
+        });
+    }
+    Ok(first_divergence(ours, reference, state))
+}
+
+#[allow(clippy::type_complexity)]
+fn compare_neptune(seed: &[u8]) -> Result<Option<Divergence>, DiffError> {
+    use neptune::poseidon::{Poseidon, PoseidonConstants};
+    use typenum::U2;
+    const NAME: &str = NEPTUNE.name;
+
+    static PERMUTATIONS: OnceLock<(
+        PoseidonConstants<Fr, U2>,
+        Spec<Fr, 3, 2>,
+        Permutation<Fr, 3>,
+        Permutation<Fr, 3>,
+    )> = OnceLock::new();
+    let (constants, spec, ours, reference) = PERMUTATIONS.get_or_init(|| {
+        let constants = PoseidonConstants::<Fr, U2>::new();
+        let m = &constants.mds_matrices.m;
+        let reference = Permutation {
+            round_constants: constants
+                .round_constants
+                .as_ref()
+                .expect("neptune keeps the round constants")
+                .chunks(3)
+                .map(|rc| rc.try_into().expect("three constants per round"))
+                .collect(),
+            // neptune multiplies the state as a row vector, by the transpose
+            mds: [0, 1, 2].map(|j| [0, 1, 2].map(|i| m[i][j])),
+            r_f: constants.full_rounds,
+            r_p: constants.partial_rounds,
+        };
+        let spec = Spec::new(reference.r_f, reference.r_p);
+        let ours = Permutation::of_spec(&spec);
+        (constants, spec, ours, reference)
+    });
+
+    let state = state_from_seed::<Fr, 3>(seed);
+    check_ours(NAME, spec, ours, state)?;
+    // the preimage follows the domain tag in the state and the digest is the element after it
+    let digest = Poseidon::new_with_preimage(&state[1..], constants).hash();
+    if digest != reference.permute([constants.domain_tag, state[1], state[2]])[1] {
+        return Err(DiffError::Replay {
+            spec: NAME,
+            implementation: "neptune",
+        });
+    }
+    Ok(first_divergence(ours, reference, state))
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+
+    use super::*;
+
+    #[test]
+    fn test_compatibility() {
+        for seed in 0..8u8 {
+            if let Err(err) = check_all(&[seed; 32]) {
+                panic!("{err}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let spec = Spec::<Fr, 3, 2>::new(8, 57);
+        let ours = Permutation::of_spec(&spec);
+        let state = state_from_seed::<Fr, 3>(b"seed");
+        check_ours("bn256", &spec, &ours, state).unwrap();
+        assert_eq!(first_divergence(&ours, &ours, state), None);
+
+        let mut other = ours.clone();
+        other.round_constants[10][2] += Fr::ONE;
+        let divergence = first_divergence(&ours, &other, state).unwrap();
+        assert_eq!(
+            (divergence.round, divergence.kind),
+            (10, RoundKind::Partial)
+        );
+        assert!(divergence.to_string().starts_with("round 10 (partial)"));
+
+        let mut broken = ours.clone();
+        broken.mds[0][0] += Fr::ONE;
+        assert!(matches!(
+            check_ours("bn256", &spec, &broken, state),
+            Err(DiffError::Replay { .. })
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+//! Why a task failed, shared by the library and the service.
+//!
+//! Every variant has a [`Error::code`] that stays the same across releases, for clients that
+//! branch on the kind of a failure rather than parse its message; the service answers with it
+//! as the `error_code` of a [`crate::task::ProofDetail`]. Errors serialize with the code as
+//! their `code` tag, e.g. `{"code": "srs_too_small", "k": 13, "needed": 15}`.
+use std::fmt;
+
+use halo2_proofs::plonk;
+use serde::{Deserialize, Serialize};
+
+use crate::{limits::LimitError, stage::Stage};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Error {
+    /// The task, its payload or the proof detail to verify cannot be read
+    Deserialization {
+        message: String,
+    },
+    /// The task cannot be proven as asked, such as `task_data` of the wrong length
+    InvalidTask {
+        message: String,
+    },
+    Keygen {
+        message: String,
+    },
+    /// The params have `2^k` rows, fewer than the `2^needed` the circuit takes if known
+    SrsTooSmall {
+        k: u32,
+        needed: Option<u32>,
+    },
+    /// Assigning the witness or creating the proof failed
+    Synthesis {
+        message: String,
+    },
+    /// The proof was created or submitted but does not verify
+    VerifyFailed {
+        message: String,
+    },
+    /// The task takes more of a resource than allowed, ran out of time or was cancelled
+    ResourceLimitExceeded {
+        /// See [`LimitError::limit`], e.g. `timeout`
+        limit: String,
+        message: String,
+    },
+}
+
+impl Error {
+    /// The error of `stage` failing with `message`.
+    pub fn new(stage: Stage, message: impl fmt::Display) -> Self {
+        let message = message.to_string();
+        match stage {
+            Stage::Parse => Self::Deserialization { message },
+            Stage::Witness => Self::InvalidTask { message },
+            Stage::Keygen => Self::Keygen { message },
+            Stage::Prove | Stage::Encode => Self::Synthesis { message },
+            Stage::Verify => Self::VerifyFailed { message },
+        }
+    }
+
+    /// The error of `stage` failing in halo2; a circuit that does not fit into its params is
+    /// [`Error::SrsTooSmall`] whatever the stage.
+    pub fn plonk(stage: Stage, err: plonk::Error) -> Self {
+        match err {
+            plonk::Error::NotEnoughRowsAvailable { current_k } => Self::SrsTooSmall {
+                k: current_k,
+                needed: None,
+            },
+            err => Self::new(stage, format!("{err:?}")),
+        }
+    }
+
+    /// The error of a task exceeding a limit.
+    pub fn limit(err: LimitError) -> Self {
+        Self::ResourceLimitExceeded {
+            limit: err.limit().to_string(),
+            message: err.to_string(),
+        }
+    }
+
+    /// The machine-readable name of the variant, its `code` tag when serialized.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Deserialization { .. } => "deserialization",
+            Self::InvalidTask { .. } => "invalid_task",
+            Self::Keygen { .. } => "keygen",
+            Self::SrsTooSmall { .. } => "srs_too_small",
+            Self::Synthesis { .. } => "synthesis",
+            Self::VerifyFailed { .. } => "verify_failed",
+            Self::ResourceLimitExceeded { .. } => "resource_limit_exceeded",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Deserialization { message }
+            | Self::InvalidTask { message }
+            | Self::Keygen { message }
+            | Self::Synthesis { message }
+            | Self::VerifyFailed { message }
+            | Self::ResourceLimitExceeded { message, .. } => f.write_str(message),
+            Self::SrsTooSmall {
+                k,
+                needed: Some(needed),
+            } => write!(
+                f,
+                "the circuit needs params of 2^{needed} rows, the loaded params have 2^{k}"
+            ),
+            Self::SrsTooSmall { k, needed: None } => {
+                write!(f, "the circuit does not fit into params of 2^{k} rows")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        let errors = [
+            Error::new(Stage::Parse, "bad json"),
+            Error::new(Stage::Witness, "too short"),
+            Error::new(Stage::Keygen, "no keys"),
+            Error::SrsTooSmall {
+                k: 13,
+                needed: Some(15),
+            },
+            Error::plonk(Stage::Prove, plonk::Error::Synthesis),
+            Error::new(Stage::Verify, "bad proof"),
+            Error::limit(LimitError::KTooLarge { k: 20, max: 18 }),
+        ];
+        for error in &errors {
+            let json = serde_json::to_value(error).unwrap();
+            assert_eq!(json["code"], error.code());
+            assert_eq!(&serde_json::from_value::<Error>(json).unwrap(), error);
+        }
+        assert_eq!(
+            errors.iter().map(Error::code).collect::<Vec<_>>(),
+            [
+                "deserialization",
+                "invalid_task",
+                "keygen",
+                "srs_too_small",
+                "synthesis",
+                "verify_failed",
+                "resource_limit_exceeded"
+            ]
+        );
+        assert_eq!(errors[0].to_string(), "bad json");
+        let json = serde_json::to_value(&errors[6]).unwrap();
+        assert_eq!(json["limit"], "k");
+        assert_eq!(
+            Error::plonk(
+                Stage::Keygen,
+                plonk::Error::NotEnoughRowsAvailable { current_k: 10 }
+            ),
+            Error::SrsTooSmall {
+                k: 10,
+                needed: None
+            }
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! On-chain verifiers for the circuits of this crate, generated as Yul by snark-verifier, and
+//! proofs in the transcript they replay.
+//!
+//! The contract takes the calldata of [`crate::prover::encode_calldata`]: every instance as a
+//! 32-byte big-endian word, then the proof. Its transcript hashes with keccak256 the way
+//! snark-verifier's `EvmTranscript` does, which is not the halo2 `Keccak256` transcript of
+//! [`ProverContext::prove_keccak`]; proofs for it come from [`prove`].
+//!
+//! Contexts with a salt or a protocol label absorb them ahead of the verifying key, which
+//! the generated contract does not, so only plain contexts get a verifier.
+use std::{fmt, rc::Rc};
+
+use halo2_proofs::{
+    plonk::{create_proof, verify_proof, Circuit, Error},
+    poly::kzg::{
+        commitment::KZGCommitmentScheme,
+        multiopen::{ProverGWC, VerifierGWC},
+        strategy::SingleStrategy,
+    },
+    transcript::{TranscriptReadBuffer, TranscriptWriterBuffer},
+};
+use halo2curves::bn256::{Bn256, Fq, Fr, G1Affine};
+use rand_core::OsRng;
+use snark_verifier::{
+    loader::evm::EvmLoader,
+    pcs::kzg::{Gwc19, KzgAs, KzgDecidingKey},
+    system::halo2::{compile, transcript::evm::EvmTranscript, Config},
+    verifier::{plonk::PlonkVerifier, SnarkVerifier},
+};
+
+use crate::prover::ProverContext;
+
+type Verifier = PlonkVerifier<KzgAs<Bn256, Gwc19>>;
+
+#[derive(Debug)]
+pub enum EvmError {
+    /// The context absorbs a salt or protocol label the contract would not
+    Unsupported(&'static str),
+    /// snark-verifier could not replay the verifying key
+    Codegen(String),
+    Plonk(Error),
+}
+
+impl fmt::Display for EvmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported(what) => write!(f, "EVM verifiers do not support a {what}"),
+            Self::Codegen(err) => write!(f, "failed to generate the verifier: {err}"),
+            Self::Plonk(err) => write!(f, "{err:?}"),
+        }
+    }
+}
+
+impl std::error::Error for EvmError {}
+
+impl From<Error> for EvmError {
+    fn from(err: Error) -> Self {
+        Self::Plonk(err)
+    }
+}
+
+fn check_plain<C: Circuit<Fr>>(ctx: &ProverContext<C>) -> Result<(), EvmError> {
+    if ctx.salt().is_some() {
+        return Err(EvmError::Unsupported("deployment salt"));
+    }
+    if ctx.protocol_label().is_some() {
+        return Err(EvmError::Unsupported("protocol label"));
+    }
+    Ok(())
+}
+
+/// Yul source of a contract verifying proofs of `ctx` with `num_instances` public inputs in
+/// its single instance column; compile it with `solc --yul`.
+pub fn verifier_yul<C: Circuit<Fr>>(
+    ctx: &ProverContext<C>,
+    num_instances: usize,
+) -> Result<String, EvmError> {
+    check_plain(ctx)?;
+    let params = ctx.params();
+    let config = Config::kzg().with_num_instance(vec![num_instances]);
+    let protocol = compile(params, ctx.pk().get_vk(), config);
+    let dk: KzgDecidingKey<Bn256> = (params.get_g()[0], params.g2(), params.s_g2()).into();
+
+    let loader = EvmLoader::new::<Fq, Fr>();
+    let protocol = protocol.loaded(&loader);
+    let mut transcript = EvmTranscript::<_, Rc<EvmLoader>, _, _>::new(&loader);
+    let instances = transcript.load_instances(vec![num_instances]);
+    let proof = Verifier::read_proof(&dk, &protocol, &instances, &mut transcript)
+        .map_err(|err| EvmError::Codegen(format!("{err:?}")))?;
+    Verifier::verify(&dk, &protocol, &instances, &proof)
+        .map_err(|err| EvmError::Codegen(format!("{err:?}")))?;
+    Ok(loader.yul_code())
+}
+
+/// Proves `circuit` in the transcript of [`verifier_yul`]; the calldata is
+/// [`crate::prover::encode_calldata`] of the instances and this proof.
+pub fn prove<C: Circuit<Fr>>(
+    ctx: &ProverContext<C>,
+    circuit: &C,
+    instances: &[Fr],
+) -> Result<Vec<u8>, EvmError> {
+    check_plain(ctx)?;
+    let mut transcript = EvmTranscript::<G1Affine, _, _, _>::init(Vec::new());
+    create_proof::<KZGCommitmentScheme<_>, ProverGWC<'_, _>, _, _, _, _>(
+        ctx.params(),
+        ctx.pk(),
+        std::slice::from_ref(circuit),
+        &[&[instances]],
+        OsRng,
+        &mut transcript,
+    )?;
+    Ok(transcript.finalize())
+}
+
+/// Checks a proof of [`prove`] natively, as the contract of [`verifier_yul`] would.
+pub fn verify<C: Circuit<Fr>>(
+    ctx: &ProverContext<C>,
+    proof: &[u8],
+    instances: &[Fr],
+) -> Result<(), EvmError> {
+    check_plain(ctx)?;
+    let params = ctx.params();
+    let mut transcript = EvmTranscript::<G1Affine, _, _, _>::init(proof);
+    verify_proof::<KZGCommitmentScheme<Bn256>, VerifierGWC<'_, Bn256>, _, _, _>(
+        params,
+        ctx.pk().get_vk(),
+        SingleStrategy::new(params),
+        &[&[instances]],
+        &mut transcript,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use poseidon::Spec;
+
+    use super::*;
+    use crate::{poseidon_hash::hash, test_circuit::TestCircuit};
+
+    #[test]
+    fn test_evm_verifier() {
+        let inputs = (0..5).map(Fr::from).collect::<Vec<_>>();
+        let digest = hash(&Spec::<Fr, 4, 3>::new(8, 56), &inputs);
+        let circuit = TestCircuit::new(inputs);
+        let ctx = ProverContext::setup(10, &circuit).unwrap();
+
+        let yul = verifier_yul(&ctx, 1).unwrap();
+        assert!(yul.contains("object"));
+        let proof = prove(&ctx, &circuit, &[digest]).unwrap();
+        assert!(verify(&ctx, &proof, &[digest]).is_ok());
+        assert!(verify(&ctx, &proof, &[digest + Fr::from(1)]).is_err());
+        // neither transcript accepts the proofs of the other
+        assert!(ctx.verify_keccak(&proof, &[&[digest]]).is_err());
+
+        let salted = ProverContext::setup(10, &circuit)
+            .unwrap()
+            .with_salt(Fr::from(7));
+        assert!(matches!(
+            verifier_yul(&salted, 1),
+            Err(EvmError::Unsupported(_))
+        ));
+    }
+}
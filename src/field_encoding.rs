@@ -0,0 +1,314 @@
+//! Text encodings of field elements accepted from clients.
+//!
+//! Every encoding denotes the integer value of the element, so one element has exactly one
+//! accepted spelling per encoding (modulo leading zeros and letter case):
+//!
+//! * hex: `0x`-prefixed, big-endian, e.g. `0x2ce4...7749`
+//! * decimal: ASCII digits only, e.g. `20304616...50537`
+//! * base64: standard alphabet over the big-endian bytes, anything that is neither of the above
+//!
+//! The canonical form produced by [`to_canonical`] is lowercase hex padded to the field size.
+//! Field representations are assumed little-endian, as for every field in `halo2curves`.
+use std::fmt;
+
+use base64::{engine::general_purpose::STANDARD as BS64, Engine};
+use ff::PrimeField;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldEncoding {
+    Hex,
+    Decimal,
+    Base64,
+}
+
+impl FieldEncoding {
+    /// Picks the encoding of `s` the way [`parse_field`] does.
+    pub fn detect(s: &str) -> Self {
+        if s.starts_with("0x") || s.starts_with("0X") {
+            FieldEncoding::Hex
+        } else if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            FieldEncoding::Decimal
+        } else {
+            FieldEncoding::Base64
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldParseErrorKind {
+    Empty,
+    InvalidHex,
+    InvalidBase64,
+    /// The value is not smaller than the field modulus
+    OutOfField,
+}
+
+impl fmt::Display for FieldParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty value"),
+            Self::InvalidHex => write!(f, "invalid hex digits"),
+            Self::InvalidBase64 => write!(f, "neither decimal, 0x-hex nor valid base64"),
+            Self::OutOfField => write!(f, "value is not smaller than the field modulus"),
+        }
+    }
+}
+
+/// A failure to parse one element of a list of field elements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldParseError {
+    /// Position of the offending element in the input list
+    pub index: usize,
+    /// The offending input, truncated to keep error messages readable
+    pub value: String,
+    pub kind: FieldParseErrorKind,
+}
+
+impl fmt::Display for FieldParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "element {} ({:?}): {}",
+            self.index, self.value, self.kind
+        )
+    }
+}
+
+impl std::error::Error for FieldParseError {}
+
+const MAX_REPORTED_LEN: usize = 80;
+
+/// Parses a single field element in any of the accepted encodings.
+pub fn parse_field<F: PrimeField>(s: &str) -> Result<F, FieldParseErrorKind> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(FieldParseErrorKind::Empty);
+    }
+    let bytes = match FieldEncoding::detect(s) {
+        FieldEncoding::Hex => hex_to_be_bytes(&s[2..])?,
+        FieldEncoding::Decimal => decimal_to_be_bytes(s, repr_len::<F>())?,
+        FieldEncoding::Base64 => BS64
+            .decode(s)
+            .map_err(|_| FieldParseErrorKind::InvalidBase64)?,
+    };
+    from_be_bytes(&bytes)
+}
+
+/// Parses a list of field elements, reporting the index of the first one that fails.
+pub fn parse_fields<F: PrimeField, S: AsRef<str>>(items: &[S]) -> Result<Vec<F>, FieldParseError> {
+    items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            parse_field(item.as_ref()).map_err(|kind| FieldParseError {
+                index,
+                value: item.as_ref().chars().take(MAX_REPORTED_LEN).collect(),
+                kind,
+            })
+        })
+        .collect()
+}
+
+/// The canonical spelling of `f`: `0x` followed by the zero-padded, lowercase big-endian hex.
+pub fn to_canonical<F: PrimeField>(f: &F) -> String {
+    encode_field(f, FieldEncoding::Hex)
+}
+
+pub fn encode_field<F: PrimeField>(f: &F, encoding: FieldEncoding) -> String {
+    let bytes = to_be_bytes(f);
+    match encoding {
+        FieldEncoding::Hex => {
+            let digits: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+            format!("0x{digits}")
+        }
+        FieldEncoding::Decimal => be_bytes_to_decimal(&bytes),
+        FieldEncoding::Base64 => BS64.encode(bytes),
+    }
+}
+
+fn repr_len<F: PrimeField>() -> usize {
+    F::Repr::default().as_ref().len()
+}
+
+fn to_be_bytes<F: PrimeField>(f: &F) -> Vec<u8> {
+    let mut bytes = f.to_repr().as_ref().to_vec();
+    bytes.reverse();
+    bytes
+}
+
+fn from_be_bytes<F: PrimeField>(bytes: &[u8]) -> Result<F, FieldParseErrorKind> {
+    let significant = &bytes[bytes.iter().take_while(|b| **b == 0).count()..];
+    let mut repr = F::Repr::default();
+    if significant.len() > repr.as_ref().len() {
+        return Err(FieldParseErrorKind::OutOfField);
+    }
+    for (dst, src) in repr.as_mut().iter_mut().zip(significant.iter().rev()) {
+        *dst = *src;
+    }
+    Option::from(F::from_repr(repr)).ok_or(FieldParseErrorKind::OutOfField)
+}
+
+fn hex_to_be_bytes(digits: &str) -> Result<Vec<u8>, FieldParseErrorKind> {
+    if digits.is_empty() {
+        return Err(FieldParseErrorKind::Empty);
+    }
+    let nibbles = digits
+        .chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(FieldParseErrorKind::InvalidHex)?;
+    // an odd number of digits gets an implicit leading zero
+    let pad = nibbles.len() % 2;
+    Ok(std::iter::repeat_n(0, pad)
+        .chain(nibbles)
+        .collect::<Vec<_>>()
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect())
+}
+
+/// Converts ASCII digits to big-endian bytes, giving up once the value needs more than
+/// `max_len` bytes since it can no longer be in the field.
+fn decimal_to_be_bytes(digits: &str, max_len: usize) -> Result<Vec<u8>, FieldParseErrorKind> {
+    let mut out: Vec<u8> = Vec::new();
+    for digit in digits.bytes() {
+        let mut carry = (digit - b'0') as u32;
+        for byte in out.iter_mut().rev() {
+            let v = *byte as u32 * 10 + carry;
+            *byte = v as u8;
+            carry = v >> 8;
+        }
+        if carry > 0 {
+            out.insert(0, carry as u8);
+        }
+        if out.len() > max_len {
+            return Err(FieldParseErrorKind::OutOfField);
+        }
+    }
+    Ok(out)
+}
+
+fn be_bytes_to_decimal(bytes: &[u8]) -> String {
+    let mut num = bytes.to_vec();
+    let mut digits = Vec::new();
+    while num.iter().any(|b| *b != 0) {
+        let mut rem = 0u32;
+        for byte in num.iter_mut() {
+            let v = (rem << 8) | *byte as u32;
+            *byte = (v / 10) as u8;
+            rem = v % 10;
+        }
+        digits.push(b'0' + rem as u8);
+    }
+    if digits.is_empty() {
+        return "0".to_string();
+    }
+    digits.reverse();
+    String::from_utf8(digits).expect("ASCII digits")
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::{Field, FromUniformBytes};
+    use halo2curves::bn256::Fr;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const HASH_DEC: &str =
+        "20304616028358001435806807494046171997958789835068077254356069730773893150537";
+    const HASH_HEX: &str = "0x2ce4016298e9e5fcaa94ccb686413e16add1bb813def8a3a0628aed46ea07749";
+
+    #[test]
+    fn test_round_trip() {
+        let values = [
+            Fr::ZERO,
+            Fr::ONE,
+            -Fr::ONE,
+            Fr::from(1u64 << 40),
+            Fr::from_str_vartime(HASH_DEC).unwrap(),
+        ];
+        for value in values {
+            for encoding in [
+                FieldEncoding::Hex,
+                FieldEncoding::Decimal,
+                FieldEncoding::Base64,
+            ] {
+                let encoded = encode_field(&value, encoding);
+                assert_eq!(FieldEncoding::detect(&encoded), encoding);
+                assert_eq!(parse_field::<Fr>(&encoded), Ok(value), "{encoded}");
+            }
+        }
+    }
+
+    fn any_fr() -> impl Strategy<Value = Fr> {
+        let half = || prop::array::uniform32(any::<u8>());
+        (half(), half()).prop_map(|(low, high)| {
+            let bytes: [u8; 64] = [low, high].concat().try_into().unwrap();
+            Fr::from_uniform_bytes(&bytes)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_any_element_round_trip(value in any_fr()) {
+            for encoding in [
+                FieldEncoding::Hex,
+                FieldEncoding::Decimal,
+                FieldEncoding::Base64,
+            ] {
+                let encoded = encode_field(&value, encoding);
+                prop_assert_eq!(parse_field::<Fr>(&encoded), Ok(value));
+            }
+            let upper = to_canonical(&value).to_uppercase();
+            prop_assert_eq!(parse_field::<Fr>(&upper), Ok(value));
+        }
+
+        /// Any text is an element or an error, and an element in every spelling of it.
+        #[test]
+        fn test_any_text(text in any::<String>()) {
+            if let Ok(value) = parse_field::<Fr>(&text) {
+                let canonical = to_canonical(&value);
+                prop_assert_eq!(parse_field::<Fr>(&canonical), Ok(value));
+            }
+        }
+    }
+
+    #[test]
+    fn test_equivalent_spellings() {
+        let hash = Fr::from_str_vartime(HASH_DEC).unwrap();
+        assert_eq!(parse_field::<Fr>(HASH_DEC), Ok(hash));
+        assert_eq!(parse_field::<Fr>(HASH_HEX), Ok(hash));
+        assert_eq!(parse_field::<Fr>(&HASH_HEX.to_uppercase()[..]), Ok(hash));
+        assert_eq!(to_canonical(&hash), HASH_HEX);
+        assert_eq!(parse_field::<Fr>("0x7"), Ok(Fr::from(7)));
+        assert_eq!(parse_field::<Fr>("007"), Ok(Fr::from(7)));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse_field::<Fr>(Fr::MODULUS),
+            Err(FieldParseErrorKind::OutOfField)
+        );
+        assert_eq!(
+            parse_field::<Fr>(
+                "21888242871839275222246405745257275088548364400416034343698204186575808495617"
+            ),
+            Err(FieldParseErrorKind::OutOfField)
+        );
+        assert_eq!(parse_field::<Fr>("0x"), Err(FieldParseErrorKind::Empty));
+        assert_eq!(
+            parse_field::<Fr>("0xzz"),
+            Err(FieldParseErrorKind::InvalidHex)
+        );
+        assert_eq!(
+            parse_field::<Fr>("-1"),
+            Err(FieldParseErrorKind::InvalidBase64)
+        );
+
+        let err = parse_fields::<Fr, _>(&["1", HASH_HEX, "0xg"]).unwrap_err();
+        assert_eq!(err.index, 2);
+        assert_eq!(err.kind, FieldParseErrorKind::InvalidHex);
+    }
+}
@@ -0,0 +1,333 @@
+//! A native Fiat–Shamir transcript over Poseidon, for protocols built next to this crate.
+//!
+//! Sigma protocols and folding schemes that derive their challenges with [`FsTranscript`]
+//! get the same challenges as an in-circuit verifier hashing the same messages with
+//! [`crate::poseidon_circuit::PoseidonChip`], since the transcript is nothing but a chain of
+//! [`crate::poseidon_hash::hash`] calls over field elements.
+//!
+//! # Domain scheme
+//!
+//! Every absorbed item is a tag followed by its encoding; byte strings are encoded with
+//! [`crate::packing::pack_bytes`]:
+//!
+//! | item               | elements                                                   |
+//! |--------------------|------------------------------------------------------------|
+//! | protocol name `p`  | `DOMAIN_PROTOCOL, pack(p)`                                 |
+//! | label `l`          | `DOMAIN_LABEL, pack(l)`                                    |
+//! | scalar `x`         | `DOMAIN_SCALAR, x`                                         |
+//! | point `(x, y)`     | `DOMAIN_POINT, 0, pack(repr(x)), pack(repr(y))`            |
+//! | point at infinity  | `DOMAIN_POINT, 1`                                          |
+//!
+//! The state starts as `hash(protocol name item)`. A challenge is
+//! `hash([DOMAIN_CHALLENGE, state, items absorbed since the last challenge...])`, and
+//! becomes the new state, so every challenge commits to the whole transcript before it.
+//!
+//! # halo2 proofs
+//!
+//! [`PoseidonWrite`] and [`PoseidonRead`] are halo2 transcripts over bn256 deriving their
+//! challenges this way, under [`HALO2_PROTOCOL`] and [`BN256_T4_R3`]: every point and scalar
+//! of the proof is absorbed as above, and a challenge is the squeezed element itself. Proofs
+//! in them cost a recursive verifier a few Poseidon hashes per challenge rather than a
+//! Blake2b or Keccak circuit.
+use std::{
+    io::{self, Read, Write},
+    sync::OnceLock,
+};
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::{
+    arithmetic::CurveAffine,
+    transcript::{
+        Challenge255, EncodedChallenge, Transcript, TranscriptRead, TranscriptReadBuffer,
+        TranscriptWrite, TranscriptWriterBuffer,
+    },
+};
+use halo2curves::{
+    bn256::{Fr, G1Affine},
+    group::GroupEncoding,
+};
+use poseidon::Spec;
+
+use crate::{packing::pack_bytes, poseidon_hash::hash, specs::BN256_T4_R3};
+
+pub const DOMAIN_PROTOCOL: u64 = 1;
+pub const DOMAIN_LABEL: u64 = 2;
+pub const DOMAIN_SCALAR: u64 = 3;
+pub const DOMAIN_POINT: u64 = 4;
+pub const DOMAIN_CHALLENGE: u64 = 5;
+
+#[derive(Clone, Debug)]
+pub struct FsTranscript<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> {
+    spec: Spec<F, T, RATE>,
+    state: F,
+    buf: Vec<F>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    FsTranscript<F, T, RATE>
+{
+    /// Starts a transcript bound to `protocol`, e.g. `"my-sigma-protocol/v1"`.
+    pub fn new(spec: Spec<F, T, RATE>, protocol: &str) -> Self {
+        let mut init = vec![F::from(DOMAIN_PROTOCOL)];
+        init.extend(pack_bytes::<F>(protocol.as_bytes()));
+        let state = hash(&spec, &init);
+        Self {
+            spec,
+            state,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn absorb_label(&mut self, label: &str) {
+        self.buf.push(F::from(DOMAIN_LABEL));
+        self.buf.extend(pack_bytes::<F>(label.as_bytes()));
+    }
+
+    pub fn absorb_scalar(&mut self, scalar: F) {
+        self.buf.extend([F::from(DOMAIN_SCALAR), scalar]);
+    }
+
+    pub fn absorb_scalars(&mut self, scalars: &[F]) {
+        for scalar in scalars {
+            self.absorb_scalar(*scalar);
+        }
+    }
+
+    /// Absorbs a point of any curve, by the canonical bytes of its coordinates.
+    pub fn absorb_point<C: CurveAffine>(&mut self, point: &C) {
+        self.buf.push(F::from(DOMAIN_POINT));
+        match Option::from(point.coordinates()) {
+            Some(coordinates) => {
+                self.buf.push(F::ZERO);
+                self.buf
+                    .extend(pack_bytes::<F>(coordinates.x().to_repr().as_ref()));
+                self.buf
+                    .extend(pack_bytes::<F>(coordinates.y().to_repr().as_ref()));
+            }
+            None => self.buf.push(F::ONE),
+        }
+    }
+
+    pub fn squeeze_challenge(&mut self) -> F {
+        let mut inputs = vec![F::from(DOMAIN_CHALLENGE), self.state];
+        inputs.append(&mut self.buf);
+        self.state = hash(&self.spec, &inputs);
+        self.state
+    }
+
+    pub fn squeeze_challenges(&mut self, n: usize) -> Vec<F> {
+        (0..n).map(|_| self.squeeze_challenge()).collect()
+    }
+}
+
+/// Protocol name of the transcripts of halo2 proofs, see [`PoseidonWrite`].
+pub const HALO2_PROTOCOL: &str = "poseidon-circuit/halo2-kzg/v1";
+
+fn halo2_transcript() -> FsTranscript<Fr, 4, 3> {
+    static SPEC: OnceLock<Spec<Fr, 4, 3>> = OnceLock::new();
+    let spec = SPEC.get_or_init(|| Spec::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p));
+    FsTranscript::new(spec.clone(), HALO2_PROTOCOL)
+}
+
+/// The squeezed element, reduced from its little-endian bytes like every halo2 challenge.
+fn halo2_challenge(fs: &mut FsTranscript<Fr, 4, 3>) -> Challenge255<G1Affine> {
+    let mut bytes = [0; 64];
+    bytes[..32].copy_from_slice(fs.squeeze_challenge().to_repr().as_ref());
+    Challenge255::new(&bytes)
+}
+
+/// Writes halo2 proofs over bn256 in the Poseidon transcript of this module.
+pub struct PoseidonWrite<W: Write> {
+    fs: FsTranscript<Fr, 4, 3>,
+    writer: W,
+}
+
+impl<W: Write> Transcript<G1Affine, Challenge255<G1Affine>> for PoseidonWrite<W> {
+    fn squeeze_challenge(&mut self) -> Challenge255<G1Affine> {
+        halo2_challenge(&mut self.fs)
+    }
+
+    fn common_point(&mut self, point: G1Affine) -> io::Result<()> {
+        self.fs.absorb_point(&point);
+        Ok(())
+    }
+
+    fn common_scalar(&mut self, scalar: Fr) -> io::Result<()> {
+        self.fs.absorb_scalar(scalar);
+        Ok(())
+    }
+}
+
+impl<W: Write> TranscriptWrite<G1Affine, Challenge255<G1Affine>> for PoseidonWrite<W> {
+    fn write_point(&mut self, point: G1Affine) -> io::Result<()> {
+        self.common_point(point)?;
+        self.writer.write_all(point.to_bytes().as_ref())
+    }
+
+    fn write_scalar(&mut self, scalar: Fr) -> io::Result<()> {
+        self.common_scalar(scalar)?;
+        self.writer.write_all(scalar.to_repr().as_ref())
+    }
+}
+
+impl<W: Write> TranscriptWriterBuffer<W, G1Affine, Challenge255<G1Affine>> for PoseidonWrite<W> {
+    fn init(writer: W) -> Self {
+        Self {
+            fs: halo2_transcript(),
+            writer,
+        }
+    }
+
+    fn finalize(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads halo2 proofs written by [`PoseidonWrite`].
+pub struct PoseidonRead<R: Read> {
+    fs: FsTranscript<Fr, 4, 3>,
+    reader: R,
+}
+
+impl<R: Read> Transcript<G1Affine, Challenge255<G1Affine>> for PoseidonRead<R> {
+    fn squeeze_challenge(&mut self) -> Challenge255<G1Affine> {
+        halo2_challenge(&mut self.fs)
+    }
+
+    fn common_point(&mut self, point: G1Affine) -> io::Result<()> {
+        self.fs.absorb_point(&point);
+        Ok(())
+    }
+
+    fn common_scalar(&mut self, scalar: Fr) -> io::Result<()> {
+        self.fs.absorb_scalar(scalar);
+        Ok(())
+    }
+}
+
+impl<R: Read> TranscriptRead<G1Affine, Challenge255<G1Affine>> for PoseidonRead<R> {
+    fn read_point(&mut self) -> io::Result<G1Affine> {
+        let mut repr = <G1Affine as GroupEncoding>::Repr::default();
+        self.reader.read_exact(repr.as_mut())?;
+        let point = Option::<G1Affine>::from(G1Affine::from_bytes(&repr))
+            .ok_or_else(|| io::Error::other("invalid point encoding in proof"))?;
+        self.common_point(point)?;
+        Ok(point)
+    }
+
+    fn read_scalar(&mut self) -> io::Result<Fr> {
+        let mut repr = <Fr as PrimeField>::Repr::default();
+        self.reader.read_exact(repr.as_mut())?;
+        let scalar = Option::<Fr>::from(Fr::from_repr(repr))
+            .ok_or_else(|| io::Error::other("invalid field element encoding in proof"))?;
+        self.common_scalar(scalar)?;
+        Ok(scalar)
+    }
+}
+
+impl<R: Read> TranscriptReadBuffer<R, G1Affine, Challenge255<G1Affine>> for PoseidonRead<R> {
+    fn init(reader: R) -> Self {
+        Self {
+            fs: halo2_transcript(),
+            reader,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::{
+        bn256::{Fr, G1Affine},
+        group::{prime::PrimeCurveAffine, Curve},
+    };
+
+    use super::*;
+
+    fn transcript() -> FsTranscript<Fr, 4, 3> {
+        FsTranscript::new(Spec::new(8, 56), "test")
+    }
+
+    #[test]
+    fn test_domain_scheme() {
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        let mut t = transcript();
+        t.absorb_label("x");
+        t.absorb_scalar(Fr::from(7));
+        let challenge = t.squeeze_challenge();
+
+        let init = hash(
+            &spec,
+            &[Fr::from(DOMAIN_PROTOCOL), Fr::from(4), Fr::from(0x74657374)],
+        );
+        let expected = hash(
+            &spec,
+            &[
+                Fr::from(DOMAIN_CHALLENGE),
+                init,
+                Fr::from(DOMAIN_LABEL),
+                Fr::from(1),
+                Fr::from(b'x' as u64),
+                Fr::from(DOMAIN_SCALAR),
+                Fr::from(7),
+            ],
+        );
+        assert_eq!(challenge, expected);
+        // the next challenge chains on the previous one
+        assert_eq!(
+            t.squeeze_challenge(),
+            hash(&spec, &[Fr::from(DOMAIN_CHALLENGE), expected])
+        );
+    }
+
+    #[test]
+    fn test_separation() {
+        let challenge = |f: &dyn Fn(&mut FsTranscript<Fr, 4, 3>)| {
+            let mut t = transcript();
+            f(&mut t);
+            t.squeeze_challenge()
+        };
+        let g = G1Affine::generator();
+        let base = challenge(&|t| t.absorb_scalar(Fr::from(1)));
+        assert_ne!(base, challenge(&|t| t.absorb_label("\u{1}")));
+        assert_ne!(
+            base,
+            challenge(&|t| t.absorb_scalars(&[Fr::from(1), Fr::from(0)]))
+        );
+        assert_ne!(
+            challenge(&|t| t.absorb_point(&g)),
+            challenge(&|t| t.absorb_point(&(g + g).to_affine()))
+        );
+        assert_ne!(
+            challenge(&|t| t.absorb_point(&G1Affine::identity())),
+            challenge(&|t| t.absorb_point(&g))
+        );
+        let other = FsTranscript::<Fr, 4, 3>::new(Spec::new(8, 56), "other").squeeze_challenge();
+        assert_ne!(transcript().squeeze_challenge(), other);
+        assert_eq!(transcript().squeeze_challenges(2).len(), 2);
+    }
+
+    #[test]
+    fn test_halo2_transcript() {
+        let g = G1Affine::generator();
+        let mut writer = PoseidonWrite::init(Vec::new());
+        writer.write_point(g).unwrap();
+        writer.write_scalar(Fr::from(9)).unwrap();
+        let challenge = writer.squeeze_challenge().get_scalar();
+
+        // the challenge is that of the native transcript
+        let mut fs = FsTranscript::<Fr, 4, 3>::new(Spec::new(8, 56), HALO2_PROTOCOL);
+        fs.absorb_point(&g);
+        fs.absorb_scalar(Fr::from(9));
+        assert_eq!(challenge, fs.squeeze_challenge());
+
+        let proof = writer.finalize();
+        let mut reader = PoseidonRead::init(proof.as_slice());
+        assert_eq!(reader.read_point().unwrap(), g);
+        assert_eq!(reader.read_scalar().unwrap(), Fr::from(9));
+        assert_eq!(reader.squeeze_challenge().get_scalar(), challenge);
+        assert!(reader.read_scalar().is_err());
+
+        let mut reader = PoseidonRead::init([0xff; 32].as_slice());
+        assert!(reader.read_scalar().is_err());
+    }
+}
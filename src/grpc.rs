@@ -0,0 +1,294 @@
+//! The prover over gRPC, for orchestrators that do not speak the snarkify protocol.
+//!
+//! [`GrpcProver`] serves the `Prover` service of `proto/prover.proto` with the semantics of
+//! the snarkify handler: requests carry the JSON of a [`Task`] and answers the JSON of its
+//! [`ProofDetail`], so a stage that fails is reported in the proof detail, not as a gRPC
+//! error. Every task goes through a [`JobQueue`]: `Prove` answers once the task is done,
+//! `ProveStream` streams where it is, queued, running and done, and `Status` tells where a
+//! task submitted by either is.
+//!
+//! Only tasks the queue does not take fail the call: unreadable ones and ones without a
+//! `uuid` with `INVALID_ARGUMENT`, ones finding the queue full with `RESOURCE_EXHAUSTED`,
+//! and ones whose `uuid` is queued or running with `ALREADY_EXISTS`.
+use std::{net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
+
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{Request, Response, Status};
+
+use crate::{
+    jobs::{JobError, JobQueue, JobStatus},
+    task::{ProofDetail, Task},
+};
+
+/// The messages and the service of `proto/prover.proto`.
+pub mod proto {
+    tonic::include_proto!("poseidon.prover.v1");
+}
+
+use proto::{
+    proof_status::Status as Phase,
+    prover_server::{Prover, ProverServer},
+    ProofReply, ProofStatus, Running, StatusRequest, TaskRequest,
+};
+
+/// How often a call waiting for a task looks at the job queue.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+type Parser = dyn Fn(&str) -> Result<Task, String> + Send + Sync;
+
+type StatusStream = Pin<Box<dyn Stream<Item = Result<ProofStatus, Status>> + Send>>;
+
+/// The `Prover` service over a job queue; clones share the queue.
+#[derive(Clone)]
+pub struct GrpcProver {
+    jobs: JobQueue,
+    parse: Arc<Parser>,
+    poll: Duration,
+}
+
+impl GrpcProver {
+    /// Proves tasks on the workers of `jobs`, reading the JSON of requests with `parse`, which
+    /// may check and resolve tasks like the snarkify handler does.
+    pub fn new(
+        jobs: JobQueue,
+        parse: impl Fn(&str) -> Result<Task, String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            jobs,
+            parse: Arc::new(parse),
+            poll: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    pub fn with_poll_interval(mut self, poll: Duration) -> Self {
+        self.poll = poll;
+        self
+    }
+
+    pub fn into_service(self) -> ProverServer<Self> {
+        ProverServer::new(self)
+    }
+
+    /// Serves on `addr` until the future is dropped.
+    pub async fn serve(self, addr: SocketAddr) -> Result<(), tonic::transport::Error> {
+        tonic::transport::Server::builder()
+            .add_service(self.into_service())
+            .serve(addr)
+            .await
+    }
+
+    /// Queues the task of `request`, answering with its `uuid`.
+    fn submit(&self, request: TaskRequest) -> Result<String, Status> {
+        let task = (self.parse)(&request.task_json).map_err(Status::invalid_argument)?;
+        if task.uuid.is_empty() {
+            return Err(Status::invalid_argument(
+                "tasks proven over gRPC need a uuid",
+            ));
+        }
+        let uuid = task.uuid.clone();
+        self.jobs.submit(task).map_err(|err| match err {
+            JobError::Full { .. } => Status::resource_exhausted(err.to_string()),
+            JobError::Duplicate(_) => Status::already_exists(err.to_string()),
+        })?;
+        Ok(uuid)
+    }
+
+    /// Sends every new status of the job of `uuid` to `updates`, until it is done or the
+    /// caller hung up; the job goes on either way.
+    async fn watch(
+        jobs: JobQueue,
+        uuid: String,
+        poll: Duration,
+        updates: mpsc::Sender<Result<ProofStatus, Status>>,
+    ) {
+        let mut last = None;
+        loop {
+            let status = jobs.status(&uuid);
+            if status != last {
+                let Some(current) = &status else {
+                    let _ = updates.send(Err(forgotten(&uuid))).await;
+                    return;
+                };
+                let done = matches!(current, JobStatus::Done { .. });
+                if updates.send(Ok(to_proto(&uuid, current))).await.is_err() || done {
+                    return;
+                }
+                last = status;
+            }
+            tokio::time::sleep(poll).await;
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Prover for GrpcProver {
+    type ProveStreamStream = StatusStream;
+
+    async fn prove(&self, request: Request<TaskRequest>) -> Result<Response<ProofReply>, Status> {
+        let uuid = self.submit(request.into_inner())?;
+        loop {
+            match self.jobs.status(&uuid) {
+                Some(JobStatus::Done { detail }) => return Ok(Response::new(reply(&detail))),
+                Some(_) => tokio::time::sleep(self.poll).await,
+                None => return Err(forgotten(&uuid)),
+            }
+        }
+    }
+
+    async fn prove_stream(
+        &self,
+        request: Request<TaskRequest>,
+    ) -> Result<Response<Self::ProveStreamStream>, Status> {
+        let uuid = self.submit(request.into_inner())?;
+        let (updates, stream) = mpsc::channel(4);
+        tokio::spawn(Self::watch(self.jobs.clone(), uuid, self.poll, updates));
+        Ok(Response::new(Box::pin(ReceiverStream::new(stream))))
+    }
+
+    async fn status(
+        &self,
+        request: Request<StatusRequest>,
+    ) -> Result<Response<ProofStatus>, Status> {
+        let uuid = request.into_inner().uuid;
+        match self.jobs.status(&uuid) {
+            Some(status) => Ok(Response::new(to_proto(&uuid, &status))),
+            None => Err(Status::not_found(format!("task {uuid} is not known"))),
+        }
+    }
+}
+
+fn reply(detail: &ProofDetail) -> ProofReply {
+    ProofReply {
+        detail_json: serde_json::to_string(detail).expect("proof details serialize"),
+    }
+}
+
+fn to_proto(uuid: &str, status: &JobStatus) -> ProofStatus {
+    let phase = match status {
+        JobStatus::Queued { position } => Phase::Queued(*position as u64),
+        JobStatus::Running => Phase::Running(Running {}),
+        JobStatus::Done { detail } => Phase::Done(reply(detail)),
+    };
+    ProofStatus {
+        uuid: uuid.to_string(),
+        status: Some(phase),
+    }
+}
+
+/// The job finished and more than [`crate::jobs::JobConfig::retain`] jobs after it did
+/// before it was looked at again.
+fn forgotten(uuid: &str) -> Status {
+    Status::not_found(format!("task {uuid} finished and is no longer retained"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc as std_mpsc, Mutex};
+
+    use tokio_stream::StreamExt;
+    use tonic::Code;
+
+    use super::*;
+    use crate::jobs::JobConfig;
+
+    /// A prover whose handler answers a task once `release` is sent a unit.
+    fn prover() -> (GrpcProver, std_mpsc::Sender<()>) {
+        let (release, gate) = std_mpsc::channel::<()>();
+        let gate = Mutex::new(gate);
+        let config = JobConfig {
+            workers: 1,
+            capacity: 1,
+            retain: 4,
+        };
+        let jobs = JobQueue::start(config, move |task| {
+            gate.lock().unwrap().recv().unwrap();
+            ProofDetail {
+                id: task.id.clone(),
+                proof_data: "AAEC".to_string(),
+                ..Default::default()
+            }
+        });
+        let parse = |json: &str| Task::from_json(json.as_bytes()).map_err(|err| err.to_string());
+        let prover = GrpcProver::new(jobs, parse).with_poll_interval(Duration::from_millis(1));
+        (prover, release)
+    }
+
+    fn request(uuid: &str) -> Request<TaskRequest> {
+        Request::new(TaskRequest {
+            task_json: format!(r#"{{"uuid": "{uuid}", "id": "id-{uuid}", "type": "chunk"}}"#),
+        })
+    }
+
+    fn detail(reply: &ProofReply) -> ProofDetail {
+        serde_json::from_str(&reply.detail_json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_prove() {
+        let (prover, release) = prover();
+        release.send(()).unwrap();
+        let reply = prover.prove(request("a")).await.unwrap().into_inner();
+        assert_eq!(detail(&reply).id, "id-a");
+        assert_eq!(detail(&reply).proof_data, "AAEC");
+
+        let status = prover
+            .status(Request::new(StatusRequest {
+                uuid: "a".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.status, Some(Phase::Done(reply)));
+
+        let unknown = Request::new(StatusRequest {
+            uuid: "b".to_string(),
+        });
+        assert_eq!(
+            prover.status(unknown).await.unwrap_err().code(),
+            Code::NotFound
+        );
+        for task_json in ["{", r#"{"uuid": "", "id": "t"}"#] {
+            let request = Request::new(TaskRequest {
+                task_json: task_json.to_string(),
+            });
+            let err = prover.prove(request).await.unwrap_err();
+            assert_eq!(err.code(), Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prove_stream() {
+        let (prover, release) = prover();
+        let mut stream = prover
+            .prove_stream(request("a"))
+            .await
+            .unwrap()
+            .into_inner();
+        // the handler holds the task until released, so it is seen running
+        let mut phases = vec![];
+        while phases.last() != Some(&Phase::Running(Running {})) {
+            let status = stream.next().await.unwrap().unwrap();
+            assert_eq!(status.uuid, "a");
+            phases.push(status.status.unwrap());
+        }
+        assert!(phases[..phases.len() - 1]
+            .iter()
+            .all(|phase| *phase == Phase::Queued(0)));
+
+        let duplicate = prover.prove_stream(request("a")).await;
+        assert_eq!(duplicate.err().unwrap().code(), Code::AlreadyExists);
+        // the worker is busy, so the next task waits and the one after finds the queue full
+        let _queued = prover.prove_stream(request("b")).await.unwrap();
+        let full = prover.prove_stream(request("c")).await;
+        assert_eq!(full.err().unwrap().code(), Code::ResourceExhausted);
+
+        release.send(()).unwrap();
+        let rest = stream.collect::<Vec<_>>().await;
+        assert_eq!(rest.len(), 1);
+        match rest[0].as_ref().unwrap().status.as_ref().unwrap() {
+            Phase::Done(reply) => assert_eq!(detail(reply).id, "id-a"),
+            phase => panic!("{phase:?} after running"),
+        }
+    }
+}
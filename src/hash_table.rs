@@ -0,0 +1,305 @@
+use ff::PrimeField;
+use halo2_proofs::{
+    circuit::Layouter,
+    plonk::{Advice, Column, ConstraintSystem, Error, Expression, Fixed, VirtualCells},
+    poly::Rotation,
+};
+use poseidon::Spec;
+
+use crate::{
+    main_gate::{AssignedValue, MainGateConfig, RegionCtx},
+    poseidon_circuit::PoseidonChip,
+};
+
+/// Columns of a lookup table of Poseidon hashes: one `(inputs..., digest)` row per message of
+/// exactly `WIDTH` elements.
+///
+/// Other sub-circuits check a hash with [`PoseidonTableConfig::lookup`] instead of
+/// instantiating the permutation themselves; [`PoseidonTable`] fills the table and
+/// copy-constrains every row to a permutation it computed.
+#[derive(Clone, Copy, Debug)]
+pub struct PoseidonTableConfig<const WIDTH: usize> {
+    pub q_enable: Column<Fixed>,
+    pub inputs: [Column<Advice>; WIDTH],
+    pub digest: Column<Advice>,
+}
+
+impl<const WIDTH: usize> PoseidonTableConfig<WIDTH> {
+    pub fn configure<F: PrimeField>(meta: &mut ConstraintSystem<F>) -> Self {
+        let q_enable = meta.fixed_column();
+        let inputs = [(); WIDTH].map(|_| meta.advice_column());
+        let digest = meta.advice_column();
+        for col in inputs.iter().chain(std::iter::once(&digest)) {
+            meta.enable_equality(*col);
+        }
+        meta.annotate_lookup_any_column(q_enable, || "poseidon table: q_enable");
+        for (i, col) in inputs.iter().enumerate() {
+            meta.annotate_lookup_any_column(*col, || format!("poseidon table: input[{i}]"));
+        }
+        meta.annotate_lookup_any_column(digest, || "poseidon table: digest");
+        Self {
+            q_enable,
+            inputs,
+            digest,
+        }
+    }
+
+    /// Registers a lookup of `(inputs..., digest)` into the table.
+    ///
+    /// `f` returns `(q, inputs, digest)` queried from the caller's columns; rows where `q` is
+    /// zero are not constrained.
+    pub fn lookup<F: PrimeField>(
+        &self,
+        meta: &mut ConstraintSystem<F>,
+        name: &'static str,
+        f: impl FnOnce(
+            &mut VirtualCells<'_, F>,
+        ) -> (Expression<F>, [Expression<F>; WIDTH], Expression<F>),
+    ) {
+        meta.lookup_any(name, |meta| {
+            let (q, inputs, digest) = f(meta);
+            let q_table = meta.query_fixed(self.q_enable, Rotation::cur());
+            let table_digest = meta.query_advice(self.digest, Rotation::cur());
+            // q takes part in the lookup so an enabled all-zero row cannot hit an unused row
+            std::iter::once((q.clone(), q_table.clone()))
+                .chain(inputs.into_iter().zip(self.inputs).map(|(input, col)| {
+                    let table_input = meta.query_advice(col, Rotation::cur());
+                    (q.clone() * input, q_table.clone() * table_input)
+                }))
+                .chain(std::iter::once((q * digest, q_table * table_digest)))
+                .collect()
+        });
+    }
+}
+
+/// Hashes messages with a [`PoseidonChip`] and publishes every `(message, digest)` pair in a
+/// [`PoseidonTableConfig`].
+pub struct PoseidonTable<F: PrimeField, const T: usize, const RATE: usize, const WIDTH: usize> {
+    main_gate: MainGateConfig<T>,
+    table: PoseidonTableConfig<WIDTH>,
+    spec: Spec<F, T, RATE>,
+    message_regions: bool,
+}
+
+impl<F: PrimeField, const T: usize, const RATE: usize, const WIDTH: usize>
+    PoseidonTable<F, T, RATE, WIDTH>
+{
+    pub fn new(
+        main_gate: MainGateConfig<T>,
+        table: PoseidonTableConfig<WIDTH>,
+        spec: Spec<F, T, RATE>,
+    ) -> Self {
+        Self {
+            main_gate,
+            table,
+            spec,
+            message_regions: false,
+        }
+    }
+
+    /// Hashes every message in its own region named after its index, so a failing
+    /// constraint points at the message that caused it.
+    pub fn with_message_regions(mut self) -> Self {
+        self.message_regions = true;
+        self
+    }
+
+    /// Hashes every message and writes the table rows, returning the digest cells in order.
+    pub fn load(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        messages: &[[F; WIDTH]],
+    ) -> Result<Vec<AssignedValue<F>>, Error> {
+        let hash = |ctx: &mut RegionCtx<'_, F>, msg: &[F; WIDTH]| {
+            let mut pchip = PoseidonChip::new(self.main_gate.clone(), self.spec.clone());
+            pchip.update(msg.to_vec());
+            pchip.squeeze_with_inputs(ctx)
+        };
+        let hashed = if self.message_regions {
+            messages
+                .iter()
+                .enumerate()
+                .map(|(i, msg)| {
+                    layouter.assign_region(
+                        || format!("poseidon table: message {i}"),
+                        |region| hash(&mut RegionCtx::new(region, 0), msg),
+                    )
+                })
+                .collect::<Result<Vec<_>, Error>>()?
+        } else {
+            layouter.assign_region(
+                || "poseidon table: hashes",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    messages
+                        .iter()
+                        .map(|msg| hash(ctx, msg))
+                        .collect::<Result<Vec<_>, Error>>()
+                },
+            )?
+        };
+
+        layouter.assign_region(
+            || "poseidon table: rows",
+            |region| {
+                let ctx = &mut RegionCtx::new(region, 0);
+                hashed
+                    .iter()
+                    .map(|(inputs, digest)| {
+                        ctx.assign_fixed(|| "table: q_enable", self.table.q_enable, F::ONE)?;
+                        for (col, input) in self.table.inputs.iter().zip(inputs) {
+                            let cell =
+                                ctx.assign_advice(|| "table: input", *col, input.value().copied())?;
+                            ctx.constrain_equal(cell.cell(), input.cell())?;
+                        }
+                        let out = ctx.assign_advice(
+                            || "table: digest",
+                            self.table.digest,
+                            digest.value().copied(),
+                        )?;
+                        ctx.constrain_equal(out.cell(), digest.cell())?;
+                        ctx.next();
+                        Ok(out)
+                    })
+                    .collect()
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{SimpleFloorPlanner, Value},
+        dev::MockProver,
+        plonk::Circuit,
+    };
+    use halo2curves::pasta::Fp;
+
+    use super::*;
+    use crate::main_gate::MainGate;
+
+    const T: usize = 3;
+    const RATE: usize = 2;
+    const R_F: usize = 4;
+    const R_P: usize = 3;
+    const WIDTH: usize = 2;
+
+    #[derive(Clone, Debug)]
+    struct LookupCircuitConfig {
+        main_gate: MainGateConfig<T>,
+        table: PoseidonTableConfig<WIDTH>,
+        q_user: Column<Fixed>,
+        user_inputs: [Column<Advice>; WIDTH],
+        user_digest: Column<Advice>,
+    }
+
+    /// Publishes `messages` in the table and claims `claim` by lookup from another column set.
+    struct LookupCircuit {
+        messages: Vec<[Fp; WIDTH]>,
+        claim: ([Fp; WIDTH], Fp),
+    }
+
+    impl Circuit<Fp> for LookupCircuit {
+        type Config = LookupCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                messages: vec![[Fp::ZERO; WIDTH]; self.messages.len()],
+                claim: ([Fp::ZERO; WIDTH], Fp::ZERO),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let main_gate = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            let table = PoseidonTableConfig::configure(meta);
+            let q_user = meta.fixed_column();
+            let user_inputs = [(); WIDTH].map(|_| meta.advice_column());
+            let user_digest = meta.advice_column();
+            table.lookup(meta, "user hash in poseidon table", |meta| {
+                (
+                    meta.query_fixed(q_user, Rotation::cur()),
+                    user_inputs.map(|col| meta.query_advice(col, Rotation::cur())),
+                    meta.query_advice(user_digest, Rotation::cur()),
+                )
+            });
+            LookupCircuitConfig {
+                main_gate,
+                table,
+                q_user,
+                user_inputs,
+                user_digest,
+            }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = Spec::<Fp, T, RATE>::new(R_F, R_P);
+            let table = PoseidonTable::new(config.main_gate, config.table, spec);
+            table.load(&mut layouter, &self.messages)?;
+            layouter.assign_region(
+                || "user",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    ctx.assign_fixed(|| "q_user", config.q_user, Fp::ONE)?;
+                    for (col, val) in config.user_inputs.iter().zip(self.claim.0) {
+                        ctx.assign_advice(|| "user input", *col, Value::known(val))?;
+                    }
+                    ctx.assign_advice(
+                        || "user digest",
+                        config.user_digest,
+                        Value::known(self.claim.1),
+                    )?;
+                    Ok(())
+                },
+            )
+        }
+    }
+
+    fn native_hash(msg: &[Fp]) -> Fp {
+        let mut hasher = poseidon::Poseidon::<Fp, T, RATE>::new(R_F, R_P);
+        hasher.update(msg);
+        hasher.squeeze()
+    }
+
+    #[test]
+    fn test_lookup_hash() {
+        const K: u32 = 10;
+        let messages = vec![
+            [Fp::from(1), Fp::from(2)],
+            [Fp::from(3), Fp::from(4)],
+            [Fp::ZERO, Fp::ZERO],
+        ];
+        let msg = messages[1];
+
+        let circuit = LookupCircuit {
+            messages: messages.clone(),
+            claim: (msg, native_hash(&msg)),
+        };
+        let prover = MockProver::run(K, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // a correct hash of a message that is not in the table
+        let other = [Fp::from(5), Fp::from(6)];
+        let circuit = LookupCircuit {
+            messages: messages.clone(),
+            claim: (other, native_hash(&other)),
+        };
+        let prover = MockProver::run(K, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err());
+
+        let circuit = LookupCircuit {
+            messages,
+            claim: (msg, native_hash(&msg) + Fp::ONE),
+        };
+        let prover = MockProver::run(K, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -0,0 +1,96 @@
+//! Hashing byte strings to bn256 G1 points from Poseidon output.
+//!
+//! [`hash_to_curve`] follows the `hash_to_curve` of RFC 9380 with
+//! [`crate::hash_to_field::hash_to_field`] as its `hash_to_field`: two elements are mapped to
+//! the curve with [`map_to_curve`] and added; G1 has a cofactor of one, so the sum needs no
+//! clearing. G1 is `y^2 = x^3 + 3`, and the simplified SWU map needs both coefficients to be
+//! non-zero, so the map is the Shallue-van de Woestijne one of section 6.6.1 with `Z = 1`.
+//!
+//! The elements are of the scalar field, below the base field modulus, so the points are not
+//! uniform, and the map is not constant-time; neither matters for hashing public messages,
+//! such as commitments to be opened in a circuit. Points are computed natively only:
+//! constraining the map needs arithmetic over the base field.
+use ff::{Field, PrimeField};
+use halo2curves::{
+    bn256::{Fq, Fr, G1Affine, G1},
+    group::Curve,
+    CurveAffine,
+};
+use poseidon::Spec;
+
+use crate::hash_to_field::hash_to_field;
+
+/// `g(x) = x^3 + 3`
+fn curve(x: Fq) -> Fq {
+    x.square() * x + Fq::from(3)
+}
+
+fn sgn0(x: Fq) -> bool {
+    x.is_odd().into()
+}
+
+/// Maps `u` to a point of G1 with the Shallue-van de Woestijne map of RFC 9380, section
+/// 6.6.1, for `Z = 1`.
+pub fn map_to_curve(u: Fq) -> G1Affine {
+    // c1 = g(Z), c2 = -Z / 2, c3 = sqrt(-g(Z) * 3 * Z^2) of even sign, c4 = -4 * g(Z) / 3 * Z^2
+    let c1 = Fq::from(4);
+    let c2 = -Fq::from(2).invert().unwrap();
+    let c3 = (-Fq::from(12)).sqrt().unwrap();
+    let c3 = if sgn0(c3) { -c3 } else { c3 };
+    let c4 = -Fq::from(16) * Fq::from(3).invert().unwrap();
+
+    let tv1 = u.square() * c1;
+    let tv2 = Fq::ONE + tv1;
+    let tv1 = Fq::ONE - tv1;
+    let tv3 = Option::from((tv1 * tv2).invert()).unwrap_or(Fq::ZERO);
+    let tv4 = u * tv1 * tv3 * c3;
+    let x1 = c2 - tv4;
+    let x2 = c2 + tv4;
+    let x3 = (tv2.square() * tv3).square() * c4 + Fq::ONE;
+    let x = [x1, x2]
+        .into_iter()
+        .find(|x| bool::from(curve(*x).sqrt().is_some()))
+        .unwrap_or(x3);
+    let y = curve(x).sqrt().unwrap();
+    let y = if sgn0(y) == sgn0(u) { y } else { -y };
+    G1Affine::from_xy(x, y).expect("the map lands on the curve")
+}
+
+/// Hashes `msg` under the tag `dst` to a point of G1.
+pub fn hash_to_curve<const T: usize, const RATE: usize>(
+    spec: &Spec<Fr, T, RATE>,
+    dst: &[u8],
+    msg: &[u8],
+) -> G1Affine {
+    let [u0, u1] = hash_to_field(spec, dst, msg, 2)
+        .try_into()
+        .map(|us: [Fr; 2]| us.map(|u| Fq::from_repr(u.to_repr()).unwrap()))
+        .expect("two elements");
+    (G1::from(map_to_curve(u0)) + map_to_curve(u1)).to_affine()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_to_curve() {
+        let half = Fq::from(2).invert().unwrap();
+        for u in [Fq::ZERO, Fq::ONE, -Fq::ONE, half, Fq::from(12345)] {
+            let point = map_to_curve(u);
+            assert!(bool::from(point.is_on_curve()));
+            assert_eq!(sgn0(*point.coordinates().unwrap().y()), sgn0(u));
+        }
+        assert_ne!(map_to_curve(Fq::ONE), map_to_curve(-Fq::ONE));
+    }
+
+    #[test]
+    fn test_hash_to_curve() {
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        let point = hash_to_curve(&spec, b"dst", b"message");
+        assert!(bool::from(point.is_on_curve()));
+        assert_eq!(point, hash_to_curve(&spec, b"dst", b"message"));
+        assert_ne!(point, hash_to_curve(&spec, b"dst", b"other message"));
+        assert_ne!(point, hash_to_curve(&spec, b"other dst", b"message"));
+    }
+}
@@ -0,0 +1,203 @@
+//! Hashing byte strings to field elements, natively and in-circuit.
+//!
+//! [`hash_to_field`] packs a domain separation tag and the message with
+//! [`crate::packing::pack_bytes`], the tag first, and squeezes `count` elements from a sponge
+//! of the [`Domain::ConstantLength`] of the packed message and `count` outputs, so asking for
+//! more elements does not extend the answer to fewer. [`HashToFieldChip`] does the same in a
+//! circuit from range checked byte cells, see [`PackingChip`], and `hash_to_curve` maps the
+//! elements to bn256 G1.
+use std::sync::Arc;
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::plonk::Error;
+use poseidon::Spec;
+
+use crate::{
+    main_gate::{AssignedValue, MainGateConfig, RegionCtx},
+    optimized_constants::OptimizedConstants,
+    packing::{pack_bytes, CHUNK_LEN},
+    packing_chip::PackingChip,
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::Sponge,
+    specs::Domain,
+};
+
+/// Hashes `msg` under the tag `dst` to `count` field elements.
+pub fn hash_to_field<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    dst: &[u8],
+    msg: &[u8],
+    count: usize,
+) -> Vec<F> {
+    let message = message(dst, msg);
+    let mut sponge = Sponge::new(spec.clone(), domain(message.len(), count));
+    sponge.absorb(&message);
+    (0..count).map(|_| sponge.squeeze()).collect()
+}
+
+fn message<F: PrimeField>(dst: &[u8], msg: &[u8]) -> Vec<F> {
+    pack_bytes(dst).into_iter().chain(pack_bytes(msg)).collect()
+}
+
+fn domain(len: usize, count: usize) -> Domain {
+    assert!(count > 0, "hashing to no element");
+    Domain::ConstantLength {
+        len,
+        outputs: count,
+    }
+}
+
+/// [`hash_to_field`] in a circuit, on the main gate.
+///
+/// The message is packed from range checked bytes by a [`PackingChip`]; the tag is a
+/// constant of the circuit.
+pub struct HashToFieldChip<F: PrimeField, const T: usize, const RATE: usize> {
+    config: MainGateConfig<T>,
+    constants: Arc<OptimizedConstants<F, T, RATE>>,
+    packing: PackingChip<F, T>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    HashToFieldChip<F, T, RATE>
+{
+    pub fn new(config: MainGateConfig<T>, spec: &Spec<F, T, RATE>) -> Self {
+        Self {
+            packing: PackingChip::new(config.clone()),
+            constants: Arc::new(OptimizedConstants::from_spec(spec)),
+            config,
+        }
+    }
+
+    /// Rows [`HashToFieldChip::hash_to_field`] uses for a tag of `dst_len` bytes and a
+    /// message of `msg_len` bytes hashed to `count` elements.
+    pub fn num_rows(
+        spec: &Spec<F, T, RATE>,
+        dst_len: usize,
+        msg_len: usize,
+        count: usize,
+    ) -> usize {
+        let dst = 1 + dst_len.div_ceil(CHUNK_LEN);
+        let len = dst + 1 + msg_len.div_ceil(CHUNK_LEN);
+        // a constant row per element of the tag
+        dst + PackingChip::<F, T>::num_rows(msg_len)
+            + PoseidonChip::num_rows_squeezing(spec, len, count)
+    }
+
+    /// Assigns the bytes of `msg` and hashes them under `dst` to `count` elements, as
+    /// [`hash_to_field`] does; returns the byte cells, for the caller to copy-constrain, and
+    /// the elements.
+    #[allow(clippy::type_complexity)]
+    pub fn hash_to_field(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        dst: &[u8],
+        msg: &[u8],
+        count: usize,
+    ) -> Result<(Vec<AssignedValue<F>>, Vec<AssignedValue<F>>), Error> {
+        let tag = pack_bytes::<F>(dst)
+            .into_iter()
+            .map(|element| self.packing.assign_constant(ctx, element))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (bytes, packed) = self.packing.pack_bytes(ctx, msg)?;
+
+        let message = message(dst, msg);
+        let mut chip = PoseidonChip::from_constants(self.config.clone(), self.constants.clone())
+            .with_domain(domain(message.len(), count));
+        chip.update(message);
+        let (inputs, outputs) = chip.squeeze_n_with_inputs(ctx, count)?;
+        for (input, cell) in inputs.iter().zip(tag.iter().chain(&packed)) {
+            ctx.constrain_equal(input.cell(), cell.cell())?;
+        }
+        Ok((bytes, outputs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+    };
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::main_gate::MainGate;
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+    const DST: &[u8] = b"poseidon-circuit/test";
+
+    struct HashToFieldCircuit {
+        msg: Vec<u8>,
+        count: usize,
+    }
+
+    impl Circuit<Fr> for HashToFieldCircuit {
+        type Config = (MainGateConfig<T>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                msg: vec![0; self.msg.len()],
+                count: self.count,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            (
+                MainGate::configure(meta, &mut adv_cols, &mut fix_cols),
+                instance,
+            )
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = HashToFieldChip::new(config, &Spec::<Fr, T, RATE>::new(8, 56));
+            let (_, outputs) = layouter.assign_region(
+                || "hash to field",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    chip.hash_to_field(ctx, DST, &self.msg, self.count)
+                },
+            )?;
+            for (i, output) in outputs.iter().enumerate() {
+                layouter.constrain_instance(output.cell(), instance, i)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_hash_to_field() {
+        let spec = Spec::<Fr, T, RATE>::new(8, 56);
+        let msg = b"a message of more than thirty-one bytes".to_vec();
+        let elements = hash_to_field(&spec, DST, &msg, 4);
+        assert_eq!(elements.len(), 4);
+        // more elements are another hash, and so are other tags and messages
+        assert_ne!(hash_to_field(&spec, DST, &msg, 1)[0], elements[0]);
+        assert_ne!(hash_to_field(&spec, b"other", &msg, 4), elements);
+        assert_ne!(hash_to_field(&spec, DST, &msg[1..], 4), elements);
+
+        let rows = HashToFieldChip::num_rows(&spec, DST.len(), msg.len(), 4);
+        assert!(rows + 6 <= 1 << 12);
+        let circuit = HashToFieldCircuit {
+            msg: msg.clone(),
+            count: 4,
+        };
+        let prover = MockProver::run(12, &circuit, vec![elements.clone()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let mut wrong = elements;
+        wrong[3] += Fr::from(1);
+        let prover = MockProver::run(12, &circuit, vec![wrong]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
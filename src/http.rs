@@ -0,0 +1,321 @@
+//! A minimal blocking HTTP/1.1 client, enough to POST JSON to callbacks and provers and to
+//! read and write objects of an S3-compatible store, and [`serve`], enough to answer the
+//! status endpoints of `snarkify`.
+//!
+//! Plain `http://` only, one connection per request (`Connection: close`); TLS is left to a
+//! sidecar or a proxy in front of the receiver.
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::limits::MAX_RESPONSE_BYTES;
+
+/// Longest line of a request head [`serve`] reads, in bytes, line break included.
+pub const MAX_HEAD_LINE: usize = 8 * 1024;
+
+/// Most lines of a request head [`serve`] reads, the request line and the blank one included.
+pub const MAX_HEAD_LINES: usize = 64;
+
+/// How long a connection of [`serve`] has to send its whole request head, and to take each
+/// write of the response.
+pub const SERVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections [`serve`] answers at once; the ones past it get 503 straight away.
+pub const MAX_CONNECTIONS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub host: String,
+    pub port: u16,
+    /// Path and query, starting with `/`
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+impl HttpUrl {
+    /// Parses `http://host[:port][/path]`, refusing credentials, IPv6 literals and
+    /// characters that would break the request line.
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.contains(['@', '[']) || path.chars().any(|c| c.is_control() || c == ' ') {
+            return None;
+        }
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (authority, 80),
+        };
+        if host.is_empty() || host.chars().any(|c| c.is_control() || c == ' ') {
+            return None;
+        }
+        Some(Self {
+            host: host.to_ascii_lowercase(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// POSTs a JSON `body` with extra `headers` and reads the whole response, of at most
+    /// [`MAX_RESPONSE_BYTES`].
+    pub fn post_json(
+        &self,
+        body: &[u8],
+        headers: &[(&str, &str)],
+        timeout: Duration,
+    ) -> io::Result<HttpResponse> {
+        let mut all = vec![("Content-Type", "application/json")];
+        all.extend_from_slice(headers);
+        self.request("POST", body, &all, timeout, MAX_RESPONSE_BYTES)
+    }
+
+    /// The value of the `Host` header sent by [`HttpUrl::request`].
+    pub fn host_header(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// Sends a `method` request with `body` and extra `headers` and reads the whole response;
+    /// one of more than `max_response` bytes, head included, is an error.
+    pub fn request(
+        &self,
+        method: &str,
+        body: &[u8],
+        headers: &[(&str, &str)],
+        timeout: Duration,
+        max_response: u64,
+    ) -> io::Result<HttpResponse> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other(format!("{} does not resolve", self.host)))?;
+        let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let mut head = format!(
+            "{method} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.path,
+            self.host_header(),
+            body.len()
+        );
+        for (name, value) in headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+
+        let mut response = Vec::new();
+        stream.take(max_response + 1).read_to_end(&mut response)?;
+        if response.len() as u64 > max_response {
+            return Err(io::Error::other(format!(
+                "response of more than {max_response} bytes"
+            )));
+        }
+        parse_response(&response)
+    }
+}
+
+/// Answers the connections of `listener`, each on a thread of its own, with the status (such
+/// as `"200 OK"`) and the `content_type` body that `handle` returns for the request line.
+/// Runs until accepting fails.
+///
+/// The body of the request is not read, so only `GET`-like requests make sense. A head with
+/// a line longer than [`MAX_HEAD_LINE`] or more than [`MAX_HEAD_LINES`] lines, or one not
+/// sent within [`SERVE_TIMEOUT`], gets 400 without reaching `handle`.
+pub fn serve<H>(listener: TcpListener, content_type: &'static str, handle: H)
+where
+    H: Fn(&str) -> (&'static str, String) + Send + Sync + 'static,
+{
+    let handle = Arc::new(handle);
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming().flatten() {
+        let _ = stream.set_write_timeout(Some(SERVE_TIMEOUT));
+        if open.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            open.fetch_sub(1, Ordering::SeqCst);
+            respond(&stream, "503 Service Unavailable", content_type, "");
+            continue;
+        }
+        let (handle, open) = (handle.clone(), open.clone());
+        std::thread::spawn(move || {
+            match read_request_line(&stream) {
+                Ok(request) => {
+                    let (code, body) = handle(&request);
+                    respond(&stream, code, content_type, &body);
+                }
+                Err(_) => {
+                    respond(&stream, "400 Bad Request", content_type, "");
+                    // read on what the client sent, so that closing does not reset the
+                    // connection before it reads the response
+                    let _ = stream.shutdown(Shutdown::Write);
+                    let limit = (MAX_HEAD_LINE * MAX_HEAD_LINES) as u64;
+                    let _ = io::copy(&mut (&stream).take(limit), &mut io::sink());
+                }
+            }
+            open.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Reads the request head from `stream` within [`SERVE_TIMEOUT`] and returns its first line.
+fn read_request_line(stream: &TcpStream) -> io::Result<String> {
+    let malformed = || io::Error::other("malformed HTTP request head");
+    let deadline = Instant::now() + SERVE_TIMEOUT;
+    let mut reader = BufReader::new(stream);
+    let mut request = None;
+    for _ in 0..MAX_HEAD_LINES {
+        let left = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|left| !left.is_zero())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut))?;
+        stream.set_read_timeout(Some(left))?;
+        let mut line = Vec::new();
+        (&mut reader)
+            .take(MAX_HEAD_LINE as u64)
+            .read_until(b'\n', &mut line)?;
+        // a line cut short by the end of the stream or by the length cap
+        if !line.ends_with(b"\n") {
+            return Err(malformed());
+        }
+        if line == b"\r\n" || line == b"\n" {
+            return request.ok_or_else(malformed);
+        }
+        if request.is_none() {
+            request = Some(String::from_utf8(line).map_err(|_| malformed())?);
+        }
+    }
+    Err(malformed())
+}
+
+fn respond(mut stream: &TcpStream, code: &str, content_type: &str, body: &str) {
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {code}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+}
+
+fn parse_response(response: &[u8]) -> io::Result<HttpResponse> {
+    let malformed = || io::Error::other("malformed HTTP response");
+    let head_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(malformed)?;
+    let head = std::str::from_utf8(&response[..head_end]).map_err(|_| malformed())?;
+    let status = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(malformed)?;
+    Ok(HttpResponse {
+        status,
+        body: response[head_end + 4..].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let url = HttpUrl::parse("http://Prover.local:8080/tasks?x=1").unwrap();
+        assert_eq!(url.host, "prover.local");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/tasks?x=1");
+        assert_eq!(HttpUrl::parse("http://prover.local").unwrap().path, "/");
+        for url in [
+            "https://prover.local/",
+            "http://user@prover.local/",
+            "http://prover.local:port/",
+            "http://prover.local/a b",
+            "http://prover.local/\r\nX-Injected: 1",
+            "http:///path",
+        ] {
+            assert!(HttpUrl::parse(url).is_none(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response =
+            parse_response(b"HTTP/1.1 202 Accepted\r\nContent-Length: 2\r\n\r\n{}").unwrap();
+        assert_eq!(response.status, 202);
+        assert_eq!(response.body, b"{}");
+        assert!(response.is_success());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            serve(listener, "text/plain", |request| {
+                match request.strip_prefix("GET ") {
+                    Some(rest) => (
+                        "200 OK",
+                        rest.split_whitespace().next().unwrap().to_string(),
+                    ),
+                    None => ("404 Not Found", String::new()),
+                }
+            })
+        });
+        let url = |path: &str| HttpUrl::parse(&format!("http://127.0.0.1:{port}{path}")).unwrap();
+        let timeout = Duration::from_secs(5);
+
+        // a connection that never sends its head holds up no other one
+        let _idle = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let response = url("/jobs/1")
+            .request("GET", &[], &[], timeout, MAX_RESPONSE_BYTES)
+            .unwrap();
+        assert_eq!((response.status, response.body), (200, b"/jobs/1".to_vec()));
+        let response = url("/")
+            .request("POST", &[], &[], timeout, MAX_RESPONSE_BYTES)
+            .unwrap();
+        assert_eq!(response.status, 404);
+
+        let long = format!("/{}", "a".repeat(MAX_HEAD_LINE));
+        let response = url(&long)
+            .request("GET", &[], &[], timeout, MAX_RESPONSE_BYTES)
+            .unwrap();
+        assert_eq!(response.status, 400);
+        let headers = vec![("X-Filler", "1"); MAX_HEAD_LINES];
+        let response = url("/")
+            .request("GET", &[], &headers, timeout, MAX_RESPONSE_BYTES)
+            .unwrap();
+        assert_eq!(response.status, 400);
+    }
+
+    #[test]
+    fn test_response_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || serve(listener, "text/plain", |_| ("200 OK", "a".repeat(4096))));
+        let url = HttpUrl::parse(&format!("http://127.0.0.1:{port}/")).unwrap();
+        let timeout = Duration::from_secs(5);
+        let response = url.request("GET", &[], &[], timeout, 8192).unwrap();
+        assert_eq!(response.body.len(), 4096);
+        assert!(url.request("GET", &[], &[], timeout, 4096).is_err());
+    }
+}
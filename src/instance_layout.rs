@@ -0,0 +1,108 @@
+//! Placement of public values in instance columns.
+//!
+//! Some verifier integrations expect all public values in one instance column, one per row
+//! ([`InstanceLayout::RowMajor`]), others one column per value with a single row
+//! ([`InstanceLayout::ColumnMajor`]). The number of instance columns is fixed when a circuit
+//! is configured, so circuits take the layout as a [`Layout`] type parameter; the runtime
+//! [`InstanceLayout`] is what tasks request and proof bundles record.
+use ff::Field;
+use halo2_proofs::{
+    circuit::{Cell, Layouter},
+    plonk::{Column, ConstraintSystem, Error, Instance},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum InstanceLayout {
+    /// One column, value `i` in row `i`
+    #[default]
+    RowMajor,
+    /// Value `i` in row 0 of column `i`
+    ColumnMajor,
+}
+
+impl InstanceLayout {
+    /// The instance columns holding `values`, as passed to the prover and verifier.
+    pub fn columns<F: Clone>(&self, values: &[F]) -> Vec<Vec<F>> {
+        match self {
+            Self::RowMajor => vec![values.to_vec()],
+            Self::ColumnMajor => values.iter().map(|v| vec![v.clone()]).collect(),
+        }
+    }
+
+    /// `(column, row)` of value `index`.
+    pub fn position(&self, index: usize) -> (usize, usize) {
+        match self {
+            Self::RowMajor => (0, index),
+            Self::ColumnMajor => (index, 0),
+        }
+    }
+
+    /// Allocates the instance columns for `count` public values, with equality enabled.
+    pub fn configure<F: Field>(
+        &self,
+        meta: &mut ConstraintSystem<F>,
+        count: usize,
+    ) -> Vec<Column<Instance>> {
+        let columns = match self {
+            Self::RowMajor => 1,
+            Self::ColumnMajor => count,
+        };
+        (0..columns)
+            .map(|_| {
+                let column = meta.instance_column();
+                meta.enable_equality(column);
+                column
+            })
+            .collect()
+    }
+
+    /// Constrains `cell` to public value `index` in `columns`.
+    pub fn constrain<F: Field>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        columns: &[Column<Instance>],
+        index: usize,
+        cell: Cell,
+    ) -> Result<(), Error> {
+        let (column, row) = self.position(index);
+        layouter.constrain_instance(cell, columns[column], row)
+    }
+}
+
+/// Type-level choice of an [`InstanceLayout`], for circuits.
+pub trait Layout {
+    const LAYOUT: InstanceLayout;
+}
+
+pub struct RowMajor;
+
+pub struct ColumnMajor;
+
+impl Layout for RowMajor {
+    const LAYOUT: InstanceLayout = InstanceLayout::RowMajor;
+}
+
+impl Layout for ColumnMajor {
+    const LAYOUT: InstanceLayout = InstanceLayout::ColumnMajor;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns() {
+        assert_eq!(InstanceLayout::RowMajor.columns(&[1, 2]), vec![vec![1, 2]]);
+        assert_eq!(
+            InstanceLayout::ColumnMajor.columns(&[1, 2]),
+            vec![vec![1], vec![2]]
+        );
+        assert_eq!(InstanceLayout::ColumnMajor.position(1), (1, 0));
+        assert_eq!(
+            serde_json::to_string(&InstanceLayout::ColumnMajor).unwrap(),
+            r#""column_major""#
+        );
+    }
+}
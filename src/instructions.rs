@@ -0,0 +1,280 @@
+//! The hash chip behind a trait, for circuits that hash cells they assigned themselves.
+//!
+//! [`crate::sub_circuit::synthesize_trace`] hashes messages the embedding circuit knows as
+//! values. A circuit that computed the message in its own cells hashes them through
+//! [`PoseidonInstructions`] instead, which copies them into the absorb rows, and gets the
+//! digest as a cell to constrain as it likes. Only the main gate configuration is shared,
+//! none of the layout of [`crate::test_circuit::TestCircuit`].
+//!
+//! [`Hash`] has the interface of `halo2_gadgets::poseidon::Hash`, so code written against
+//! the gadget ports by swapping the import and the chip. It cannot be an implementation of
+//! the traits of `halo2_gadgets` itself: they are over the `halo2_proofs` of zcash rather
+//! than the fork this crate builds on. The digests are those of this crate, see
+//! [`crate::poseidon_hash::hash_with_domain`], not those of the gadget's specs.
+use std::marker::PhantomData;
+
+use ff::PrimeField;
+use halo2_proofs::{circuit::Layouter, plonk::Error};
+
+use crate::{
+    main_gate::{AssignedValue, RegionCtx},
+    specs::Domain,
+};
+
+/// A chip hashing assigned cells.
+pub trait PoseidonInstructions<F: PrimeField> {
+    /// Hashes under `domain` rather than the one the chip was built with.
+    fn with_domain(self, domain: Domain) -> Self
+    where
+        Self: Sized;
+
+    /// Hashes the values of `message`, constraining the cells it absorbs them from to equal
+    /// the cells of `message`; returns the digest cell.
+    fn hash(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        message: &[AssignedValue<F>],
+    ) -> Result<AssignedValue<F>, Error>;
+
+    /// Rows [`PoseidonInstructions::hash`] takes for a message of `len` elements.
+    fn num_rows(&self, len: usize) -> usize;
+
+    /// Constrains `digest` to be the hash of `message`.
+    fn constrain_digest(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        message: &[AssignedValue<F>],
+        digest: &AssignedValue<F>,
+    ) -> Result<(), Error> {
+        let hashed = self.hash(ctx, message)?;
+        ctx.constrain_equal(hashed.cell(), digest.cell())
+    }
+
+    /// [`PoseidonInstructions::hash`] in a region of its own.
+    fn hash_in_region(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        message: &[AssignedValue<F>],
+    ) -> Result<AssignedValue<F>, Error> {
+        layouter.assign_region(
+            || "poseidon hash",
+            |region| {
+                let ctx = &mut RegionCtx::new(region, 0);
+                self.hash(ctx, message)
+            },
+        )
+    }
+}
+
+/// The domain a [`Hash`] hashes under, the counterpart of `halo2_gadgets::poseidon::Domain`.
+pub trait HashDomain {
+    fn domain() -> Domain;
+}
+
+/// Messages of `L` elements, under [`Domain::ConstantLength`].
+#[derive(Clone, Copy, Debug)]
+pub struct ConstantLength<const L: usize>;
+
+impl<const L: usize> HashDomain for ConstantLength<L> {
+    fn domain() -> Domain {
+        Domain::ConstantLength { len: L, outputs: 1 }
+    }
+}
+
+/// Messages of any length, under [`Domain::Pse`].
+#[derive(Clone, Copy, Debug)]
+pub struct Pse;
+
+impl HashDomain for Pse {
+    fn domain() -> Domain {
+        Domain::Pse
+    }
+}
+
+/// A hash of cells under `D`, with the interface of `halo2_gadgets::poseidon::Hash`.
+#[derive(Debug)]
+pub struct Hash<F: PrimeField, C: PoseidonInstructions<F>, D: HashDomain> {
+    chip: C,
+    _marker: PhantomData<(F, D)>,
+}
+
+impl<F: PrimeField, C: PoseidonInstructions<F>, D: HashDomain> Hash<F, C, D> {
+    /// Nothing is assigned: the initial state is folded into the round constants.
+    pub fn init(chip: C, _layouter: impl Layouter<F>) -> Result<Self, Error> {
+        Ok(Self {
+            chip: chip.with_domain(D::domain()),
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<F: PrimeField, C: PoseidonInstructions<F>, const L: usize> Hash<F, C, ConstantLength<L>> {
+    pub fn hash(
+        self,
+        mut layouter: impl Layouter<F>,
+        message: [AssignedValue<F>; L],
+    ) -> Result<AssignedValue<F>, Error> {
+        self.chip.hash_in_region(&mut layouter, &message)
+    }
+}
+
+impl<F: PrimeField, C: PoseidonInstructions<F>> Hash<F, C, Pse> {
+    pub fn hash(
+        self,
+        mut layouter: impl Layouter<F>,
+        message: &[AssignedValue<F>],
+    ) -> Result<AssignedValue<F>, Error> {
+        self.chip.hash_in_region(&mut layouter, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{SimpleFloorPlanner, Value},
+        dev::MockProver,
+        plonk::{Advice, Circuit, Column, ConstraintSystem, Instance},
+    };
+    use halo2curves::bn256::Fr;
+    use poseidon::Spec;
+
+    use super::*;
+    use crate::{
+        main_gate::{MainGate, MainGateConfig},
+        poseidon_circuit::PoseidonChip,
+        poseidon_hash::hash_with_domain,
+    };
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+    const L: usize = 5;
+    const K: u32 = 10;
+
+    fn spec() -> Spec<Fr, T, RATE> {
+        Spec::new(8, 56)
+    }
+
+    #[derive(Clone, Debug)]
+    struct ForeignConfig {
+        pconfig: MainGateConfig<T>,
+        /// A column of the embedding circuit, holding the message
+        message: Column<Advice>,
+        instance: Column<Instance>,
+    }
+
+    /// Assigns the message in a region of its own and exposes its digest through [`Hash`],
+    /// and through [`PoseidonInstructions::constrain_digest`] if `claimed` is set.
+    struct ForeignCircuit {
+        message: [Fr; L],
+        claimed: Option<Fr>,
+    }
+
+    impl Circuit<Fr> for ForeignCircuit {
+        type Config = ForeignConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                message: [Fr::ZERO; L],
+                claimed: self.claimed.map(|_| Fr::ZERO),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let message = meta.advice_column();
+            meta.enable_equality(message);
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            ForeignConfig {
+                pconfig,
+                message,
+                instance,
+            }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let cells = layouter.assign_region(
+                || "message",
+                |mut region| {
+                    let cells = self.message.iter().enumerate().map(|(row, value)| {
+                        region.assign_advice(
+                            || "message",
+                            config.message,
+                            row,
+                            || Value::known(*value),
+                        )
+                    });
+                    cells.collect::<Result<Vec<_>, _>>()
+                },
+            )?;
+            let message: [AssignedValue<Fr>; L] = cells.try_into().unwrap();
+
+            let chip = PoseidonChip::new(config.pconfig.clone(), spec());
+            let digest =
+                Hash::<_, _, ConstantLength<L>>::init(chip, layouter.namespace(|| "init"))?
+                    .hash(layouter.namespace(|| "hash"), message.clone())?;
+            layouter.constrain_instance(digest.cell(), config.instance, 0)?;
+
+            if let Some(claimed) = self.claimed {
+                let chip = PoseidonChip::new(config.pconfig.clone(), spec());
+                layouter.assign_region(
+                    || "claimed digest",
+                    |region| {
+                        let ctx = &mut RegionCtx::new(region, 0);
+                        let claimed = ctx.assign_advice(
+                            || "claimed",
+                            config.pconfig.out,
+                            Value::known(claimed),
+                        )?;
+                        ctx.next();
+                        chip.constrain_digest(ctx, &message, &claimed)?;
+                        assert_eq!(ctx.offset(), 1 + chip.num_rows(L));
+                        Ok(())
+                    },
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_foreign_circuit() {
+        let message = [1, 2, 3, 4, 5].map(Fr::from);
+        let domain = ConstantLength::<L>::domain();
+        let digest = hash_with_domain(&spec(), domain, &message);
+        let circuit = ForeignCircuit {
+            message,
+            claimed: None,
+        };
+        let prover = MockProver::run(K, &circuit, vec![vec![digest]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        // the digest of the PSE domain is another one
+        let pse = hash_with_domain(&spec(), Pse::domain(), &message);
+        let prover = MockProver::run(K, &circuit, vec![vec![pse]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_constrain_digest() {
+        let message = [1, 2, 3, 4, 5].map(Fr::from);
+        let digest = hash_with_domain(&spec(), Pse::domain(), &message);
+        let domain = ConstantLength::<L>::domain();
+        let expected = hash_with_domain(&spec(), domain, &message);
+        for (claimed, ok) in [(digest, true), (digest + Fr::ONE, false)] {
+            let circuit = ForeignCircuit {
+                message,
+                claimed: Some(claimed),
+            };
+            let prover = MockProver::run(K, &circuit, vec![vec![expected]]).unwrap();
+            assert_eq!(prover.verify().is_ok(), ok);
+        }
+    }
+}
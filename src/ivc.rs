@@ -0,0 +1,823 @@
+//! Incrementally verifiable hash chains `h_{i+1} = Poseidon(h_i, m_i)`, for hash-chain
+//! timestamping and chains of state commitments, with the halo2 loader of snark-verifier.
+//!
+//! Each link is proven by a [`ChainLinkCircuit`], exposing `[h_i, h_{i+1}]`. The
+//! [`IvcCircuit`] of step `i` verifies the proof of link `i` and the [`IvcCircuit`] proof of
+//! step `i - 1` up to their pairings and folds both into one KZG accumulator, as
+//! [`crate::aggregation`] folds chunk proofs. It exposes the accumulator limbs, followed by
+//! [`IVC_PUBLIC_INPUTS`]: the digest of its own verifying key, `h_0`, `h_{i+1}` and `i`. A
+//! chain of any length thus ends in one proof of constant size, valid when the proof verifies,
+//! the digest is that of the verifier's key and the accumulator passes the pairing check;
+//! [`ChainProver::verify`] does all three.
+//!
+//! A circuit cannot hold its own verifying key as constants, so the proof of the previous
+//! step is verified against preprocessed commitments loaded as witnesses and hashed into the
+//! key digest, which every step must repeat. The first step has no previous proof: it
+//! verifies a dummy one, discards its accumulator for the default `[g1, g0]` and checks
+//! `h_0` against the first link instead. This is the recursion of snark-verifier's examples.
+//!
+//! Links hash under [`CHAIN_DOMAIN`] with messages of the fixed length of the
+//! [`ChainProver`]. The [`IvcCircuit`] needs params of about `2^22` rows, the link circuit
+//! few; both are proven on the same params, the link circuit on a downsized copy.
+use std::{marker::PhantomData, rc::Rc};
+
+use ff::Field;
+use halo2_proofs::{
+    arithmetic::CurveAffine,
+    circuit::{Layouter, SimpleFloorPlanner, Value},
+    plonk::{keygen_vk, Circuit, Column, ConstraintSystem, Error, Instance},
+    poly::{commitment::Params, kzg::commitment::ParamsKZG},
+    transcript::{TranscriptWrite, TranscriptWriterBuffer},
+};
+use halo2curves::{
+    bn256::{Bn256, Fq, Fr, G1Affine},
+    group::prime::PrimeCurveAffine,
+};
+use poseidon::Spec;
+use rand_core::OsRng;
+use snark_verifier::{
+    loader::{
+        halo2::halo2_wrong_ecc::{
+            integer::rns::Rns,
+            maingate::{
+                AssignedCondition, AssignedValue, MainGate, MainGateConfig, MainGateInstructions,
+                RangeChip, RangeConfig, RangeInstructions, RegionCtx,
+            },
+            EccConfig,
+        },
+        native::NativeLoader,
+        Loader,
+    },
+    pcs::{kzg::KzgAccumulator, AccumulationScheme, AccumulationSchemeProver},
+    system::halo2::{compile, Config},
+    util::{
+        arithmetic::{fe_to_fe, fe_to_limbs},
+        hash::Poseidon,
+    },
+    verifier::{plonk::PlonkProtocol, SnarkVerifier},
+};
+
+use crate::{
+    aggregation::{
+        decide, prove_chunk, verify_chunk, AggregationError, As, BaseFieldEccChip, ChunkSnark,
+        Halo2Loader, PoseidonTranscript, SnarkWitness, SuccinctVerifier, Svk,
+        ACCUMULATOR_INSTANCES, BITS, LIMBS,
+    },
+    main_gate,
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash_with_domain,
+    prover::ProverContext,
+    specs::{Domain, BN256_T4_R3},
+};
+
+/// `2^128 - 5`: the domain of the links of a chain, below that of Schnorr challenges.
+pub const CHAIN_DOMAIN: Domain = Domain::Constant(u128::MAX - 4);
+
+/// Public inputs of an [`IvcCircuit`] after its accumulator: the digest of its verifying
+/// key, the initial state, the state and the index of the step.
+pub const IVC_PUBLIC_INPUTS: usize = 4;
+
+const DIGEST_ROW: usize = ACCUMULATOR_INSTANCES;
+const INITIAL_ROW: usize = ACCUMULATOR_INSTANCES + 1;
+const STATE_ROW: usize = ACCUMULATOR_INSTANCES + 2;
+const ROUND_ROW: usize = ACCUMULATOR_INSTANCES + 3;
+
+const T: usize = BN256_T4_R3.width;
+const RATE: usize = BN256_T4_R3.rate;
+
+fn spec() -> Spec<Fr, T, RATE> {
+    Spec::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p)
+}
+
+/// The state after absorbing `message` into `state`.
+pub fn chain_link(state: Fr, message: &[Fr]) -> Fr {
+    let inputs = [&[state], message].concat();
+    hash_with_domain(&spec(), CHAIN_DOMAIN, &inputs)
+}
+
+#[derive(Debug)]
+pub enum IvcError {
+    /// A message of another length than that of the link circuit
+    MessageLength {
+        expected: usize,
+        len: usize,
+    },
+    /// The public inputs of a chain proof are not those of its states and steps under the
+    /// key of the prover
+    Instances,
+    /// A chain proof does not verify against the key of the IVC circuit
+    InvalidProof(String),
+    Aggregation(AggregationError),
+}
+
+impl std::fmt::Display for IvcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MessageLength { expected, len } => {
+                write!(f, "message of {len} elements, the chain takes {expected}")
+            }
+            Self::Instances => write!(f, "public inputs of another chain or key"),
+            Self::InvalidProof(reason) => write!(f, "chain proof does not verify: {reason}"),
+            Self::Aggregation(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for IvcError {}
+
+impl From<AggregationError> for IvcError {
+    fn from(err: AggregationError) -> Self {
+        Self::Aggregation(err)
+    }
+}
+
+impl From<Error> for IvcError {
+    fn from(err: Error) -> Self {
+        Self::Aggregation(AggregationError::Plonk(err))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChainLinkConfig {
+    main_gate: main_gate::MainGateConfig<T>,
+    instance: Column<Instance>,
+}
+
+/// Proves one link, `[state, chain_link(state, message)]`.
+#[derive(Clone, Debug)]
+pub struct ChainLinkCircuit {
+    state: Fr,
+    message: Vec<Fr>,
+}
+
+impl ChainLinkCircuit {
+    pub fn new(state: Fr, message: Vec<Fr>) -> Self {
+        Self { state, message }
+    }
+
+    /// The public inputs of the link.
+    pub fn instances(&self) -> [Fr; 2] {
+        [self.state, chain_link(self.state, &self.message)]
+    }
+}
+
+impl Circuit<Fr> for ChainLinkCircuit {
+    type Config = ChainLinkConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self::new(Fr::ZERO, vec![Fr::ZERO; self.message.len()])
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let instance = meta.instance_column();
+        meta.enable_equality(instance);
+        let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+        let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+        let main_gate = main_gate::MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+        ChainLinkConfig {
+            main_gate,
+            instance,
+        }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let mut pchip = PoseidonChip::new(config.main_gate, spec()).with_domain(CHAIN_DOMAIN);
+        pchip.update([&[self.state], &self.message[..]].concat());
+        let (inputs, next) = layouter.assign_region(
+            || "chain link",
+            |region| {
+                let ctx = &mut main_gate::RegionCtx::new(region, 0);
+                pchip.squeeze_with_inputs(ctx)
+            },
+        )?;
+        layouter.constrain_instance(inputs[0].cell(), config.instance, 0)?;
+        layouter.constrain_instance(next.cell(), config.instance, 1)?;
+        Ok(())
+    }
+}
+
+/// The Poseidon hash of snark-verifier's transcript, over `inputs`.
+fn poseidon<L: Loader<G1Affine>>(loader: &L, inputs: &[L::LoadedScalar]) -> L::LoadedScalar {
+    let mut hasher = Poseidon::<Fr, L::LoadedScalar, 5, 4>::new(loader, 8, 60);
+    hasher.update(inputs);
+    hasher.squeeze()
+}
+
+/// The digest binding the proofs of the previous step to the verifying key `protocol` was
+/// compiled from: of its preprocessed commitments and its transcript initial state.
+fn key_digest(protocol: &PlonkProtocol<G1Affine>) -> Fr {
+    let mut inputs = Vec::new();
+    for point in &protocol.preprocessed {
+        let coordinates = point.coordinates().expect("preprocessed points are finite");
+        inputs.push(fe_to_fe::<Fq, Fr>(*coordinates.x()));
+        inputs.push(fe_to_fe::<Fq, Fr>(*coordinates.y()));
+    }
+    inputs.extend(protocol.transcript_initial_state);
+    poseidon(&NativeLoader, &inputs)
+}
+
+fn ivc_config() -> Config {
+    Config::kzg()
+        .with_num_instance(vec![ACCUMULATOR_INSTANCES + IVC_PUBLIC_INPUTS])
+        .with_accumulator_indices(Some(
+            (0..ACCUMULATOR_INSTANCES).map(|row| (0, row)).collect(),
+        ))
+}
+
+/// A proof of the shape `protocol` reads, of random points and scalars, standing in for the
+/// proof of a step before the first.
+fn dummy_proof(protocol: &PlonkProtocol<G1Affine>) -> Vec<u8> {
+    let mut transcript = PoseidonTranscript::<NativeLoader, _>::init(Vec::new());
+    let points = protocol.num_witness.iter().sum::<usize>() + protocol.quotient.num_chunk();
+    for _ in 0..points {
+        transcript.write_point(G1Affine::random(OsRng)).unwrap();
+    }
+    for _ in 0..protocol.evaluations.len() {
+        transcript.write_scalar(Fr::random(OsRng)).unwrap();
+    }
+    // at least the openings of GWC, one per rotation; trailing bytes are never read
+    for _ in 0..protocol.queries.len() {
+        transcript.write_point(G1Affine::random(OsRng)).unwrap();
+    }
+    transcript.finalize()
+}
+
+/// The accumulator the first step folds in place of that of a previous step.
+fn default_accumulator(params: &ParamsKZG<Bn256>) -> KzgAccumulator<G1Affine, NativeLoader> {
+    KzgAccumulator::new(params.get_g()[1], params.get_g()[0])
+}
+
+/// The stand-in for the proof before the first step, of the key `protocol` was compiled from.
+fn initial_snark(params: &ParamsKZG<Bn256>, protocol: &PlonkProtocol<G1Affine>) -> ChunkSnark {
+    let KzgAccumulator { lhs, rhs } = default_accumulator(params);
+    let mut instances = [lhs.x, lhs.y, rhs.x, rhs.y]
+        .map(fe_to_limbs::<Fq, Fr, LIMBS, BITS>)
+        .concat();
+    instances.extend([Fr::ZERO; IVC_PUBLIC_INPUTS]);
+    ChunkSnark {
+        instances,
+        proof: dummy_proof(protocol),
+    }
+}
+
+/// The constraint system of `C` with nothing assigned: its verifying key differs from that
+/// of `C` only in the preprocessed commitments.
+struct Shape<C>(PhantomData<C>);
+
+impl<C: Circuit<Fr>> Circuit<Fr> for Shape<C> {
+    type Config = C::Config;
+    type FloorPlanner = C::FloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Shape(PhantomData)
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        C::configure(meta)
+    }
+
+    fn synthesize(&self, _: Self::Config, _: impl Layouter<Fr>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The protocol of the [`IvcCircuit`] before its keys exist, to generate them with.
+fn shape_protocol(params: &ParamsKZG<Bn256>) -> Result<PlonkProtocol<G1Affine>, Error> {
+    let vk = keygen_vk(params, &Shape::<IvcCircuit>(PhantomData))?;
+    let mut protocol = compile(params, &vk, ivc_config());
+    // empty fixed columns commit to the identity, which the circuit cannot load as a point
+    for point in protocol.preprocessed.iter_mut() {
+        if bool::from(point.is_identity()) {
+            *point = G1Affine::generator();
+        }
+    }
+    Ok(protocol)
+}
+
+/// Verifies `snark` up to its pairing in the circuit; returns the cells of its public inputs
+/// and its accumulators. With `digest`, the preprocessed commitments are witnesses whose
+/// [`key_digest`] is constrained to it.
+#[allow(clippy::type_complexity)]
+fn succinct_verify<'a>(
+    svk: &Svk,
+    loader: &Rc<Halo2Loader<'a>>,
+    snark: &SnarkWitness,
+    digest: Option<&AssignedValue<Fr>>,
+) -> Result<
+    (
+        Vec<AssignedValue<Fr>>,
+        Vec<KzgAccumulator<G1Affine, Rc<Halo2Loader<'a>>>>,
+    ),
+    Error,
+> {
+    let protocol = match digest {
+        Some(digest) => {
+            let protocol = snark.protocol.loaded_preprocessed_as_witness(loader);
+            let mut inputs = Vec::new();
+            for point in &protocol.preprocessed {
+                let assigned = point.assigned();
+                for coordinate in [assigned.x(), assigned.y()] {
+                    inputs.push(loader.scalar_from_assigned(coordinate.native().clone()));
+                }
+            }
+            inputs.extend(protocol.transcript_initial_state.clone());
+            let hashed = poseidon(loader, &inputs).into_assigned();
+            loader
+                .ctx_mut()
+                .constrain_equal(hashed.cell(), digest.cell())?;
+            protocol
+        }
+        None => snark.protocol.loaded(loader),
+    };
+    let instances = snark
+        .instances
+        .iter()
+        .map(|column| {
+            column
+                .iter()
+                .map(|value| loader.assign_scalar(*value))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut transcript = PoseidonTranscript::<Rc<Halo2Loader>, _>::new(loader, snark.proof());
+    let proof = SuccinctVerifier::read_proof(svk, &protocol, &instances, &mut transcript)
+        .map_err(|_| Error::Synthesis)?;
+    let accumulators = SuccinctVerifier::verify(svk, &protocol, &instances, &proof)
+        .map_err(|_| Error::Synthesis)?;
+    let instances = instances
+        .into_iter()
+        .flatten()
+        .map(|scalar| scalar.into_assigned())
+        .collect();
+    Ok((instances, accumulators))
+}
+
+/// `when_true` if `condition` holds, else `when_false`.
+fn select_accumulator<'a>(
+    loader: &Rc<Halo2Loader<'a>>,
+    condition: &AssignedCondition<Fr>,
+    when_true: &KzgAccumulator<G1Affine, Rc<Halo2Loader<'a>>>,
+    when_false: &KzgAccumulator<G1Affine, Rc<Halo2Loader<'a>>>,
+) -> Result<KzgAccumulator<G1Affine, Rc<Halo2Loader<'a>>>, Error> {
+    let mut points = Vec::new();
+    for (a, b) in [
+        (&when_true.lhs, &when_false.lhs),
+        (&when_true.rhs, &when_false.rhs),
+    ] {
+        let selected = loader.ecc_chip().select(
+            &mut loader.ctx_mut(),
+            condition,
+            &a.assigned(),
+            &b.assigned(),
+        )?;
+        points.push(loader.ec_point_from_assigned(selected));
+    }
+    let rhs = points.pop().unwrap();
+    let lhs = points.pop().unwrap();
+    Ok(KzgAccumulator::new(lhs, rhs))
+}
+
+#[derive(Clone, Debug)]
+pub struct IvcConfig {
+    main_gate: MainGateConfig,
+    range: RangeConfig,
+}
+
+/// Verifies the proof of a link and that of the previous step, see the
+/// [module documentation](self).
+#[derive(Clone)]
+pub struct IvcCircuit {
+    svk: Svk,
+    default_accumulator: KzgAccumulator<G1Affine, NativeLoader>,
+    link: SnarkWitness,
+    previous: SnarkWitness,
+    instances: Vec<Fr>,
+    as_proof: Value<Vec<u8>>,
+}
+
+impl IvcCircuit {
+    /// Step `round` from `initial`: folds the proof `link`, of the key `link_protocol` was
+    /// compiled from, and the proof `previous` of the step before, of the key `protocol` was
+    /// compiled from, or [`initial_snark`] in the first round.
+    fn new(
+        params: &ParamsKZG<Bn256>,
+        link_protocol: &PlonkProtocol<G1Affine>,
+        link: &ChunkSnark,
+        protocol: &PlonkProtocol<G1Affine>,
+        previous: &ChunkSnark,
+        initial: Fr,
+        round: u64,
+    ) -> Result<Self, IvcError> {
+        let svk: Svk = params.get_g()[0].into();
+        let default_accumulator = default_accumulator(params);
+        let succinct = |protocol: &PlonkProtocol<G1Affine>, snark: &ChunkSnark| {
+            let instances = vec![snark.instances.clone()];
+            let mut transcript = PoseidonTranscript::<NativeLoader, _>::new(snark.proof.as_slice());
+            let proof = SuccinctVerifier::read_proof(&svk, protocol, &instances, &mut transcript)
+                .map_err(|err| IvcError::InvalidProof(format!("{err:?}")))?;
+            SuccinctVerifier::verify(&svk, protocol, &instances, &proof)
+                .map_err(|err| IvcError::InvalidProof(format!("{err:?}")))
+        };
+        let mut accumulators = succinct(link_protocol, link)?;
+        if round == 0 {
+            let n = 1 + protocol.accumulator_indices.len();
+            accumulators.extend(vec![default_accumulator.clone(); n]);
+        } else {
+            accumulators.extend(succinct(protocol, previous)?);
+        }
+        let mut transcript = PoseidonTranscript::<NativeLoader, _>::new(Vec::new());
+        let KzgAccumulator { lhs, rhs } =
+            As::create_proof(&Default::default(), &accumulators, &mut transcript, OsRng)
+                .map_err(|_| AggregationError::Accumulator)?;
+        let mut instances = [lhs.x, lhs.y, rhs.x, rhs.y]
+            .map(fe_to_limbs::<Fq, Fr, LIMBS, BITS>)
+            .concat();
+        instances.extend([
+            key_digest(protocol),
+            initial,
+            link.instances[1],
+            Fr::from(round),
+        ]);
+        Ok(Self {
+            svk,
+            default_accumulator,
+            link: SnarkWitness::new(link_protocol, link),
+            previous: SnarkWitness::new(protocol, previous),
+            instances,
+            as_proof: Value::known(transcript.finalize()),
+        })
+    }
+
+    /// The accumulator limbs, then [`IVC_PUBLIC_INPUTS`].
+    pub fn instances(&self) -> Vec<Fr> {
+        self.instances.clone()
+    }
+
+    fn instance(&self, row: usize) -> Value<Fr> {
+        self.instances
+            .get(row)
+            .map_or(Value::unknown(), |value| Value::known(*value))
+    }
+}
+
+impl Circuit<Fr> for IvcCircuit {
+    type Config = IvcConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            svk: self.svk,
+            default_accumulator: self.default_accumulator.clone(),
+            link: self.link.without_witnesses(),
+            previous: self.previous.without_witnesses(),
+            instances: Vec::new(),
+            as_proof: Value::unknown(),
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let main_gate = MainGate::<Fr>::configure(meta);
+        let overflow_bits = Rns::<Fq, Fr, LIMBS, BITS>::construct().overflow_lengths();
+        let range = RangeChip::<Fr>::configure(meta, &main_gate, vec![BITS / LIMBS], overflow_bits);
+        IvcConfig { main_gate, range }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let main_gate = MainGate::<Fr>::new(config.main_gate.clone());
+        RangeChip::<Fr>::new(config.range.clone()).load_table(&mut layouter)?;
+
+        let public = layouter.assign_region(
+            || "ivc step",
+            |region| {
+                let mut ctx = RegionCtx::new(region, 0);
+                let [digest, initial, state, round] =
+                    [DIGEST_ROW, INITIAL_ROW, STATE_ROW, ROUND_ROW]
+                        .map(|row| main_gate.assign_value(&mut ctx, self.instance(row)));
+                let (digest, initial, state, round) = (digest?, initial?, state?, round?);
+                let first_round = main_gate.is_zero(&mut ctx, &round)?;
+                let not_first_round = main_gate.not(&mut ctx, &first_round)?;
+
+                let ecc_chip = BaseFieldEccChip::new(EccConfig::new(
+                    config.range.clone(),
+                    config.main_gate.clone(),
+                ));
+                let loader = Halo2Loader::new(ecc_chip, ctx);
+                let (link, link_accumulators) =
+                    succinct_verify(&self.svk, &loader, &self.link, None)?;
+                let (previous, previous_accumulators) =
+                    succinct_verify(&self.svk, &loader, &self.previous, Some(&digest))?;
+
+                // the first round folds the default accumulator for those of the dummy proof
+                let default = {
+                    let KzgAccumulator { lhs, rhs } = &self.default_accumulator;
+                    let mut points = Vec::new();
+                    for point in [*lhs, *rhs] {
+                        let assigned = loader
+                            .ecc_chip()
+                            .assign_constant(&mut loader.ctx_mut(), point)?;
+                        points.push(loader.ec_point_from_assigned(assigned));
+                    }
+                    let rhs = points.pop().unwrap();
+                    KzgAccumulator::new(points.pop().unwrap(), rhs)
+                };
+                let mut accumulators = link_accumulators;
+                for accumulator in &previous_accumulators {
+                    accumulators.push(select_accumulator(
+                        &loader,
+                        &first_round,
+                        &default,
+                        accumulator,
+                    )?);
+                }
+                let mut transcript = PoseidonTranscript::<Rc<Halo2Loader>, _>::new(
+                    &loader,
+                    self.as_proof.as_ref().map(Vec::as_slice),
+                );
+                let proof = As::read_proof(&Default::default(), &accumulators, &mut transcript)
+                    .map_err(|_| Error::Synthesis)?;
+                let accumulator = As::verify(&Default::default(), &accumulators, &proof)
+                    .map_err(|_| Error::Synthesis)?;
+
+                {
+                    let mut ctx = loader.ctx_mut();
+                    let ctx = &mut *ctx;
+                    let first_initial = main_gate.mul(ctx, &initial, &first_round)?;
+                    let link_initial = main_gate.mul(ctx, &link[0], &first_round)?;
+                    for (lhs, rhs) in [
+                        // later steps repeat the key digest and initial state of the previous one
+                        (
+                            main_gate.mul(ctx, &digest, &not_first_round)?,
+                            previous[DIGEST_ROW].clone(),
+                        ),
+                        (
+                            main_gate.mul(ctx, &initial, &not_first_round)?,
+                            previous[INITIAL_ROW].clone(),
+                        ),
+                        // the first link starts from the initial state
+                        (first_initial, link_initial),
+                        // later links start from the state of the previous step
+                        (
+                            main_gate.mul(ctx, &link[0], &not_first_round)?,
+                            previous[STATE_ROW].clone(),
+                        ),
+                        (state.clone(), link[1].clone()),
+                        // and count one more step
+                        (
+                            round.clone(),
+                            main_gate.add(ctx, &not_first_round, &previous[ROUND_ROW])?,
+                        ),
+                    ] {
+                        ctx.constrain_equal(lhs.cell(), rhs.cell())?;
+                    }
+                }
+
+                let mut public = Vec::new();
+                for point in [accumulator.lhs, accumulator.rhs] {
+                    let limbs = loader
+                        .ecc_chip()
+                        .assign_ec_point_to_limbs(&mut loader.ctx_mut(), point.assigned())?;
+                    public.extend(limbs);
+                }
+                public.extend([digest, initial, state, round]);
+                Ok(public)
+            },
+        )?;
+        for (row, cell) in public.into_iter().enumerate() {
+            main_gate.expose_public(layouter.namespace(|| "public input"), cell, row)?;
+        }
+        Ok(())
+    }
+}
+
+/// The proof of a chain of `steps` links from `initial` to `state`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainProof {
+    pub initial: Fr,
+    pub state: Fr,
+    pub steps: u64,
+    /// The [`IvcCircuit`] proof, in the transcript of [`prove_chunk`]
+    pub proof: Vec<u8>,
+    pub instances: Vec<Fr>,
+}
+
+/// Proves and verifies chains of messages of one length, holding the keys of the link and
+/// IVC circuits.
+pub struct ChainProver {
+    link_ctx: ProverContext<ChainLinkCircuit>,
+    link_protocol: PlonkProtocol<G1Affine>,
+    ctx: ProverContext<IvcCircuit>,
+    protocol: PlonkProtocol<G1Affine>,
+    digest: Fr,
+    message_len: usize,
+}
+
+impl ChainProver {
+    /// Generates the keys of chains of messages of `message_len` elements: the link circuit's
+    /// on `params`, the link circuit's on `params` downsized to `link_k`.
+    pub fn new(
+        params: ParamsKZG<Bn256>,
+        link_k: u32,
+        message_len: usize,
+    ) -> Result<Self, IvcError> {
+        let mut link_params = params.clone();
+        link_params.downsize(link_k);
+        let link = ChainLinkCircuit::new(Fr::ZERO, vec![Fr::ZERO; message_len]);
+        let link_ctx = ProverContext::new(link_params, &link)?;
+        let link_protocol = compile(
+            link_ctx.params(),
+            link_ctx.pk().get_vk(),
+            Config::kzg().with_num_instance(vec![2]),
+        );
+
+        let shape = shape_protocol(&params)?;
+        let dummy_link = ChunkSnark {
+            instances: link.instances().to_vec(),
+            proof: dummy_proof(&link_protocol),
+        };
+        let circuit = IvcCircuit::new(
+            &params,
+            &link_protocol,
+            &dummy_link,
+            &shape,
+            &initial_snark(&params, &shape),
+            Fr::ZERO,
+            0,
+        )?;
+        let ctx = ProverContext::new(params, &circuit)?;
+        let protocol = compile(ctx.params(), ctx.pk().get_vk(), ivc_config());
+        Ok(Self {
+            link_ctx,
+            link_protocol,
+            digest: key_digest(&protocol),
+            ctx,
+            protocol,
+            message_len,
+        })
+    }
+
+    /// Proves the first link of a chain from `initial`.
+    pub fn start(&self, initial: Fr, message: &[Fr]) -> Result<ChainProof, IvcError> {
+        let previous = initial_snark(self.ctx.params(), &self.protocol);
+        self.step(previous, initial, initial, 0, message)
+    }
+
+    /// Checks `chain`, then proves the link absorbing `message` into its state.
+    pub fn extend(&self, chain: &ChainProof, message: &[Fr]) -> Result<ChainProof, IvcError> {
+        self.verify(chain)?;
+        let previous = ChunkSnark {
+            instances: chain.instances.clone(),
+            proof: chain.proof.clone(),
+        };
+        self.step(previous, chain.initial, chain.state, chain.steps, message)
+    }
+
+    fn step(
+        &self,
+        previous: ChunkSnark,
+        initial: Fr,
+        state: Fr,
+        round: u64,
+        message: &[Fr],
+    ) -> Result<ChainProof, IvcError> {
+        if message.len() != self.message_len {
+            return Err(IvcError::MessageLength {
+                expected: self.message_len,
+                len: message.len(),
+            });
+        }
+        let link = ChainLinkCircuit::new(state, message.to_vec());
+        let link_snark = prove_chunk(&self.link_ctx, &link, &link.instances())?;
+        let circuit = IvcCircuit::new(
+            self.ctx.params(),
+            &self.link_protocol,
+            &link_snark,
+            &self.protocol,
+            &previous,
+            initial,
+            round,
+        )?;
+        let instances = circuit.instances();
+        let proof = prove_chunk(&self.ctx, &circuit, &instances)?;
+        Ok(ChainProof {
+            initial,
+            state: link_snark.instances[1],
+            steps: round + 1,
+            proof: proof.proof,
+            instances,
+        })
+    }
+
+    /// Checks that the public inputs of `chain` are its states and steps under the key of
+    /// this prover, then its proof and the pairing of its accumulator.
+    pub fn verify(&self, chain: &ChainProof) -> Result<(), IvcError> {
+        let expected = [
+            self.digest,
+            chain.initial,
+            chain.state,
+            Fr::from(chain.steps.wrapping_sub(1)),
+        ];
+        if chain.steps == 0
+            || chain.instances.len() != ACCUMULATOR_INSTANCES + IVC_PUBLIC_INPUTS
+            || chain.instances[ACCUMULATOR_INSTANCES..] != expected
+        {
+            return Err(IvcError::Instances);
+        }
+        let snark = ChunkSnark {
+            instances: chain.instances.clone(),
+            proof: chain.proof.clone(),
+        };
+        verify_chunk(self.ctx.params(), self.ctx.pk().get_vk(), &snark)
+            .map_err(|err| IvcError::InvalidProof(format!("{err:?}")))?;
+        decide(self.ctx.params(), &chain.instances)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::dev::MockProver;
+
+    use super::*;
+
+    const LINK_K: u32 = 10;
+
+    #[test]
+    fn test_chain_link() {
+        let message = vec![Fr::from(7), Fr::from(8)];
+        let link = ChainLinkCircuit::new(Fr::from(1), message.clone());
+        let [state, next] = link.instances();
+        assert_eq!(next, chain_link(state, &message));
+        assert_ne!(
+            next,
+            crate::poseidon_hash::hash(&spec(), &[state, message[0], message[1]])
+        );
+
+        let prover = MockProver::run(LINK_K, &link, vec![vec![state, next]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        let prover = MockProver::run(LINK_K, &link, vec![vec![state, next + Fr::ONE]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_first_step() {
+        let params = ParamsKZG::<Bn256>::setup(22, OsRng);
+        let mut link_params = params.clone();
+        link_params.downsize(LINK_K);
+        let link = ChainLinkCircuit::new(Fr::from(1), vec![Fr::from(2)]);
+        let link_ctx = ProverContext::new(link_params, &link).unwrap();
+        let link_protocol = compile(
+            link_ctx.params(),
+            link_ctx.pk().get_vk(),
+            Config::kzg().with_num_instance(vec![2]),
+        );
+        let link_snark = prove_chunk(&link_ctx, &link, &link.instances()).unwrap();
+
+        let shape = shape_protocol(&params).unwrap();
+        let previous = initial_snark(&params, &shape);
+        let circuit = IvcCircuit::new(
+            &params,
+            &link_protocol,
+            &link_snark,
+            &shape,
+            &previous,
+            Fr::from(1),
+            0,
+        )
+        .unwrap();
+        let instances = circuit.instances();
+        assert_eq!(
+            instances[ACCUMULATOR_INSTANCES..],
+            [
+                key_digest(&shape),
+                Fr::from(1),
+                link.instances()[1],
+                Fr::ZERO
+            ]
+        );
+        let prover = MockProver::run(22, &circuit, vec![instances.clone()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // the first link has to start from the initial state
+        let forged = IvcCircuit::new(
+            &params,
+            &link_protocol,
+            &link_snark,
+            &shape,
+            &previous,
+            Fr::from(3),
+            0,
+        )
+        .unwrap();
+        let prover = MockProver::run(22, &forged, vec![forged.instances()]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
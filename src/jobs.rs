@@ -0,0 +1,296 @@
+//! Tasks accepted at once and proven in the background by a bounded pool of workers.
+//!
+//! [`JobQueue::submit`] queues a task under its `uuid` and returns without proving it; a
+//! fixed number of worker threads take tasks in submission order and run the handler the
+//! queue was started with. [`JobQueue::status`] tells where a job is, and holds the
+//! [`ProofDetail`] of a finished one until [`JobConfig::retain`] newer jobs have finished.
+//!
+//! Proofs in flight are what takes the memory of a prover, so the number of workers bounds
+//! it, and the capacity bounds the tasks waiting: a full queue rejects new tasks rather than
+//! buffering them.
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex},
+};
+
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    stage::Stage,
+    task::{ProofDetail, Task},
+};
+
+/// Workers and bounds of a [`JobQueue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobConfig {
+    /// Tasks proven at the same time
+    pub workers: usize,
+    /// Tasks waiting for a worker
+    pub capacity: usize,
+    /// Finished jobs whose proof details are kept for [`JobQueue::status`]
+    pub retain: usize,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            capacity: 64,
+            retain: 1024,
+        }
+    }
+}
+
+/// Where a job is.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting behind `position` other tasks
+    Queued {
+        position: usize,
+    },
+    Running,
+    Done {
+        detail: Box<ProofDetail>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobError {
+    /// `capacity` tasks are already waiting
+    Full { capacity: usize },
+    /// A task of the same `uuid` is queued or running
+    Duplicate(String),
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full { capacity } => {
+                write!(f, "job queue is full with {capacity} waiting tasks")
+            }
+            Self::Duplicate(uuid) => write!(f, "task {uuid} is already queued or running"),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+type Handler = dyn Fn(&Task) -> ProofDetail + Send + Sync;
+
+enum Job {
+    Running,
+    Done(ProofDetail),
+}
+
+#[derive(Default)]
+struct Jobs {
+    queue: VecDeque<Task>,
+    /// Jobs taken by a worker, by `uuid`
+    jobs: HashMap<String, Job>,
+    /// `uuid`s of finished jobs, oldest first
+    finished: VecDeque<String>,
+}
+
+struct Shared {
+    config: JobConfig,
+    jobs: Mutex<Jobs>,
+    queued: Condvar,
+    handler: Box<Handler>,
+}
+
+/// A queue of tasks and the workers proving them; clones share both.
+#[derive(Clone)]
+pub struct JobQueue {
+    shared: Arc<Shared>,
+}
+
+impl JobQueue {
+    /// Starts `config.workers` threads answering tasks with `handler`; they live as long as
+    /// the process.
+    pub fn start(
+        config: JobConfig,
+        handler: impl Fn(&Task) -> ProofDetail + Send + Sync + 'static,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            config,
+            jobs: Mutex::default(),
+            queued: Condvar::new(),
+            handler: Box::new(handler),
+        });
+        for _ in 0..config.workers.max(1) {
+            let shared = shared.clone();
+            std::thread::spawn(move || work(&shared));
+        }
+        Self { shared }
+    }
+
+    pub fn config(&self) -> JobConfig {
+        self.shared.config
+    }
+
+    /// Queues `task` under its `uuid`, replacing the finished job of the same `uuid` if any.
+    pub fn submit(&self, task: Task) -> Result<(), JobError> {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        if matches!(jobs.jobs.get(&task.uuid), Some(Job::Running))
+            || jobs.queue.iter().any(|queued| queued.uuid == task.uuid)
+        {
+            return Err(JobError::Duplicate(task.uuid));
+        }
+        if jobs.queue.len() >= self.shared.config.capacity {
+            return Err(JobError::Full {
+                capacity: self.shared.config.capacity,
+            });
+        }
+        if jobs.jobs.remove(&task.uuid).is_some() {
+            jobs.finished.retain(|uuid| *uuid != task.uuid);
+        }
+        jobs.queue.push_back(task);
+        self.shared.queued.notify_one();
+        Ok(())
+    }
+
+    /// Where the job of `uuid` is; `None` if it was never submitted or is no longer retained.
+    pub fn status(&self, uuid: &str) -> Option<JobStatus> {
+        let jobs = self.shared.jobs.lock().unwrap();
+        if let Some(position) = jobs.queue.iter().position(|task| task.uuid == uuid) {
+            return Some(JobStatus::Queued { position });
+        }
+        match jobs.jobs.get(uuid)? {
+            Job::Running => Some(JobStatus::Running),
+            Job::Done(detail) => Some(JobStatus::Done {
+                detail: Box::new(detail.clone()),
+            }),
+        }
+    }
+
+    /// Tasks waiting for a worker.
+    pub fn waiting(&self) -> usize {
+        self.shared.jobs.lock().unwrap().queue.len()
+    }
+}
+
+/// Takes tasks off the queue and answers them, forever.
+fn work(shared: &Shared) {
+    loop {
+        let task = {
+            let mut jobs = shared
+                .queued
+                .wait_while(shared.jobs.lock().unwrap(), |jobs| jobs.queue.is_empty())
+                .unwrap();
+            let task = jobs.queue.pop_front().expect("woken with a task");
+            jobs.jobs.insert(task.uuid.clone(), Job::Running);
+            task
+        };
+        // a panicking handler fails its task rather than the worker
+        let detail = panic::catch_unwind(AssertUnwindSafe(|| (shared.handler)(&task)))
+            .unwrap_or_else(|_| {
+                ProofDetail {
+                    id: task.id.clone(),
+                    proof_type: task.task_type,
+                    ..Default::default()
+                }
+                .with_error(
+                    Stage::Prove,
+                    &Error::new(Stage::Prove, "the prover panicked"),
+                )
+            });
+        let mut jobs = shared.jobs.lock().unwrap();
+        jobs.jobs.insert(task.uuid.clone(), Job::Done(detail));
+        jobs.finished.push_back(task.uuid);
+        while jobs.finished.len() > shared.config.retain {
+            let oldest = jobs.finished.pop_front().expect("more than retained");
+            jobs.jobs.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use super::*;
+
+    fn task(uuid: &str) -> Task {
+        Task {
+            uuid: uuid.to_string(),
+            id: format!("id-{uuid}"),
+            ..Default::default()
+        }
+    }
+
+    fn wait_done(queue: &JobQueue, uuid: &str) -> ProofDetail {
+        for _ in 0..500 {
+            if let Some(JobStatus::Done { detail }) = queue.status(uuid) {
+                return *detail;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("job {uuid} did not finish");
+    }
+
+    #[test]
+    fn test_queue() {
+        // the handler blocks until released, so jobs stay queued as long as the test needs
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Mutex::new(gate);
+        let config = JobConfig {
+            workers: 1,
+            capacity: 2,
+            retain: 2,
+        };
+        let queue = JobQueue::start(config, move |task| {
+            gate.lock().unwrap().recv().unwrap();
+            if task.uuid == "panics" {
+                panic!("handler failure");
+            }
+            ProofDetail {
+                id: task.id.clone(),
+                proof_data: "AAEC".to_string(),
+                ..Default::default()
+            }
+        });
+
+        queue.submit(task("a")).unwrap();
+        // the worker takes the first task, the others wait behind it
+        while queue.status("a") != Some(JobStatus::Running) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        queue.submit(task("b")).unwrap();
+        queue.submit(task("panics")).unwrap();
+        assert_eq!(queue.status("b"), Some(JobStatus::Queued { position: 0 }));
+        assert_eq!(
+            queue.status("panics"),
+            Some(JobStatus::Queued { position: 1 })
+        );
+        assert_eq!(queue.submit(task("c")), Err(JobError::Full { capacity: 2 }));
+        assert_eq!(
+            queue.submit(task("b")),
+            Err(JobError::Duplicate("b".to_string()))
+        );
+        assert_eq!(queue.status("unknown"), None);
+
+        release.send(()).unwrap();
+        assert_eq!(wait_done(&queue, "a").id, "id-a");
+        release.send(()).unwrap();
+        assert_eq!(wait_done(&queue, "b").proof_data, "AAEC");
+        release.send(()).unwrap();
+        let failed = wait_done(&queue, "panics");
+        assert_eq!(failed.failed_stage, Some(Stage::Prove));
+        assert_eq!(failed.error_code, "synthesis");
+        // only the two newest finished jobs are kept
+        assert_eq!(queue.status("a"), None);
+        assert_eq!(queue.waiting(), 0);
+
+        // a finished job may be submitted again
+        queue.submit(task("b")).unwrap();
+        release.send(()).unwrap();
+        assert_eq!(wait_done(&queue, "b").id, "id-b");
+
+        let json = serde_json::to_value(JobStatus::Queued { position: 3 }).unwrap();
+        assert_eq!(json, serde_json::json!({"status": "queued", "position": 3}));
+    }
+}
@@ -0,0 +1,244 @@
+//! Proving keys of circuit shapes that are not keyed at startup, reused across requests.
+//!
+//! A chunk or batch task hashing another number of elements than
+//! [`crate::state::HASH_INPUTS`] needs a circuit of another shape, and keygen for it takes
+//! longer than the proof. [`KeyCache`] keeps the keys of recently used shapes, named by
+//! [`KeyId`], so repeated tasks of one shape run keygen once. With a directory, every
+//! generated key is also written there as `<key id>.pk` and [`KeyCache::load`] reads them
+//! back on the next start.
+//!
+//! A [`ShapeHistogram`] counts the tasks of every shape, so that a service can regenerate
+//! the keys of its frequent shapes while idle after keys of rare ones evicted them, see
+//! [`crate::state::ProverState::prewarm`].
+use std::{
+    collections::HashMap,
+    fs,
+    hash::Hash,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use halo2_proofs::{
+    plonk::{keygen_pk, keygen_vk, Circuit, Error, ProvingKey},
+    poly::kzg::commitment::ParamsKZG,
+    SerdeFormat,
+};
+use halo2curves::bn256::{Bn256, Fr, G1Affine};
+
+use crate::{
+    artifacts::{ArtifactStore, LocalStore, STALE_TEMP_AGE},
+    prover::read_pk,
+    vk_cache::LruCache,
+};
+
+/// Default number of proving keys kept in memory; keys of large shapes take megabytes.
+pub const DEFAULT_KEY_CACHE_CAPACITY: usize = 8;
+
+/// Names the proving key of one circuit shape.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyId<'a> {
+    /// Circuit and whatever sizes its layout, e.g. `hash7` for a hash of 7 elements
+    pub shape: &'a str,
+    pub k: u32,
+    pub hard_fork: &'a str,
+    /// Identifies the params and circuit version the key is for, e.g. the hash of the
+    /// verifying key the service is keyed with at startup; keys of other params are
+    /// never loaded
+    pub generation: &'a str,
+}
+
+impl KeyId<'_> {
+    pub fn name(&self) -> String {
+        format!(
+            "{}-k{}-{}-{}",
+            self.shape, self.k, self.hard_fork, self.generation
+        )
+    }
+}
+
+/// Proving keys by [`KeyId`], least recently used first out.
+#[derive(Debug)]
+pub struct KeyCache {
+    cache: LruCache<ProvingKey<G1Affine>>,
+    /// Where keys are persisted, if anywhere
+    store: Option<LocalStore>,
+}
+
+impl KeyCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cache: LruCache::new(capacity),
+            store: None,
+        }
+    }
+
+    /// Also writes generated keys to `dir`, see [`KeyCache::load`].
+    pub fn persisted(dir: impl Into<PathBuf>, capacity: usize) -> Self {
+        Self {
+            store: Some(LocalStore::new(dir)),
+            ..Self::new(capacity)
+        }
+    }
+
+    /// Reads keys of `generation` persisted by earlier runs, up to the capacity of the
+    /// cache, and returns how many were read.
+    ///
+    /// A missing directory holds no keys; a key that does not deserialize is an error, as
+    /// the directory is not shared with anything else. Keys whose writes a crash interrupted
+    /// are removed, see [`LocalStore::sweep`].
+    pub fn load<ConcreteCircuit: Circuit<Fr>>(&self, generation: &str) -> io::Result<usize> {
+        let Some(store) = &self.store else {
+            return Ok(0);
+        };
+        store.sweep(STALE_TEMP_AGE)?;
+        let entries = match fs::read_dir(store.root()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        let suffix = format!("-{generation}.pk");
+        let mut loaded = 0;
+        for entry in entries {
+            let file_name = entry?.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            if !file_name.ends_with(&suffix) {
+                continue;
+            }
+            if loaded == self.cache.capacity() {
+                break;
+            }
+            let pk = read_pk::<ConcreteCircuit>(&store.get(file_name)?)?;
+            let name = file_name.trim_end_matches(".pk");
+            self.cache.get_or_load::<io::Error>(name, || Ok(pk))?;
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// The key of `id`, running keygen for `circuit` on `params` on a miss.
+    ///
+    /// A key that cannot be persisted, e.g. because the hard fork name is not a valid file
+    /// name, is still cached in memory and generated again after a restart.
+    pub fn get_or_keygen<ConcreteCircuit: Circuit<Fr>>(
+        &self,
+        id: &KeyId,
+        params: &ParamsKZG<Bn256>,
+        circuit: &ConcreteCircuit,
+    ) -> Result<Arc<ProvingKey<G1Affine>>, Error> {
+        let name = id.name();
+        self.cache.get_or_load(&name, || {
+            let vk = keygen_vk(params, circuit)?;
+            let pk = keygen_pk(params, vk, circuit)?;
+            if let Some(store) = &self.store {
+                let _ = store.put(&format!("{name}.pk"), &pk.to_bytes(SerdeFormat::RawBytes));
+            }
+            Ok(pk)
+        })
+    }
+
+    /// Whether the key of `id` is in memory.
+    pub fn contains(&self, id: &KeyId) -> bool {
+        self.cache.contains(&id.name())
+    }
+
+    pub fn cached(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cache.capacity()
+    }
+}
+
+/// Number of tasks of every shape seen.
+#[derive(Debug)]
+pub struct ShapeHistogram<S> {
+    counts: Mutex<HashMap<S, u64>>,
+}
+
+impl<S> Default for ShapeHistogram<S> {
+    fn default() -> Self {
+        Self {
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<S: Clone + Eq + Hash> ShapeHistogram<S> {
+    pub fn record(&self, shape: S) {
+        *self.counts.lock().unwrap().entry(shape).or_default() += 1;
+    }
+
+    /// Every shape seen with its count, most frequent first.
+    pub fn counts(&self) -> Vec<(S, u64)> {
+        let counts = self.counts.lock().unwrap();
+        let mut counts = counts
+            .iter()
+            .map(|(shape, count)| (shape.clone(), *count))
+            .collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(&a.1));
+        counts
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.lock().unwrap().values().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::poly::commitment::Params;
+    use rand_core::OsRng;
+
+    use super::*;
+    use crate::test_circuit::TestCircuit;
+
+    #[test]
+    fn test_reuse_and_persist() {
+        let dir = std::env::temp_dir().join(format!("key-cache-{}", std::process::id()));
+        let params = ParamsKZG::<Bn256>::setup(8, OsRng);
+        let circuit = TestCircuit::new(vec![Fr::ZERO; 2]);
+        let id = KeyId {
+            shape: "hash2",
+            k: params.k(),
+            hard_fork: "bernoulli",
+            generation: "g1",
+        };
+
+        let cache = KeyCache::persisted(&dir, 2);
+        let pk = cache.get_or_keygen(&id, &params, &circuit).unwrap();
+        let again = cache.get_or_keygen(&id, &params, &circuit).unwrap();
+        assert!(Arc::ptr_eq(&pk, &again));
+        assert!(dir.join(format!("{}.pk", id.name())).exists());
+
+        // a restart reads the key back instead of running keygen
+        let restarted = KeyCache::persisted(&dir, 2);
+        assert_eq!(restarted.load::<TestCircuit<Fr>>("g1").unwrap(), 1);
+        assert_eq!(restarted.load::<TestCircuit<Fr>>("g2").unwrap(), 0);
+        let loaded = restarted.cache.get(&id.name()).unwrap();
+        assert_eq!(
+            loaded.get_vk().to_bytes(SerdeFormat::RawBytes),
+            pk.get_vk().to_bytes(SerdeFormat::RawBytes)
+        );
+
+        assert_eq!(KeyCache::new(2).load::<TestCircuit<Fr>>("g1").unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = ShapeHistogram::default();
+        assert!(histogram.counts().is_empty());
+        for len in [7, 3, 7, 9, 7, 3] {
+            histogram.record(len);
+        }
+        let counts = histogram.counts();
+        assert_eq!(counts[..2], [(7, 3), (3, 2)]);
+        assert_eq!(counts[2], (9, 1));
+        assert_eq!(histogram.total(), 6);
+    }
+}
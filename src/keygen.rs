@@ -0,0 +1,440 @@
+//! Offline keygen for every configured circuit variant, for deployments that split keygen
+//! from proving.
+//!
+//! A config lists the variants to build, e.g.
+//!
+//! ```toml
+//! # optional; without it insecure test params are set up and written next to the keys
+//! params = "params/kzg_bn254_13.srs"
+//!
+//! [[variant]]
+//! circuit = "preimage"
+//! k = 10
+//!
+//! [[variant]]
+//! name = "membership-depth-20"
+//! circuit = "membership"
+//! k = 13
+//! fork = "genesis"
+//! depth = 20
+//! ```
+//!
+//! [`keygen_all`] generates the keys of all variants in parallel and puts them into an
+//! [`ArtifactStore`] as `<vk hash>.pk` and `<vk hash>.vk`, the keys
+//! [`crate::vk_cache::VkStore`] reads, next to a `manifest.json` with the fingerprints of
+//! every object, see [`Manifest`].
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use ff::Field;
+use halo2_proofs::{
+    plonk::{keygen_pk, keygen_vk, Circuit, Error, ProvingKey},
+    poly::{commitment::Params, kzg::commitment::ParamsKZG},
+    SerdeFormat,
+};
+use halo2curves::bn256::{Bn256, Fr, G1Affine};
+use rand_core::OsRng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    artifacts::{pk_key, vk_key, ArtifactError, ArtifactStore},
+    membership::MembershipCircuit,
+    merkle::MerklePath,
+    preimage::PreimageCircuit,
+    prover::{read_params, vk_hash},
+    range_proof::RangeProofCircuit,
+    specs::{SpecParams, BN256_T4_R3, SPECS},
+    state::{HASH_INPUTS, MEMBERSHIP_DEPTH, RANGE_PROOF_BITS},
+    test_circuit::TestCircuit,
+};
+
+/// Name of the manifest in the output directory.
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// Name of the params written when the config has none.
+pub const PARAMS_FILE: &str = "params.bin";
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct KeygenConfig {
+    /// Params file of at least `2^k` rows for the largest `k`
+    pub params: Option<PathBuf>,
+    #[serde(rename = "variant", default)]
+    pub variants: Vec<Variant>,
+}
+
+/// A circuit of a fixed shape; every variant has its own keys.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Variant {
+    /// Defaults to `<circuit>-k<k>`
+    pub name: Option<String>,
+    pub circuit: CircuitKind,
+    pub k: u32,
+    /// Id of the spec, see [`crate::specs::SPECS`]
+    pub spec: Option<String>,
+    /// Hard fork whose spec is used, if `spec` is not given; the latest spec otherwise
+    pub fork: Option<String>,
+    /// Number of hashed inputs of the `hash` circuit
+    pub inputs: Option<usize>,
+    /// Bit size of the `range` circuit
+    pub bits: Option<usize>,
+    /// Tree depth of the `membership` circuit
+    pub depth: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CircuitKind {
+    /// [`TestCircuit`], proving chunk and batch tasks
+    Hash,
+    Preimage,
+    Range,
+    Membership,
+}
+
+impl CircuitKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hash => "hash",
+            Self::Preimage => "preimage",
+            Self::Range => "range",
+            Self::Membership => "membership",
+        }
+    }
+}
+
+impl Variant {
+    pub fn name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("{}-k{}", self.circuit.as_str(), self.k))
+    }
+
+    /// The spec of the variant, by id, by fork or the latest one.
+    pub fn spec(&self) -> Result<&'static SpecParams, KeygenError> {
+        let spec = match (&self.spec, &self.fork) {
+            (Some(id), _) => SPECS.iter().find(|spec| spec.id == id),
+            (None, Some(fork)) => SPECS.iter().rev().find(|spec| spec.introduced_in == fork),
+            (None, None) => SPECS.last(),
+        };
+        spec.ok_or_else(|| {
+            KeygenError::Config(format!(
+                "variant {}: no spec {}",
+                self.name(),
+                self.spec.as_ref().or(self.fork.as_ref()).map_or("", |s| s)
+            ))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum KeygenError {
+    Config(String),
+    Io(io::Error),
+    Store(ArtifactError),
+    Keygen { variant: String, err: Error },
+}
+
+impl fmt::Display for KeygenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Config(msg) => write!(f, "invalid keygen config: {msg}"),
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Store(err) => write!(f, "cannot store artifacts: {err}"),
+            Self::Keygen { variant, err } => write!(f, "keygen of {variant} failed: {err:?}"),
+        }
+    }
+}
+
+impl std::error::Error for KeygenError {}
+
+impl From<io::Error> for KeygenError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ArtifactError> for KeygenError {
+    fn from(err: ArtifactError) -> Self {
+        Self::Store(err)
+    }
+}
+
+impl KeygenConfig {
+    pub fn parse(s: &str) -> Result<Self, KeygenError> {
+        let config: Self = toml::from_str(s).map_err(|err| KeygenError::Config(err.to_string()))?;
+        if config.variants.is_empty() {
+            return Err(KeygenError::Config("no variants".to_string()));
+        }
+        let mut names = Vec::new();
+        for variant in &config.variants {
+            let name = variant.name();
+            let valid = |c: char| c.is_ascii_alphanumeric() || "-_.".contains(c);
+            if name.is_empty() || !name.chars().all(valid) {
+                return Err(KeygenError::Config(format!(
+                    "invalid variant name {name:?}"
+                )));
+            }
+            if names.contains(&name) {
+                return Err(KeygenError::Config(format!("duplicate variant {name}")));
+            }
+            // circuits are compiled for a single spec so far
+            let spec = variant.spec()?;
+            if spec.id != BN256_T4_R3.id {
+                return Err(KeygenError::Config(format!(
+                    "variant {name}: {} circuits are not compiled for spec {}",
+                    variant.circuit.as_str(),
+                    spec.id
+                )));
+            }
+            names.push(name);
+        }
+        Ok(config)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, KeygenError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn max_k(&self) -> u32 {
+        self.variants.iter().map(|v| v.k).max().unwrap_or(0)
+    }
+}
+
+/// Describes the artifacts of a keygen run, stored next to them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub params: Artifact,
+    pub variants: Vec<VariantKeys>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VariantKeys {
+    pub name: String,
+    pub circuit: CircuitKind,
+    pub k: u32,
+    pub spec: String,
+    pub fork: String,
+    pub vk_hash: String,
+    pub vk: Artifact,
+    pub pk: Artifact,
+}
+
+/// A stored object, or the params file named in the config, and the hex Blake2b-256 of its
+/// contents.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    pub file: String,
+    pub blake2b: String,
+    pub size: u64,
+}
+
+impl Artifact {
+    fn new(file: String, bytes: &[u8]) -> Self {
+        Self {
+            file,
+            blake2b: fingerprint(bytes),
+            size: bytes.len() as u64,
+        }
+    }
+
+    fn put(store: &dyn ArtifactStore, key: String, bytes: &[u8]) -> Result<Self, ArtifactError> {
+        store.put(&key, bytes)?;
+        Ok(Self::new(key, bytes))
+    }
+
+    /// Checks the object in `store` still matches its fingerprint.
+    pub fn check(&self, store: &dyn ArtifactStore) -> Result<bool, ArtifactError> {
+        Ok(fingerprint(&store.get(&self.file)?) == self.blake2b)
+    }
+}
+
+fn fingerprint(bytes: &[u8]) -> String {
+    blake2b_simd::Params::new()
+        .hash_length(32)
+        .hash(bytes)
+        .to_hex()
+        .to_string()
+}
+
+/// Generates the keys of every variant of `config` in parallel and puts them, the params
+/// if the config has none and the manifest into `store`.
+pub fn keygen_all(
+    config: &KeygenConfig,
+    store: &dyn ArtifactStore,
+) -> Result<Manifest, KeygenError> {
+    let max_k = config.max_k();
+    let params = match &config.params {
+        Some(path) => {
+            let params = read_params(path)?;
+            if params.k() < max_k {
+                return Err(KeygenError::Config(format!(
+                    "params of 2^{} rows, need 2^{max_k}",
+                    params.k()
+                )));
+            }
+            params
+        }
+        None => ParamsKZG::<Bn256>::setup(max_k, OsRng),
+    };
+    let mut bytes = Vec::new();
+    params.write(&mut bytes)?;
+    let params_artifact = match &config.params {
+        Some(path) => Artifact::new(path.display().to_string(), &bytes),
+        None => Artifact::put(store, PARAMS_FILE.to_string(), &bytes)?,
+    };
+    drop(bytes);
+
+    let variants = config
+        .variants
+        .par_iter()
+        .map(|variant| keygen_variant(&params, variant, store))
+        .collect::<Result<Vec<_>, _>>()?;
+    let manifest = Manifest {
+        params: params_artifact,
+        variants,
+    };
+    let json = serde_json::to_vec_pretty(&manifest).map_err(io::Error::other)?;
+    store.put(MANIFEST_FILE, &json)?;
+    Ok(manifest)
+}
+
+fn keygen_variant(
+    params: &ParamsKZG<Bn256>,
+    variant: &Variant,
+    store: &dyn ArtifactStore,
+) -> Result<VariantKeys, KeygenError> {
+    let name = variant.name();
+    let spec = variant.spec()?;
+    let mut params = params.clone();
+    params.downsize(variant.k);
+    // keygen depends on the shape of a circuit, not on its witness
+    let pk = match variant.circuit {
+        CircuitKind::Hash => keygen(
+            &params,
+            &TestCircuit::new(vec![Fr::ZERO; variant.inputs.unwrap_or(HASH_INPUTS)]),
+        ),
+        CircuitKind::Preimage => keygen(&params, &PreimageCircuit::new(Fr::ZERO)),
+        CircuitKind::Range => keygen(
+            &params,
+            &RangeProofCircuit::new(Fr::ZERO, Fr::ZERO, variant.bits.unwrap_or(RANGE_PROOF_BITS)),
+        ),
+        CircuitKind::Membership => keygen(
+            &params,
+            &MembershipCircuit::new(
+                Fr::ZERO,
+                MerklePath {
+                    index: 0,
+                    siblings: vec![Fr::ZERO; variant.depth.unwrap_or(MEMBERSHIP_DEPTH)],
+                },
+            ),
+        ),
+    }
+    .map_err(|err| KeygenError::Keygen {
+        variant: name.clone(),
+        err,
+    })?;
+
+    let vk_hash = vk_hash(pk.get_vk());
+    let vk = Artifact::put(
+        store,
+        vk_key(&vk_hash),
+        &pk.get_vk().to_bytes(SerdeFormat::RawBytes),
+    )?;
+    let pk = Artifact::put(store, pk_key(&vk_hash), &pk.to_bytes(SerdeFormat::RawBytes))?;
+    Ok(VariantKeys {
+        name,
+        circuit: variant.circuit,
+        k: variant.k,
+        spec: spec.id.to_string(),
+        fork: spec.introduced_in.to_string(),
+        vk_hash,
+        vk,
+        pk,
+    })
+}
+
+fn keygen<C: Circuit<Fr>>(
+    params: &ParamsKZG<Bn256>,
+    circuit: &C,
+) -> Result<ProvingKey<G1Affine>, Error> {
+    let vk = keygen_vk(params, circuit)?;
+    keygen_pk(params, vk, circuit)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{artifacts::MemoryStore, prover::read_vk, vk_cache::VkStore};
+
+    #[test]
+    fn test_parse_config() {
+        let config = KeygenConfig::parse(
+            r#"
+            params = "params.srs"
+
+            [[variant]]
+            circuit = "preimage"
+            k = 10
+
+            [[variant]]
+            name = "range-32"
+            circuit = "range"
+            k = 10
+            fork = "genesis"
+            bits = 32
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.params, Some(PathBuf::from("params.srs")));
+        assert_eq!(config.variants[0].name(), "preimage-k10");
+        assert_eq!(config.variants[1].circuit, CircuitKind::Range);
+        assert_eq!(config.variants[1].spec().unwrap().id, BN256_T4_R3.id);
+        assert_eq!(config.max_k(), 10);
+
+        for bad in [
+            "",
+            "[[variant]]\ncircuit = \"sha256\"\nk = 10",
+            "[[variant]]\ncircuit = \"hash\"\nk = 10\nfork = \"undefined\"",
+            "[[variant]]\ncircuit = \"hash\"\nk = 10\n[[variant]]\ncircuit = \"hash\"\nk = 10",
+            "[[variant]]\ncircuit = \"hash\"\nk = 10\nsize = 3",
+            "[[variant]]\nname = \"../hash\"\ncircuit = \"hash\"\nk = 10",
+        ] {
+            assert!(
+                matches!(KeygenConfig::parse(bad), Err(KeygenError::Config(_))),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_keygen_all() {
+        let store = Arc::new(MemoryStore::new());
+        let config = KeygenConfig::parse(
+            "[[variant]]\ncircuit = \"hash\"\nk = 10\ninputs = 1\n\
+             [[variant]]\ncircuit = \"preimage\"\nk = 10",
+        )
+        .unwrap();
+        let manifest = keygen_all(&config, store.as_ref()).unwrap();
+        assert_eq!(manifest.variants.len(), 2);
+        assert_ne!(manifest.variants[0].vk_hash, manifest.variants[1].vk_hash);
+        assert!(manifest.params.check(store.as_ref()).unwrap());
+        // params, manifest and two keys per variant
+        assert_eq!(store.len(), 6);
+
+        let written: Manifest = serde_json::from_slice(&store.get(MANIFEST_FILE).unwrap()).unwrap();
+        assert_eq!(written, manifest);
+        let keys = &manifest.variants[0];
+        assert!(keys.vk.check(store.as_ref()).unwrap() && keys.pk.check(store.as_ref()).unwrap());
+        let vk = read_vk::<TestCircuit<Fr>>(&store.get(&keys.vk.file).unwrap()).unwrap();
+        assert_eq!(vk_hash(&vk), keys.vk_hash);
+        // the verifier picks the keys up by hash
+        let vks = VkStore::from_store(store, 1);
+        assert!(vks.get::<TestCircuit<Fr>>(&keys.vk_hash).is_ok());
+    }
+}
@@ -1,9 +1,97 @@
+pub use error::Error;
 pub use ff;
 pub use halo2_proofs;
 pub use halo2curves;
 
+pub mod affinity;
+#[cfg(feature = "aggregation")]
+pub mod aggregation;
+pub mod artifacts;
+pub mod batching;
+pub mod bridge;
+pub mod callback;
+pub mod capabilities;
+pub mod cipher;
+pub mod commitment;
+pub mod compression;
+pub mod cost;
+#[cfg(any(feature = "rlp", feature = "ssz"))]
+pub mod decoders;
+#[cfg(feature = "differential")]
+pub mod differential;
+pub mod error;
+#[cfg(feature = "evm-verifier")]
+pub mod evm;
+pub mod field_encoding;
+pub mod fs_transcript;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hash_table;
+#[cfg(feature = "hash-to-curve")]
+pub mod hash_to_curve;
+pub mod hash_to_field;
+pub mod http;
+pub mod instance_layout;
+pub mod instructions;
+#[cfg(feature = "aggregation")]
+pub mod ivc;
+pub mod jobs;
+pub mod key_cache;
+pub mod keygen;
+pub mod limits;
+pub mod loadtest;
 pub mod main_gate;
+pub mod mem_stats;
+pub mod membership;
+pub mod merkle;
+pub mod merkle_batch;
+pub mod optimized_constants;
+pub mod packing;
+pub mod packing_chip;
+#[cfg(feature = "pasta")]
+pub mod pasta;
+pub mod payload;
+pub mod pipeline;
+pub mod poseidon2;
+pub mod poseidon2_circuit;
 pub mod poseidon_circuit;
 pub mod poseidon_hash;
+pub mod preimage;
+pub mod prelude;
+pub mod preprocess;
+pub mod presets;
+pub mod primitives;
+pub mod proof_store;
+pub mod proof_stream;
+pub mod prover;
+#[cfg(feature = "pse-compat")]
+pub mod pse_compat;
+pub mod range_chip;
+pub mod range_proof;
+pub mod replay;
 pub mod ro_types;
+pub mod same_digest;
+pub mod scheduler;
+pub mod schema;
+pub mod schnorr;
+pub mod service_config;
+pub mod smt;
+pub mod spec_bench;
+pub mod specs;
+pub mod stage;
+pub mod state;
+pub mod sub_circuit;
+pub mod task;
+pub mod task_data;
+pub mod telemetry;
 pub mod test_circuit;
+pub mod test_vectors;
+pub mod trace;
+pub mod trace_cache;
+pub mod vector_commitment;
+pub mod vk_cache;
+pub mod vk_export;
+pub mod vrf;
+pub mod wire;
+pub mod witness;
+pub mod witness_bench;
@@ -0,0 +1,263 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use ff::{FromUniformBytes, PrimeField};
+use poseidon::Spec;
+
+use crate::poseidon_circuit::PoseidonChip;
+
+/// Rows reserved for blinding at the end of every circuit.
+pub(crate) const UNUSABLE_ROWS: usize = 6;
+
+/// Most bytes of a response read by [`HttpUrl::post_json`]: the acknowledgement of a
+/// callback, or the proof detail of another prover.
+///
+/// [`HttpUrl::post_json`]: crate::http::HttpUrl::post_json
+pub const MAX_RESPONSE_BYTES: u64 = 16 << 20;
+
+/// Most bytes of an object downloaded over HTTP, from an S3-compatible store or as a params
+/// file: the proving keys and params of circuits up to `2^26` rows.
+pub const MAX_ARTIFACT_BYTES: u64 = 8 << 30;
+
+/// Bounds on the number of absorbed elements, checked before synthesis.
+///
+/// A message that does not fit into the circuit otherwise fails deep inside halo2 with
+/// `NotEnoughRowsAvailable` and no hint of which message was too long.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageLimits {
+    /// Most elements absorbed by a single hash
+    pub max_hash_len: usize,
+    /// Most elements absorbed by all hashes of a task together
+    pub max_task_len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    HashTooLong {
+        index: usize,
+        len: usize,
+        max: usize,
+    },
+    TaskTooLong {
+        len: usize,
+        max: usize,
+    },
+    /// The proof, or its calldata for EVM proofs, would take more bytes than allowed
+    ProofTooLarge {
+        estimate: usize,
+        max: usize,
+    },
+    /// The circuit of the task takes more than the `2^max` rows allowed
+    KTooLarge {
+        k: u32,
+        max: u32,
+    },
+    /// The task was still running `timeout` after it started, at a check of its
+    /// [`CancelToken`]
+    TimedOut {
+        timeout: Duration,
+    },
+    /// The task was cancelled through its [`CancelToken`]
+    Cancelled,
+}
+
+impl LimitError {
+    /// Which limit was exceeded, the `limit` of
+    /// [`crate::Error::ResourceLimitExceeded`].
+    pub fn limit(&self) -> &'static str {
+        match self {
+            Self::HashTooLong { .. } => "hash_len",
+            Self::TaskTooLong { .. } => "task_len",
+            Self::ProofTooLarge { .. } => "proof_size",
+            Self::KTooLarge { .. } => "k",
+            Self::TimedOut { .. } => "timeout",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HashTooLong { index, len, max } => write!(
+                f,
+                "message {index} has {len} elements, at most {max} can be hashed"
+            ),
+            Self::TaskTooLong { len, max } => write!(
+                f,
+                "task absorbs {len} elements in total, at most {max} are allowed"
+            ),
+            Self::ProofTooLarge { estimate, max } => write!(
+                f,
+                "proof is estimated at {estimate} bytes, over the budget of {max} bytes"
+            ),
+            Self::KTooLarge { k, max } => write!(
+                f,
+                "task needs a circuit of 2^{k} rows, at most 2^{max} are allowed"
+            ),
+            Self::TimedOut { timeout } => write!(f, "task took longer than {timeout:?}"),
+            Self::Cancelled => write!(f, "task was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+impl MessageLimits {
+    /// The longest message whose hash fits into a circuit of `2^k` rows, for both limits.
+    pub fn for_k<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+        spec: &Spec<F, T, RATE>,
+        k: u32,
+    ) -> Self {
+        let usable = (1usize << k).saturating_sub(UNUSABLE_ROWS);
+        // a message of len elements takes len / RATE + 1 permutations
+        let permutations = usable / PoseidonChip::num_rows(spec, 0);
+        let max = (permutations * RATE).saturating_sub(1);
+        Self {
+            max_hash_len: max,
+            max_task_len: max,
+        }
+    }
+
+    /// Checks the lengths of the messages of one task, reporting the first one too long.
+    pub fn check(&self, lens: impl IntoIterator<Item = usize>) -> Result<(), LimitError> {
+        let mut total = 0usize;
+        for (index, len) in lens.into_iter().enumerate() {
+            if len > self.max_hash_len {
+                return Err(LimitError::HashTooLong {
+                    index,
+                    len,
+                    max: self.max_hash_len,
+                });
+            }
+            total = total.saturating_add(len);
+        }
+        if total > self.max_task_len {
+            return Err(LimitError::TaskTooLong {
+                len: total,
+                max: self.max_task_len,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Stops a task at its next check once cancelled or past its deadline; clones share it.
+///
+/// The token is checked, not enforced. The prover checks it between the stages of a task and
+/// circuits check it while they are synthesized, e.g.
+/// [`crate::test_circuit::TestCircuit::with_cancel_token`]. Key generation, `create_proof`
+/// and verification are not interrupted once started: a task overruns its deadline by up to
+/// the length of the stage it is in, and cancelling it frees neither the thread nor the
+/// memory of that stage before the stage returns.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    inner: Arc<TokenState>,
+}
+
+#[derive(Debug, Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    /// The deadline and the timeout it was set from
+    deadline: Option<(Instant, Duration)>,
+    /// A token that cancels this one as well
+    parent: Option<CancelToken>,
+}
+
+impl CancelToken {
+    /// A token without deadline, stopping its task only when cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token stopping its task `timeout` from now.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::new().child(Some(timeout))
+    }
+
+    /// A token cancelled with this one, and `timeout` from now if one is given.
+    pub fn child(&self, timeout: Option<Duration>) -> Self {
+        Self {
+            inner: Arc::new(TokenState {
+                cancelled: AtomicBool::new(false),
+                deadline: timeout.map(|timeout| (Instant::now() + timeout, timeout)),
+                parent: Some(self.clone()),
+            }),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the task should stop, and why.
+    pub fn check(&self) -> Result<(), LimitError> {
+        if self.inner.cancelled.load(Ordering::Relaxed) {
+            return Err(LimitError::Cancelled);
+        }
+        if let Some((deadline, timeout)) = self.inner.deadline {
+            if Instant::now() >= deadline {
+                return Err(LimitError::TimedOut { timeout });
+            }
+        }
+        match &self.inner.parent {
+            Some(parent) => parent.check(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+
+    #[test]
+    fn test_limits_for_k() {
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        let limits = MessageLimits::for_k(&spec, 10);
+        // 3 permutations of 260 rows fit into 1018 usable rows
+        assert_eq!(limits.max_hash_len, 8);
+        assert!(PoseidonChip::num_rows(&spec, 8) + UNUSABLE_ROWS <= 1 << 10);
+        assert!(PoseidonChip::num_rows(&spec, 9) + UNUSABLE_ROWS > 1 << 10);
+
+        assert_eq!(limits.check([8]), Ok(()));
+        assert_eq!(
+            limits.check([1, 9]),
+            Err(LimitError::HashTooLong {
+                index: 1,
+                len: 9,
+                max: 8
+            })
+        );
+        assert_eq!(
+            limits.check([4, 5]),
+            Err(LimitError::TaskTooLong { len: 9, max: 8 })
+        );
+    }
+
+    #[test]
+    fn test_cancel_token() {
+        let parent = CancelToken::new();
+        let child = parent.child(Some(Duration::from_secs(3600)));
+        assert_eq!(child.check(), Ok(()));
+        parent.cancel();
+        assert_eq!(child.check(), Err(LimitError::Cancelled));
+
+        let timeout = Duration::ZERO;
+        let expired = CancelToken::with_timeout(timeout);
+        assert_eq!(expired.check(), Err(LimitError::TimedOut { timeout }));
+        assert_eq!(expired.check().unwrap_err().limit(), "timeout");
+        // cancelling a child leaves its parent running
+        let parent = CancelToken::new();
+        parent.child(None).cancel();
+        assert_eq!(parent.check(), Ok(()));
+    }
+}
@@ -0,0 +1,245 @@
+//! Sustained synthetic load against a prover, to validate a deployment before launch.
+//!
+//! [`run`] submits one [`synthetic_task`] every `1 / rps` seconds for the configured
+//! duration and records how long each submission took. The schedule is open loop: tasks
+//! are sent on time whether or not earlier ones have been answered, so a prover that falls
+//! behind shows up as growing latency instead of silently lowering the offered load.
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+
+use crate::{
+    state::{HASH_INPUTS, MEMBERSHIP_DEPTH},
+    task::{ProofType, Task},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoadConfig {
+    /// Tasks submitted per second
+    pub rps: f64,
+    pub duration: Duration,
+    pub profile: Profile,
+}
+
+/// The kind of task to submit, named as on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile(pub ProofType);
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let proof_type = match s {
+            "chunk" => ProofType::Chunk,
+            "batch" => ProofType::Batch,
+            "preimage" => ProofType::Preimage,
+            "range" => ProofType::Range,
+            "membership" => ProofType::Membership,
+            _ => return Err(format!("unknown profile {s:?}")),
+        };
+        Ok(Self(proof_type))
+    }
+}
+
+/// Parses durations such as `500ms`, `30s`, `10m` or `1h`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("duration {s:?} has no unit"))?;
+    let (value, unit) = s.split_at(split);
+    let value = value
+        .parse::<u64>()
+        .map_err(|_| format!("invalid duration {s:?}"))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        "m" => Ok(Duration::from_secs(value * 60)),
+        "h" => Ok(Duration::from_secs(value * 3600)),
+        _ => Err(format!("unknown duration unit {unit:?}")),
+    }
+}
+
+/// The `seq`-th task of a run; ids are unique within a run so responses can be told apart.
+///
+/// `task_data` fits the keys the service generates at startup, see
+/// [`crate::state::ProverState::prove`].
+pub fn synthetic_task(profile: Profile, seq: u64) -> Task {
+    let task_data = match profile.0 {
+        ProofType::Preimage => json!([seq]),
+        ProofType::Range => json!([seq, seq + 1]),
+        ProofType::Membership => {
+            let mut items = vec![seq, 0];
+            items.resize(2 + MEMBERSHIP_DEPTH, 0);
+            json!(items)
+        }
+        _ => json!((seq..seq + HASH_INPUTS as u64).collect::<Vec<_>>()),
+    };
+    Task {
+        uuid: format!("loadtest-{seq}"),
+        id: format!("loadtest-{seq}"),
+        task_type: profile.0,
+        task_data: task_data.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Submits tasks at `config.rps` for `config.duration` and waits for every answer.
+///
+/// `submit` returns `Err` with a short reason when the prover failed the task; reasons are
+/// counted separately in the report.
+pub fn run(config: &LoadConfig, submit: impl Fn(&Task) -> Result<(), String> + Sync) -> LoadReport {
+    let total = (config.duration.as_secs_f64() * config.rps).floor() as u64;
+    let interval = Duration::from_secs_f64(1.0 / config.rps.max(f64::MIN_POSITIVE));
+    let samples = Mutex::new(Vec::with_capacity(total as usize));
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for seq in 0..total {
+            let due = start + interval.mul_f64(seq as f64);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+            let (submit, samples) = (&submit, &samples);
+            scope.spawn(move || {
+                let task = synthetic_task(config.profile, seq);
+                let sent = Instant::now();
+                let result = submit(&task);
+                samples.lock().unwrap().push((sent.elapsed(), result));
+            });
+        }
+    });
+    let elapsed = start.elapsed();
+
+    let mut report = LoadReport {
+        sent: total,
+        elapsed,
+        ..Default::default()
+    };
+    for (latency, result) in samples.into_inner().unwrap() {
+        report.latencies.push(latency);
+        if let Err(reason) = result {
+            *report.errors.entry(reason).or_default() += 1;
+        }
+    }
+    report.latencies.sort();
+    report
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadReport {
+    pub sent: u64,
+    /// Wall time from the first submission to the last answer
+    pub elapsed: Duration,
+    /// Latency of every submission, failed ones included, sorted
+    pub latencies: Vec<Duration>,
+    /// Failed submissions by reason
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl LoadReport {
+    /// Nearest-rank percentile of the latencies, `p` in `0..=100`.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let rank = (p / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        Some(self.latencies[rank.clamp(1, self.latencies.len()) - 1])
+    }
+
+    pub fn failed(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        match self.sent {
+            0 => 0.0,
+            sent => self.failed() as f64 / sent as f64,
+        }
+    }
+
+    /// Submissions per second actually achieved.
+    pub fn throughput(&self) -> f64 {
+        self.sent as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} tasks in {:.1?} ({:.2}/s), {} failed ({:.2}%)",
+            self.sent,
+            self.elapsed,
+            self.throughput(),
+            self.failed(),
+            self.error_rate() * 100.0
+        )?;
+        for (name, p) in [("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("max", 100.0)] {
+            if let Some(latency) = self.percentile(p) {
+                writeln!(f, "  {name}: {latency:.1?}")?;
+            }
+        }
+        for (reason, count) in &self.errors {
+            writeln!(f, "  {count} x {reason}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        for bad in ["10", "m", "10d", "-1s"] {
+            assert!(parse_duration(bad).is_err(), "{bad}");
+        }
+        assert_eq!("chunk".parse(), Ok(Profile(ProofType::Chunk)));
+        assert!("undefined".parse::<Profile>().is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let config = LoadConfig {
+            rps: 200.0,
+            duration: Duration::from_millis(100),
+            profile: Profile(ProofType::Batch),
+        };
+        let report = run(&config, |task| {
+            assert_eq!(task.task_type, ProofType::Batch);
+            if task.id.ends_with('3') {
+                Err("boom".to_string())
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(report.sent, 20);
+        assert_eq!(report.latencies.len(), 20);
+        // loadtest-3 and loadtest-13
+        assert_eq!(report.errors.get("boom"), Some(&2));
+        assert!((report.error_rate() - 0.1).abs() < 1e-9);
+        assert!(report.elapsed >= Duration::from_millis(95));
+    }
+
+    #[test]
+    fn test_percentile() {
+        let report = LoadReport {
+            sent: 10,
+            latencies: (1..=10).map(Duration::from_millis).collect(),
+            ..Default::default()
+        };
+        assert_eq!(report.percentile(50.0), Some(Duration::from_millis(5)));
+        assert_eq!(report.percentile(99.0), Some(Duration::from_millis(10)));
+        assert_eq!(report.percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(LoadReport::default().percentile(50.0), None);
+    }
+}
@@ -3,7 +3,7 @@ use std::marker::PhantomData;
 use ff::PrimeField;
 use halo2_proofs::{
     circuit::{AssignedCell, Cell, Chip, Region, Value},
-    plonk::{Advice, Column, ConstraintSystem, Error, Expression, Fixed},
+    plonk::{Advice, Column, ConstraintSystem, Error, Expression, Fixed, VirtualCells},
     poly::Rotation,
 };
 
@@ -90,6 +90,152 @@ impl<F: PrimeField> From<&AssignedValue<F>> for WrapValue<F> {
     }
 }
 
+/// How the quintic term `q_5[i] * s[i]^5` of the main gate is constrained.
+///
+/// The direct form is a degree 6 gate. Backends or aggregators with a lower maximum degree
+/// can witness `s^2` (and `s^4`) in extra advice columns on the rows that use the quintic
+/// term, which caps the degree without adding rows.
+///
+/// There is no lookup form. A table of `(x, x^5)` only covers inputs from a small range,
+/// while the state cells range over the whole field, and `x^5` of a field element is not a
+/// combination of the fifth powers of its limbs, so no decomposition reaches a table either.
+/// The S-box costs no rows in any form: the narrow layout spends `T` rows per round because
+/// the gate outputs one state cell per row, and the [`RoundLayout`]s are what fit more hashes
+/// in a `k`; `cargo bench --bench circuit` proves with each of them at its smallest `k`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SboxDegree {
+    /// `q_5 * s^5` in one constraint
+    #[default]
+    Direct,
+    /// `s^2` witnessed in one extra column per state cell
+    Squared,
+    /// `s^2` and `s^4` witnessed in two extra columns per state cell
+    Staged,
+}
+
+impl SboxDegree {
+    /// The cheapest form whose gates fit in `max_degree`; none do below 3.
+    pub fn for_max_degree(max_degree: usize) -> Option<Self> {
+        match max_degree {
+            0..=2 => None,
+            3 => Some(Self::Staged),
+            4 | 5 => Some(Self::Squared),
+            _ => Some(Self::Direct),
+        }
+    }
+
+    /// Degree of the main gate and its staging constraints.
+    pub fn gate_degree(&self) -> usize {
+        match self {
+            Self::Direct => 6,
+            Self::Squared => 4,
+            Self::Staged => 3,
+        }
+    }
+
+    /// Witnessed powers per state cell: `s^2`, then `s^4`.
+    pub fn num_powers(&self) -> usize {
+        match self {
+            Self::Direct => 0,
+            Self::Squared => 1,
+            Self::Staged => 2,
+        }
+    }
+}
+
+/// How many rounds of the permutation a row of the chip holds.
+///
+/// The narrow layout outputs one state cell per row through `out`, so a round takes `T`
+/// rows. The wide layouts add a gate writing the whole next state into the state columns of
+/// the next row, one row per round, at the price of `2 * T * T + T + 1` fixed columns for
+/// its coefficients. Folding also computes two partial rounds per row: the first S-box
+/// output is witnessed in `out` by the main gate, which takes `T` more fixed columns.
+///
+/// Every layout absorbs the message in the `T` narrow rows of the first round, and the row
+/// counts of the other chips assume the narrow one, see
+/// [`crate::poseidon_circuit::PoseidonChip::num_rows_with_layout`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RoundLayout {
+    /// `T` rows per round
+    #[default]
+    Narrow,
+    /// One row per round
+    Wide,
+    /// One row per full round and per two partial rounds
+    Folded,
+}
+
+/// The knobs of [`MainGate::configure_with_layout`]: the maximum degree through the S-box
+/// form, and the rows against the columns through the round layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct GateLayout {
+    sbox: SboxDegree,
+    rounds: RoundLayout,
+}
+
+impl GateLayout {
+    /// The narrow layout with the cheapest S-box form whose gates fit in `max_degree`.
+    pub fn for_max_degree(max_degree: usize) -> Option<Self> {
+        SboxDegree::for_max_degree(max_degree).map(|sbox| Self::default().with_sbox(sbox))
+    }
+
+    pub fn with_sbox(mut self, sbox: SboxDegree) -> Self {
+        self.sbox = sbox;
+        self
+    }
+
+    pub fn with_rounds(mut self, rounds: RoundLayout) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    pub fn sbox(&self) -> SboxDegree {
+        self.sbox
+    }
+
+    pub fn rounds(&self) -> RoundLayout {
+        self.rounds
+    }
+
+    /// Degree of every gate; the wide gate has the degree of the main gate.
+    pub fn degree(&self) -> usize {
+        self.sbox.gate_degree()
+    }
+
+    /// Advice columns the gate takes for a state of width `t`.
+    pub fn advice_columns(&self, t: usize) -> usize {
+        let out_powers = match self.rounds {
+            RoundLayout::Folded => self.sbox.num_powers(),
+            _ => 0,
+        };
+        t + 2 + self.sbox.num_powers() * t + out_powers
+    }
+
+    /// Fixed columns the gate takes for a state of width `t`.
+    pub fn fixed_columns(&self, t: usize) -> usize {
+        let wide = match self.rounds {
+            RoundLayout::Narrow => 0,
+            RoundLayout::Wide => 2 * t * t + t + 1,
+            RoundLayout::Folded => 2 * t * t + 2 * t + 1,
+        };
+        2 * t + 4 + wide
+    }
+}
+
+/// Columns of the gate computing a round per row in the wide layouts:
+/// `q_next * s'[i] + sum_j(a[i][j] * s[j]^5 + b[i][j] * s[j]) + e[i] * out^5 + c[i] = 0`,
+/// where `s'` is the state on the next row.
+#[derive(Clone, Debug)]
+pub(crate) struct WideRoundConfig<const T: usize> {
+    pub(crate) a: [[Column<Fixed>; T]; T],
+    pub(crate) b: [[Column<Fixed>; T]; T],
+    pub(crate) c: [Column<Fixed>; T],
+    pub(crate) q_next: Column<Fixed>,
+    // folded layout only: the coefficients of out^5, and its witnessed powers
+    pub(crate) e: Option<[Column<Fixed>; T]>,
+    pub(crate) out_powers: Vec<Column<Advice>>,
+}
+
 #[derive(Clone, Debug)]
 pub struct MainGateConfig<const T: usize> {
     pub(crate) state: [Column<Advice>; T],
@@ -103,6 +249,66 @@ pub struct MainGateConfig<const T: usize> {
     pub(crate) q_i: Column<Fixed>,
     pub(crate) q_o: Column<Fixed>,
     pub(crate) rc: Column<Fixed>,
+    pub(crate) sbox: SboxDegree,
+    // witnessed powers of the state: s^2, then s^4, as many as `sbox` needs
+    pub(crate) powers: Vec<[Column<Advice>; T]>,
+    pub(crate) rounds: RoundLayout,
+    pub(crate) wide: Option<WideRoundConfig<T>>,
+}
+
+impl<const T: usize> MainGateConfig<T> {
+    /// Names every column in `region`, so MockProver failures read `state[1]` instead of
+    /// `Column('Advice', 1)`.
+    pub fn annotate_columns<F: PrimeField>(&self, region: &mut Region<'_, F>) {
+        for (i, col) in self.state.iter().enumerate() {
+            region.name_column(|| format!("state[{i}]"), *col);
+        }
+        for (i, (q_1, q_5)) in self.q_1.iter().zip(self.q_5.iter()).enumerate() {
+            region.name_column(|| format!("q_1[{i}]"), *q_1);
+            region.name_column(|| format!("q_5[{i}]"), *q_5);
+        }
+        region.name_column(|| "input", self.input);
+        region.name_column(|| "out", self.out);
+        region.name_column(|| "q_m", self.q_m);
+        region.name_column(|| "q_i", self.q_i);
+        region.name_column(|| "q_o", self.q_o);
+        region.name_column(|| "rc", self.rc);
+        for (k, powers) in self.powers.iter().enumerate() {
+            for (i, col) in powers.iter().enumerate() {
+                region.name_column(|| format!("state[{i}]^{}", 2 << k), *col);
+            }
+        }
+        if let Some(wide) = &self.wide {
+            for i in 0..T {
+                for j in 0..T {
+                    region.name_column(|| format!("a[{i}][{j}]"), wide.a[i][j]);
+                    region.name_column(|| format!("b[{i}][{j}]"), wide.b[i][j]);
+                }
+                region.name_column(|| format!("c[{i}]"), wide.c[i]);
+            }
+            region.name_column(|| "q_next", wide.q_next);
+            for (i, col) in wide.e.iter().flatten().enumerate() {
+                region.name_column(|| format!("e[{i}]"), *col);
+            }
+            for (k, col) in wide.out_powers.iter().enumerate() {
+                region.name_column(|| format!("out^{}", 2 << k), *col);
+            }
+        }
+    }
+
+    pub fn sbox(&self) -> SboxDegree {
+        self.sbox
+    }
+
+    pub fn rounds(&self) -> RoundLayout {
+        self.rounds
+    }
+
+    pub fn layout(&self) -> GateLayout {
+        GateLayout::default()
+            .with_sbox(self.sbox)
+            .with_rounds(self.rounds)
+    }
 }
 
 #[derive(Debug)]
@@ -136,8 +342,33 @@ impl<F: PrimeField, const T: usize> MainGate<F, T> {
         meta: &mut ConstraintSystem<F>,
         adv_cols: &mut (impl Iterator<Item = Column<Advice>> + Clone),
         fix_cols: &mut (impl Iterator<Item = Column<Fixed>> + Clone),
+    ) -> MainGateConfig<T> {
+        Self::configure_with_sbox(meta, adv_cols, fix_cols, SboxDegree::Direct)
+    }
+
+    /// Like [`MainGate::configure`], with the quintic term constrained as `sbox` says; the
+    /// staged forms take `sbox.num_powers() * T` more advice columns after the others.
+    pub fn configure_with_sbox(
+        meta: &mut ConstraintSystem<F>,
+        adv_cols: &mut (impl Iterator<Item = Column<Advice>> + Clone),
+        fix_cols: &mut (impl Iterator<Item = Column<Fixed>> + Clone),
+        sbox: SboxDegree,
+    ) -> MainGateConfig<T> {
+        let layout = GateLayout::default().with_sbox(sbox);
+        Self::configure_with_layout(meta, adv_cols, fix_cols, layout)
+    }
+
+    /// Like [`MainGate::configure_with_sbox`], with the rounds laid out as `layout` says.
+    /// The gate takes [`GateLayout::advice_columns`] and [`GateLayout::fixed_columns`]
+    /// columns, the ones of the wide layouts after the others.
+    pub fn configure_with_layout(
+        meta: &mut ConstraintSystem<F>,
+        adv_cols: &mut (impl Iterator<Item = Column<Advice>> + Clone),
+        fix_cols: &mut (impl Iterator<Item = Column<Fixed>> + Clone),
+        layout: GateLayout,
     ) -> MainGateConfig<T> {
         assert!(T >= 2);
+        let sbox = layout.sbox;
         let state = [0; T].map(|_| adv_cols.next().unwrap());
         let input = adv_cols.next().unwrap();
         let out = adv_cols.next().unwrap();
@@ -147,6 +378,9 @@ impl<F: PrimeField, const T: usize> MainGate<F, T> {
         let q_i = fix_cols.next().unwrap();
         let q_o = fix_cols.next().unwrap();
         let rc = fix_cols.next().unwrap();
+        let powers = (0..sbox.num_powers())
+            .map(|_| [0; T].map(|_| adv_cols.next().unwrap()))
+            .collect::<Vec<_>>();
 
         state.map(|s| {
             meta.enable_equality(s);
@@ -154,9 +388,41 @@ impl<F: PrimeField, const T: usize> MainGate<F, T> {
         meta.enable_equality(input);
         meta.enable_equality(out);
 
-        let pow_5 = |v: Expression<F>| {
-            let v2 = v.clone() * v.clone();
-            v2.clone() * v2 * v
+        let wide = (layout.rounds != RoundLayout::Narrow).then(|| WideRoundConfig {
+            a: [0; T].map(|_| [0; T].map(|_| fix_cols.next().unwrap())),
+            b: [0; T].map(|_| [0; T].map(|_| fix_cols.next().unwrap())),
+            c: [0; T].map(|_| fix_cols.next().unwrap()),
+            q_next: fix_cols.next().unwrap(),
+            e: (layout.rounds == RoundLayout::Folded)
+                .then(|| [0; T].map(|_| fix_cols.next().unwrap())),
+            out_powers: match layout.rounds {
+                RoundLayout::Folded => (0..sbox.num_powers())
+                    .map(|_| adv_cols.next().unwrap())
+                    .collect(),
+                _ => Vec::new(),
+            },
+        });
+
+        // `v^5` through the witnessed powers of `v` in `cols`
+        let pow_5_of = |meta: &mut VirtualCells<'_, F>,
+                        cols: &[Column<Advice>],
+                        v: Expression<F>| {
+            match cols {
+                [] => {
+                    let v2 = v.clone() * v.clone();
+                    v2.clone() * v2 * v
+                }
+                [sq] => {
+                    let v2 = meta.query_advice(*sq, Rotation::cur());
+                    v2.clone() * v2 * v
+                }
+                [_, quad] => meta.query_advice(*quad, Rotation::cur()) * v,
+                _ => unreachable!(),
+            }
+        };
+        let state_powers = |i: usize| powers.iter().map(|p| p[i]).collect::<Vec<_>>();
+        let pow_5 = |meta: &mut VirtualCells<'_, F>, i: usize, v: Expression<F>| {
+            pow_5_of(meta, &state_powers(i), v)
         };
 
         meta.create_gate("q_m*s[0]*s[1] + sum_i(q_1[i]*s[i]) + sum_i(q_5[i]*s[i]^5) + rc + q_i*input + q_o*out=0", |meta|{
@@ -170,14 +436,89 @@ impl<F: PrimeField, const T: usize> MainGate<F, T> {
             let q_o = meta.query_fixed(q_o, Rotation::cur());
             let rc = meta.query_fixed(rc, Rotation::cur());
             let init_term = q_m * state[0].clone() * state[1].clone() + q_i * input + rc + q_o * out;
-            let res = state.into_iter().zip(q_1).zip(q_5).map(|((s, q1), q5)| {
-                q1 * s.clone()  +  q5 * pow_5(s)
+            let res = state.into_iter().zip(q_1).zip(q_5).enumerate().map(|(i, ((s, q1), q5))| {
+                q1 * s.clone()  +  q5 * pow_5(meta, i, s)
             }).fold(init_term, |acc, item| {
                 acc + item
             });
             vec![res]
         });
 
+        if !powers.is_empty() {
+            meta.create_gate(
+                "q_5[i] * (s[i]^2 - s[i]*s[i]) = 0, q_5[i] * (s[i]^4 - s[i]^2*s[i]^2) = 0",
+                |meta| {
+                    let mut constraints = Vec::new();
+                    for i in 0..T {
+                        let q5 = meta.query_fixed(q_5[i], Rotation::cur());
+                        let mut prev = meta.query_advice(state[i], Rotation::cur());
+                        for power in powers.iter() {
+                            let cur = meta.query_advice(power[i], Rotation::cur());
+                            constraints.push(q5.clone() * (cur.clone() - prev.clone() * prev));
+                            prev = cur;
+                        }
+                    }
+                    constraints
+                },
+            );
+        }
+
+        if let Some(wide) = &wide {
+            meta.create_gate(
+                "q_next*s'[i] + sum_j(a[i][j]*s[j]^5 + b[i][j]*s[j]) + e[i]*out^5 + c[i] = 0",
+                |meta| {
+                    let cur = state.map(|s| meta.query_advice(s, Rotation::cur()));
+                    let next = state.map(|s| meta.query_advice(s, Rotation::next()));
+                    let quintic = (0..T)
+                        .map(|j| pow_5(meta, j, cur[j].clone()))
+                        .collect::<Vec<_>>();
+                    let q_next = meta.query_fixed(wide.q_next, Rotation::cur());
+                    let out_5 = wide.e.map(|_| {
+                        let out = meta.query_advice(out, Rotation::cur());
+                        pow_5_of(meta, &wide.out_powers, out)
+                    });
+                    (0..T)
+                        .map(|i| {
+                            let c = meta.query_fixed(wide.c[i], Rotation::cur());
+                            let mut res = q_next.clone() * next[i].clone() + c;
+                            for j in 0..T {
+                                let a = meta.query_fixed(wide.a[i][j], Rotation::cur());
+                                let b = meta.query_fixed(wide.b[i][j], Rotation::cur());
+                                res = res + a * quintic[j].clone() + b * cur[j].clone();
+                            }
+                            if let (Some(e), Some(out_5)) = (wide.e, out_5.clone()) {
+                                res = res + meta.query_fixed(e[i], Rotation::cur()) * out_5;
+                            }
+                            res
+                        })
+                        .collect::<Vec<_>>()
+                },
+            );
+
+            if !powers.is_empty() {
+                meta.create_gate(
+                    "q_next * (s[i]^2 - s[i]*s[i]) = 0, q_next * (out^2 - out*out) = 0",
+                    |meta| {
+                        let q_next = meta.query_fixed(wide.q_next, Rotation::cur());
+                        let mut constraints = Vec::new();
+                        let cells = (0..T)
+                            .map(|i| (state[i], state_powers(i)))
+                            .chain(wide.e.map(|_| (out, wide.out_powers.clone())));
+                        for (cell, cols) in cells {
+                            let mut prev = meta.query_advice(cell, Rotation::cur());
+                            for col in cols {
+                                let cur = meta.query_advice(col, Rotation::cur());
+                                constraints
+                                    .push(q_next.clone() * (cur.clone() - prev.clone() * prev));
+                                prev = cur;
+                            }
+                        }
+                        constraints
+                    },
+                );
+            }
+        }
+
         MainGateConfig {
             state,
             input,
@@ -188,7 +529,40 @@ impl<F: PrimeField, const T: usize> MainGate<F, T> {
             q_i,
             q_o,
             rc,
+            sbox,
+            powers,
+            rounds: layout.rounds,
+            wide,
+        }
+    }
+
+    /// Witnesses the powers of `value` that the quintic term of `state[i]` needs on this row.
+    pub fn assign_sbox_powers(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        i: usize,
+        value: Value<F>,
+    ) -> Result<(), Error> {
+        let mut power = value;
+        for columns in self.config.powers.iter() {
+            power = power * power;
+            ctx.assign_advice(|| "sbox power", columns[i], power)?;
+        }
+        Ok(())
+    }
+
+    /// Witnesses the powers of `value` that the quintic term of `out` needs on a folded row.
+    pub fn assign_out_powers(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        value: Value<F>,
+    ) -> Result<(), Error> {
+        let mut power = value;
+        for column in self.config.wide.iter().flat_map(|wide| &wide.out_powers) {
+            power = power * power;
+            ctx.assign_advice(|| "out power", *column, power)?;
         }
+        Ok(())
     }
 
     // helper function for some usecases: no copy constraints, only return out cell
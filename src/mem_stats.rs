@@ -0,0 +1,153 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::stage::Stage;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// A [`System`] wrapper counting live heap bytes, the heap high-water mark and allocations.
+///
+/// Only has an effect once installed as the `#[global_allocator]`, which the snarkify binary
+/// does behind the `mem-stats` feature.
+pub struct TrackingAllocator;
+
+fn record_alloc(size: usize) {
+    let current = CURRENT.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(current, Ordering::Relaxed);
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn record_dealloc(size: usize) {
+    CURRENT.fetch_sub(size, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        record_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            if new_size >= layout.size() {
+                record_alloc(new_size - layout.size());
+            } else {
+                record_dealloc(layout.size() - new_size);
+            }
+        }
+        new_ptr
+    }
+}
+
+/// Heap usage of one stage of a proof.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PhaseMemory {
+    pub phase: Stage,
+    /// Heap high-water mark reached during the phase, above what was live when it started
+    pub peak_heap_bytes: usize,
+    pub allocations: u64,
+}
+
+/// Memory diagnostics attached to a proof.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// Process resident set high-water mark, `None` where `/proc` is unavailable
+    pub peak_rss_bytes: Option<u64>,
+    /// Empty unless the [`TrackingAllocator`] is installed
+    pub phases: Vec<PhaseMemory>,
+}
+
+/// Collects a [`MemoryReport`] for a single proof.
+///
+/// The counters are process wide, so phases of proofs running concurrently are attributed
+/// to each other; the numbers are exact for a worker proving one task at a time.
+#[derive(Debug, Default)]
+pub struct MemoryProfiler {
+    report: MemoryReport,
+}
+
+impl MemoryProfiler {
+    /// Starts a profile, resetting the kernel's RSS high-water mark where supported.
+    pub fn new() -> Self {
+        // writing 5 to clear_refs resets VmHWM (Linux >= 4.0); failure only widens the window
+        let _ = std::fs::write("/proc/self/clear_refs", "5");
+        Self::default()
+    }
+
+    pub fn measure<T>(&mut self, phase: Stage, f: impl FnOnce() -> T) -> T {
+        let start = CURRENT.load(Ordering::Relaxed);
+        PEAK.store(start, Ordering::Relaxed);
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+
+        let out = f();
+
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        if allocations > 0 {
+            self.report.phases.push(PhaseMemory {
+                phase,
+                peak_heap_bytes: PEAK.load(Ordering::Relaxed).saturating_sub(start),
+                allocations,
+            });
+        }
+        out
+    }
+
+    pub fn finish(mut self) -> MemoryReport {
+        self.report.peak_rss_bytes = peak_rss_bytes();
+        self.report
+    }
+}
+
+/// Reads `VmHWM` from `/proc/self/status`.
+pub fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_vm_hwm(&status)
+}
+
+fn parse_vm_hwm(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_hwm() {
+        let status =
+            "Name:\tsnarkify\nVmPeak:\t  20000 kB\nVmHWM:\t    1536 kB\nVmRSS:\t 1024 kB\n";
+        assert_eq!(parse_vm_hwm(status), Some(1536 * 1024));
+        assert_eq!(parse_vm_hwm("Name:\tsnarkify\n"), None);
+    }
+}
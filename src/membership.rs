@@ -0,0 +1,410 @@
+//! Proof that a private leaf belongs to a Poseidon Merkle tree with a public root.
+//!
+//! The prover knows a `secret` whose commitment `leaf = Poseidon(secret)` sits at some
+//! private index of the tree. The public inputs are the root and the nullifier
+//! `Poseidon(secret, index)`, which is the same every time the leaf is proven, so a
+//! verifier can reject repeated use without learning which leaf was used.
+//!
+//! [`MembershipChip`] is the gadget of the circuit, over any spec, for shielded pools and
+//! other circuits that check membership and derive nullifiers among other constraints.
+use std::{fmt, marker::PhantomData};
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner, Value},
+    plonk::{Circuit, Column, ConstraintSystem, Error, Instance},
+};
+use poseidon::Spec;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    field_encoding::{
+        parse_field, parse_fields, to_canonical, FieldParseError, FieldParseErrorKind,
+    },
+    instance_layout::{Layout, RowMajor},
+    main_gate::{AssignedValue, MainGate, MainGateConfig, RegionCtx},
+    merkle::{MerkleChip, MerklePath, MerkleTree},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash,
+    specs::BN256_T4_R3,
+};
+
+const T: usize = BN256_T4_R3.width;
+const RATE: usize = BN256_T4_R3.rate;
+const R_F: usize = BN256_T4_R3.r_f;
+const R_P: usize = BN256_T4_R3.r_p;
+
+pub(crate) fn spec<F: PrimeField + FromUniformBytes<64>>() -> Spec<F, T, RATE> {
+    Spec::new(R_F, R_P)
+}
+
+/// The tree leaf committing to `secret`.
+pub fn leaf<F: PrimeField + FromUniformBytes<64>>(secret: F) -> F {
+    hash(&spec(), &[secret])
+}
+
+pub fn nullifier<F: PrimeField + FromUniformBytes<64>>(secret: F, index: u64) -> F {
+    hash(&spec(), &[secret, F::from(index)])
+}
+
+/// Builds the tree the service proves membership in.
+pub fn tree<F: PrimeField + FromUniformBytes<64>>(leaves: Vec<F>, depth: usize) -> MerkleTree<F> {
+    MerkleTree::new(&spec(), leaves, depth)
+}
+
+/// Rows of a [`MembershipCircuit`] for a tree of `depth` levels.
+pub fn num_rows<F: PrimeField + FromUniformBytes<64>>(depth: usize) -> usize {
+    MembershipChip::num_rows(&spec::<F>(), depth)
+}
+
+/// The cells of a membership proof by [`MembershipChip::assign`]; `root` and `nullifier` are
+/// for the caller to expose.
+#[derive(Clone, Debug)]
+pub struct AssignedMembership<F: PrimeField> {
+    pub secret: AssignedValue<F>,
+    pub index: AssignedValue<F>,
+    pub leaf: AssignedValue<F>,
+    pub root: AssignedValue<F>,
+    pub nullifier: AssignedValue<F>,
+}
+
+/// Checks that the leaf `Poseidon(secret)` sits at its index of a tree and derives the
+/// nullifier `Poseidon(secret, index)`, with the hashes of `spec`.
+pub struct MembershipChip<F: PrimeField, const T: usize, const RATE: usize> {
+    config: MainGateConfig<T>,
+    spec: Spec<F, T, RATE>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    MembershipChip<F, T, RATE>
+{
+    pub fn new(config: MainGateConfig<T>, spec: Spec<F, T, RATE>) -> Self {
+        Self { config, spec }
+    }
+
+    /// Rows used by [`MembershipChip::assign`] for a tree of `depth` levels.
+    pub fn num_rows(spec: &Spec<F, T, RATE>, depth: usize) -> usize {
+        PoseidonChip::num_rows(spec, 1)
+            + 1
+            + MerkleChip::num_rows(spec, depth)
+            + PoseidonChip::num_rows(spec, 2)
+    }
+
+    /// Assigns `secret` and the index of `path`, and constrains the root and the nullifier.
+    pub fn assign(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        secret: F,
+        path: &MerklePath<F>,
+    ) -> Result<AssignedMembership<F>, Error> {
+        let index_val = F::from(path.index);
+        let mut pchip = PoseidonChip::new(self.config.clone(), self.spec.clone());
+        pchip.update(vec![secret]);
+        let (secret_cells, leaf) = pchip.squeeze_with_inputs(ctx)?;
+
+        // a free cell: every selector of the main gate is zero on this row
+        let index = ctx.assign_advice(
+            || "membership: index",
+            self.config.input,
+            Value::known(index_val),
+        )?;
+        ctx.next();
+
+        let merkle = MerkleChip::new(self.config.clone(), self.spec.clone());
+        let leaf_val = hash(&self.spec, &[secret]);
+        let root = merkle.compute_root(ctx, &leaf, leaf_val, &index, path)?;
+
+        let mut pchip = PoseidonChip::new(self.config.clone(), self.spec.clone());
+        pchip.update(vec![secret, index_val]);
+        let (inputs, nullifier) = pchip.squeeze_with_inputs(ctx)?;
+        ctx.constrain_equal(inputs[0].cell(), secret_cells[0].cell())?;
+        ctx.constrain_equal(inputs[1].cell(), index.cell())?;
+        Ok(AssignedMembership {
+            secret: secret_cells[0].clone(),
+            index,
+            leaf,
+            root,
+            nullifier,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MembershipConfig {
+    pconfig: MainGateConfig<T>,
+    instance: Vec<Column<Instance>>,
+}
+
+/// Proves membership, with the root and nullifier placed in the instance columns as `L`
+/// lays them out.
+pub struct MembershipCircuit<F: PrimeField, L: Layout = RowMajor> {
+    secret: F,
+    path: MerklePath<F>,
+    _layout: PhantomData<L>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> MembershipCircuit<F> {
+    pub fn new(secret: F, path: MerklePath<F>) -> Self {
+        Self::with_layout(secret, path)
+    }
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, L: Layout> MembershipCircuit<F, L> {
+    pub fn with_layout(secret: F, path: MerklePath<F>) -> Self {
+        Self {
+            secret,
+            path,
+            _layout: PhantomData,
+        }
+    }
+
+    /// `[root, nullifier]`, laid out as `L`
+    pub fn instance(&self) -> Vec<Vec<F>> {
+        L::LAYOUT.columns(&[
+            self.path.root(&spec(), leaf(self.secret)),
+            nullifier(self.secret, self.path.index),
+        ])
+    }
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, L: Layout> Circuit<F> for MembershipCircuit<F, L> {
+    type Config = MembershipConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self::with_layout(
+            F::ZERO,
+            MerklePath {
+                index: 0,
+                siblings: vec![F::ZERO; self.path.siblings.len()],
+            },
+        )
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        let instance = L::LAYOUT.configure(meta, 2);
+        let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+        let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+        let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+        Self::Config { pconfig, instance }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        let chip = MembershipChip::new(config.pconfig, spec::<F>());
+        let membership = layouter.assign_region(
+            || "membership",
+            |region| chip.assign(&mut RegionCtx::new(region, 0), self.secret, &self.path),
+        )?;
+        let nullifier = membership.nullifier.cell();
+        L::LAYOUT.constrain(&mut layouter, &config.instance, 0, membership.root.cell())?;
+        L::LAYOUT.constrain(&mut layouter, &config.instance, 1, nullifier)?;
+        Ok(())
+    }
+}
+
+/// Everything a client needs to request a membership proof, as produced from a leaf file.
+///
+/// Field elements are in the canonical encoding of [`crate::field_encoding`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MembershipWitness {
+    pub secret: String,
+    pub index: u64,
+    pub siblings: Vec<String>,
+    pub root: String,
+    pub nullifier: String,
+}
+
+#[derive(Debug)]
+pub enum MembershipError {
+    InvalidLeaf(FieldParseError),
+    InvalidSecret(FieldParseErrorKind),
+    TooManyLeaves {
+        leaves: usize,
+        depth: usize,
+    },
+    /// The commitment of the secret is not among the leaves
+    LeafNotFound,
+}
+
+impl fmt::Display for MembershipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLeaf(e) => write!(f, "invalid leaf: {e}"),
+            Self::InvalidSecret(kind) => write!(f, "invalid secret: {kind}"),
+            Self::TooManyLeaves { leaves, depth } => {
+                write!(f, "{leaves} leaves do not fit into a tree of depth {depth}")
+            }
+            Self::LeafNotFound => write!(f, "the commitment of the secret is not a leaf"),
+        }
+    }
+}
+
+impl std::error::Error for MembershipError {}
+
+impl MembershipWitness {
+    /// Finds the leaf of `secret` among `leaves` (one field element each) and builds its
+    /// witness in a tree of `depth` levels.
+    pub fn from_leaves<F: PrimeField + FromUniformBytes<64>, S: AsRef<str>>(
+        leaves: &[S],
+        secret: &str,
+        depth: usize,
+    ) -> Result<Self, MembershipError> {
+        let leaves = parse_fields::<F, _>(leaves).map_err(MembershipError::InvalidLeaf)?;
+        let secret = parse_field::<F>(secret).map_err(MembershipError::InvalidSecret)?;
+        if depth >= 64 || leaves.len() > 1 << depth {
+            return Err(MembershipError::TooManyLeaves {
+                leaves: leaves.len(),
+                depth,
+            });
+        }
+        let index = leaves
+            .iter()
+            .position(|l| *l == leaf(secret))
+            .ok_or(MembershipError::LeafNotFound)? as u64;
+        let tree = tree(leaves, depth);
+        let path = tree.path(index);
+        Ok(Self {
+            secret: to_canonical(&secret),
+            index,
+            siblings: path.siblings.iter().map(to_canonical).collect(),
+            root: to_canonical(&tree.root()),
+            nullifier: to_canonical(&nullifier(secret, index)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::dev::MockProver;
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::instance_layout::ColumnMajor;
+
+    const K: u32 = 11;
+    const DEPTH: usize = 3;
+
+    #[test]
+    fn test_membership() {
+        assert!(num_rows::<Fr>(DEPTH) + 6 <= 1 << K);
+        let secrets = (10..15).map(Fr::from).collect::<Vec<_>>();
+        let tree = tree(secrets.iter().copied().map(leaf).collect(), DEPTH);
+        for index in [0, 3, 4] {
+            let circuit = MembershipCircuit::new(secrets[index as usize], tree.path(index));
+            let instance = circuit.instance();
+            assert_eq!(instance[0][0], tree.root());
+            let prover = MockProver::run(K, &circuit, instance).unwrap();
+            assert_eq!(prover.verify(), Ok(()));
+        }
+
+        // a secret whose leaf is not in the tree
+        let circuit = MembershipCircuit::new(Fr::from(99), tree.path(1));
+        let instance = vec![vec![tree.root(), nullifier(Fr::from(99), 1)]];
+        let prover = MockProver::run(K, &circuit, instance).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_column_major() {
+        let secrets = (10..15).map(Fr::from).collect::<Vec<_>>();
+        let tree = tree(secrets.iter().copied().map(leaf).collect(), DEPTH);
+        let circuit = MembershipCircuit::<Fr, ColumnMajor>::with_layout(secrets[2], tree.path(2));
+        let instance = circuit.instance();
+        assert_eq!(
+            instance,
+            vec![vec![tree.root()], vec![nullifier(secrets[2], 2)]]
+        );
+        let prover = MockProver::run(K, &circuit, instance).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // the row-major instance does not fit the column-major circuit
+        let row_major = MembershipCircuit::new(secrets[2], tree.path(2)).instance();
+        assert!(MockProver::run(K, &circuit, row_major).is_err());
+    }
+
+    /// A shielded pool spend over width 3: exposes `[root, nullifier]` of the chip
+    struct SpendCircuit {
+        secret: Fr,
+        path: MerklePath<Fr>,
+    }
+
+    impl Circuit<Fr> for SpendCircuit {
+        type Config = (MainGateConfig<3>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                secret: Fr::ZERO,
+                path: MerklePath {
+                    index: 0,
+                    siblings: vec![Fr::ZERO; self.path.siblings.len()],
+                },
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); 3 + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * 3 + 4].map(|_| meta.fixed_column()).into_iter();
+            let config = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            (config, instance)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = MembershipChip::new(config, Spec::<Fr, 3, 2>::new(8, 57));
+            let membership = layouter.assign_region(
+                || "spend",
+                |region| chip.assign(&mut RegionCtx::new(region, 0), self.secret, &self.path),
+            )?;
+            layouter.constrain_instance(membership.root.cell(), instance, 0)?;
+            layouter.constrain_instance(membership.nullifier.cell(), instance, 1)
+        }
+    }
+
+    #[test]
+    fn test_chip_with_other_spec() {
+        let spec = Spec::<Fr, 3, 2>::new(8, 57);
+        // width 3 takes two permutations per node
+        let k = K + 1;
+        assert!(MembershipChip::num_rows(&spec, DEPTH) + 6 <= 1 << k);
+        let secrets = (10..15).map(Fr::from).collect::<Vec<_>>();
+        let leaves = secrets.iter().map(|s| hash(&spec, &[*s])).collect();
+        let tree = MerkleTree::new(&spec, leaves, DEPTH);
+        let circuit = SpendCircuit {
+            secret: secrets[3],
+            path: tree.path(3),
+        };
+        let nullifier = hash(&spec, &[secrets[3], Fr::from(3)]);
+        let prover = MockProver::run(k, &circuit, vec![vec![tree.root(), nullifier]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // the nullifier of another index does not verify
+        let other = hash(&spec, &[secrets[3], Fr::from(2)]);
+        let prover = MockProver::run(k, &circuit, vec![vec![tree.root(), other]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_witness_from_leaves() {
+        let leaves = (1..4)
+            .map(|s| to_canonical(&leaf(Fr::from(s))))
+            .collect::<Vec<_>>();
+        let witness = MembershipWitness::from_leaves::<Fr, _>(&leaves, "2", DEPTH).unwrap();
+        assert_eq!(witness.index, 1);
+        assert_eq!(witness.siblings.len(), DEPTH);
+        assert_eq!(witness.nullifier, to_canonical(&nullifier(Fr::from(2), 1)));
+        assert!(matches!(
+            MembershipWitness::from_leaves::<Fr, _>(&leaves, "7", DEPTH),
+            Err(MembershipError::LeafNotFound)
+        ));
+    }
+}
@@ -0,0 +1,675 @@
+//! Poseidon Merkle trees of any arity, natively and in-circuit.
+//!
+//! A [`MerkleTree`] builds roots and [`MerklePath`]s, and a [`MerkleChip`] of the same arity
+//! recomputes the root of a path from assigned leaf and index cells, so a circuit proves
+//! membership by constraining that root. Binary trees are the default; the layout of their
+//! chip is the one of the membership and vector commitment circuits.
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::{
+    circuit::{Chip, Value},
+    plonk::Error,
+};
+use poseidon::Spec;
+
+use crate::{
+    main_gate::{AssignedValue, MainGate, MainGateConfig, RegionCtx},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash,
+    range_chip::RangeChip,
+};
+
+/// A Poseidon Merkle tree of fixed depth and arity; missing leaves are zero.
+///
+/// A parent is `Poseidon(c_0, .., c_{arity - 1})` of its children, `Poseidon(left, right)`
+/// in a binary tree. Only the non-empty part of every level is stored, the rest is covered
+/// by the roots of empty subtrees.
+#[derive(Clone, Debug)]
+pub struct MerkleTree<F> {
+    arity: usize,
+    levels: Vec<Vec<F>>,
+    empty: Vec<F>,
+}
+
+/// Authentication path of a leaf, siblings from the leaf level up.
+///
+/// Every level has `arity - 1` siblings, the other children of the parent in order; the
+/// position of the leaf among them is its index in base `arity`, least significant digit
+/// first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MerklePath<F> {
+    pub index: u64,
+    pub siblings: Vec<F>,
+}
+
+/// Checks that `arity^depth` leaves can be indexed by a `u64`, and returns that number.
+fn capacity(arity: usize, depth: usize) -> u64 {
+    assert!(arity >= 2, "a Merkle tree has an arity of 2 or more");
+    u32::try_from(depth)
+        .ok()
+        .and_then(|depth| (arity as u64).checked_pow(depth))
+        .filter(|capacity| *capacity <= 1 << 63)
+        .unwrap_or_else(|| panic!("{arity}^{depth} leaves do not fit into 64-bit indexes"))
+}
+
+/// Position of the node on the path of leaf `index` among its siblings, at every level.
+fn positions(index: u64, arity: usize, depth: usize) -> impl Iterator<Item = usize> {
+    let arity = arity as u64;
+    (0..depth).scan(index, move |node, _| {
+        let position = *node % arity;
+        *node /= arity;
+        Some(position as usize)
+    })
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> MerkleTree<F> {
+    /// Builds a binary tree of `depth` levels; panics if `leaves` does not fit.
+    pub fn new<const T: usize, const RATE: usize>(
+        spec: &Spec<F, T, RATE>,
+        leaves: Vec<F>,
+        depth: usize,
+    ) -> Self {
+        Self::with_arity(spec, leaves, depth, 2)
+    }
+
+    /// Builds a tree of `depth` levels whose nodes have `arity` children; panics if
+    /// `leaves` does not fit.
+    pub fn with_arity<const T: usize, const RATE: usize>(
+        spec: &Spec<F, T, RATE>,
+        leaves: Vec<F>,
+        depth: usize,
+        arity: usize,
+    ) -> Self {
+        assert!(leaves.len() as u64 <= capacity(arity, depth));
+        let mut empty = vec![F::ZERO];
+        for i in 0..depth {
+            empty.push(hash(spec, &vec![empty[i]; arity]));
+        }
+        let mut levels = vec![leaves];
+        for i in 0..depth {
+            let next = levels[i]
+                .chunks(arity)
+                .map(|children| {
+                    let mut children = children.to_vec();
+                    children.resize(arity, empty[i]);
+                    hash(spec, &children)
+                })
+                .collect();
+            levels.push(next);
+        }
+        Self {
+            arity,
+            levels,
+            empty,
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    pub fn leaves(&self) -> &[F] {
+        &self.levels[0]
+    }
+
+    pub fn root(&self) -> F {
+        self.levels[self.depth()]
+            .first()
+            .copied()
+            .unwrap_or(self.empty[self.depth()])
+    }
+
+    /// The path of the leaf at `index`, which must be below the number of leaves.
+    pub fn path(&self, index: u64) -> MerklePath<F> {
+        assert!((index as usize) < self.leaves().len());
+        let mut siblings = Vec::with_capacity(self.depth() * (self.arity - 1));
+        let mut node = index as usize;
+        for level in 0..self.depth() {
+            let first = node - node % self.arity;
+            siblings.extend((first..first + self.arity).filter(|i| *i != node).map(|i| {
+                self.levels[level]
+                    .get(i)
+                    .copied()
+                    .unwrap_or(self.empty[level])
+            }));
+            node /= self.arity;
+        }
+        MerklePath { index, siblings }
+    }
+
+    /// Sets the leaf at `index`, which may be past the last leaf, and updates the nodes above
+    /// it; returns its path, which the update leaves unchanged.
+    pub fn set<const T: usize, const RATE: usize>(
+        &mut self,
+        spec: &Spec<F, T, RATE>,
+        index: u64,
+        leaf: F,
+    ) -> MerklePath<F> {
+        assert!(index < capacity(self.arity, self.depth()));
+        let mut node = index as usize;
+        if self.levels[0].len() <= node {
+            self.levels[0].resize(node + 1, F::ZERO);
+        }
+        let path = self.path(index);
+        self.levels[0][node] = leaf;
+        for level in 0..self.depth() {
+            let first = node - node % self.arity;
+            let children = (first..first + self.arity)
+                .map(|i| {
+                    self.levels[level]
+                        .get(i)
+                        .copied()
+                        .unwrap_or(self.empty[level])
+                })
+                .collect::<Vec<_>>();
+            node /= self.arity;
+            let empty = self.empty[level + 1];
+            let parents = &mut self.levels[level + 1];
+            if parents.len() <= node {
+                parents.resize(node + 1, empty);
+            }
+            parents[node] = hash(spec, &children);
+        }
+        path
+    }
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> MerklePath<F> {
+    /// The root this path of a binary tree leads to from `leaf`.
+    pub fn root<const T: usize, const RATE: usize>(&self, spec: &Spec<F, T, RATE>, leaf: F) -> F {
+        self.root_with_arity(spec, leaf, 2)
+    }
+
+    /// The root this path of a tree of `arity` leads to from `leaf`.
+    pub fn root_with_arity<const T: usize, const RATE: usize>(
+        &self,
+        spec: &Spec<F, T, RATE>,
+        leaf: F,
+        arity: usize,
+    ) -> F {
+        let depth = self.depth(arity);
+        self.siblings
+            .chunks(arity - 1)
+            .zip(positions(self.index, arity, depth))
+            .fold(leaf, |cur, (siblings, position)| {
+                hash(spec, &ordered_children(cur, siblings, position))
+            })
+    }
+
+    /// Levels of the path in a tree of `arity`.
+    pub fn depth(&self, arity: usize) -> usize {
+        assert!(arity >= 2 && self.siblings.len() % (arity - 1) == 0);
+        self.siblings.len() / (arity - 1)
+    }
+}
+
+/// The children of a parent: `siblings` with `cur` inserted at `position`.
+fn ordered_children<F: Copy>(cur: F, siblings: &[F], position: usize) -> Vec<F> {
+    let mut children = siblings.to_vec();
+    children.insert(position, cur);
+    children
+}
+
+/// Recomputes a Merkle root in-circuit from a leaf cell, an index cell and a path.
+///
+/// Every level orders the current node among its siblings with a chain of `arity - 1`
+/// conditional swaps: the node starts in front of them, and swap `j` moves it past sibling
+/// `j` if its bit `t_j`, meaning "the position is above `j`", is set. A swap of `(x, y)`
+/// computes `left = x + t * (y - x)` and `right = x + y - left`. The ordered children are
+/// then hashed with a [`PoseidonChip`].
+///
+/// In a binary tree the bits of a level are the bits of the index, decomposed with a
+/// [`RangeChip`] which also bounds the index by the depth. Otherwise the bits of a level
+/// are boolean, non-increasing (`t_{j+1} * t_j - t_{j+1} = 0`) and sum to the position,
+/// and the positions of all levels, as digits in base `arity`, must sum to the index.
+pub struct MerkleChip<F: PrimeField, const T: usize, const RATE: usize> {
+    main_gate: MainGate<F, T>,
+    spec: Spec<F, T, RATE>,
+    arity: usize,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    MerkleChip<F, T, RATE>
+{
+    /// A chip for binary trees.
+    pub fn new(config: MainGateConfig<T>, spec: Spec<F, T, RATE>) -> Self {
+        assert!(T >= 3);
+        Self {
+            main_gate: MainGate::new(config),
+            spec,
+            arity: 2,
+        }
+    }
+
+    /// Checks paths of trees of `arity` instead.
+    pub fn with_arity(mut self, arity: usize) -> Self {
+        assert!(arity >= 2, "a Merkle tree has an arity of 2 or more");
+        self.arity = arity;
+        self
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Number of rows [`MerkleChip::compute_root`] uses for a binary path of `depth` levels.
+    pub fn num_rows(spec: &Spec<F, T, RATE>, depth: usize) -> usize {
+        Self::num_rows_with_arity(spec, depth, 2)
+    }
+
+    /// Number of rows [`MerkleChip::compute_root`] uses for a path of `depth` levels in a
+    /// tree of `arity`.
+    pub fn num_rows_with_arity(spec: &Spec<F, T, RATE>, depth: usize, arity: usize) -> usize {
+        // two rows per bit to decompose the index, one per pair of bits to order them, and
+        // three per swap
+        let swaps = arity - 1;
+        depth * (5 * swaps + (swaps - 1) + PoseidonChip::num_rows(spec, arity))
+    }
+
+    /// Returns the root cell; `leaf_value` is the value of `leaf`, needed to hash natively.
+    pub fn compute_root(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        leaf: &AssignedValue<F>,
+        leaf_value: F,
+        index: &AssignedValue<F>,
+        path: &MerklePath<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.compute_root_along(ctx, leaf, leaf_value, index, path, None)
+            .map(|(root, _)| root)
+    }
+
+    /// Like [`MerkleChip::compute_root`], also returning the sibling cells in path order.
+    ///
+    /// With `siblings`, the cells of an earlier call, the siblings are constrained to them:
+    /// both roots then come from the same tree with only the leaf at `index` changed.
+    pub fn compute_root_along(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        leaf: &AssignedValue<F>,
+        leaf_value: F,
+        index: &AssignedValue<F>,
+        path: &MerklePath<F>,
+        siblings: Option<&[AssignedValue<F>]>,
+    ) -> Result<(AssignedValue<F>, Vec<AssignedValue<F>>), Error> {
+        if let Some(siblings) = siblings {
+            assert_eq!(siblings.len(), path.siblings.len());
+        }
+        let depth = path.depth(self.arity);
+        let bits = if self.arity == 2 {
+            let range_chip = RangeChip::<F, T>::new(self.main_gate.config().clone());
+            let bits = range_chip.range_check(ctx, index, depth)?;
+            bits.into_iter().map(|bit| vec![bit]).collect()
+        } else {
+            self.decompose(ctx, index, path.index, depth)?
+        };
+
+        let mut cur = leaf.clone();
+        let mut cur_val = leaf_value;
+        let mut sibling_cells = Vec::with_capacity(path.siblings.len());
+        let levels = path
+            .siblings
+            .chunks(self.arity - 1)
+            .zip(positions(path.index, self.arity, depth));
+        for ((sibling_vals, position), bits) in levels.zip(bits) {
+            // the current node is last until it stops moving, and is then followed by the
+            // siblings it did not pass
+            let mut children = vec![cur.clone()];
+            for (j, (sibling_val, bit)) in sibling_vals.iter().zip(bits).enumerate() {
+                let x = children.pop().expect("a swap leaves two children");
+                let x_val = if j <= position {
+                    cur_val
+                } else {
+                    sibling_vals[j - 1]
+                };
+                let given = siblings.map(|siblings| &siblings[sibling_cells.len()]);
+                let (left, right, y) =
+                    self.swap(ctx, &x, x_val, *sibling_val, given, &bit, j < position)?;
+                children.extend([left, right]);
+                sibling_cells.push(y);
+            }
+
+            let child_vals = ordered_children(cur_val, sibling_vals, position);
+            let mut pchip = PoseidonChip::new(self.main_gate.config().clone(), self.spec.clone());
+            pchip.update(child_vals.clone());
+            let (inputs, parent) = pchip.squeeze_with_inputs(ctx)?;
+            for (input, child) in inputs.iter().zip(&children) {
+                ctx.constrain_equal(input.cell(), child.cell())?;
+            }
+
+            cur = parent;
+            cur_val = hash(&self.spec, &child_vals);
+        }
+        Ok((cur, sibling_cells))
+    }
+
+    /// Orders `(x, y)` by `bit`, which is `swapped`: `y` is a new cell for `y_val`, equal to
+    /// `given` if any. Returns `(left, right, y)`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn swap(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        x: &AssignedValue<F>,
+        x_val: F,
+        y_val: F,
+        given: Option<&AssignedValue<F>>,
+        bit: &AssignedValue<F>,
+        swapped: bool,
+    ) -> Result<(AssignedValue<F>, AssignedValue<F>, AssignedValue<F>), Error> {
+        let config = self.main_gate.config();
+        let (left_val, right_val) = if swapped {
+            (y_val, x_val)
+        } else {
+            (x_val, y_val)
+        };
+
+        // y - x - d = 0
+        let y = ctx.assign_advice(|| "merkle: sibling", config.state[0], Value::known(y_val))?;
+        if let Some(given) = given {
+            ctx.constrain_equal(y.cell(), given.cell())?;
+        }
+        let c = ctx.assign_advice(|| "merkle: cur", config.state[1], x.value().copied())?;
+        ctx.constrain_equal(c.cell(), x.cell())?;
+        ctx.assign_fixed(|| "merkle: q_1", config.q_1[0], F::ONE)?;
+        ctx.assign_fixed(|| "merkle: q_1", config.q_1[1], -F::ONE)?;
+        ctx.assign_fixed(|| "merkle: q_o", config.q_o, -F::ONE)?;
+        let d = ctx.assign_advice(
+            || "merkle: sibling - cur",
+            config.out,
+            Value::known(y_val - x_val),
+        )?;
+        ctx.next();
+
+        // b * d + x - left = 0
+        let b = ctx.assign_advice(|| "merkle: bit", config.state[0], bit.value().copied())?;
+        ctx.constrain_equal(b.cell(), bit.cell())?;
+        let dc = ctx.assign_advice(
+            || "merkle: sibling - cur",
+            config.state[1],
+            d.value().copied(),
+        )?;
+        ctx.constrain_equal(dc.cell(), d.cell())?;
+        let c = ctx.assign_advice(|| "merkle: cur", config.state[2], x.value().copied())?;
+        ctx.constrain_equal(c.cell(), x.cell())?;
+        ctx.assign_fixed(|| "merkle: q_m", config.q_m, F::ONE)?;
+        ctx.assign_fixed(|| "merkle: q_1", config.q_1[2], F::ONE)?;
+        ctx.assign_fixed(|| "merkle: q_o", config.q_o, -F::ONE)?;
+        let left = ctx.assign_advice(|| "merkle: left", config.out, Value::known(left_val))?;
+        ctx.next();
+
+        // y + x - left - right = 0
+        let s = ctx.assign_advice(|| "merkle: sibling", config.state[0], Value::known(y_val))?;
+        ctx.constrain_equal(s.cell(), y.cell())?;
+        let c = ctx.assign_advice(|| "merkle: cur", config.state[1], x.value().copied())?;
+        ctx.constrain_equal(c.cell(), x.cell())?;
+        let l = ctx.assign_advice(|| "merkle: left", config.state[2], Value::known(left_val))?;
+        ctx.constrain_equal(l.cell(), left.cell())?;
+        ctx.assign_fixed(|| "merkle: q_1", config.q_1[0], F::ONE)?;
+        ctx.assign_fixed(|| "merkle: q_1", config.q_1[1], F::ONE)?;
+        ctx.assign_fixed(|| "merkle: q_1", config.q_1[2], -F::ONE)?;
+        ctx.assign_fixed(|| "merkle: q_o", config.q_o, -F::ONE)?;
+        let right = ctx.assign_advice(|| "merkle: right", config.out, Value::known(right_val))?;
+        ctx.next();
+
+        Ok((left, right, y))
+    }
+
+    /// Decomposes `index` into the swap bits of every level of a tree of more than two
+    /// children per node.
+    fn decompose(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        index: &AssignedValue<F>,
+        index_val: u64,
+        depth: usize,
+    ) -> Result<Vec<Vec<AssignedValue<F>>>, Error> {
+        // keeps the sum of the digits below the modulus
+        capacity(self.arity, depth);
+        let config = self.main_gate.config();
+
+        let mut acc: Option<AssignedValue<F>> = None;
+        let mut levels = Vec::with_capacity(depth);
+        let mut coeff = F::ONE;
+        for position in positions(index_val, self.arity, depth) {
+            let mut bits: Vec<AssignedValue<F>> = Vec::with_capacity(self.arity - 1);
+            for j in 0..self.arity - 1 {
+                let bit_val = Value::known(if j < position { F::ONE } else { F::ZERO });
+
+                // t * t - t = 0
+                let t = ctx.assign_advice(|| "merkle: bit", config.state[0], bit_val)?;
+                let t_copy = ctx.assign_advice(|| "merkle: bit", config.state[1], bit_val)?;
+                ctx.constrain_equal(t.cell(), t_copy.cell())?;
+                ctx.assign_fixed(|| "merkle: q_m", config.q_m, F::ONE)?;
+                ctx.assign_fixed(|| "merkle: q_1", config.q_1[0], -F::ONE)?;
+                ctx.next();
+
+                // t_{j+1} * t_j - t_{j+1} = 0
+                if let Some(prev) = bits.last() {
+                    let tc = ctx.assign_advice(|| "merkle: bit", config.state[0], bit_val)?;
+                    ctx.constrain_equal(tc.cell(), t.cell())?;
+                    let p = ctx.assign_advice(
+                        || "merkle: bit",
+                        config.state[1],
+                        prev.value().copied(),
+                    )?;
+                    ctx.constrain_equal(p.cell(), prev.cell())?;
+                    ctx.assign_fixed(|| "merkle: q_m", config.q_m, F::ONE)?;
+                    ctx.assign_fixed(|| "merkle: q_1", config.q_1[0], -F::ONE)?;
+                    ctx.next();
+                }
+
+                // acc + arity^level * t - acc' = 0
+                let acc_val = match &acc {
+                    Some(acc) => {
+                        let a = ctx.assign_advice(
+                            || "merkle: acc",
+                            config.state[0],
+                            acc.value().copied(),
+                        )?;
+                        ctx.constrain_equal(a.cell(), acc.cell())?;
+                        ctx.assign_fixed(|| "merkle: q_1", config.q_1[0], F::ONE)?;
+                        acc.value().copied()
+                    }
+                    None => Value::known(F::ZERO),
+                };
+                let tc = ctx.assign_advice(|| "merkle: bit", config.state[1], bit_val)?;
+                ctx.constrain_equal(tc.cell(), t.cell())?;
+                ctx.assign_fixed(|| "merkle: arity^level", config.q_1[1], coeff)?;
+                ctx.assign_fixed(|| "merkle: q_o", config.q_o, -F::ONE)?;
+                let next = ctx.assign_advice(
+                    || "merkle: acc",
+                    config.out,
+                    acc_val + bit_val * Value::known(coeff),
+                )?;
+                ctx.next();
+
+                bits.push(t);
+                acc = Some(next);
+            }
+            levels.push(bits);
+            coeff *= F::from(self.arity as u64);
+        }
+        let acc = acc.expect("at least one level");
+        ctx.constrain_equal(acc.cell(), index.cell())?;
+        Ok(levels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+    };
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+    const K: u32 = 11;
+
+    fn spec() -> Spec<Fr, T, RATE> {
+        Spec::new(8, 56)
+    }
+
+    /// Recomputes the root of `leaf` at `path` and exposes `[root, index]`.
+    struct RootCircuit {
+        arity: usize,
+        leaf: Fr,
+        path: MerklePath<Fr>,
+    }
+
+    impl Circuit<Fr> for RootCircuit {
+        type Config = (MainGateConfig<T>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                arity: self.arity,
+                leaf: Fr::ZERO,
+                path: MerklePath {
+                    index: 0,
+                    siblings: vec![Fr::ZERO; self.path.siblings.len()],
+                },
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let config = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            (config, instance)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = MerkleChip::new(config.clone(), spec()).with_arity(self.arity);
+            let cells = layouter.assign_region(
+                || "merkle root",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    // free cells: every selector of the main gate is zero on this row
+                    let leaf =
+                        ctx.assign_advice(|| "leaf", config.state[0], Value::known(self.leaf))?;
+                    let index = ctx.assign_advice(
+                        || "index",
+                        config.state[1],
+                        Value::known(Fr::from(self.path.index)),
+                    )?;
+                    ctx.next();
+                    let root = chip.compute_root(ctx, &leaf, self.leaf, &index, &self.path)?;
+                    Ok([root, index])
+                },
+            )?;
+            for (row, cell) in cells.iter().enumerate() {
+                layouter.constrain_instance(cell.cell(), instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_native_paths() {
+        let spec = spec();
+        let leaves = (0..10).map(|i| Fr::from(100 + i)).collect::<Vec<_>>();
+        for (arity, depth) in [(2, 4), (3, 3), (4, 2)] {
+            let tree = MerkleTree::with_arity(&spec, leaves.clone(), depth, arity);
+            assert_eq!((tree.arity(), tree.depth()), (arity, depth));
+            for (index, leaf) in leaves.iter().enumerate() {
+                let path = tree.path(index as u64);
+                assert_eq!(path.siblings.len(), depth * (arity - 1));
+                assert_eq!(path.root_with_arity(&spec, *leaf, arity), tree.root());
+                assert_ne!(path.root_with_arity(&spec, Fr::ONE, arity), tree.root());
+            }
+        }
+
+        // a binary tree is the default, with a parent of Poseidon(left, right)
+        let tree = MerkleTree::new(&spec, leaves[..3].to_vec(), 2);
+        let path = tree.path(2);
+        assert_eq!(path.root(&spec, leaves[2]), tree.root());
+        let empty = hash(&spec, &[Fr::ZERO, Fr::ZERO]);
+        assert_eq!(
+            path.siblings,
+            [Fr::ZERO, hash(&spec, &[leaves[0], leaves[1]])]
+        );
+        assert_eq!(
+            tree.root(),
+            hash(
+                &spec,
+                &[path.siblings[1], hash(&spec, &[leaves[2], Fr::ZERO])]
+            )
+        );
+        assert_eq!(
+            MerkleTree::new(&spec, Vec::new(), 2).root(),
+            hash(&spec, &[empty, empty])
+        );
+    }
+
+    #[test]
+    fn test_set() {
+        let spec = spec();
+        let leaves = (0..10).map(|i| Fr::from(100 + i)).collect::<Vec<_>>();
+        for (arity, depth) in [(2, 4), (3, 3)] {
+            let mut tree = MerkleTree::with_arity(&spec, leaves[..4].to_vec(), depth, arity);
+            // overwrite a leaf, then write past the last one, leaving zeros between
+            let path = tree.set(&spec, 1, leaves[1] + Fr::ONE);
+            assert_eq!(path, tree.path(1));
+            tree.set(&spec, 7, leaves[7]);
+
+            let mut expected = leaves[..8].to_vec();
+            expected[1] += Fr::ONE;
+            expected[4..7].fill(Fr::ZERO);
+            let rebuilt = MerkleTree::with_arity(&spec, expected.clone(), depth, arity);
+            assert_eq!(tree.leaves(), rebuilt.leaves());
+            assert_eq!(tree.root(), rebuilt.root());
+            assert_eq!(tree.path(5), rebuilt.path(5));
+        }
+    }
+
+    #[test]
+    fn test_compute_root() {
+        let spec = spec();
+        // the binary layout is the one of the range chip and three rows per level
+        assert_eq!(
+            MerkleChip::num_rows(&spec, 3),
+            RangeChip::<Fr, T>::num_rows(3) + 3 * (3 + PoseidonChip::num_rows(&spec, 2))
+        );
+        let leaves = (0..10).map(|i| Fr::from(100 + i)).collect::<Vec<_>>();
+        for (arity, depth, index) in [(2, 4, 9), (3, 3, 7), (3, 3, 0), (4, 2, 9)] {
+            assert!(1 + MerkleChip::num_rows_with_arity(&spec, depth, arity) + 6 <= 1 << K);
+            let tree = MerkleTree::with_arity(&spec, leaves.clone(), depth, arity);
+            let circuit = RootCircuit {
+                arity,
+                leaf: leaves[index],
+                path: tree.path(index as u64),
+            };
+            let instance = vec![vec![tree.root(), Fr::from(index as u64)]];
+            let prover = MockProver::run(K, &circuit, instance).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "arity {arity}, index {index}");
+
+            // the path of the leaf does not lead to the root from another index
+            let other = (index + 1) % leaves.len();
+            let instance = vec![vec![tree.root(), Fr::from(other as u64)]];
+            let circuit = RootCircuit {
+                path: MerklePath {
+                    index: other as u64,
+                    ..circuit.path
+                },
+                ..circuit
+            };
+            let prover = MockProver::run(K, &circuit, instance).unwrap();
+            assert!(prover.verify().is_err(), "arity {arity}, index {index}");
+        }
+    }
+}
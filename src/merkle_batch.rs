@@ -0,0 +1,386 @@
+//! Proof of inserting a batch of leaves into empty slots of a binary Poseidon Merkle tree,
+//! from the old root to the new one, for rollups updating a state tree once per batch.
+//!
+//! [`insert_batch`] updates a [`MerkleTree`] natively and returns the witness of the batch:
+//! the path of every slot, taken after the insertions before it. [`BatchInsertionChip`]
+//! recomputes, for every leaf in order, the root of its path from an empty slot and from the
+//! leaf along the same siblings, and chains the new root of each insertion into the old root
+//! of the next. The leaves go to consecutive slots from `start`; the circuit exposes
+//! `[old_root, new_root, start, Poseidon(leaves)]`, binding the batch without a public input
+//! per leaf.
+use std::fmt;
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner, Value},
+    plonk::{Circuit, Column, ConstraintSystem, Error, Instance},
+};
+use poseidon::Spec;
+
+use crate::{
+    main_gate::{AssignedValue, MainGate, MainGateConfig, RegionCtx},
+    merkle::{MerkleChip, MerklePath, MerkleTree},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash,
+    specs::BN256_T4_R3,
+};
+
+const T: usize = BN256_T4_R3.width;
+const RATE: usize = BN256_T4_R3.rate;
+
+fn spec<F: PrimeField + FromUniformBytes<64>>() -> Spec<F, T, RATE> {
+    Spec::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p)
+}
+
+/// The witness of a batch insertion.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchInsertion<F> {
+    pub start: u64,
+    pub leaves: Vec<F>,
+    /// The path of every slot, in the tree holding the leaves before it
+    pub paths: Vec<MerklePath<F>>,
+    pub old_root: F,
+    pub new_root: F,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> BatchInsertion<F> {
+    /// `[old_root, new_root, start, Poseidon(leaves)]`
+    pub fn instance<const T: usize, const RATE: usize>(&self, spec: &Spec<F, T, RATE>) -> Vec<F> {
+        vec![
+            self.old_root,
+            self.new_root,
+            F::from(self.start),
+            hash(spec, &self.leaves),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchError {
+    Empty,
+    /// The batch runs past the last slot of the tree
+    OutOfRange {
+        start: u64,
+        len: usize,
+        capacity: u64,
+    },
+    /// The slot at this index already holds a leaf
+    Occupied(u64),
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "the batch has no leaves"),
+            Self::OutOfRange {
+                start,
+                len,
+                capacity,
+            } => write!(
+                f,
+                "{len} leaves from slot {start} do not fit into {capacity} slots"
+            ),
+            Self::Occupied(index) => write!(f, "slot {index} is not empty"),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+/// Inserts `leaves` into the empty slots of a binary `tree` from `start` on; the tree is left
+/// alone if any slot is taken.
+pub fn insert_batch<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    tree: &mut MerkleTree<F>,
+    spec: &Spec<F, T, RATE>,
+    start: u64,
+    leaves: Vec<F>,
+) -> Result<BatchInsertion<F>, BatchError> {
+    assert_eq!(tree.arity(), 2, "batches are inserted into binary trees");
+    if leaves.is_empty() {
+        return Err(BatchError::Empty);
+    }
+    let capacity = 1u64 << tree.depth();
+    let end = start
+        .checked_add(leaves.len() as u64)
+        .filter(|end| *end <= capacity)
+        .ok_or(BatchError::OutOfRange {
+            start,
+            len: leaves.len(),
+            capacity,
+        })?;
+    if let Some(index) = (start..end).find(|i| {
+        tree.leaves()
+            .get(*i as usize)
+            .is_some_and(|leaf| !bool::from(leaf.is_zero()))
+    }) {
+        return Err(BatchError::Occupied(index));
+    }
+
+    let old_root = tree.root();
+    let paths = (start..)
+        .zip(&leaves)
+        .map(|(index, leaf)| tree.set(spec, index, *leaf))
+        .collect();
+    Ok(BatchInsertion {
+        start,
+        leaves,
+        paths,
+        old_root,
+        new_root: tree.root(),
+    })
+}
+
+/// Rows of a [`BatchInsertionCircuit`] of `len` leaves into a tree of `depth` levels.
+pub fn num_rows<F: PrimeField + FromUniformBytes<64>>(depth: usize, len: usize) -> usize {
+    BatchInsertionChip::num_rows(&spec::<F>(), depth, len)
+}
+
+/// The cells of a batch insertion by [`BatchInsertionChip::assign`], for the caller to
+/// expose.
+#[derive(Clone, Debug)]
+pub struct AssignedBatch<F: PrimeField> {
+    pub old_root: AssignedValue<F>,
+    pub new_root: AssignedValue<F>,
+    pub start: AssignedValue<F>,
+    /// `Poseidon(leaves)`
+    pub digest: AssignedValue<F>,
+}
+
+/// Checks a batch insertion into a binary tree, with the hashes of `spec`.
+pub struct BatchInsertionChip<F: PrimeField, const T: usize, const RATE: usize> {
+    config: MainGateConfig<T>,
+    spec: Spec<F, T, RATE>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    BatchInsertionChip<F, T, RATE>
+{
+    pub fn new(config: MainGateConfig<T>, spec: Spec<F, T, RATE>) -> Self {
+        Self { config, spec }
+    }
+
+    /// Rows used by [`BatchInsertionChip::assign`] for `len` leaves and `depth` levels.
+    pub fn num_rows(spec: &Spec<F, T, RATE>, depth: usize, len: usize) -> usize {
+        // the start, then the index, an empty slot and the leaf of every insertion
+        1 + len * (3 + 2 * MerkleChip::num_rows(spec, depth)) + PoseidonChip::num_rows(spec, len)
+    }
+
+    pub fn assign(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        batch: &BatchInsertion<F>,
+    ) -> Result<AssignedBatch<F>, Error> {
+        assert!(!batch.leaves.is_empty() && batch.leaves.len() == batch.paths.len());
+        let main_gate = MainGate::new(self.config.clone());
+        let merkle = MerkleChip::new(self.config.clone(), self.spec.clone());
+        // a free cell: every selector of the main gate is zero on this row
+        let free = |ctx: &mut RegionCtx<'_, F>, value: F| {
+            main_gate.apply(
+                ctx,
+                (None, None, None),
+                None,
+                (F::ZERO, Value::known(value).into()),
+            )
+        };
+        let start = free(ctx, F::from(batch.start))?;
+
+        let mut roots: Option<(AssignedValue<F>, AssignedValue<F>)> = None;
+        let mut leaves = Vec::with_capacity(batch.leaves.len());
+        for (i, (leaf_val, path)) in batch.leaves.iter().zip(&batch.paths).enumerate() {
+            // start + i - index = 0
+            let index = main_gate.apply(
+                ctx,
+                (Some(vec![F::ONE]), None, Some(vec![(&start).into()])),
+                Some(F::from(i as u64)),
+                (-F::ONE, Value::known(F::from(path.index)).into()),
+            )?;
+            // -empty = 0
+            let empty = main_gate.apply(
+                ctx,
+                (None, None, None),
+                None,
+                (-F::ONE, Value::known(F::ZERO).into()),
+            )?;
+            let leaf = free(ctx, *leaf_val)?;
+
+            let (old, siblings) =
+                merkle.compute_root_along(ctx, &empty, F::ZERO, &index, path, None)?;
+            let (new, _) =
+                merkle.compute_root_along(ctx, &leaf, *leaf_val, &index, path, Some(&siblings))?;
+            match &mut roots {
+                Some((_, root)) => {
+                    ctx.constrain_equal(old.cell(), root.cell())?;
+                    *root = new;
+                }
+                None => roots = Some((old, new)),
+            }
+            leaves.push(leaf);
+        }
+
+        let mut pchip = PoseidonChip::new(self.config.clone(), self.spec.clone());
+        pchip.update(batch.leaves.clone());
+        let (inputs, digest) = pchip.squeeze_with_inputs(ctx)?;
+        for (input, leaf) in inputs.iter().zip(&leaves) {
+            ctx.constrain_equal(input.cell(), leaf.cell())?;
+        }
+        let (old_root, new_root) = roots.expect("a batch has a leaf");
+        Ok(AssignedBatch {
+            old_root,
+            new_root,
+            start,
+            digest,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BatchInsertionConfig {
+    pconfig: MainGateConfig<T>,
+    instance: Column<Instance>,
+}
+
+/// Proves a batch insertion, exposing `[old_root, new_root, start, Poseidon(leaves)]`.
+pub struct BatchInsertionCircuit<F: PrimeField> {
+    batch: BatchInsertion<F>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> BatchInsertionCircuit<F> {
+    pub fn new(batch: BatchInsertion<F>) -> Self {
+        Self { batch }
+    }
+
+    pub fn instance(&self) -> Vec<F> {
+        self.batch.instance(&spec())
+    }
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for BatchInsertionCircuit<F> {
+    type Config = BatchInsertionConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        let path = MerklePath {
+            index: 0,
+            siblings: vec![F::ZERO; self.batch.paths[0].siblings.len()],
+        };
+        Self::new(BatchInsertion {
+            start: 0,
+            leaves: vec![F::ZERO; self.batch.leaves.len()],
+            paths: vec![path; self.batch.paths.len()],
+            old_root: F::ZERO,
+            new_root: F::ZERO,
+        })
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        let instance = meta.instance_column();
+        meta.enable_equality(instance);
+        let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+        let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+        let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+        Self::Config { pconfig, instance }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        let chip = BatchInsertionChip::new(config.pconfig, spec::<F>());
+        let batch = layouter.assign_region(
+            || "merkle batch insertion",
+            |region| chip.assign(&mut RegionCtx::new(region, 0), &self.batch),
+        )?;
+        for (row, cell) in [batch.old_root, batch.new_root, batch.start, batch.digest]
+            .iter()
+            .enumerate()
+        {
+            layouter.constrain_instance(cell.cell(), config.instance, row)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+
+    const K: u32 = 12;
+    const DEPTH: usize = 4;
+
+    fn leaves(from: u64, len: u64) -> Vec<Fr> {
+        (from..from + len).map(Fr::from).collect()
+    }
+
+    #[test]
+    fn test_insert_batch() {
+        let spec = spec::<Fr>();
+        let mut tree = MerkleTree::new(&spec, leaves(100, 3), DEPTH);
+        let batch = insert_batch(&mut tree, &spec, 5, leaves(200, 4)).unwrap();
+
+        let mut all = leaves(100, 3);
+        all.extend([Fr::ZERO; 2]);
+        all.extend(leaves(200, 4));
+        assert_eq!(
+            batch.old_root,
+            MerkleTree::new(&spec, leaves(100, 3), DEPTH).root()
+        );
+        assert_eq!(batch.new_root, MerkleTree::new(&spec, all, DEPTH).root());
+        assert_eq!(tree.root(), batch.new_root);
+        assert_eq!(batch.paths[3].index, 8);
+
+        assert_eq!(
+            insert_batch(&mut tree, &spec, 2, leaves(300, 2)),
+            Err(BatchError::Occupied(2))
+        );
+        assert!(matches!(
+            insert_batch(&mut tree, &spec, 14, leaves(300, 3)),
+            Err(BatchError::OutOfRange { capacity: 16, .. })
+        ));
+        assert_eq!(
+            insert_batch(&mut tree, &spec, 3, Vec::new()),
+            Err(BatchError::Empty)
+        );
+        assert_eq!(tree.root(), batch.new_root);
+    }
+
+    #[test]
+    fn test_batch_circuit() {
+        assert!(num_rows::<Fr>(DEPTH, 4) + 6 <= 1 << K);
+        let spec = spec::<Fr>();
+        let mut tree = MerkleTree::new(&spec, leaves(100, 3), DEPTH);
+        let batch = insert_batch(&mut tree, &spec, 3, leaves(200, 4)).unwrap();
+        let circuit = BatchInsertionCircuit::new(batch.clone());
+        let instance = circuit.instance();
+        assert_eq!(instance[..3], [batch.old_root, batch.new_root, Fr::from(3)]);
+        let prover = MockProver::run(K, &circuit, vec![instance.clone()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // another new root or start does not verify
+        for row in [1, 2] {
+            let mut forged = instance.clone();
+            forged[row] += Fr::ONE;
+            let prover = MockProver::run(K, &circuit, vec![forged]).unwrap();
+            assert!(prover.verify().is_err(), "row {row}");
+        }
+
+        // nor does overwriting a leaf, whose slot is not empty
+        let mut tree = MerkleTree::new(&spec, leaves(100, 3), DEPTH);
+        let old_root = tree.root();
+        let path = tree.set(&spec, 1, Fr::from(7));
+        let overwrite = BatchInsertion {
+            start: 1,
+            leaves: vec![Fr::from(7)],
+            paths: vec![path],
+            old_root,
+            new_root: tree.root(),
+        };
+        let circuit = BatchInsertionCircuit::new(overwrite.clone());
+        let prover = MockProver::run(K, &circuit, vec![overwrite.instance(&spec)]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! The round constants and optimized MDS matrices of a spec, computed once and reused.
+//!
+//! Building a [`Spec`] inverts the MDS matrix and derives the pre-sparse and sparse
+//! matrices of the partial rounds. [`OptimizedConstants`] keeps the result: workers share
+//! one copy behind an `Arc` and persist it with [`OptimizedConstants::load_or_compute`], so
+//! a restart reads the matrices from disk instead of deriving them again.
+use std::{fmt, io, path::Path};
+
+use ff::{FromUniformBytes, PrimeField};
+use poseidon::Spec;
+use serde::{Deserialize, Serialize};
+
+use crate::field_encoding::{parse_field, to_canonical, FieldParseErrorKind};
+
+/// Everything [`crate::poseidon_circuit::PoseidonChip`] reads from a spec.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptimizedConstants<F, const T: usize, const RATE: usize> {
+    pub(crate) r_f: usize,
+    pub(crate) start: Vec<[F; T]>,
+    pub(crate) partial: Vec<F>,
+    pub(crate) end: Vec<[F; T]>,
+    pub(crate) mds: [[F; T]; T],
+    pub(crate) pre_sparse_mds: [[F; T]; T],
+    /// `(row, col_hat)` of the sparse matrix of every partial round
+    pub(crate) sparse: Vec<([F; T], [F; RATE])>,
+}
+
+/// On-disk form: dimensions plus every element in canonical hex.
+#[derive(Serialize, Deserialize)]
+struct ConstantsFile {
+    width: usize,
+    rate: usize,
+    r_f: usize,
+    start: Vec<Vec<String>>,
+    partial: Vec<String>,
+    end: Vec<Vec<String>>,
+    mds: Vec<Vec<String>>,
+    pre_sparse_mds: Vec<Vec<String>>,
+    sparse_rows: Vec<Vec<String>>,
+    sparse_col_hats: Vec<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub enum ConstantsError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The file was written for another width, rate or number of rounds
+    Shape(&'static str),
+    Field(FieldParseErrorKind),
+}
+
+impl fmt::Display for ConstantsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot access the constants file: {err}"),
+            Self::Json(err) => write!(f, "malformed constants file: {err}"),
+            Self::Shape(what) => write!(f, "constants file does not match the spec: {what}"),
+            Self::Field(kind) => write!(f, "invalid element in constants file: {kind}"),
+        }
+    }
+}
+
+impl std::error::Error for ConstantsError {}
+
+impl<F: PrimeField, const T: usize, const RATE: usize> OptimizedConstants<F, T, RATE> {
+    pub fn from_spec(spec: &Spec<F, T, RATE>) -> Self {
+        let constants = spec.constants();
+        let mds = spec.mds_matrices();
+        Self {
+            r_f: spec.r_f(),
+            start: constants.start().clone(),
+            partial: constants.partial().clone(),
+            end: constants.end().clone(),
+            mds: mds.mds().rows(),
+            pre_sparse_mds: mds.pre_sparse_mds().rows(),
+            sparse: mds
+                .sparse_matrices()
+                .iter()
+                .map(|m| (*m.row(), *m.col_hat()))
+                .collect(),
+        }
+    }
+
+    pub fn r_f(&self) -> usize {
+        self.r_f
+    }
+
+    pub fn r_p(&self) -> usize {
+        self.partial.len()
+    }
+
+    pub fn to_json(&self) -> String {
+        let row = |row: &[F]| row.iter().map(to_canonical).collect::<Vec<_>>();
+        let file = ConstantsFile {
+            width: T,
+            rate: RATE,
+            r_f: self.r_f,
+            start: self.start.iter().map(|r| row(r)).collect(),
+            partial: row(&self.partial),
+            end: self.end.iter().map(|r| row(r)).collect(),
+            mds: self.mds.iter().map(|r| row(r)).collect(),
+            pre_sparse_mds: self.pre_sparse_mds.iter().map(|r| row(r)).collect(),
+            sparse_rows: self.sparse.iter().map(|(r, _)| row(r)).collect(),
+            sparse_col_hats: self.sparse.iter().map(|(_, c)| row(c)).collect(),
+        };
+        serde_json::to_string(&file).expect("constants serialize")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ConstantsError> {
+        let file: ConstantsFile = serde_json::from_str(json).map_err(ConstantsError::Json)?;
+        if file.width != T || file.rate != RATE {
+            return Err(ConstantsError::Shape("width or rate"));
+        }
+        if file.sparse_rows.len() != file.partial.len()
+            || file.sparse_col_hats.len() != file.partial.len()
+        {
+            return Err(ConstantsError::Shape("number of partial rounds"));
+        }
+        let elements = |row: &[String]| {
+            row.iter()
+                .map(|s| parse_field::<F>(s).map_err(ConstantsError::Field))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        fn array<F, const N: usize>(v: Vec<F>) -> Result<[F; N], ConstantsError> {
+            v.try_into()
+                .map_err(|_| ConstantsError::Shape("row length"))
+        }
+        let rows = |rows: &[Vec<String>]| {
+            rows.iter()
+                .map(|r| array::<F, T>(elements(r)?))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            r_f: file.r_f,
+            start: rows(&file.start)?,
+            partial: elements(&file.partial)?,
+            end: rows(&file.end)?,
+            mds: array(rows(&file.mds)?)?,
+            pre_sparse_mds: array(rows(&file.pre_sparse_mds)?)?,
+            sparse: rows(&file.sparse_rows)?
+                .into_iter()
+                .zip(file.sparse_col_hats.iter())
+                .map(|(row, col_hat)| Ok((row, array::<F, RATE>(elements(col_hat)?)?)))
+                .collect::<Result<Vec<_>, ConstantsError>>()?,
+        })
+    }
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    OptimizedConstants<F, T, RATE>
+{
+    /// Reads the constants of `(r_f, r_p)` from `path`, or derives and writes them there.
+    pub fn load_or_compute(
+        path: impl AsRef<Path>,
+        r_f: usize,
+        r_p: usize,
+    ) -> Result<Self, ConstantsError> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(json) => {
+                let constants = Self::from_json(&json)?;
+                if constants.r_f() != r_f || constants.r_p() != r_p {
+                    return Err(ConstantsError::Shape("number of rounds"));
+                }
+                Ok(constants)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let constants = Self::from_spec(&Spec::new(r_f, r_p));
+                std::fs::write(path, constants.to_json()).map_err(ConstantsError::Io)?;
+                Ok(constants)
+            }
+            Err(err) => Err(ConstantsError::Io(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+
+    #[test]
+    fn test_persist_constants() {
+        let path =
+            std::env::temp_dir().join(format!("poseidon-constants-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let expected = OptimizedConstants::from_spec(&Spec::<Fr, 4, 3>::new(8, 56));
+
+        let computed = OptimizedConstants::<Fr, 4, 3>::load_or_compute(&path, 8, 56).unwrap();
+        assert_eq!(computed, expected);
+        let loaded = OptimizedConstants::<Fr, 4, 3>::load_or_compute(&path, 8, 56).unwrap();
+        assert_eq!(loaded, expected);
+        assert!(matches!(
+            OptimizedConstants::<Fr, 4, 3>::load_or_compute(&path, 8, 57),
+            Err(ConstantsError::Shape(_))
+        ));
+        assert!(matches!(
+            OptimizedConstants::<Fr, 3, 2>::from_json(&expected.to_json()),
+            Err(ConstantsError::Shape(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
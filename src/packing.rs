@@ -0,0 +1,44 @@
+//! Canonical packing of byte strings into field elements.
+//!
+//! A byte string of `n` bytes becomes `n` as a field element followed by its 31-byte chunks,
+//! each read as a big-endian integer; the last chunk may be shorter. Chunks of 31 bytes
+//! always fit into the fields of this crate, and the length prefix keeps strings that only
+//! differ by trailing zeros apart.
+use ff::PrimeField;
+
+/// Bytes per packed element.
+pub const CHUNK_LEN: usize = 31;
+
+/// Packs `bytes` into `1 + ceil(len / 31)` field elements.
+pub fn pack_bytes<F: PrimeField>(bytes: &[u8]) -> Vec<F> {
+    assert!(F::NUM_BITS as usize > 8 * CHUNK_LEN);
+    std::iter::once(F::from(bytes.len() as u64))
+        .chain(bytes.chunks(CHUNK_LEN).map(|chunk| {
+            let mut repr = F::Repr::default();
+            for (dst, src) in repr.as_mut().iter_mut().zip(chunk.iter().rev()) {
+                *dst = *src;
+            }
+            F::from_repr(repr).expect("31 bytes are below the modulus")
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+
+    #[test]
+    fn test_pack_bytes() {
+        assert_eq!(pack_bytes::<Fr>(&[]), vec![Fr::from(0)]);
+        assert_eq!(
+            pack_bytes::<Fr>(&[1, 2]),
+            vec![Fr::from(2), Fr::from(0x0102)]
+        );
+        let long = pack_bytes::<Fr>(&[0xff; 32]);
+        assert_eq!(long.len(), 3);
+        assert_eq!(long[2], Fr::from(0xff));
+        assert_ne!(pack_bytes::<Fr>(&[1]), pack_bytes::<Fr>(&[1, 0]));
+    }
+}
@@ -0,0 +1,240 @@
+//! [`crate::packing::pack_bytes`] in a circuit, so that digests bind to the bytes of a
+//! message rather than to elements the prover packed.
+//!
+//! Every byte is a cell range checked to 8 bits, and the bytes of a 31-byte chunk are
+//! composed into its limb with `256 * acc + b - acc' = 0` on the main gate, the first byte
+//! the most significant; the length prefix is a constant, so a circuit packs messages of one
+//! length. The byte cells are returned for callers to copy-constrain, e.g. to the bytes of a
+//! transaction they decode.
+use std::{iter, sync::Arc};
+
+use ff::PrimeField;
+use halo2_proofs::{circuit::Value, plonk::Error};
+
+use crate::{
+    main_gate::{AssignedValue, MainGateConfig, RegionCtx},
+    optimized_constants::OptimizedConstants,
+    packing::CHUNK_LEN,
+    poseidon_circuit::PoseidonChip,
+    range_chip::RangeChip,
+};
+
+pub struct PackingChip<F: PrimeField, const T: usize> {
+    config: MainGateConfig<T>,
+    range: RangeChip<F, T>,
+}
+
+impl<F: PrimeField, const T: usize> PackingChip<F, T> {
+    pub fn new(config: MainGateConfig<T>) -> Self {
+        Self {
+            range: RangeChip::new(config.clone()),
+            config,
+        }
+    }
+
+    /// Number of rows [`PackingChip::pack_bytes`] uses for `len` bytes.
+    pub fn num_rows(len: usize) -> usize {
+        1 + len * (1 + RangeChip::<F, T>::num_rows(8))
+    }
+
+    /// Assigns `value` in a cell constrained to equal it, on one row.
+    pub fn assign_constant(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        value: F,
+    ) -> Result<AssignedValue<F>, Error> {
+        // x - c = 0
+        let x = ctx.assign_advice(
+            || "packing: constant",
+            self.config.state[0],
+            Value::known(value),
+        )?;
+        ctx.assign_fixed(|| "packing: q_1", self.config.q_1[0], F::ONE)?;
+        ctx.assign_fixed(|| "packing: rc", self.config.rc, -value)?;
+        ctx.next();
+        Ok(x)
+    }
+
+    /// Assigns `bytes` and packs them as [`crate::packing::pack_bytes`] does; returns the
+    /// byte cells and the packed elements, the length first.
+    #[allow(clippy::type_complexity)]
+    pub fn pack_bytes(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        bytes: &[u8],
+    ) -> Result<(Vec<AssignedValue<F>>, Vec<AssignedValue<F>>), Error> {
+        let bytes: Vec<_> = bytes.iter().map(|b| F::from(u64::from(*b))).collect();
+        self.pack(ctx, &bytes)
+    }
+
+    /// Packs elements that should be bytes; the range checks fail on any that is not.
+    #[allow(clippy::type_complexity)]
+    fn pack(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        bytes: &[F],
+    ) -> Result<(Vec<AssignedValue<F>>, Vec<AssignedValue<F>>), Error> {
+        let config = &self.config;
+        let shift = F::from(256);
+        let mut packed = vec![self.assign_constant(ctx, F::from(bytes.len() as u64))?];
+        let mut cells = Vec::with_capacity(bytes.len());
+        for chunk in bytes.chunks(CHUNK_LEN) {
+            let mut acc: Option<AssignedValue<F>> = None;
+            for byte in chunk {
+                // 256 * acc + b - acc' = 0
+                let acc_val = match &acc {
+                    Some(acc) => {
+                        let a = ctx.assign_advice(
+                            || "packing: acc",
+                            config.state[0],
+                            acc.value().copied(),
+                        )?;
+                        ctx.constrain_equal(a.cell(), acc.cell())?;
+                        ctx.assign_fixed(|| "packing: 256", config.q_1[0], shift)?;
+                        acc.value().copied() * Value::known(shift)
+                    }
+                    None => Value::known(F::ZERO),
+                };
+                let b_val = Value::known(*byte);
+                let b = ctx.assign_advice(|| "packing: byte", config.state[1], b_val)?;
+                ctx.assign_fixed(|| "packing: q_1", config.q_1[1], F::ONE)?;
+                ctx.assign_fixed(|| "packing: q_o", config.q_o, -F::ONE)?;
+                let next = ctx.assign_advice(|| "packing: acc", config.out, acc_val + b_val)?;
+                ctx.next();
+                cells.push(b);
+                acc = Some(next);
+            }
+            packed.push(acc.expect("chunks are not empty"));
+        }
+        for cell in &cells {
+            self.range.range_check(ctx, cell, 8)?;
+        }
+        Ok((cells, packed))
+    }
+
+    /// Packs `bytes` and hashes the packed elements, as `poseidon_hash::hash` of
+    /// [`crate::packing::pack_bytes`] does; returns the byte cells and the digest.
+    pub fn hash_bytes<const RATE: usize>(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        constants: Arc<OptimizedConstants<F, T, RATE>>,
+        bytes: &[u8],
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        let bytes: Vec<_> = bytes.iter().map(|b| F::from(u64::from(*b))).collect();
+        self.hash(ctx, constants, &bytes)
+    }
+
+    fn hash<const RATE: usize>(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        constants: Arc<OptimizedConstants<F, T, RATE>>,
+        bytes: &[F],
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        let (cells, packed) = self.pack(ctx, bytes)?;
+        let shift = F::from(256);
+        let mut chip = PoseidonChip::from_constants(self.config.clone(), constants);
+        chip.update(
+            iter::once(F::from(bytes.len() as u64))
+                .chain(
+                    bytes
+                        .chunks(CHUNK_LEN)
+                        .map(|chunk| chunk.iter().fold(F::ZERO, |acc, b| acc * shift + b)),
+                )
+                .collect(),
+        );
+        let (inputs, digest) = chip.squeeze_with_inputs(ctx)?;
+        for (input, limb) in inputs.iter().zip(&packed) {
+            ctx.constrain_equal(input.cell(), limb.cell())?;
+        }
+        Ok((cells, digest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+    };
+    use halo2curves::bn256::Fr;
+    use poseidon::Spec;
+
+    use super::*;
+    use crate::{main_gate::MainGate, packing::pack_bytes, poseidon_hash::hash};
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+
+    /// Hashes `bytes`, which may hold non-bytes
+    struct BytesCircuit {
+        bytes: Vec<Fr>,
+    }
+
+    impl Circuit<Fr> for BytesCircuit {
+        type Config = (MainGateConfig<T>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                bytes: vec![Fr::ZERO; self.bytes.len()],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            (
+                MainGate::configure(meta, &mut adv_cols, &mut fix_cols),
+                instance,
+            )
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = PackingChip::new(config);
+            let spec = Spec::<Fr, T, RATE>::new(8, 56);
+            let constants = Arc::new(OptimizedConstants::from_spec(&spec));
+            let (_, digest) = layouter.assign_region(
+                || "packing",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    chip.hash(ctx, constants.clone(), &self.bytes)
+                },
+            )?;
+            layouter.constrain_instance(digest.cell(), instance, 0)
+        }
+    }
+
+    fn digest(bytes: &[u8]) -> Fr {
+        hash(&Spec::<Fr, T, RATE>::new(8, 56), &pack_bytes(bytes))
+    }
+
+    #[test]
+    fn test_pack_bytes() {
+        let bytes: Vec<u8> = (0..40).map(|i| 0xff - i).collect();
+        let circuit = BytesCircuit {
+            bytes: bytes.iter().map(|b| Fr::from(u64::from(*b))).collect(),
+        };
+        let prover = MockProver::run(11, &circuit, vec![vec![digest(&bytes)]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        let prover = MockProver::run(11, &circuit, vec![vec![digest(&bytes[1..])]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_not_a_byte() {
+        // 256 at the end of a limb packs like a carry into the byte before it
+        let circuit = BytesCircuit {
+            bytes: vec![Fr::from(1), Fr::from(256)],
+        };
+        let prover = MockProver::run(11, &circuit, vec![vec![digest(&[2, 0])]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
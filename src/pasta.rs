@@ -0,0 +1,159 @@
+//! Instantiations over the Pasta fields, for circuits proven with IPA over Pallas and Vesta.
+//!
+//! The chip, the sponges and the round constants are generic over the field; this module
+//! pins the vetted parameter set of each Pasta field, the width 3 instance of
+//! halo2_gadgets' `P128Pow5T3`. A circuit committed on Vesta, as in Halo2, has the Pallas
+//! base field as scalars and hashes with [`PallasSpec`]; one committed on Pallas with
+//! [`VestaSpec`].
+use ff::{FromUniformBytes, PrimeField};
+use halo2curves::pasta::{Fp, Fq};
+use poseidon::Spec;
+
+use crate::{
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash,
+    specs::{checked_spec, SpecError},
+    test_circuit::TestCircuit,
+};
+
+pub const WIDTH: usize = 3;
+pub const RATE: usize = 2;
+pub const R_F: usize = 8;
+pub const R_P: usize = 56;
+
+/// Spec over the Pallas base field `Fp`.
+pub type PallasSpec = Spec<Fp, WIDTH, RATE>;
+/// Spec over the Vesta base field `Fq`.
+pub type VestaSpec = Spec<Fq, WIDTH, RATE>;
+pub type PallasChip = PoseidonChip<Fp, WIDTH, RATE>;
+pub type VestaChip = PoseidonChip<Fq, WIDTH, RATE>;
+pub type PallasCircuit = TestCircuit<Fp, WIDTH, RATE>;
+pub type VestaCircuit = TestCircuit<Fq, WIDTH, RATE>;
+
+pub fn pallas_spec() -> PallasSpec {
+    vetted().expect("the Pallas instance is in specs::SUPPORTED")
+}
+
+pub fn vesta_spec() -> VestaSpec {
+    vetted().expect("the Vesta instance is in specs::SUPPORTED")
+}
+
+fn vetted<F: PrimeField + FromUniformBytes<64>>() -> Result<Spec<F, WIDTH, RATE>, SpecError> {
+    checked_spec(R_F, R_P)
+}
+
+/// Hashes `inputs` like [`poseidon_hash::hash`] under [`pallas_spec`].
+pub fn hash_pallas(inputs: &[Fp]) -> Fp {
+    poseidon_hash::hash(&pallas_spec(), inputs)
+}
+
+/// Hashes `inputs` like [`poseidon_hash::hash`] under [`vesta_spec`].
+pub fn hash_vesta(inputs: &[Fq]) -> Fq {
+    poseidon_hash::hash(&vesta_spec(), inputs)
+}
+
+impl PallasCircuit {
+    /// A circuit hashing `inputs` under [`pallas_spec`].
+    pub fn pallas(inputs: Vec<Fp>) -> Self {
+        Self::with_spec(inputs, R_F, R_P)
+    }
+}
+
+impl VestaCircuit {
+    /// A circuit hashing `inputs` under [`vesta_spec`].
+    pub fn vesta(inputs: Vec<Fq>) -> Self {
+        Self::with_spec(inputs, R_F, R_P)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        dev::MockProver,
+        plonk::{create_proof, keygen_pk, keygen_vk, verify_proof},
+        poly::{
+            commitment::ParamsProver,
+            ipa::{
+                commitment::{IPACommitmentScheme, ParamsIPA},
+                multiopen::{ProverIPA, VerifierIPA},
+                strategy::SingleStrategy,
+            },
+        },
+        transcript::{
+            Blake2bRead, Blake2bWrite, Challenge255, TranscriptReadBuffer, TranscriptWriterBuffer,
+        },
+    };
+    use halo2curves::pasta::EqAffine;
+    use rand_core::OsRng;
+
+    use super::*;
+    use crate::specs::{PALLAS_MODULUS, VESTA_MODULUS};
+
+    const K: u32 = 10;
+
+    #[test]
+    fn test_vetted_specs() {
+        assert_eq!(Fp::MODULUS, PALLAS_MODULUS);
+        assert_eq!(Fq::MODULUS, VESTA_MODULUS);
+        assert!(checked_spec::<Fp, WIDTH, RATE>(R_F, R_P + 1).is_err());
+        // the fields differ, so do the constants Grain draws for them
+        let (pallas, vesta) = (pallas_spec(), vesta_spec());
+        assert_ne!(
+            pallas.constants().start()[0][0].to_repr(),
+            vesta.constants().start()[0][0].to_repr()
+        );
+    }
+
+    #[test]
+    fn test_circuits() {
+        let inputs = (0..5).map(Fp::from).collect::<Vec<_>>();
+        let digest = hash_pallas(&inputs);
+        let circuit = PallasCircuit::pallas(inputs);
+        let prover = MockProver::run(K, &circuit, vec![vec![digest]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        let prover = MockProver::run(K, &circuit, vec![vec![digest + Fp::ONE]]).unwrap();
+        assert!(prover.verify().is_err());
+
+        let inputs = (0..5).map(Fq::from).collect::<Vec<_>>();
+        let digest = hash_vesta(&inputs);
+        let prover = MockProver::run(K, &VestaCircuit::vesta(inputs), vec![vec![digest]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_ipa_proof() {
+        let inputs = (0..3).map(Fp::from).collect::<Vec<_>>();
+        let digest = hash_pallas(&inputs);
+        let circuit = PallasCircuit::pallas(inputs);
+        let params = ParamsIPA::<EqAffine>::new(K);
+        let vk = keygen_vk(&params, &circuit).unwrap();
+        let pk = keygen_pk(&params, vk, &circuit).unwrap();
+        let instances: &[&[Fp]] = &[&[digest]];
+
+        let mut transcript = Blake2bWrite::<_, EqAffine, Challenge255<_>>::init(vec![]);
+        create_proof::<IPACommitmentScheme<_>, ProverIPA<'_, _>, _, _, _, _>(
+            &params,
+            &pk,
+            &[circuit],
+            &[instances],
+            OsRng,
+            &mut transcript,
+        )
+        .unwrap();
+        let proof = transcript.finalize();
+
+        let verify = |instances: &[&[Fp]]| {
+            let mut transcript = Blake2bRead::<_, EqAffine, Challenge255<_>>::init(&proof[..]);
+            verify_proof::<IPACommitmentScheme<_>, VerifierIPA<'_, _>, _, _, _>(
+                &params,
+                pk.get_vk(),
+                SingleStrategy::new(&params),
+                &[instances],
+                &mut transcript,
+            )
+        };
+        assert!(verify(instances).is_ok());
+        assert!(verify(&[&[digest + Fp::ONE]]).is_err());
+    }
+}
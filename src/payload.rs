@@ -0,0 +1,238 @@
+//! `task_data` too large for a queue message, sent as a reference to an object.
+//!
+//! A task may carry a [`PayloadRef`] instead of inline `task_data`: the URI of the object
+//! holding it, its length and its Blake2b-256 digest. The object is streamed from a
+//! [`PayloadSource`] into the task by [`crate::task::Task::resolve_task_data`], with the
+//! length bounded and the digest checked as it is read. [`LocalFiles`] serves objects from
+//! a directory, e.g. a mounted bucket; other stores implement [`PayloadSource`].
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Largest referenced `task_data`.
+pub const MAX_REFERENCED_TASK_DATA_LEN: u64 = 1 << 30;
+
+/// Bytes read from a source at once.
+const READ_CHUNK_LEN: usize = 1 << 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PayloadRef {
+    pub uri: String,
+    /// Length of the object in bytes
+    pub len: u64,
+    /// Hex Blake2b-256 of the object
+    pub blake2b: String,
+}
+
+pub trait PayloadSource {
+    fn open(&self, uri: &str) -> Result<Box<dyn Read + '_>, PayloadError>;
+}
+
+/// Objects under a directory, addressed as `file:///<path relative to the directory>`.
+#[derive(Debug, Clone)]
+pub struct LocalFiles {
+    root: PathBuf,
+}
+
+impl LocalFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The file of `uri`, refusing paths that would leave the directory.
+    pub fn path(&self, uri: &str) -> Result<PathBuf, PayloadError> {
+        let relative = uri
+            .strip_prefix("file:///")
+            .map(Path::new)
+            .ok_or_else(|| PayloadError::UnsupportedUri(uri.to_string()))?;
+        if relative.as_os_str().is_empty()
+            || !relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(PayloadError::UnsupportedUri(uri.to_string()));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+impl PayloadSource for LocalFiles {
+    fn open(&self, uri: &str) -> Result<Box<dyn Read + '_>, PayloadError> {
+        let file = File::open(self.path(uri)?).map_err(PayloadError::Io)?;
+        Ok(Box::new(file))
+    }
+}
+
+#[derive(Debug)]
+pub enum PayloadError {
+    UnsupportedUri(String),
+    Io(io::Error),
+    TooLarge {
+        len: u64,
+        max: u64,
+    },
+    /// The object is not as long as the reference says
+    LengthMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    ChecksumMismatch,
+    NotUtf8,
+    /// The task carries both inline `task_data` and a reference
+    Conflict,
+    /// The task refers to its payload but the service has no source configured
+    NoSource,
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedUri(uri) => write!(f, "unsupported payload uri {uri:?}"),
+            Self::Io(err) => write!(f, "cannot read the payload: {err}"),
+            Self::TooLarge { len, max } => {
+                write!(f, "payload of {len} bytes exceeds the limit of {max}")
+            }
+            Self::LengthMismatch { expected, actual } => {
+                write!(f, "payload is {actual} bytes long, expected {expected}")
+            }
+            Self::ChecksumMismatch => write!(f, "payload does not match its blake2b digest"),
+            Self::NotUtf8 => write!(f, "payload is not UTF-8"),
+            Self::Conflict => write!(f, "task has both task_data and task_data_ref"),
+            Self::NoSource => write!(f, "referenced payloads are not enabled"),
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+impl PayloadRef {
+    /// Streams the object from `source`, checking its length and digest.
+    pub fn fetch(&self, source: &dyn PayloadSource) -> Result<String, PayloadError> {
+        if self.len > MAX_REFERENCED_TASK_DATA_LEN {
+            return Err(PayloadError::TooLarge {
+                len: self.len,
+                max: MAX_REFERENCED_TASK_DATA_LEN,
+            });
+        }
+        // one byte more than announced, to tell a longer object from an exact one
+        let mut reader = source.open(&self.uri)?.take(self.len + 1);
+        let mut state = blake2b_simd::Params::new().hash_length(32).to_state();
+        let mut data = Vec::with_capacity(self.len as usize);
+        let mut chunk = vec![0u8; READ_CHUNK_LEN];
+        loop {
+            let n = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(PayloadError::Io(err)),
+            };
+            state.update(&chunk[..n]);
+            data.extend_from_slice(&chunk[..n]);
+        }
+        if data.len() as u64 != self.len {
+            return Err(PayloadError::LengthMismatch {
+                expected: self.len,
+                actual: data.len() as u64,
+            });
+        }
+        if !state
+            .finalize()
+            .to_hex()
+            .eq_ignore_ascii_case(&self.blake2b)
+        {
+            return Err(PayloadError::ChecksumMismatch);
+        }
+        String::from_utf8(data).map_err(|_| PayloadError::NotUtf8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    struct Objects(HashMap<&'static str, Vec<u8>>);
+
+    impl PayloadSource for Objects {
+        fn open(&self, uri: &str) -> Result<Box<dyn Read + '_>, PayloadError> {
+            match self.0.get(uri) {
+                Some(data) => Ok(Box::new(data.as_slice())),
+                None => Err(PayloadError::UnsupportedUri(uri.to_string())),
+            }
+        }
+    }
+
+    fn reference(uri: &str, data: &[u8]) -> PayloadRef {
+        PayloadRef {
+            uri: uri.to_string(),
+            len: data.len() as u64,
+            blake2b: blake2b_simd::Params::new()
+                .hash_length(32)
+                .hash(data)
+                .to_hex()
+                .to_string(),
+        }
+    }
+
+    #[test]
+    fn test_fetch() {
+        let data = "[1,2,3]".repeat(20_000);
+        let source = Objects(HashMap::from([
+            ("mem://a", data.clone().into_bytes()),
+            ("mem://b", b"\xff\xfe".to_vec()),
+        ]));
+        assert_eq!(
+            reference("mem://a", data.as_bytes())
+                .fetch(&source)
+                .unwrap(),
+            data
+        );
+
+        let mut short = reference("mem://a", data.as_bytes());
+        short.len -= 1;
+        assert!(matches!(
+            short.fetch(&source),
+            Err(PayloadError::LengthMismatch { .. })
+        ));
+        let mut tampered = reference("mem://a", data.as_bytes());
+        tampered.blake2b = reference("mem://a", b"other").blake2b;
+        assert!(matches!(
+            tampered.fetch(&source),
+            Err(PayloadError::ChecksumMismatch)
+        ));
+        assert!(matches!(
+            reference("mem://b", b"\xff\xfe").fetch(&source),
+            Err(PayloadError::NotUtf8)
+        ));
+        let mut huge = reference("mem://a", b"");
+        huge.len = MAX_REFERENCED_TASK_DATA_LEN + 1;
+        assert!(matches!(
+            huge.fetch(&source),
+            Err(PayloadError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_local_paths() {
+        let files = LocalFiles::new("/data");
+        assert_eq!(
+            files.path("file:///batches/17.json").unwrap(),
+            PathBuf::from("/data/batches/17.json")
+        );
+        for uri in [
+            "file:///../etc/passwd",
+            "file:///a/../../b",
+            "file:////etc/passwd",
+            "file:///",
+            "s3://bucket/key",
+        ] {
+            assert!(files.path(uri).is_err(), "{uri}");
+        }
+    }
+}
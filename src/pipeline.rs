@@ -0,0 +1,455 @@
+//! Hash pipelines described in JSON instead of circuit code.
+//!
+//! A pipeline is a list of nodes in topological order. A leaf holds a private field element
+//! (in any encoding accepted by [`crate::field_encoding`]), a hash node hashes the values of
+//! earlier nodes, and `expose` lists the hash nodes whose digests become public inputs, in
+//! order:
+//!
+//! ```json
+//! {
+//!   "nodes": [{"leaf": "1"}, {"leaf": "2"}, {"leaf": "3"}, {"hash": [0, 1]}, {"hash": [3, 2]}],
+//!   "expose": [4]
+//! }
+//! ```
+//!
+//! The layout of a [`PipelineCircuit`] depends on the shape of its pipeline, so keys are
+//! generated per shape; only the leaf values may change between proofs under one key.
+use std::fmt;
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner},
+    plonk::{Circuit, Column, ConstraintSystem, Error, Instance},
+};
+use poseidon::Spec;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    field_encoding::{parse_field, FieldParseErrorKind},
+    main_gate::{AssignedValue, MainGate, MainGateConfig, RegionCtx},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash,
+    specs::BN256_T4_R3,
+};
+
+const T: usize = BN256_T4_R3.width;
+const RATE: usize = BN256_T4_R3.rate;
+const R_F: usize = BN256_T4_R3.r_f;
+const R_P: usize = BN256_T4_R3.r_p;
+
+/// Largest number of nodes accepted in one pipeline.
+pub const MAX_NODES: usize = 4096;
+/// Rows reserved for blinding at the end of the circuit.
+const UNUSABLE_ROWS: usize = 6;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeDescription {
+    Leaf(String),
+    /// Indices of the earlier nodes to hash, in absorption order
+    Hash(Vec<usize>),
+}
+
+/// A pipeline as sent by clients.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PipelineDescription {
+    pub nodes: Vec<NodeDescription>,
+    pub expose: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub enum PipelineError {
+    Json(serde_json::Error),
+    TooManyNodes {
+        nodes: usize,
+        max: usize,
+    },
+    InvalidLeaf {
+        node: usize,
+        kind: FieldParseErrorKind,
+    },
+    EmptyHash {
+        node: usize,
+    },
+    /// A hash node refers to itself or to a later node
+    ForwardReference {
+        node: usize,
+        input: usize,
+    },
+    /// Only hash nodes can be exposed
+    InvalidExpose {
+        node: usize,
+    },
+    TooManyRows {
+        rows: usize,
+        max: usize,
+    },
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(e) => write!(f, "invalid pipeline description: {e}"),
+            Self::TooManyNodes { nodes, max } => {
+                write!(f, "pipeline has {nodes} nodes, at most {max} are allowed")
+            }
+            Self::InvalidLeaf { node, kind } => write!(f, "node {node}: {kind}"),
+            Self::EmptyHash { node } => write!(f, "node {node} hashes no inputs"),
+            Self::ForwardReference { node, input } => {
+                write!(
+                    f,
+                    "node {node} refers to node {input}, which does not precede it"
+                )
+            }
+            Self::InvalidExpose { node } => {
+                write!(f, "exposed node {node} is not a hash node of the pipeline")
+            }
+            Self::TooManyRows { rows, max } => {
+                write!(f, "pipeline needs {rows} rows, the circuit has {max}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node<F> {
+    Leaf(F),
+    Hash(Vec<usize>),
+}
+
+/// A validated pipeline that fits into a circuit of a given size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline<F> {
+    nodes: Vec<Node<F>>,
+    expose: Vec<usize>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> Pipeline<F> {
+    /// Parses and validates a JSON [`PipelineDescription`] for a circuit of `2^k` rows.
+    pub fn from_json(json: &str, k: u32) -> Result<Self, PipelineError> {
+        let desc: PipelineDescription = serde_json::from_str(json).map_err(PipelineError::Json)?;
+        Self::new(desc, k)
+    }
+
+    pub fn new(desc: PipelineDescription, k: u32) -> Result<Self, PipelineError> {
+        if desc.nodes.len() > MAX_NODES {
+            return Err(PipelineError::TooManyNodes {
+                nodes: desc.nodes.len(),
+                max: MAX_NODES,
+            });
+        }
+        let nodes = desc
+            .nodes
+            .into_iter()
+            .enumerate()
+            .map(|(node, desc)| match desc {
+                NodeDescription::Leaf(value) => parse_field(&value)
+                    .map(Node::Leaf)
+                    .map_err(|kind| PipelineError::InvalidLeaf { node, kind }),
+                NodeDescription::Hash(inputs) if inputs.is_empty() => {
+                    Err(PipelineError::EmptyHash { node })
+                }
+                NodeDescription::Hash(inputs) => {
+                    match inputs.iter().find(|input| **input >= node) {
+                        Some(input) => Err(PipelineError::ForwardReference {
+                            node,
+                            input: *input,
+                        }),
+                        None => Ok(Node::Hash(inputs)),
+                    }
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(node) = desc
+            .expose
+            .iter()
+            .find(|node| !matches!(nodes.get(**node), Some(Node::Hash(_))))
+        {
+            return Err(PipelineError::InvalidExpose { node: *node });
+        }
+
+        let pipeline = Self {
+            nodes,
+            expose: desc.expose,
+        };
+        let (rows, max) = (pipeline.num_rows(), (1usize << k) - UNUSABLE_ROWS);
+        if rows > max {
+            return Err(PipelineError::TooManyRows { rows, max });
+        }
+        Ok(pipeline)
+    }
+
+    pub fn nodes(&self) -> &[Node<F>] {
+        &self.nodes
+    }
+
+    /// Rows used by the hash nodes of the pipeline.
+    pub fn num_rows(&self) -> usize {
+        let spec = Spec::<F, T, RATE>::new(R_F, R_P);
+        self.nodes
+            .iter()
+            .map(|node| match node {
+                Node::Leaf(_) => 0,
+                Node::Hash(inputs) => PoseidonChip::num_rows(&spec, inputs.len()),
+            })
+            .sum()
+    }
+
+    /// The value of every node, computed natively.
+    pub fn evaluate(&self) -> Vec<F> {
+        let spec = Spec::<F, T, RATE>::new(R_F, R_P);
+        let mut values = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let value = match node {
+                Node::Leaf(value) => *value,
+                Node::Hash(inputs) => {
+                    let inputs = inputs.iter().map(|i| values[*i]).collect::<Vec<_>>();
+                    hash(&spec, &inputs)
+                }
+            };
+            values.push(value);
+        }
+        values
+    }
+
+    /// The public inputs of the pipeline circuit: the digests of the exposed nodes.
+    pub fn instance(&self) -> Vec<F> {
+        let values = self.evaluate();
+        self.expose.iter().map(|node| values[*node]).collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PipelineCircuitConfig {
+    pconfig: MainGateConfig<T>,
+    instance: Column<Instance>,
+}
+
+/// Proves the exposed digests of a [`Pipeline`] without revealing its leaves.
+pub struct PipelineCircuit<F: PrimeField> {
+    pipeline: Pipeline<F>,
+    message_regions: bool,
+}
+
+impl<F: PrimeField> PipelineCircuit<F> {
+    pub fn new(pipeline: Pipeline<F>) -> Self {
+        Self {
+            pipeline,
+            message_regions: false,
+        }
+    }
+
+    /// Hashes every node in its own region named after the node index, so a failing
+    /// constraint points at the node that caused it.
+    pub fn with_message_regions(mut self) -> Self {
+        self.message_regions = true;
+        self
+    }
+
+    /// Hashes the inputs of one node, copy-constraining inputs that were absorbed before,
+    /// and returns the input cells and the digest.
+    #[allow(clippy::type_complexity)]
+    fn hash_node(
+        ctx: &mut RegionCtx<'_, F>,
+        pchip: &mut PoseidonChip<F, T, RATE>,
+        inputs: &[usize],
+        values: &[F],
+        cells: &[Option<AssignedValue<F>>],
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        pchip.update(inputs.iter().map(|i| values[*i]).collect());
+        let (input_cells, digest) = pchip.squeeze_with_inputs(ctx)?;
+        for (pos, (input, cell)) in inputs.iter().zip(&input_cells).enumerate() {
+            let prev = cells[*input].as_ref().or_else(|| {
+                inputs[..pos]
+                    .iter()
+                    .position(|i| i == input)
+                    .map(|first| &input_cells[first])
+            });
+            if let Some(prev) = prev {
+                ctx.constrain_equal(prev.cell(), cell.cell())?;
+            }
+        }
+        Ok((input_cells, digest))
+    }
+
+    /// Remembers the digest of node `idx` and the cells its inputs were first absorbed from.
+    fn record(
+        cells: &mut [Option<AssignedValue<F>>],
+        idx: usize,
+        inputs: &[usize],
+        (input_cells, digest): (Vec<AssignedValue<F>>, AssignedValue<F>),
+    ) {
+        for (input, cell) in inputs.iter().zip(input_cells) {
+            cells[*input].get_or_insert(cell);
+        }
+        cells[idx] = Some(digest);
+    }
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for PipelineCircuit<F> {
+    type Config = PipelineCircuitConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        let nodes = self
+            .pipeline
+            .nodes
+            .iter()
+            .map(|node| match node {
+                Node::Leaf(_) => Node::Leaf(F::ZERO),
+                Node::Hash(inputs) => Node::Hash(inputs.clone()),
+            })
+            .collect();
+        Self {
+            pipeline: Pipeline {
+                nodes,
+                expose: self.pipeline.expose.clone(),
+            },
+            message_regions: self.message_regions,
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        let instance = meta.instance_column();
+        meta.enable_equality(instance);
+        let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+        let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+        let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+        Self::Config { pconfig, instance }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        let spec = Spec::<F, T, RATE>::new(R_F, R_P);
+        let values = self.pipeline.evaluate();
+        // a leaf gets the cell it is first absorbed from, a hash node its digest
+        let mut cells: Vec<Option<AssignedValue<F>>> = vec![None; values.len()];
+        if self.message_regions {
+            for (idx, node) in self.pipeline.nodes.iter().enumerate() {
+                let Node::Hash(inputs) = node else {
+                    continue;
+                };
+                let hashed = layouter.assign_region(
+                    || format!("hash pipeline: node {idx}"),
+                    |region| {
+                        let ctx = &mut RegionCtx::new(region, 0);
+                        let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone());
+                        Self::hash_node(ctx, &mut pchip, inputs, &values, &cells)
+                    },
+                )?;
+                Self::record(&mut cells, idx, inputs, hashed);
+            }
+        } else {
+            cells = layouter.assign_region(
+                || "hash pipeline",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut cells = vec![None; values.len()];
+                    for (idx, node) in self.pipeline.nodes.iter().enumerate() {
+                        let Node::Hash(inputs) = node else {
+                            continue;
+                        };
+                        let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone());
+                        let hashed = Self::hash_node(ctx, &mut pchip, inputs, &values, &cells)?;
+                        Self::record(&mut cells, idx, inputs, hashed);
+                    }
+                    Ok(cells)
+                },
+            )?;
+        }
+        for (row, node) in self.pipeline.expose.iter().enumerate() {
+            let digest = cells[*node].as_ref().expect("exposed nodes are hash nodes");
+            layouter.constrain_instance(digest.cell(), config.instance, row)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+
+    const K: u32 = 10;
+
+    // four leaves combined pairwise
+    const TREE: &str = r#"{
+        "nodes": [
+            {"leaf": "0"}, {"leaf": "0x01"}, {"leaf": "2"}, {"leaf": "3"},
+            {"hash": [0, 1]}, {"hash": [2, 3]}, {"hash": [4, 5]}
+        ],
+        "expose": [6]
+    }"#;
+
+    #[test]
+    fn test_pipeline_circuit() {
+        let pipeline = Pipeline::<Fr>::from_json(TREE, K).unwrap();
+        let spec = Spec::<Fr, T, RATE>::new(R_F, R_P);
+        let left = hash(&spec, &[Fr::from(0), Fr::from(1)]);
+        let right = hash(&spec, &[Fr::from(2), Fr::from(3)]);
+        let root = hash(&spec, &[left, right]);
+        assert_eq!(pipeline.instance(), vec![root]);
+
+        let circuit = PipelineCircuit::new(pipeline);
+        let prover = MockProver::run(K, &circuit, vec![vec![root]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let prover = MockProver::run(K, &circuit, vec![vec![root + Fr::ONE]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_message_regions() {
+        // leaf 0 is absorbed by two nodes and twice by one
+        let json = r#"{
+            "nodes": [{"leaf": "1"}, {"leaf": "2"}, {"hash": [0, 1]}, {"hash": [0, 0, 2]}],
+            "expose": [3]
+        }"#;
+        let pipeline = Pipeline::<Fr>::from_json(json, K).unwrap();
+        let instance = pipeline.instance();
+        let circuit = PipelineCircuit::new(pipeline).with_message_regions();
+        let prover = MockProver::run(K, &circuit, vec![instance.clone()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let circuit = PipelineCircuit::new(circuit.pipeline);
+        let prover = MockProver::run(K, &circuit, vec![instance]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_pipelines() {
+        let check = |json: &str| Pipeline::<Fr>::from_json(json, K).unwrap_err();
+        assert!(matches!(
+            check(r#"{"nodes": [{"leaf": "1"}, {"hash": [1]}], "expose": [1]}"#),
+            PipelineError::ForwardReference { node: 1, input: 1 }
+        ));
+        assert!(matches!(
+            check(r#"{"nodes": [{"leaf": "1"}, {"hash": []}], "expose": []}"#),
+            PipelineError::EmptyHash { node: 1 }
+        ));
+        assert!(matches!(
+            check(r#"{"nodes": [{"leaf": "1"}, {"hash": [0]}], "expose": [0]}"#),
+            PipelineError::InvalidExpose { node: 0 }
+        ));
+        assert!(matches!(
+            check(r#"{"nodes": [{"leaf": "0xzz"}], "expose": []}"#),
+            PipelineError::InvalidLeaf { node: 0, .. }
+        ));
+        // four hashes of 260 rows each do not fit into 2^10 rows
+        assert!(matches!(
+            check(
+                r#"{"nodes": [{"leaf": "1"}, {"hash": [0]}, {"hash": [1]}, {"hash": [2]},
+                    {"hash": [3]}], "expose": [4]}"#
+            ),
+            PipelineError::TooManyRows { .. }
+        ));
+    }
+}
@@ -0,0 +1,247 @@
+//! The Poseidon2 permutation, natively; [`crate::poseidon2_circuit::Poseidon2Chip`] is its
+//! circuit.
+//!
+//! Poseidon2 keeps the rounds of Poseidon but replaces the dense MDS matrix with two cheap
+//! ones: external (full) rounds multiply by `M_E` and internal (partial) rounds by
+//! `M_I = J + diag(d)`, where `J` is the all-ones matrix. Both are `J` plus a diagonal for
+//! the widths implemented here, so a matrix multiplication is a sum and one product per
+//! element. The state also goes through `M_E` once before the first round.
+//!
+//! Round constants come from the Grain LFSR of the Poseidon reference scripts with the
+//! parameters of the instance: `T` constants per external round and one per internal
+//! round, in round order. Widths 2 and 3 are implemented, with the matrices of the Poseidon2
+//! paper; with [`R_F`] and [`R_P`] the width 3 instance over bn256 is the one of the
+//! reference implementation.
+//!
+//! Hashing absorbs, pads and outputs exactly like [`crate::poseidon_hash::hash`], so the two
+//! permutations are interchangeable behind [`crate::poseidon_hash::HashSpec`]; their
+//! digests differ, and the existing Poseidon digests are unaffected by this module.
+use ff::PrimeField;
+use poseidon_circuit_native::grain::Grain;
+
+use crate::specs::{DigestIndex, Domain};
+
+/// Full rounds of the reference instances over bn256.
+pub const R_F: usize = 8;
+/// Partial rounds of the reference instances over bn256.
+pub const R_P: usize = 56;
+
+/// Round constants of a Poseidon2 instance of width `T`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Poseidon2Spec<F, const T: usize> {
+    r_f: usize,
+    r_p: usize,
+    /// Constants of the external rounds, first half then second half
+    external: Vec<[F; T]>,
+    internal: Vec<F>,
+}
+
+/// One round of a permutation, in the order the permutation runs them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Round<'a, F, const T: usize> {
+    External(&'a [F; T]),
+    Internal(F),
+}
+
+impl<F: PrimeField, const T: usize> Poseidon2Spec<F, T> {
+    /// Generates the constants of `r_f` full and `r_p` partial rounds.
+    ///
+    /// # Panics
+    ///
+    /// For widths other than 2 and 3, and an odd `r_f`.
+    pub fn new(r_f: usize, r_p: usize) -> Self {
+        assert!(
+            T == 2 || T == 3,
+            "Poseidon2 is implemented for widths 2 and 3, not {T}"
+        );
+        assert!(r_f % 2 == 0, "full rounds are split in two halves");
+        let mut grain = Grain::new(F::NUM_BITS, T, r_f, r_p);
+        let mut external = (0..r_f / 2)
+            .map(|_| grain.next_elements())
+            .collect::<Vec<_>>();
+        let internal = (0..r_p).map(|_| grain.next_element()).collect();
+        external.extend((0..r_f / 2).map(|_| grain.next_elements()));
+        Self {
+            r_f,
+            r_p,
+            external,
+            internal,
+        }
+    }
+
+    pub fn r_f(&self) -> usize {
+        self.r_f
+    }
+
+    pub fn r_p(&self) -> usize {
+        self.r_p
+    }
+
+    /// The diagonal `d` of `M_E = J + diag(d)`, the circulant matrix `circ(2, 1, ..)`.
+    pub fn external_diag() -> [F; T] {
+        [F::ONE; T]
+    }
+
+    /// The diagonal `d` of `M_I = J + diag(d)`: `[[2, 1], [1, 3]]` at width 2 and
+    /// `[[2, 1, 1], [1, 2, 1], [1, 1, 3]]` at width 3.
+    pub fn internal_diag() -> [F; T] {
+        let mut diag = [F::ONE; T];
+        diag[T - 1] = F::from(2);
+        diag
+    }
+
+    /// Every round after the initial linear layer, in order.
+    pub fn rounds(&self) -> impl Iterator<Item = Round<'_, F, T>> {
+        let (first, second) = self.external.split_at(self.r_f / 2);
+        first
+            .iter()
+            .map(Round::External)
+            .chain(self.internal.iter().copied().map(Round::Internal))
+            .chain(second.iter().map(Round::External))
+    }
+
+    pub fn permute(&self, state: &mut [F; T]) {
+        linear_layer(state);
+        for round in self.rounds() {
+            round.apply(state);
+        }
+    }
+}
+
+impl<F: PrimeField, const T: usize> Round<'_, F, T> {
+    pub fn apply(&self, state: &mut [F; T]) {
+        match *self {
+            Self::External(constants) => {
+                for (s, c) in state.iter_mut().zip(constants) {
+                    *s = pow5(*s + c);
+                }
+                mix(state, &Poseidon2Spec::<F, T>::external_diag());
+            }
+            Self::Internal(constant) => {
+                state[0] = pow5(state[0] + constant);
+                mix(state, &Poseidon2Spec::<F, T>::internal_diag());
+            }
+        }
+    }
+
+    /// The constants the round adds to the state before its S-boxes.
+    pub fn constants(&self) -> [F; T] {
+        match *self {
+            Self::External(constants) => *constants,
+            Self::Internal(constant) => {
+                let mut constants = [F::ZERO; T];
+                constants[0] = constant;
+                constants
+            }
+        }
+    }
+}
+
+/// Multiplies by `M_E`, as the permutation does before its first round.
+pub(crate) fn linear_layer<F: PrimeField, const T: usize>(state: &mut [F; T]) {
+    mix(state, &Poseidon2Spec::<F, T>::external_diag());
+}
+
+/// Multiplies `state` by `J + diag(diag)`.
+fn mix<F: PrimeField, const T: usize>(state: &mut [F; T], diag: &[F; T]) {
+    let sum = state.iter().fold(F::ZERO, |acc, s| acc + s);
+    for (s, d) in state.iter_mut().zip(diag) {
+        *s = sum + *s * d;
+    }
+}
+
+fn pow5<F: PrimeField>(v: F) -> F {
+    v.square().square() * v
+}
+
+/// Adds a chunk of at most `T - 1` message elements to the rate, and the padding one after
+/// them if the chunk does not fill it.
+fn absorb<F: PrimeField, const T: usize>(state: &mut [F; T], chunk: &[F]) {
+    assert!(chunk.len() < T);
+    for (s, x) in state[1..].iter_mut().zip(chunk) {
+        *s += x;
+    }
+    if chunk.len() < T - 1 {
+        state[1 + chunk.len()] += F::ONE;
+    }
+}
+
+/// Hashes `inputs` with the sponge of [`crate::poseidon_hash::hash`] over Poseidon2.
+pub fn hash<F: PrimeField, const T: usize>(spec: &Poseidon2Spec<F, T>, inputs: &[F]) -> F {
+    hash_to(spec, inputs, DigestIndex::PSE)
+}
+
+/// Hashes like [`hash`] and outputs the state element at `digest`.
+pub fn hash_to<F: PrimeField, const T: usize>(
+    spec: &Poseidon2Spec<F, T>,
+    inputs: &[F],
+    digest: DigestIndex,
+) -> F {
+    assert!(
+        digest.0 < T,
+        "digest index {} out of a width {T} state",
+        digest.0
+    );
+    let mut state = [F::ZERO; T];
+    state[0] = Domain::Pse.capacity();
+    let mut chunks = inputs.chunks(T - 1).collect::<Vec<_>>();
+    // a message filling whole permutations gets one more, absorbing only the padding
+    if inputs.len() % (T - 1) == 0 {
+        chunks.push(&[]);
+    }
+    for chunk in chunks {
+        absorb(&mut state, chunk);
+        spec.permute(&mut state);
+    }
+    state[digest.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff::Field;
+    use halo2curves::bn256::Fr;
+
+    fn fr(hex: &str) -> Fr {
+        let mut repr = [0u8; 32];
+        for (i, byte) in (0..64).step_by(2).rev().enumerate() {
+            repr[i] = u8::from_str_radix(&hex[2..][byte..byte + 2], 16).unwrap();
+        }
+        Fr::from_repr(repr).unwrap()
+    }
+
+    #[test]
+    fn test_reference_permutation() {
+        let spec = Poseidon2Spec::<Fr, 3>::new(R_F, R_P);
+        let mut state = [Fr::from(0), Fr::from(1), Fr::from(2)];
+        spec.permute(&mut state);
+        assert_eq!(
+            state,
+            [
+                fr("0x0bb61d24daca55eebcb1929a82650f328134334da98ea4f847f760054f4a3033"),
+                fr("0x303b6f7c86d043bfcbcc80214f26a30277a15d3f74ca654992defe7ff8d03570"),
+                fr("0x1ed25194542b12eef8617361c3ba7c52e660b145994427cc86296242cf766ec8"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hash() {
+        let spec = Poseidon2Spec::<Fr, 3>::new(R_F, R_P);
+        assert_eq!(spec.rounds().count(), R_F + R_P);
+        let digests = (0..5)
+            .map(|len| hash(&spec, &(0..len).map(Fr::from).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        // padding keeps messages that differ by trailing zeros or by length apart
+        assert_ne!(digests[0], hash(&spec, &[Fr::ZERO]));
+        assert_ne!(hash(&spec, &[Fr::ONE]), hash(&spec, &[Fr::ONE, Fr::ZERO]));
+        for (i, a) in digests.iter().enumerate() {
+            assert!(digests[i + 1..].iter().all(|b| a != b));
+        }
+        let inputs = [Fr::from(7)];
+        assert_ne!(
+            hash_to(&spec, &inputs, DigestIndex::CIRCOM),
+            hash(&spec, &inputs)
+        );
+    }
+}
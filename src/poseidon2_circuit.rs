@@ -0,0 +1,407 @@
+//! Poseidon2 in a circuit, one row per round.
+//!
+//! [`crate::poseidon_circuit::PoseidonChip`] computes one state element per row of the main
+//! gate, `T` rows per round. The matrices of Poseidon2 are cheap enough to constrain the
+//! whole next state of a round at once, so [`Poseidon2Chip`] has gates of its own: every row
+//! holds the state before a round and the gate of its round constrains the state on the
+//! next row. A permutation takes `1 + r_f + r_p` rows, its absorb row included, and the rows
+//! of consecutive permutations follow each other.
+//!
+//! The gates have degree 6, like the main gate with [`crate::main_gate::SboxDegree::Direct`].
+//! A circuit picks the permutation when it configures its columns; natively,
+//! [`crate::poseidon_hash::HashSpec`] computes the digest of either.
+use std::sync::Arc;
+
+use ff::PrimeField;
+use halo2_proofs::{
+    circuit::Value,
+    plonk::{Advice, Column, ConstraintSystem, Error, Expression, Fixed, Selector, VirtualCells},
+    poly::Rotation,
+};
+
+use crate::{
+    main_gate::{AssignedValue, RegionCtx},
+    poseidon2::{self, Poseidon2Spec, Round},
+    specs::{DigestIndex, Domain},
+};
+
+#[derive(Clone, Debug)]
+pub struct Poseidon2Config<const T: usize, const RATE: usize> {
+    pub(crate) state: [Column<Advice>; T],
+    pub(crate) input: [Column<Advice>; RATE],
+    /// Constants added to the state before the round of the row
+    pub(crate) rc: [Column<Fixed>; T],
+    /// 1 where the absorb row adds to the previous state, 0 where it starts from constants
+    pub(crate) q_state: Column<Fixed>,
+    /// 1 where an input column holds a message element
+    pub(crate) q_input: [Column<Fixed>; RATE],
+    pub(crate) q_absorb: Selector,
+    pub(crate) q_external: Selector,
+    pub(crate) q_internal: Selector,
+}
+
+pub struct Poseidon2Chip<F: PrimeField, const T: usize, const RATE: usize> {
+    config: Poseidon2Config<T, RATE>,
+    spec: Arc<Poseidon2Spec<F, T>>,
+    buf: Vec<F>,
+}
+
+impl<F: PrimeField, const T: usize, const RATE: usize> Poseidon2Chip<F, T, RATE> {
+    pub fn new(config: Poseidon2Config<T, RATE>, spec: Poseidon2Spec<F, T>) -> Self {
+        Self {
+            config,
+            spec: Arc::new(spec),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Takes `T + RATE` advice and `T + 1 + RATE` fixed columns.
+    pub fn configure(
+        meta: &mut ConstraintSystem<F>,
+        adv_cols: &mut impl Iterator<Item = Column<Advice>>,
+        fix_cols: &mut impl Iterator<Item = Column<Fixed>>,
+    ) -> Poseidon2Config<T, RATE> {
+        assert_eq!(RATE, T - 1, "the capacity is one element");
+        let state = [0; T].map(|_| adv_cols.next().unwrap());
+        let input = [0; RATE].map(|_| adv_cols.next().unwrap());
+        let rc = [0; T].map(|_| fix_cols.next().unwrap());
+        let q_state = fix_cols.next().unwrap();
+        let q_input = [0; RATE].map(|_| fix_cols.next().unwrap());
+        let q_absorb = meta.selector();
+        let q_external = meta.selector();
+        let q_internal = meta.selector();
+        for column in state.iter().chain(input.iter()) {
+            meta.enable_equality(*column);
+        }
+
+        let pow5 = |v: Expression<F>| {
+            let v2 = v.clone() * v.clone();
+            v2.clone() * v2 * v
+        };
+        // selector * (M * x - next state), with M = J + diag(diag)
+        let transition = |meta: &mut VirtualCells<'_, F>,
+                          q: Expression<F>,
+                          x: Vec<Expression<F>>,
+                          diag: [F; T]| {
+            let sum = x.iter().cloned().reduce(|acc, x| acc + x).unwrap();
+            x.into_iter()
+                .zip(diag)
+                .zip(state)
+                .map(|((x, d), next)| {
+                    let next = meta.query_advice(next, Rotation::next());
+                    q.clone() * (sum.clone() + x * Expression::Constant(d) - next)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        meta.create_gate(
+            "poseidon2 absorb: next = M_E * (q_s * s + q_i * input + rc)",
+            |meta| {
+                let q = meta.query_selector(q_absorb);
+                let q_state = meta.query_fixed(q_state, Rotation::cur());
+                let x = (0..T)
+                    .map(|i| {
+                        let s = meta.query_advice(state[i], Rotation::cur());
+                        let rc = meta.query_fixed(rc[i], Rotation::cur());
+                        let x = q_state.clone() * s + rc;
+                        // state[0] is the capacity element and receives no input
+                        if i == 0 {
+                            return x;
+                        }
+                        let q_input = meta.query_fixed(q_input[i - 1], Rotation::cur());
+                        x + q_input * meta.query_advice(input[i - 1], Rotation::cur())
+                    })
+                    .collect();
+                transition(meta, q, x, Poseidon2Spec::<F, T>::external_diag())
+            },
+        );
+
+        meta.create_gate(
+            "poseidon2 external round: next = M_E * (s + rc)^5",
+            |meta| {
+                let q = meta.query_selector(q_external);
+                let x = (0..T)
+                    .map(|i| {
+                        let s = meta.query_advice(state[i], Rotation::cur());
+                        pow5(s + meta.query_fixed(rc[i], Rotation::cur()))
+                    })
+                    .collect();
+                transition(meta, q, x, Poseidon2Spec::<F, T>::external_diag())
+            },
+        );
+
+        meta.create_gate(
+            "poseidon2 internal round: next = M_I * ((s[0] + rc)^5, s[1..])",
+            |meta| {
+                let q = meta.query_selector(q_internal);
+                let x = (0..T)
+                    .map(|i| {
+                        let s = meta.query_advice(state[i], Rotation::cur());
+                        if i == 0 {
+                            pow5(s + meta.query_fixed(rc[0], Rotation::cur()))
+                        } else {
+                            s
+                        }
+                    })
+                    .collect();
+                transition(meta, q, x, Poseidon2Spec::<F, T>::internal_diag())
+            },
+        );
+
+        Poseidon2Config {
+            state,
+            input,
+            rc,
+            q_state,
+            q_input,
+            q_absorb,
+            q_external,
+            q_internal,
+        }
+    }
+
+    /// Number of rows [`Poseidon2Chip::squeeze`] uses for a message of `len` elements.
+    ///
+    /// Every permutation takes its absorb row and a row per round, a message always needs
+    /// `len / RATE + 1` permutations because of padding, and the final state takes a row.
+    pub fn num_rows(spec: &Poseidon2Spec<F, T>, len: usize) -> usize {
+        (len / RATE + 1) * (1 + spec.r_f() + spec.r_p()) + 1
+    }
+
+    pub fn update(&mut self, inputs: Vec<F>) {
+        self.buf.extend(inputs)
+    }
+
+    pub fn squeeze(&mut self, ctx: &mut RegionCtx<'_, F>) -> Result<AssignedValue<F>, Error> {
+        self.squeeze_with_inputs(ctx).map(|(_, out)| out)
+    }
+
+    /// Squeezes like [`Poseidon2Chip::squeeze`] and also returns the cells the buffered
+    /// elements were absorbed from, in order, so callers can copy-constrain them.
+    #[allow(clippy::type_complexity)]
+    pub fn squeeze_with_inputs(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        self.squeeze_with_inputs_to(ctx, DigestIndex::PSE)
+    }
+
+    /// Squeezes the state element at `digest`, see [`crate::poseidon2::hash_to`].
+    #[allow(clippy::type_complexity)]
+    pub fn squeeze_with_inputs_to(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        digest: DigestIndex,
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        assert!(
+            digest.0 < T,
+            "digest index {} out of a width {T} state",
+            digest.0
+        );
+        let (input_cells, state) = self.absorb_all(ctx)?;
+        Ok((input_cells, state[digest.0].clone()))
+    }
+
+    /// Absorbs the buffered message, padding included, and returns its cells and the state.
+    #[allow(clippy::type_complexity)]
+    fn absorb_all(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+    ) -> Result<(Vec<AssignedValue<F>>, [AssignedValue<F>; T]), Error> {
+        let buf = self.buf.clone();
+        let spec = self.spec.clone();
+        let mut chunks = buf.chunks(RATE).collect::<Vec<_>>();
+        // a message filling whole permutations gets one more, absorbing only the padding
+        if buf.len() % RATE == 0 {
+            chunks.push(&[]);
+        }
+
+        // the absorb row of the first permutation ignores its state cells
+        let mut state = Value::known([F::ZERO; T]);
+        let mut cells = self.assign_state(ctx, state)?;
+        let mut input_cells = Vec::with_capacity(buf.len());
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let first = i == 0;
+            self.config.q_absorb.enable(&mut ctx.region, ctx.offset())?;
+            let q_state = if first { F::ZERO } else { F::ONE };
+            ctx.assign_fixed(|| "absorb: q_state", self.config.q_state, q_state)?;
+            // the initial state and the padding are constants, only the message is advice
+            let mut rc = [F::ZERO; T];
+            if first {
+                rc[0] = Domain::Pse.capacity();
+            }
+            if chunk.len() < RATE {
+                rc[1 + chunk.len()] = F::ONE;
+            }
+            for (j, column) in self.config.input.iter().enumerate() {
+                let element = chunk.get(j).copied();
+                let q_input = if element.is_some() { F::ONE } else { F::ZERO };
+                ctx.assign_fixed(|| "absorb: q_input", self.config.q_input[j], q_input)?;
+                let cell = ctx.assign_advice(
+                    || "absorb: input",
+                    *column,
+                    Value::known(element.unwrap_or(F::ZERO)),
+                )?;
+                if element.is_some() {
+                    input_cells.push(cell);
+                }
+            }
+            state = state.map(|mut state| {
+                if first {
+                    state = [F::ZERO; T];
+                }
+                for (s, c) in state.iter_mut().zip(rc) {
+                    *s += c;
+                }
+                for (s, x) in state[1..].iter_mut().zip(chunk) {
+                    *s += x;
+                }
+                poseidon2::linear_layer(&mut state);
+                state
+            });
+            cells = self.next_row(ctx, rc, state)?;
+
+            for round in spec.rounds() {
+                let selector = match round {
+                    Round::External(_) => &self.config.q_external,
+                    Round::Internal(_) => &self.config.q_internal,
+                };
+                selector.enable(&mut ctx.region, ctx.offset())?;
+                state = state.map(|mut state| {
+                    round.apply(&mut state);
+                    state
+                });
+                cells = self.next_row(ctx, round.constants(), state)?;
+            }
+        }
+        ctx.next();
+        Ok((input_cells, cells))
+    }
+
+    /// Assigns the constants of the current row and moves to the next, which holds `state`.
+    fn next_row(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        rc: [F; T],
+        state: Value<[F; T]>,
+    ) -> Result<[AssignedValue<F>; T], Error> {
+        for (column, c) in self.config.rc.iter().zip(rc) {
+            ctx.assign_fixed(|| "rc", *column, c)?;
+        }
+        ctx.next();
+        self.assign_state(ctx, state)
+    }
+
+    fn assign_state(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        state: Value<[F; T]>,
+    ) -> Result<[AssignedValue<F>; T], Error> {
+        let cells = (0..T)
+            .map(|i| {
+                ctx.assign_advice(
+                    || format!("state[{i}]"),
+                    self.config.state[i],
+                    state.map(|s| s[i]),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(cells.try_into().expect("T cells"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Instance},
+    };
+    use halo2curves::bn256::Fr;
+    use poseidon::Spec;
+
+    use super::*;
+    use crate::{
+        poseidon2::{hash, R_F, R_P},
+        poseidon_circuit::PoseidonChip,
+    };
+
+    const T: usize = 3;
+    const RATE: usize = 2;
+
+    #[derive(Clone, Debug)]
+    struct TestCircuitConfig {
+        config: Poseidon2Config<T, RATE>,
+        instance: Column<Instance>,
+    }
+
+    struct TestCircuit {
+        inputs: Vec<Fr>,
+    }
+
+    impl Circuit<Fr> for TestCircuit {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self { inputs: Vec::new() }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + RATE].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); T + 1 + RATE].map(|_| meta.fixed_column()).into_iter();
+            let config = Poseidon2Chip::configure(meta, &mut adv_cols, &mut fix_cols);
+            Self::Config { config, instance }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let spec = Poseidon2Spec::new(R_F, R_P);
+            let mut chip = Poseidon2Chip::<Fr, T, RATE>::new(config.config, spec);
+            chip.update(self.inputs.clone());
+            let output = layouter.assign_region(
+                || "poseidon2 hash",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    chip.squeeze(ctx)
+                },
+            )?;
+            layouter.constrain_instance(output.cell(), config.instance, 0)
+        }
+    }
+
+    #[test]
+    fn test_mock() {
+        let spec = Poseidon2Spec::<Fr, T>::new(R_F, R_P);
+        for len in [0, 1, 2, 5] {
+            let inputs = (0..len).map(Fr::from).collect::<Vec<_>>();
+            let expected = hash(&spec, &inputs);
+            let circuit = TestCircuit { inputs };
+            let prover = MockProver::run(9, &circuit, vec![vec![expected]]).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "{len} elements");
+            let prover = MockProver::run(9, &circuit, vec![vec![expected + Fr::ONE]]).unwrap();
+            assert!(prover.verify().is_err());
+        }
+    }
+
+    #[test]
+    fn test_num_rows() {
+        let spec = Poseidon2Spec::<Fr, T>::new(R_F, R_P);
+        assert_eq!(
+            Poseidon2Chip::<Fr, T, RATE>::num_rows(&spec, 5),
+            3 * (1 + R_F + R_P) + 1
+        );
+        // a row per round instead of a row per state element and round
+        let poseidon = Spec::<Fr, T, RATE>::new(8, 57);
+        assert!(
+            3 * Poseidon2Chip::<Fr, T, RATE>::num_rows(&spec, 5)
+                < PoseidonChip::num_rows(&poseidon, 5)
+        );
+    }
+}
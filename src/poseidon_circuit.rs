@@ -1,4 +1,4 @@
-use std::convert::TryInto;
+use std::{convert::TryInto, iter, sync::Arc};
 
 use ff::PrimeField;
 use halo2_proofs::{
@@ -6,25 +6,209 @@ use halo2_proofs::{
     plonk::Error,
 };
 use poseidon::Spec;
+use rayon::prelude::*;
 
-use crate::main_gate::{AssignedValue, MainGate, MainGateConfig, RegionCtx};
+use crate::{
+    instructions::PoseidonInstructions,
+    main_gate::{
+        AssignedValue, GateLayout, MainGate, MainGateConfig, RegionCtx, RoundLayout, SboxDegree,
+    },
+    optimized_constants::OptimizedConstants,
+    specs::{DigestIndex, Domain},
+    trace_cache::TraceCache,
+};
+
+/// Layout cost of a Poseidon hash, see [`PoseidonChip::cost`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GateCost {
+    /// Maximum degree of the main gate
+    pub degree: usize,
+    pub advice_columns: usize,
+    pub fixed_columns: usize,
+    pub rows: usize,
+    /// Size of the quotient evaluation domain relative to the rows
+    pub quotient_blowup: usize,
+}
+
+/// Constant cells of first absorb rounds, by state index and round constant, that
+/// [`PoseidonChip::hash_many`] copies into every hash of a batch.
+type SharedCells<F> = Vec<(usize, F, AssignedValue<F>)>;
+
+/// The coefficients of a row of the wide layouts, see [`RoundLayout`]: the next state is
+/// `sum_j(a[i][j] * s[j]^5 + b[i][j] * s[j]) + e[i] * mid^5 + c[i]`, where `mid` is the
+/// output of the main gate row `(q_1, q_5, rc)` of the first of two folded partial rounds.
+struct WideRow<F, const T: usize> {
+    a: [[F; T]; T],
+    b: [[F; T]; T],
+    c: [F; T],
+    e: [F; T],
+    mid: Option<([F; T], [F; T], F)>,
+    /// Index in [`PoseidonChip::permutation_trace`] of the state the row ends with
+    round: usize,
+}
+
+impl<F: PrimeField, const T: usize> WideRow<F, T> {
+    fn new(round: usize) -> Self {
+        Self {
+            a: [[F::ZERO; T]; T],
+            b: [[F::ZERO; T]; T],
+            c: [F::ZERO; T],
+            e: [F::ZERO; T],
+            mid: None,
+            round,
+        }
+    }
+
+    /// The state the row permutes `state` to.
+    fn next(&self, state: [Value<F>; T], mid: Value<F>) -> [Value<F>; T] {
+        let pow_5 = |v: Value<F>| {
+            let v2 = v * v;
+            v2 * v2 * v
+        };
+        let quintic = state.map(pow_5);
+        let mid_5 = pow_5(mid);
+        std::array::from_fn(|i| {
+            let mut out = Value::known(self.c[i]) + mid_5 * Value::known(self.e[i]);
+            for j in 0..T {
+                out = out
+                    + quintic[j] * Value::known(self.a[i][j])
+                    + state[j] * Value::known(self.b[i][j]);
+            }
+            out
+        })
+    }
+}
 
 pub struct PoseidonChip<F: PrimeField, const T: usize, const RATE: usize> {
     main_gate: MainGate<F, T>,
-    spec: Spec<F, T, RATE>,
+    constants: Arc<OptimizedConstants<F, T, RATE>>,
+    /// The state the first permutation starts from, see [`PoseidonChip::with_domain`]
+    initial: [F; T],
     buf: Vec<F>,
+    /// See [`PoseidonChip::with_trace_cache`]
+    trace_cache: Option<Arc<TraceCache<F, T, RATE>>>,
 }
 
 impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE> {
     pub fn new(config: MainGateConfig<T>, spec: Spec<F, T, RATE>) -> Self {
+        Self::from_constants(config, Arc::new(OptimizedConstants::from_spec(&spec)))
+    }
+
+    /// Builds a chip from constants shared with other chips, without touching a [`Spec`].
+    pub fn from_constants(
+        config: MainGateConfig<T>,
+        constants: Arc<OptimizedConstants<F, T, RATE>>,
+    ) -> Self {
         let main_gate: MainGate<F, T> = MainGate::new(config);
         Self {
             main_gate,
-            spec,
+            constants,
+            initial: poseidon::State::<F, T>::default().words(),
             buf: Vec::new(),
+            trace_cache: None,
         }
     }
 
+    /// Starts the sponge from the capacity of `domain` rather than from [`Domain::Pse`].
+    pub fn with_domain(mut self, domain: Domain) -> Self {
+        self.initial[0] = domain.capacity();
+        self
+    }
+
+    /// Takes the values of the permutations in `cache` rather than computing them, and
+    /// leaves the ones it computes there, see [`crate::trace_cache`]. Panics if the cache
+    /// holds traces of another spec.
+    pub fn with_trace_cache(mut self, cache: Arc<TraceCache<F, T, RATE>>) -> Self {
+        assert!(
+            cache.serves(&self.constants),
+            "a trace cache of another spec"
+        );
+        self.trace_cache = Some(cache);
+        self
+    }
+
+    /// What hashing a message of `len` elements costs when the gate is configured with
+    /// `sbox`; staging the S-box trades advice columns for degree and leaves the rows alone.
+    pub fn cost(spec: &Spec<F, T, RATE>, len: usize, sbox: SboxDegree) -> GateCost {
+        Self::cost_with_layout(spec, len, GateLayout::default().with_sbox(sbox))
+    }
+
+    /// [`PoseidonChip::cost`] for a gate configured with `layout`; the wide round layouts
+    /// trade fixed columns for rows and leave the degree alone.
+    pub fn cost_with_layout(spec: &Spec<F, T, RATE>, len: usize, layout: GateLayout) -> GateCost {
+        let degree = layout.degree();
+        GateCost {
+            degree,
+            advice_columns: layout.advice_columns(T),
+            fixed_columns: layout.fixed_columns(T),
+            rows: Self::num_rows_with_layout(spec, len, layout.rounds()),
+            quotient_blowup: (degree - 1).next_power_of_two(),
+        }
+    }
+
+    /// Number of rows [`PoseidonChip::squeeze`] uses for a message of `len` elements.
+    ///
+    /// Every permutation takes `T` rows for the input round and `T` rows per full and partial
+    /// round, and a message always needs `len / RATE + 1` permutations because of padding.
+    pub fn num_rows(spec: &Spec<F, T, RATE>, len: usize) -> usize {
+        Self::num_rows_squeezing(spec, len, 1)
+    }
+
+    /// [`PoseidonChip::num_rows`] for a gate configured with the `rounds` layout.
+    pub fn num_rows_with_layout(spec: &Spec<F, T, RATE>, len: usize, rounds: RoundLayout) -> usize {
+        (len / RATE + 1) * Self::permutation_rows(spec, rounds)
+    }
+
+    /// [`PoseidonChip::num_rows`] in the layout of the gate of this chip.
+    pub fn rows(&self, len: usize) -> usize {
+        let (r_f, r_p) = (self.constants.r_f, self.constants.r_p());
+        (len / RATE + 1) * Self::rows_per_permutation(r_f, r_p, self.main_gate.config().rounds)
+    }
+
+    fn permutation_rows(spec: &Spec<F, T, RATE>, rounds: RoundLayout) -> usize {
+        let (r_f, r_p) = (spec.r_f(), spec.constants().partial().len());
+        Self::rows_per_permutation(r_f, r_p, rounds)
+    }
+
+    /// Rows of one permutation in the `rounds` layout. The wide layouts take the `T` rows of
+    /// the input round, one per wide row, and one holding the output.
+    fn rows_per_permutation(r_f: usize, r_p: usize, rounds: RoundLayout) -> usize {
+        match rounds {
+            RoundLayout::Narrow => (1 + r_f + r_p) * T,
+            RoundLayout::Wide => T + r_f + r_p + 1,
+            RoundLayout::Folded => T + r_f + (r_p + 1) / 2 + 1,
+        }
+    }
+
+    /// Number of rows [`PoseidonChip::squeeze_n`] uses for `outputs` elements of a message
+    /// of `len` elements; every `RATE` outputs after the first cost another permutation.
+    pub fn num_rows_squeezing(spec: &Spec<F, T, RATE>, len: usize, outputs: usize) -> usize {
+        let permutations = len / RATE + 1 + outputs.saturating_sub(1) / RATE;
+        permutations * Self::permutation_rows(spec, RoundLayout::Narrow)
+    }
+
+    /// Number of rows [`PoseidonChip::hash_many`] uses for messages of `lens` elements.
+    ///
+    /// The first absorb round of a message has `T - min(len, RATE)` rows that absorb no
+    /// element; the batch assigns each distinct one of them once, so every message after the
+    /// first of its length saves up to that many rows over [`PoseidonChip::num_rows`].
+    pub fn num_rows_many(spec: &Spec<F, T, RATE>, lens: &[usize]) -> usize {
+        let mut shared = Vec::new();
+        let mut rows = 0;
+        for &len in lens {
+            let absorbed = len.min(RATE);
+            rows += Self::num_rows(spec, len) - (T - absorbed);
+            for state_idx in iter::once(0).chain(absorbed + 1..T) {
+                let key = (state_idx, state_idx == absorbed + 1);
+                if !shared.contains(&key) {
+                    shared.push(key);
+                    rows += 1;
+                }
+            }
+        }
+        rows
+    }
+
     pub fn next_state_val(
         state: [Value<F>; T],
         q_1: [F; T],
@@ -43,6 +227,15 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
         out * Value::known((-q_o).invert().unwrap())
     }
 
+    /// [`PoseidonChip::next_state_val`] of known values, for rounds whose `q_o` is `-1`.
+    fn next_state(state: &[F; T], q_1: [F; T], q_5: [F; T], rc: F) -> F {
+        let mut out = rc;
+        for ((s, q1), q5) in state.iter().zip(q_1).zip(q_5) {
+            out += s.square().square() * s * q5 + *s * q1;
+        }
+        out
+    }
+
     pub fn pre_round(
         &self,
         ctx: &mut RegionCtx<'_, F>,
@@ -50,47 +243,97 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
         state_idx: usize,
         state: &[AssignedValue<F>; T],
     ) -> Result<AssignedValue<F>, Error> {
-        assert!(inputs.len() <= RATE);
-        let s_val = state[state_idx].value().copied();
-
-        let inputs = std::iter::once(F::ZERO)
-            .chain(inputs)
-            .chain(std::iter::once(F::ONE))
-            .chain(std::iter::repeat(F::ZERO))
-            .take(T)
-            .collect::<Vec<_>>();
-        let input_val = Value::known(inputs[state_idx]);
+        self.pre_round_with_input(ctx, inputs, state_idx, state)
+            .map(|(_, out)| out)
+    }
 
-        let constants = self.spec.constants().start();
-        let pre_constants = constants[0];
-        let rc_val = pre_constants[state_idx];
+    // same as pre_round, but also returns the cell holding the absorbed input
+    pub fn pre_round_with_input(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: Vec<F>,
+        state_idx: usize,
+        state: &[AssignedValue<F>; T],
+    ) -> Result<(AssignedValue<F>, AssignedValue<F>), Error> {
+        self.absorb_round(ctx, inputs, state_idx, Some(state), None)
+    }
 
-        let out_val = s_val + input_val + Value::known(rc_val);
+    /// Adds the input of `state_idx` and the round constant to the previous state, or to the
+    /// initial state if there is none.
+    ///
+    /// Only message elements are advice: the padding and the initial state are folded into
+    /// the round constant, so they are fixed by the verifying key rather than chosen by the
+    /// prover. `known` is the output if it was computed ahead, see [`PoseidonChip::trace`].
+    fn absorb_round(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: Vec<F>,
+        state_idx: usize,
+        state: Option<&[AssignedValue<F>; T]>,
+        known: Option<F>,
+    ) -> Result<(AssignedValue<F>, AssignedValue<F>), Error> {
+        assert!(inputs.len() <= RATE);
+        // state[0] is the capacity element and receives no input
+        let is_message = (1..=inputs.len()).contains(&state_idx);
+        let input_val = if is_message {
+            inputs[state_idx - 1]
+        } else {
+            F::ZERO
+        };
+        let rc_val = self.absorb_constant(inputs.len(), state_idx);
+        // the initial state is a constant, the state cell of the first round stays unused
+        let (s_val, q_1, rc_val) = match state {
+            Some(state) => (state[state_idx].value().copied(), F::ONE, rc_val),
+            None => (
+                Value::known(F::ZERO),
+                F::ZERO,
+                rc_val + self.initial[state_idx],
+            ),
+        };
+        let out_val = match known {
+            Some(out) => Value::known(out),
+            None => s_val + Value::known(input_val + rc_val),
+        };
 
         let si = ctx.assign_advice(
             || "first round: state",
             self.main_gate.config().state[state_idx],
             s_val,
         )?;
-        ctx.constrain_equal(state[state_idx].cell(), si.cell())?;
+        if let Some(state) = state {
+            ctx.constrain_equal(state[state_idx].cell(), si.cell())?;
+        }
 
-        ctx.assign_advice(
+        let input = ctx.assign_advice(
             || "pre_round: input",
             self.main_gate.config().input,
-            input_val,
+            Value::known(input_val),
         )?;
         ctx.assign_fixed(
             || "pre_round: q_1",
             self.main_gate.config().q_1[state_idx],
-            F::ONE,
+            q_1,
         )?;
-        ctx.assign_fixed(|| "pre_round: q_i", self.main_gate.config().q_i, F::ONE)?;
+        if is_message {
+            ctx.assign_fixed(|| "pre_round: q_i", self.main_gate.config().q_i, F::ONE)?;
+        }
         ctx.assign_fixed(|| "pre_round: q_o", self.main_gate.config().q_o, -F::ONE)?;
         ctx.assign_fixed(|| "pre_round: rc", self.main_gate.config().rc, rc_val)?;
         let out = ctx.assign_advice(|| "pre_round: out", self.main_gate.config().out, out_val)?;
 
         ctx.next();
-        Ok(out)
+        Ok((input, out))
+    }
+
+    /// Round constant of the absorb row of `state_idx`, with the padding after `num_inputs`
+    /// elements folded in.
+    fn absorb_constant(&self, num_inputs: usize, state_idx: usize) -> F {
+        let padding = if state_idx == num_inputs + 1 {
+            F::ONE
+        } else {
+            F::ZERO
+        };
+        self.constants.start[0][state_idx] + padding
     }
 
     // round_idx \in [0; r_f - 1] indicates the round index of either first half full or second half full
@@ -102,16 +345,21 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
         state_idx: usize,
         state: &[AssignedCell<F, F>; T],
     ) -> Result<AssignedCell<F, F>, Error> {
-        let mut state_vals = [Value::known(F::ZERO); T];
-        let q_1_vals = [F::ZERO; T];
-        let mut q_5_vals = [F::ZERO; T];
-        let q_o_val = -F::ONE;
+        self.assign_full_round(ctx, is_first_half_full, round_idx, state_idx, state, None)
+    }
 
-        let r_f = self.spec.r_f() / 2;
+    /// The `q_5` coefficients and round constant of the row of `state_idx` in a full round.
+    fn full_round_coeffs(
+        &self,
+        is_first_half_full: bool,
+        round_idx: usize,
+        state_idx: usize,
+    ) -> ([F; T], F) {
+        let r_f = self.constants.r_f / 2;
         let constants = if is_first_half_full {
-            self.spec.constants().start()
+            &self.constants.start
         } else {
-            self.spec.constants().end()
+            &self.constants.end
         };
         let rcs = if is_first_half_full {
             constants[round_idx + 1]
@@ -122,20 +370,36 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
         };
 
         let mds = if is_first_half_full && round_idx == r_f - 1 {
-            self.spec.mds_matrices().pre_sparse_mds().rows()
+            &self.constants.pre_sparse_mds
         } else {
-            self.spec.mds_matrices().mds().rows()
+            &self.constants.mds
         };
         let mds_row = mds[state_idx];
+        let rc_val = mds_row.iter().zip(rcs).map(|(mij, cj)| *mij * cj).sum();
+        (mds_row, rc_val)
+    }
+
+    /// [`PoseidonChip::full_round`], assigning `known` as the output if it was computed
+    /// ahead, see [`PoseidonChip::trace`].
+    fn assign_full_round(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        is_first_half_full: bool,
+        round_idx: usize,
+        state_idx: usize,
+        state: &[AssignedCell<F, F>; T],
+        known: Option<F>,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        let mut state_vals = [Value::known(F::ZERO); T];
+        let q_1_vals = [F::ZERO; T];
+        let q_o_val = -F::ONE;
+        let (q_5_vals, rc_val) = self.full_round_coeffs(is_first_half_full, round_idx, state_idx);
 
-        let mut rc_val = F::ZERO;
-        for (j, (mij, cj)) in mds_row.iter().zip(rcs).enumerate() {
-            rc_val += *mij * cj;
-            q_5_vals[j] = *mij;
+        for (j, q_5) in q_5_vals.into_iter().enumerate() {
             ctx.assign_fixed(
                 || format!("full_round {}: q_5", round_idx),
                 self.main_gate.config().q_5[j],
-                q_5_vals[j],
+                q_5,
             )?;
         }
 
@@ -147,6 +411,8 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
                 s.value().copied(),
             )?;
             ctx.constrain_equal(s.cell(), si.cell())?;
+            self.main_gate
+                .assign_sbox_powers(ctx, i, s.value().copied())?;
         }
 
         ctx.assign_fixed(
@@ -159,7 +425,10 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
             self.main_gate.config().q_o,
             q_o_val,
         )?;
-        let out_val = Self::next_state_val(state_vals, q_1_vals, q_5_vals, q_o_val, rc_val);
+        let out_val = match known {
+            Some(out) => Value::known(out),
+            None => Self::next_state_val(state_vals, q_1_vals, q_5_vals, q_o_val, rc_val),
+        };
         let out = ctx.assign_advice(
             || format!("full_round {}: out", round_idx),
             self.main_gate.config().out,
@@ -176,17 +445,40 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
         state_idx: usize,
         state: &[AssignedValue<F>; T],
     ) -> Result<AssignedValue<F>, Error> {
-        let mut state_vals = [Value::known(F::ZERO); T];
+        self.assign_partial_round(ctx, round_idx, state_idx, state, None)
+    }
+
+    /// The `q_1` and `q_5` coefficients and round constant of the row of `state_idx` in a
+    /// partial round: the first row applies the sparse row, the others its `col_hat`.
+    fn partial_round_coeffs(&self, round_idx: usize, state_idx: usize) -> ([F; T], [F; T], F) {
         let mut q_1_vals = [F::ZERO; T];
         let mut q_5_vals = [F::ZERO; T];
-        let q_o_val = -F::ONE;
-
-        let constants = self.spec.constants().partial();
-        let rc = constants[round_idx];
+        let rc = self.constants.partial[round_idx];
+        let (row, col_hat) = &self.constants.sparse[round_idx];
+        if state_idx == 0 {
+            q_5_vals[0] = row[0];
+            q_1_vals[1..].copy_from_slice(&row[1..]);
+            (q_1_vals, q_5_vals, row[0] * rc)
+        } else {
+            q_5_vals[0] = col_hat[state_idx - 1];
+            q_1_vals[state_idx] = F::ONE;
+            (q_1_vals, q_5_vals, col_hat[state_idx - 1] * rc)
+        }
+    }
 
-        let sparse_mds = self.spec.mds_matrices().sparse_matrices();
-        let row = sparse_mds[round_idx].row();
-        let col_hat = sparse_mds[round_idx].col_hat();
+    /// [`PoseidonChip::partial_round`], assigning `known` as the output if it was computed
+    /// ahead, see [`PoseidonChip::trace`].
+    fn assign_partial_round(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        round_idx: usize,
+        state_idx: usize,
+        state: &[AssignedValue<F>; T],
+        known: Option<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        let mut state_vals = [Value::known(F::ZERO); T];
+        let q_o_val = -F::ONE;
+        let (q_1_vals, q_5_vals, rc_val) = self.partial_round_coeffs(round_idx, state_idx);
 
         for (i, s) in state.iter().enumerate() {
             state_vals[i] = s.value().copied();
@@ -197,51 +489,38 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
             )?;
             ctx.constrain_equal(s.cell(), si.cell())?;
         }
+        // only s[0] goes through the S-box in a partial round
+        self.main_gate
+            .assign_sbox_powers(ctx, 0, state[0].value().copied())?;
 
-        let rc_val;
-        if state_idx == 0 {
-            q_5_vals[0] = row[0];
-            ctx.assign_fixed(
-                || format!("partial_round {}: q_5", round_idx),
-                self.main_gate.config().q_5[0],
-                q_5_vals[0],
-            )?;
-            rc_val = row[0] * rc;
-            ctx.assign_fixed(
-                || format!("partial_round {}: rc", round_idx),
-                self.main_gate.config().rc,
-                rc_val,
-            )?;
-            for j in 1..T {
-                q_1_vals[j] = row[j];
-                ctx.assign_fixed(
-                    || format!("partial_round {}: q_1", round_idx),
-                    self.main_gate.config().q_1[j],
-                    q_1_vals[j],
-                )?;
-            }
+        ctx.assign_fixed(
+            || format!("partial_round {}: q_5", round_idx),
+            self.main_gate.config().q_5[0],
+            q_5_vals[0],
+        )?;
+        // the first row applies the sparse row to every other element, the others add one
+        let q_1_cols = if state_idx == 0 {
+            1..T
         } else {
-            q_5_vals[0] = col_hat[state_idx - 1];
-            q_1_vals[state_idx] = F::ONE;
-            ctx.assign_fixed(
-                || format!("partial_round {}: q_5", round_idx),
-                self.main_gate.config().q_5[0],
-                q_5_vals[0],
-            )?;
+            state_idx..state_idx + 1
+        };
+        for j in q_1_cols {
             ctx.assign_fixed(
                 || format!("partial_round {}: q_1", round_idx),
-                self.main_gate.config().q_1[state_idx],
-                q_1_vals[state_idx],
-            )?;
-            rc_val = col_hat[state_idx - 1] * rc;
-            ctx.assign_fixed(
-                || format!("partial_round {}, rc", round_idx),
-                self.main_gate.config().rc,
-                rc_val,
+                self.main_gate.config().q_1[j],
+                q_1_vals[j],
             )?;
         }
+        ctx.assign_fixed(
+            || format!("partial_round {}: rc", round_idx),
+            self.main_gate.config().rc,
+            rc_val,
+        )?;
 
-        let out_val = Self::next_state_val(state_vals, q_1_vals, q_5_vals, -F::ONE, rc_val);
+        let out_val = match known {
+            Some(out) => Value::known(out),
+            None => Self::next_state_val(state_vals, q_1_vals, q_5_vals, -F::ONE, rc_val),
+        };
         ctx.assign_fixed(
             || format!("full_round {}: q_o", round_idx),
             self.main_gate.config().q_o,
@@ -262,24 +541,83 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
         inputs: Vec<F>,
         init_state: &[AssignedValue<F>; T],
     ) -> Result<[AssignedValue<F>; T], Error> {
+        self.permutation_with_inputs(ctx, inputs, init_state)
+            .map(|(state, _)| state)
+    }
+
+    // same as permutation, but also returns the cells holding the absorbed inputs
+    #[allow(clippy::type_complexity)]
+    pub fn permutation_with_inputs(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: Vec<F>,
+        init_state: &[AssignedValue<F>; T],
+    ) -> Result<([AssignedValue<F>; T], Vec<AssignedValue<F>>), Error> {
+        self.permute(ctx, inputs, Some(init_state), None, None)
+    }
+
+    /// Permutes the previous state, or the initial state if there is none, with `inputs`.
+    ///
+    /// Starting from the initial state, absorb rows without an element only hold constants;
+    /// with `shared`, each is taken from there if an earlier hash assigned it already. With
+    /// `trace`, the outputs are those of [`PoseidonChip::permutation_trace`] rather than
+    /// computed from the cells.
+    #[allow(clippy::type_complexity)]
+    fn permute(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: Vec<F>,
+        init_state: Option<&[AssignedValue<F>; T]>,
+        mut shared: Option<&mut SharedCells<F>>,
+        trace: Option<&[[F; T]]>,
+    ) -> Result<([AssignedValue<F>; T], Vec<AssignedValue<F>>), Error> {
+        let known = |round: usize, state_idx: usize| trace.map(|trace| trace[round][state_idx]);
+        let num_inputs = inputs.len();
         let mut state = Vec::new();
+        let mut input_cells = Vec::new();
         for i in 0..T {
-            let si = self.pre_round(ctx, inputs.clone(), i, init_state)?;
+            // state[0] is the capacity element and receives no input
+            let is_message = (1..=num_inputs).contains(&i);
+            if let (None, false, Some(shared)) = (init_state, is_message, shared.as_deref_mut()) {
+                let rc = self.absorb_constant(num_inputs, i);
+                let cached = shared.iter().find(|(j, c, _)| *j == i && *c == rc);
+                let si = match cached {
+                    Some((_, _, cell)) => cell.clone(),
+                    None => {
+                        let (_, si) =
+                            self.absorb_round(ctx, inputs.clone(), i, None, known(0, i))?;
+                        shared.push((i, rc, si.clone()));
+                        si
+                    }
+                };
+                state.push(si);
+                continue;
+            }
+            let (input, si) = self.absorb_round(ctx, inputs.clone(), i, init_state, known(0, i))?;
+            if is_message {
+                input_cells.push(input);
+            }
             state.push(si);
         }
 
-        let r_f = self.spec.r_f() / 2;
-        let r_p = self.spec.constants().partial().len();
+        if self.main_gate.config().rounds != RoundLayout::Narrow {
+            let state = self.wide_rounds(ctx, state[..].try_into().unwrap(), trace)?;
+            return Ok((state, input_cells));
+        }
+
+        let r_f = self.constants.r_f / 2;
+        let r_p = self.constants.r_p();
 
         for round_idx in 0..r_f {
             let mut next_state = Vec::new();
             for state_idx in 0..T {
-                let si = self.full_round(
+                let si = self.assign_full_round(
                     ctx,
                     true,
                     round_idx,
                     state_idx,
                     state[..].try_into().unwrap(),
+                    known(1 + round_idx, state_idx),
                 )?;
                 next_state.push(si);
             }
@@ -289,8 +627,13 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
         for round_idx in 0..r_p {
             let mut next_state = Vec::new();
             for state_idx in 0..T {
-                let si =
-                    self.partial_round(ctx, round_idx, state_idx, state[..].try_into().unwrap())?;
+                let si = self.assign_partial_round(
+                    ctx,
+                    round_idx,
+                    state_idx,
+                    state[..].try_into().unwrap(),
+                    known(1 + r_f + round_idx, state_idx),
+                )?;
                 next_state.push(si);
             }
             state = next_state;
@@ -299,19 +642,142 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
         for round_idx in 0..r_f {
             let mut next_state = Vec::new();
             for state_idx in 0..T {
-                let si = self.full_round(
+                let si = self.assign_full_round(
                     ctx,
                     false,
                     round_idx,
                     state_idx,
                     state[..].try_into().unwrap(),
+                    known(1 + r_f + r_p + round_idx, state_idx),
                 )?;
                 next_state.push(si);
             }
             state = next_state;
         }
         let res: [AssignedValue<F>; T] = state.try_into().unwrap();
-        Ok(res)
+        Ok((res, input_cells))
+    }
+
+    /// The rows of the rounds of a permutation in the wide layouts, two partial rounds per
+    /// row if `folded`.
+    fn wide_rows(&self, folded: bool) -> Vec<WideRow<F, T>> {
+        let r_f = self.constants.r_f / 2;
+        let r_p = self.constants.r_p();
+        let full = |is_first_half_full, round_idx, round| {
+            let mut row = WideRow::new(round);
+            for i in 0..T {
+                (row.a[i], row.c[i]) = self.full_round_coeffs(is_first_half_full, round_idx, i);
+            }
+            row
+        };
+        let mut rows = (0..r_f)
+            .map(|round_idx| full(true, round_idx, 1 + round_idx))
+            .collect::<Vec<_>>();
+        let mut round_idx = 0;
+        while round_idx < r_p {
+            if folded && round_idx + 1 < r_p {
+                rows.push(self.folded_partial_rounds(round_idx, 1 + r_f + round_idx + 1));
+                round_idx += 2;
+                continue;
+            }
+            let mut row = WideRow::new(1 + r_f + round_idx);
+            for i in 0..T {
+                (row.b[i], row.a[i], row.c[i]) = self.partial_round_coeffs(round_idx, i);
+            }
+            rows.push(row);
+            round_idx += 1;
+        }
+        rows.extend((0..r_f).map(|round_idx| full(false, round_idx, 1 + r_f + r_p + round_idx)));
+        rows
+    }
+
+    /// Partial rounds `round_idx` and `round_idx + 1` in one row ending with the state of
+    /// `round`. The main gate witnesses the first output of the first round as `mid`; its
+    /// other outputs are linear in the state, so they are substituted into the second round.
+    fn folded_partial_rounds(&self, round_idx: usize, round: usize) -> WideRow<F, T> {
+        let first = (0..T)
+            .map(|j| self.partial_round_coeffs(round_idx, j))
+            .collect::<Vec<_>>();
+        let mut row = WideRow::new(round);
+        for i in 0..T {
+            let (q_1, q_5, rc) = self.partial_round_coeffs(round_idx + 1, i);
+            debug_assert_eq!(q_1[0], F::ZERO, "s[0] only enters a partial round quintic");
+            row.e[i] = q_5[0];
+            row.c[i] = rc;
+            for (j, (b_j, a_j, c_j)) in first.iter().enumerate().skip(1) {
+                row.c[i] += q_1[j] * c_j;
+                for l in 0..T {
+                    row.a[i][l] += q_1[j] * a_j[l];
+                    row.b[i][l] += q_1[j] * b_j[l];
+                }
+            }
+        }
+        row.mid = Some(first[0]);
+        row
+    }
+
+    /// The rounds of a permutation of `state` in the wide layouts: every row holds the state
+    /// and the next row the state the round permutes it to, the last the output. `trace` is
+    /// as for [`PoseidonChip::permute`].
+    fn wide_rounds(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        state: &[AssignedValue<F>; T],
+        trace: Option<&[[F; T]]>,
+    ) -> Result<[AssignedValue<F>; T], Error> {
+        let config = self.main_gate.config();
+        let wide = config.wide.as_ref().expect("a wide round layout");
+        let mut cells = Vec::with_capacity(T);
+        for (i, s) in state.iter().enumerate() {
+            let si =
+                ctx.assign_advice(|| "wide round: state", config.state[i], s.value().copied())?;
+            ctx.constrain_equal(s.cell(), si.cell())?;
+            cells.push(si);
+        }
+
+        for row in self.wide_rows(config.rounds == RoundLayout::Folded) {
+            let vals: [Value<F>; T] = std::array::from_fn(|i| cells[i].value().copied());
+            for i in 0..T {
+                for j in 0..T {
+                    ctx.assign_fixed(|| "wide round: a", wide.a[i][j], row.a[i][j])?;
+                    ctx.assign_fixed(|| "wide round: b", wide.b[i][j], row.b[i][j])?;
+                }
+                ctx.assign_fixed(|| "wide round: c", wide.c[i], row.c[i])?;
+                if let Some(e) = &wide.e {
+                    ctx.assign_fixed(|| "wide round: e", e[i], row.e[i])?;
+                }
+                self.main_gate.assign_sbox_powers(ctx, i, vals[i])?;
+            }
+            ctx.assign_fixed(|| "wide round: q_next", wide.q_next, -F::ONE)?;
+
+            let mut mid = Value::known(F::ZERO);
+            if let Some((q_1, q_5, rc)) = row.mid {
+                mid = match trace {
+                    Some(trace) => Value::known(trace[row.round - 1][0]),
+                    None => Self::next_state_val(vals, q_1, q_5, -F::ONE, rc),
+                };
+                for (j, q_1) in q_1.into_iter().enumerate() {
+                    ctx.assign_fixed(|| "folded round: q_1", config.q_1[j], q_1)?;
+                }
+                ctx.assign_fixed(|| "folded round: q_5", config.q_5[0], q_5[0])?;
+                ctx.assign_fixed(|| "folded round: rc", config.rc, rc)?;
+                ctx.assign_fixed(|| "folded round: q_o", config.q_o, -F::ONE)?;
+                ctx.assign_advice(|| "folded round: mid", config.out, mid)?;
+                self.main_gate.assign_out_powers(ctx, mid)?;
+            }
+            let next = match trace {
+                Some(trace) => trace[row.round].map(Value::known),
+                None => row.next(vals, mid),
+            };
+
+            ctx.next();
+            cells.clear();
+            for (i, val) in next.into_iter().enumerate() {
+                cells.push(ctx.assign_advice(|| "wide round: state", config.state[i], val)?);
+            }
+        }
+        ctx.next();
+        Ok(cells.try_into().unwrap())
     }
 
     pub fn update(&mut self, inputs: Vec<F>) {
@@ -319,31 +785,292 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
     }
 
     pub fn squeeze(&mut self, ctx: &mut RegionCtx<'_, F>) -> Result<AssignedValue<F>, Error> {
-        let buf = self.buf.clone();
-        let exact = buf.len() % RATE == 0;
+        self.squeeze_with_inputs(ctx).map(|(_, out)| out)
+    }
+
+    /// Squeezes like [`PoseidonChip::squeeze`] and also returns the cells the buffered
+    /// elements were absorbed from, in order, so callers can copy-constrain them.
+    #[allow(clippy::type_complexity)]
+    pub fn squeeze_with_inputs(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        self.squeeze_with_inputs_to(ctx, DigestIndex::PSE)
+    }
 
-        let mut state: [_; T] = self
-            .main_gate
-            .config()
-            .state
+    /// Hashes `inputs` under `domain`, as [`crate::poseidon_hash::hash_with_domain`] does,
+    /// and returns the cells the inputs were absorbed from along with the digest.
+    #[allow(clippy::type_complexity)]
+    pub fn hash_with_domain(
+        self,
+        ctx: &mut RegionCtx<'_, F>,
+        domain: Domain,
+        inputs: &[F],
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        let mut chip = self.with_domain(domain);
+        chip.update(inputs.to_vec());
+        chip.squeeze_with_inputs(ctx)
+    }
+
+    /// Hashes every message of `messages` under the domain of the chip, one after another in
+    /// the region of `ctx`, and returns the input cells and digest of each.
+    ///
+    /// Unlike squeezing each message with a chip of its own, the constant absorb rows of the
+    /// batch are assigned once, see [`PoseidonChip::num_rows_many`]; the buffer of the chip
+    /// is left alone.
+    ///
+    /// The messages are independent, so the values of their rows are computed ahead of
+    /// assigning them, see [`PoseidonChip::trace`]; with the `parallel-witness` feature, on
+    /// the rayon pool, one message per task.
+    #[allow(clippy::type_complexity)]
+    pub fn hash_many(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        messages: &[Vec<F>],
+    ) -> Result<Vec<(Vec<AssignedValue<F>>, AssignedValue<F>)>, Error> {
+        self.main_gate.config().annotate_columns(&mut ctx.region);
+        let traces = self.traces(messages, cfg!(feature = "parallel-witness"));
+        let mut shared = Vec::new();
+        messages
             .iter()
-            .zip(poseidon::State::<F, T>::default().words().iter())
-            .map(|(col, val)| ctx.assign_advice(|| "initial state", *col, Value::known(*val)))
-            .collect::<Result<Vec<_>, _>>()?
-            .try_into()
-            .expect("Safe, because zip two arrays");
-
-        for chunk in buf.chunks(RATE) {
-            let next_state = self.permutation(ctx, chunk.to_vec(), &state)?;
-            state = next_state;
+            .zip(&traces)
+            .map(|(message, trace)| {
+                let (input_cells, state) =
+                    self.absorb(ctx, message, Some(&mut shared), Some(&trace[..]))?;
+                Ok((input_cells, state[DigestIndex::PSE.0].clone()))
+            })
+            .collect()
+    }
+
+    /// The [`PoseidonChip::trace`] of every message of `messages`, on the rayon pool if
+    /// `parallel`.
+    pub fn traces(&self, messages: &[Vec<F>], parallel: bool) -> Vec<Vec<Vec<[F; T]>>> {
+        if parallel {
+            messages
+                .par_iter()
+                .map(|message| self.trace(message))
+                .collect()
+        } else {
+            messages.iter().map(|message| self.trace(message)).collect()
         }
+    }
 
-        if exact {
-            let next_state = self.permutation(ctx, Vec::new(), &state)?;
-            state = next_state;
+    /// The values [`PoseidonChip::hash_many`] assigns for `message`, computed natively: for
+    /// every permutation, the state after each of its rounds, absorbing first.
+    pub fn trace(&self, message: &[F]) -> Vec<Vec<[F; T]>> {
+        let mut permutations = Vec::with_capacity(message.len() / RATE + 1);
+        let mut state = None;
+        for chunk in message.chunks(RATE) {
+            let trace = self.cached_permutation_trace(chunk, state);
+            state = trace.last().copied();
+            permutations.push(trace);
+        }
+        // a message filling whole permutations gets one more, absorbing only the padding
+        if message.len() % RATE == 0 {
+            permutations.push(self.cached_permutation_trace(&[], state));
+        }
+        permutations
+    }
+
+    /// [`PoseidonChip::permutation_trace`], from the trace cache of the chip if it has one.
+    fn cached_permutation_trace(&self, inputs: &[F], state: Option<[F; T]>) -> Vec<[F; T]> {
+        match &self.trace_cache {
+            Some(cache) => {
+                let start = state.unwrap_or(self.initial);
+                let trace =
+                    cache.get_or_compute(&start, inputs, || self.permutation_trace(inputs, state));
+                trace.to_vec()
+            }
+            None => self.permutation_trace(inputs, state),
+        }
+    }
+
+    /// The state after every round of permuting `state`, or the initial state if there is
+    /// none, with `inputs`, as the rows of [`PoseidonChip::permute`] compute them.
+    fn permutation_trace(&self, inputs: &[F], state: Option<[F; T]>) -> Vec<[F; T]> {
+        let r_f = self.constants.r_f / 2;
+        let r_p = self.constants.r_p();
+        let mut rounds = Vec::with_capacity(1 + 2 * r_f + r_p);
+        let start = state.unwrap_or(self.initial);
+        rounds.push(std::array::from_fn(|i| {
+            let input = if (1..=inputs.len()).contains(&i) {
+                inputs[i - 1]
+            } else {
+                F::ZERO
+            };
+            start[i] + input + self.absorb_constant(inputs.len(), i)
+        }));
+        let full = |state: &[F; T], is_first_half_full, round_idx| {
+            std::array::from_fn(|i| {
+                let (q_5, rc) = self.full_round_coeffs(is_first_half_full, round_idx, i);
+                Self::next_state(state, [F::ZERO; T], q_5, rc)
+            })
+        };
+        for round_idx in 0..r_f {
+            let next = full(rounds.last().unwrap(), true, round_idx);
+            rounds.push(next);
+        }
+        for round_idx in 0..r_p {
+            let state = rounds.last().unwrap();
+            let next = std::array::from_fn(|i| {
+                let (q_1, q_5, rc) = self.partial_round_coeffs(round_idx, i);
+                Self::next_state(state, q_1, q_5, rc)
+            });
+            rounds.push(next);
+        }
+        for round_idx in 0..r_f {
+            let next = full(rounds.last().unwrap(), false, round_idx);
+            rounds.push(next);
+        }
+        rounds
+    }
+
+    /// Squeezes the state element at `digest`, see [`crate::poseidon_hash::hash_to`].
+    pub fn squeeze_to(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        digest: DigestIndex,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.squeeze_with_inputs_to(ctx, digest).map(|(_, out)| out)
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn squeeze_with_inputs_to(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        digest: DigestIndex,
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        assert!(
+            digest.0 < T,
+            "digest index {} out of a width {T} state",
+            digest.0
+        );
+        let (input_cells, state) = self.absorb_all(ctx)?;
+        Ok((input_cells, state[digest.0].clone()))
+    }
+
+    /// Squeezes `n` elements: the rate elements of the final state in order, permuting again
+    /// whenever they run out, as [`crate::poseidon_hash::Sponge::squeeze`] does.
+    ///
+    /// Pair it with [`PoseidonChip::with_domain`] and a [`Domain::ConstantLength`] of `n`
+    /// outputs, so that hashes squeezing fewer elements are not prefixes of this one.
+    pub fn squeeze_n(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        n: usize,
+    ) -> Result<Vec<AssignedValue<F>>, Error> {
+        self.squeeze_n_with_inputs(ctx, n)
+            .map(|(_, outputs)| outputs)
+    }
+
+    /// Squeezes like [`PoseidonChip::squeeze_n`] and also returns the cells the buffered
+    /// elements were absorbed from.
+    #[allow(clippy::type_complexity)]
+    pub fn squeeze_n_with_inputs(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        n: usize,
+    ) -> Result<(Vec<AssignedValue<F>>, Vec<AssignedValue<F>>), Error> {
+        let (input_cells, mut state) = self.absorb_all(ctx)?;
+        let mut outputs = Vec::with_capacity(n);
+        for i in 0..n {
+            if i > 0 && i % RATE == 0 {
+                state = self.permutation(ctx, Vec::new(), &state)?;
+            }
+            outputs.push(state[1 + i % RATE].clone());
+        }
+        Ok((input_cells, outputs))
+    }
+
+    /// One step of a duplex sponge: adds the `RATE` elements of `inputs` to the rate part of
+    /// `state`, or of the initial state, without padding, and permutes; returns the new state
+    /// and the input cells. Takes the rows of [`PoseidonChip::num_rows`] for an empty message.
+    #[allow(clippy::type_complexity)]
+    pub fn duplex(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: Vec<F>,
+        state: Option<&[AssignedValue<F>; T]>,
+    ) -> Result<([AssignedValue<F>; T], Vec<AssignedValue<F>>), Error> {
+        assert_eq!(inputs.len(), RATE, "duplexing a partial chunk");
+        self.permute(ctx, inputs, state, None, None)
+    }
+
+    /// Absorbs the buffered message, padding included, and returns its cells and the state.
+    #[allow(clippy::type_complexity)]
+    fn absorb_all(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+    ) -> Result<(Vec<AssignedValue<F>>, [AssignedValue<F>; T]), Error> {
+        let buf = self.buf.clone();
+        self.main_gate.config().annotate_columns(&mut ctx.region);
+        // with a trace cache, the values come from there rather than from the cells
+        let trace = self.trace_cache.is_some().then(|| self.trace(&buf));
+        self.absorb(ctx, &buf, None, trace.as_deref())
+    }
+
+    /// Absorbs `message` from the initial state, padding included, with the values of its
+    /// [`PoseidonChip::trace`] if given.
+    #[allow(clippy::type_complexity)]
+    fn absorb(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        message: &[F],
+        mut shared: Option<&mut SharedCells<F>>,
+        trace: Option<&[Vec<[F; T]>]>,
+    ) -> Result<(Vec<AssignedValue<F>>, [AssignedValue<F>; T]), Error> {
+        let permutation = |i: usize| trace.map(|trace| &trace[i][..]);
+        let mut state = None;
+        let mut input_cells = Vec::with_capacity(message.len());
+        for (i, chunk) in message.chunks(RATE).enumerate() {
+            let (next_state, inputs) = self.permute(
+                ctx,
+                chunk.to_vec(),
+                state.as_ref(),
+                shared.as_deref_mut(),
+                permutation(i),
+            )?;
+            input_cells.extend(inputs);
+            state = Some(next_state);
+        }
+        // a message filling whole permutations gets one more, absorbing only the padding
+        if message.len() % RATE == 0 {
+            let last = permutation(message.len() / RATE);
+            let (next_state, _) = self.permute(ctx, Vec::new(), state.as_ref(), shared, last)?;
+            state = Some(next_state);
+        }
+        Ok((input_cells, state.expect("at least one permutation")))
+    }
+}
+
+impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonInstructions<F>
+    for PoseidonChip<F, T, RATE>
+{
+    fn with_domain(self, domain: Domain) -> Self {
+        PoseidonChip::with_domain(self, domain)
+    }
+
+    /// Hashes under the domain of the chip, leaving what [`PoseidonChip::update`] buffered.
+    fn hash(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        message: &[AssignedValue<F>],
+    ) -> Result<AssignedValue<F>, Error> {
+        // unknown values only occur at key generation, which assigns no message values
+        let mut values = vec![F::ZERO; message.len()];
+        for (value, cell) in values.iter_mut().zip(message) {
+            cell.value().map(|v| *value = *v);
         }
+        self.main_gate.config().annotate_columns(&mut ctx.region);
+        let (inputs, state) = self.absorb(ctx, &values, None, None)?;
+        for (input, cell) in inputs.iter().zip(message) {
+            ctx.constrain_equal(input.cell(), cell.cell())?;
+        }
+        Ok(state[DigestIndex::PSE.0].clone())
+    }
 
-        Ok(state[1].clone())
+    fn num_rows(&self, len: usize) -> usize {
+        self.rows(len)
     }
 }
 
@@ -362,6 +1089,8 @@ mod tests {
     const RATE: usize = 2;
     const R_F: usize = 4;
     const R_P: usize = 3;
+    /// The layouts of the `LAYOUT` parameter of [`TestCircuit`]
+    const LAYOUTS: [RoundLayout; 3] = [RoundLayout::Narrow, RoundLayout::Wide, RoundLayout::Folded];
 
     #[derive(Clone, Debug)]
     struct TestCircuitConfig {
@@ -369,30 +1098,56 @@ mod tests {
         instance: Column<Instance>,
     }
 
-    struct TestCircuit<F: PrimeField> {
+    struct TestCircuit<F: PrimeField, const MAX_DEGREE: usize = 6, const LAYOUT: usize = 0> {
         inputs: Vec<F>,
+        digest: DigestIndex,
+        /// Squeezes this many elements with [`PoseidonChip::squeeze_n`] if set
+        sponge: Option<(Domain, usize)>,
     }
 
-    impl<F: PrimeField> TestCircuit<F> {
+    impl<F: PrimeField, const MAX_DEGREE: usize, const LAYOUT: usize>
+        TestCircuit<F, MAX_DEGREE, LAYOUT>
+    {
         fn new(inputs: Vec<F>) -> Self {
-            Self { inputs }
+            Self {
+                inputs,
+                digest: DigestIndex::PSE,
+                sponge: None,
+            }
         }
     }
 
-    impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for TestCircuit<F> {
+    impl<F: PrimeField + FromUniformBytes<64>, const MAX_DEGREE: usize, const LAYOUT: usize>
+        Circuit<F> for TestCircuit<F, MAX_DEGREE, LAYOUT>
+    {
         type Config = TestCircuitConfig;
         type FloorPlanner = SimpleFloorPlanner;
 
         fn without_witnesses(&self) -> Self {
-            Self { inputs: Vec::new() }
+            Self {
+                inputs: Vec::new(),
+                digest: self.digest,
+                sponge: self.sponge,
+            }
         }
 
         fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
             let instance = meta.instance_column();
             meta.enable_equality(instance);
-            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
-            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
-            let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            let layout = GateLayout::for_max_degree(MAX_DEGREE)
+                .unwrap()
+                .with_rounds(LAYOUTS[LAYOUT]);
+            let mut adv_cols = (0..layout.advice_columns(T))
+                .map(|_| meta.advice_column())
+                .collect::<Vec<_>>()
+                .into_iter();
+            let mut fix_cols = (0..layout.fixed_columns(T))
+                .map(|_| meta.fixed_column())
+                .collect::<Vec<_>>()
+                .into_iter();
+            let pconfig =
+                MainGate::configure_with_layout(meta, &mut adv_cols, &mut fix_cols, layout);
+            assert!(adv_cols.next().is_none() && fix_cols.next().is_none());
             Self::Config { pconfig, instance }
         }
 
@@ -402,29 +1157,264 @@ mod tests {
             mut layouter: impl Layouter<F>,
         ) -> Result<(), Error> {
             let spec = Spec::<F, T, RATE>::new(R_F, R_P);
-            let mut pchip = PoseidonChip::new(config.pconfig, spec);
+            let mut pchip = PoseidonChip::new(config.pconfig, spec.clone());
+            if let Some((domain, _)) = self.sponge {
+                pchip = pchip.with_domain(domain);
+            }
             pchip.update(self.inputs.clone());
-            let output = layouter.assign_region(
+            let outputs = layouter.assign_region(
                 || "poseidon hash",
                 |region| {
                     let ctx = &mut RegionCtx::new(region, 0);
-                    pchip.squeeze(ctx)
+                    match self.sponge {
+                        Some((_, n)) => pchip.squeeze_n(ctx, n),
+                        None => {
+                            let out = pchip.squeeze_to(ctx, self.digest)?;
+                            let len = self.inputs.len();
+                            let rows =
+                                PoseidonChip::num_rows_with_layout(&spec, len, LAYOUTS[LAYOUT]);
+                            assert_eq!(ctx.offset(), rows);
+                            Ok(vec![out])
+                        }
+                    }
                 },
             )?;
-            layouter.constrain_instance(output.cell(), config.instance, 0)?;
+            for (row, output) in outputs.iter().enumerate() {
+                layouter.constrain_instance(output.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// The digests of several messages, hashed in one region by [`PoseidonChip::hash_many`].
+    struct BatchCircuit<F: PrimeField> {
+        messages: Vec<Vec<F>>,
+    }
+
+    impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for BatchCircuit<F> {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                messages: self
+                    .messages
+                    .iter()
+                    .map(|m| vec![F::ZERO; m.len()])
+                    .collect(),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+            TestCircuit::<F>::configure(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<F>,
+        ) -> Result<(), Error> {
+            let spec = Spec::<F, T, RATE>::new(R_F, R_P);
+            let lens = self.messages.iter().map(Vec::len).collect::<Vec<_>>();
+            let rows = PoseidonChip::num_rows_many(&spec, &lens);
+            let pchip = PoseidonChip::new(config.pconfig, spec);
+            let hashes = layouter.assign_region(
+                || "poseidon hashes",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let hashes = pchip.hash_many(ctx, &self.messages)?;
+                    assert_eq!(ctx.offset(), rows);
+                    Ok(hashes)
+                },
+            )?;
+            for (row, (_, digest)) in hashes.iter().enumerate() {
+                layouter.constrain_instance(digest.cell(), config.instance, row)?;
+            }
             Ok(())
         }
     }
 
     #[test]
     fn test_mock() {
+        check_mock::<6>();
+    }
+
+    #[test]
+    fn test_capped_degree() {
+        fn degree<const MAX_DEGREE: usize>() -> usize {
+            let mut cs = ConstraintSystem::<Fp>::default();
+            TestCircuit::<Fp, MAX_DEGREE>::configure(&mut cs);
+            cs.degree()
+        }
+        assert_eq!(degree::<6>(), 6);
+        assert_eq!(degree::<4>(), 4);
+        assert_eq!(degree::<3>(), 3);
+        check_mock::<4>();
+        check_mock::<3>();
+
+        let spec = Spec::<Fp, T, RATE>::new(R_F, R_P);
+        let direct = PoseidonChip::cost(&spec, 5, SboxDegree::Direct);
+        let staged = PoseidonChip::cost(&spec, 5, SboxDegree::Staged);
+        assert_eq!(direct.rows, staged.rows);
+        assert_eq!(staged.advice_columns, direct.advice_columns + 2 * T);
+        assert_eq!((direct.quotient_blowup, staged.quotient_blowup), (8, 2));
+    }
+
+    #[test]
+    fn test_round_layouts() {
+        check_mock_in::<6, 1>();
+        check_mock_in::<6, 2>();
+        check_mock_in::<3, 1>();
+        check_mock_in::<4, 2>();
+        check_mock_in::<3, 2>();
+
+        // R_P = 3: the folded layout folds the first two partial rounds and widens the last
+        let spec = Spec::<Fp, T, RATE>::new(R_F, R_P);
+        let rows = |rounds| PoseidonChip::num_rows_with_layout(&spec, 5, rounds);
+        assert_eq!(rows(RoundLayout::Narrow), PoseidonChip::num_rows(&spec, 5));
+        assert_eq!(rows(RoundLayout::Wide), 3 * (T + R_F + R_P + 1));
+        assert_eq!(rows(RoundLayout::Folded), 3 * (T + R_F + 2 + 1));
+
+        let cost = |rounds| {
+            let layout = GateLayout::default().with_rounds(rounds);
+            PoseidonChip::cost_with_layout(&spec, 5, layout)
+        };
+        let (narrow, wide, folded) = (
+            cost(RoundLayout::Narrow),
+            cost(RoundLayout::Wide),
+            cost(RoundLayout::Folded),
+        );
+        assert_eq!(narrow, PoseidonChip::cost(&spec, 5, SboxDegree::Direct));
+        assert_eq!((wide.degree, folded.degree), (narrow.degree, narrow.degree));
+        assert_eq!(wide.advice_columns, narrow.advice_columns);
+        assert_eq!(wide.fixed_columns, narrow.fixed_columns + 2 * T * T + T + 1);
+        assert_eq!(folded.fixed_columns, wide.fixed_columns + T);
+        assert!(folded.rows < wide.rows && wide.rows < narrow.rows);
+    }
+
+    #[test]
+    fn test_digest_index() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::poseidon_hash::hash_to;
+
+        let inputs = (0..5).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let spec = Spec::<Fp, T, RATE>::new(R_F, R_P);
+        let circuit = TestCircuit::<Fp> {
+            inputs: inputs.clone(),
+            digest: DigestIndex::CIRCOM,
+            sponge: None,
+        };
+        let expected = hash_to(&spec, &inputs, DigestIndex::CIRCOM);
+        let prover = MockProver::run(10, &circuit, vec![vec![expected]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        let pse = hash_to(&spec, &inputs, DigestIndex::PSE);
+        let prover = MockProver::run(10, &circuit, vec![vec![pse]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_sponge() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::poseidon_hash::Sponge;
+
+        let spec = Spec::<Fp, T, RATE>::new(R_F, R_P);
+        // one and several permutations of input, and more outputs than the rate
+        for (len, n) in [(1, 1), (4, 3), (7, 5)] {
+            let inputs = (0..len as u64).map(Fp::from).collect::<Vec<_>>();
+            let domain = Domain::ConstantLength { len, outputs: n };
+            let mut sponge = Sponge::new(spec.clone(), domain);
+            sponge.absorb(&inputs);
+            let expected = (0..n).map(|_| sponge.squeeze()).collect::<Vec<_>>();
+            let circuit = TestCircuit::<Fp> {
+                inputs,
+                digest: DigestIndex::PSE,
+                sponge: Some((domain, n)),
+            };
+            let prover = MockProver::run(10, &circuit, vec![expected.clone()]).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "{len} elements, {n} outputs");
+
+            // the outputs of another domain do not satisfy the circuit
+            let mut other = Sponge::new(spec.clone(), Domain::Pse);
+            other.absorb(&circuit.inputs);
+            let pse = (0..n).map(|_| other.squeeze()).collect::<Vec<_>>();
+            let prover = MockProver::run(10, &circuit, vec![pse]).unwrap();
+            assert!(prover.verify().is_err());
+        }
+        assert_eq!(
+            PoseidonChip::num_rows_squeezing(&spec, 7, 5),
+            (7 / RATE + 1 + 2) * (1 + R_F + R_P) * T
+        );
+    }
+
+    #[test]
+    fn test_hash_many() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::poseidon_hash::hash;
+
+        let spec = Spec::<Fp, T, RATE>::new(R_F, R_P);
+        let lens = [0, 1, 1, 2, 3, 1];
+        let messages = lens
+            .iter()
+            .enumerate()
+            .map(|(i, &len)| {
+                (0..len as u64)
+                    .map(|j| Fp::from(10 * i as u64 + j))
+                    .collect()
+            })
+            .collect::<Vec<Vec<_>>>();
+        let digests = messages
+            .iter()
+            .map(|message| hash(&spec, message))
+            .collect::<Vec<_>>();
+
+        // the rows computed ahead end in the digests, in parallel or not
+        let mut cs = ConstraintSystem::<Fp>::default();
+        let config = TestCircuit::<Fp>::configure(&mut cs).pconfig;
+        let pchip = PoseidonChip::new(config, spec.clone());
+        let traces = pchip.traces(&messages, true);
+        assert_eq!(traces, pchip.traces(&messages, false));
+        for ((trace, message), digest) in traces.iter().zip(&messages).zip(&digests) {
+            assert_eq!(trace.len(), message.len() / RATE + 1);
+            let last = trace.last().unwrap();
+            assert_eq!(last.len(), 1 + R_F + R_P);
+            assert_eq!(last.last().unwrap()[DigestIndex::PSE.0], *digest);
+        }
+
+        let circuit = BatchCircuit { messages };
+        let prover = MockProver::run(10, &circuit, vec![digests.clone()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        let mut swapped = digests;
+        swapped.swap(1, 2);
+        let prover = MockProver::run(10, &circuit, vec![swapped]).unwrap();
+        assert!(prover.verify().is_err());
+
+        // the capacity row once, the padding and empty rows once per shape
+        let separate = lens
+            .iter()
+            .map(|&len| PoseidonChip::num_rows(&spec, len))
+            .sum::<usize>();
+        assert_eq!(PoseidonChip::num_rows_many(&spec, &lens), separate - 7);
+        assert_eq!(
+            PoseidonChip::num_rows_many(&spec, &[3]),
+            PoseidonChip::num_rows(&spec, 3)
+        );
+    }
+
+    fn check_mock<const MAX_DEGREE: usize>() {
+        check_mock_in::<MAX_DEGREE, 0>()
+    }
+
+    fn check_mock_in<const MAX_DEGREE: usize, const LAYOUT: usize>() {
         use halo2_proofs::dev::MockProver;
         const K: u32 = 10;
         let mut inputs = Vec::new();
         for i in 0..5 {
             inputs.push(Fp::from(i as u64));
         }
-        let circuit = TestCircuit::new(inputs);
+        let circuit = TestCircuit::<_, MAX_DEGREE, LAYOUT>::new(inputs);
         // hex = 0x1cd3150d8e12454ff385da8a4d864af6d0f021529207b16dd6c3d8f2b52cfc67
         let out_hash = Fp::from_str_vartime(
             "13037709793114148810823325920380362524528554380279235267325741570708489436263",
@@ -5,20 +5,29 @@ use halo2_proofs::arithmetic::CurveAffine;
 use halo2curves::group::ff::{FromUniformBytes, PrimeField};
 use poseidon::{SparseMDSMatrix, Spec};
 
-use crate::ro_types::{ROConstantsTrait, ROTrait};
+use crate::{
+    poseidon2::{self, Poseidon2Spec},
+    ro_types::{ROConstantsTrait, ROTrait},
+    specs::{DigestIndex, Domain},
+    trace::RoundKind,
+};
 
 // adapted from: https://github.com/privacy-scaling-explorations/snark-verifier
 
 #[derive(Clone, Debug)]
-struct State<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> {
+pub(crate) struct State<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> {
     inner: [F; T],
 }
 
 impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> State<F, T, RATE> {
-    fn new(inner: [F; T]) -> Self {
+    pub(crate) fn new(inner: [F; T]) -> Self {
         Self { inner }
     }
 
+    pub(crate) fn words(&self) -> [F; T] {
+        self.inner
+    }
+
     fn sbox_full(&mut self, constants: &[F; T]) {
         let pow5 = |v: &F| v.square() * v.square() * v;
         for (state, constant) in self.inner.iter_mut().zip(constants.iter()) {
@@ -90,6 +99,66 @@ impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> St
         .try_into()
         .unwrap();
     }
+
+    pub(crate) fn permute(&mut self, spec: &Spec<F, T, RATE>, inputs: &[F]) {
+        self.permute_traced(spec, inputs, |_, _, _| {});
+    }
+
+    /// Permutes like [`State::permute`], reporting the state before and after every round.
+    pub(crate) fn permute_traced(
+        &mut self,
+        spec: &Spec<F, T, RATE>,
+        inputs: &[F],
+        mut step: impl FnMut(RoundKind, [F; T], [F; T]),
+    ) {
+        let r_f = spec.r_f() / 2;
+        let mds = spec.mds_matrices().mds().rows();
+        let pre_sparse_mds = spec.mds_matrices().pre_sparse_mds().rows();
+        let sparse_matrices = spec.mds_matrices().sparse_matrices();
+        let mut round = |state: &mut Self, kind: RoundKind, f: &dyn Fn(&mut Self)| {
+            let before = state.inner;
+            f(state);
+            step(kind, before, state.inner);
+        };
+
+        // First half of the full rounds
+        let constants = spec.constants().start();
+        round(self, RoundKind::Absorb, &|s| {
+            s.pre_round(inputs, &constants[0])
+        });
+        for constants in constants.iter().skip(1).take(r_f - 1) {
+            round(self, RoundKind::Full, &|s| {
+                s.sbox_full(constants);
+                s.apply_mds(&mds);
+            });
+        }
+        round(self, RoundKind::Full, &|s| {
+            s.sbox_full(constants.last().unwrap());
+            s.apply_mds(&pre_sparse_mds);
+        });
+
+        // Partial rounds
+        let constants = spec.constants().partial();
+        for (constant, sparse_mds) in constants.iter().zip(sparse_matrices.iter()) {
+            round(self, RoundKind::Partial, &|s| {
+                s.sbox_part(constant);
+                s.apply_sparse_mds(sparse_mds);
+            });
+        }
+
+        // Second half of the full rounds
+        let constants = spec.constants().end();
+        for constants in constants.iter() {
+            round(self, RoundKind::Full, &|s| {
+                s.sbox_full(constants);
+                s.apply_mds(&mds);
+            });
+        }
+        round(self, RoundKind::Full, &|s| {
+            s.sbox_full(&[F::ZERO; T]);
+            s.apply_mds(&mds);
+        });
+    }
 }
 
 impl<F, const T: usize, const RATE: usize> ROConstantsTrait for Spec<F, T, RATE>
@@ -121,6 +190,168 @@ where
     }
 }
 
+/// Natively hashes `inputs` with the same sponge as [`PoseidonHash`] and the circuit.
+pub fn hash<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    inputs: &[F],
+) -> F {
+    hash_to(spec, inputs, DigestIndex::PSE)
+}
+
+/// Hashes like [`hash`] and outputs the state element at `digest`.
+pub fn hash_to<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    inputs: &[F],
+    digest: DigestIndex,
+) -> F {
+    hash_in(spec, inputs, Domain::Pse, digest)
+}
+
+/// Hashes like [`hash`] from the capacity of `domain`, so that messages hashed for
+/// different uses never share a digest; the chip is [`PoseidonChip::hash_with_domain`].
+///
+/// [`PoseidonChip::hash_with_domain`]: crate::poseidon_circuit::PoseidonChip::hash_with_domain
+pub fn hash_with_domain<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    domain: Domain,
+    inputs: &[F],
+) -> F {
+    hash_in(spec, inputs, domain, DigestIndex::PSE)
+}
+
+/// Hashes like [`hash_to`] from the capacity of `domain`, as
+/// [`crate::poseidon_circuit::PoseidonChip::with_domain`] does.
+pub(crate) fn hash_in<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    inputs: &[F],
+    domain: Domain,
+    digest: DigestIndex,
+) -> F {
+    assert!(
+        digest.0 < T,
+        "digest index {} out of a width {T} state",
+        digest.0
+    );
+    let mut initial = [F::ZERO; T];
+    initial[0] = domain.capacity();
+    let mut state = State::<F, T, RATE>::new(initial);
+    for chunk in inputs.chunks(RATE) {
+        state.permute(spec, chunk);
+    }
+    if inputs.len() % RATE == 0 {
+        state.permute(spec, &[]);
+    }
+    state.inner[digest.0]
+}
+
+/// The permutation a hash runs, for code that hashes with either.
+///
+/// Both hash with the same sponge, so a message hashes to the same digest under a spec no
+/// matter where it is computed; Poseidon digests stay what they were.
+#[derive(Clone, Debug)]
+pub enum HashSpec<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> {
+    Poseidon(Spec<F, T, RATE>),
+    Poseidon2(Poseidon2Spec<F, T>),
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> HashSpec<F, T, RATE> {
+    pub fn permutation(&self) -> &'static str {
+        match self {
+            Self::Poseidon(_) => "poseidon",
+            Self::Poseidon2(_) => "poseidon2",
+        }
+    }
+
+    pub fn hash(&self, inputs: &[F]) -> F {
+        self.hash_to(inputs, DigestIndex::PSE)
+    }
+
+    pub fn hash_to(&self, inputs: &[F], digest: DigestIndex) -> F {
+        match self {
+            Self::Poseidon(spec) => hash_to(spec, inputs, digest),
+            Self::Poseidon2(spec) => poseidon2::hash_to(spec, inputs, digest),
+        }
+    }
+}
+
+/// A sponge absorbing messages of any length and squeezing any number of elements, natively;
+/// [`crate::poseidon_circuit::PoseidonChip::squeeze_n`] is its circuit.
+///
+/// The message is padded with a single one after its last element, taking a permutation of
+/// its own if the message fills the rate exactly. Squeezing outputs the rate elements of the
+/// final state and permutes again whenever they run out. Under [`Domain::Pse`], the first
+/// element squeezed is [`hash`].
+///
+/// ```
+/// use halo2curves::bn256::Fr;
+/// use poseidon::Spec;
+/// use poseidon_circuit::{poseidon_hash::Sponge, specs::Domain};
+///
+/// let message = (0..10).map(Fr::from).collect::<Vec<_>>();
+/// let domain = Domain::ConstantLength { len: message.len(), outputs: 2 };
+/// let mut sponge = Sponge::new(Spec::<Fr, 4, 3>::new(8, 56), domain);
+/// sponge.absorb(&message[..4]);
+/// sponge.absorb(&message[4..]);
+/// let (a, b) = (sponge.squeeze(), sponge.squeeze());
+/// assert_ne!(a, b);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Sponge<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> {
+    spec: Spec<F, T, RATE>,
+    state: State<F, T, RATE>,
+    buf: Vec<F>,
+    /// Index of the next output in the rate, once squeezing started
+    squeezed: Option<usize>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> Sponge<F, T, RATE> {
+    pub fn new(spec: Spec<F, T, RATE>, domain: Domain) -> Self {
+        let mut state = [F::ZERO; T];
+        state[0] = domain.capacity();
+        Self {
+            spec,
+            state: State::new(state),
+            buf: Vec::new(),
+            squeezed: None,
+        }
+    }
+
+    /// Appends `elements` to the message.
+    ///
+    /// # Panics
+    ///
+    /// Once squeezing started; the message is padded by then.
+    pub fn absorb(&mut self, elements: &[F]) {
+        assert!(self.squeezed.is_none(), "absorbing after squeezing");
+        self.buf.extend_from_slice(elements);
+        // keep the last chunk buffered, it may still fill up or take the padding
+        let whole = self.buf.len().saturating_sub(1) / RATE * RATE;
+        for chunk in self.buf.drain(..whole).collect::<Vec<_>>().chunks(RATE) {
+            self.state.permute(&self.spec, chunk);
+        }
+    }
+
+    pub fn squeeze(&mut self) -> F {
+        let next = match self.squeezed {
+            None => {
+                let buf = mem::take(&mut self.buf);
+                self.state.permute(&self.spec, &buf);
+                if buf.len() == RATE {
+                    self.state.permute(&self.spec, &[]);
+                }
+                0
+            }
+            Some(next) if next == RATE => {
+                self.state.permute(&self.spec, &[]);
+                0
+            }
+            Some(next) => next,
+        };
+        self.squeezed = Some(next + 1);
+        self.state.inner[1 + next]
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct PoseidonHash<
     C: CurveAffine<ScalarExt = F>,
@@ -160,36 +391,7 @@ impl<
     }
 
     fn permutation(&mut self, inputs: &[F]) {
-        let r_f = self.spec.r_f() / 2;
-        let mds = self.spec.mds_matrices().mds().rows();
-        let pre_sparse_mds = self.spec.mds_matrices().pre_sparse_mds().rows();
-        let sparse_matrices = self.spec.mds_matrices().sparse_matrices();
-
-        // First half of the full rounds
-        let constants = self.spec.constants().start();
-        self.state.pre_round(inputs, &constants[0]);
-        for constants in constants.iter().skip(1).take(r_f - 1) {
-            self.state.sbox_full(constants);
-            self.state.apply_mds(&mds);
-        }
-        self.state.sbox_full(constants.last().unwrap());
-        self.state.apply_mds(&pre_sparse_mds);
-
-        // Partial rounds
-        let constants = self.spec.constants().partial();
-        for (constant, sparse_mds) in constants.iter().zip(sparse_matrices.iter()) {
-            self.state.sbox_part(constant);
-            self.state.apply_sparse_mds(sparse_mds);
-        }
-
-        // Second half of the full rounds
-        let constants = self.spec.constants().end();
-        for constants in constants.iter() {
-            self.state.sbox_full(constants);
-            self.state.apply_mds(&mds);
-        }
-        self.state.sbox_full(&[F::ZERO; T]);
-        self.state.apply_mds(&mds);
+        self.state.permute(&self.spec, inputs);
     }
 }
 
@@ -222,4 +424,159 @@ mod tests {
         .unwrap();
         assert_eq!(output, out_hash);
     }
+
+    #[test]
+    fn test_hash() {
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        let inputs = (0..5).map(Fr::from).collect::<Vec<_>>();
+        // the free function hashes like the sponge of the PSE crate
+        let mut poseidon = PoseidonHash::<G1Affine, Fr, 4, 3>::new(spec.clone());
+        poseidon.update(&inputs);
+        assert_eq!(hash(&spec, &inputs), poseidon.squeeze());
+    }
+
+    #[test]
+    fn test_hash_to() {
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        let inputs = (0..5).map(Fr::from).collect::<Vec<_>>();
+        assert_eq!(DigestIndex::default(), DigestIndex::PSE);
+        assert_eq!(
+            hash_to(&spec, &inputs, DigestIndex::PSE),
+            hash(&spec, &inputs)
+        );
+        assert_ne!(
+            hash_to(&spec, &inputs, DigestIndex::CIRCOM),
+            hash(&spec, &inputs)
+        );
+    }
+
+    #[test]
+    fn test_hash_spec() {
+        let inputs = (0..5).map(Fr::from).collect::<Vec<_>>();
+        let poseidon = HashSpec::Poseidon(Spec::<Fr, 3, 2>::new(8, 57));
+        let poseidon2 = HashSpec::<Fr, 3, 2>::Poseidon2(Poseidon2Spec::new(8, 56));
+        assert_eq!(
+            poseidon.hash(&inputs),
+            hash(&Spec::<Fr, 3, 2>::new(8, 57), &inputs)
+        );
+        assert_ne!(poseidon.hash(&inputs), poseidon2.hash(&inputs));
+        assert_eq!(
+            (poseidon.permutation(), poseidon2.permutation()),
+            ("poseidon", "poseidon2")
+        );
+    }
+
+    #[test]
+    fn test_sponge() {
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        for len in [0, 1, 3, 5, 6, 10] {
+            let inputs = (0..len as u64).map(Fr::from).collect::<Vec<_>>();
+            // absorbing in pieces is absorbing at once
+            let mut sponge = Sponge::new(spec.clone(), Domain::Pse);
+            for piece in inputs.chunks(2) {
+                sponge.absorb(piece);
+            }
+            assert_eq!(sponge.squeeze(), hash(&spec, &inputs), "{len}");
+
+            let domain = Domain::ConstantLength { len, outputs: 5 };
+            let mut sponge = Sponge::new(spec.clone(), domain);
+            sponge.absorb(&inputs);
+            let outputs = (0..5).map(|_| sponge.squeeze()).collect::<Vec<_>>();
+            let mut other = Sponge::new(spec.clone(), Domain::ConstantLength { len, outputs: 4 });
+            other.absorb(&inputs);
+            assert_ne!(other.squeeze(), outputs[0]);
+        }
+        assert_eq!(
+            Domain::ConstantLength { len: 1, outputs: 1 }.capacity::<Fr>(),
+            Domain::Pse.capacity::<Fr>()
+        );
+    }
+
+    #[test]
+    fn test_domains() {
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        let inputs = (0..3).map(Fr::from).collect::<Vec<_>>();
+        let domains = [
+            Domain::Pse,
+            Domain::ConstantLength { len: 3, outputs: 1 },
+            Domain::Constant(3),
+            Domain::Constant(1 << 64),
+            Domain::Constant(u128::MAX),
+            Domain::User(0),
+            Domain::User(1),
+            Domain::User(u64::MAX),
+            // the capacity of a user tag is out of reach of the length tags
+            Domain::ConstantLength {
+                len: usize::MAX,
+                outputs: usize::MAX,
+            },
+        ];
+        let digests = domains.map(|domain| hash_with_domain(&spec, domain, &inputs));
+        for i in 0..domains.len() {
+            for j in 0..i {
+                assert_ne!(digests[i], digests[j], "{:?} {:?}", domains[i], domains[j]);
+                assert_ne!(
+                    domains[i].capacity::<Fr>(),
+                    domains[j].capacity::<Fr>(),
+                    "{:?} {:?}",
+                    domains[i],
+                    domains[j]
+                );
+            }
+        }
+        assert_eq!(digests[0], hash(&spec, &inputs));
+        assert_eq!(
+            Domain::User(0).capacity::<Fr>(),
+            Fr::from_u128(1 << 64) * Fr::from_u128(1 << 64)
+        );
+        // constant tags are apart from the others, even where the tags themselves agree
+        let two_64 = Fr::from_u128(1 << 64);
+        assert_ne!(
+            Domain::Constant(1 << 64).capacity::<Fr>(),
+            Domain::Pse.capacity::<Fr>()
+        );
+        assert_ne!(
+            Domain::Constant(0).capacity::<Fr>(),
+            Domain::User(u64::MAX).capacity::<Fr>()
+        );
+        assert_eq!(
+            Domain::Constant(3).capacity::<Fr>(),
+            Fr::from(2) * two_64 * two_64 * two_64 + Fr::from(3)
+        );
+    }
+
+    #[test]
+    fn test_native_core() {
+        use poseidon_circuit_native::grain::Grain;
+
+        // the PSE spec draws its round constants from the same generator, and keeps those
+        // of the first round as they are
+        let mut grain = Grain::new(Fr::NUM_BITS, 4, 8, 56);
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        assert_eq!(grain.next_elements::<Fr, 4>(), spec.constants().start()[0]);
+
+        // and so does the no_std core, whose digests are those of this crate
+        let core = poseidon_circuit_native::Spec::<Fr, 4, 3>::new(8, 56);
+        assert_eq!(core.mds(), &spec.mds_matrices().mds().rows());
+        for len in [0, 1, 2, 3, 5, 6, 10] {
+            let inputs = (0..len as u64).map(Fr::from).collect::<Vec<_>>();
+            assert_eq!(
+                poseidon_circuit_native::hash(&core, &inputs),
+                hash(&spec, &inputs),
+                "{len}"
+            );
+            let domain = Domain::User(7);
+            assert_eq!(
+                poseidon_circuit_native::hash_with_capacity(&core, domain.capacity(), &inputs),
+                hash_with_domain(&spec, domain, &inputs),
+                "{len}"
+            );
+        }
+        let pallas = Spec::<Fp, 3, 2>::new(8, 56);
+        let inputs = [Fp::from(1), Fp::from(2)];
+        assert_eq!(
+            poseidon_circuit_native::hash(&poseidon_circuit_native::Spec::new(8, 56), &inputs),
+            hash(&pallas, &inputs)
+        );
+    }
 }
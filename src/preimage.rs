@@ -0,0 +1,142 @@
+//! Proof of knowledge of a Poseidon preimage: "I know `x` such that `Poseidon(x) = y`".
+//!
+//! `x` is a single private field element and `y` the only public input. Since the input
+//! length is fixed, one key serves every proof:
+//!
+//! ```
+//! use halo2_proofs::dev::MockProver;
+//! use halo2curves::bn256::Fr;
+//! use poseidon_circuit::preimage::PreimageCircuit;
+//!
+//! let circuit = PreimageCircuit::new(Fr::from(42));
+//! let y = PreimageCircuit::digest(Fr::from(42));
+//! let prover = MockProver::run(10, &circuit, circuit.instance()).unwrap();
+//! assert_eq!(prover.verify(), Ok(()));
+//! assert_eq!(circuit.instance(), vec![vec![y]]);
+//! ```
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner},
+    plonk::{Circuit, Column, ConstraintSystem, Error, Instance},
+};
+use poseidon::Spec;
+
+use crate::{
+    limits::UNUSABLE_ROWS,
+    main_gate::{MainGate, MainGateConfig, RegionCtx},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash,
+    specs::BN256_T4_R3,
+};
+
+const T: usize = BN256_T4_R3.width;
+const RATE: usize = BN256_T4_R3.rate;
+const R_F: usize = BN256_T4_R3.r_f;
+const R_P: usize = BN256_T4_R3.r_p;
+
+#[derive(Clone, Debug)]
+pub struct PreimageCircuitConfig {
+    pconfig: MainGateConfig<T>,
+    instance: Column<Instance>,
+}
+
+pub struct PreimageCircuit<F: PrimeField> {
+    preimage: F,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> PreimageCircuit<F> {
+    pub fn new(preimage: F) -> Self {
+        Self { preimage }
+    }
+
+    /// The public input `y` matching the preimage `x`.
+    pub fn digest(preimage: F) -> F {
+        hash(&Spec::<F, T, RATE>::new(R_F, R_P), &[preimage])
+    }
+
+    pub fn instance(&self) -> Vec<Vec<F>> {
+        vec![vec![Self::digest(self.preimage)]]
+    }
+
+    /// The smallest `k` the circuit fits into; proving at a larger `k` only costs time.
+    pub fn min_k() -> u32 {
+        let rows = PoseidonChip::num_rows(&Spec::<F, T, RATE>::new(R_F, R_P), 1);
+        (rows + UNUSABLE_ROWS).next_power_of_two().trailing_zeros()
+    }
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for PreimageCircuit<F> {
+    type Config = PreimageCircuitConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self { preimage: F::ZERO }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        let instance = meta.instance_column();
+        meta.enable_equality(instance);
+        let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+        let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+        let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+        Self::Config { pconfig, instance }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        let spec = Spec::<F, T, RATE>::new(R_F, R_P);
+        let mut pchip = PoseidonChip::new(config.pconfig, spec);
+        pchip.update(vec![self.preimage]);
+        let output = layouter.assign_region(
+            || "poseidon preimage",
+            |region| {
+                let ctx = &mut RegionCtx::new(region, 0);
+                pchip.squeeze(ctx)
+            },
+        )?;
+        layouter.constrain_instance(output.cell(), config.instance, 0)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::prover::ProverContext;
+
+    #[test]
+    fn test_preimage_proof() {
+        let x = Fr::from(0xc0ffee_u64);
+        let y = PreimageCircuit::digest(x);
+
+        // keys only depend on the circuit, not on x
+        let ctx = ProverContext::setup(10, &PreimageCircuit::new(Fr::ZERO)).unwrap();
+        let proof = ctx.prove(&PreimageCircuit::new(x), &[&[y]]).unwrap();
+        assert!(ctx.verify(&proof, &[&[y]]).is_ok());
+        assert!(ctx.verify(&proof, &[&[y + Fr::ONE]]).is_err());
+    }
+
+    #[test]
+    fn test_min_k() {
+        let k = PreimageCircuit::<Fr>::min_k();
+        let circuit = PreimageCircuit::new(Fr::from(3));
+        let prover = MockProver::run(k, &circuit, circuit.instance()).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        assert!(MockProver::run(k - 1, &circuit, circuit.instance()).is_err());
+    }
+
+    #[test]
+    fn test_wrong_preimage() {
+        let y = PreimageCircuit::digest(Fr::from(1));
+        let circuit = PreimageCircuit::new(Fr::from(2));
+        let prover = MockProver::run(10, &circuit, vec![vec![y]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -0,0 +1,51 @@
+//! The items most integrations use, from one path that stays put when modules move:
+//! `use poseidon_circuit::prelude::*;`.
+//!
+//! Natively hashing and proving the same digest in a circuit:
+//!
+//! ```
+//! use poseidon_circuit::prelude::*;
+//!
+//! let spec = vetted_spec::<Fr, 4, 3>().unwrap();
+//! let message = [Fr::from(1), Fr::from(2)];
+//! let digest = hash(&spec, &message);
+//! assert_eq!(Hash::new(spec).hash_var_len(&message), digest);
+//!
+//! // exposes the digest of `message` as its only instance
+//! let _circuit = TestCircuit::new(message.to_vec());
+//! ```
+//!
+//! Functions whose names would clash, such as [`crate::poseidon2::hash`] or
+//! [`crate::smt::leaf`], stay in their modules.
+pub use ff::{Field, FromUniformBytes, PrimeField};
+pub use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner, Value},
+    plonk::{Circuit, ConstraintSystem, Error},
+};
+pub use halo2curves::bn256::{Bn256, Fr, G1Affine};
+pub use poseidon::Spec;
+
+pub use crate::{
+    commitment::{commit, CommitmentChip, Opening as CommitmentOpening},
+    instructions::PoseidonInstructions,
+    main_gate::{AssignedValue, MainGate, MainGateConfig, RegionCtx},
+    membership::{MembershipChip, MembershipCircuit},
+    merkle::{MerkleChip, MerklePath, MerkleTree},
+    packing_chip::PackingChip,
+    poseidon2::Poseidon2Spec,
+    poseidon2_circuit::Poseidon2Chip,
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::{hash, hash_to, hash_with_domain, HashSpec, PoseidonHash, Sponge},
+    preimage::PreimageCircuit,
+    presets::{Membership, Preimage, Preset, Range},
+    primitives::Hash,
+    prover::{ProverContext, TranscriptHash},
+    range_chip::RangeChip,
+    range_proof::RangeProofCircuit,
+    same_digest::SameDigestChip,
+    smt::{NodeStore, SmtChip, SparseMerkleTree},
+    specs::{checked_spec, vetted_spec, DigestIndex, Domain, SpecError, SpecParams, BN256_T4_R3},
+    sub_circuit::{PoseidonSubCircuit, SubCircuit, SubCircuitConfig},
+    test_circuit::TestCircuit,
+    vector_commitment::VectorCommitmentChip,
+};
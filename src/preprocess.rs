@@ -0,0 +1,347 @@
+//! Preprocessors turning the `task_data` of chunk and batch tasks into the message to hash.
+//!
+//! A task selects one by name in [`Task::preprocessor`], and gets the JSON of
+//! [`crate::task_data`] without one. A preprocessor also picks the [`Domain`] the message is
+//! hashed under, so messages of different formats never share a digest. [`Preprocessors`]
+//! holds the built-ins:
+//!
+//! * `json`: [`HashWitness`], under [`Domain::Pse`]
+//! * `hex`: a `0x`-prefixed hex blob, packed with [`pack_bytes`]
+//! * `rlp`, with the `rlp` feature: an RLP value in `0x`-hex or base64, every byte string of
+//!   it packed with [`pack_bytes`], in encoding order
+//!
+//! Blobs are hashed under [`Domain::ConstantLength`] of their packed length. Another input
+//! format is a [`TaskPreprocessor`] registered with
+//! [`crate::state::ProverState::with_preprocessor`].
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use halo2curves::bn256::Fr;
+
+use crate::{
+    packing::pack_bytes,
+    specs::Domain,
+    task::Task,
+    task_data::{HashWitness, TaskDataError},
+};
+
+/// Name of the preprocessor of tasks that do not select one.
+pub const DEFAULT_PREPROCESSOR: &str = "json";
+
+/// The message of a task and the domain to hash it under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashInput {
+    /// The inputs, and the digest the client expects if it sent one
+    pub witness: HashWitness<Fr>,
+    pub domain: Domain,
+}
+
+impl HashInput {
+    /// `inputs` under a constant length domain of their length, without an expected digest.
+    pub fn constant_length(inputs: Vec<Fr>) -> Self {
+        Self {
+            domain: Domain::ConstantLength {
+                len: inputs.len(),
+                outputs: 1,
+            },
+            witness: HashWitness {
+                inputs,
+                digest: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreprocessError {
+    /// No preprocessor of this name is registered
+    Unknown(String),
+    TaskData(TaskDataError),
+    /// `task_data` is not in the format of the preprocessor
+    Invalid {
+        preprocessor: String,
+        reason: String,
+    },
+}
+
+impl PreprocessError {
+    pub fn invalid(preprocessor: &str, reason: impl fmt::Display) -> Self {
+        Self::Invalid {
+            preprocessor: preprocessor.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(f, "unknown task_data preprocessor {name:?}"),
+            Self::TaskData(err) => write!(f, "{err}"),
+            Self::Invalid {
+                preprocessor,
+                reason,
+            } => write!(f, "task_data is not {preprocessor}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for PreprocessError {}
+
+impl From<TaskDataError> for PreprocessError {
+    fn from(err: TaskDataError) -> Self {
+        Self::TaskData(err)
+    }
+}
+
+/// Reads the message of a task from its `task_data`.
+pub trait TaskPreprocessor: Send + Sync {
+    /// The name tasks select it by.
+    fn name(&self) -> &str;
+
+    fn preprocess(&self, task_data: &[u8]) -> Result<HashInput, PreprocessError>;
+}
+
+/// The JSON of [`crate::task_data`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonArray;
+
+impl TaskPreprocessor for JsonArray {
+    fn name(&self) -> &str {
+        DEFAULT_PREPROCESSOR
+    }
+
+    fn preprocess(&self, task_data: &[u8]) -> Result<HashInput, PreprocessError> {
+        let task_data =
+            std::str::from_utf8(task_data).map_err(|err| PreprocessError::invalid("json", err))?;
+        Ok(HashInput {
+            witness: HashWitness::parse(task_data)?,
+            domain: Domain::Pse,
+        })
+    }
+}
+
+/// A `0x`-prefixed hex blob.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HexBlob;
+
+impl TaskPreprocessor for HexBlob {
+    fn name(&self) -> &str {
+        "hex"
+    }
+
+    fn preprocess(&self, task_data: &[u8]) -> Result<HashInput, PreprocessError> {
+        let invalid = |reason: &str| PreprocessError::invalid("hex", reason);
+        let hex = task_data
+            .strip_prefix(b"0x")
+            .ok_or_else(|| invalid("missing 0x prefix"))?;
+        if !hex.len().is_multiple_of(2) {
+            return Err(invalid("odd number of digits"));
+        }
+        let digit = |c: u8| {
+            (c as char)
+                .to_digit(16)
+                .ok_or_else(|| invalid("not a hex digit"))
+        };
+        let bytes = hex
+            .chunks(2)
+            .map(|pair| Ok(((digit(pair[0])? << 4) | digit(pair[1])?) as u8))
+            .collect::<Result<Vec<_>, PreprocessError>>()?;
+        Ok(HashInput::constant_length(pack_bytes(&bytes)))
+    }
+}
+
+/// An RLP value, see [`crate::decoders`].
+#[cfg(feature = "rlp")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rlp;
+
+#[cfg(feature = "rlp")]
+impl TaskPreprocessor for Rlp {
+    fn name(&self) -> &str {
+        "rlp"
+    }
+
+    fn preprocess(&self, task_data: &[u8]) -> Result<HashInput, PreprocessError> {
+        use crate::decoders::{BlobEncoding, BlobTask};
+
+        let data = String::from_utf8(task_data.to_vec())
+            .map_err(|err| PreprocessError::invalid("rlp", err))?;
+        let blob = BlobTask {
+            encoding: BlobEncoding::Rlp,
+            data,
+        };
+        let messages = blob
+            .messages::<Fr>()
+            .map_err(|err| PreprocessError::invalid("rlp", err))?;
+        Ok(HashInput::constant_length(messages.concat()))
+    }
+}
+
+/// Preprocessors by name.
+#[derive(Clone)]
+pub struct Preprocessors {
+    by_name: BTreeMap<String, Arc<dyn TaskPreprocessor>>,
+}
+
+impl Default for Preprocessors {
+    /// The built-ins.
+    fn default() -> Self {
+        Self {
+            by_name: BTreeMap::new(),
+        }
+        .with(JsonArray)
+        .with(HexBlob)
+        .with_rlp()
+    }
+}
+
+impl fmt::Debug for Preprocessors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.by_name.keys()).finish()
+    }
+}
+
+impl Preprocessors {
+    /// Adds `preprocessor`, replacing any registered under the same name.
+    pub fn with(mut self, preprocessor: impl TaskPreprocessor + 'static) -> Self {
+        self.by_name
+            .insert(preprocessor.name().to_string(), Arc::new(preprocessor));
+        self
+    }
+
+    #[cfg(feature = "rlp")]
+    fn with_rlp(self) -> Self {
+        self.with(Rlp)
+    }
+
+    #[cfg(not(feature = "rlp"))]
+    fn with_rlp(self) -> Self {
+        self
+    }
+
+    /// Registered names, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.by_name.keys().map(String::as_str)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn TaskPreprocessor> {
+        self.by_name.get(name).map(|p| p.as_ref())
+    }
+
+    /// The message of `task`, read by the preprocessor it selects.
+    pub fn preprocess(&self, task: &Task) -> Result<HashInput, PreprocessError> {
+        let name = task.preprocessor.as_deref().unwrap_or(DEFAULT_PREPROCESSOR);
+        let preprocessor = self
+            .get(name)
+            .ok_or_else(|| PreprocessError::Unknown(name.to_string()))?;
+        preprocessor.preprocess(task.task_data.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(preprocessor: Option<&str>, task_data: &str) -> Task {
+        Task {
+            task_data: task_data.to_string(),
+            preprocessor: preprocessor.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    /// Reads decimal numbers separated by commas.
+    struct Csv;
+
+    impl TaskPreprocessor for Csv {
+        fn name(&self) -> &str {
+            "csv"
+        }
+
+        fn preprocess(&self, task_data: &[u8]) -> Result<HashInput, PreprocessError> {
+            let inputs = String::from_utf8_lossy(task_data)
+                .split(',')
+                .map(|n| n.trim().parse::<u64>().map(Fr::from))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| PreprocessError::invalid("csv", err))?;
+            Ok(HashInput::constant_length(inputs))
+        }
+    }
+
+    #[test]
+    fn test_builtins() {
+        let preprocessors = Preprocessors::default();
+        let json = preprocessors
+            .preprocess(&task(None, r#"{"inputs": [1, "0x2"], "digest": "3"}"#))
+            .unwrap();
+        assert_eq!(json.witness.inputs, [Fr::from(1), Fr::from(2)]);
+        assert_eq!(json.witness.digest, Some(Fr::from(3)));
+        assert_eq!(json.domain, Domain::Pse);
+        assert_eq!(
+            preprocessors.preprocess(&task(Some("json"), "[1, 2]")),
+            Ok(HashInput {
+                witness: HashWitness {
+                    inputs: vec![Fr::from(1), Fr::from(2)],
+                    digest: None,
+                },
+                domain: Domain::Pse,
+            })
+        );
+
+        let hex = preprocessors
+            .preprocess(&task(Some("hex"), "0x0102"))
+            .unwrap();
+        assert_eq!(hex, HashInput::constant_length(pack_bytes(&[1, 2])));
+        assert_eq!(hex.domain, Domain::ConstantLength { len: 2, outputs: 1 });
+        for bad in ["0102", "0x012", "0x0g"] {
+            assert!(
+                matches!(
+                    preprocessors.preprocess(&task(Some("hex"), bad)),
+                    Err(PreprocessError::Invalid { .. })
+                ),
+                "{bad}"
+            );
+        }
+        assert!(matches!(
+            preprocessors.preprocess(&task(None, "0x0102")),
+            Err(PreprocessError::TaskData(TaskDataError::Json(_)))
+        ));
+        assert_eq!(
+            preprocessors.preprocess(&task(Some("csv"), "1,2")),
+            Err(PreprocessError::Unknown("csv".to_string()))
+        );
+    }
+
+    #[cfg(feature = "rlp")]
+    #[test]
+    fn test_rlp() {
+        let preprocessors = Preprocessors::default();
+        // [0x01, 0x02], as hex and as base64
+        for task_data in ["0xc20102", "wgEC"] {
+            let input = preprocessors
+                .preprocess(&task(Some("rlp"), task_data))
+                .unwrap();
+            assert_eq!(input.witness.inputs, [1u64, 1, 1, 2].map(Fr::from));
+        }
+        assert!(preprocessors
+            .preprocess(&task(Some("rlp"), "0xc201"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_plugin() {
+        let preprocessors = Preprocessors::default().with(Csv);
+        assert!(preprocessors.names().any(|name| name == "csv"));
+        let input = preprocessors
+            .preprocess(&task(Some("csv"), "4, 5, 6"))
+            .unwrap();
+        assert_eq!(
+            input,
+            HashInput::constant_length([4u64, 5, 6].map(Fr::from).to_vec())
+        );
+        assert!(matches!(
+            preprocessors.preprocess(&task(Some("csv"), "4, x")),
+            Err(PreprocessError::Invalid { preprocessor, .. }) if preprocessor == "csv"
+        ));
+    }
+}
@@ -0,0 +1,184 @@
+//! The statements this crate proves, behind one interface.
+//!
+//! Every [`Preset`] names a circuit, the public inputs a verifier checks it against and how
+//! both are built from a witness, so a service can wire up a statement by its
+//! [`Preset::NAME`] instead of assembling circuits and instance columns by hand:
+//!
+//! ```
+//! use halo2_proofs::dev::MockProver;
+//! use halo2curves::bn256::Fr;
+//! use poseidon_circuit::presets::{Preimage, Preset};
+//!
+//! let (circuit, public) = Preimage::witness_from(Fr::from(42));
+//! let prover = MockProver::run(10, &circuit, Preimage::instance(&public)).unwrap();
+//! assert_eq!(prover.verify(), Ok(()));
+//! ```
+//!
+//! Nullifiers are proven by [`Membership`], whose public inputs carry the nullifier of the
+//! opened leaf next to the root. There is no Merkle update statement yet.
+use halo2_proofs::plonk::Circuit;
+use halo2curves::bn256::Fr;
+
+use crate::{
+    membership::{self, MembershipCircuit},
+    merkle::MerklePath,
+    preimage::PreimageCircuit,
+    range_proof::RangeProofCircuit,
+};
+
+/// A statement: its circuit, its public inputs and how to build both from a witness.
+pub trait Preset {
+    /// Name the statement is requested by
+    const NAME: &'static str;
+    type Circuit: Circuit<Fr>;
+    type PublicInputs: Clone + std::fmt::Debug + PartialEq;
+    /// What the prover knows
+    type Witness;
+
+    fn witness_from(witness: Self::Witness) -> (Self::Circuit, Self::PublicInputs);
+
+    /// The instance columns the circuit is verified against.
+    fn instance(public: &Self::PublicInputs) -> Vec<Vec<Fr>>;
+}
+
+/// Names of every preset.
+pub const NAMES: &[&str] = &[Preimage::NAME, Membership::NAME, Range::NAME];
+
+/// Knowledge of `x` with `Poseidon(x) = digest`, see [`crate::preimage`].
+#[derive(Debug, Clone, Copy)]
+pub struct Preimage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreimagePublicInputs {
+    pub digest: Fr,
+}
+
+impl Preset for Preimage {
+    const NAME: &'static str = "preimage";
+    type Circuit = PreimageCircuit<Fr>;
+    type PublicInputs = PreimagePublicInputs;
+    type Witness = Fr;
+
+    fn witness_from(preimage: Fr) -> (Self::Circuit, Self::PublicInputs) {
+        let digest = PreimageCircuit::digest(preimage);
+        (
+            PreimageCircuit::new(preimage),
+            PreimagePublicInputs { digest },
+        )
+    }
+
+    fn instance(public: &Self::PublicInputs) -> Vec<Vec<Fr>> {
+        vec![vec![public.digest]]
+    }
+}
+
+/// A private leaf is in a public tree, with a nullifier, see [`crate::membership`].
+#[derive(Debug, Clone, Copy)]
+pub struct Membership;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MembershipPublicInputs {
+    pub root: Fr,
+    pub nullifier: Fr,
+}
+
+/// The secret committed to by the leaf and the path from the leaf to the root.
+#[derive(Debug, Clone)]
+pub struct LeafOpening {
+    pub secret: Fr,
+    pub path: MerklePath<Fr>,
+}
+
+impl Preset for Membership {
+    const NAME: &'static str = "membership";
+    type Circuit = MembershipCircuit<Fr>;
+    type PublicInputs = MembershipPublicInputs;
+    type Witness = LeafOpening;
+
+    fn witness_from(witness: LeafOpening) -> (Self::Circuit, Self::PublicInputs) {
+        let public = MembershipPublicInputs {
+            root: witness
+                .path
+                .root(&membership::spec(), membership::leaf(witness.secret)),
+            nullifier: membership::nullifier(witness.secret, witness.path.index),
+        };
+        (MembershipCircuit::new(witness.secret, witness.path), public)
+    }
+
+    fn instance(public: &Self::PublicInputs) -> Vec<Vec<Fr>> {
+        vec![vec![public.root, public.nullifier]]
+    }
+}
+
+/// A committed value is in `[0, 2^bits)`, see [`crate::range_proof`].
+#[derive(Debug, Clone, Copy)]
+pub struct Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangePublicInputs {
+    pub commitment: Fr,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RangeWitness {
+    pub value: Fr,
+    pub blinding: Fr,
+    /// Part of the circuit shape, so every bit size has its own keys
+    pub bits: usize,
+}
+
+impl Preset for Range {
+    const NAME: &'static str = "range";
+    type Circuit = RangeProofCircuit<Fr>;
+    type PublicInputs = RangePublicInputs;
+    type Witness = RangeWitness;
+
+    fn witness_from(witness: RangeWitness) -> (Self::Circuit, Self::PublicInputs) {
+        let commitment = RangeProofCircuit::commitment(witness.value, witness.blinding);
+        let circuit = RangeProofCircuit::new(witness.value, witness.blinding, witness.bits);
+        (circuit, RangePublicInputs { commitment })
+    }
+
+    fn instance(public: &Self::PublicInputs) -> Vec<Vec<Fr>> {
+        vec![vec![public.commitment]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::dev::MockProver;
+
+    use super::*;
+
+    #[test]
+    fn test_presets_match_circuits() {
+        let (circuit, public) = Preimage::witness_from(Fr::from(7));
+        assert_eq!(Preimage::instance(&public), circuit.instance());
+
+        let secrets = (1..5).map(Fr::from).collect::<Vec<_>>();
+        let tree = membership::tree(secrets.iter().copied().map(membership::leaf).collect(), 3);
+        let (circuit, public) = Membership::witness_from(LeafOpening {
+            secret: secrets[2],
+            path: tree.path(2),
+        });
+        assert_eq!(public.root, tree.root());
+        assert_eq!(Membership::instance(&public), circuit.instance());
+        let prover = MockProver::run(11, &circuit, Membership::instance(&public)).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let (circuit, public) = Range::witness_from(RangeWitness {
+            value: Fr::from(200),
+            blinding: Fr::from(3),
+            bits: 8,
+        });
+        assert_eq!(Range::instance(&public), circuit.instance());
+    }
+
+    #[test]
+    fn test_names_are_unique() {
+        let mut names = NAMES.to_vec();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), NAMES.len());
+    }
+}
@@ -0,0 +1,230 @@
+//! Native Poseidon hashing that matches [`PoseidonChip`] bit for bit, for computing the
+//! digests circuits expose as public inputs.
+//!
+//! Every function here absorbs, pads and squeezes like the chip started from the same
+//! [`Domain`]: the tests run both over every spec of [`crate::specs::SUPPORTED`].
+//!
+//! ```
+//! use halo2curves::bn256::Fr;
+//! use poseidon_circuit::primitives::Hash;
+//!
+//! let hasher = Hash::<Fr, 4, 3>::checked(8, 56).unwrap();
+//! let message = (0..5).map(Fr::from).collect::<Vec<_>>();
+//! // the digest of the service's chunk and batch tasks
+//! let digest = hasher.hash_var_len(&message);
+//! assert_ne!(digest, hasher.hash_fixed(&message));
+//! ```
+//!
+//! [`PoseidonChip`]: crate::poseidon_circuit::PoseidonChip
+use ff::{FromUniformBytes, PrimeField};
+use poseidon::Spec;
+
+use crate::{
+    poseidon_hash::hash_with_domain,
+    specs::{checked_spec, Domain, SpecError},
+};
+
+/// Hashes messages under one spec.
+#[derive(Clone, Debug)]
+pub struct Hash<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> {
+    spec: Spec<F, T, RATE>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> Hash<F, T, RATE> {
+    pub fn new(spec: Spec<F, T, RATE>) -> Self {
+        Self { spec }
+    }
+
+    /// A hasher under a vetted spec, see [`checked_spec`].
+    pub fn checked(r_f: usize, r_p: usize) -> Result<Self, SpecError> {
+        checked_spec(r_f, r_p).map(Self::new)
+    }
+
+    pub fn spec(&self) -> &Spec<F, T, RATE> {
+        &self.spec
+    }
+
+    /// Hashes a message whose length the application fixes, under
+    /// [`Domain::ConstantLength`] of its length and one output.
+    ///
+    /// The circuit is [`PoseidonChip::with_domain`] of that domain, then
+    /// [`PoseidonChip::squeeze`].
+    ///
+    /// [`PoseidonChip::with_domain`]: crate::poseidon_circuit::PoseidonChip::with_domain
+    /// [`PoseidonChip::squeeze`]: crate::poseidon_circuit::PoseidonChip::squeeze
+    pub fn hash_fixed(&self, inputs: &[F]) -> F {
+        let domain = Domain::ConstantLength {
+            len: inputs.len(),
+            outputs: 1,
+        };
+        self.hash_with_domain(domain, inputs)
+    }
+
+    /// Hashes a message of any length under [`Domain::Pse`], the digest of
+    /// [`crate::poseidon_hash::hash`] and of the chip's default domain.
+    pub fn hash_var_len(&self, inputs: &[F]) -> F {
+        self.hash_with_domain(Domain::Pse, inputs)
+    }
+
+    /// Hashes `inputs` starting from the capacity of `domain`, see
+    /// [`crate::poseidon_hash::hash_with_domain`].
+    pub fn hash_with_domain(&self, domain: Domain, inputs: &[F]) -> F {
+        hash_with_domain(&self.spec, domain, inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Error, Instance},
+    };
+    use halo2curves::bn256::Fr;
+    #[cfg(feature = "pasta")]
+    use halo2curves::pasta::{Fp, Fq};
+
+    use super::*;
+    use crate::{
+        main_gate::{MainGate, MainGateConfig, RegionCtx},
+        poseidon_circuit::PoseidonChip,
+        poseidon_hash::Sponge,
+        specs::SUPPORTED,
+    };
+
+    const K: u32 = 11;
+
+    #[derive(Clone, Debug)]
+    struct DomainConfig<const T: usize> {
+        main_gate: MainGateConfig<T>,
+        instance: Column<Instance>,
+    }
+
+    /// The digest of `inputs` under `domain`, as a public input.
+    struct DomainCircuit<F: PrimeField, const T: usize, const RATE: usize> {
+        inputs: Vec<F>,
+        domain: Domain,
+        r_f: usize,
+        r_p: usize,
+    }
+
+    impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> Circuit<F>
+        for DomainCircuit<F, T, RATE>
+    {
+        type Config = DomainConfig<T>;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                inputs: Vec::new(),
+                ..*self
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let main_gate = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            DomainConfig {
+                main_gate,
+                instance,
+            }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<F>,
+        ) -> Result<(), Error> {
+            let spec = Spec::<F, T, RATE>::new(self.r_f, self.r_p);
+            let (_, digest) = layouter.assign_region(
+                || "poseidon hash",
+                |region| {
+                    let chip = PoseidonChip::new(config.main_gate.clone(), spec.clone());
+                    chip.hash_with_domain(&mut RegionCtx::new(region, 0), self.domain, &self.inputs)
+                },
+            )?;
+            layouter.constrain_instance(digest.cell(), config.instance, 0)
+        }
+    }
+
+    /// Checks every hash of [`Hash`] against the chip, for one spec.
+    fn check_spec<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+        r_f: usize,
+        r_p: usize,
+    ) {
+        let hasher = Hash::<F, T, RATE>::checked(r_f, r_p).unwrap();
+        // fills the rate exactly at width 2 and 4, and leaves room at the wider ones
+        let inputs = (0..3u64).map(F::from).collect::<Vec<_>>();
+        let fixed = Domain::ConstantLength {
+            len: inputs.len(),
+            outputs: 1,
+        };
+        let other = Domain::ConstantLength { len: 9, outputs: 1 };
+        let mut digests = vec![
+            (fixed, hasher.hash_fixed(&inputs)),
+            (Domain::Pse, hasher.hash_var_len(&inputs)),
+        ];
+        for domain in [other, Domain::Constant(7), Domain::User(0), Domain::User(1)] {
+            digests.push((domain, hasher.hash_with_domain(domain, &inputs)));
+        }
+        for (domain, digest) in digests {
+            let circuit = DomainCircuit::<F, T, RATE> {
+                inputs: inputs.clone(),
+                domain,
+                r_f,
+                r_p,
+            };
+            let prover = MockProver::run(K, &circuit, vec![vec![digest]]).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "T={T} {domain:?}");
+            let prover = MockProver::run(K, &circuit, vec![vec![digest + F::ONE]]).unwrap();
+            assert!(prover.verify().is_err(), "T={T} {domain:?}");
+
+            // the sponge agrees as well
+            let mut sponge = Sponge::new(hasher.spec().clone(), domain);
+            sponge.absorb(&inputs);
+            assert_eq!(sponge.squeeze(), digest);
+        }
+        assert_eq!(
+            hasher.hash_var_len(&inputs),
+            crate::poseidon_hash::hash(hasher.spec(), &inputs)
+        );
+        assert_ne!(hasher.hash_fixed(&inputs), hasher.hash_var_len(&inputs));
+    }
+
+    #[test]
+    fn test_supported_specs() {
+        let mut checked = Vec::new();
+        let mut check = |field: &str, width: usize, run: fn(usize, usize)| {
+            let config = SUPPORTED
+                .iter()
+                .find(|c| c.field == field && c.width == width)
+                .unwrap_or_else(|| panic!("{field} t={width} is not supported"));
+            run(config.r_f, config.r_p);
+            checked.push((field.to_string(), width));
+        };
+        #[cfg(feature = "bn256-t2")]
+        check("bn256::Fr", 2, check_spec::<Fr, 2, 1>);
+        #[cfg(feature = "bn256-t3")]
+        check("bn256::Fr", 3, check_spec::<Fr, 3, 2>);
+        check("bn256::Fr", 4, check_spec::<Fr, 4, 3>);
+        #[cfg(feature = "bn256-t5")]
+        check("bn256::Fr", 5, check_spec::<Fr, 5, 4>);
+        #[cfg(feature = "bn256-t6")]
+        check("bn256::Fr", 6, check_spec::<Fr, 6, 5>);
+        #[cfg(feature = "bn256-t7")]
+        check("bn256::Fr", 7, check_spec::<Fr, 7, 6>);
+        #[cfg(feature = "bn256-t8")]
+        check("bn256::Fr", 8, check_spec::<Fr, 8, 7>);
+        #[cfg(feature = "bn256-t9")]
+        check("bn256::Fr", 9, check_spec::<Fr, 9, 8>);
+        #[cfg(feature = "pasta")]
+        check("pallas::Base", 3, check_spec::<Fp, 3, 2>);
+        #[cfg(feature = "pasta")]
+        check("vesta::Base", 3, check_spec::<Fq, 3, 2>);
+        // a spec added to SUPPORTED needs a line above
+        assert_eq!(checked.len(), SUPPORTED.len());
+    }
+}
@@ -0,0 +1,261 @@
+//! Proof details kept in an [`ArtifactStore`], so answers survive restarts and a task proven
+//! once is answered from the store when it comes again.
+//!
+//! A [`ProofStore`] writes the proof detail of a task, with its proof and public inputs,
+//! under `proofs/<generation>/<task digest>.json`, see [`Task::task_digest`], so any task of
+//! the same content finds it, whatever its `uuid` and `id`. `tasks/<uuid>` names the proof
+//! of the task of that `uuid`, and the verifying key of a proof goes under [`vk_key`], where
+//! [`crate::vk_cache::VkStore`] reads keys of earlier circuit versions from.
+//!
+//! The generation, e.g. [`crate::state::ProverState::proof_generation`], names the params,
+//! circuit version, salt, protocol label and default transcript: proofs of another one are
+//! not reused, though still found by `uuid`. Only successful proofs are kept, and no dry
+//! runs. Tasks of the same content proven at the same time are both proven; the last one to
+//! finish is kept.
+use std::{
+    collections::HashSet,
+    io,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    artifacts::{check_key, vk_key, ArtifactError, ArtifactStore},
+    task::{ProofDetail, Task},
+};
+
+/// Key of the proof detail of the tasks of `digest` proven by `generation`.
+pub fn proof_key(generation: &str, digest: &str) -> String {
+    format!("proofs/{generation}/{digest}.json")
+}
+
+/// Key of the [`proof_key`] of the task of `uuid`.
+pub fn task_key(uuid: &str) -> String {
+    format!("tasks/{uuid}")
+}
+
+#[derive(Debug)]
+pub struct ProofStore {
+    store: Arc<dyn ArtifactStore>,
+    generation: String,
+    /// Hashes of the verifying keys known to be in the store
+    vks: Mutex<HashSet<String>>,
+}
+
+impl ProofStore {
+    /// Keeps proofs of `generation` in `store`, which may hold the keys of
+    /// [`crate::keygen`] too.
+    ///
+    /// # Panics
+    ///
+    /// If `generation` is not a key segment, see [`check_key`].
+    pub fn new(store: Arc<dyn ArtifactStore>, generation: impl Into<String>) -> Self {
+        let generation = generation.into();
+        assert!(
+            !generation.contains('/') && check_key(&generation).is_ok(),
+            "generation {generation:?} is not a key segment"
+        );
+        Self {
+            store,
+            generation,
+            vks: Mutex::default(),
+        }
+    }
+
+    pub fn generation(&self) -> &str {
+        &self.generation
+    }
+
+    /// The proof of a task of the same content as `task`, as the answer to `task`; `None` if
+    /// there is none, or if it was compressed otherwise than `task` asks for.
+    pub fn lookup(&self, task: &Task) -> Result<Option<ProofDetail>, ArtifactError> {
+        let key = proof_key(&self.generation, &task.task_digest());
+        let Some(detail) = self.read(&key)? else {
+            return Ok(None);
+        };
+        if detail.compression != task.compression {
+            return Ok(None);
+        }
+        Ok(Some(ProofDetail {
+            id: task.id.clone(),
+            job: None,
+            ..detail
+        }))
+    }
+
+    /// The proof of the task of `uuid`, as it was answered.
+    pub fn by_uuid(&self, uuid: &str) -> Result<Option<ProofDetail>, ArtifactError> {
+        let key = match self.store.get(&task_key(uuid)) {
+            Ok(key) => String::from_utf8(key).map_err(|err| invalid_data(err.to_string()))?,
+            Err(ArtifactError::NotFound(_)) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        self.read(&key)
+    }
+
+    /// The proof of the tasks of `digest` proven by this generation.
+    pub fn by_digest(&self, digest: &str) -> Result<Option<ProofDetail>, ArtifactError> {
+        self.read(&proof_key(&self.generation, digest))
+    }
+
+    /// Keeps `detail`, the answer to `task`, if it is a proof; returns whether it did. Tasks
+    /// whose `uuid` is not a key segment are only found by their content.
+    pub fn record(&self, task: &Task, detail: &ProofDetail) -> Result<bool, ArtifactError> {
+        if !detail.error.is_empty() || task.dry_run || detail.proof_data.is_empty() {
+            return Ok(false);
+        }
+        let key = proof_key(&self.generation, &task.task_digest());
+        let json = serde_json::to_vec(detail).expect("proof details serialize");
+        self.store.put(&key, &json)?;
+        let pointer = task_key(&task.uuid);
+        if !task.uuid.contains('/') && check_key(&pointer).is_ok() {
+            self.store.put(&pointer, key.as_bytes())?;
+        }
+        Ok(true)
+    }
+
+    /// Puts the verifying key of `vk_hash` into the store unless it is there already; `vk`
+    /// gives its bytes, in `SerdeFormat::RawBytes`, and is only called if it is not.
+    pub fn record_vk(
+        &self,
+        vk_hash: &str,
+        vk: impl FnOnce() -> Option<Vec<u8>>,
+    ) -> Result<(), ArtifactError> {
+        if self.vks.lock().unwrap().contains(vk_hash) {
+            return Ok(());
+        }
+        let key = vk_key(vk_hash);
+        match self.store.get(&key) {
+            Ok(_) => {}
+            Err(ArtifactError::NotFound(_)) => match vk() {
+                Some(bytes) => self.store.put(&key, &bytes)?,
+                None => return Ok(()),
+            },
+            Err(err) => return Err(err),
+        }
+        self.vks.lock().unwrap().insert(vk_hash.to_string());
+        Ok(())
+    }
+
+    fn read(&self, key: &str) -> Result<Option<ProofDetail>, ArtifactError> {
+        match self.store.get(key) {
+            Ok(json) => serde_json::from_slice(&json)
+                .map(Some)
+                .map_err(|err| invalid_data(format!("{key}: {err}"))),
+            Err(ArtifactError::NotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+fn invalid_data(message: String) -> ArtifactError {
+    ArtifactError::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        artifacts::{LocalStore, MemoryStore},
+        compression::ProofCompression,
+        stage::Stage,
+        task::ProofType,
+        Error,
+    };
+
+    fn task(uuid: &str, task_data: &str) -> Task {
+        Task {
+            uuid: uuid.to_string(),
+            id: format!("id-{uuid}"),
+            task_type: ProofType::Chunk,
+            task_data: task_data.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn proof(task: &Task) -> ProofDetail {
+        ProofDetail {
+            id: task.id.clone(),
+            proof_type: task.task_type,
+            proof_data: "AAEC".to_string(),
+            instances: vec!["0x01".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_dedup() {
+        let store = ProofStore::new(Arc::new(MemoryStore::new()), "gen1");
+        let first = task("a", "[1, 2]");
+        assert_eq!(store.lookup(&first).unwrap(), None);
+        assert!(store.record(&first, &proof(&first)).unwrap());
+
+        // the same content in another layout is answered under its own id
+        let again = task("b", "[1,2]");
+        let answer = store.lookup(&again).unwrap().unwrap();
+        assert_eq!(answer.id, "id-b");
+        assert_eq!(answer.instances, ["0x01"]);
+        assert_eq!(store.lookup(&task("c", "[1, 3]")).unwrap(), None);
+        let gzip = Task {
+            compression: Some(ProofCompression::Gzip),
+            ..again.clone()
+        };
+        assert_eq!(store.lookup(&gzip).unwrap(), None);
+
+        assert_eq!(store.by_uuid("a").unwrap().unwrap().id, "id-a");
+        assert_eq!(store.by_uuid("b").unwrap(), None);
+        let digest = first.task_digest();
+        assert_eq!(store.by_digest(&digest).unwrap(), Some(proof(&first)));
+
+        // failures and dry runs are not kept
+        let failed = proof(&again).with_error(Stage::Prove, &Error::new(Stage::Prove, "oom"));
+        assert!(!store.record(&task("d", "[4]"), &failed).unwrap());
+        let dry_run = Task {
+            dry_run: true,
+            ..task("e", "[5]")
+        };
+        assert!(!store.record(&dry_run, &proof(&dry_run)).unwrap());
+        assert_eq!(store.by_uuid("d").unwrap(), None);
+        assert_eq!(store.by_uuid("e").unwrap(), None);
+    }
+
+    #[test]
+    fn test_restart() {
+        let dir = std::env::temp_dir().join(format!("proof-store-{}", std::process::id()));
+        let first = task("a", "[1]");
+        let store = ProofStore::new(Arc::new(LocalStore::new(&dir)), "gen1");
+        store.record(&first, &proof(&first)).unwrap();
+        let mut calls = 0;
+        for _ in 0..2 {
+            store
+                .record_vk("abcd", || {
+                    calls += 1;
+                    Some(vec![1, 2, 3])
+                })
+                .unwrap();
+        }
+        assert_eq!(calls, 1);
+        // an odd uuid is only found by content
+        let odd = task("../x", "[2]");
+        assert!(store.record(&odd, &proof(&odd)).unwrap());
+
+        let reopened = ProofStore::new(Arc::new(LocalStore::new(&dir)), "gen1");
+        assert_eq!(reopened.by_uuid("a").unwrap(), Some(proof(&first)));
+        assert!(reopened.lookup(&odd).unwrap().is_some());
+        reopened.record_vk("abcd", || unreachable!()).unwrap();
+        assert_eq!(
+            LocalStore::new(&dir).get(&vk_key("abcd")).unwrap(),
+            [1, 2, 3]
+        );
+        // another generation does not reuse the proof, but still finds it by uuid
+        let upgraded = ProofStore::new(Arc::new(LocalStore::new(&dir)), "gen2");
+        assert_eq!(upgraded.lookup(&first).unwrap(), None);
+        assert!(upgraded.by_uuid("a").unwrap().is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "key segment")]
+    fn test_generation() {
+        ProofStore::new(Arc::new(MemoryStore::new()), "a/b");
+    }
+}
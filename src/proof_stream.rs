@@ -0,0 +1,318 @@
+//! Large proofs sent as a stream of frames instead of one Base64 body.
+//!
+//! An aggregated batch proof can be too large for a single response. Past a configured
+//! size, [`StreamConfig::frames`] cuts it into [`ProofFrame`]s, each holding one chunk and
+//! its offset in the proof; the last frame also carries the total length and the Blake2b-256
+//! of the whole proof. Frames are produced lazily and [`write_frames`] writes one JSON line
+//! per frame, so a slow reader holds the producer back instead of the proof being buffered
+//! in an encoded copy. [`Reassembler`] (or [`read_frames`]) puts the proof back together on
+//! the client and checks it.
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+use base64::{engine::general_purpose::STANDARD as BS64, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::task::{ProofDetail, ProofType};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProofFrame {
+    /// Id of the task the proof answers
+    pub id: String,
+    /// Position of `data` in the proof
+    pub offset: u64,
+    /// Base64 chunk of the proof
+    #[serde(with = "crate::task::base64_bytes")]
+    pub data: String,
+    /// Present on the last frame only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<StreamEnd>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StreamEnd {
+    pub total_len: u64,
+    /// Hex Blake2b-256 of the whole proof
+    pub blake2b: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamConfig {
+    /// Batch proofs longer than this many bytes are streamed
+    pub threshold: usize,
+    pub chunk_len: usize,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 4 << 20,
+            chunk_len: 1 << 20,
+        }
+    }
+}
+
+impl StreamConfig {
+    /// Whether `detail` should be streamed rather than sent whole.
+    pub fn should_stream(&self, detail: &ProofDetail) -> bool {
+        // decoded length, without decoding
+        let padding = detail
+            .proof_data
+            .bytes()
+            .rev()
+            .take_while(|b| *b == b'=')
+            .count();
+        let len = (detail.proof_data.len() / 4 * 3).saturating_sub(padding);
+        detail.proof_type == ProofType::Batch && len > self.threshold
+    }
+
+    pub fn frames<'a>(&self, id: &str, proof: &'a [u8]) -> Frames<'a> {
+        Frames {
+            id: id.to_string(),
+            proof,
+            chunk_len: self.chunk_len.max(1),
+            offset: 0,
+            done: false,
+        }
+    }
+}
+
+/// The frames of one proof, built as they are pulled.
+pub struct Frames<'a> {
+    id: String,
+    proof: &'a [u8],
+    chunk_len: usize,
+    offset: usize,
+    done: bool,
+}
+
+impl Iterator for Frames<'_> {
+    type Item = ProofFrame;
+
+    fn next(&mut self) -> Option<ProofFrame> {
+        if self.done {
+            return None;
+        }
+        let end = (self.offset + self.chunk_len).min(self.proof.len());
+        let frame = ProofFrame {
+            id: self.id.clone(),
+            offset: self.offset as u64,
+            data: BS64.encode(&self.proof[self.offset..end]),
+            end: (end == self.proof.len()).then(|| StreamEnd {
+                total_len: self.proof.len() as u64,
+                blake2b: digest(self.proof),
+            }),
+        };
+        self.done = frame.end.is_some();
+        self.offset = end;
+        Some(frame)
+    }
+}
+
+fn digest(bytes: &[u8]) -> String {
+    blake2b_simd::Params::new()
+        .hash_length(32)
+        .hash(bytes)
+        .to_hex()
+        .to_string()
+}
+
+/// Writes every frame as a JSON line, flushing after each one.
+pub fn write_frames(
+    mut writer: impl Write,
+    frames: impl Iterator<Item = ProofFrame>,
+) -> io::Result<()> {
+    for frame in frames {
+        serde_json::to_writer(&mut writer, &frame)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    Frame(serde_json::Error),
+    /// A frame of another proof
+    WrongId(String),
+    /// A frame does not start where the previous one ended
+    Gap {
+        expected: u64,
+        offset: u64,
+    },
+    InvalidData,
+    LengthMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    ChecksumMismatch,
+    /// The stream ended before the last frame
+    Truncated,
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read the proof stream: {err}"),
+            Self::Frame(err) => write!(f, "malformed frame: {err}"),
+            Self::WrongId(id) => write!(f, "frame of another proof {id:?}"),
+            Self::Gap { expected, offset } => {
+                write!(f, "frame at offset {offset}, expected {expected}")
+            }
+            Self::InvalidData => write!(f, "frame data is not base64"),
+            Self::LengthMismatch { expected, actual } => {
+                write!(f, "proof is {actual} bytes long, expected {expected}")
+            }
+            Self::ChecksumMismatch => write!(f, "proof does not match its blake2b digest"),
+            Self::Truncated => write!(f, "proof stream ended early"),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+/// Collects the frames of one proof, in order.
+#[derive(Debug)]
+pub struct Reassembler {
+    id: String,
+    proof: Vec<u8>,
+}
+
+impl Reassembler {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            proof: Vec::new(),
+        }
+    }
+
+    /// Adds the next frame; returns the proof once the last frame checks out.
+    pub fn push(&mut self, frame: ProofFrame) -> Result<Option<Vec<u8>>, StreamError> {
+        if frame.id != self.id {
+            return Err(StreamError::WrongId(frame.id));
+        }
+        let expected = self.proof.len() as u64;
+        if frame.offset != expected {
+            return Err(StreamError::Gap {
+                expected,
+                offset: frame.offset,
+            });
+        }
+        let chunk = BS64
+            .decode(&frame.data)
+            .map_err(|_| StreamError::InvalidData)?;
+        self.proof.extend_from_slice(&chunk);
+        let Some(end) = frame.end else {
+            return Ok(None);
+        };
+        if end.total_len != self.proof.len() as u64 {
+            return Err(StreamError::LengthMismatch {
+                expected: end.total_len,
+                actual: self.proof.len() as u64,
+            });
+        }
+        if !digest(&self.proof).eq_ignore_ascii_case(&end.blake2b) {
+            return Err(StreamError::ChecksumMismatch);
+        }
+        Ok(Some(std::mem::take(&mut self.proof)))
+    }
+}
+
+/// Reads the JSON-line frames of proof `id` up to the last one and returns the checked proof.
+pub fn read_frames(reader: impl BufRead, id: &str) -> Result<Vec<u8>, StreamError> {
+    let mut reassembler = Reassembler::new(id);
+    for line in reader.lines() {
+        let line = line.map_err(StreamError::Io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let frame = serde_json::from_str(&line).map_err(StreamError::Frame)?;
+        if let Some(proof) = reassembler.push(frame)? {
+            return Ok(proof);
+        }
+    }
+    Err(StreamError::Truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> StreamConfig {
+        StreamConfig {
+            threshold: 8,
+            chunk_len: 7,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let proof = (0..=255u8).collect::<Vec<_>>();
+        let frames = config().frames("b-1", &proof).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 37);
+        assert!(frames[..36].iter().all(|frame| frame.end.is_none()));
+        assert_eq!(frames[36].end.as_ref().unwrap().total_len, 256);
+
+        let mut stream = Vec::new();
+        write_frames(&mut stream, frames.into_iter()).unwrap();
+        assert_eq!(read_frames(stream.as_slice(), "b-1").unwrap(), proof);
+
+        // an empty proof is still one frame
+        let empty = config().frames("b-2", &[]).collect::<Vec<_>>();
+        assert_eq!(empty.len(), 1);
+        assert_eq!(
+            Reassembler::new("b-2").push(empty[0].clone()).unwrap(),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn test_rejections() {
+        let proof = [7u8; 20];
+        let frames = config().frames("b-1", &proof).collect::<Vec<_>>();
+
+        let mut skipped = Reassembler::new("b-1");
+        assert!(matches!(
+            skipped.push(frames[1].clone()),
+            Err(StreamError::Gap {
+                expected: 0,
+                offset: 7
+            })
+        ));
+        assert!(matches!(
+            Reassembler::new("b-2").push(frames[0].clone()),
+            Err(StreamError::WrongId(_))
+        ));
+
+        let mut tampered = frames.clone();
+        tampered[1].data = BS64.encode([8u8; 7]);
+        let mut reassembler = Reassembler::new("b-1");
+        let results = tampered
+            .into_iter()
+            .map(|frame| reassembler.push(frame))
+            .collect::<Vec<_>>();
+        assert!(matches!(results[2], Err(StreamError::ChecksumMismatch)));
+
+        let mut stream = Vec::new();
+        write_frames(&mut stream, frames.into_iter().take(2)).unwrap();
+        assert!(matches!(
+            read_frames(stream.as_slice(), "b-1"),
+            Err(StreamError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn test_should_stream() {
+        let detail = |proof_type, len| ProofDetail {
+            proof_type,
+            proof_data: BS64.encode(vec![0u8; len]),
+            ..Default::default()
+        };
+        assert!(config().should_stream(&detail(ProofType::Batch, 9)));
+        assert!(!config().should_stream(&detail(ProofType::Batch, 8)));
+        assert!(!config().should_stream(&detail(ProofType::Chunk, 64)));
+    }
+}
@@ -0,0 +1,730 @@
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io,
+    io::BufReader,
+    marker::PhantomData,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::{
+    plonk::{
+        create_proof, keygen_pk, keygen_vk, verify_proof, Circuit, ConstraintSystem, Error,
+        ProvingKey, VerifyingKey,
+    },
+    poly::{
+        commitment::Params,
+        kzg::{
+            commitment::{KZGCommitmentScheme, ParamsKZG},
+            multiopen::{ProverGWC, VerifierGWC},
+            strategy::SingleStrategy,
+        },
+        Rotation,
+    },
+    transcript::{
+        Blake2bRead, Blake2bWrite, Challenge255, Keccak256Read, Keccak256Write, Transcript,
+        TranscriptReadBuffer, TranscriptWriterBuffer,
+    },
+    SerdeFormat,
+};
+use halo2curves::bn256::{Bn256, Fr, G1Affine};
+use rand_core::OsRng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fs_transcript::{PoseidonRead, PoseidonWrite},
+    packing::pack_bytes,
+};
+
+/// The hash deriving the challenges of a proof; a proof only verifies in the transcript it
+/// was created in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptHash {
+    /// For native verifiers
+    #[default]
+    Blake2b,
+    /// For EVM verifiers, see [`ProverContext::prove_keccak`]
+    Keccak256,
+    /// For verifiers in circuits, see [`crate::fs_transcript::PoseidonWrite`]
+    Poseidon,
+}
+
+impl TranscriptHash {
+    pub const ALL: [Self; 3] = [Self::Blake2b, Self::Keccak256, Self::Poseidon];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Blake2b => "blake2b",
+            Self::Keccak256 => "keccak256",
+            Self::Poseidon => "poseidon",
+        }
+    }
+}
+
+impl FromStr for TranscriptHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|hash| hash.as_str() == s)
+            .ok_or_else(|| {
+                format!("unknown transcript {s:?}, expected blake2b, keccak256 or poseidon")
+            })
+    }
+}
+
+/// The params and the proving key of one circuit shape at one `k`, built once and reused
+/// across proofs, so that a worker runs keygen once per context instead of once per proof.
+///
+/// Only those two are kept. The proving key holds the fixed and permutation polynomials and
+/// the constants of the evaluation domain; the FFT twiddles and the scratch buffers are
+/// still computed and allocated by halo2 within every `create_proof` call.
+pub struct ProverContext<ConcreteCircuit: Circuit<Fr>> {
+    params: ParamsKZG<Bn256>,
+    pk: Arc<ProvingKey<G1Affine>>,
+    vk_hash: String,
+    salt: Option<Fr>,
+    protocol_label: Option<String>,
+    _marker: PhantomData<ConcreteCircuit>,
+}
+
+impl<ConcreteCircuit: Circuit<Fr>> ProverContext<ConcreteCircuit> {
+    /// Runs keygen for `circuit` against already loaded params.
+    pub fn new(params: ParamsKZG<Bn256>, circuit: &ConcreteCircuit) -> Result<Self, Error> {
+        let vk = keygen_vk(&params, circuit)?;
+        let pk = keygen_pk(&params, vk, circuit)?;
+        Ok(Self::from_pk(params, Arc::new(pk)))
+    }
+
+    /// A context around a proving key generated earlier for `params`, skipping keygen.
+    pub fn from_pk(params: ParamsKZG<Bn256>, pk: Arc<ProvingKey<G1Affine>>) -> Self {
+        Self {
+            params,
+            vk_hash: vk_hash(pk.get_vk()),
+            pk,
+            salt: None,
+            protocol_label: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Absorbs `salt` into the transcript before anything else, on proving and verifying.
+    ///
+    /// Proofs of a context with one salt fail to verify under any other salt or none, so a
+    /// staging deployment cannot produce proofs the production verifier accepts even though
+    /// the circuits and keys match. EVM verifiers have to absorb the same salt first.
+    pub fn with_salt(mut self, salt: Fr) -> Self {
+        self.salt = Some(salt);
+        self
+    }
+
+    pub fn salt(&self) -> Option<Fr> {
+        self.salt
+    }
+
+    /// Absorbs `label`, e.g. `"acme-rollup/v1"`, into the transcript before the verifying key.
+    ///
+    /// Applications sharing this circuit use different labels, so a proof made for one is
+    /// rejected by the verifiers of all others. Unlike the salt, the label is public and
+    /// recorded next to every proof.
+    pub fn with_protocol_label(mut self, label: impl Into<String>) -> Self {
+        self.protocol_label = Some(label.into());
+        self
+    }
+
+    pub fn protocol_label(&self) -> Option<&str> {
+        self.protocol_label.as_deref()
+    }
+
+    /// Generates fresh (insecure, test-only) params of size `2^k` and runs keygen.
+    pub fn setup(k: u32, circuit: &ConcreteCircuit) -> Result<Self, Error> {
+        Self::new(ParamsKZG::<Bn256>::setup(k, OsRng), circuit)
+    }
+
+    pub fn k(&self) -> u32 {
+        self.params.k()
+    }
+
+    pub fn params(&self) -> &ParamsKZG<Bn256> {
+        &self.params
+    }
+
+    pub fn pk(&self) -> &ProvingKey<G1Affine> {
+        &self.pk
+    }
+
+    /// [`vk_hash`] of the verifying key of this context.
+    pub fn vk_hash(&self) -> &str {
+        &self.vk_hash
+    }
+
+    /// Bytes of every proof of this context, see [`estimate_proof_size`].
+    pub fn proof_size(&self) -> usize {
+        estimate_proof_size(self.pk.get_vk().cs())
+    }
+
+    /// Creates a Blake2b-transcript proof of `circuit` for the given instance columns.
+    pub fn prove(&self, circuit: &ConcreteCircuit, instances: &[&[Fr]]) -> Result<Vec<u8>, Error> {
+        self.prove_with::<Blake2bWrite<_, _, _>>(circuit, instances)
+    }
+
+    /// Checks a proof produced by [`ProverContext::prove`] against the instance columns.
+    pub fn verify(&self, proof: &[u8], instances: &[&[Fr]]) -> Result<(), Error> {
+        self.verify_with::<Blake2bRead<_, _, _>>(proof, instances)
+    }
+
+    /// Checks a Blake2b-transcript proof against another verifying key of the same circuit,
+    /// e.g. the key of an older circuit version; the params must fit it.
+    pub fn verify_with_vk(
+        &self,
+        vk: &VerifyingKey<G1Affine>,
+        proof: &[u8],
+        instances: &[&[Fr]],
+    ) -> Result<(), Error> {
+        self.verify_against::<Blake2bRead<_, _, _>>(vk, proof, instances)
+    }
+
+    /// Creates a Keccak256-transcript proof, the transcript EVM verifiers replay.
+    pub fn prove_keccak(
+        &self,
+        circuit: &ConcreteCircuit,
+        instances: &[&[Fr]],
+    ) -> Result<Vec<u8>, Error> {
+        self.prove_with::<Keccak256Write<_, _, _>>(circuit, instances)
+    }
+
+    /// Checks a proof produced by [`ProverContext::prove_keccak`].
+    pub fn verify_keccak(&self, proof: &[u8], instances: &[&[Fr]]) -> Result<(), Error> {
+        self.verify_with::<Keccak256Read<_, _, _>>(proof, instances)
+    }
+
+    /// Creates a proof in the transcript of `hash`; [`ProverContext::prove`] and
+    /// [`ProverContext::prove_keccak`] for Blake2b and Keccak256.
+    pub fn prove_in(
+        &self,
+        hash: TranscriptHash,
+        circuit: &ConcreteCircuit,
+        instances: &[&[Fr]],
+    ) -> Result<Vec<u8>, Error> {
+        match hash {
+            TranscriptHash::Blake2b => self.prove(circuit, instances),
+            TranscriptHash::Keccak256 => self.prove_keccak(circuit, instances),
+            TranscriptHash::Poseidon => self.prove_with::<PoseidonWrite<_>>(circuit, instances),
+        }
+    }
+
+    /// Checks a proof produced by [`ProverContext::prove_in`] with the same `hash`.
+    pub fn verify_in(
+        &self,
+        hash: TranscriptHash,
+        proof: &[u8],
+        instances: &[&[Fr]],
+    ) -> Result<(), Error> {
+        self.verify_with_vk_in(hash, self.pk.get_vk(), proof, instances)
+    }
+
+    /// As [`ProverContext::verify_with_vk`], for proofs in the transcript of `hash`.
+    pub fn verify_with_vk_in(
+        &self,
+        hash: TranscriptHash,
+        vk: &VerifyingKey<G1Affine>,
+        proof: &[u8],
+        instances: &[&[Fr]],
+    ) -> Result<(), Error> {
+        match hash {
+            TranscriptHash::Blake2b => {
+                self.verify_against::<Blake2bRead<_, _, _>>(vk, proof, instances)
+            }
+            TranscriptHash::Keccak256 => {
+                self.verify_against::<Keccak256Read<_, _, _>>(vk, proof, instances)
+            }
+            TranscriptHash::Poseidon => {
+                self.verify_against::<PoseidonRead<_>>(vk, proof, instances)
+            }
+        }
+    }
+
+    /// Verifies `(proof, instances)` pairs in parallel, see [`verify_all`].
+    pub fn verify_batch(
+        &self,
+        proofs: &[(Vec<u8>, Vec<Vec<Fr>>)],
+        abort_on_failure: bool,
+    ) -> Vec<VerifyOutcome>
+    where
+        ConcreteCircuit: Sync,
+    {
+        verify_all(proofs, abort_on_failure, |(proof, instances)| {
+            let instances = instances.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            self.verify(proof, &instances)
+                .map_err(|err| format!("{err:?}"))
+        })
+    }
+
+    fn prove_with<W: TranscriptWriterBuffer<Vec<u8>, G1Affine, Challenge255<G1Affine>>>(
+        &self,
+        circuit: &ConcreteCircuit,
+        instances: &[&[Fr]],
+    ) -> Result<Vec<u8>, Error> {
+        let mut transcript = W::init(vec![]);
+        init_transcript(&mut transcript, self.protocol_label.as_deref(), self.salt)?;
+        create_proof::<KZGCommitmentScheme<_>, ProverGWC<'_, _>, _, _, W, _>(
+            &self.params,
+            &self.pk,
+            std::slice::from_ref(circuit),
+            &[instances],
+            OsRng,
+            &mut transcript,
+        )?;
+        Ok(transcript.finalize())
+    }
+
+    fn verify_with<'a, R: TranscriptReadBuffer<&'a [u8], G1Affine, Challenge255<G1Affine>>>(
+        &self,
+        proof: &'a [u8],
+        instances: &[&[Fr]],
+    ) -> Result<(), Error> {
+        self.verify_against::<R>(self.pk.get_vk(), proof, instances)
+    }
+
+    fn verify_against<'a, R: TranscriptReadBuffer<&'a [u8], G1Affine, Challenge255<G1Affine>>>(
+        &self,
+        vk: &VerifyingKey<G1Affine>,
+        proof: &'a [u8],
+        instances: &[&[Fr]],
+    ) -> Result<(), Error> {
+        verify_transcript::<R>(
+            &self.params,
+            vk,
+            self.protocol_label.as_deref(),
+            self.salt,
+            proof,
+            instances,
+        )
+    }
+}
+
+/// Absorbs the protocol label, then the salt, ahead of everything `create_proof` writes.
+fn init_transcript(
+    transcript: &mut impl Transcript<G1Affine, Challenge255<G1Affine>>,
+    protocol_label: Option<&str>,
+    salt: Option<Fr>,
+) -> Result<(), Error> {
+    if let Some(label) = protocol_label {
+        for element in pack_bytes::<Fr>(label.as_bytes()) {
+            transcript.common_scalar(element)?;
+        }
+    }
+    if let Some(salt) = salt {
+        transcript.common_scalar(salt)?;
+    }
+    Ok(())
+}
+
+fn verify_transcript<'a, R: TranscriptReadBuffer<&'a [u8], G1Affine, Challenge255<G1Affine>>>(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    protocol_label: Option<&str>,
+    salt: Option<Fr>,
+    proof: &'a [u8],
+    instances: &[&[Fr]],
+) -> Result<(), Error> {
+    let mut transcript = R::init(proof);
+    init_transcript(&mut transcript, protocol_label, salt)?;
+    let strategy = SingleStrategy::new(params);
+    verify_proof::<
+        KZGCommitmentScheme<Bn256>,
+        VerifierGWC<'_, Bn256>,
+        Challenge255<G1Affine>,
+        R,
+        SingleStrategy<'_, Bn256>,
+    >(params, vk, strategy, &[instances], &mut transcript)
+}
+
+/// Checks a Blake2b-transcript proof of an unsalted context with only the verifying key and
+/// params of `vk`'s size, e.g. in light clients that never hold a proving key.
+pub fn verify_detached(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    protocol_label: Option<&str>,
+    proof: &[u8],
+    instances: &[&[Fr]],
+) -> Result<(), Error> {
+    verify_transcript::<Blake2bRead<_, _, _>>(params, vk, protocol_label, None, proof, instances)
+}
+
+/// As [`verify_detached`], for proofs in the transcript of `hash` of a context with `salt`,
+/// see [`ProverContext::with_salt`].
+pub fn verify_detached_in(
+    hash: TranscriptHash,
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    protocol_label: Option<&str>,
+    salt: Option<Fr>,
+    proof: &[u8],
+    instances: &[&[Fr]],
+) -> Result<(), Error> {
+    let label = protocol_label;
+    match hash {
+        TranscriptHash::Blake2b => {
+            verify_transcript::<Blake2bRead<_, _, _>>(params, vk, label, salt, proof, instances)
+        }
+        TranscriptHash::Keccak256 => {
+            verify_transcript::<Keccak256Read<_, _, _>>(params, vk, label, salt, proof, instances)
+        }
+        TranscriptHash::Poseidon => {
+            verify_transcript::<PoseidonRead<_>>(params, vk, label, salt, proof, instances)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    Valid,
+    Invalid(String),
+    /// Not checked because an earlier failure aborted the batch
+    Skipped,
+}
+
+/// Runs `verify` over `items` on the rayon pool, returning one outcome per item, in order.
+///
+/// With `abort_on_failure`, items not yet started when one fails are skipped.
+pub fn verify_all<T: Sync>(
+    items: &[T],
+    abort_on_failure: bool,
+    verify: impl Fn(&T) -> Result<(), String> + Sync,
+) -> Vec<VerifyOutcome> {
+    let failed = AtomicBool::new(false);
+    items
+        .par_iter()
+        .map(|item| {
+            if abort_on_failure && failed.load(Ordering::Relaxed) {
+                return VerifyOutcome::Skipped;
+            }
+            match verify(item) {
+                Ok(()) => VerifyOutcome::Valid,
+                Err(err) => {
+                    failed.store(true, Ordering::Relaxed);
+                    VerifyOutcome::Invalid(err)
+                }
+            }
+        })
+        .collect()
+}
+
+/// Bytes of a proof of a circuit with the constraint system `cs`, from its shape alone.
+///
+/// A proof is a fixed sequence of 32-byte points and scalars whatever the witness: the
+/// commitments of the advice columns, lookups, permutation products and quotient pieces,
+/// the evaluations at `x` of every query, and one GWC opening per distinct point queried.
+/// Both transcripts compress points, so the size is that of either. `cs` has to be the
+/// system keygen produced, e.g. that of a verifying key, as selectors become fixed columns.
+pub fn estimate_proof_size(cs: &ConstraintSystem<Fr>) -> usize {
+    const ELEMENT_BYTES: usize = 32;
+    let permutation_columns = cs.permutation().get_columns().len();
+    // each product spans as many columns as the degree leaves room for
+    let products = permutation_columns.div_ceil(cs.degree() - 2);
+    let lookups = cs.lookups().len();
+
+    let mut rotations = cs
+        .advice_queries()
+        .iter()
+        .map(|(_, rotation)| rotation.0)
+        .chain(cs.fixed_queries().iter().map(|(_, rotation)| rotation.0))
+        .chain([Rotation::cur().0])
+        .collect::<BTreeSet<_>>();
+    if products > 0 || lookups > 0 {
+        rotations.insert(Rotation::next().0);
+    }
+    if products > 1 {
+        // products chain at the last usable row
+        rotations.insert(-(cs.blinding_factors() as i32 + 1));
+    }
+    if lookups > 0 {
+        rotations.insert(Rotation::prev().0);
+    }
+
+    let commitments = cs.num_advice_columns()
+        + 3 * lookups
+        + products
+        // the random polynomial and the pieces of the quotient
+        + 1
+        + (cs.degree() - 1);
+    let evaluations = cs.advice_queries().len()
+        + cs.fixed_queries().len()
+        + 1
+        + permutation_columns
+        + (3 * products).saturating_sub(1)
+        + 5 * lookups;
+    (commitments + evaluations + rotations.len()) * ELEMENT_BYTES
+}
+
+/// Reads params written by `ParamsKZG::write`, e.g. a downloaded SRS.
+pub fn read_params(path: impl AsRef<Path>) -> io::Result<ParamsKZG<Bn256>> {
+    ParamsKZG::read(&mut BufReader::new(File::open(path)?))
+}
+
+/// Hex Blake2b-256 of the raw serialization of `vk`, naming it across releases.
+pub fn vk_hash(vk: &VerifyingKey<G1Affine>) -> String {
+    blake2b_simd::Params::new()
+        .hash_length(32)
+        .hash(&vk.to_bytes(SerdeFormat::RawBytes))
+        .to_hex()
+        .to_string()
+}
+
+/// Deserializes a verifying key of `ConcreteCircuit` written with `SerdeFormat::RawBytes`.
+pub fn read_vk<ConcreteCircuit: Circuit<Fr>>(
+    mut bytes: &[u8],
+) -> io::Result<VerifyingKey<G1Affine>> {
+    VerifyingKey::read::<_, ConcreteCircuit>(&mut bytes, SerdeFormat::RawBytes)
+}
+
+/// Deserializes a proving key of `ConcreteCircuit` written with `SerdeFormat::RawBytes`.
+pub fn read_pk<ConcreteCircuit: Circuit<Fr>>(mut bytes: &[u8]) -> io::Result<ProvingKey<G1Affine>> {
+    ProvingKey::read::<_, ConcreteCircuit>(&mut bytes, SerdeFormat::RawBytes)
+}
+
+/// Maps a deployment name, e.g. `"staging"`, to the salt of [`ProverContext::with_salt`].
+pub fn deployment_salt(name: &str) -> Fr {
+    let digest = blake2b_simd::Params::new()
+        .hash_length(64)
+        .personal(b"poseidon-salt\0\0\0")
+        .hash(name.as_bytes());
+    let mut bytes = [0u8; 64];
+    bytes.copy_from_slice(digest.as_bytes());
+    Fr::from_uniform_bytes(&bytes)
+}
+
+/// Encodes a proof as EVM verifier calldata: every instance as a 32-byte big-endian word,
+/// column after column, followed by the proof bytes.
+pub fn encode_calldata(instances: &[&[Fr]], proof: &[u8]) -> Vec<u8> {
+    instances
+        .iter()
+        .flat_map(|column| column.iter())
+        .flat_map(|value| {
+            let mut word = value.to_repr();
+            word.as_mut().reverse();
+            word
+        })
+        .chain(proof.iter().copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_circuit::TestCircuit;
+
+    #[test]
+    fn test_reuse_context() {
+        const K: u32 = 10;
+        let circuit = TestCircuit::new((0..5).map(|i| Fr::from(i as u64)).collect());
+        let ctx = ProverContext::setup(K, &circuit).expect("keygen should not fail");
+        let out_hash = Fr::from_str_vartime(
+            "20304616028358001435806807494046171997958789835068077254356069730773893150537",
+        )
+        .unwrap();
+        let public_inputs: &[&[Fr]] = &[&[out_hash]];
+
+        // the same context serves several proofs without another keygen
+        for _ in 0..2 {
+            let proof = ctx.prove(&circuit, public_inputs).unwrap();
+            assert!(ctx.verify(&proof, public_inputs).is_ok());
+            assert_eq!(proof.len(), ctx.proof_size());
+        }
+        assert!(ctx.verify(&[], public_inputs).is_err());
+    }
+
+    #[test]
+    fn test_instance_counts() {
+        let circuit = TestCircuit::new((0..5).map(Fr::from).collect());
+        let ctx = ProverContext::setup(10, &circuit).unwrap();
+        let spec = poseidon::Spec::<Fr, 4, 3>::new(8, 56);
+        let digest = crate::poseidon_hash::hash(&spec, &(0..5).map(Fr::from).collect::<Vec<_>>());
+        let proof = ctx.prove(&circuit, &[&[digest]]).unwrap();
+        assert!(ctx.verify(&proof, &[&[digest]]).is_ok());
+
+        // the circuit has a single instance column
+        assert!(matches!(
+            ctx.prove(&circuit, &[]),
+            Err(Error::InvalidInstances)
+        ));
+        assert!(matches!(
+            ctx.verify(&proof, &[&[digest], &[digest]]),
+            Err(Error::InvalidInstances)
+        ));
+        // of which the proof commits to every row, not just the constrained one
+        assert!(ctx.verify(&proof, &[&[digest, digest]]).is_err());
+        assert!(ctx.verify(&proof, &[&[]]).is_err());
+    }
+
+    #[test]
+    fn test_keccak_transcript() {
+        let circuit = TestCircuit::new(vec![Fr::from(1)]);
+        let ctx = ProverContext::setup(10, &circuit).unwrap();
+        let out_hash =
+            crate::poseidon_hash::hash(&poseidon::Spec::<Fr, 4, 3>::new(8, 56), &[Fr::from(1)]);
+        let public_inputs: &[&[Fr]] = &[&[out_hash]];
+
+        let proof = ctx.prove_keccak(&circuit, public_inputs).unwrap();
+        assert!(ctx.verify_keccak(&proof, public_inputs).is_ok());
+        // the transcripts are not interchangeable
+        assert!(ctx.verify(&proof, public_inputs).is_err());
+
+        let calldata = encode_calldata(public_inputs, &proof);
+        assert_eq!(calldata.len(), 32 + proof.len());
+        assert_eq!(proof.len(), ctx.proof_size());
+        assert_eq!(calldata[31], out_hash.to_repr()[0]);
+        assert_eq!(&calldata[32..], &proof[..]);
+    }
+
+    #[test]
+    fn test_transcript_hashes() {
+        let circuit = TestCircuit::new(vec![Fr::from(3)]);
+        let ctx = ProverContext::setup(10, &circuit)
+            .unwrap()
+            .with_protocol_label("test/v1");
+        let out_hash =
+            crate::poseidon_hash::hash(&poseidon::Spec::<Fr, 4, 3>::new(8, 56), &[Fr::from(3)]);
+        let public_inputs: &[&[Fr]] = &[&[out_hash]];
+
+        for hash in TranscriptHash::ALL {
+            assert_eq!(hash.as_str().parse(), Ok(hash));
+            let proof = ctx.prove_in(hash, &circuit, public_inputs).unwrap();
+            assert_eq!(proof.len(), ctx.proof_size(), "{hash:?}");
+            for other in TranscriptHash::ALL {
+                let verified = ctx.verify_in(other, &proof, public_inputs);
+                assert_eq!(verified.is_ok(), hash == other, "{hash:?} in {other:?}");
+            }
+            assert!(ctx
+                .verify_in(hash, &proof, &[&[out_hash + Fr::from(1)]])
+                .is_err());
+        }
+        assert!("sha256".parse::<TranscriptHash>().is_err());
+        assert_eq!(
+            serde_json::to_string(&TranscriptHash::Keccak256).unwrap(),
+            r#""keccak256""#
+        );
+    }
+
+    #[test]
+    fn test_historical_vk() {
+        let params = ParamsKZG::<Bn256>::setup(10, OsRng);
+        let old_circuit = TestCircuit::new(vec![Fr::from(1)]);
+        let old = ProverContext::new(params.clone(), &old_circuit).unwrap();
+        let current = ProverContext::new(params, &TestCircuit::new(vec![Fr::from(0); 5])).unwrap();
+        assert_ne!(old.vk_hash(), current.vk_hash());
+
+        let out_hash =
+            crate::poseidon_hash::hash(&poseidon::Spec::<Fr, 4, 3>::new(8, 56), &[Fr::from(1)]);
+        let public_inputs: &[&[Fr]] = &[&[out_hash]];
+        let proof = old.prove(&old_circuit, public_inputs).unwrap();
+        assert!(current.verify(&proof, public_inputs).is_err());
+
+        let bytes = old.pk().get_vk().to_bytes(SerdeFormat::RawBytes);
+        let vk = read_vk::<TestCircuit<Fr>>(&bytes).unwrap();
+        assert_eq!(vk_hash(&vk), old.vk_hash());
+        assert!(current.verify_with_vk(&vk, &proof, public_inputs).is_ok());
+    }
+
+    #[test]
+    fn test_read_params() {
+        let params = ParamsKZG::<Bn256>::setup(4, OsRng);
+        let path = std::env::temp_dir().join(format!("params-{}.bin", std::process::id()));
+        params.write(&mut File::create(&path).unwrap()).unwrap();
+        let read = read_params(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (mut expected, mut actual) = (vec![], vec![]);
+        params.write(&mut expected).unwrap();
+        read.write(&mut actual).unwrap();
+        assert_eq!(actual, expected);
+        assert!(read_params(&path).is_err());
+    }
+
+    #[test]
+    fn test_verify_batch() {
+        let circuit = TestCircuit::new(vec![Fr::from(1)]);
+        let ctx = ProverContext::setup(10, &circuit).unwrap();
+        let out_hash =
+            crate::poseidon_hash::hash(&poseidon::Spec::<Fr, 4, 3>::new(8, 56), &[Fr::from(1)]);
+        let proof = ctx.prove(&circuit, &[&[out_hash]]).unwrap();
+        let proofs = vec![
+            (proof.clone(), vec![vec![out_hash]]),
+            (proof, vec![vec![out_hash + Fr::from(1)]]),
+        ];
+        let outcomes = ctx.verify_batch(&proofs, false);
+        assert_eq!(outcomes[0], VerifyOutcome::Valid);
+        assert!(matches!(outcomes[1], VerifyOutcome::Invalid(_)));
+
+        let outcomes = verify_all(&[1, 2, 3], true, |i| match i {
+            1 => Err("bad".to_string()),
+            _ => Ok(()),
+        });
+        assert_eq!(outcomes[0], VerifyOutcome::Invalid("bad".to_string()));
+        assert_eq!(outcomes.len(), 3);
+        assert!(verify_all(&[1, 2], true, |_| Ok(()))
+            .iter()
+            .all(|o| *o == VerifyOutcome::Valid));
+    }
+
+    #[test]
+    fn test_protocol_label() {
+        let circuit = TestCircuit::new(vec![Fr::from(1)]);
+        let out_hash =
+            crate::poseidon_hash::hash(&poseidon::Spec::<Fr, 4, 3>::new(8, 56), &[Fr::from(1)]);
+        let public_inputs: &[&[Fr]] = &[&[out_hash]];
+        let params = ParamsKZG::<Bn256>::setup(10, OsRng);
+        let context = |label: &str| {
+            ProverContext::new(params.clone(), &circuit)
+                .unwrap()
+                .with_protocol_label(label)
+        };
+        let app = context("app-a");
+        let proof = app.prove(&circuit, public_inputs).unwrap();
+        assert!(app.verify(&proof, public_inputs).is_ok());
+        assert!(context("app-b").verify(&proof, public_inputs).is_err());
+        assert_eq!(app.protocol_label(), Some("app-a"));
+
+        // a light client holding only the verifying key reaches the same verdicts
+        let vk = app.pk().get_vk();
+        assert!(verify_detached(&params, vk, Some("app-a"), &proof, public_inputs).is_ok());
+        assert!(verify_detached(&params, vk, None, &proof, public_inputs).is_err());
+    }
+
+    #[test]
+    fn test_salt_binding() {
+        let circuit = TestCircuit::new(vec![Fr::from(1)]);
+        let out_hash =
+            crate::poseidon_hash::hash(&poseidon::Spec::<Fr, 4, 3>::new(8, 56), &[Fr::from(1)]);
+        let public_inputs: &[&[Fr]] = &[&[out_hash]];
+        let params = ParamsKZG::<Bn256>::setup(10, OsRng);
+        let context = |salt: Option<&str>| {
+            let ctx = ProverContext::new(params.clone(), &circuit).unwrap();
+            match salt {
+                Some(name) => ctx.with_salt(deployment_salt(name)),
+                None => ctx,
+            }
+        };
+        let staging = context(Some("staging"));
+        let production = context(Some("production"));
+        let unsalted = context(None);
+
+        let proof = staging.prove(&circuit, public_inputs).unwrap();
+        assert!(staging.verify(&proof, public_inputs).is_ok());
+        assert!(production.verify(&proof, public_inputs).is_err());
+        assert!(unsalted.verify(&proof, public_inputs).is_err());
+        let proof = unsalted.prove(&circuit, public_inputs).unwrap();
+        assert!(staging.verify(&proof, public_inputs).is_err());
+        assert_eq!(deployment_salt("staging"), deployment_salt("staging"));
+    }
+}
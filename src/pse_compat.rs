@@ -0,0 +1,141 @@
+//! The native API of the PSE `poseidon` crate over the sponge of this crate, for projects
+//! coded against `poseidon::Poseidon` that want the vetted specs and domains of this one.
+//!
+//! [`Poseidon`] absorbs and squeezes exactly like its namesake: `update` permutes every full
+//! chunk, `squeeze` pads what is left with a one, permutes and returns the first rate
+//! element, and both may be called again afterwards. Swapping the import is all it takes:
+//!
+//! ```
+//! use halo2curves::bn256::Fr;
+//! use poseidon_circuit::{poseidon_hash::hash, pse_compat::Poseidon};
+//!
+//! let message = [Fr::from(1), Fr::from(2)];
+//! let mut hasher = Poseidon::<Fr, 4, 3>::new(8, 56);
+//! hasher.update(&message);
+//! assert_eq!(hasher.squeeze(), hash(hasher.spec(), &message));
+//! ```
+use ff::{FromUniformBytes, PrimeField};
+use poseidon::Spec;
+
+use crate::{
+    poseidon_hash::State,
+    specs::{checked_spec, vetted_spec, Domain, SpecError},
+};
+
+/// `poseidon::Poseidon`, over the native permutation of [`crate::poseidon_hash`].
+#[derive(Clone, Debug)]
+pub struct Poseidon<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> {
+    spec: Spec<F, T, RATE>,
+    state: State<F, T, RATE>,
+    absorbing: Vec<F>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> Poseidon<F, T, RATE> {
+    /// A hasher under any round numbers, like `poseidon::Poseidon::new`.
+    pub fn new(r_f: usize, r_p: usize) -> Self {
+        Self::from_spec(Spec::new(r_f, r_p), Domain::Pse)
+    }
+
+    /// A hasher under a vetted spec, see [`checked_spec`].
+    pub fn checked(r_f: usize, r_p: usize) -> Result<Self, SpecError> {
+        checked_spec(r_f, r_p).map(|spec| Self::from_spec(spec, Domain::Pse))
+    }
+
+    /// A hasher under the vetted spec of width `T`, see [`vetted_spec`].
+    pub fn vetted() -> Result<Self, SpecError> {
+        vetted_spec().map(|spec| Self::from_spec(spec, Domain::Pse))
+    }
+
+    /// A hasher starting from the capacity of `domain`; `poseidon::Poseidon` always starts
+    /// from that of [`Domain::Pse`].
+    pub fn from_spec(spec: Spec<F, T, RATE>, domain: Domain) -> Self {
+        let mut initial = [F::ZERO; T];
+        initial[0] = domain.capacity();
+        Self {
+            spec,
+            state: State::new(initial),
+            absorbing: Vec::new(),
+        }
+    }
+
+    pub fn spec(&self) -> &Spec<F, T, RATE> {
+        &self.spec
+    }
+
+    /// Absorbs `elements`, permuting every chunk that fills the rate.
+    pub fn update(&mut self, elements: &[F]) {
+        self.absorbing.extend_from_slice(elements);
+        let whole = self.absorbing.len() / RATE * RATE;
+        for chunk in self
+            .absorbing
+            .drain(..whole)
+            .collect::<Vec<_>>()
+            .chunks(RATE)
+        {
+            self.state.permute(&self.spec, chunk);
+        }
+    }
+
+    /// Pads the elements absorbed since the last full chunk, permutes and returns the first
+    /// rate element; the state carries on into further updates and squeezes.
+    pub fn squeeze(&mut self) -> F {
+        let last = std::mem::take(&mut self.absorbing);
+        self.state.permute(&self.spec, &last);
+        self.state.words()[1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+
+    #[test]
+    fn test_matches_pse() {
+        // updates across chunk boundaries, squeezes in between and twice in a row
+        for calls in [
+            vec![0],
+            vec![3],
+            vec![2, 2, 0],
+            vec![5, 0, 0],
+            vec![1, 6, 0, 3, 0],
+        ] {
+            let mut ours = Poseidon::<Fr, 4, 3>::new(8, 56);
+            let mut theirs = poseidon::Poseidon::<Fr, 4, 3>::new(8, 56);
+            for (i, len) in calls.iter().enumerate() {
+                if *len == 0 {
+                    assert_eq!(ours.squeeze(), theirs.squeeze(), "{calls:?}");
+                } else {
+                    let elements = (0..*len as u64)
+                        .map(|j| Fr::from(100 * i as u64 + j))
+                        .collect::<Vec<_>>();
+                    ours.update(&elements);
+                    theirs.update(&elements);
+                }
+            }
+            assert_eq!(ours.squeeze(), theirs.squeeze(), "{calls:?}");
+        }
+    }
+
+    #[test]
+    fn test_constructors() {
+        let inputs = [Fr::from(7)];
+        let digest = |mut hasher: Poseidon<Fr, 4, 3>| {
+            hasher.update(&inputs);
+            hasher.squeeze()
+        };
+        let vetted = digest(Poseidon::vetted().unwrap());
+        assert_eq!(vetted, digest(Poseidon::checked(8, 56).unwrap()));
+        assert_eq!(vetted, digest(Poseidon::new(8, 56)));
+        assert!(Poseidon::<Fr, 4, 3>::checked(8, 55).is_err());
+
+        let spec = Spec::new(8, 56);
+        let tagged = digest(Poseidon::from_spec(spec.clone(), Domain::User(1)));
+        assert_eq!(
+            tagged,
+            crate::poseidon_hash::hash_with_domain(&spec, Domain::User(1), &inputs)
+        );
+        assert_ne!(tagged, vetted);
+    }
+}
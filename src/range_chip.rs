@@ -0,0 +1,88 @@
+use ff::PrimeField;
+use halo2_proofs::{
+    circuit::{Chip, Value},
+    plonk::Error,
+};
+
+use crate::main_gate::{AssignedValue, MainGate, MainGateConfig, RegionCtx};
+
+/// Range checks on top of [`MainGate`], by decomposition into bits.
+///
+/// Every bit takes two rows: `b * b - b = 0` keeps it boolean, and
+/// `acc + 2^i * b - acc' = 0` adds it to the running sum, which must end equal to the
+/// checked value. No columns or gates are added to the main gate.
+pub struct RangeChip<F: PrimeField, const T: usize> {
+    main_gate: MainGate<F, T>,
+}
+
+impl<F: PrimeField, const T: usize> RangeChip<F, T> {
+    pub fn new(config: MainGateConfig<T>) -> Self {
+        Self {
+            main_gate: MainGate::new(config),
+        }
+    }
+
+    /// Number of rows [`RangeChip::range_check`] uses for `bits` bits.
+    pub fn num_rows(bits: usize) -> usize {
+        2 * bits
+    }
+
+    /// Constrains `value` to `[0, 2^bits)` and returns its bits, least significant first.
+    pub fn range_check(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        value: &AssignedValue<F>,
+        bits: usize,
+    ) -> Result<Vec<AssignedValue<F>>, Error> {
+        // 2^bits must stay below the modulus or the sum could wrap around
+        assert!(bits > 0 && bits < F::NUM_BITS as usize);
+        let config = self.main_gate.config();
+
+        let mut acc: Option<AssignedValue<F>> = None;
+        let mut out = Vec::with_capacity(bits);
+        let mut coeff = F::ONE;
+        for i in 0..bits {
+            let bit_val = value.value().map(|v| {
+                let repr = v.to_repr();
+                F::from(((repr.as_ref()[i / 8] >> (i % 8)) & 1) as u64)
+            });
+
+            // b * b - b = 0
+            let b = ctx.assign_advice(|| "range: bit", config.state[0], bit_val)?;
+            let b_copy = ctx.assign_advice(|| "range: bit", config.state[1], bit_val)?;
+            ctx.constrain_equal(b.cell(), b_copy.cell())?;
+            ctx.assign_fixed(|| "range: q_m", config.q_m, F::ONE)?;
+            ctx.assign_fixed(|| "range: q_1", config.q_1[0], -F::ONE)?;
+            ctx.next();
+
+            // acc + 2^i * b - acc' = 0
+            let acc_val = match &acc {
+                Some(acc) => {
+                    let a =
+                        ctx.assign_advice(|| "range: acc", config.state[0], acc.value().copied())?;
+                    ctx.constrain_equal(a.cell(), acc.cell())?;
+                    ctx.assign_fixed(|| "range: q_1", config.q_1[0], F::ONE)?;
+                    acc.value().copied()
+                }
+                None => Value::known(F::ZERO),
+            };
+            let bi = ctx.assign_advice(|| "range: bit", config.state[1], bit_val)?;
+            ctx.constrain_equal(bi.cell(), b.cell())?;
+            ctx.assign_fixed(|| "range: 2^i", config.q_1[1], coeff)?;
+            ctx.assign_fixed(|| "range: q_o", config.q_o, -F::ONE)?;
+            let next = ctx.assign_advice(
+                || "range: acc",
+                config.out,
+                acc_val + bit_val * Value::known(coeff),
+            )?;
+            ctx.next();
+
+            out.push(b);
+            acc = Some(next);
+            coeff = coeff.double();
+        }
+        let acc = acc.expect("at least one bit");
+        ctx.constrain_equal(acc.cell(), value.cell())?;
+        Ok(out)
+    }
+}
@@ -0,0 +1,136 @@
+//! Proof that a committed value lies in `[0, 2^n)`.
+//!
+//! The commitment is `Poseidon(value, blinding)` and is the only public input; the value
+//! and the blinding factor stay private. `n` is part of the circuit shape, so each bit
+//! size needs its own keys.
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner},
+    plonk::{Circuit, Column, ConstraintSystem, Error, Instance},
+};
+use poseidon::Spec;
+
+use crate::{
+    main_gate::{MainGate, MainGateConfig, RegionCtx},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash,
+    range_chip::RangeChip,
+    specs::BN256_T4_R3,
+};
+
+const T: usize = BN256_T4_R3.width;
+const RATE: usize = BN256_T4_R3.rate;
+const R_F: usize = BN256_T4_R3.r_f;
+const R_P: usize = BN256_T4_R3.r_p;
+
+#[derive(Clone, Debug)]
+pub struct RangeProofConfig {
+    pconfig: MainGateConfig<T>,
+    instance: Column<Instance>,
+}
+
+pub struct RangeProofCircuit<F: PrimeField> {
+    value: F,
+    blinding: F,
+    bits: usize,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> RangeProofCircuit<F> {
+    pub fn new(value: F, blinding: F, bits: usize) -> Self {
+        Self {
+            value,
+            blinding,
+            bits,
+        }
+    }
+
+    /// The public commitment to `value` under `blinding`.
+    pub fn commitment(value: F, blinding: F) -> F {
+        hash(&Spec::<F, T, RATE>::new(R_F, R_P), &[value, blinding])
+    }
+
+    pub fn instance(&self) -> Vec<Vec<F>> {
+        vec![vec![Self::commitment(self.value, self.blinding)]]
+    }
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for RangeProofCircuit<F> {
+    type Config = RangeProofConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self::new(F::ZERO, F::ZERO, self.bits)
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        let instance = meta.instance_column();
+        meta.enable_equality(instance);
+        let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+        let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+        let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+        Self::Config { pconfig, instance }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        let spec = Spec::<F, T, RATE>::new(R_F, R_P);
+        let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec);
+        let range_chip = RangeChip::<F, T>::new(config.pconfig);
+        pchip.update(vec![self.value, self.blinding]);
+        let commitment = layouter.assign_region(
+            || "range proof",
+            |region| {
+                let ctx = &mut RegionCtx::new(region, 0);
+                let (inputs, commitment) = pchip.squeeze_with_inputs(ctx)?;
+                range_chip.range_check(ctx, &inputs[0], self.bits)?;
+                Ok(commitment)
+            },
+        )?;
+        layouter.constrain_instance(commitment.cell(), config.instance, 0)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+
+    const K: u32 = 10;
+    const BITS: usize = 64;
+
+    fn run(value: Fr, bits: usize) -> bool {
+        let blinding = Fr::from(0x5eed_u64);
+        let circuit = RangeProofCircuit::new(value, blinding, bits);
+        let prover = MockProver::run(K, &circuit, circuit.instance()).unwrap();
+        prover.verify().is_ok()
+    }
+
+    #[test]
+    fn test_in_range() {
+        assert!(run(Fr::ZERO, BITS));
+        assert!(run(Fr::from(u64::MAX), BITS));
+        assert!(run(Fr::from(255), 8));
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert!(!run(Fr::from(256), 8));
+        assert!(!run(Fr::from(u64::MAX) + Fr::ONE, BITS));
+        assert!(!run(-Fr::ONE, BITS));
+    }
+
+    #[test]
+    fn test_wrong_commitment() {
+        let circuit = RangeProofCircuit::new(Fr::from(7), Fr::from(1), BITS);
+        let other = RangeProofCircuit::<Fr>::commitment(Fr::from(7), Fr::from(2));
+        let prover = MockProver::run(K, &circuit, vec![vec![other]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Re-execution of recorded tasks, to validate a new prover binary before rolling it out.
+//!
+//! A log holds one [`TaskRecord`] per line: the task as received and the [`ProofDetail`]
+//! the previous binary answered with. [`replay`] runs every task again and reports how the
+//! outcome of each one changed.
+use std::{fmt, io::BufRead, time::Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    schema::{self, SchemaError},
+    task::{ProofDetail, Task},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskRecord {
+    pub task: Task,
+    pub detail: ProofDetail,
+    /// Wall time the recorded binary spent on the task
+    pub elapsed_ms: u64,
+}
+
+/// How the outcome of one recorded task changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayDiff {
+    pub id: String,
+    /// [`Task::task_digest`], the same for recorded tasks that differ only in id or key order
+    pub digest: String,
+    /// Whether the recorded and the current run produced a proof
+    pub recorded_ok: bool,
+    pub current_ok: bool,
+    pub instances_match: bool,
+    pub recorded_ms: u64,
+    pub current_ms: u64,
+}
+
+impl ReplayDiff {
+    /// A task that used to be proven and no longer is, or whose public inputs changed.
+    pub fn is_regression(&self) -> bool {
+        (self.recorded_ok && !self.current_ok) || !self.instances_match
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub diffs: Vec<ReplayDiff>,
+}
+
+impl ReplayReport {
+    pub fn regressions(&self) -> impl Iterator<Item = &ReplayDiff> {
+        self.diffs.iter().filter(|diff| diff.is_regression())
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diff in &self.diffs {
+            let status = |ok| if ok { "ok" } else { "failed" };
+            writeln!(
+                f,
+                "{}{}: {} -> {}, instances {}, {} ms -> {} ms",
+                if diff.is_regression() {
+                    "REGRESSION "
+                } else {
+                    ""
+                },
+                diff.id,
+                status(diff.recorded_ok),
+                status(diff.current_ok),
+                if diff.instances_match {
+                    "match"
+                } else {
+                    "differ"
+                },
+                diff.recorded_ms,
+                diff.current_ms,
+            )?;
+        }
+        let recorded: u64 = self.diffs.iter().map(|d| d.recorded_ms).sum();
+        let current: u64 = self.diffs.iter().map(|d| d.current_ms).sum();
+        write!(
+            f,
+            "{} tasks, {} regressions, {recorded} ms -> {current} ms in total",
+            self.diffs.len(),
+            self.regressions().count(),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    /// Line `line` (1-based) of the log is not a [`TaskRecord`]
+    Record {
+        line: usize,
+        err: serde_json::Error,
+    },
+    /// Line `line` holds a task or proof detail of an unsupported schema version
+    Schema {
+        line: usize,
+        err: SchemaError,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read the task log: {err}"),
+            Self::Record { line, err } => write!(f, "line {line} of the task log: {err}"),
+            Self::Schema { line, err } => write!(f, "line {line} of the task log: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+enum RecordError {
+    Json(serde_json::Error),
+    Schema(SchemaError),
+}
+
+/// Parses a record written by any earlier version of the service.
+fn parse_record(line: &str) -> Result<TaskRecord, RecordError> {
+    let mut value = serde_json::from_str::<serde_json::Value>(line).map_err(RecordError::Json)?;
+    if let Some(record) = value.as_object_mut() {
+        for (field, upgrade) in [
+            ("task", schema::upgrade_task as fn(_) -> _),
+            ("detail", schema::upgrade_proof_detail),
+        ] {
+            if let Some(part) = record.remove(field) {
+                record.insert(
+                    field.to_string(),
+                    upgrade(part).map_err(RecordError::Schema)?,
+                );
+            }
+        }
+    }
+    serde_json::from_value(value).map_err(RecordError::Json)
+}
+
+fn succeeded(detail: &ProofDetail) -> bool {
+    detail.error.is_empty() && !detail.proof_data.is_empty()
+}
+
+/// Runs `execute` on every task of `log` and compares its answers with the recorded ones.
+pub fn replay(
+    log: impl BufRead,
+    mut execute: impl FnMut(&Task) -> ProofDetail,
+) -> Result<ReplayReport, ReplayError> {
+    let mut report = ReplayReport::default();
+    for (idx, line) in log.lines().enumerate() {
+        let line = line.map_err(ReplayError::Io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = parse_record(&line).map_err(|err| match err {
+            RecordError::Json(err) => ReplayError::Record { line: idx + 1, err },
+            RecordError::Schema(err) => ReplayError::Schema { line: idx + 1, err },
+        })?;
+
+        let start = Instant::now();
+        let detail = execute(&record.task);
+        let elapsed = start.elapsed();
+
+        report.diffs.push(ReplayDiff {
+            id: record.task.id.clone(),
+            digest: record.task.task_digest(),
+            recorded_ok: succeeded(&record.detail),
+            current_ok: succeeded(&detail),
+            instances_match: detail.instances == record.detail.instances,
+            recorded_ms: record.elapsed_ms,
+            current_ms: elapsed.as_millis() as u64,
+        });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, error: &str, instances: &[&str]) -> String {
+        serde_json::to_string(&TaskRecord {
+            task: Task {
+                id: id.to_string(),
+                ..Default::default()
+            },
+            detail: ProofDetail {
+                id: id.to_string(),
+                proof_data: "cHJvb2Y=".to_string(),
+                error: error.to_string(),
+                instances: instances.iter().map(|i| i.to_string()).collect(),
+                ..Default::default()
+            },
+            elapsed_ms: 10,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_replay() {
+        let log = [
+            record("a", "", &["0x01"]),
+            String::new(),
+            record("b", "", &["0x02"]),
+            record("c", "synthesis failed", &[]),
+        ]
+        .join("\n");
+        let report = replay(log.as_bytes(), |task| ProofDetail {
+            id: task.id.clone(),
+            proof_data: "cHJvb2Y=".to_string(),
+            error: if task.id == "b" {
+                "verification failed".to_string()
+            } else {
+                String::new()
+            },
+            instances: match task.id.as_str() {
+                "a" => vec!["0x01".to_string()],
+                _ => vec![],
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(report.diffs.len(), 3);
+        let regressions = report
+            .regressions()
+            .map(|d| d.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(regressions, ["b"]);
+        // c was failing and is now proven
+        assert!(!report.diffs[2].recorded_ok && report.diffs[2].current_ok);
+        // the tasks only differ in their ids
+        assert_eq!(report.diffs[0].digest, report.diffs[1].digest);
+    }
+
+    #[test]
+    fn test_malformed_log() {
+        let log = format!("{}\nnot json\n", record("a", "", &[]));
+        assert!(matches!(
+            replay(log.as_bytes(), |_| ProofDetail::default()),
+            Err(ReplayError::Record { line: 2, .. })
+        ));
+
+        let newer =
+            record("a", "", &[]).replacen(r#""id":"a""#, r#""id":"a","schema_version":99"#, 1);
+        assert!(matches!(
+            replay(newer.as_bytes(), |_| ProofDetail::default()),
+            Err(ReplayError::Schema { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+//! Constraint that two input sets hash to the same Poseidon digest.
+//!
+//! Proves that two encodings of the same data commit identically, e.g. a message packed by
+//! [`crate::packing::pack_bytes`] and the same message decoded from limbs elsewhere in the
+//! circuit. Both hashes run one after the other in the caller's region on the same main gate
+//! columns, and share one copy of the round constants.
+use std::sync::Arc;
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::plonk::Error;
+use poseidon::Spec;
+
+use crate::{
+    main_gate::{AssignedValue, MainGateConfig, RegionCtx},
+    optimized_constants::OptimizedConstants,
+    poseidon_circuit::PoseidonChip,
+};
+
+pub struct SameDigestChip<F: PrimeField, const T: usize, const RATE: usize> {
+    config: MainGateConfig<T>,
+    constants: Arc<OptimizedConstants<F, T, RATE>>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    SameDigestChip<F, T, RATE>
+{
+    pub fn new(config: MainGateConfig<T>, spec: &Spec<F, T, RATE>) -> Self {
+        Self {
+            config,
+            constants: Arc::new(OptimizedConstants::from_spec(spec)),
+        }
+    }
+
+    /// Rows used by [`SameDigestChip::assert_same_digest`] for inputs of these lengths.
+    pub fn num_rows(spec: &Spec<F, T, RATE>, a_len: usize, b_len: usize) -> usize {
+        PoseidonChip::num_rows(spec, a_len) + PoseidonChip::num_rows(spec, b_len)
+    }
+
+    /// Hashes `a` and `b` and constrains the digests to be equal.
+    ///
+    /// Returns the cells `a` and `b` were absorbed from, for the caller to copy-constrain,
+    /// and the shared digest.
+    #[allow(clippy::type_complexity)]
+    pub fn assert_same_digest(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: Vec<F>,
+        b: Vec<F>,
+    ) -> Result<
+        (
+            Vec<AssignedValue<F>>,
+            Vec<AssignedValue<F>>,
+            AssignedValue<F>,
+        ),
+        Error,
+    > {
+        let mut hash = |inputs: Vec<F>| {
+            let mut pchip =
+                PoseidonChip::from_constants(self.config.clone(), self.constants.clone());
+            pchip.update(inputs);
+            pchip.squeeze_with_inputs(ctx)
+        };
+        let (a_cells, a_digest) = hash(a)?;
+        let (b_cells, b_digest) = hash(b)?;
+        ctx.constrain_equal(a_digest.cell(), b_digest.cell())?;
+        Ok((a_cells, b_cells, a_digest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, ConstraintSystem},
+    };
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{main_gate::MainGate, packing::pack_bytes};
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+
+    struct SameDigestCircuit {
+        a: Vec<Fr>,
+        b: Vec<Fr>,
+    }
+
+    impl Circuit<Fr> for SameDigestCircuit {
+        type Config = MainGateConfig<T>;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                a: vec![Fr::ZERO; self.a.len()],
+                b: vec![Fr::ZERO; self.b.len()],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            MainGate::configure(meta, &mut adv_cols, &mut fix_cols)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = SameDigestChip::new(config, &Spec::<Fr, T, RATE>::new(8, 56));
+            layouter.assign_region(
+                || "same digest",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    chip.assert_same_digest(ctx, self.a.clone(), self.b.clone())
+                        .map(|_| ())
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn test_same_digest() {
+        let spec = Spec::<Fr, T, RATE>::new(8, 56);
+        assert!(SameDigestChip::num_rows(&spec, 2, 2) + 6 <= 1 << 10);
+        // the same bytes, packed and given as length and limb
+        let packed = pack_bytes::<Fr>(b"abc");
+        let limbs = vec![Fr::from(3), Fr::from(0x616263)];
+        let circuit = SameDigestCircuit {
+            a: packed,
+            b: limbs,
+        };
+        let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let circuit = SameDigestCircuit {
+            a: pack_bytes::<Fr>(b"abc"),
+            b: pack_bytes::<Fr>(b"abd"),
+        };
+        let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! Packing of the messages of a batch into sponge instances.
+//!
+//! Every message costs `len / RATE + 1` permutations, the last one partly padding, and a
+//! circuit of `2^k` rows has room for a fixed number of permutation slots. Proving each
+//! message in its own circuit wastes most of the slots on short messages; [`schedule`]
+//! bin-packs messages into as few circuits as first-fit decreasing finds.
+use std::fmt;
+
+use ff::{FromUniformBytes, PrimeField};
+use poseidon::Spec;
+
+use crate::{limits::UNUSABLE_ROWS, poseidon_circuit::PoseidonChip};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// Message `index` needs more permutations than a single instance has
+    MessageTooLong {
+        index: usize,
+        permutations: usize,
+        slots: usize,
+    },
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MessageTooLong {
+                index,
+                permutations,
+                slots,
+            } => write!(
+                f,
+                "message {index} needs {permutations} permutations, an instance has {slots}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+/// Permutations absorbing a message of `len` elements, padding included.
+pub fn permutations<const RATE: usize>(len: usize) -> usize {
+    len / RATE + 1
+}
+
+/// Permutation slots in a circuit of `2^k` rows.
+pub fn slots_for_k<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    k: u32,
+) -> usize {
+    (1usize << k).saturating_sub(UNUSABLE_ROWS) / PoseidonChip::num_rows(spec, 0)
+}
+
+/// Assigns the messages of lengths `lens` to sponge instances of `slots` permutations each.
+///
+/// Returns the indices of the messages of every instance, ascending within an instance.
+pub fn schedule<const RATE: usize>(
+    lens: &[usize],
+    slots: usize,
+) -> Result<Vec<Vec<usize>>, ScheduleError> {
+    let mut order = (0..lens.len()).collect::<Vec<_>>();
+    // stable, so equal messages keep their batch order
+    order.sort_by_key(|&i| std::cmp::Reverse(permutations::<RATE>(lens[i])));
+
+    let mut bins: Vec<(usize, Vec<usize>)> = Vec::new();
+    for index in order {
+        let cost = permutations::<RATE>(lens[index]);
+        if cost > slots {
+            return Err(ScheduleError::MessageTooLong {
+                index,
+                permutations: cost,
+                slots,
+            });
+        }
+        match bins.iter_mut().find(|(used, _)| used + cost <= slots) {
+            Some((used, messages)) => {
+                *used += cost;
+                messages.push(index);
+            }
+            None => bins.push((cost, vec![index])),
+        }
+    }
+    Ok(bins
+        .into_iter()
+        .map(|(_, mut messages)| {
+            messages.sort_unstable();
+            messages
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+
+    #[test]
+    fn test_schedule_fits_rows() {
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        let k = 12;
+        let slots = slots_for_k(&spec, k);
+        // 4090 usable rows hold 15 permutations of 260 rows
+        assert_eq!(slots, 15);
+
+        let lens = [20, 1, 2, 14, 8, 0, 5, 26, 11];
+        let bins = schedule::<3>(&lens, slots).unwrap();
+        // 7 + 1 + 1 + 5 + 3 + 1 + 2 + 9 + 4 = 33 permutations
+        assert_eq!(bins.len(), 3);
+        let mut seen = bins.concat();
+        seen.sort_unstable();
+        assert_eq!(seen, (0..lens.len()).collect::<Vec<_>>());
+        for bin in &bins {
+            let rows: usize = bin
+                .iter()
+                .map(|&i| PoseidonChip::num_rows(&spec, lens[i]))
+                .sum();
+            assert!(rows + UNUSABLE_ROWS <= 1 << k);
+        }
+    }
+
+    #[test]
+    fn test_schedule_errors() {
+        assert_eq!(schedule::<3>(&[], 4), Ok(vec![]));
+        assert_eq!(
+            schedule::<3>(&[2, 12], 4),
+            Err(ScheduleError::MessageTooLong {
+                index: 1,
+                permutations: 5,
+                slots: 4
+            })
+        );
+        // one instance per message would use 3 instances
+        assert_eq!(schedule::<3>(&[5, 0, 2], 4), Ok(vec![vec![0, 1, 2]]));
+    }
+}
@@ -0,0 +1,527 @@
+//! Versions of the [`Task`] and [`ProofDetail`] JSON schemas, and upgrades of old payloads.
+//!
+//! Every change to either document bumps [`SCHEMA_VERSION`] and is listed in [`VERSIONS`].
+//! Additions are optional fields, so old payloads keep parsing; [`upgrade_task`] and
+//! [`upgrade_proof_detail`] rewrite whatever else changed before serde sees the payload.
+//! Producers may declare the version they write as `schema_version`: a task from a newer
+//! producer is rejected rather than parsed with its new fields silently dropped.
+//!
+//! Golden payloads of every version live in `fixtures/schema`, and the tests check that each
+//! still parses into the current types.
+//!
+//! [`Task`]: crate::task::Task
+//! [`ProofDetail`]: crate::task::ProofDetail
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use crate::stage::Stage;
+
+/// Version of the schemas this build reads and writes.
+pub const SCHEMA_VERSION: u32 = 19;
+
+/// A schema version and the fields it added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchemaVersion {
+    pub version: u32,
+    pub task_fields: &'static [&'static str],
+    pub proof_detail_fields: &'static [&'static str],
+    /// What else changed, for the changelog
+    pub note: &'static str,
+}
+
+/// Every version, oldest first.
+pub const VERSIONS: &[SchemaVersion] = &[
+    SchemaVersion {
+        version: 1,
+        task_fields: &["uuid", "id", "type", "task_data", "hard_fork_name"],
+        proof_detail_fields: &["id", "type", "proof_data", "error", "memory"],
+        note: "memory phases are free-form names",
+    },
+    SchemaVersion {
+        version: 2,
+        task_fields: &[],
+        proof_detail_fields: &["instances"],
+        note: "",
+    },
+    SchemaVersion {
+        version: 3,
+        task_fields: &["evm"],
+        proof_detail_fields: &["evm"],
+        note: "",
+    },
+    SchemaVersion {
+        version: 4,
+        task_fields: &["instance_layout"],
+        proof_detail_fields: &["instance_layout"],
+        note: "",
+    },
+    SchemaVersion {
+        version: 5,
+        task_fields: &[],
+        proof_detail_fields: &["protocol_label"],
+        note: "",
+    },
+    SchemaVersion {
+        version: 6,
+        task_fields: &["task_data_ref"],
+        proof_detail_fields: &[],
+        note: "task_data may be left out when task_data_ref is present",
+    },
+    SchemaVersion {
+        version: 7,
+        task_fields: &["callback_url"],
+        proof_detail_fields: &[],
+        note: "",
+    },
+    SchemaVersion {
+        version: 8,
+        task_fields: &[],
+        proof_detail_fields: &["failed_stage"],
+        note: "memory phases are stage names",
+    },
+    SchemaVersion {
+        version: 9,
+        task_fields: &[],
+        proof_detail_fields: &["vk_hash"],
+        note: "",
+    },
+    SchemaVersion {
+        version: 10,
+        task_fields: &["schema_version"],
+        proof_detail_fields: &[],
+        note: "",
+    },
+    SchemaVersion {
+        version: 11,
+        task_fields: &[],
+        proof_detail_fields: &["verification"],
+        note: "",
+    },
+    SchemaVersion {
+        version: 12,
+        task_fields: &["batch"],
+        proof_detail_fields: &[],
+        note: "",
+    },
+    SchemaVersion {
+        version: 13,
+        task_fields: &["preprocessor"],
+        proof_detail_fields: &[],
+        note: "",
+    },
+    SchemaVersion {
+        version: 14,
+        task_fields: &[],
+        proof_detail_fields: &["metadata"],
+        note: "",
+    },
+    SchemaVersion {
+        version: 15,
+        task_fields: &["transcript"],
+        proof_detail_fields: &["transcript"],
+        note: "",
+    },
+    SchemaVersion {
+        version: 16,
+        task_fields: &["dry_run"],
+        proof_detail_fields: &["constraint_failures"],
+        note: "",
+    },
+    SchemaVersion {
+        version: 17,
+        task_fields: &[],
+        proof_detail_fields: &["job"],
+        note: "",
+    },
+    SchemaVersion {
+        version: 18,
+        task_fields: &[],
+        proof_detail_fields: &["error_code"],
+        note: "",
+    },
+    SchemaVersion {
+        version: 19,
+        task_fields: &["compression"],
+        proof_detail_fields: &["compression", "proof_size", "proving_time_us", "circuit"],
+        note: "",
+    },
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaError {
+    NotAnObject,
+    InvalidVersion(Value),
+    /// Written by a producer newer than this build
+    Newer(u32),
+    /// A field that does not exist yet in the declared version
+    FieldTooNew {
+        field: String,
+        declared: u32,
+        since: u32,
+    },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAnObject => write!(f, "payload is not a JSON object"),
+            Self::InvalidVersion(version) => write!(f, "invalid schema_version {version}"),
+            Self::Newer(version) => write!(
+                f,
+                "schema version {version} is newer than the supported {SCHEMA_VERSION}"
+            ),
+            Self::FieldTooNew {
+                field,
+                declared,
+                since,
+            } => write!(
+                f,
+                "field `{field}` exists since schema version {since}, payload declares {declared}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// The version that added `field`, `None` for fields the schema never had.
+fn since(field: &str, fields: fn(&SchemaVersion) -> &'static [&'static str]) -> Option<u32> {
+    VERSIONS
+        .iter()
+        .find(|v| fields(v).contains(&field))
+        .map(|v| v.version)
+}
+
+/// The oldest version with every field of `object`; unknown fields are left to the parser.
+fn detect(
+    object: &Map<String, Value>,
+    fields: fn(&SchemaVersion) -> &'static [&'static str],
+) -> u32 {
+    object
+        .keys()
+        .filter_map(|key| since(key, fields))
+        .max()
+        .unwrap_or(1)
+}
+
+/// Rewrites a task payload of any known version into the current schema.
+pub fn upgrade_task(mut value: Value) -> Result<Value, SchemaError> {
+    let object = value.as_object_mut().ok_or(SchemaError::NotAnObject)?;
+    let fields = |v: &SchemaVersion| v.task_fields;
+    let declared = match object.get("schema_version") {
+        None => None,
+        Some(version) => Some(
+            version
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .filter(|v| *v >= 1)
+                .ok_or_else(|| SchemaError::InvalidVersion(version.clone()))?,
+        ),
+    };
+    if let Some(declared) = declared {
+        if declared > SCHEMA_VERSION {
+            return Err(SchemaError::Newer(declared));
+        }
+        // schema_version itself may be declared by a producer of any version
+        if let Some((field, since)) = object
+            .keys()
+            .filter(|key| *key != "schema_version")
+            .filter_map(|key| Some((key, since(key, fields)?)))
+            .find(|(_, since)| *since > declared)
+        {
+            return Err(SchemaError::FieldTooNew {
+                field: field.clone(),
+                declared,
+                since,
+            });
+        }
+    }
+    // no task field has changed meaning so far: stamping the version is the whole upgrade
+    object.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    Ok(value)
+}
+
+/// Rewrites a proof detail of any known version into the current schema.
+pub fn upgrade_proof_detail(mut value: Value) -> Result<Value, SchemaError> {
+    let object = value.as_object_mut().ok_or(SchemaError::NotAnObject)?;
+    if detect(object, |v| v.proof_detail_fields) < 8 {
+        if let Some(phases) = object
+            .get_mut("memory")
+            .and_then(|memory| memory.get_mut("phases"))
+            .and_then(Value::as_array_mut)
+        {
+            upgrade_phases(phases);
+        }
+    }
+    Ok(value)
+}
+
+/// Maps the free-form phase names of versions before 8 to stages, dropping the others.
+fn upgrade_phases(phases: &mut Vec<Value>) {
+    phases.retain_mut(|phase| {
+        let Some(name) = phase.get("phase").and_then(Value::as_str) else {
+            return false;
+        };
+        let stage = match name {
+            "keygen_vk" | "keygen_pk" => Stage::Keygen,
+            "proving" => Stage::Prove,
+            "verifying" => Stage::Verify,
+            name => match name.parse::<Stage>() {
+                Ok(stage) => stage,
+                Err(_) => return false,
+            },
+        };
+        phase["phase"] = stage.as_str().into();
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        compression::ProofCompression,
+        instance_layout::InstanceLayout,
+        mem_stats::{MemoryReport, PhaseMemory},
+        payload::PayloadRef,
+        prover::TranscriptHash,
+        task::{
+            BatchRef, CircuitInfo, ConstraintFailure, EvmProof, ParseMode, ProofDetail,
+            ProofMetadata, ProofType, Task, Verification,
+        },
+    };
+
+    const TASK_FIXTURES: &[(u32, &str)] = &[
+        (1, include_str!("../fixtures/schema/task_v1.json")),
+        (3, include_str!("../fixtures/schema/task_v3.json")),
+        (4, include_str!("../fixtures/schema/task_v4.json")),
+        (6, include_str!("../fixtures/schema/task_v6.json")),
+        (7, include_str!("../fixtures/schema/task_v7.json")),
+        (10, include_str!("../fixtures/schema/task_v10.json")),
+        (11, include_str!("../fixtures/schema/task_v11.json")),
+        (12, include_str!("../fixtures/schema/task_v12.json")),
+        (13, include_str!("../fixtures/schema/task_v13.json")),
+        (14, include_str!("../fixtures/schema/task_v14.json")),
+        (15, include_str!("../fixtures/schema/task_v15.json")),
+        (16, include_str!("../fixtures/schema/task_v16.json")),
+        (17, include_str!("../fixtures/schema/task_v17.json")),
+        (18, include_str!("../fixtures/schema/task_v18.json")),
+        (19, include_str!("../fixtures/schema/task_v19.json")),
+    ];
+
+    const PROOF_DETAIL_FIXTURES: &[(u32, &str)] = &[
+        (1, include_str!("../fixtures/schema/proof_detail_v1.json")),
+        (2, include_str!("../fixtures/schema/proof_detail_v2.json")),
+        (3, include_str!("../fixtures/schema/proof_detail_v3.json")),
+        (4, include_str!("../fixtures/schema/proof_detail_v4.json")),
+        (5, include_str!("../fixtures/schema/proof_detail_v5.json")),
+        (8, include_str!("../fixtures/schema/proof_detail_v8.json")),
+        (9, include_str!("../fixtures/schema/proof_detail_v9.json")),
+        (11, include_str!("../fixtures/schema/proof_detail_v11.json")),
+        (14, include_str!("../fixtures/schema/proof_detail_v14.json")),
+        (15, include_str!("../fixtures/schema/proof_detail_v15.json")),
+        (16, include_str!("../fixtures/schema/proof_detail_v16.json")),
+        (17, include_str!("../fixtures/schema/proof_detail_v17.json")),
+        (18, include_str!("../fixtures/schema/proof_detail_v18.json")),
+        (19, include_str!("../fixtures/schema/proof_detail_v19.json")),
+    ];
+
+    fn full_task() -> Task {
+        Task {
+            uuid: "u-1".to_string(),
+            id: "1".to_string(),
+            task_type: ProofType::Chunk,
+            task_data: String::new(),
+            task_data_ref: Some(PayloadRef {
+                uri: "file:///tasks/1.json".to_string(),
+                len: 7,
+                blake2b: "00".repeat(32),
+            }),
+            hard_fork_name: "bernoulli".to_string(),
+            evm: true,
+            dry_run: true,
+            instance_layout: InstanceLayout::ColumnMajor,
+            transcript: Some(TranscriptHash::Poseidon),
+            callback_url: Some("http://hooks.example.com/done".to_string()),
+            batch: Some(BatchRef {
+                id: "b-1".to_string(),
+                index: 2,
+                size: Some(4),
+            }),
+            preprocessor: Some("hex".to_string()),
+            compression: Some(ProofCompression::Gzip),
+            schema_version: Some(SCHEMA_VERSION),
+        }
+    }
+
+    fn full_proof_detail() -> ProofDetail {
+        ProofDetail {
+            id: "1".to_string(),
+            proof_type: ProofType::Chunk,
+            proof_data: "AAEC".to_string(),
+            transcript: Some(TranscriptHash::Poseidon),
+            compression: Some(ProofCompression::Gzip),
+            proof_size: Some(3),
+            proving_time_us: Some(250000),
+            circuit: Some(CircuitInfo {
+                name: "test_circuit".to_string(),
+                k: 10,
+            }),
+            error: "prove failed".to_string(),
+            error_code: "synthesis".to_string(),
+            failed_stage: Some(Stage::Prove),
+            constraint_failures: vec![ConstraintFailure {
+                kind: "constraint".to_string(),
+                gate: "Constraint 0 in gate 0 ('main')".to_string(),
+                region: "Region 1 ('hash')".to_string(),
+                row: Some(3),
+                message: "Constraint 0 in gate 0 ('main') is not satisfied".to_string(),
+            }],
+            instances: vec![format!("0x{}01", "00".repeat(31))],
+            instance_layout: InstanceLayout::ColumnMajor,
+            vk_hash: "ab".repeat(32),
+            protocol_label: "poseidon/v1".to_string(),
+            evm: Some(EvmProof {
+                transcript: "keccak256".to_string(),
+                proof: "AAEC".to_string(),
+                calldata: "0x000102".to_string(),
+            }),
+            memory: Some(MemoryReport {
+                peak_rss_bytes: Some(4096),
+                phases: vec![PhaseMemory {
+                    phase: Stage::Keygen,
+                    peak_heap_bytes: 1024,
+                    allocations: 3,
+                }],
+            }),
+            verification: Some(Verification {
+                verified: false,
+                duration_us: 1500,
+            }),
+            metadata: Some(ProofMetadata {
+                crate_version: "0.1.0".to_string(),
+                halo2_proofs: "pse-halo2@4d2c2f4e17a9df18e165fc088051838d9ac260f4".to_string(),
+                spec_id: "bn256-t4-r3".to_string(),
+                constants_hash: "cd".repeat(32),
+            }),
+            job: Some("u-1".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_versions_are_consistent() {
+        assert_eq!(VERSIONS.last().unwrap().version, SCHEMA_VERSION);
+        for (i, version) in VERSIONS.iter().enumerate() {
+            assert_eq!(version.version, i as u32 + 1);
+        }
+        let task = serde_json::to_value(full_task()).unwrap();
+        for field in task.as_object().unwrap().keys() {
+            assert!(since(field, |v| v.task_fields).is_some(), "{field}");
+        }
+        let detail = serde_json::to_value(full_proof_detail()).unwrap();
+        for field in detail.as_object().unwrap().keys() {
+            assert!(since(field, |v| v.proof_detail_fields).is_some(), "{field}");
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let task = full_task();
+        let json = serde_json::to_vec(&task).unwrap();
+        assert_eq!(Task::from_json(&json).unwrap(), task);
+        assert_eq!(
+            Task::from_json_with(&json, ParseMode::Strict).unwrap(),
+            task
+        );
+        let minimal = Task::default();
+        let json = serde_json::to_vec(&minimal).unwrap();
+        assert_eq!(
+            Task::from_json(&json).unwrap(),
+            Task {
+                schema_version: Some(SCHEMA_VERSION),
+                ..minimal
+            }
+        );
+
+        for detail in [full_proof_detail(), ProofDetail::default()] {
+            let json = serde_json::to_string(&detail).unwrap();
+            assert_eq!(serde_json::from_str::<ProofDetail>(&json).unwrap(), detail);
+        }
+    }
+
+    #[test]
+    fn test_golden_task_fixtures() {
+        for (version, fixture) in TASK_FIXTURES {
+            let task = Task::from_json_with(fixture.as_bytes(), ParseMode::Strict)
+                .unwrap_or_else(|err| panic!("task v{version}: {err}"));
+            assert_eq!(task.schema_version, Some(SCHEMA_VERSION));
+            assert_eq!(task.id, "1", "task v{version}");
+        }
+        // the newest fixture is what this build writes
+        let (_, current) = TASK_FIXTURES.last().unwrap();
+        assert_eq!(
+            serde_json::to_value(full_task()).unwrap(),
+            serde_json::from_str::<Value>(current).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_golden_proof_detail_fixtures() {
+        for (version, fixture) in PROOF_DETAIL_FIXTURES {
+            let value = upgrade_proof_detail(serde_json::from_str(fixture).unwrap()).unwrap();
+            let detail = serde_json::from_value::<ProofDetail>(value)
+                .unwrap_or_else(|err| panic!("proof detail v{version}: {err}"));
+            assert_eq!(detail.proof_data, "AAEC", "proof detail v{version}");
+        }
+        let (_, current) = PROOF_DETAIL_FIXTURES.last().unwrap();
+        assert_eq!(
+            serde_json::to_value(full_proof_detail()).unwrap(),
+            serde_json::from_str::<Value>(current).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_upgrade_phases() {
+        let (_, v1) = PROOF_DETAIL_FIXTURES[0];
+        let value = upgrade_proof_detail(serde_json::from_str(v1).unwrap()).unwrap();
+        let detail = serde_json::from_value::<ProofDetail>(value).unwrap();
+        let phases = detail
+            .memory
+            .unwrap()
+            .phases
+            .into_iter()
+            .map(|phase| phase.phase)
+            .collect::<Vec<_>>();
+        // keygen_vk, keygen_pk, prove and an unknown "warmup"
+        assert_eq!(phases, [Stage::Keygen, Stage::Keygen, Stage::Prove]);
+    }
+
+    #[test]
+    fn test_declared_versions() {
+        let task = |value: Value| upgrade_task(value).map(|_| ());
+        assert_eq!(
+            task(json!({"id": "1", "schema_version": SCHEMA_VERSION + 1})),
+            Err(SchemaError::Newer(SCHEMA_VERSION + 1))
+        );
+        assert_eq!(
+            task(json!({"id": "1", "schema_version": 3, "callback_url": "http://a/"})),
+            Err(SchemaError::FieldTooNew {
+                field: "callback_url".to_string(),
+                declared: 3,
+                since: 7,
+            })
+        );
+        assert!(matches!(
+            task(json!({"schema_version": "10"})),
+            Err(SchemaError::InvalidVersion(_))
+        ));
+        assert_eq!(
+            task(json!({"id": "1", "schema_version": 3, "evm": true})),
+            Ok(())
+        );
+        assert_eq!(task(json!([])), Err(SchemaError::NotAnObject));
+    }
+}
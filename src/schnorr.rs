@@ -0,0 +1,828 @@
+//! Schnorr signatures over Grumpkin with a Poseidon challenge, verified in-circuit.
+//!
+//! Grumpkin is `y^2 = x^3 - 17` over the bn256 scalar field, of prime order the bn256 base
+//! field modulus, so its points are pairs of circuit elements and adding them takes a few
+//! main gate rows, where checking an ECDSA signature over SHA-256 or Keccak would take a
+//! hash gadget of its own. A signature of a message `m`, one field element, under the key
+//! `P = x * G` is `(R, s)` with `R = k * G` for a random `k`, `s = k + e * x` and the
+//! challenge `e = Poseidon(R, P, m)` under [`SCHNORR_DOMAIN`]; it verifies when
+//! `s * G = R + e * P`. `G` is `(1, sqrt(-16))` of even `y`.
+//!
+//! [`SchnorrChip`] checks the equation with incomplete addition from an offset point whose
+//! discrete logarithm nobody knows: the cases incomplete addition cannot handle only occur
+//! for keys related to that point. Scalars are decomposed into 254 bits. `s` is not checked
+//! to be canonical: `s + q`, where it fits, is the same multiple of points of order `q`. The
+//! challenge is a circuit element, so its bits are constrained to the canonical `e < r`:
+//! `e + r` is another multiple, as `r` is not `q`.
+use ff::{Field, PrimeField};
+use halo2_proofs::{
+    circuit::{Chip, Value},
+    plonk::Error,
+};
+use halo2curves::bn256::{Fq, Fr};
+use poseidon::Spec;
+use rand_core::RngCore;
+
+use crate::{
+    main_gate::{AssignedValue, MainGate, MainGateConfig, RegionCtx, WrapValue},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash_with_domain,
+    range_chip::RangeChip,
+    specs::Domain,
+};
+
+/// `2^128 - 4`, next to [`crate::cipher::ENCRYPTION_DOMAIN`]
+pub const SCHNORR_DOMAIN: Domain = Domain::Constant(u128::MAX - 3);
+
+/// Bits of a scalar, of both fields of bn256
+pub(crate) const SCALAR_BITS: usize = 254;
+
+/// Bits of each half of a decomposed scalar
+pub(crate) const HALF_BITS: usize = SCALAR_BITS / 2;
+
+/// `b` of `y^2 = x^3 + b`
+pub(crate) fn b() -> Fr {
+    -Fr::from(17)
+}
+
+/// An affine Grumpkin point other than the point at infinity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Point {
+    pub x: Fr,
+    pub y: Fr,
+}
+
+impl Point {
+    pub(crate) fn with_even_y(x: Fr) -> Option<Self> {
+        let y: Fr = Option::from((x.square() * x + b()).sqrt())?;
+        let y = if bool::from(y.is_odd()) { -y } else { y };
+        Some(Self { x, y })
+    }
+
+    pub fn generator() -> Self {
+        Self::with_even_y(Fr::ONE).expect("-16 is a square")
+    }
+
+    /// The point of even `y` with the least `x` above one, where scalar multiplications in
+    /// the circuit start.
+    pub(crate) fn offset() -> Self {
+        (2..)
+            .find_map(|x| Self::with_even_y(Fr::from(x)))
+            .expect("half of the x are on the curve")
+    }
+
+    pub fn is_on_curve(&self) -> bool {
+        self.y.square() == self.x.square() * self.x + b()
+    }
+}
+
+/// `p + q`, with `None` the point at infinity.
+pub(crate) fn add(p: Option<Point>, q: Option<Point>) -> Option<Point> {
+    let (p, q) = match (p, q) {
+        (None, q) => return q,
+        (p, None) => return p,
+        (Some(p), Some(q)) => (p, q),
+    };
+    let lambda = if p.x != q.x {
+        (q.y - p.y) * (q.x - p.x).invert().unwrap()
+    } else if p.y == q.y {
+        // no point of order two, y is not zero
+        p.x.square() * Fr::from(3) * p.y.double().invert().unwrap()
+    } else {
+        return None;
+    };
+    let x = lambda.square() - p.x - q.x;
+    Some(Point {
+        x,
+        y: lambda * (p.x - x) - p.y,
+    })
+}
+
+/// `k * p`
+pub(crate) fn mul(p: Point, k: &Fq) -> Option<Point> {
+    let repr = k.to_repr();
+    (0..SCALAR_BITS).rev().fold(None, |acc, i| {
+        let acc = add(acc, acc);
+        if (repr.as_ref()[i / 8] >> (i % 8)) & 1 == 1 {
+            add(acc, Some(p))
+        } else {
+            acc
+        }
+    })
+}
+
+/// The challenge of a signature, as a scalar; the bn256 scalar field is below its base
+/// field, so every element is a scalar.
+fn challenge<const T: usize, const RATE: usize>(
+    spec: &Spec<Fr, T, RATE>,
+    r: &Point,
+    public_key: &Point,
+    msg: Fr,
+) -> Fr {
+    hash_with_domain(
+        spec,
+        SCHNORR_DOMAIN,
+        &[r.x, r.y, public_key.x, public_key.y, msg],
+    )
+}
+
+pub(crate) fn to_scalar(e: Fr) -> Fq {
+    Fq::from_repr(e.to_repr()).expect("the scalar field is below the base field")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub r: Point,
+    pub s: Fq,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SigningKey(pub(crate) Fq);
+
+impl SigningKey {
+    pub fn random(rng: impl RngCore) -> Self {
+        Self(Fq::random(rng))
+    }
+
+    pub fn public_key(&self) -> Point {
+        mul(Point::generator(), &self.0).expect("the key is not zero")
+    }
+
+    /// Signs `msg` with a nonce drawn from `rng`, which must never repeat.
+    pub fn sign<const T: usize, const RATE: usize>(
+        &self,
+        spec: &Spec<Fr, T, RATE>,
+        msg: Fr,
+        rng: impl RngCore,
+    ) -> Signature {
+        let k = Fq::random(rng);
+        let r = mul(Point::generator(), &k).expect("the nonce is not zero");
+        let e = challenge(spec, &r, &self.public_key(), msg);
+        Signature {
+            r,
+            s: k + to_scalar(e) * self.0,
+        }
+    }
+}
+
+/// Checks a signature of `msg` under `public_key` natively.
+pub fn verify<const T: usize, const RATE: usize>(
+    spec: &Spec<Fr, T, RATE>,
+    public_key: &Point,
+    msg: Fr,
+    signature: &Signature,
+) -> bool {
+    let e = challenge(spec, &signature.r, public_key, msg);
+    public_key.is_on_curve()
+        && signature.r.is_on_curve()
+        && mul(Point::generator(), &signature.s)
+            == add(Some(signature.r), mul(*public_key, &to_scalar(e)))
+}
+
+/// `lo + 2^127 * hi` of a scalar below `2^254`, little-endian.
+fn halves(repr: [u8; 32]) -> (u128, u128) {
+    let lo = u128::from_le_bytes(repr[..16].try_into().unwrap());
+    let hi = u128::from_le_bytes(repr[16..].try_into().unwrap());
+    (lo & ((1 << HALF_BITS) - 1), hi << 1 | lo >> HALF_BITS)
+}
+
+fn split(repr: [u8; 32]) -> (Fr, Fr) {
+    let (lo, hi) = halves(repr);
+    (Fr::from_u128(lo), Fr::from_u128(hi))
+}
+
+#[derive(Clone, Debug)]
+pub struct AssignedPoint {
+    pub x: AssignedValue<Fr>,
+    pub y: AssignedValue<Fr>,
+}
+
+/// The cells of a verified signature, for the caller to expose or constrain.
+#[derive(Clone, Debug)]
+pub struct AssignedVerification {
+    pub public_key: AssignedPoint,
+    pub msg: AssignedValue<Fr>,
+    pub r: AssignedPoint,
+}
+
+pub struct SchnorrChip<const T: usize, const RATE: usize> {
+    main_gate: MainGate<Fr, T>,
+    range: RangeChip<Fr, T>,
+    spec: Spec<Fr, T, RATE>,
+}
+
+impl<const T: usize, const RATE: usize> SchnorrChip<T, RATE> {
+    pub fn new(config: MainGateConfig<T>, spec: Spec<Fr, T, RATE>) -> Self {
+        // rows use three state cells
+        assert!(T >= 3);
+        Self {
+            range: RangeChip::new(config.clone()),
+            main_gate: MainGate::new(config),
+            spec,
+        }
+    }
+
+    /// Rows used by [`SchnorrChip::verify`].
+    pub fn num_rows(spec: &Spec<Fr, T, RATE>) -> usize {
+        // two points witnessed and checked on the curve, the generator and the offset,
+        // the challenge, two scalars decomposed, the challenge recomposed and checked to be
+        // canonical, a doubling, an addition and a selection per bit of both
+        // multiplications, and an addition
+        2 * (2 + 3)
+            + 2 * 2
+            + PoseidonChip::num_rows(spec, 5)
+            + 2 * (2 + 2 * RangeChip::<Fr, T>::num_rows(HALF_BITS))
+            + 1
+            + Self::canonical_rows()
+            + 2 * SCALAR_BITS * (6 + 8 + 4)
+            + 8
+    }
+
+    /// Rows used by [`SchnorrChip::assert_canonical`].
+    pub(crate) fn canonical_rows() -> usize {
+        4 + 2 * RangeChip::<Fr, T>::num_rows(HALF_BITS)
+    }
+
+    /// `q_m * s[0] * s[1] + sum q_1[i] * s[i] + rc - out = 0`, returning `out`.
+    pub(crate) fn gate(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        q_m: Fr,
+        terms: Vec<(Fr, WrapValue<Fr>)>,
+        rc: Fr,
+        out: Value<Fr>,
+    ) -> Result<AssignedValue<Fr>, Error> {
+        let (q_1, state) = terms.into_iter().unzip();
+        self.main_gate.apply(
+            ctx,
+            (Some(q_1), Some(q_m), Some(state)),
+            Some(rc),
+            (-Fr::ONE, out.into()),
+        )
+    }
+
+    /// `q_m * s[0] * s[1] + sum q_1[i] * s[i] + rc = 0`
+    pub(crate) fn assert_zero(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        q_m: Fr,
+        terms: Vec<(Fr, WrapValue<Fr>)>,
+        rc: Fr,
+    ) -> Result<(), Error> {
+        let (q_1, state) = terms.into_iter().unzip();
+        self.main_gate.apply(
+            ctx,
+            (Some(q_1), Some(q_m), Some(state)),
+            Some(rc),
+            (Fr::ZERO, Value::known(Fr::ZERO).into()),
+        )?;
+        Ok(())
+    }
+
+    /// A free cell: every selector of the main gate is zero on its row.
+    pub(crate) fn witness(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        value: Value<Fr>,
+    ) -> Result<AssignedValue<Fr>, Error> {
+        self.main_gate
+            .apply(ctx, (None, None, None), None, (Fr::ZERO, value.into()))
+    }
+
+    pub(crate) fn constant(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        value: Fr,
+    ) -> Result<AssignedValue<Fr>, Error> {
+        // rc - out = 0
+        self.gate(ctx, Fr::ZERO, vec![], value, Value::known(value))
+    }
+
+    pub(crate) fn value(cell: &AssignedValue<Fr>) -> Value<Fr> {
+        cell.value().copied()
+    }
+
+    pub(crate) fn assign_point(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        point: &Point,
+    ) -> Result<AssignedPoint, Error> {
+        let x = self.witness(ctx, Value::known(point.x))?;
+        let y = self.witness(ctx, Value::known(point.y))?;
+        // y * y - x^3 + 17 = 0
+        let xx = self.gate(
+            ctx,
+            Fr::ONE,
+            vec![(Fr::ZERO, (&x).into()), (Fr::ZERO, (&x).into())],
+            Fr::ZERO,
+            Self::value(&x) * Self::value(&x),
+        )?;
+        let xxx = self.gate(
+            ctx,
+            Fr::ONE,
+            vec![(Fr::ZERO, (&xx).into()), (Fr::ZERO, (&x).into())],
+            Fr::ZERO,
+            Self::value(&xx) * Self::value(&x),
+        )?;
+        self.assert_zero(
+            ctx,
+            Fr::ONE,
+            vec![
+                (Fr::ZERO, (&y).into()),
+                (Fr::ZERO, (&y).into()),
+                (-Fr::ONE, xxx.into()),
+            ],
+            -b(),
+        )?;
+        Ok(AssignedPoint { x, y })
+    }
+
+    pub(crate) fn constant_point(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        point: &Point,
+    ) -> Result<AssignedPoint, Error> {
+        Ok(AssignedPoint {
+            x: self.constant(ctx, point.x)?,
+            y: self.constant(ctx, point.y)?,
+        })
+    }
+
+    /// `p + q` for `p.x != q.x`.
+    pub(crate) fn add(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        p: &AssignedPoint,
+        q: &AssignedPoint,
+    ) -> Result<AssignedPoint, Error> {
+        let linear = |a: &AssignedValue<Fr>, ca: Fr, b: &AssignedValue<Fr>, cb: Fr| {
+            (
+                vec![(ca, WrapValue::from(a)), (cb, WrapValue::from(b))],
+                Self::value(a) * Value::known(ca) + Self::value(b) * Value::known(cb),
+            )
+        };
+        let (terms, value) = linear(&q.x, Fr::ONE, &p.x, -Fr::ONE);
+        let dx = self.gate(ctx, Fr::ZERO, terms, Fr::ZERO, value)?;
+        let (terms, value) = linear(&q.y, Fr::ONE, &p.y, -Fr::ONE);
+        let dy = self.gate(ctx, Fr::ZERO, terms, Fr::ZERO, value)?;
+
+        // lambda * dx - dy = 0
+        let lambda = Self::value(&dy)
+            .zip(Self::value(&dx))
+            .map(|(dy, dx)| dy * dx.invert().unwrap_or(Fr::ZERO));
+        let lambda = self.witness(ctx, lambda)?;
+        self.assert_zero(
+            ctx,
+            Fr::ONE,
+            vec![
+                (Fr::ZERO, (&lambda).into()),
+                (Fr::ZERO, dx.into()),
+                (-Fr::ONE, dy.into()),
+            ],
+            Fr::ZERO,
+        )?;
+
+        // x' = lambda^2 - p.x - q.x
+        let (terms, value) = linear(&p.x, Fr::ONE, &q.x, Fr::ONE);
+        let sum = self.gate(ctx, Fr::ZERO, terms, Fr::ZERO, value)?;
+        let x = self.gate(
+            ctx,
+            Fr::ONE,
+            vec![
+                (Fr::ZERO, (&lambda).into()),
+                (Fr::ZERO, (&lambda).into()),
+                (-Fr::ONE, (&sum).into()),
+            ],
+            Fr::ZERO,
+            Self::value(&lambda) * Self::value(&lambda) - Self::value(&sum),
+        )?;
+
+        // y' = lambda * (p.x - x') - p.y
+        let y = self.lambda_y(ctx, &lambda, p, &x)?;
+        Ok(AssignedPoint { x, y })
+    }
+
+    /// `2 * p`; Grumpkin has no point of order two, so `p.y` is not zero.
+    fn double(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        p: &AssignedPoint,
+    ) -> Result<AssignedPoint, Error> {
+        let xx = self.gate(
+            ctx,
+            Fr::ONE,
+            vec![(Fr::ZERO, (&p.x).into()), (Fr::ZERO, (&p.x).into())],
+            Fr::ZERO,
+            Self::value(&p.x) * Self::value(&p.x),
+        )?;
+
+        // 2 * lambda * y - 3 * x^2 = 0
+        let lambda = Self::value(&xx)
+            .zip(Self::value(&p.y))
+            .map(|(xx, y)| xx * Fr::from(3) * y.double().invert().unwrap_or(Fr::ZERO));
+        let lambda = self.witness(ctx, lambda)?;
+        self.assert_zero(
+            ctx,
+            Fr::from(2),
+            vec![
+                (Fr::ZERO, (&lambda).into()),
+                (Fr::ZERO, (&p.y).into()),
+                (-Fr::from(3), xx.into()),
+            ],
+            Fr::ZERO,
+        )?;
+
+        // x' = lambda^2 - 2 * x
+        let x = self.gate(
+            ctx,
+            Fr::ONE,
+            vec![
+                (Fr::ZERO, (&lambda).into()),
+                (Fr::ZERO, (&lambda).into()),
+                (-Fr::from(2), (&p.x).into()),
+            ],
+            Fr::ZERO,
+            Self::value(&lambda) * Self::value(&lambda) - Self::value(&p.x).map(|x| x.double()),
+        )?;
+        let y = self.lambda_y(ctx, &lambda, p, &x)?;
+        Ok(AssignedPoint { x, y })
+    }
+
+    /// `lambda * (p.x - x) - p.y`, the `y` of a sum or a doubling
+    fn lambda_y(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        lambda: &AssignedValue<Fr>,
+        p: &AssignedPoint,
+        x: &AssignedValue<Fr>,
+    ) -> Result<AssignedValue<Fr>, Error> {
+        let d = self.gate(
+            ctx,
+            Fr::ZERO,
+            vec![(Fr::ONE, (&p.x).into()), (-Fr::ONE, x.into())],
+            Fr::ZERO,
+            Self::value(&p.x) - Self::value(x),
+        )?;
+        self.gate(
+            ctx,
+            Fr::ONE,
+            vec![
+                (Fr::ZERO, lambda.into()),
+                (Fr::ZERO, (&d).into()),
+                (-Fr::ONE, (&p.y).into()),
+            ],
+            Fr::ZERO,
+            Self::value(lambda) * Self::value(&d) - Self::value(&p.y),
+        )
+    }
+
+    /// `p` if `bit` is one, `q` if it is zero.
+    fn select(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        bit: &AssignedValue<Fr>,
+        p: &AssignedPoint,
+        q: &AssignedPoint,
+    ) -> Result<AssignedPoint, Error> {
+        let mut select =
+            |a: &AssignedValue<Fr>, b: &AssignedValue<Fr>| -> Result<AssignedValue<Fr>, Error> {
+                // bit * (a - b) + b - out = 0
+                let d = self.gate(
+                    ctx,
+                    Fr::ZERO,
+                    vec![(Fr::ONE, a.into()), (-Fr::ONE, b.into())],
+                    Fr::ZERO,
+                    Self::value(a) - Self::value(b),
+                )?;
+                self.gate(
+                    ctx,
+                    Fr::ONE,
+                    vec![
+                        (Fr::ZERO, bit.into()),
+                        (Fr::ZERO, (&d).into()),
+                        (Fr::ONE, b.into()),
+                    ],
+                    Fr::ZERO,
+                    Self::value(bit) * Self::value(&d) + Self::value(b),
+                )
+            };
+        Ok(AssignedPoint {
+            x: select(&p.x, &q.x)?,
+            y: select(&p.y, &q.y)?,
+        })
+    }
+
+    /// The 254 bits of a scalar of little-endian `repr`, least significant first, and the
+    /// cells of its halves.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn decompose(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        repr: [u8; 32],
+    ) -> Result<(Vec<AssignedValue<Fr>>, [AssignedValue<Fr>; 2]), Error> {
+        let (lo_val, hi_val) = split(repr);
+        let lo = self.witness(ctx, Value::known(lo_val))?;
+        let hi = self.witness(ctx, Value::known(hi_val))?;
+        let mut bits = self.range.range_check(ctx, &lo, HALF_BITS)?;
+        bits.extend(self.range.range_check(ctx, &hi, HALF_BITS)?);
+        Ok((bits, [lo, hi]))
+    }
+
+    /// Constrains the halves of a decomposition, as [`SchnorrChip::decompose`] returns them,
+    /// to `lo + 2^127 * hi <= r - 1`, so that they decompose a circuit element canonically.
+    ///
+    /// With `r - 1 = max_lo + 2^127 * max_hi` and a borrow bit `b`, both
+    /// `max_lo - lo + 2^127 * b` and `max_hi - hi - b` are range checked to 127 bits; they
+    /// are the halves of `r - 1 - (lo + 2^127 * hi)`, which is then not negative.
+    pub(crate) fn assert_canonical(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        [lo, hi]: &[AssignedValue<Fr>; 2],
+    ) -> Result<(), Error> {
+        let (max_lo, max_hi) = halves((-Fr::ONE).to_repr());
+        let borrow = Self::value(lo).map(|lo| Fr::from(u64::from(halves(lo.to_repr()).0 > max_lo)));
+        let borrow = self.witness(ctx, borrow)?;
+        // borrow * borrow - borrow = 0
+        self.assert_zero(
+            ctx,
+            Fr::ONE,
+            vec![(Fr::ZERO, (&borrow).into()), (-Fr::ONE, (&borrow).into())],
+            Fr::ZERO,
+        )?;
+        let two_127 = Fr::from_u128(1 << HALF_BITS);
+        let d_lo = self.gate(
+            ctx,
+            Fr::ZERO,
+            vec![(-Fr::ONE, lo.into()), (two_127, (&borrow).into())],
+            Fr::from_u128(max_lo),
+            Value::known(Fr::from_u128(max_lo)) - Self::value(lo)
+                + Self::value(&borrow) * Value::known(two_127),
+        )?;
+        let d_hi = self.gate(
+            ctx,
+            Fr::ZERO,
+            vec![(-Fr::ONE, hi.into()), (-Fr::ONE, (&borrow).into())],
+            Fr::from_u128(max_hi),
+            Value::known(Fr::from_u128(max_hi)) - Self::value(hi) - Self::value(&borrow),
+        )?;
+        self.range.range_check(ctx, &d_lo, HALF_BITS)?;
+        self.range.range_check(ctx, &d_hi, HALF_BITS)?;
+        Ok(())
+    }
+
+    /// `offset * 2^254 + k * p` for the bits of `k`, least significant first.
+    pub(crate) fn mul(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        offset: &AssignedPoint,
+        bits: &[AssignedValue<Fr>],
+        p: &AssignedPoint,
+    ) -> Result<AssignedPoint, Error> {
+        let mut acc = offset.clone();
+        for bit in bits.iter().rev() {
+            acc = self.double(ctx, &acc)?;
+            let sum = self.add(ctx, &acc, p)?;
+            acc = self.select(ctx, bit, &sum, &acc)?;
+        }
+        Ok(acc)
+    }
+
+    /// Constrains `signature` to sign `msg` under `public_key`, as [`verify`] checks.
+    pub fn verify(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        public_key: &Point,
+        msg: Fr,
+        signature: &Signature,
+    ) -> Result<AssignedVerification, Error> {
+        let pk = self.assign_point(ctx, public_key)?;
+        let r = self.assign_point(ctx, &signature.r)?;
+        let generator = self.constant_point(ctx, &Point::generator())?;
+        let offset = self.constant_point(ctx, &Point::offset())?;
+
+        let mut pchip = PoseidonChip::new(self.main_gate.config().clone(), self.spec.clone())
+            .with_domain(SCHNORR_DOMAIN);
+        let e_val = challenge(&self.spec, &signature.r, public_key, msg);
+        pchip.update(vec![
+            signature.r.x,
+            signature.r.y,
+            public_key.x,
+            public_key.y,
+            msg,
+        ]);
+        let (inputs, e) = pchip.squeeze_with_inputs(ctx)?;
+        for (input, cell) in inputs.iter().zip([&r.x, &r.y, &pk.x, &pk.y]) {
+            ctx.constrain_equal(input.cell(), cell.cell())?;
+        }
+
+        let (s_bits, _) = self.decompose(ctx, signature.s.to_repr())?;
+        let (e_bits, e_halves) = self.decompose(ctx, e_val.to_repr())?;
+        // lo + 2^127 * hi - e = 0, with lo + 2^127 * hi < r
+        let [lo, hi] = &e_halves;
+        self.assert_zero(
+            ctx,
+            Fr::ZERO,
+            vec![
+                (Fr::ONE, lo.into()),
+                (Fr::from_u128(1 << HALF_BITS), hi.into()),
+                (-Fr::ONE, e.into()),
+            ],
+            Fr::ZERO,
+        )?;
+        self.assert_canonical(ctx, &e_halves)?;
+
+        // s * G = R + e * P, both sides offset by the same multiple of the offset point
+        let lhs = self.mul(ctx, &offset, &s_bits, &generator)?;
+        let rhs = self.mul(ctx, &offset, &e_bits, &pk)?;
+        let rhs = self.add(ctx, &rhs, &r)?;
+        ctx.constrain_equal(lhs.x.cell(), rhs.x.cell())?;
+        ctx.constrain_equal(lhs.y.cell(), rhs.y.cell())?;
+
+        Ok(AssignedVerification {
+            public_key: pk,
+            msg: inputs[4].clone(),
+            r,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+    };
+    use rand_core::OsRng;
+
+    use super::*;
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+    const K: u32 = 14;
+
+    fn spec() -> Spec<Fr, T, RATE> {
+        Spec::new(8, 56)
+    }
+
+    /// Verifies a private signature and exposes `[public_key.x, public_key.y, msg]`.
+    struct SignatureCircuit {
+        public_key: Point,
+        msg: Fr,
+        signature: Signature,
+    }
+
+    impl Circuit<Fr> for SignatureCircuit {
+        type Config = (MainGateConfig<T>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                public_key: Point::generator(),
+                msg: Fr::ZERO,
+                signature: Signature {
+                    r: Point::generator(),
+                    s: Fq::ZERO,
+                },
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let config = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            (config, instance)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = SchnorrChip::new(config, spec());
+            let verified = layouter.assign_region(
+                || "schnorr",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    chip.verify(ctx, &self.public_key, self.msg, &self.signature)
+                },
+            )?;
+            let public = [verified.public_key.x, verified.public_key.y, verified.msg];
+            for (i, cell) in public.iter().enumerate() {
+                layouter.constrain_instance(cell.cell(), instance, i)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Decomposes the integer of little-endian `repr` and checks it is below `r`.
+    struct CanonicalCircuit {
+        repr: [u8; 32],
+    }
+
+    impl Circuit<Fr> for CanonicalCircuit {
+        type Config = MainGateConfig<T>;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self { repr: [0; 32] }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            MainGate::configure(meta, &mut adv_cols, &mut fix_cols)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = SchnorrChip::new(config, spec());
+            layouter.assign_region(
+                || "canonical",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let (_, halves) = chip.decompose(ctx, self.repr)?;
+                    chip.assert_canonical(ctx, &halves)
+                },
+            )
+        }
+    }
+
+    /// The little-endian integer `repr + n`.
+    fn add_small(mut repr: [u8; 32], n: u64) -> [u8; 32] {
+        let mut carry = n;
+        for byte in &mut repr {
+            let sum = u64::from(*byte) + carry;
+            *byte = sum as u8;
+            carry = sum >> 8;
+        }
+        repr
+    }
+
+    #[test]
+    fn test_canonical_challenge() {
+        let accepts = |repr| {
+            let prover = MockProver::run(K, &CanonicalCircuit { repr }, vec![]).unwrap();
+            prover.verify().is_ok()
+        };
+        let max = (-Fr::ONE).to_repr();
+        assert!(accepts(max));
+        assert!(accepts(Fr::from(42).to_repr()));
+        assert!(accepts(Fr::from_u128(u128::MAX).to_repr()));
+        // r itself, and a challenge of 42 recomposed as 42 + r, which would multiply the
+        // public key by another scalar
+        assert!(!accepts(add_small(max, 1)));
+        assert!(!accepts(add_small(max, 43)));
+    }
+
+    #[test]
+    fn test_sign() {
+        let spec = spec();
+        assert!(Point::generator().is_on_curve());
+        assert!(Point::offset().is_on_curve());
+        let key = SigningKey::random(OsRng);
+        let public_key = key.public_key();
+        let msg = Fr::from(42);
+        let signature = key.sign(&spec, msg, OsRng);
+        assert!(verify(&spec, &public_key, msg, &signature));
+        assert!(!verify(&spec, &public_key, msg + Fr::ONE, &signature));
+        let other = SigningKey::random(OsRng).public_key();
+        assert!(!verify(&spec, &other, msg, &signature));
+        let forged = Signature {
+            s: signature.s + Fq::ONE,
+            ..signature
+        };
+        assert!(!verify(&spec, &public_key, msg, &forged));
+    }
+
+    #[test]
+    fn test_verify_in_circuit() {
+        let spec = spec();
+        assert!(SchnorrChip::num_rows(&spec) + 6 <= 1 << K);
+        let key = SigningKey::random(OsRng);
+        let public_key = key.public_key();
+        let msg = Fr::from(42);
+        let circuit = SignatureCircuit {
+            public_key,
+            msg,
+            signature: key.sign(&spec, msg, OsRng),
+        };
+        let public = vec![public_key.x, public_key.y, msg];
+        let prover = MockProver::run(K, &circuit, vec![public]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // a signature of another message
+        let circuit = SignatureCircuit {
+            signature: key.sign(&spec, msg + Fr::ONE, OsRng),
+            ..circuit
+        };
+        let public = vec![public_key.x, public_key.y, msg];
+        let prover = MockProver::run(K, &circuit, vec![public]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! Config file of the prover service, read by `snarkify --config <file>` at startup.
+//!
+//! ```toml
+//! # optional; without it insecure test params are set up
+//! params = "params/kzg_bn254_13.srs"
+//! k = 10
+//! workers = 4
+//! threads = 32
+//! transcript = "blake2b"
+//! backend = "halo2-kzg-gwc"
+//! vk_store_dir = "/var/lib/prover/vks"
+//! key_cache_dir = "/var/cache/prover/keys"
+//! proof_types = ["chunk", "batch", "preimage"]
+//! ```
+//!
+//! Every setting is optional. The environment variable of a setting overrides the file, so
+//! machines of one fleet share a file and differ only in what their environment sets.
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::Error as _, Deserialize, Deserializer};
+
+use crate::{capabilities::BACKENDS, prover::TranscriptHash, task::ProofType};
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    /// Params file loaded at startup, e.g. a `.srs` written by `ParamsKZG::write`
+    pub params: Option<PathBuf>,
+    /// Degree of the hash, preimage and range circuits
+    pub k: Option<u32>,
+    /// Workers proving tasks in the background, see [`crate::jobs`]
+    pub workers: Option<usize>,
+    /// Prover threads
+    pub threads: Option<usize>,
+    /// Transcript of tasks that do not name one
+    pub transcript: Option<TranscriptHash>,
+    /// Proving backend, one of [`BACKENDS`]
+    pub backend: Option<String>,
+    /// Directory of verifying keys of earlier circuit versions
+    pub vk_store_dir: Option<PathBuf>,
+    /// Directory persisting the keys of other message lengths
+    pub key_cache_dir: Option<PathBuf>,
+    /// Proof types answered, all if unset, see [`crate::state::ProverState::with_proof_types`]
+    #[serde(default, deserialize_with = "proof_type_names")]
+    pub proof_types: Option<Vec<ProofType>>,
+}
+
+#[derive(Debug)]
+pub enum ServiceConfigError {
+    Io(io::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ServiceConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read service config: {err}"),
+            Self::Invalid(msg) => write!(f, "invalid service config: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ServiceConfigError {}
+
+impl From<io::Error> for ServiceConfigError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl ServiceConfig {
+    pub fn parse(s: &str) -> Result<Self, ServiceConfigError> {
+        let config: Self =
+            toml::from_str(s).map_err(|err| ServiceConfigError::Invalid(err.to_string()))?;
+        if config.k == Some(0) {
+            return Err(ServiceConfigError::Invalid("k is 0".to_string()));
+        }
+        if config.workers == Some(0) || config.threads == Some(0) {
+            return Err(ServiceConfigError::Invalid(
+                "workers and threads are positive".to_string(),
+            ));
+        }
+        if let Some(backend) = &config.backend {
+            check_backend(backend).map_err(ServiceConfigError::Invalid)?;
+        }
+        Ok(config)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, ServiceConfigError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+/// Checks that `backend` is compiled into this crate; only one is so far.
+pub fn check_backend(backend: &str) -> Result<(), String> {
+    if BACKENDS.contains(&backend) {
+        Ok(())
+    } else {
+        Err(format!(
+            "unknown backend {backend:?}, expected {}",
+            BACKENDS.join(" or ")
+        ))
+    }
+}
+
+/// Parses comma-separated proof type names, e.g. `chunk,batch`.
+pub fn parse_proof_types(names: &str) -> Result<Vec<ProofType>, String> {
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+fn proof_type_names<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<ProofType>>, D::Error> {
+    Option::<Vec<String>>::deserialize(deserializer)?
+        .map(|names| {
+            names
+                .iter()
+                .map(|name| name.parse().map_err(D::Error::custom))
+                .collect()
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = ServiceConfig::parse(
+            r#"
+            params = "params.srs"
+            k = 12
+            workers = 2
+            transcript = "keccak256"
+            backend = "halo2-kzg-gwc"
+            proof_types = ["chunk", "membership"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.params, Some(PathBuf::from("params.srs")));
+        assert_eq!(config.k, Some(12));
+        assert_eq!(config.workers, Some(2));
+        assert_eq!(config.threads, None);
+        assert_eq!(config.transcript, Some(TranscriptHash::Keccak256));
+        assert_eq!(
+            config.proof_types,
+            Some(vec![ProofType::Chunk, ProofType::Membership])
+        );
+        assert_eq!(ServiceConfig::parse("").unwrap(), ServiceConfig::default());
+
+        for invalid in [
+            "kk = 10",
+            "k = 0",
+            "workers = 0",
+            "backend = \"halo2-kzg-shplonk\"",
+            "transcript = \"sha256\"",
+            "proof_types = [\"chunk\", \"undefined\"]",
+        ] {
+            assert!(
+                matches!(
+                    ServiceConfig::parse(invalid),
+                    Err(ServiceConfigError::Invalid(_))
+                ),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_proof_types() {
+        assert_eq!(
+            parse_proof_types("chunk, batch,"),
+            Ok(vec![ProofType::Chunk, ProofType::Batch])
+        );
+        assert!(parse_proof_types("chunk,sha").is_err());
+    }
+}
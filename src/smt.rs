@@ -0,0 +1,1227 @@
+//! Sparse Poseidon Merkle trees: key-value maps with membership, non-membership and update
+//! proofs, e.g. for rollup state.
+//!
+//! A tree of depth `d` has a slot for every key below `2^d`, the path to it being the bits
+//! of the key. An occupied slot holds the leaf `Poseidon(key, value)`, an empty one zero;
+//! parents are `Poseidon(left, right)` as in [`MerkleTree`], so a subtree without leaves has
+//! the root of the empty subtree of its height. Only the nodes of non-empty subtrees are
+//! stored, in a [`NodeStore`]: [`MemoryStore`] keeps them in memory, and an implementation
+//! over a database keeps a tree across restarts, as the root is read back from the store.
+//!
+//! [`SmtChip`] checks the proofs of [`SparseMerkleTree`] in-circuit on top of a
+//! [`MerkleChip`], with the key as the index.
+//!
+//! [`SparseMerkleTree::apply`] changes several keys at once, e.g. the state diff of a block,
+//! and proves them as one [`SmtDiff`] whose paths are merged, so the nodes they share are
+//! hashed once per root rather than once per key, natively and by [`SmtChip::diff`].
+//!
+//! [`MerkleTree`]: crate::merkle::MerkleTree
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, io,
+};
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::{circuit::Value, plonk::Error};
+use poseidon::Spec;
+
+use crate::{
+    main_gate::{AssignedValue, MainGateConfig, RegionCtx},
+    merkle::{MerkleChip, MerklePath},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash,
+    range_chip::RangeChip,
+};
+
+/// Deepest tree supported: keys are `u64`s, and [`MerkleTree`] indexes below `2^63`.
+pub const MAX_DEPTH: usize = 63;
+
+/// Storage of the non-empty nodes and values of a tree.
+///
+/// Level 0 holds the leaves and level `depth` the root; a node is addressed by its level
+/// and its index in the level. Setting `None` removes an entry.
+pub trait NodeStore<F> {
+    fn node(&self, level: usize, index: u64) -> io::Result<Option<F>>;
+    fn set_node(&mut self, level: usize, index: u64, node: Option<F>) -> io::Result<()>;
+    fn value(&self, key: u64) -> io::Result<Option<F>>;
+    fn set_value(&mut self, key: u64, value: Option<F>) -> io::Result<()>;
+}
+
+/// A [`NodeStore`] in memory.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore<F> {
+    nodes: HashMap<(usize, u64), F>,
+    values: BTreeMap<u64, F>,
+}
+
+impl<F> MemoryStore<F> {
+    /// Stored nodes, leaves and root included.
+    pub fn nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The key-value pairs of the tree, by key.
+    pub fn values(&self) -> impl Iterator<Item = (u64, &F)> {
+        self.values.iter().map(|(key, value)| (*key, value))
+    }
+}
+
+impl<F: Copy> NodeStore<F> for MemoryStore<F> {
+    fn node(&self, level: usize, index: u64) -> io::Result<Option<F>> {
+        Ok(self.nodes.get(&(level, index)).copied())
+    }
+
+    fn set_node(&mut self, level: usize, index: u64, node: Option<F>) -> io::Result<()> {
+        match node {
+            Some(node) => self.nodes.insert((level, index), node),
+            None => self.nodes.remove(&(level, index)),
+        };
+        Ok(())
+    }
+
+    fn value(&self, key: u64) -> io::Result<Option<F>> {
+        Ok(self.values.get(&key).copied())
+    }
+
+    fn set_value(&mut self, key: u64, value: Option<F>) -> io::Result<()> {
+        match value {
+            Some(value) => self.values.insert(key, value),
+            None => self.values.remove(&key),
+        };
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SmtError {
+    /// The key has no slot in a tree of this depth
+    KeyOutOfRange {
+        key: u64,
+        depth: usize,
+    },
+    /// Inserting a key the tree has
+    Exists(u64),
+    /// Updating a key the tree does not have
+    Missing(u64),
+    /// Changing a key twice in one diff
+    Duplicate(u64),
+    /// A diff without changes
+    NoChanges,
+    Store(io::Error),
+}
+
+impl fmt::Display for SmtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KeyOutOfRange { key, depth } => {
+                write!(f, "key {key} is out of a tree of depth {depth}")
+            }
+            Self::Exists(key) => write!(f, "key {key} is in the tree already"),
+            Self::Missing(key) => write!(f, "key {key} is not in the tree"),
+            Self::Duplicate(key) => write!(f, "key {key} is changed twice"),
+            Self::NoChanges => write!(f, "a diff needs a change"),
+            Self::Store(err) => write!(f, "node store failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for SmtError {}
+
+impl From<io::Error> for SmtError {
+    fn from(err: io::Error) -> Self {
+        Self::Store(err)
+    }
+}
+
+/// The leaf of `key` holding `value`.
+pub fn leaf<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    key: u64,
+    value: F,
+) -> F {
+    hash(spec, &[F::from(key), value])
+}
+
+/// The value of a key, or its absence, and the path to its slot.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SmtProof<F> {
+    /// `None` proves that the key is not in the tree
+    pub value: Option<F>,
+    /// Path of the slot, whose index is the key
+    pub path: MerklePath<F>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> SmtProof<F> {
+    pub fn key(&self) -> u64 {
+        self.path.index
+    }
+
+    /// The root the proof leads to.
+    pub fn root<const T: usize, const RATE: usize>(&self, spec: &Spec<F, T, RATE>) -> F {
+        let node = self
+            .value
+            .map_or(F::ZERO, |value| leaf(spec, self.key(), value));
+        self.path.root(spec, node)
+    }
+}
+
+/// Proof that setting a key changed the root from `old_root` to `new_root` and nothing else.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SmtUpdate<F> {
+    /// The value before the update, `None` for an insertion
+    pub old_value: Option<F>,
+    pub new_value: F,
+    /// Path of the slot, the same before and after
+    pub path: MerklePath<F>,
+    pub old_root: F,
+    pub new_root: F,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> SmtUpdate<F> {
+    pub fn key(&self) -> u64 {
+        self.path.index
+    }
+
+    /// The proof of the key before the update.
+    pub fn before(&self) -> SmtProof<F> {
+        SmtProof {
+            value: self.old_value,
+            path: self.path.clone(),
+        }
+    }
+
+    /// The proof of the key after the update.
+    pub fn after(&self) -> SmtProof<F> {
+        SmtProof {
+            value: Some(self.new_value),
+            path: self.path.clone(),
+        }
+    }
+
+    /// Checks both roots natively.
+    pub fn verify<const T: usize, const RATE: usize>(&self, spec: &Spec<F, T, RATE>) -> bool {
+        self.before().root(spec) == self.old_root && self.after().root(spec) == self.new_root
+    }
+}
+
+/// A change of a [`SmtDiff`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SmtChange<F> {
+    pub key: u64,
+    /// The value before the change, `None` for an insertion
+    pub old_value: Option<F>,
+    pub new_value: F,
+}
+
+/// Proof that a set of changes, and nothing else, changed the root from `old_root` to
+/// `new_root`, e.g. the state diff of a block.
+///
+/// The paths of the changed keys are merged: a node on several of them is hashed once per
+/// root, and only the siblings no path covers are listed, level by level from the leaves
+/// and by index within a level.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SmtDiff<F> {
+    pub depth: usize,
+    /// By increasing key
+    pub changes: Vec<SmtChange<F>>,
+    pub siblings: Vec<F>,
+    pub old_root: F,
+    pub new_root: F,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> SmtDiff<F> {
+    pub fn keys(&self) -> Vec<u64> {
+        self.changes.iter().map(|change| change.key).collect()
+    }
+
+    /// The level at which the paths of every two consecutive keys meet, which is all the
+    /// layout of [`SmtChip::diff`] depends on. `None` if the keys are not increasing or not
+    /// in a tree of the depth.
+    pub fn shape(&self) -> Option<Vec<usize>> {
+        let keys = self.keys();
+        let in_tree = |key: u64| key >> self.depth == 0;
+        if self.depth == 0 || self.depth > MAX_DEPTH || !keys.iter().all(|key| in_tree(*key)) {
+            return None;
+        }
+        if keys.is_empty() || keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return None;
+        }
+        Some(meeting_levels(&keys))
+    }
+
+    /// The roots before and after the changes, or `None` for a malformed diff.
+    pub fn roots<const T: usize, const RATE: usize>(
+        &self,
+        spec: &Spec<F, T, RATE>,
+    ) -> Option<(F, F)> {
+        let shape = self.shape()?;
+        let keys = self.keys();
+        let root = |leaves: Vec<F>| {
+            let mut siblings = self.siblings.iter();
+            let root = fold(spec, self.depth, &keys, &shape, leaves, |_, _| {
+                siblings.next().copied().ok_or(())
+            });
+            root.ok().filter(|_| siblings.next().is_none())
+        };
+        let old_leaves = self.changes.iter().map(|change| {
+            change
+                .old_value
+                .map_or(F::ZERO, |value| leaf(spec, change.key, value))
+        });
+        let new_leaves = self
+            .changes
+            .iter()
+            .map(|change| leaf(spec, change.key, change.new_value));
+        Some((root(old_leaves.collect())?, root(new_leaves.collect())?))
+    }
+
+    /// Checks both roots natively.
+    pub fn verify<const T: usize, const RATE: usize>(&self, spec: &Spec<F, T, RATE>) -> bool {
+        self.roots(spec) == Some((self.old_root, self.new_root))
+    }
+}
+
+/// The highest bit in which each two consecutive increasing keys differ.
+fn meeting_levels(keys: &[u64]) -> Vec<usize> {
+    keys.windows(2)
+        .map(|pair| (63 - (pair[0] ^ pair[1]).leading_zeros()) as usize)
+        .collect()
+}
+
+/// Hashes the nodes of increasing `keys` up to the root: at each level, the nodes of two
+/// consecutive keys whose paths meet there are hashed together, and any other node with
+/// `sibling(level, index)` of the index of its sibling.
+fn fold<F, E, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    depth: usize,
+    keys: &[u64],
+    shape: &[usize],
+    mut nodes: Vec<F>,
+    mut sibling: impl FnMut(usize, u64) -> Result<F, E>,
+) -> Result<F, E>
+where
+    F: PrimeField + FromUniformBytes<64>,
+{
+    // the first key under each node
+    let mut firsts = (0..keys.len()).collect::<Vec<_>>();
+    for level in 0..depth {
+        let (mut parents, mut parent_firsts) = (Vec::new(), Vec::new());
+        let mut i = 0;
+        while i < nodes.len() {
+            let index = keys[firsts[i]] >> level;
+            let merged = i + 1 < nodes.len() && shape[firsts[i + 1] - 1] == level;
+            parents.push(if merged {
+                hash(spec, &[nodes[i], nodes[i + 1]])
+            } else {
+                let sibling = sibling(level, index ^ 1)?;
+                if index & 1 == 1 {
+                    hash(spec, &[sibling, nodes[i]])
+                } else {
+                    hash(spec, &[nodes[i], sibling])
+                }
+            });
+            parent_firsts.push(firsts[i]);
+            i += if merged { 2 } else { 1 };
+        }
+        (nodes, firsts) = (parents, parent_firsts);
+    }
+    Ok(nodes[0])
+}
+
+/// A sparse Merkle tree over a [`NodeStore`].
+#[derive(Debug)]
+pub struct SparseMerkleTree<F: PrimeField, S, const T: usize, const RATE: usize> {
+    spec: Spec<F, T, RATE>,
+    /// Roots of empty subtrees, by height
+    empty: Vec<F>,
+    store: S,
+}
+
+impl<F, const T: usize, const RATE: usize> SparseMerkleTree<F, MemoryStore<F>, T, RATE>
+where
+    F: PrimeField + FromUniformBytes<64>,
+{
+    /// An empty tree of `depth` levels in memory.
+    pub fn in_memory(spec: Spec<F, T, RATE>, depth: usize) -> Self {
+        Self::open(spec, depth, MemoryStore::default())
+    }
+}
+
+impl<F, S, const T: usize, const RATE: usize> SparseMerkleTree<F, S, T, RATE>
+where
+    F: PrimeField + FromUniformBytes<64>,
+    S: NodeStore<F>,
+{
+    /// The tree of `depth` levels in `store`, empty for an empty store.
+    ///
+    /// # Panics
+    ///
+    /// For depths of 0 or above [`MAX_DEPTH`].
+    pub fn open(spec: Spec<F, T, RATE>, depth: usize, store: S) -> Self {
+        assert!(
+            depth > 0 && depth <= MAX_DEPTH,
+            "depth {depth} is not in 1..={MAX_DEPTH}"
+        );
+        let mut empty = vec![F::ZERO];
+        for i in 0..depth {
+            empty.push(hash(&spec, &[empty[i], empty[i]]));
+        }
+        Self { spec, empty, store }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.empty.len() - 1
+    }
+
+    pub fn spec(&self) -> &Spec<F, T, RATE> {
+        &self.spec
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    pub fn root(&self) -> io::Result<F> {
+        self.node(self.depth(), 0)
+    }
+
+    pub fn get(&self, key: u64) -> Result<Option<F>, SmtError> {
+        self.check_key(key)?;
+        Ok(self.store.value(key)?)
+    }
+
+    /// The proof of the value of `key`, or of its absence.
+    pub fn prove(&self, key: u64) -> Result<SmtProof<F>, SmtError> {
+        Ok(SmtProof {
+            value: self.get(key)?,
+            path: self.path(key)?,
+        })
+    }
+
+    /// Adds a key the tree does not have.
+    pub fn insert(&mut self, key: u64, value: F) -> Result<SmtUpdate<F>, SmtError> {
+        if self.get(key)?.is_some() {
+            return Err(SmtError::Exists(key));
+        }
+        self.set(key, value)
+    }
+
+    /// Changes the value of a key the tree has.
+    pub fn update(&mut self, key: u64, value: F) -> Result<SmtUpdate<F>, SmtError> {
+        if self.get(key)?.is_none() {
+            return Err(SmtError::Missing(key));
+        }
+        self.set(key, value)
+    }
+
+    /// Inserts or updates `key`.
+    pub fn set(&mut self, key: u64, value: F) -> Result<SmtUpdate<F>, SmtError> {
+        let old_value = self.get(key)?;
+        let path = self.path(key)?;
+        let old_root = self.root()?;
+
+        self.store.set_value(key, Some(value))?;
+        let mut node = leaf(&self.spec, key, value);
+        let mut index = key;
+        for (level, sibling) in path.siblings.iter().enumerate() {
+            self.store.set_node(level, index, Some(node))?;
+            node = if index & 1 == 1 {
+                hash(&self.spec, &[*sibling, node])
+            } else {
+                hash(&self.spec, &[node, *sibling])
+            };
+            index >>= 1;
+        }
+        self.store.set_node(self.depth(), 0, Some(node))?;
+
+        Ok(SmtUpdate {
+            old_value,
+            new_value: value,
+            path,
+            old_root,
+            new_root: node,
+        })
+    }
+
+    /// Sets every key of `changes` to its value at once and returns their [`SmtDiff`],
+    /// whose siblings are read before the first change.
+    pub fn apply(&mut self, changes: &[(u64, F)]) -> Result<SmtDiff<F>, SmtError> {
+        let mut changes = changes.to_vec();
+        changes.sort_by_key(|(key, _)| *key);
+        if let Some(pair) = changes.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(SmtError::Duplicate(pair[0].0));
+        }
+        if changes.is_empty() {
+            return Err(SmtError::NoChanges);
+        }
+        let changes = changes
+            .into_iter()
+            .map(|(key, new_value)| {
+                Ok::<_, SmtError>(SmtChange {
+                    key,
+                    old_value: self.get(key)?,
+                    new_value,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let keys = changes.iter().map(|change| change.key).collect::<Vec<_>>();
+        let leaves = changes
+            .iter()
+            .map(|change| {
+                change
+                    .old_value
+                    .map_or(F::ZERO, |value| leaf(&self.spec, change.key, value))
+            })
+            .collect();
+        let mut siblings = Vec::new();
+        let old_root = fold(
+            &self.spec,
+            self.depth(),
+            &keys,
+            &meeting_levels(&keys),
+            leaves,
+            |level, index| {
+                let node = self.node(level, index)?;
+                siblings.push(node);
+                Ok::<_, io::Error>(node)
+            },
+        )?;
+        for change in &changes {
+            self.set(change.key, change.new_value)?;
+        }
+
+        Ok(SmtDiff {
+            depth: self.depth(),
+            changes,
+            siblings,
+            old_root,
+            new_root: self.root()?,
+        })
+    }
+
+    fn check_key(&self, key: u64) -> Result<(), SmtError> {
+        let depth = self.depth();
+        if key >> depth != 0 {
+            return Err(SmtError::KeyOutOfRange { key, depth });
+        }
+        Ok(())
+    }
+
+    fn node(&self, level: usize, index: u64) -> io::Result<F> {
+        Ok(self.store.node(level, index)?.unwrap_or(self.empty[level]))
+    }
+
+    fn path(&self, key: u64) -> Result<MerklePath<F>, SmtError> {
+        self.check_key(key)?;
+        let siblings = (0..self.depth())
+            .map(|level| self.node(level, (key >> level) ^ 1))
+            .collect::<io::Result<_>>()?;
+        Ok(MerklePath {
+            index: key,
+            siblings,
+        })
+    }
+}
+
+/// Checks [`SmtProof`]s, [`SmtUpdate`]s and [`SmtDiff`]s in-circuit, against key cells.
+///
+/// The key is the index of a [`MerkleChip`] over the path, which bounds it by the depth.
+/// The leaf of an absent key is a cell constrained to zero, and an update recomputes the
+/// root twice along the same sibling cells, from the old leaf and from the new one.
+pub struct SmtChip<F: PrimeField, const T: usize, const RATE: usize> {
+    config: MainGateConfig<T>,
+    spec: Spec<F, T, RATE>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> SmtChip<F, T, RATE> {
+    pub fn new(config: MainGateConfig<T>, spec: Spec<F, T, RATE>) -> Self {
+        Self { config, spec }
+    }
+
+    /// Rows used by [`SmtChip::membership`] for a tree of `depth` levels.
+    pub fn membership_rows(spec: &Spec<F, T, RATE>, depth: usize) -> usize {
+        PoseidonChip::num_rows(spec, 2) + MerkleChip::num_rows(spec, depth)
+    }
+
+    /// Rows used by [`SmtChip::non_membership`] for a tree of `depth` levels.
+    pub fn non_membership_rows(spec: &Spec<F, T, RATE>, depth: usize) -> usize {
+        1 + MerkleChip::num_rows(spec, depth)
+    }
+
+    /// Rows used by [`SmtChip::update`] for a tree of `depth` levels.
+    pub fn update_rows(spec: &Spec<F, T, RATE>, depth: usize) -> usize {
+        2 * Self::membership_rows(spec, depth) + 3
+    }
+
+    /// Rows used by [`SmtChip::diff`] for a diff of the shape `shape`, see
+    /// [`SmtDiff::shape`], in a tree of `depth` levels.
+    pub fn diff_rows(spec: &Spec<F, T, RATE>, depth: usize, shape: &[usize]) -> usize {
+        let changes = shape.len() + 1;
+        let hash_rows = PoseidonChip::num_rows(spec, 2);
+        // parents hashed per root: at each level, one per key less one per merged pair
+        let parents = (0..depth)
+            .map(|level| changes - shape.iter().filter(|meet| **meet <= level).count())
+            .sum::<usize>();
+        let swaps = parents - shape.len();
+        changes * (RangeChip::<F, T>::num_rows(depth) + 2 * hash_rows + 3)
+            + shape.len()
+            + 2 * (parents * hash_rows + swaps * 3)
+    }
+
+    /// Constrains `key` to hold `value` and returns the root cell, for the caller to
+    /// constrain to the expected root.
+    pub fn membership(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        key: &AssignedValue<F>,
+        value: &AssignedValue<F>,
+        proof: &SmtProof<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        let value_val = proof.value.unwrap_or(F::ZERO);
+        let (leaf_cell, leaf_val) = self.leaf(ctx, key, value, proof.key(), value_val)?;
+        self.merkle()
+            .compute_root(ctx, &leaf_cell, leaf_val, key, &proof.path)
+    }
+
+    /// Constrains `key` to be absent and returns the root cell.
+    pub fn non_membership(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        key: &AssignedValue<F>,
+        proof: &SmtProof<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        // s[0] = 0
+        let config = &self.config;
+        let empty =
+            ctx.assign_advice(|| "smt: empty leaf", config.state[0], Value::known(F::ZERO))?;
+        ctx.assign_fixed(|| "smt: q_1", config.q_1[0], F::ONE)?;
+        ctx.next();
+        self.merkle()
+            .compute_root(ctx, &empty, F::ZERO, key, &proof.path)
+    }
+
+    /// Constrains setting `key` to `new_value` to change the root and nothing else, and
+    /// returns `(old_root, new_root, inserted)`; `inserted` is one if the key was absent
+    /// before and zero otherwise.
+    pub fn update(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        key: &AssignedValue<F>,
+        new_value: &AssignedValue<F>,
+        update: &SmtUpdate<F>,
+    ) -> Result<(AssignedValue<F>, AssignedValue<F>, AssignedValue<F>), Error> {
+        let (old_leaf, old_leaf_val, e) =
+            self.old_leaf(ctx, key, update.key(), update.old_value)?;
+        let merkle = self.merkle();
+        let (old_root, siblings) =
+            merkle.compute_root_along(ctx, &old_leaf, old_leaf_val, key, &update.path, None)?;
+        let (new_leaf, new_leaf_val) =
+            self.leaf(ctx, key, new_value, update.key(), update.new_value)?;
+        let (new_root, _) = merkle.compute_root_along(
+            ctx,
+            &new_leaf,
+            new_leaf_val,
+            key,
+            &update.path,
+            Some(&siblings),
+        )?;
+        Ok((old_root, new_root, e))
+    }
+
+    /// Constrains the changes of `diff` to change the root and nothing else, and returns
+    /// `(old_root, new_root, inserted)` with an `inserted` flag per change as in
+    /// [`SmtChip::update`]; `keys` and `new_values` are the cells of the changes, in order.
+    ///
+    /// A node on the paths of several keys is hashed once per root instead of once per
+    /// key. The keys are constrained to increase and to meet where [`SmtDiff::shape`] says,
+    /// so the layout depends on the shape, not the keys; a layout for any `n` changes is
+    /// [`SmtChip::update`] `n` times.
+    #[allow(clippy::type_complexity)]
+    pub fn diff(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        keys: &[AssignedValue<F>],
+        new_values: &[AssignedValue<F>],
+        diff: &SmtDiff<F>,
+    ) -> Result<(AssignedValue<F>, AssignedValue<F>, Vec<AssignedValue<F>>), Error> {
+        let shape = diff.shape().ok_or(Error::Synthesis)?;
+        assert_eq!(keys.len(), diff.changes.len());
+        assert_eq!(new_values.len(), diff.changes.len());
+        let config = &self.config;
+        let range_chip = RangeChip::<F, T>::new(config.clone());
+
+        let mut bits = Vec::with_capacity(keys.len());
+        let mut old_leaves = Vec::with_capacity(keys.len());
+        let mut new_leaves = Vec::with_capacity(keys.len());
+        let mut inserted = Vec::with_capacity(keys.len());
+        for ((key, new_value), change) in keys.iter().zip(new_values).zip(&diff.changes) {
+            bits.push(range_chip.range_check(ctx, key, diff.depth)?);
+            let (old_leaf, old_leaf_val, e) =
+                self.old_leaf(ctx, key, change.key, change.old_value)?;
+            old_leaves.push((old_leaf, old_leaf_val));
+            new_leaves.push(self.leaf(ctx, key, new_value, change.key, change.new_value)?);
+            inserted.push(e);
+        }
+
+        // consecutive keys agree above the level they meet at, where the first goes left
+        for (i, level) in shape.iter().enumerate() {
+            for above in level + 1..diff.depth {
+                ctx.constrain_equal(bits[i][above].cell(), bits[i + 1][above].cell())?;
+            }
+            // b' - b - 1 = 0
+            let (left, right) = (&bits[i][*level], &bits[i + 1][*level]);
+            let b =
+                ctx.assign_advice(|| "smt: left bit", config.state[0], left.value().copied())?;
+            ctx.constrain_equal(b.cell(), left.cell())?;
+            let b_next =
+                ctx.assign_advice(|| "smt: right bit", config.state[1], right.value().copied())?;
+            ctx.constrain_equal(b_next.cell(), right.cell())?;
+            ctx.assign_fixed(|| "smt: q_1", config.q_1[0], -F::ONE)?;
+            ctx.assign_fixed(|| "smt: q_1", config.q_1[1], F::ONE)?;
+            ctx.assign_fixed(|| "smt: rc", config.rc, -F::ONE)?;
+            ctx.next();
+        }
+
+        let (old_root, siblings) = self.fold_cells(ctx, diff, &shape, &bits, old_leaves, None)?;
+        let (new_root, _) =
+            self.fold_cells(ctx, diff, &shape, &bits, new_leaves, Some(&siblings))?;
+        Ok((old_root, new_root, inserted))
+    }
+
+    /// The leaf of `key` before a change from `old_value`, zero for an absent key; returns
+    /// it with its value and the `inserted` flag of [`SmtChip::update`].
+    fn old_leaf(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        key: &AssignedValue<F>,
+        key_val: u64,
+        old_value: Option<F>,
+    ) -> Result<(AssignedValue<F>, F, AssignedValue<F>), Error> {
+        let config = &self.config;
+        let inserted_val = if old_value.is_none() { F::ONE } else { F::ZERO };
+        // the value hashed for an absent key is not used
+        let old_val = old_value.unwrap_or(F::ZERO);
+        let old = ctx.assign_advice(|| "smt: old value", config.input, Value::known(old_val))?;
+        ctx.next();
+        let (hashed, hashed_val) = self.leaf(ctx, key, &old, key_val, old_val)?;
+
+        // e * e - e = 0
+        let e = ctx.assign_advice(
+            || "smt: inserted",
+            config.state[0],
+            Value::known(inserted_val),
+        )?;
+        let e_copy = ctx.assign_advice(
+            || "smt: inserted",
+            config.state[1],
+            Value::known(inserted_val),
+        )?;
+        ctx.constrain_equal(e.cell(), e_copy.cell())?;
+        ctx.assign_fixed(|| "smt: q_m", config.q_m, F::ONE)?;
+        ctx.assign_fixed(|| "smt: q_1", config.q_1[0], -F::ONE)?;
+        ctx.next();
+
+        // h - e * h - old_leaf = 0
+        let old_leaf_val = if old_value.is_some() {
+            hashed_val
+        } else {
+            F::ZERO
+        };
+        let ec = ctx.assign_advice(
+            || "smt: inserted",
+            config.state[0],
+            Value::known(inserted_val),
+        )?;
+        ctx.constrain_equal(ec.cell(), e.cell())?;
+        let h = ctx.assign_advice(
+            || "smt: old leaf",
+            config.state[1],
+            Value::known(hashed_val),
+        )?;
+        ctx.constrain_equal(h.cell(), hashed.cell())?;
+        ctx.assign_fixed(|| "smt: q_m", config.q_m, -F::ONE)?;
+        ctx.assign_fixed(|| "smt: q_1", config.q_1[1], F::ONE)?;
+        ctx.assign_fixed(|| "smt: q_o", config.q_o, -F::ONE)?;
+        let old_leaf =
+            ctx.assign_advice(|| "smt: old leaf", config.out, Value::known(old_leaf_val))?;
+        ctx.next();
+        Ok((old_leaf, old_leaf_val, e))
+    }
+
+    /// [`fold`] in-circuit, swapping each node with its sibling along the bits of its first
+    /// key; returns the root and the sibling cells, which `siblings` gives for a second fold.
+    #[allow(clippy::type_complexity)]
+    fn fold_cells(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        diff: &SmtDiff<F>,
+        shape: &[usize],
+        bits: &[Vec<AssignedValue<F>>],
+        mut nodes: Vec<(AssignedValue<F>, F)>,
+        siblings: Option<&[AssignedValue<F>]>,
+    ) -> Result<(AssignedValue<F>, Vec<AssignedValue<F>>), Error> {
+        let merkle = self.merkle();
+        let keys = diff.keys();
+        let mut sibling_vals = diff.siblings.iter();
+        let mut sibling_cells = Vec::with_capacity(diff.siblings.len());
+        let mut firsts = (0..keys.len()).collect::<Vec<_>>();
+        for level in 0..diff.depth {
+            let (mut parents, mut parent_firsts) = (Vec::new(), Vec::new());
+            let mut i = 0;
+            while i < nodes.len() {
+                let first = firsts[i];
+                let merged = i + 1 < nodes.len() && shape[firsts[i + 1] - 1] == level;
+                let (left, right) = if merged {
+                    (nodes[i].clone(), nodes[i + 1].clone())
+                } else {
+                    let (x, x_val) = &nodes[i];
+                    let y_val = *sibling_vals.next().ok_or(Error::Synthesis)?;
+                    let given = siblings.map(|siblings| &siblings[sibling_cells.len()]);
+                    let swapped = (keys[first] >> level) & 1 == 1;
+                    let bit = &bits[first][level];
+                    let (left, right, y) =
+                        merkle.swap(ctx, x, *x_val, y_val, given, bit, swapped)?;
+                    sibling_cells.push(y);
+                    if swapped {
+                        ((left, y_val), (right, *x_val))
+                    } else {
+                        ((left, *x_val), (right, y_val))
+                    }
+                };
+                parents.push(self.parent(ctx, left, right)?);
+                parent_firsts.push(first);
+                i += if merged { 2 } else { 1 };
+            }
+            (nodes, firsts) = (parents, parent_firsts);
+        }
+        if sibling_vals.next().is_some() {
+            return Err(Error::Synthesis);
+        }
+        Ok((nodes.swap_remove(0).0, sibling_cells))
+    }
+
+    /// Hashes `left` and `right` into their parent.
+    fn parent(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        (left, left_val): (AssignedValue<F>, F),
+        (right, right_val): (AssignedValue<F>, F),
+    ) -> Result<(AssignedValue<F>, F), Error> {
+        let mut pchip = PoseidonChip::new(self.config.clone(), self.spec.clone());
+        pchip.update(vec![left_val, right_val]);
+        let (inputs, parent) = pchip.squeeze_with_inputs(ctx)?;
+        ctx.constrain_equal(inputs[0].cell(), left.cell())?;
+        ctx.constrain_equal(inputs[1].cell(), right.cell())?;
+        Ok((parent, hash(&self.spec, &[left_val, right_val])))
+    }
+
+    fn merkle(&self) -> MerkleChip<F, T, RATE> {
+        MerkleChip::new(self.config.clone(), self.spec.clone())
+    }
+
+    /// Hashes the leaf of `key` holding `value`.
+    fn leaf(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        key: &AssignedValue<F>,
+        value: &AssignedValue<F>,
+        key_val: u64,
+        value_val: F,
+    ) -> Result<(AssignedValue<F>, F), Error> {
+        let mut pchip = PoseidonChip::new(self.config.clone(), self.spec.clone());
+        pchip.update(vec![F::from(key_val), value_val]);
+        let (inputs, leaf_cell) = pchip.squeeze_with_inputs(ctx)?;
+        ctx.constrain_equal(inputs[0].cell(), key.cell())?;
+        ctx.constrain_equal(inputs[1].cell(), value.cell())?;
+        Ok((leaf_cell, leaf(&self.spec, key_val, value_val)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+    };
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{main_gate::MainGate, merkle::MerkleTree};
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+    const K: u32 = 12;
+    const DEPTH: usize = 4;
+
+    fn spec() -> Spec<Fr, T, RATE> {
+        Spec::new(8, 56)
+    }
+
+    fn tree() -> SparseMerkleTree<Fr, MemoryStore<Fr>, T, RATE> {
+        let mut tree = SparseMerkleTree::in_memory(spec(), DEPTH);
+        for (key, value) in [(3, 30), (9, 90), (10, 100)] {
+            tree.insert(key, Fr::from(value)).unwrap();
+        }
+        tree
+    }
+
+    #[derive(Clone)]
+    enum Op {
+        Member(SmtProof<Fr>),
+        Absent(SmtProof<Fr>),
+        Update(SmtUpdate<Fr>),
+    }
+
+    /// Runs one gadget of [`SmtChip`] on free `key` and `value` cells and exposes the cells
+    /// it returns.
+    struct SmtCircuit {
+        key: u64,
+        value: Fr,
+        op: Op,
+    }
+
+    impl Circuit<Fr> for SmtCircuit {
+        type Config = (MainGateConfig<T>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                key: self.key,
+                value: self.value,
+                op: self.op.clone(),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let config = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            (config, instance)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = SmtChip::new(config.clone(), spec());
+            let cells = layouter.assign_region(
+                || "smt",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    // free cells: every selector of the main gate is zero on this row
+                    let key = ctx.assign_advice(
+                        || "key",
+                        config.state[0],
+                        Value::known(Fr::from(self.key)),
+                    )?;
+                    let value =
+                        ctx.assign_advice(|| "value", config.state[1], Value::known(self.value))?;
+                    ctx.next();
+                    Ok(match &self.op {
+                        Op::Member(proof) => vec![chip.membership(ctx, &key, &value, proof)?],
+                        Op::Absent(proof) => vec![chip.non_membership(ctx, &key, proof)?],
+                        Op::Update(update) => {
+                            let (old_root, new_root, inserted) =
+                                chip.update(ctx, &key, &value, update)?;
+                            vec![old_root, new_root, inserted]
+                        }
+                    })
+                },
+            )?;
+            for (row, cell) in cells.iter().enumerate() {
+                layouter.constrain_instance(cell.cell(), instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Runs [`SmtChip::diff`] on free key and value cells and exposes the roots and flags.
+    struct DiffCircuit {
+        keys: Vec<u64>,
+        values: Vec<Fr>,
+        diff: SmtDiff<Fr>,
+    }
+
+    impl Circuit<Fr> for DiffCircuit {
+        type Config = (MainGateConfig<T>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                keys: self.keys.clone(),
+                values: self.values.clone(),
+                diff: self.diff.clone(),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            SmtCircuit::configure(meta)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = SmtChip::new(config.clone(), spec());
+            let cells = layouter.assign_region(
+                || "smt diff",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let (mut keys, mut values) = (Vec::new(), Vec::new());
+                    for (key, value) in self.keys.iter().zip(&self.values) {
+                        let key = Value::known(Fr::from(*key));
+                        keys.push(ctx.assign_advice(|| "key", config.state[0], key)?);
+                        let value = Value::known(*value);
+                        values.push(ctx.assign_advice(|| "value", config.state[1], value)?);
+                        ctx.next();
+                    }
+                    let (old_root, new_root, inserted) =
+                        chip.diff(ctx, &keys, &values, &self.diff)?;
+                    Ok([old_root, new_root]
+                        .into_iter()
+                        .chain(inserted)
+                        .collect::<Vec<_>>())
+                },
+            )?;
+            for (row, cell) in cells.iter().enumerate() {
+                layouter.constrain_instance(cell.cell(), instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn verify(circuit: &SmtCircuit, instance: Vec<Fr>) -> bool {
+        let prover = MockProver::run(K, circuit, vec![instance]).unwrap();
+        prover.verify().is_ok()
+    }
+
+    #[test]
+    fn test_native() {
+        let spec = spec();
+        let mut tree = tree();
+        let empty = SparseMerkleTree::<Fr, _, T, RATE>::in_memory(spec.clone(), DEPTH);
+        assert_eq!(
+            empty.root().unwrap(),
+            MerkleTree::new(&spec, Vec::new(), DEPTH).root()
+        );
+
+        // the root of the dense tree of the same leaves
+        let mut leaves = vec![Fr::ZERO; 1 << DEPTH];
+        for (key, value) in [(3, 30), (9, 90), (10, 100)] {
+            leaves[key as usize] = leaf(&spec, key, Fr::from(value));
+        }
+        let root = tree.root().unwrap();
+        assert_eq!(root, MerkleTree::new(&spec, leaves, DEPTH).root());
+
+        let proof = tree.prove(9).unwrap();
+        assert_eq!((proof.key(), proof.value), (9, Some(Fr::from(90))));
+        assert_eq!(proof.root(&spec), root);
+        let absent = tree.prove(4).unwrap();
+        assert_eq!(absent.value, None);
+        assert_eq!(absent.root(&spec), root);
+
+        let update = tree.update(9, Fr::from(91)).unwrap();
+        assert!(update.verify(&spec));
+        assert_eq!(
+            (update.old_root, update.old_value),
+            (root, Some(Fr::from(90)))
+        );
+        assert_eq!(update.new_root, tree.root().unwrap());
+        assert_eq!(tree.get(9).unwrap(), Some(Fr::from(91)));
+        let insert = tree.insert(4, Fr::from(40)).unwrap();
+        assert!(insert.verify(&spec));
+        assert_eq!(insert.old_value, None);
+        assert!(!SmtUpdate {
+            new_root: insert.old_root,
+            ..insert
+        }
+        .verify(&spec));
+
+        assert!(matches!(tree.insert(4, Fr::ONE), Err(SmtError::Exists(4))));
+        assert!(matches!(tree.update(5, Fr::ONE), Err(SmtError::Missing(5))));
+        assert!(matches!(
+            tree.get(16),
+            Err(SmtError::KeyOutOfRange { key: 16, depth: 4 })
+        ));
+    }
+
+    #[test]
+    fn test_store() {
+        let tree = tree();
+        let root = tree.root().unwrap();
+        let store = tree.into_store();
+        // the leaf and its ancestors but the root, for each key, and the root
+        assert!(store.nodes() <= 3 * DEPTH + 1);
+        assert_eq!(
+            store.values().map(|(key, _)| key).collect::<Vec<_>>(),
+            [3, 9, 10]
+        );
+
+        let mut reopened = SparseMerkleTree::open(spec(), DEPTH, store);
+        assert_eq!(reopened.root().unwrap(), root);
+        assert_eq!(reopened.get(10).unwrap(), Some(Fr::from(100)));
+        let update = reopened.set(10, Fr::from(101)).unwrap();
+        assert_eq!(update.old_root, root);
+        assert_ne!(reopened.root().unwrap(), root);
+    }
+
+    #[test]
+    fn test_gadgets() {
+        let spec = spec();
+        assert!(SmtChip::update_rows(&spec, DEPTH) + 6 <= 1 << K);
+        let mut tree = tree();
+        let root = tree.root().unwrap();
+
+        let member = |key, value: u64, proof| SmtCircuit {
+            key,
+            value: Fr::from(value),
+            op: Op::Member(proof),
+        };
+        let proof = tree.prove(9).unwrap();
+        assert!(verify(&member(9, 90, proof.clone()), vec![root]));
+        assert!(!verify(&member(9, 91, proof.clone()), vec![root]));
+        // the path of key 9 does not lead to the root from key 8
+        assert!(!verify(&member(8, 90, proof), vec![root]));
+
+        let absent = |key, proof| SmtCircuit {
+            key,
+            value: Fr::ZERO,
+            op: Op::Absent(proof),
+        };
+        assert!(verify(&absent(4, tree.prove(4).unwrap()), vec![root]));
+        // key 3 holds a value, so its slot does not hash to the root when empty
+        let proof = SmtProof {
+            value: None,
+            ..tree.prove(3).unwrap()
+        };
+        assert!(!verify(&absent(3, proof), vec![root]));
+
+        for (key, value, inserted) in [(9, 91, false), (4, 40, true)] {
+            let update = tree.set(key, Fr::from(value)).unwrap();
+            let circuit = SmtCircuit {
+                key,
+                value: Fr::from(value),
+                op: Op::Update(update.clone()),
+            };
+            let flag = Fr::from(inserted as u64);
+            let instance = vec![update.old_root, update.new_root, flag];
+            assert!(verify(&circuit, instance), "key {key}");
+            // claiming the other case changes the old leaf
+            let old_value = if inserted { Some(Fr::ZERO) } else { None };
+            let circuit = SmtCircuit {
+                op: Op::Update(SmtUpdate {
+                    old_value,
+                    ..update.clone()
+                }),
+                ..circuit
+            };
+            let instance = vec![update.old_root, update.new_root, Fr::ONE - flag];
+            assert!(!verify(&circuit, instance), "key {key}");
+            let instance = vec![update.old_root, update.new_root + Fr::ONE, flag];
+            assert!(!verify(&circuit, instance), "key {key}");
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let spec = spec();
+        let mut tree = tree();
+        let mut sequential = tree();
+        let root = tree.root().unwrap();
+        let changes = [(9, 91), (4, 40), (5, 50), (3, 31), (15, 150)]
+            .map(|(key, value)| (key, Fr::from(value)));
+
+        let diff = tree.apply(&changes).unwrap();
+        for (key, value) in changes {
+            sequential.set(key, value).unwrap();
+        }
+        assert_eq!(diff.old_root, root);
+        assert_eq!(diff.new_root, sequential.root().unwrap());
+        assert_eq!(tree.root().unwrap(), diff.new_root);
+        assert!(diff.verify(&spec));
+        assert_eq!(diff.keys(), [3, 4, 5, 9, 15]);
+        assert_eq!(diff.changes[1].old_value, None);
+        assert_eq!(diff.changes[3].old_value, Some(Fr::from(90)));
+        // 3, 4 and 5 meet below the root, 4 and 5 at the leaves
+        assert_eq!(diff.shape().unwrap(), [2, 0, 3, 2]);
+        assert!(diff.siblings.len() < changes.len() * DEPTH);
+
+        let mut tampered = diff.clone();
+        tampered.siblings[0] += Fr::ONE;
+        assert!(!tampered.verify(&spec));
+        let mut tampered = diff.clone();
+        tampered.siblings.push(Fr::ZERO);
+        assert!(!tampered.verify(&spec));
+        let mut tampered = diff.clone();
+        tampered.changes.swap(0, 1);
+        assert_eq!(tampered.roots(&spec), None);
+
+        assert!(matches!(
+            tree.apply(&[(1, Fr::ONE), (1, Fr::ZERO)]),
+            Err(SmtError::Duplicate(1))
+        ));
+        assert!(matches!(tree.apply(&[]), Err(SmtError::NoChanges)));
+        assert!(matches!(
+            tree.apply(&[(16, Fr::ONE)]),
+            Err(SmtError::KeyOutOfRange { key: 16, depth: 4 })
+        ));
+        assert_eq!(tree.root().unwrap(), diff.new_root);
+    }
+
+    #[test]
+    fn test_diff_gadget() {
+        const K: u32 = 15;
+        let spec = spec();
+        let mut tree = tree();
+        let diff = tree
+            .apply(&[(3, 31), (4, 40), (5, 50), (9, 91)].map(|(k, v)| (k, Fr::from(v))))
+            .unwrap();
+        let shape = diff.shape().unwrap();
+        let rows = SmtChip::diff_rows(&spec, DEPTH, &shape);
+        assert!(rows + 4 <= 1 << K);
+        assert!(rows < 4 * SmtChip::update_rows(&spec, DEPTH));
+
+        let circuit = DiffCircuit {
+            keys: diff.keys(),
+            values: diff.changes.iter().map(|change| change.new_value).collect(),
+            diff: diff.clone(),
+        };
+        let flags = [0, 1, 1, 0].map(Fr::from);
+        let instance = [diff.old_root, diff.new_root].into_iter().chain(flags);
+        let run = |circuit: &DiffCircuit, instance: Vec<Fr>| {
+            let prover = MockProver::run(K, circuit, vec![instance]).unwrap();
+            prover.verify().is_ok()
+        };
+        assert!(run(&circuit, instance.clone().collect()));
+
+        let mut forged = instance.clone().collect::<Vec<_>>();
+        forged[1] += Fr::ONE;
+        assert!(!run(&circuit, forged));
+        let mut values = circuit.values.clone();
+        values[2] = Fr::from(51);
+        let wrong = DiffCircuit {
+            values,
+            ..circuit.without_witnesses()
+        };
+        assert!(!run(&wrong, instance.clone().collect()));
+        // key 2 meets key 4 where key 3 does, but its slot is not the one changed
+        let mut keys = circuit.keys.clone();
+        keys[0] = 2;
+        let moved = DiffCircuit {
+            keys,
+            ..circuit.without_witnesses()
+        };
+        assert!(!run(&moved, instance.collect()));
+    }
+}
@@ -0,0 +1,351 @@
+//! Side-by-side measurements of every vetted spec, to pick a width with data from this
+//! codebase rather than from papers.
+//!
+//! [`benchmark`] hashes the same message under a [`SupportedConfig`] natively and in
+//! [`TestCircuit`], with the smallest `k` the circuit fits in, and records the rows, the
+//! proof size and how long the witness, the proof and its verification took. All configs of
+//! [`SUPPORTED`] have round numbers for 128-bit security, so rows compare like for like.
+//!
+//! [`benchmark_poseidon2`] measures [`crate::poseidon2`] at the widths it is implemented
+//! for on the same message, and [`benchmark_all`] puts both permutations into one
+//! [`ComparisonReport`], told apart by its `permutation` column. There is no Rescue
+//! implementation in this crate to compare with.
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use ff::Field;
+use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner},
+    plonk::{Circuit, Column, ConstraintSystem, Error, Instance},
+};
+use halo2curves::bn256::Fr;
+use poseidon::Spec;
+use serde::Serialize;
+
+use crate::{
+    limits::UNUSABLE_ROWS,
+    main_gate::RegionCtx,
+    poseidon2::{self, Poseidon2Spec},
+    poseidon2_circuit::{Poseidon2Chip, Poseidon2Config},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash,
+    prover::ProverContext,
+    specs::{SupportedConfig, SUPPORTED},
+    test_circuit::TestCircuit,
+};
+
+/// Measurements of one spec hashing one message.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SpecBenchmark {
+    pub permutation: &'static str,
+    pub field: &'static str,
+    pub width: usize,
+    pub rate: usize,
+    pub r_f: usize,
+    pub r_p: usize,
+    /// Elements in the hashed message
+    pub len: usize,
+    /// Rows the hash takes in the circuit
+    pub rows: usize,
+    pub k: u32,
+    pub proof_bytes: usize,
+    /// Native hash of the message, the witness of the public input
+    pub witness: Duration,
+    pub prove: Duration,
+    pub verify: Duration,
+}
+
+#[derive(Debug)]
+pub enum BenchError {
+    /// The width has no circuit instantiation
+    Unsupported(usize),
+    Plonk(Error),
+}
+
+impl fmt::Display for BenchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported(width) => write!(f, "no circuit for width {width}"),
+            Self::Plonk(err) => write!(f, "benchmark failed: {err:?}"),
+        }
+    }
+}
+
+impl std::error::Error for BenchError {}
+
+/// Measures `config` hashing a message of `len` elements.
+pub fn benchmark(config: &SupportedConfig, len: usize) -> Result<SpecBenchmark, BenchError> {
+    // only the widths of the enabled specs are instantiated
+    match config.width {
+        #[cfg(feature = "bn256-t2")]
+        2 => run::<2, 1>(config, len),
+        #[cfg(any(feature = "bn256-t3", feature = "pasta"))]
+        3 => run::<3, 2>(config, len),
+        4 => run::<4, 3>(config, len),
+        #[cfg(feature = "bn256-t5")]
+        5 => run::<5, 4>(config, len),
+        #[cfg(feature = "bn256-t6")]
+        6 => run::<6, 5>(config, len),
+        #[cfg(feature = "bn256-t7")]
+        7 => run::<7, 6>(config, len),
+        #[cfg(feature = "bn256-t8")]
+        8 => run::<8, 7>(config, len),
+        #[cfg(feature = "bn256-t9")]
+        9 => run::<9, 8>(config, len),
+        width => Err(BenchError::Unsupported(width)),
+    }
+}
+
+/// Measures Poseidon2 over bn256 with [`poseidon2::R_F`] and [`poseidon2::R_P`], at widths
+/// 2 and 3, hashing a message of `len` elements.
+pub fn benchmark_poseidon2(len: usize) -> Result<Vec<SpecBenchmark>, BenchError> {
+    Ok(vec![
+        run_poseidon2::<2, 1>(len)?,
+        run_poseidon2::<3, 2>(len)?,
+    ])
+}
+
+/// Measures every config of [`SUPPORTED`] and then Poseidon2 on the same message.
+pub fn benchmark_all(len: usize) -> Result<ComparisonReport, BenchError> {
+    let mut rows = SUPPORTED
+        .iter()
+        .map(|config| benchmark(config, len))
+        .collect::<Result<Vec<_>, _>>()?;
+    rows.extend(benchmark_poseidon2(len)?);
+    Ok(ComparisonReport(rows))
+}
+
+fn run<const T: usize, const RATE: usize>(
+    config: &SupportedConfig,
+    len: usize,
+) -> Result<SpecBenchmark, BenchError> {
+    let spec = Spec::<Fr, T, RATE>::new(config.r_f, config.r_p);
+    let inputs = (0..len as u64).map(Fr::from).collect::<Vec<_>>();
+    let rows = PoseidonChip::num_rows(&spec, len);
+
+    let start = Instant::now();
+    let digest = hash(&spec, &inputs);
+    let witness = start.elapsed();
+
+    let circuit = TestCircuit::<Fr, T, RATE>::with_spec(inputs, config.r_f, config.r_p);
+    let proven = prove(&circuit, rows, digest)?;
+    Ok(SpecBenchmark {
+        permutation: "poseidon",
+        field: config.field,
+        width: T,
+        rate: RATE,
+        r_f: config.r_f,
+        r_p: config.r_p,
+        len,
+        rows,
+        k: proven.k,
+        proof_bytes: proven.proof_bytes,
+        witness,
+        prove: proven.prove,
+        verify: proven.verify,
+    })
+}
+
+fn run_poseidon2<const T: usize, const RATE: usize>(
+    len: usize,
+) -> Result<SpecBenchmark, BenchError> {
+    let spec = Poseidon2Spec::<Fr, T>::new(poseidon2::R_F, poseidon2::R_P);
+    let inputs = (0..len as u64).map(Fr::from).collect::<Vec<_>>();
+    let rows = Poseidon2Chip::<Fr, T, RATE>::num_rows(&spec, len);
+
+    let start = Instant::now();
+    let digest = poseidon2::hash(&spec, &inputs);
+    let witness = start.elapsed();
+
+    let circuit = Poseidon2Circuit::<T, RATE> { inputs };
+    let proven = prove(&circuit, rows, digest)?;
+    Ok(SpecBenchmark {
+        permutation: "poseidon2",
+        field: "bn256::Fr",
+        width: T,
+        rate: RATE,
+        r_f: spec.r_f(),
+        r_p: spec.r_p(),
+        len,
+        rows,
+        k: proven.k,
+        proof_bytes: proven.proof_bytes,
+        witness,
+        prove: proven.prove,
+        verify: proven.verify,
+    })
+}
+
+/// What [`prove`] measured.
+struct Proven {
+    k: u32,
+    proof_bytes: usize,
+    prove: Duration,
+    verify: Duration,
+}
+
+/// Generates keys for `circuit` at the smallest `k` its `rows` fit into, then proves that it
+/// hashes to `digest` and verifies the proof.
+fn prove<C: Circuit<Fr>>(circuit: &C, rows: usize, digest: Fr) -> Result<Proven, BenchError> {
+    let k = (rows + UNUSABLE_ROWS).next_power_of_two().trailing_zeros();
+    let ctx = ProverContext::setup(k, circuit).map_err(BenchError::Plonk)?;
+    let instances: &[&[Fr]] = &[&[digest]];
+    let start = Instant::now();
+    let proof = ctx.prove(circuit, instances).map_err(BenchError::Plonk)?;
+    let prove = start.elapsed();
+    let start = Instant::now();
+    ctx.verify(&proof, instances).map_err(BenchError::Plonk)?;
+    let verify = start.elapsed();
+    Ok(Proven {
+        k,
+        proof_bytes: proof.len(),
+        prove,
+        verify,
+    })
+}
+
+/// The Poseidon2 hash of `inputs` as its public input, the counterpart of [`TestCircuit`].
+struct Poseidon2Circuit<const T: usize, const RATE: usize> {
+    inputs: Vec<Fr>,
+}
+
+impl<const T: usize, const RATE: usize> Circuit<Fr> for Poseidon2Circuit<T, RATE> {
+    type Config = (Poseidon2Config<T, RATE>, Column<Instance>);
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            inputs: vec![Fr::ZERO; self.inputs.len()],
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let instance = meta.instance_column();
+        meta.enable_equality(instance);
+        let adv_cols = (0..T + RATE)
+            .map(|_| meta.advice_column())
+            .collect::<Vec<_>>();
+        let fix_cols = (0..T + 1 + RATE)
+            .map(|_| meta.fixed_column())
+            .collect::<Vec<_>>();
+        let config =
+            Poseidon2Chip::configure(meta, &mut adv_cols.into_iter(), &mut fix_cols.into_iter());
+        (config, instance)
+    }
+
+    fn synthesize(
+        &self,
+        (config, instance): Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let spec = Poseidon2Spec::new(poseidon2::R_F, poseidon2::R_P);
+        let mut chip = Poseidon2Chip::<Fr, T, RATE>::new(config, spec);
+        chip.update(self.inputs.clone());
+        let digest = layouter.assign_region(
+            || "poseidon2 hash",
+            |region| {
+                let ctx = &mut RegionCtx::new(region, 0);
+                chip.squeeze(ctx)
+            },
+        )?;
+        layouter.constrain_instance(digest.cell(), instance, 0)
+    }
+}
+
+/// Benchmarks of several specs, printed as a table.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct ComparisonReport(pub Vec<SpecBenchmark>);
+
+impl ComparisonReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("benchmarks serialize")
+    }
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<11} {:<10} {:>2} {:>4} {:>3} {:>3} {:>6} {:>2} {:>6} {:>10} {:>10} {:>10}",
+            "permutation",
+            "field",
+            "t",
+            "rate",
+            "r_f",
+            "r_p",
+            "rows",
+            "k",
+            "proof",
+            "witness",
+            "prove",
+            "verify"
+        )?;
+        for b in &self.0 {
+            writeln!(
+                f,
+                "{:<11} {:<10} {:>2} {:>4} {:>3} {:>3} {:>6} {:>2} {:>6} {:>10} {:>10} {:>10}",
+                b.permutation,
+                b.field,
+                b.width,
+                b.rate,
+                b.r_f,
+                b.r_p,
+                b.rows,
+                b.k,
+                b.proof_bytes,
+                format!("{:.1?}", b.witness),
+                format!("{:.1?}", b.prove),
+                format!("{:.1?}", b.verify)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "bn256-t3")]
+    #[test]
+    fn test_benchmark() {
+        let config = SUPPORTED.iter().find(|c| c.width == 3).unwrap();
+        let result = benchmark(config, 4).unwrap();
+        assert_eq!((result.width, result.rate), (3, 2));
+        // three permutations of r_f + r_p rounds plus the input round, three rows each
+        assert_eq!(result.rows, 3 * (1 + 8 + 57) * 3);
+        assert!(1usize << result.k >= result.rows + UNUSABLE_ROWS);
+        assert!(result.proof_bytes > 0);
+
+        let poseidon2 = benchmark_poseidon2(4).unwrap();
+        assert_eq!(
+            poseidon2
+                .iter()
+                .map(|b| (b.width, b.rate))
+                .collect::<Vec<_>>(),
+            [(2, 1), (3, 2)]
+        );
+        // a row per round instead of a row per state element and round
+        assert!(poseidon2[1].rows < result.rows);
+        assert!(poseidon2.iter().all(|b| b.proof_bytes > 0));
+
+        let report = ComparisonReport(vec![result, poseidon2[1].clone()]);
+        let table = report.to_string();
+        assert_eq!(table.lines().count(), 3);
+        assert!(table.lines().nth(1).unwrap().starts_with("poseidon "));
+        assert!(table.lines().nth(2).unwrap().starts_with("poseidon2"));
+        assert!(report.to_json().contains("\"proof_bytes\""));
+
+        let unsupported = SupportedConfig {
+            width: 10,
+            ..*config
+        };
+        assert!(matches!(
+            benchmark(&unsupported, 1),
+            Err(BenchError::Unsupported(10))
+        ));
+    }
+}
@@ -0,0 +1,480 @@
+//! Registry of the Poseidon specs compiled into this crate.
+//!
+//! Orchestration services call [`available`] to learn what a prover binary supports instead
+//! of hardcoding it; every spec used by a circuit in this crate is listed here.
+//!
+//! [`SUPPORTED`] is the wider matrix of `(field, width, α)` combinations whose round numbers
+//! have been evaluated for 128-bit security; [`checked_spec`] refuses to build anything
+//! else, since the constants of an arbitrary combination are generated just as happily.
+//! Apart from bn256 at width 4, every combination is behind a cargo feature of its own,
+//! `bn256-t2`, `bn256-t3`, `bn256-t5` to `bn256-t9` and `pasta`, so embedders only compile
+//! the widths they use; the default features are `bn256-t3` and `pasta`, and `all-specs` turns
+//! on every one.
+use std::fmt;
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2curves::bn256::Fr;
+use poseidon::Spec;
+use serde::Serialize;
+
+/// Which element of the final sponge state a hash outputs.
+///
+/// Every hash in this crate absorbs into `state[1..]` and keeps `state[0]` as capacity, but
+/// implementations disagree on the element they output. Picking the index here lets a tree
+/// or commitment interoperate without reordering states after the fact; the constants of
+/// the spec must of course match as well.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct DigestIndex(pub usize);
+
+impl DigestIndex {
+    /// The first rate element, as in this crate and the PSE sponge
+    pub const PSE: Self = Self(1);
+    /// The capacity element, as in circomlib and its ports
+    pub const CIRCOM: Self = Self(0);
+}
+
+impl Default for DigestIndex {
+    fn default() -> Self {
+        Self::PSE
+    }
+}
+
+/// The capacity element a sponge starts from, keeping hashes of different kinds apart.
+///
+/// Follows the domain tags of the Poseidon paper (section 4.2): a hash of a message of
+/// `len` elements squeezing `outputs` elements starts from `len * 2^64 + outputs - 1`, so
+/// messages of different lengths never share a sponge even if their padded forms agree.
+/// Protocols hashing several kinds of messages give each its own [`Domain::User`] tag, which
+/// no length tag reaches; the schemes of this crate use [`Domain::Constant`] tags, which no
+/// other tag reaches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum Domain {
+    /// `2^64`, the capacity of the PSE sponge and of every hash in this crate so far; the
+    /// same as a constant length domain of one element and one output
+    #[default]
+    Pse,
+    ConstantLength {
+        len: usize,
+        outputs: usize,
+    },
+    /// `2^193 + tag`, above every capacity of the other domains, for the schemes of this
+    /// crate such as [`crate::commitment::COMMITMENT_DOMAIN`]; needs a field of more than 194
+    /// bits, as every supported one is
+    Constant(u128),
+    /// `(tag + 1) * 2^128`, between the length tags and the constant ones, for application
+    /// tags; needs a field of more than 192 bits, as every supported one is
+    User(u64),
+}
+
+impl Domain {
+    pub fn capacity<F: PrimeField>(&self) -> F {
+        match *self {
+            Self::Pse => F::from_u128(1 << 64),
+            Self::ConstantLength { len, outputs } => {
+                assert!(outputs > 0, "a hash squeezes at least one element");
+                F::from_u128(((len as u128) << 64) + (outputs as u128 - 1))
+            }
+            Self::Constant(tag) => {
+                assert!(
+                    F::NUM_BITS > 194,
+                    "constant tags need a field of more than 194 bits"
+                );
+                F::from_u128(1 << 65) * F::from_u128(1 << 64).square() + F::from_u128(tag)
+            }
+            Self::User(tag) => {
+                assert!(
+                    F::NUM_BITS > 192,
+                    "user tags need a field of more than 192 bits"
+                );
+                F::from_u128(tag as u128 + 1) * F::from_u128(1 << 64).square()
+            }
+        }
+    }
+}
+
+/// Static description of a compiled-in spec.
+#[derive(Clone, Copy, Debug)]
+pub struct SpecParams {
+    pub id: &'static str,
+    pub field: &'static str,
+    pub width: usize,
+    pub rate: usize,
+    pub r_f: usize,
+    pub r_p: usize,
+    /// State element the hashes of this spec output
+    pub digest_index: DigestIndex,
+    /// First hard fork whose tasks are proven with this spec
+    pub introduced_in: &'static str,
+    constants_hash: fn(usize, usize) -> String,
+}
+
+/// bn256 scalar field, width 4, rate 3: the spec of [`crate::test_circuit::TestCircuit`].
+pub const BN256_T4_R3: SpecParams = SpecParams {
+    id: "bn256-t4-r3",
+    field: "bn256::Fr",
+    width: 4,
+    rate: 3,
+    r_f: 8,
+    r_p: 56,
+    digest_index: DigestIndex::PSE,
+    introduced_in: "genesis",
+    constants_hash: constants_hash::<Fr, 4, 3>,
+};
+
+/// Every spec compiled into the crate, oldest first.
+pub const SPECS: &[SpecParams] = &[BN256_T4_R3];
+
+/// The S-box exponent of every spec in this crate.
+pub const ALPHA: u64 = 5;
+
+/// A vetted `(field, width, α)` combination and its round numbers.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SupportedConfig {
+    pub field: &'static str,
+    #[serde(skip)]
+    pub modulus: &'static str,
+    pub width: usize,
+    pub alpha: u64,
+    pub r_f: usize,
+    pub r_p: usize,
+}
+
+const BN256_MODULUS: &str = "0x30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000001";
+
+const fn bn256(width: usize, r_p: usize) -> SupportedConfig {
+    SupportedConfig {
+        field: "bn256::Fr",
+        modulus: BN256_MODULUS,
+        width,
+        alpha: ALPHA,
+        r_f: 8,
+        r_p,
+    }
+}
+
+/// Base field of the Pallas curve, the scalar field of Vesta.
+pub const PALLAS_MODULUS: &str =
+    "0x40000000000000000000000000000000224698fc094cf91b992d30ed00000001";
+/// Base field of the Vesta curve, the scalar field of Pallas.
+pub const VESTA_MODULUS: &str =
+    "0x40000000000000000000000000000000224698fc0994a8dd8c46eb2100000001";
+
+/// The width 3 instance of halo2_gadgets' `P128Pow5T3` over a Pasta field.
+#[cfg(feature = "pasta")]
+const fn pasta(field: &'static str, modulus: &'static str) -> SupportedConfig {
+    SupportedConfig {
+        field,
+        modulus,
+        width: 3,
+        alpha: ALPHA,
+        r_f: 8,
+        r_p: 56,
+    }
+}
+
+/// Every vetted combination with `α = 5`: bn256 with the round numbers of the reference
+/// parameter script of the Poseidon paper, and the Pasta fields at the width and rounds
+/// halo2_gadgets uses, see [`crate::pasta`].
+///
+/// Only the combinations of the enabled features are listed.
+pub const SUPPORTED: &[SupportedConfig] = &[
+    #[cfg(feature = "bn256-t2")]
+    bn256(2, 56),
+    #[cfg(feature = "bn256-t3")]
+    bn256(3, 57),
+    bn256(4, 56),
+    #[cfg(feature = "bn256-t5")]
+    bn256(5, 60),
+    #[cfg(feature = "bn256-t6")]
+    bn256(6, 60),
+    #[cfg(feature = "bn256-t7")]
+    bn256(7, 63),
+    #[cfg(feature = "bn256-t8")]
+    bn256(8, 64),
+    #[cfg(feature = "bn256-t9")]
+    bn256(9, 63),
+    #[cfg(feature = "pasta")]
+    pasta("pallas::Base", PALLAS_MODULUS),
+    #[cfg(feature = "pasta")]
+    pasta("vesta::Base", VESTA_MODULUS),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpecError {
+    /// No vetted constants for this combination; `nearest` lists the closest ones
+    Unsupported {
+        modulus: String,
+        width: usize,
+        alpha: u64,
+        r_f: usize,
+        r_p: usize,
+        nearest: Vec<SupportedConfig>,
+    },
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported {
+                modulus,
+                width,
+                alpha,
+                r_f,
+                r_p,
+                nearest,
+            } => {
+                write!(
+                    f,
+                    "no vetted Poseidon constants for width {width}, alpha {alpha}, \
+                     r_f {r_f}, r_p {r_p} over the field of modulus {modulus} in this build; \
+                     nearest supported:"
+                )?;
+                for config in nearest {
+                    write!(
+                        f,
+                        " {} t={} alpha={} r_f={} r_p={};",
+                        config.field, config.width, config.alpha, config.r_f, config.r_p
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+/// Checks a combination against [`SUPPORTED`].
+pub fn check_config(
+    modulus: &str,
+    width: usize,
+    alpha: u64,
+    r_f: usize,
+    r_p: usize,
+) -> Result<&'static SupportedConfig, SpecError> {
+    let found = SUPPORTED.iter().find(|c| {
+        c.modulus == modulus && c.width == width && c.alpha == alpha && c.r_f == r_f && c.r_p == r_p
+    });
+    found.ok_or_else(|| {
+        // the same field first, then the closest width
+        let mut nearest = SUPPORTED.to_vec();
+        nearest.sort_by_key(|c| {
+            (
+                c.modulus != modulus,
+                c.width.abs_diff(width),
+                c.alpha != alpha,
+            )
+        });
+        nearest.truncate(3);
+        SpecError::Unsupported {
+            modulus: modulus.to_string(),
+            width,
+            alpha,
+            r_f,
+            r_p,
+            nearest,
+        }
+    })
+}
+
+/// Builds a spec, failing unless its combination is in [`SUPPORTED`].
+///
+/// Builds with the `experimental` feature accept any other round numbers and width as well
+/// when [`EXPERIMENTAL_SPECS_ENV`] is `1`, see [`experimental_spec`].
+pub fn checked_spec<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    r_f: usize,
+    r_p: usize,
+) -> Result<Spec<F, T, RATE>, SpecError> {
+    match check_config(F::MODULUS, T, ALPHA, r_f, r_p) {
+        Ok(_) => Ok(Spec::new(r_f, r_p)),
+        #[cfg(feature = "experimental")]
+        Err(_) if std::env::var(EXPERIMENTAL_SPECS_ENV).is_ok_and(|on| on == "1") => {
+            experimental_spec(r_f, r_p)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Set to `1` to let [`checked_spec`] build unvetted specs, in builds with the
+/// `experimental` feature; ignored otherwise.
+pub const EXPERIMENTAL_SPECS_ENV: &str = "POSEIDON_EXPERIMENTAL_SPECS";
+
+// unvetted round numbers must never reach a release build
+#[cfg(all(feature = "experimental", not(debug_assertions)))]
+compile_error!("the experimental feature is for debug builds only, it accepts unvetted specs");
+
+/// Builds a spec of any width and round numbers without checking them against
+/// [`SUPPORTED`], for prototyping round-reduced variants with the same chips and layout.
+///
+/// Only the round numbers are free: the S-box of the gates and of the native hash is `x^5`,
+/// so [`ALPHA`] is not. The round counts still have to fit the optimized layout, an even
+/// `r_f` of at least 2 and at least one partial round.
+#[cfg(feature = "experimental")]
+pub fn experimental_spec<
+    F: PrimeField + FromUniformBytes<64>,
+    const T: usize,
+    const RATE: usize,
+>(
+    r_f: usize,
+    r_p: usize,
+) -> Result<Spec<F, T, RATE>, SpecError> {
+    if r_f < 2 || r_f % 2 != 0 || r_p == 0 {
+        // no entry of SUPPORTED is near an unusable layout
+        return Err(check_config(F::MODULUS, T, ALPHA, r_f, r_p).unwrap_err());
+    }
+    Ok(Spec::new(r_f, r_p))
+}
+
+/// Builds the vetted spec of width `T` over `F`, with the round numbers [`SUPPORTED`] lists
+/// for them; wide specs absorb `RATE` elements per permutation, e.g. eight at width 9.
+pub fn vetted_spec<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+) -> Result<Spec<F, T, RATE>, SpecError> {
+    // without an entry, the check fails and lists the nearest ones
+    let (r_f, r_p) = SUPPORTED
+        .iter()
+        .find(|c| c.modulus == F::MODULUS && c.width == T)
+        .map_or((0, 0), |c| (c.r_f, c.r_p));
+    checked_spec(r_f, r_p)
+}
+
+/// Runtime metadata of a spec, as advertised to orchestration services.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SpecInfo {
+    pub id: &'static str,
+    pub field: &'static str,
+    pub width: usize,
+    pub rate: usize,
+    pub r_f: usize,
+    pub r_p: usize,
+    pub digest_index: DigestIndex,
+    /// Hex Blake2b-256 digest of the round constants and MDS matrix
+    pub constants_hash: String,
+    pub introduced_in: &'static str,
+}
+
+impl SpecParams {
+    pub fn info(&self) -> SpecInfo {
+        SpecInfo {
+            id: self.id,
+            field: self.field,
+            width: self.width,
+            rate: self.rate,
+            r_f: self.r_f,
+            r_p: self.r_p,
+            digest_index: self.digest_index,
+            constants_hash: (self.constants_hash)(self.r_f, self.r_p),
+            introduced_in: self.introduced_in,
+        }
+    }
+}
+
+/// Metadata of every compiled-in spec.
+pub fn available() -> Vec<SpecInfo> {
+    SPECS.iter().map(SpecParams::info).collect()
+}
+
+/// Digest of everything that determines the permutation of a spec.
+pub fn constants_hash<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    r_f: usize,
+    r_p: usize,
+) -> String {
+    let spec = Spec::<F, T, RATE>::new(r_f, r_p);
+    let constants = spec.constants();
+    let mds = spec.mds_matrices().mds().rows();
+    let mut state = blake2b_simd::Params::new().hash_length(32).to_state();
+    state.update(&(T as u64).to_le_bytes());
+    state.update(&(RATE as u64).to_le_bytes());
+    state.update(&(r_f as u64).to_le_bytes());
+    state.update(&(r_p as u64).to_le_bytes());
+    let elements = constants
+        .start()
+        .iter()
+        .flatten()
+        .chain(constants.partial().iter())
+        .chain(constants.end().iter().flatten())
+        .chain(mds.iter().flatten());
+    for element in elements {
+        state.update(element.to_repr().as_ref());
+    }
+    state
+        .finalize()
+        .as_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_available() {
+        let specs = available();
+        assert_eq!(specs.len(), SPECS.len());
+        let info = &specs[0];
+        assert_eq!(info.id, "bn256-t4-r3");
+        assert_eq!(info.constants_hash.len(), 64);
+        assert_eq!(info.constants_hash, BN256_T4_R3.info().constants_hash);
+        assert_ne!(
+            info.constants_hash,
+            constants_hash::<Fr, 4, 3>(info.r_f, info.r_p + 1)
+        );
+    }
+
+    #[test]
+    fn test_support_matrix() {
+        for params in SPECS {
+            let modulus = SUPPORTED[0].modulus;
+            assert!(check_config(modulus, params.width, ALPHA, params.r_f, params.r_p).is_ok());
+        }
+        assert_eq!(Fr::MODULUS, BN256_MODULUS);
+        assert!(checked_spec::<Fr, 4, 3>(8, 56).is_ok());
+
+        let Err(SpecError::Unsupported { nearest, .. }) = checked_spec::<Fr, 4, 3>(8, 55) else {
+            panic!("8/55 is not vetted");
+        };
+        assert_eq!(nearest[0].width, 4);
+        let Err(SpecError::Unsupported { nearest, .. }) = checked_spec::<Fr, 10, 9>(8, 60) else {
+            panic!("width 10 is not vetted");
+        };
+        assert_eq!(
+            nearest[0].width,
+            SUPPORTED.iter().map(|c| c.width).max().unwrap()
+        );
+        let err = check_config("0x07", 4, 5, 8, 56).unwrap_err();
+        assert!(err.to_string().contains("bn256::Fr t=4"));
+    }
+
+    #[test]
+    fn test_vetted_spec() {
+        assert!(vetted_spec::<Fr, 4, 3>().is_ok());
+        assert!(vetted_spec::<Fr, 10, 9>().is_err());
+        #[cfg(feature = "bn256-t9")]
+        {
+            let spec = vetted_spec::<Fr, 9, 8>().unwrap();
+            assert_eq!((spec.r_f(), spec.constants().partial().len()), (8, 63));
+        }
+    }
+
+    #[cfg(feature = "experimental")]
+    #[test]
+    fn test_experimental_spec() {
+        use crate::poseidon_hash::hash;
+
+        let reduced = experimental_spec::<Fr, 4, 3>(4, 20).unwrap();
+        let vetted = checked_spec::<Fr, 4, 3>(8, 56).unwrap();
+        let inputs = [Fr::from(1), Fr::from(2)];
+        assert_ne!(hash(&reduced, &inputs), hash(&vetted, &inputs));
+        assert!(experimental_spec::<Fr, 4, 3>(3, 20).is_err());
+        assert!(experimental_spec::<Fr, 4, 3>(4, 0).is_err());
+    }
+
+    #[cfg(not(feature = "bn256-t5"))]
+    #[test]
+    fn test_disabled_spec() {
+        let err = checked_spec::<Fr, 5, 4>(8, 60).unwrap_err();
+        assert!(err.to_string().contains("in this build"));
+    }
+}
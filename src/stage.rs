@@ -0,0 +1,76 @@
+//! The stages a task goes through, named the same in errors, proof details and memory
+//! reports.
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Decoding the task and fetching its payload
+    Parse,
+    Keygen,
+    /// Building the circuit inputs from `task_data`
+    Witness,
+    Prove,
+    Verify,
+    /// Serializing the proof into the [`crate::task::ProofDetail`]
+    Encode,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 6] = [
+        Self::Parse,
+        Self::Keygen,
+        Self::Witness,
+        Self::Prove,
+        Self::Verify,
+        Self::Encode,
+    ];
+
+    /// The name used in serialized documents, log lines and metric labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Parse => "parse",
+            Self::Keygen => "keygen",
+            Self::Witness => "witness",
+            Self::Prove => "prove",
+            Self::Verify => "verify",
+            Self::Encode => "encode",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Stage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|stage| stage.as_str() == s)
+            .ok_or_else(|| format!("unknown stage {s:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_names() {
+        for stage in Stage::ALL {
+            assert_eq!(
+                serde_json::to_string(&stage).unwrap(),
+                format!("\"{stage}\"")
+            );
+            assert_eq!(stage.as_str().parse(), Ok(stage));
+        }
+        assert!("proving".parse::<Stage>().is_err());
+    }
+}
@@ -0,0 +1,2080 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD as BS64, Engine};
+use ff::{Field, PrimeField};
+use halo2_proofs::{
+    dev::{FailureLocation, MockProver, VerifyFailure},
+    plonk::{Circuit, Error},
+    poly::{commitment::Params, kzg::commitment::ParamsKZG},
+};
+use halo2curves::bn256::{Bn256, Fr};
+use poseidon::Spec;
+use rand_core::OsRng;
+
+#[cfg(feature = "aggregation")]
+use crate::aggregation::{AggregationError, Aggregator, ChunkSnark, ACCUMULATOR_INSTANCES};
+use crate::{
+    artifacts::ArtifactStore,
+    cost,
+    field_encoding::{parse_fields, to_canonical},
+    instance_layout::InstanceLayout,
+    key_cache::{KeyCache, KeyId, ShapeHistogram, DEFAULT_KEY_CACHE_CAPACITY},
+    limits::{CancelToken, LimitError, MessageLimits},
+    membership::MembershipCircuit,
+    merkle::MerklePath,
+    preimage::PreimageCircuit,
+    preprocess::{Preprocessors, TaskPreprocessor},
+    presets::{LeafOpening, Membership, Preimage, Preset, Range, RangeWitness},
+    primitives::Hash,
+    prover::{read_params, ProverContext, TranscriptHash},
+    range_proof::RangeProofCircuit,
+    specs::{Domain, BN256_T4_R3},
+    stage::Stage,
+    task::{
+        CircuitInfo, ConstraintFailure, EvmProof, ProofDetail, ProofMetadata, ProofType, Task,
+        Verification,
+    },
+    task_data::{parse_array, TaskDataError},
+    telemetry::Metrics,
+    test_circuit::TestCircuit,
+    trace_cache::{TraceCache, TraceCacheStats},
+    vk_cache::VkStore,
+    vk_export::VkExport,
+};
+
+/// Bit size of the values proven in range by the service.
+pub const RANGE_PROOF_BITS: usize = 64;
+/// Depth of the trees the service proves membership in.
+pub const MEMBERSHIP_DEPTH: usize = 20;
+/// Number of inputs the hash circuit of chunk and batch tasks is keyed for; messages of
+/// other lengths have circuits of another shape and get keys of their own.
+pub const HASH_INPUTS: usize = 5;
+/// Degree of the membership circuit, which does not fit into the `k` of the others.
+pub const MEMBERSHIP_K: u32 = 13;
+
+/// How much optional work the service does around the proofs of preimage tasks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LatencyProfile {
+    /// Preimage proofs at the `k` of the service, verified before returning them
+    #[default]
+    Standard,
+    /// For interactive use: preimage proofs on params trimmed to
+    /// [`PreimageCircuit::min_k`], under a key of their own.
+    ///
+    /// The sanity check stays as [`ProverState::with_sanity_check`] sets it; turning it off
+    /// is up to the operator, and leaves `verification` unset in the proof details. Two
+    /// other savings are not offered: proofs without blinding rows, as the prover of this
+    /// halo2 always blinds and has no switch for zero knowledge, and precommitted fixed
+    /// columns, as they are committed once into the proving key at startup in either
+    /// profile, leaving no work per proof to skip.
+    Interactive,
+}
+
+/// The shape of the hash circuit of a chunk or batch task, which its keys depend on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HashShape {
+    /// Number of elements hashed
+    pub len: usize,
+    pub domain: Domain,
+    pub hard_fork: String,
+}
+
+impl HashShape {
+    /// Names the circuit in [`KeyId::shape`].
+    fn key_shape(&self) -> String {
+        let len = self.len;
+        match self.domain {
+            Domain::Pse => format!("hash{len}"),
+            Domain::ConstantLength {
+                len: tagged,
+                outputs,
+            } => format!("hash{len}-cl{tagged}x{outputs}"),
+            // `-c` named the constant tags before they were kept apart from the others
+            Domain::Constant(tag) => format!("hash{len}-k{tag}"),
+            Domain::User(tag) => format!("hash{len}-u{tag}"),
+        }
+    }
+
+    /// Whether the keys generated at startup fit the shape.
+    fn is_shared(&self) -> bool {
+        self.len == HASH_INPUTS && self.domain == Domain::Pse
+    }
+}
+
+/// Tasks being proven, and when the last one finished.
+#[derive(Debug)]
+struct Activity {
+    running: usize,
+    since: Instant,
+}
+
+/// Counts a task as running for as long as it lives.
+struct Running<'a>(&'a Mutex<Activity>);
+
+impl<'a> Running<'a> {
+    fn start(activity: &'a Mutex<Activity>) -> Self {
+        activity.lock().unwrap().running += 1;
+        Self(activity)
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let mut activity = self.0.lock().unwrap();
+        activity.running -= 1;
+        activity.since = Instant::now();
+    }
+}
+
+/// Read-only state of the prover service.
+///
+/// Built once at startup and handed to every handler behind an `Arc`, so params and keys
+/// are shared rather than rebuilt per request.
+pub struct ProverState {
+    test_circuit: ProverContext<TestCircuit<Fr>>,
+    preimage: ProverContext<PreimageCircuit<Fr>>,
+    range_proof: ProverContext<RangeProofCircuit<Fr>>,
+    membership: ProverContext<MembershipCircuit<Fr>>,
+    /// The params as loaded, which hash circuits of other sizes are keyed on, see
+    /// [`ProverState::prove`]
+    srs: ParamsKZG<Bn256>,
+    /// `srs` downsized to the `k` of every hash circuit proven so far
+    sized_params: Mutex<HashMap<u32, ParamsKZG<Bn256>>>,
+    limits: MessageLimits,
+    /// Verifying keys of earlier circuit versions, see [`ProverState::with_vk_store`]
+    vk_store: Option<VkStore>,
+    /// See [`ProverState::with_sanity_check`]
+    sanity_check: bool,
+    /// See [`ProverState::with_max_proof_size`]
+    max_proof_size: Option<usize>,
+    /// See [`ProverState::with_max_k`]
+    max_k: Option<u32>,
+    /// See [`ProverState::with_timeout`]
+    timeout: Option<Duration>,
+    /// Readers of the messages of chunk and batch tasks, see [`crate::preprocess`]
+    preprocessors: Preprocessors,
+    /// Keys of hash circuits for messages of other lengths than [`HASH_INPUTS`]
+    key_cache: KeyCache,
+    /// Messages hashed by chunk and batch tasks, see [`ProverState::task_sizes`]
+    task_sizes: ShapeHistogram<HashShape>,
+    /// See [`ProverState::with_trace_cache`]
+    trace_cache: Option<Arc<ServiceTraceCache>>,
+    /// See [`ProverState::idle_for`]
+    activity: Mutex<Activity>,
+    /// Recorded in every proof, see [`ProverState::metadata`]
+    metadata: ProofMetadata,
+    /// See [`ProverState::with_latency_profile`]
+    latency_profile: LatencyProfile,
+    /// See [`ProverState::with_transcript`]
+    transcript: TranscriptHash,
+    /// See [`ProverState::with_proof_types`]
+    proof_types: Option<Vec<ProofType>>,
+    /// See [`ProverState::with_aggregation`]
+    #[cfg(feature = "aggregation")]
+    aggregator: Option<Arc<Aggregator>>,
+    /// See [`ProverState::metrics`]
+    metrics: Metrics,
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    /// The params file cannot be read or is too small
+    Params(io::Error),
+    Keygen(Error),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Params(err) => write!(f, "cannot load params: {err}"),
+            Self::Keygen(err) => write!(f, "keygen failed: {err:?}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl StateError {
+    /// Params are only loaded to generate keys, so both failures belong to keygen.
+    pub fn stage(&self) -> Stage {
+        Stage::Keygen
+    }
+}
+
+/// A task that could not be proven, and where it failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskError {
+    pub stage: Stage,
+    pub error: crate::Error,
+    /// The failed sanity check, if the proof was created but did not verify
+    pub verification: Option<Verification>,
+    /// What a dry run found wrong with the witness, see [`Task::dry_run`]
+    pub constraint_failures: Vec<ConstraintFailure>,
+}
+
+impl TaskError {
+    fn new(stage: Stage, message: impl fmt::Display) -> Self {
+        Self::from_error(stage, crate::Error::new(stage, message))
+    }
+
+    fn plonk(stage: Stage, err: Error) -> Self {
+        Self::from_error(stage, crate::Error::plonk(stage, err))
+    }
+
+    fn limit(stage: Stage, err: LimitError) -> Self {
+        Self::from_error(stage, crate::Error::limit(err))
+    }
+
+    /// The limit `cancel` stopped the task for if it did, as a failure of the stage of `self`,
+    /// which may come from the task being stopped.
+    fn or_stopped(self, cancel: &CancelToken) -> Self {
+        match cancel.check() {
+            Ok(()) => self,
+            Err(err) => Self::limit(self.stage, err),
+        }
+    }
+
+    fn from_error(stage: Stage, error: crate::Error) -> Self {
+        Self {
+            stage,
+            error,
+            verification: None,
+            constraint_failures: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {}", self.stage, self.error)
+    }
+}
+
+impl std::error::Error for TaskError {}
+
+/// A verified proof of a task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskProof {
+    /// Proof in the transcript of `transcript`
+    pub proof: Vec<u8>,
+    pub transcript: TranscriptHash,
+    /// Public inputs, in the order of the task's instance layout
+    pub instances: Vec<Fr>,
+    /// Hash of the key the proof verifies with if it is not the current key of its type
+    pub vk_hash: Option<String>,
+    /// Present if the task asked for an EVM proof
+    pub evm: Option<EvmProof>,
+    /// Present if the proof was verified before returning it
+    pub verification: Option<Verification>,
+    /// The build that produced the proof
+    pub metadata: Option<ProofMetadata>,
+    /// How long `create_proof` took, absent for dry runs
+    pub proving_time_us: Option<u64>,
+    pub circuit: Option<CircuitInfo>,
+}
+
+impl TaskProof {
+    /// The proof detail answering `task`; the Base64 proof, compressed if the task asks for
+    /// it, and canonical instances.
+    pub fn detail(&self, task: &Task) -> ProofDetail {
+        // tasks asking for a compression this build lacks are rejected before proving
+        let compressed = task
+            .compression
+            .filter(|_| !self.proof.is_empty())
+            .and_then(|c| c.compress(&self.proof).ok().map(|proof| (c, proof)));
+        let (compression, proof) = match &compressed {
+            Some((compression, proof)) => (Some(*compression), proof),
+            None => (None, &self.proof),
+        };
+        ProofDetail {
+            id: task.id.clone(),
+            proof_type: task.task_type,
+            proof_data: BS64.encode(proof),
+            compression,
+            proof_size: (!self.proof.is_empty()).then_some(self.proof.len()),
+            proving_time_us: self.proving_time_us,
+            circuit: self.circuit.clone(),
+            transcript: (self.transcript != TranscriptHash::Blake2b).then_some(self.transcript),
+            instances: self.instances.iter().map(to_canonical).collect(),
+            instance_layout: task.instance_layout,
+            vk_hash: self.vk_hash.clone().unwrap_or_default(),
+            evm: self.evm.clone(),
+            verification: self.verification,
+            metadata: self.metadata.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl ProverState {
+    /// Sets up (insecure, test-only) params and runs keygen for every circuit the service
+    /// proves, at `2^k` rows except for the membership circuit.
+    ///
+    /// Message limits default to the longest message that fits into the params, of
+    /// `2^max(k, MEMBERSHIP_K)` rows.
+    pub fn new(k: u32) -> Result<Self, Error> {
+        let params = ParamsKZG::<Bn256>::setup(k.max(MEMBERSHIP_K), OsRng);
+        Self::from_params(&params, k)
+    }
+
+    /// Runs keygen against `params`, downsized to the `k` of every circuit.
+    ///
+    /// The circuits are independent, so their keys are generated in parallel.
+    pub fn from_params(params: &ParamsKZG<Bn256>, k: u32) -> Result<Self, Error> {
+        Self::keygen(params, k, MessageLimits::for_k(&default_spec(), params.k()))
+    }
+
+    /// Reads params from `path` while the spec constants are derived, then runs keygen.
+    ///
+    /// Deserializing large params and deriving constants both take seconds at startup; this
+    /// overlaps them instead of running one after the other.
+    pub fn prefetch(path: impl AsRef<Path> + Send, k: u32) -> Result<Self, StateError> {
+        Self::prefetch_with(move || read_params(path), k)
+    }
+
+    /// As [`ProverState::prefetch`], reading the params stored under `key`.
+    pub fn prefetch_from(store: &dyn ArtifactStore, key: &str, k: u32) -> Result<Self, StateError> {
+        Self::prefetch_with(|| ParamsKZG::read(&mut store.get(key)?.as_slice()), k)
+    }
+
+    fn prefetch_with(
+        load: impl FnOnce() -> io::Result<ParamsKZG<Bn256>> + Send,
+        k: u32,
+    ) -> Result<Self, StateError> {
+        thread::scope(|s| {
+            let params = s.spawn(load);
+            let spec = default_spec();
+            let params = join(params).map_err(StateError::Params)?;
+            let needed = k.max(MEMBERSHIP_K);
+            if params.k() < needed {
+                return Err(StateError::Params(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("params of 2^{} rows, need 2^{needed}", params.k()),
+                )));
+            }
+            let limits = MessageLimits::for_k(&spec, params.k());
+            Self::keygen(&params, k, limits).map_err(StateError::Keygen)
+        })
+    }
+
+    fn keygen(params: &ParamsKZG<Bn256>, k: u32, limits: MessageLimits) -> Result<Self, Error> {
+        let sized = |k: u32| {
+            let mut params = params.clone();
+            params.downsize(k);
+            params
+        };
+        thread::scope(|s| {
+            // keygen depends on the number of absorbed elements, not on their values
+            let test_circuit = s.spawn(|| {
+                ProverContext::new(sized(k), &TestCircuit::new(vec![Fr::ZERO; HASH_INPUTS]))
+            });
+            let preimage =
+                s.spawn(|| ProverContext::new(sized(k), &PreimageCircuit::new(Fr::ZERO)));
+            let range_proof = s.spawn(|| {
+                ProverContext::new(
+                    sized(k),
+                    &RangeProofCircuit::new(Fr::ZERO, Fr::ZERO, RANGE_PROOF_BITS),
+                )
+            });
+            let membership = s.spawn(|| {
+                ProverContext::new(
+                    sized(MEMBERSHIP_K),
+                    &MembershipCircuit::new(
+                        Fr::ZERO,
+                        MerklePath {
+                            index: 0,
+                            siblings: vec![Fr::ZERO; MEMBERSHIP_DEPTH],
+                        },
+                    ),
+                )
+            });
+            Ok(Self {
+                test_circuit: join(test_circuit)?,
+                preimage: join(preimage)?,
+                range_proof: join(range_proof)?,
+                membership: join(membership)?,
+                srs: params.clone(),
+                sized_params: Mutex::default(),
+                limits,
+                vk_store: None,
+                sanity_check: true,
+                max_proof_size: None,
+                max_k: None,
+                timeout: None,
+                preprocessors: Preprocessors::default(),
+                key_cache: KeyCache::new(DEFAULT_KEY_CACHE_CAPACITY),
+                task_sizes: ShapeHistogram::default(),
+                trace_cache: None,
+                activity: Mutex::new(Activity {
+                    running: 0,
+                    since: Instant::now(),
+                }),
+                metadata: ProofMetadata::current(&BN256_T4_R3),
+                latency_profile: LatencyProfile::Standard,
+                transcript: TranscriptHash::Blake2b,
+                proof_types: None,
+                #[cfg(feature = "aggregation")]
+                aggregator: None,
+                metrics: Metrics::new(),
+            })
+        })
+    }
+
+    /// Replaces the default message limits, e.g. to keep tasks well below capacity.
+    pub fn with_limits(mut self, limits: MessageLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Whether every proof is verified before [`ProverState::prove`] returns it; on by default.
+    ///
+    /// A proof that fails this check points at keys and params that do not belong together,
+    /// which is cheaper to learn here than from every downstream verifier.
+    pub fn with_sanity_check(mut self, on: bool) -> Self {
+        self.sanity_check = on;
+        self
+    }
+
+    /// Switches preimage tasks to `profile`, regenerating their keys for its `k`; the
+    /// preimage verifying key changes with the `k`.
+    ///
+    /// The new keys keep the protocol label and salt set so far.
+    pub fn with_latency_profile(mut self, profile: LatencyProfile) -> Result<Self, Error> {
+        if profile == self.latency_profile {
+            return Ok(self);
+        }
+        let k = match profile {
+            LatencyProfile::Standard => self.test_circuit.k(),
+            LatencyProfile::Interactive => PreimageCircuit::<Fr>::min_k(),
+        };
+        // the k of the service fits the hash circuit, so it is above the preimage minimum
+        let mut params = self.test_circuit.params().clone();
+        params.downsize(k);
+        let mut preimage = ProverContext::new(params, &PreimageCircuit::new(Fr::ZERO))?;
+        if let Some(label) = self.protocol_label() {
+            preimage = preimage.with_protocol_label(label);
+        }
+        if let Some(salt) = self.preimage.salt() {
+            preimage = preimage.with_salt(salt);
+        }
+        self.preimage = preimage;
+        self.latency_profile = profile;
+        Ok(self)
+    }
+
+    pub fn latency_profile(&self) -> LatencyProfile {
+        self.latency_profile
+    }
+
+    /// Rejects tasks whose proof would take more than `bytes`, or whose calldata would for
+    /// tasks asking for an EVM proof, e.g. to stay within a chain's calldata limit.
+    ///
+    /// The size follows from the circuit shape, see [`ProverContext::proof_size`], so tasks
+    /// are rejected before proving, with the estimate in the error.
+    pub fn with_max_proof_size(mut self, bytes: usize) -> Self {
+        self.max_proof_size = Some(bytes);
+        self
+    }
+
+    pub fn max_proof_size(&self) -> Option<usize> {
+        self.max_proof_size
+    }
+
+    /// Rejects tasks whose circuit takes more than `2^k` rows before generating keys or
+    /// proving. The message limits bound the rows of hash tasks already, this bounds them
+    /// below what the params allow. The memory of a proof grows with its rows, but memory
+    /// itself is not limited.
+    pub fn with_max_k(mut self, k: u32) -> Self {
+        self.max_k = Some(k);
+        self
+    }
+
+    pub fn max_k(&self) -> Option<u32> {
+        self.max_k
+    }
+
+    /// Fails tasks found still running `timeout` after they started at a check of their
+    /// [`CancelToken`]. The stage running at the deadline, e.g. `create_proof`, runs to its
+    /// end first, so the timeout bounds when a task is given up, not how long it runs.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Answers only tasks of `types` and rejects the others before reading their witness,
+    /// e.g. to keep a fleet of membership provers free of chunk tasks. Tasks of an undefined
+    /// type count as chunk tasks. All types are accepted by default.
+    pub fn with_proof_types(mut self, types: Vec<ProofType>) -> Self {
+        self.proof_types = Some(types);
+        self
+    }
+
+    /// Computes the witness of chunk and batch tasks through a cache of the last `capacity`
+    /// permutations, so tasks sharing a prefix, such as identical headers, compute its
+    /// permutations once, see [`crate::trace_cache`].
+    pub fn with_trace_cache(mut self, capacity: usize) -> Self {
+        self.trace_cache = Some(Arc::new(TraceCache::for_spec(&default_spec(), capacity)));
+        self
+    }
+
+    /// Hits and misses of the cache of [`ProverState::with_trace_cache`], if there is one.
+    pub fn trace_cache_stats(&self) -> Option<TraceCacheStats> {
+        self.trace_cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Whether tasks of `proof_type` are answered, see [`ProverState::with_proof_types`].
+    pub fn accepts(&self, proof_type: ProofType) -> bool {
+        let proof_type = match proof_type {
+            ProofType::Undefined => ProofType::Chunk,
+            other => other,
+        };
+        match &self.proof_types {
+            Some(types) => types.contains(&proof_type),
+            None => true,
+        }
+    }
+
+    /// Accepts chunk and batch tasks whose `task_data` is read by `preprocessor`, next to the
+    /// built-ins of [`crate::preprocess`]; one of the same name replaces the built-in.
+    pub fn with_preprocessor(mut self, preprocessor: impl TaskPreprocessor + 'static) -> Self {
+        self.preprocessors = self.preprocessors.with(preprocessor);
+        self
+    }
+
+    pub fn preprocessors(&self) -> &Preprocessors {
+        &self.preprocessors
+    }
+
+    /// Binds every proof of the service to `salt`, see [`ProverContext::with_salt`].
+    pub fn with_salt(self, salt: Fr) -> Self {
+        Self {
+            test_circuit: self.test_circuit.with_salt(salt),
+            preimage: self.preimage.with_salt(salt),
+            range_proof: self.range_proof.with_salt(salt),
+            membership: self.membership.with_salt(salt),
+            ..self
+        }
+    }
+
+    /// Proves `task` with the keys of its proof type and, with the sanity check on, verifies
+    /// the proof before returning it, so a proof that does not verify is reported as an error
+    /// instead of being sent.
+    ///
+    /// `task_data` follows [`crate::task_data`]; chunk and batch tasks send the message whose
+    /// Poseidon hash is the public input, in the format of their preprocessor, see
+    /// [`crate::preprocess`]. The other proof types send a bare array of:
+    /// - preimage: `[preimage]`
+    /// - range: `[value, blinding]`, proving `value < 2^RANGE_PROOF_BITS`
+    /// - membership: `[secret, index, siblings...]` with `MEMBERSHIP_DEPTH` siblings
+    ///
+    /// The keys of chunk and batch tasks fit messages of [`HASH_INPUTS`] elements at the `k`
+    /// of the service. Messages of other lengths are proven at the smallest `k` they fit
+    /// into, see [`cost::estimate`], on the loaded params downsized to it; a message too long
+    /// for the loaded params fails at keygen, saying how many rows it needs.
+    ///
+    /// With the `aggregation` feature, a batch task may instead send the chunk proofs to
+    /// aggregate, see [`ProverState::with_aggregation`].
+    ///
+    /// Every task is counted in [`ProverState::metrics`], with the time of its stages, in a
+    /// `task` span.
+    pub fn prove(&self, task: &Task) -> Result<TaskProof, TaskError> {
+        self.prove_with(task, &CancelToken::new())
+    }
+
+    /// [`ProverState::prove`], stopping once `cancel` is cancelled or the timeout of the
+    /// prover passed, see [`ProverState::with_timeout`]. A stopped task fails with
+    /// [`crate::Error::ResourceLimitExceeded`] at the stage it stopped in.
+    pub fn prove_with(&self, task: &Task, cancel: &CancelToken) -> Result<TaskProof, TaskError> {
+        let cancel = cancel.child(self.timeout);
+        let _running = Running::start(&self.activity);
+        let _span = tracing::info_span!(
+            "task",
+            id = %task.id,
+            uuid = %task.uuid,
+            proof_type = ?task.task_type
+        )
+        .entered();
+        let proven = self.prove_task(task, &cancel);
+        if let Err(err) = &proven {
+            tracing::warn!(
+                stage = err.stage.as_str(),
+                code = err.error.code(),
+                error = %err.error,
+                "task failed"
+            );
+        }
+        self.metrics
+            .record_task(proven.as_ref().err().map(|err| err.stage));
+        proven
+    }
+
+    fn prove_task(&self, task: &Task, cancel: &CancelToken) -> Result<TaskProof, TaskError> {
+        if !self.accepts(task.task_type) {
+            return Err(TaskError::new(
+                Stage::Witness,
+                format!("{} tasks are disabled", task.task_type.as_str()),
+            ));
+        }
+        if let Some(compression) = task.compression {
+            compression.check().map_err(|err| {
+                TaskError::new(
+                    Stage::Witness,
+                    format!(
+                        "cannot compress proofs with {}: {err}",
+                        compression.as_str()
+                    ),
+                )
+            })?;
+        }
+        let hashes = matches!(
+            task.task_type,
+            ProofType::Undefined | ProofType::Chunk | ProofType::Batch
+        );
+        if task.preprocessor.is_some() && !hashes {
+            return Err(TaskError::new(
+                Stage::Witness,
+                "only chunk and batch tasks select a task_data preprocessor",
+            ));
+        }
+        #[cfg(feature = "aggregation")]
+        if task.task_type == ProofType::Batch {
+            if let Some(chunks) = parse_chunk_proofs(&task.task_data) {
+                let chunks = chunks.map_err(|err| TaskError::new(Stage::Witness, err))?;
+                return self.prove_aggregation(&chunks, task, cancel);
+            }
+        }
+        let witness_error = |err: TaskDataError| TaskError::new(Stage::Witness, err);
+        let elements = |expected: usize| {
+            let values = parse_array::<Fr>(&task.task_data).map_err(witness_error)?;
+            if values.len() == expected {
+                Ok(values)
+            } else {
+                Err(TaskError::new(
+                    Stage::Witness,
+                    format!(
+                        "expected {expected} elements in task_data, got {}",
+                        values.len()
+                    ),
+                ))
+            }
+        };
+        match task.task_type {
+            ProofType::Preimage => {
+                let (circuit, instances) = self.metrics.time(Stage::Witness, || {
+                    let values = elements(1)?;
+                    let (circuit, public) = Preimage::witness_from(values[0]);
+                    Ok::<_, TaskError>((circuit, Preimage::instance(&public).concat()))
+                })?;
+                self.prove_on(&self.preimage, &circuit, instances, task, cancel)
+            }
+            ProofType::Range => {
+                let (circuit, instances) = self.metrics.time(Stage::Witness, || {
+                    let values = elements(2)?;
+                    let (circuit, public) = Range::witness_from(RangeWitness {
+                        value: values[0],
+                        blinding: values[1],
+                        bits: RANGE_PROOF_BITS,
+                    });
+                    Ok::<_, TaskError>((circuit, Range::instance(&public).concat()))
+                })?;
+                self.prove_on(&self.range_proof, &circuit, instances, task, cancel)
+            }
+            ProofType::Membership => {
+                let (circuit, instances) = self.metrics.time(Stage::Witness, || {
+                    let mut values = elements(2 + MEMBERSHIP_DEPTH)?;
+                    let index = to_u64(values[1]).ok_or_else(|| {
+                        TaskError::new(Stage::Witness, "leaf index does not fit into 64 bits")
+                    })?;
+                    let siblings = values.split_off(2);
+                    let (circuit, public) = Membership::witness_from(LeafOpening {
+                        secret: values[0],
+                        path: MerklePath { index, siblings },
+                    });
+                    Ok::<_, TaskError>((circuit, Membership::instance(&public).concat()))
+                })?;
+                self.prove_on(&self.membership, &circuit, instances, task, cancel)
+            }
+            ProofType::Undefined | ProofType::Chunk | ProofType::Batch => {
+                let (inputs, domain, digest) = self.metrics.time(Stage::Witness, || {
+                    let input = self
+                        .preprocessors
+                        .preprocess(task)
+                        .map_err(|err| TaskError::new(Stage::Witness, err))?;
+                    let (witness, domain) = (input.witness, input.domain);
+                    self.limits
+                        .check([witness.inputs.len()])
+                        .map_err(|err| TaskError::limit(Stage::Witness, err))?;
+                    let digest =
+                        Hash::new(default_spec()).hash_with_domain(domain, &witness.inputs);
+                    witness.check_digest(digest).map_err(witness_error)?;
+                    Ok::<_, TaskError>((witness.inputs, domain, digest))
+                })?;
+                let k = cost::estimate(&default_spec(), 1, inputs.len()).k;
+                let instances = vec![digest];
+                let shape = HashShape {
+                    len: inputs.len(),
+                    domain,
+                    hard_fork: task.hard_fork_name.clone(),
+                };
+                self.task_sizes.record(shape.clone());
+                let mut circuit = TestCircuit::new(inputs)
+                    .with_domain(domain)
+                    .with_cancel_token(cancel.clone());
+                if let Some(cache) = &self.trace_cache {
+                    circuit = circuit.with_trace_cache(cache.clone());
+                }
+                if shape.is_shared() {
+                    return self.prove_on(&self.test_circuit, &circuit, instances, task, cancel);
+                }
+                // the shared keys only fit messages of HASH_INPUTS elements in the PSE domain,
+                // others are proven at the smallest k they fit into, under keys of their own
+                if task.dry_run {
+                    return self.dry_run_on(k, &circuit, instances, task, cancel);
+                }
+                self.check_k(k)?;
+                check_cancel(Stage::Keygen, cancel)?;
+                let ctx = self
+                    .hash_context(&circuit, &shape, k)
+                    .map_err(|err| err.or_stopped(cancel))?;
+                let mut proof = self.prove_on(&ctx, &circuit, instances, task, cancel)?;
+                proof.vk_hash = Some(ctx.vk_hash().to_string());
+                Ok(proof)
+            }
+        }
+    }
+
+    /// Keys for hashing a message of `shape` with `circuit` at `2^k` rows, from the key
+    /// cache, on the loaded params downsized to `k` and with the salt and label of the keys
+    /// of [`HASH_INPUTS`] elements.
+    fn hash_context(
+        &self,
+        circuit: &TestCircuit<Fr>,
+        shape: &HashShape,
+        k: u32,
+    ) -> Result<ProverContext<TestCircuit<Fr>>, TaskError> {
+        let base = &self.test_circuit;
+        let params = self.params_for(k)?;
+        let key_shape = shape.key_shape();
+        let key_id = self.key_id(&key_shape, shape, k);
+        let pk = self
+            .metrics
+            .time(Stage::Keygen, || {
+                self.key_cache.get_or_keygen(&key_id, &params, circuit)
+            })
+            .map_err(|err| TaskError::plonk(Stage::Keygen, err))?;
+        let mut keyed = ProverContext::from_pk(params, pk);
+        if let Some(salt) = base.salt() {
+            keyed = keyed.with_salt(salt);
+        }
+        if let Some(label) = base.protocol_label() {
+            keyed = keyed.with_protocol_label(label);
+        }
+        Ok(keyed)
+    }
+
+    /// The loaded params downsized to `2^k` rows, or [`crate::Error::SrsTooSmall`] if they
+    /// have fewer.
+    fn params_for(&self, k: u32) -> Result<ParamsKZG<Bn256>, TaskError> {
+        if k > self.srs.k() {
+            return Err(TaskError::from_error(
+                Stage::Keygen,
+                crate::Error::SrsTooSmall {
+                    k: self.srs.k(),
+                    needed: Some(k),
+                },
+            ));
+        }
+        let mut sized = self.sized_params.lock().unwrap();
+        let params = sized.entry(k).or_insert_with(|| {
+            let mut params = self.srs.clone();
+            params.downsize(k);
+            params
+        });
+        Ok(params.clone())
+    }
+
+    fn key_id<'a>(&'a self, key_shape: &'a str, shape: &'a HashShape, k: u32) -> KeyId<'a> {
+        KeyId {
+            shape: key_shape,
+            k,
+            hard_fork: &shape.hard_fork,
+            generation: self.key_generation(),
+        }
+    }
+
+    /// Names the params and circuit version of cached keys: keys generated for other params
+    /// or by another version of the hash circuit come with another verifying key here.
+    pub fn key_generation(&self) -> &str {
+        &self.test_circuit.vk_hash()[..16]
+    }
+
+    /// Names everything besides a task that determines its proof: the [`key_generation`],
+    /// the salt, the protocol label and the transcript of tasks that name none. Proofs kept
+    /// under one, see [`crate::proof_store`], are not answers for a prover of another.
+    ///
+    /// The salt stays secret: only a digest of it goes into the name.
+    ///
+    /// [`key_generation`]: ProverState::key_generation
+    pub fn proof_generation(&self) -> String {
+        let mut hasher = blake2b_simd::Params::new()
+            .hash_length(8)
+            .personal(b"poseidon-proofs\0")
+            .to_state();
+        match self.test_circuit.salt() {
+            Some(salt) => hasher.update(&[1]).update(salt.to_repr().as_ref()),
+            None => hasher.update(&[0]),
+        };
+        let label = self.protocol_label().unwrap_or_default();
+        hasher
+            .update(&(label.len() as u64).to_le_bytes())
+            .update(label.as_bytes())
+            .update(self.transcript.as_str().as_bytes());
+        format!("{}-{}", self.key_generation(), hasher.finalize().to_hex())
+    }
+
+    /// Persists keys generated for messages of other lengths than [`HASH_INPUTS`] to `dir`,
+    /// and reads back those that an earlier run with the same params persisted there.
+    pub fn with_key_dir(mut self, dir: impl Into<PathBuf>, capacity: usize) -> io::Result<Self> {
+        let cache = KeyCache::persisted(dir, capacity);
+        cache.load::<TestCircuit<Fr>>(self.key_generation())?;
+        self.key_cache = cache;
+        Ok(self)
+    }
+
+    pub fn cached_keys(&self) -> usize {
+        self.key_cache.cached()
+    }
+
+    /// The shapes of the messages hashed by chunk and batch tasks so far, most frequent
+    /// first, with the number of tasks of each.
+    pub fn task_sizes(&self) -> Vec<(HashShape, u64)> {
+        self.task_sizes.counts()
+    }
+
+    /// How long no task has been proven for, or `None` while one is.
+    pub fn idle_for(&self) -> Option<Duration> {
+        let activity = self.activity.lock().unwrap();
+        (activity.running == 0).then(|| activity.since.elapsed())
+    }
+
+    /// Generates the keys of the `shapes` most frequent message shapes seen whose keys are
+    /// not cached, e.g. because a task of a rare shape evicted them, and returns the shapes
+    /// it generated keys for.
+    ///
+    /// Meant for idle periods: it stops before the next keygen once a task is running, and
+    /// never considers more shapes than the key cache holds, so it does not evict the keys
+    /// it generates.
+    pub fn prewarm(&self, shapes: usize) -> Result<Vec<HashShape>, TaskError> {
+        let spec = default_spec();
+        let mut prewarmed = Vec::new();
+        let frequent = self
+            .task_sizes()
+            .into_iter()
+            .filter(|(shape, _)| !shape.is_shared())
+            .take(shapes.min(self.key_cache.capacity()));
+        for (shape, _) in frequent {
+            if self.idle_for().is_none() {
+                break;
+            }
+            let key_shape = shape.key_shape();
+            let k = cost::estimate(&spec, 1, shape.len).k;
+            if self.max_k.is_some_and(|max| k > max) {
+                continue;
+            }
+            if self.key_cache.contains(&self.key_id(&key_shape, &shape, k)) {
+                continue;
+            }
+            // keygen depends on the number of absorbed elements, not on their values
+            let circuit = TestCircuit::new(vec![Fr::ZERO; shape.len]).with_domain(shape.domain);
+            self.hash_context(&circuit, &shape, k)?;
+            prewarmed.push(shape);
+        }
+        Ok(prewarmed)
+    }
+
+    /// Fails tasks whose circuit takes more rows than [`ProverState::with_max_k`] allows.
+    fn check_k(&self, k: u32) -> Result<(), TaskError> {
+        match self.max_k {
+            Some(max) if k > max => Err(TaskError::limit(
+                Stage::Witness,
+                LimitError::KTooLarge { k, max },
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn prove_on<C: Circuit<Fr>>(
+        &self,
+        ctx: &ProverContext<C>,
+        circuit: &C,
+        instances: Vec<Fr>,
+        task: &Task,
+        cancel: &CancelToken,
+    ) -> Result<TaskProof, TaskError> {
+        // every circuit of the service has a single instance column
+        if task.instance_layout == InstanceLayout::ColumnMajor && instances.len() > 1 {
+            return Err(TaskError::new(
+                Stage::Witness,
+                format!(
+                    "{:?} proofs only support the row_major instance layout",
+                    task.task_type
+                ),
+            ));
+        }
+        if task.dry_run {
+            return self.dry_run_on(ctx.k(), circuit, instances, task, cancel);
+        }
+        self.check_k(ctx.k())?;
+        if let Some(max) = self.max_proof_size {
+            let mut estimate = ctx.proof_size();
+            if task.evm {
+                // calldata leads with every instance as a 32-byte word
+                estimate += 32 * instances.len();
+            }
+            if estimate > max {
+                return Err(TaskError::limit(
+                    Stage::Witness,
+                    LimitError::ProofTooLarge { estimate, max },
+                ));
+            }
+        }
+        check_cancel(Stage::Prove, cancel)?;
+        let columns: &[&[Fr]] = &[&instances];
+        let transcript = self.transcript(task);
+        let (proof, proving_time, evm_proof) = self
+            .metrics
+            .time(Stage::Prove, || {
+                // the time of the proof asked for, not of the EVM proof next to it
+                let start = Instant::now();
+                let proof = ctx.prove_in(transcript, circuit, columns)?;
+                let proving_time = start.elapsed();
+                let evm_proof = if task.evm {
+                    Some(ctx.prove_keccak(circuit, columns)?)
+                } else {
+                    None
+                };
+                Ok::<_, Error>((proof, proving_time, evm_proof))
+            })
+            .map_err(|err| TaskError::plonk(Stage::Prove, err).or_stopped(cancel))?;
+        let verification = if self.sanity_check {
+            check_cancel(Stage::Verify, cancel)?;
+            let start = Instant::now();
+            let verified = self.metrics.time(Stage::Verify, || {
+                ctx.verify_in(transcript, &proof, columns)
+                    .and_then(|()| match &evm_proof {
+                        Some(evm_proof) => ctx.verify_keccak(evm_proof, columns),
+                        None => Ok(()),
+                    })
+            });
+            let verification = Verification {
+                verified: verified.is_ok(),
+                duration_us: start.elapsed().as_micros() as u64,
+            };
+            if let Err(err) = verified {
+                return Err(TaskError {
+                    verification: Some(verification),
+                    ..TaskError::plonk(Stage::Verify, err)
+                });
+            }
+            Some(verification)
+        } else {
+            None
+        };
+        Ok(TaskProof {
+            proof,
+            transcript,
+            instances,
+            vk_hash: None,
+            evm: evm_proof.map(|proof| EvmProof::new(columns, &proof)),
+            verification,
+            metadata: Some(self.metadata.clone()),
+            proving_time_us: Some(proving_time.as_micros() as u64),
+            circuit: Some(circuit_info(task.task_type, ctx.k())),
+        })
+    }
+
+    /// Checks the witness of `circuit` with `MockProver` at `2^k` rows instead of proving
+    /// it, see [`Task::dry_run`]; the proof it returns is empty.
+    fn dry_run_on<C: Circuit<Fr>>(
+        &self,
+        k: u32,
+        circuit: &C,
+        instances: Vec<Fr>,
+        task: &Task,
+        cancel: &CancelToken,
+    ) -> Result<TaskProof, TaskError> {
+        self.check_k(k)?;
+        check_cancel(Stage::Prove, cancel)?;
+        let prover = MockProver::run(k, circuit, vec![instances.clone()])
+            .map_err(|err| TaskError::plonk(Stage::Prove, err).or_stopped(cancel))?;
+        if let Err(failures) = prover.verify() {
+            return Err(TaskError {
+                constraint_failures: failures.iter().map(constraint_failure).collect(),
+                ..TaskError::new(
+                    Stage::Prove,
+                    format!("the witness fails {} checks of the circuit", failures.len()),
+                )
+            });
+        }
+        Ok(TaskProof {
+            proof: Vec::new(),
+            transcript: self.transcript(task),
+            instances,
+            vk_hash: None,
+            evm: None,
+            verification: None,
+            metadata: Some(self.metadata.clone()),
+            proving_time_us: None,
+            circuit: Some(circuit_info(task.task_type, k)),
+        })
+    }
+
+    /// Aggregates the chunk proofs of a batch task into one batch proof, see
+    /// [`crate::aggregation`].
+    #[cfg(feature = "aggregation")]
+    fn prove_aggregation(
+        &self,
+        chunks: &[ChunkSnark],
+        task: &Task,
+        cancel: &CancelToken,
+    ) -> Result<TaskProof, TaskError> {
+        let aggregator = self
+            .aggregator
+            .as_ref()
+            .ok_or_else(|| TaskError::new(Stage::Witness, "this prover has no aggregation keys"))?;
+        if task.evm {
+            return Err(TaskError::new(
+                Stage::Witness,
+                "aggregated batch proofs have no EVM proof",
+            ));
+        }
+        if task.dry_run {
+            return Err(TaskError::new(
+                Stage::Witness,
+                "aggregated batch proofs have no dry run",
+            ));
+        }
+        check_cancel(Stage::Prove, cancel)?;
+        // keys of the batch size are generated by the first aggregation of that size
+        let transcript = self.transcript(task);
+        let start = Instant::now();
+        let (proof, instances) = self
+            .metrics
+            .time(Stage::Prove, || aggregator.aggregate(chunks, transcript))
+            .map_err(|err| match err {
+                AggregationError::Plonk(err) => TaskError::plonk(Stage::Prove, err),
+                err => TaskError::new(Stage::Witness, err),
+            })
+            .map_err(|err| err.or_stopped(cancel))?;
+        let proving_time = start.elapsed();
+        let ctx = aggregator
+            .context(chunks.len())
+            .map_err(|err| TaskError::plonk(Stage::Keygen, err))?;
+        let verification = if self.sanity_check {
+            let start = Instant::now();
+            let verified = self.metrics.time(Stage::Verify, || {
+                aggregator.verify(&proof, &instances, transcript)
+            });
+            let verification = Verification {
+                verified: verified.is_ok(),
+                duration_us: start.elapsed().as_micros() as u64,
+            };
+            if let Err(err) = verified {
+                return Err(TaskError {
+                    verification: Some(verification),
+                    ..TaskError::new(Stage::Verify, err)
+                });
+            }
+            Some(verification)
+        } else {
+            None
+        };
+        Ok(TaskProof {
+            proof,
+            transcript,
+            instances,
+            vk_hash: Some(ctx.vk_hash().to_string()),
+            evm: None,
+            verification,
+            metadata: Some(self.metadata.clone()),
+            proving_time_us: Some(proving_time.as_micros() as u64),
+            circuit: Some(CircuitInfo {
+                name: "aggregation".to_string(),
+                k: ctx.k(),
+            }),
+        })
+    }
+
+    /// Checks the native proof of `detail` with the key of its proof type.
+    ///
+    /// A proof that fails says so along with how the build that produced it differs from
+    /// this one, see [`ProverState::metadata_mismatches`].
+    pub fn verify(&self, detail: &ProofDetail) -> Result<(), String> {
+        self.verify_proof(detail)
+            .map_err(|err| match self.metadata_mismatches(detail).as_slice() {
+                [] => err,
+                mismatches => format!("{err} (built differently: {})", mismatches.join("; ")),
+            })
+    }
+
+    /// Answers a request to verify `detail`: the detail with the outcome of
+    /// [`ProverState::verify`] as its `verification`, and why the proof is rejected as its
+    /// `error` with the verify stage failed.
+    pub fn check(&self, detail: &ProofDetail) -> ProofDetail {
+        let start = Instant::now();
+        let verified = self.verify(detail);
+        let verification = Some(Verification {
+            verified: verified.is_ok(),
+            duration_us: start.elapsed().as_micros() as u64,
+        });
+        match verified {
+            Ok(()) => ProofDetail {
+                verification,
+                error: String::new(),
+                error_code: String::new(),
+                failed_stage: None,
+                ..detail.clone()
+            },
+            Err(err) => ProofDetail {
+                verification,
+                ..detail.clone()
+            }
+            .with_error(Stage::Verify, &crate::Error::VerifyFailed { message: err }),
+        }
+    }
+
+    /// The metadata of the proofs of this build, all of which hash with [`BN256_T4_R3`].
+    pub fn metadata(&self) -> &ProofMetadata {
+        &self.metadata
+    }
+
+    /// Latencies of the stages of the tasks proven so far, keygen including hits of the key
+    /// cache, and the tasks and errors counted by [`ProverState::prove`].
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// How the build that produced the proof of `detail` differs from this one; empty for
+    /// proofs without metadata.
+    pub fn metadata_mismatches(&self, detail: &ProofDetail) -> Vec<String> {
+        detail
+            .metadata
+            .as_ref()
+            .map(|metadata| metadata.mismatches(&self.metadata))
+            .unwrap_or_default()
+    }
+
+    fn verify_proof(&self, detail: &ProofDetail) -> Result<(), String> {
+        let label = self.protocol_label().unwrap_or_default();
+        if detail.protocol_label != label {
+            return Err(format!(
+                "proof is for protocol {:?}, this verifier checks {label:?}",
+                detail.protocol_label
+            ));
+        }
+        let mut proof = BS64
+            .decode(&detail.proof_data)
+            .map_err(|err| format!("proof_data is not base64: {err}"))?;
+        if let Some(compression) = detail.compression {
+            proof = compression
+                .decompress(&proof)
+                .map_err(|err| err.to_string())?;
+        }
+        let values = parse_fields::<Fr, _>(&detail.instances).map_err(|err| err.to_string())?;
+        let transcript = detail.transcript.unwrap_or_default();
+        #[cfg(feature = "aggregation")]
+        if detail.proof_type == ProofType::Batch && values.len() > ACCUMULATOR_INSTANCES {
+            let aggregator = self
+                .aggregator
+                .as_ref()
+                .ok_or("this verifier has no aggregation keys")?;
+            return aggregator
+                .verify(&proof, &values, transcript)
+                .map_err(|err| err.to_string());
+        }
+        let columns = detail.instance_layout.columns(&values);
+        let instances = columns.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let vk_hash = &detail.vk_hash;
+        let (proof, instances) = (proof.as_slice(), instances.as_slice());
+        match detail.proof_type {
+            ProofType::Preimage => {
+                self.verify_on(&self.preimage, vk_hash, transcript, proof, instances)
+            }
+            ProofType::Range => {
+                self.verify_on(&self.range_proof, vk_hash, transcript, proof, instances)
+            }
+            ProofType::Membership => {
+                self.verify_on(&self.membership, vk_hash, transcript, proof, instances)
+            }
+            _ => self.verify_on(&self.test_circuit, vk_hash, transcript, proof, instances),
+        }
+    }
+
+    /// Verifies with the key of `ctx`, or with the historical key `vk_hash` if it is another.
+    fn verify_on<C: Circuit<Fr>>(
+        &self,
+        ctx: &ProverContext<C>,
+        vk_hash: &str,
+        transcript: TranscriptHash,
+        proof: &[u8],
+        instances: &[&[Fr]],
+    ) -> Result<(), String> {
+        if vk_hash.is_empty() || vk_hash.eq_ignore_ascii_case(ctx.vk_hash()) {
+            return ctx
+                .verify_in(transcript, proof, instances)
+                .map_err(|err| format!("{err:?}"));
+        }
+        let store = self
+            .vk_store
+            .as_ref()
+            .ok_or_else(|| format!("unknown verifying key {vk_hash}"))?;
+        let vk = store
+            .get::<C>(vk_hash)
+            .map_err(|err| format!("cannot load verifying key {vk_hash}: {err}"))?;
+        ctx.verify_with_vk_in(transcript, &vk, proof, instances)
+            .map_err(|err| format!("{err:?}"))
+    }
+
+    /// Verifies proofs recording another verifying key than the current ones with the keys
+    /// in `dir`, keeping the last `capacity` of them deserialized.
+    pub fn with_vk_store(mut self, dir: impl Into<PathBuf>, capacity: usize) -> Self {
+        self.vk_store = Some(VkStore::new(dir, capacity));
+        self
+    }
+
+    /// As [`ProverState::with_vk_store`], reading the keys from `store`, e.g. the bucket
+    /// [`crate::keygen::keygen_all`] put them into.
+    pub fn with_artifact_store(mut self, store: Arc<dyn ArtifactStore>, capacity: usize) -> Self {
+        self.vk_store = Some(VkStore::from_store(store, capacity));
+        self
+    }
+
+    /// Proves tasks that do not name a transcript in it, Blake2b by default.
+    pub fn with_transcript(mut self, transcript: TranscriptHash) -> Self {
+        self.transcript = transcript;
+        self
+    }
+
+    /// The transcript `task` is proven in.
+    pub fn transcript(&self, task: &Task) -> TranscriptHash {
+        task.transcript.unwrap_or(self.transcript)
+    }
+
+    /// Binds every proof of the service to `label`, see [`ProverContext::with_protocol_label`].
+    pub fn with_protocol_label(self, label: &str) -> Self {
+        Self {
+            test_circuit: self.test_circuit.with_protocol_label(label),
+            preimage: self.preimage.with_protocol_label(label),
+            range_proof: self.range_proof.with_protocol_label(label),
+            membership: self.membership.with_protocol_label(label),
+            ..self
+        }
+    }
+
+    /// Aggregates the chunk proofs batch tasks send as
+    /// `{"chunk_proofs": [{"proof": base64, "instances": [digest]}, ...]}` with keys on
+    /// `params`, which need to be large enough for the aggregation circuit.
+    ///
+    /// Chunk proofs are of the chunk keys of this state in the transcript of
+    /// [`crate::aggregation::prove_chunk`]; a salted or labelled state cannot aggregate.
+    #[cfg(feature = "aggregation")]
+    pub fn with_aggregation(mut self, params: ParamsKZG<Bn256>) -> Result<Self, AggregationError> {
+        self.aggregator = Some(Arc::new(Aggregator::new(params, &self.test_circuit, 1)?));
+        Ok(self)
+    }
+
+    pub fn protocol_label(&self) -> Option<&str> {
+        self.test_circuit.protocol_label()
+    }
+
+    pub fn limits(&self) -> &MessageLimits {
+        &self.limits
+    }
+
+    /// The verifying key of the circuit named `circuit` in the capability document, for
+    /// verifiers outside the service; `None` for unknown names.
+    pub fn export_vk(&self, circuit: &str) -> Option<VkExport> {
+        Some(match circuit {
+            "test_circuit" => VkExport::new(circuit, &self.test_circuit),
+            "preimage" => VkExport::new(circuit, &self.preimage),
+            "range_proof" => VkExport::new(circuit, &self.range_proof),
+            "membership" => VkExport::new(circuit, &self.membership),
+            _ => return None,
+        })
+    }
+
+    /// [`crate::prover::vk_hash`] of the current key of a circuit, named as for
+    /// [`ProverState::export_vk`].
+    pub fn vk_hash_of(&self, circuit: &str) -> Option<&str> {
+        Some(match circuit {
+            "test_circuit" => self.test_circuit.vk_hash(),
+            "preimage" => self.preimage.vk_hash(),
+            "range_proof" => self.range_proof.vk_hash(),
+            "membership" => self.membership.vk_hash(),
+            _ => return None,
+        })
+    }
+
+    pub fn test_circuit(&self) -> &ProverContext<TestCircuit<Fr>> {
+        &self.test_circuit
+    }
+
+    pub fn preimage(&self) -> &ProverContext<PreimageCircuit<Fr>> {
+        &self.preimage
+    }
+
+    pub fn range_proof(&self) -> &ProverContext<RangeProofCircuit<Fr>> {
+        &self.range_proof
+    }
+
+    pub fn membership(&self) -> &ProverContext<MembershipCircuit<Fr>> {
+        &self.membership
+    }
+}
+
+/// Proves and verifies `circuit` on `ctx`, with a Keccak proof as well if the task asks for one.
+
+/// `value` as an integer, if it is small enough.
+/// The chunk proofs of a batch task to aggregate, or `None` for a task sending a message.
+#[cfg(feature = "aggregation")]
+fn parse_chunk_proofs(task_data: &str) -> Option<Result<Vec<ChunkSnark>, String>> {
+    #[derive(serde::Deserialize)]
+    struct ChunkProof {
+        proof: String,
+        instances: Vec<String>,
+    }
+    #[derive(serde::Deserialize)]
+    struct ChunkProofs {
+        chunk_proofs: Vec<ChunkProof>,
+    }
+
+    let value = serde_json::from_str::<serde_json::Value>(task_data).ok()?;
+    value.get("chunk_proofs")?;
+    let parsed = serde_json::from_value::<ChunkProofs>(value)
+        .map_err(|err| format!("invalid chunk_proofs: {err}"))
+        .and_then(|data| {
+            if data.chunk_proofs.is_empty() {
+                return Err("chunk_proofs is empty".to_string());
+            }
+            data.chunk_proofs
+                .into_iter()
+                .enumerate()
+                .map(|(index, chunk)| {
+                    Ok(ChunkSnark {
+                        proof: BS64.decode(&chunk.proof).map_err(|err| {
+                            format!("proof of chunk {index} is not base64: {err}")
+                        })?,
+                        instances: parse_fields::<Fr, _>(&chunk.instances)
+                            .map_err(|err| format!("instances of chunk {index}: {err}"))?,
+                    })
+                })
+                .collect()
+        });
+    Some(parsed)
+}
+
+/// The wire form of a failure of `MockProver`.
+/// Fails the task at `stage` if `cancel` stopped it.
+fn check_cancel(stage: Stage, cancel: &CancelToken) -> Result<(), TaskError> {
+    cancel.check().map_err(|err| TaskError::limit(stage, err))
+}
+
+fn constraint_failure(failure: &VerifyFailure) -> ConstraintFailure {
+    let located = |kind: &str, gate: String, location: &FailureLocation| {
+        let (region, row) = match location {
+            FailureLocation::InRegion { region, offset } => (region.to_string(), *offset),
+            FailureLocation::OutsideRegion { row } => (String::new(), *row),
+        };
+        ConstraintFailure {
+            kind: kind.to_string(),
+            gate,
+            region,
+            row: Some(row),
+            message: failure.to_string(),
+        }
+    };
+    match failure {
+        VerifyFailure::ConstraintNotSatisfied {
+            constraint,
+            location,
+            ..
+        } => located("constraint", constraint.to_string(), location),
+        VerifyFailure::Lookup {
+            lookup_index,
+            location,
+            ..
+        } => located("lookup", format!("lookup {lookup_index}"), location),
+        VerifyFailure::Permutation { column, location } => {
+            located("permutation", column.to_string(), location)
+        }
+        VerifyFailure::CellNotAssigned {
+            gate,
+            region,
+            offset,
+            ..
+        } => ConstraintFailure {
+            kind: "cell_not_assigned".to_string(),
+            gate: gate.to_string(),
+            region: region.to_string(),
+            row: usize::try_from(*offset).ok(),
+            message: failure.to_string(),
+        },
+        VerifyFailure::ConstraintPoisoned { constraint } => ConstraintFailure {
+            kind: "poisoned".to_string(),
+            gate: constraint.to_string(),
+            message: failure.to_string(),
+            ..Default::default()
+        },
+        VerifyFailure::InstanceCellNotAssigned {
+            gate, region, row, ..
+        } => ConstraintFailure {
+            kind: "instance_not_assigned".to_string(),
+            gate: gate.to_string(),
+            region: region.to_string(),
+            row: Some(*row),
+            message: failure.to_string(),
+        },
+        #[allow(unreachable_patterns)]
+        _ => ConstraintFailure {
+            kind: "other".to_string(),
+            message: failure.to_string(),
+            ..Default::default()
+        },
+    }
+}
+
+fn to_u64(value: Fr) -> Option<u64> {
+    let repr = value.to_repr();
+    let (low, high) = repr.as_ref().split_at(8);
+    high.iter()
+        .all(|b| *b == 0)
+        .then(|| u64::from_le_bytes(low.try_into().expect("8 bytes")))
+}
+
+/// The trace cache of the spec of the service.
+type ServiceTraceCache = TraceCache<Fr, { BN256_T4_R3.width }, { BN256_T4_R3.rate }>;
+
+/// The longest message that fits into `2^k` rows; deriving the spec is the costly part.
+fn default_spec() -> Spec<Fr, { BN256_T4_R3.width }, { BN256_T4_R3.rate }> {
+    Spec::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p)
+}
+
+/// The circuit answering tasks of `proof_type`, named as in the capability document.
+fn circuit_info(proof_type: ProofType, k: u32) -> CircuitInfo {
+    let name = match proof_type {
+        ProofType::Preimage => "preimage",
+        ProofType::Range => "range_proof",
+        ProofType::Membership => "membership",
+        ProofType::Undefined | ProofType::Chunk | ProofType::Batch => "test_circuit",
+    };
+    CircuitInfo {
+        name: name.to_string(),
+        k,
+    }
+}
+
+/// Joins a scoped thread, re-raising its panic on the caller.
+fn join<T>(handle: thread::ScopedJoinHandle<'_, T>) -> T {
+    handle
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, sync::OnceLock};
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{artifacts::MemoryStore, compression::ProofCompression, proof_store::ProofStore};
+
+    #[cfg(feature = "aggregation")]
+    #[test]
+    fn test_chunk_proofs_task() {
+        // tasks sending a message are left to the preprocessors
+        assert!(parse_chunk_proofs("[1, 2]").is_none());
+        assert!(parse_chunk_proofs(r#"{"inputs": [1], "digest": "2"}"#).is_none());
+        assert!(parse_chunk_proofs(r#"{"chunk_proofs": []}"#)
+            .unwrap()
+            .is_err());
+        assert!(
+            parse_chunk_proofs(r#"{"chunk_proofs": [{"proof": "@", "instances": []}]}"#)
+                .unwrap()
+                .is_err()
+        );
+        let data = r#"{"chunk_proofs": [{"proof": "AQI=", "instances": ["0x05"]}]}"#;
+        assert_eq!(
+            parse_chunk_proofs(data).unwrap().unwrap(),
+            vec![ChunkSnark {
+                instances: vec![Fr::from(5)],
+                proof: vec![1, 2],
+            }]
+        );
+
+        let state = ProverState::new(10).unwrap();
+        let err = state
+            .prove(&Task {
+                id: "t".to_string(),
+                task_type: ProofType::Batch,
+                task_data: data.to_string(),
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert_eq!(err.stage, Stage::Witness);
+        assert!(
+            err.error.to_string().contains("aggregation keys"),
+            "{}",
+            err.error
+        );
+        assert!(state
+            .with_salt(Fr::from(7))
+            .with_aggregation(ParamsKZG::setup(10, OsRng))
+            .is_err());
+    }
+
+    #[test]
+    fn test_prove_task() {
+        let state = ProverState::new(10).unwrap();
+        let task = |task_type, task_data: &str| Task {
+            id: "t".to_string(),
+            task_type,
+            task_data: task_data.to_string(),
+            ..Default::default()
+        };
+
+        let chunk = task(
+            ProofType::Chunk,
+            r#"{"inputs": [0, 1, 2, 3, "4"], "digest": "20304616028358001435806807494046171997958789835068077254356069730773893150537"}"#,
+        );
+        let proof = state.prove(&chunk).unwrap();
+        assert_eq!(proof.vk_hash, None);
+        let out_hash = Fr::from_str_vartime(
+            "20304616028358001435806807494046171997958789835068077254356069730773893150537",
+        )
+        .unwrap();
+        assert_eq!(proof.instances, vec![out_hash]);
+        let detail = proof.detail(&chunk);
+        assert_eq!(state.verify(&detail), Ok(()));
+        assert_eq!(detail.proof_size, Some(proof.proof.len()));
+        assert!(detail.proving_time_us.is_some());
+        assert_eq!(
+            detail.circuit,
+            Some(CircuitInfo {
+                name: "test_circuit".to_string(),
+                k: 10,
+            })
+        );
+
+        // compressed proofs verify as they are
+        let compressed = Task {
+            compression: Some(ProofCompression::Gzip),
+            ..chunk.clone()
+        };
+        match state.prove(&compressed) {
+            Ok(proof) => {
+                let detail = proof.detail(&compressed);
+                assert_eq!(detail.compression, Some(ProofCompression::Gzip));
+                assert_eq!(detail.proof_size, Some(proof.proof.len()));
+                assert_eq!(state.verify(&detail), Ok(()));
+            }
+            Err(err) => {
+                assert!(cfg!(not(feature = "gzip")));
+                assert_eq!(err.stage, Stage::Witness);
+            }
+        }
+
+        // another message length needs keys of its own
+        let short = task(ProofType::Batch, "[1]");
+        let proof = state.prove(&short).unwrap();
+        assert!(proof.vk_hash.is_some());
+        assert_ne!(
+            proof.vk_hash.as_deref(),
+            Some(state.test_circuit().vk_hash())
+        );
+        let metrics = state.metrics().render();
+        assert!(metrics.contains("poseidon_prover_tasks_total 2\n"));
+        assert!(metrics.contains("poseidon_prover_stage_seconds_count{stage=\"keygen\"} 1\n"));
+        assert!(metrics.contains("poseidon_prover_stage_seconds_count{stage=\"prove\"} 2\n"));
+        // and reuses them for the next message of that length
+        let again = state.prove(&task(ProofType::Chunk, "[2]")).unwrap();
+        assert_eq!(again.vk_hash, proof.vk_hash);
+        assert_eq!(state.cached_keys(), 1);
+
+        // a blob hashes under its own domain, with keys of their own
+        let blob = Task {
+            preprocessor: Some("hex".to_string()),
+            ..task(ProofType::Chunk, "0x0102")
+        };
+        let proof = state.prove(&blob).unwrap();
+        let spec = Spec::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p);
+        let packed = crate::packing::pack_bytes(&[1, 2]);
+        assert_eq!(
+            proof.instances,
+            [Hash::<Fr, 4, 3>::new(spec).hash_fixed(&packed)]
+        );
+        assert_eq!(state.cached_keys(), 2);
+        for preprocessor in ["yaml", "hex"] {
+            let err = state
+                .prove(&Task {
+                    preprocessor: Some(preprocessor.to_string()),
+                    ..task(ProofType::Preimage, "[42]")
+                })
+                .unwrap_err();
+            assert_eq!(err.stage, Stage::Witness, "{preprocessor}");
+        }
+        let unknown = Task {
+            preprocessor: Some("yaml".to_string()),
+            ..task(ProofType::Chunk, "[1]")
+        };
+        assert!(state
+            .prove(&unknown)
+            .unwrap_err()
+            .error
+            .to_string()
+            .contains("yaml"));
+
+        let preimage = task(ProofType::Preimage, "[42]");
+        let detail = state.prove(&preimage).unwrap().detail(&preimage);
+        assert_eq!(state.verify(&detail), Ok(()));
+        assert!(detail.verification.is_some_and(|v| v.verified));
+        assert_eq!(detail.metadata.as_ref(), Some(state.metadata()));
+        // a proof of another build that fails says how the build differs
+        let foreign = ProofDetail {
+            instances: vec![to_canonical(&Fr::from(43))],
+            metadata: Some(ProofMetadata {
+                crate_version: "0.0.1".to_string(),
+                ..state.metadata().clone()
+            }),
+            ..detail.clone()
+        };
+        assert_eq!(state.metadata_mismatches(&foreign).len(), 1);
+        assert!(state
+            .verify(&foreign)
+            .unwrap_err()
+            .contains("crate_version: proof 0.0.1"));
+        // the exported key is the one the proof verifies with
+        let export = state.export_vk("preimage").unwrap();
+        assert_eq!(export.vk_hash(), state.preimage().vk_hash());
+        let vk = export.verifying_key::<PreimageCircuit<Fr>>().unwrap();
+        assert_eq!(crate::prover::vk_hash(&vk), state.preimage().vk_hash());
+        assert!(state.export_vk("chunk").is_none());
+        // verification requests answer with the outcome rather than an error
+        let checked = state.check(&detail);
+        assert!(checked.verification.is_some_and(|v| v.verified));
+        assert_eq!((checked.error.as_str(), checked.failed_stage), ("", None));
+        let rejected = state.check(&foreign);
+        assert!(rejected.verification.is_some_and(|v| !v.verified));
+        assert_eq!(rejected.failed_stage, Some(Stage::Verify));
+        assert_eq!(rejected.error, state.verify(&foreign).unwrap_err());
+        assert_eq!(rejected.error_code, "verify_failed");
+
+        for (task_type, task_data) in [
+            (ProofType::Chunk, "{}"),
+            (ProofType::Chunk, "[-1]"),
+            (ProofType::Chunk, "{\"inputs\": [1], \"digest\": \"0x1\"}"),
+            (ProofType::Preimage, "[1, 2]"),
+            (ProofType::Membership, "[1, \"x\"]"),
+        ] {
+            let err = state.prove(&task(task_type, task_data)).unwrap_err();
+            assert_eq!(err.stage, Stage::Witness, "{task_data}");
+        }
+        // a message over capacity is turned down with the limit before any synthesis
+        let max = state.limits().max_hash_len;
+        let long = format!("{:?}", (0..=max).collect::<Vec<_>>());
+        let err = state.prove(&task(ProofType::Chunk, &long)).unwrap_err();
+        assert_eq!(err.stage, Stage::Witness);
+        assert!(err.error.to_string().contains(&format!("at most {max}")));
+        assert_eq!(state.cached_keys(), 2);
+        // as is a membership proof, with two instances, in the column-major layout
+        let opening = format!("{:?}", vec![0; 2 + MEMBERSHIP_DEPTH]);
+        let column_major = Task {
+            instance_layout: InstanceLayout::ColumnMajor,
+            ..task(ProofType::Membership, &opening)
+        };
+        let err = state.prove(&column_major).unwrap_err();
+        assert_eq!(err.stage, Stage::Witness);
+        assert!(err.error.to_string().contains("row_major"));
+
+        // a value out of range fails to prove rather than yielding an invalid proof
+        let err = state
+            .prove(&task(ProofType::Range, "[\"0x10000000000000000\", 1]"))
+            .unwrap_err();
+        assert!(matches!(err.stage, Stage::Prove | Stage::Verify));
+
+        // tasks pick their transcript, the others get the service's, and verifiers follow
+        // the one recorded in the proof detail
+        let poseidon = Task {
+            transcript: Some(TranscriptHash::Poseidon),
+            ..preimage.clone()
+        };
+        let proof = state.prove(&poseidon).unwrap();
+        assert_eq!(proof.transcript, TranscriptHash::Poseidon);
+        let mut detail = proof.detail(&poseidon);
+        assert_eq!(detail.transcript, Some(TranscriptHash::Poseidon));
+        assert_eq!(state.verify(&detail), Ok(()));
+        detail.transcript = None;
+        assert!(state.verify(&detail).is_err());
+        let state = state.with_transcript(TranscriptHash::Keccak256);
+        let proof = state.prove(&preimage).unwrap();
+        assert_eq!(proof.transcript, TranscriptHash::Keccak256);
+        assert_eq!(state.verify(&proof.detail(&preimage)), Ok(()));
+        assert_eq!(state.transcript(&poseidon), TranscriptHash::Poseidon);
+        let state = state.with_transcript(TranscriptHash::Blake2b);
+        assert_eq!(
+            state.prove(&preimage).unwrap().detail(&preimage).transcript,
+            None
+        );
+
+        // the budget is checked against the estimate before proving
+        let size = state.preimage().proof_size();
+        let state = state.with_max_proof_size(size);
+        assert!(state.prove(&preimage).is_ok());
+        let evm = Task {
+            evm: true,
+            ..preimage.clone()
+        };
+        let err = state.prove(&evm).unwrap_err();
+        assert_eq!(err.stage, Stage::Witness);
+        assert!(err
+            .error
+            .to_string()
+            .contains(&format!("{} bytes", size + 32)));
+
+        // interactive preimage proofs: trimmed params and a key of their own, still verified
+        // before they are returned
+        let standard_vk = state.preimage().vk_hash().to_string();
+        let state = state
+            .with_latency_profile(LatencyProfile::Interactive)
+            .unwrap();
+        assert_eq!(state.preimage().k(), PreimageCircuit::<Fr>::min_k());
+        assert_ne!(state.preimage().vk_hash(), standard_vk);
+        let proof = state.prove(&preimage).unwrap();
+        assert_eq!(proof.verification.map(|v| v.verified), Some(true));
+        assert_eq!(state.verify(&proof.detail(&preimage)), Ok(()));
+        let state = state
+            .with_latency_profile(LatencyProfile::Standard)
+            .unwrap();
+        assert_eq!(state.preimage().vk_hash(), standard_vk);
+
+        let state = state.with_sanity_check(false);
+        let proof = state.prove(&preimage).unwrap();
+        assert_eq!(proof.verification, None);
+        assert_eq!(state.verify(&proof.detail(&preimage)), Ok(()));
+    }
+
+    #[test]
+    fn test_hash_k() {
+        let state = ProverState::new(10).unwrap();
+        let chunk = |len: u64| Task {
+            id: "t".to_string(),
+            task_type: ProofType::Chunk,
+            task_data: format!("{:?}", (0..len).collect::<Vec<_>>()),
+            ..Default::default()
+        };
+        let k = |len| cost::estimate(&default_spec(), 1, len).k;
+        // a short message is proven at the smallest k it fits into
+        assert!(k(1) < state.test_circuit().k());
+        state.prove(&chunk(1)).unwrap();
+        // and a long one above the k of the service, as long as the params hold it
+        assert!(k(20) > state.test_circuit().k());
+        state.prove(&chunk(20)).unwrap();
+        let sized = state.sized_params.lock().unwrap();
+        assert_eq!(
+            sized.keys().copied().collect::<BTreeSet<_>>(),
+            [k(1), k(20)].into()
+        );
+        drop(sized);
+
+        let limits = MessageLimits {
+            max_hash_len: 1000,
+            max_task_len: 1000,
+        };
+        let state = state.with_limits(limits);
+        let err = state.prove(&chunk(200)).unwrap_err();
+        assert_eq!(err.stage, Stage::Keygen);
+        assert_eq!(
+            err.error,
+            crate::Error::SrsTooSmall {
+                k: 13,
+                needed: Some(k(200))
+            }
+        );
+        let expected = format!(
+            "needs params of 2^{} rows, the loaded params have 2^13",
+            k(200)
+        );
+        assert!(err.error.to_string().contains(&expected), "{}", err.error);
+    }
+
+    #[test]
+    fn test_proof_types() {
+        let state = ProverState::new(10)
+            .unwrap()
+            .with_proof_types(vec![ProofType::Chunk, ProofType::Preimage]);
+        assert!(state.accepts(ProofType::Undefined));
+        assert!(!state.accepts(ProofType::Membership));
+        let task = |task_type, task_data: &str| Task {
+            id: "t".to_string(),
+            task_type,
+            task_data: task_data.to_string(),
+            dry_run: true,
+            ..Default::default()
+        };
+        assert!(state.prove(&task(ProofType::Preimage, "[42]")).is_ok());
+        let err = state.prove(&task(ProofType::Range, "[1, 2]")).unwrap_err();
+        assert_eq!(err.stage, Stage::Witness);
+        assert!(err.error.to_string().contains("range tasks are disabled"));
+        assert_eq!(state.capabilities().proof_types, vec![ProofType::Preimage]);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let state = ProverState::new(10).unwrap();
+        let task = |task_type, task_data: &str| Task {
+            id: "t".to_string(),
+            task_type,
+            task_data: task_data.to_string(),
+            dry_run: true,
+            ..Default::default()
+        };
+        let preimage = task(ProofType::Preimage, "[42]");
+        let proof = state.prove(&preimage).unwrap();
+        assert!(proof.proof.is_empty());
+        assert_eq!(proof.instances.len(), 1);
+        // messages of other lengths are checked without generating their keys
+        assert!(state.prove(&task(ProofType::Chunk, "[1]")).is_ok());
+        assert_eq!(state.cached_keys(), 0);
+
+        // a witness that does not match its instances reports where it fails
+        let (circuit, _) = Preimage::witness_from(Fr::from(42));
+        let err = state
+            .dry_run_on(
+                state.preimage().k(),
+                &circuit,
+                vec![Fr::from(43)],
+                &preimage,
+                &CancelToken::new(),
+            )
+            .unwrap_err();
+        assert_eq!(err.stage, Stage::Prove);
+        assert!(!err.constraint_failures.is_empty());
+        let failure = &err.constraint_failures[0];
+        assert_eq!(failure.kind, "permutation");
+        assert!(failure.row.is_some());
+        assert!(err.error.to_string().contains("fails"), "{}", err.error);
+    }
+
+    #[test]
+    fn test_prewarm() {
+        let dir = std::env::temp_dir().join(format!("prewarm-{}", std::process::id()));
+        // a single cached key, so that every new message length evicts the last one
+        let state = ProverState::new(10).unwrap().with_key_dir(&dir, 1).unwrap();
+        let hash = |task_data: &str| {
+            let task = Task {
+                id: "t".to_string(),
+                task_type: ProofType::Chunk,
+                task_data: task_data.to_string(),
+                ..Default::default()
+            };
+            state.prove(&task).unwrap()
+        };
+        for task_data in ["[1]", "[2]", "[0, 1, 2, 3, 4]", "[1, 2]"] {
+            hash(task_data);
+        }
+        let sizes = state.task_sizes();
+        assert_eq!(sizes.len(), 3);
+        assert_eq!((sizes[0].0.len, sizes[0].1), (1, 2));
+        assert_eq!(sizes[0].0.domain, Domain::Pse);
+        assert!(state.idle_for().is_some());
+
+        // the message of two elements evicted the keys of the most frequent length
+        let prewarmed = state.prewarm(8).unwrap();
+        assert_eq!(prewarmed, [sizes[0].0.clone()]);
+        assert_eq!(state.cached_keys(), 1);
+        assert!(state.prewarm(8).unwrap().is_empty());
+        let running = Running::start(&state.activity);
+        assert_eq!(state.idle_for(), None);
+        drop(running);
+        assert!(state.idle_for().is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trace_cache() {
+        let state = ProverState::new(10).unwrap().with_trace_cache(16);
+        let hash = |task_data: &str| {
+            let task = Task {
+                id: "t".to_string(),
+                task_type: ProofType::Chunk,
+                task_data: task_data.to_string(),
+                ..Default::default()
+            };
+            state.prove(&task).unwrap()
+        };
+        hash("[1, 2, 3, 4, 5]");
+        let misses = state.trace_cache_stats().unwrap().misses;
+        assert!(misses >= 2);
+        // the first chunk is shared, the second is not
+        let second = hash("[1, 2, 3, 9, 9]");
+        let stats = state.trace_cache_stats().unwrap();
+        assert!(stats.hits >= 1 && stats.misses > misses);
+        let spec = Spec::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p);
+        let message = [1, 2, 3, 9, 9].map(Fr::from);
+        assert_eq!(
+            second.instances,
+            [Hash::<Fr, 4, 3>::new(spec).hash_with_domain(Domain::Pse, &message)]
+        );
+        assert!(ProverState::new(10).unwrap().trace_cache_stats().is_none());
+    }
+
+    #[test]
+    fn test_proof_generation() {
+        let state = ProverState::new(10).unwrap();
+        let task = Task {
+            uuid: "a".to_string(),
+            id: "t".to_string(),
+            task_type: ProofType::Chunk,
+            task_data: "[1, 2]".to_string(),
+            ..Default::default()
+        };
+        let detail = ProofDetail {
+            id: task.id.clone(),
+            proof_data: "AAEC".to_string(),
+            ..Default::default()
+        };
+
+        // provers of two salts share a store without answering each other's tasks
+        let unsalted = state.proof_generation();
+        let state = state.with_salt(Fr::from(1));
+        let staging = state.proof_generation();
+        let state = state.with_salt(Fr::from(2));
+        let production = state.proof_generation();
+        assert!(staging.starts_with(state.key_generation()));
+        let store: Arc<dyn ArtifactStore> = Arc::new(MemoryStore::new());
+        let staging_proofs = ProofStore::new(store.clone(), &staging);
+        let production_proofs = ProofStore::new(store, &production);
+        assert!(staging_proofs.record(&task, &detail).unwrap());
+        assert!(staging_proofs.lookup(&task).unwrap().is_some());
+        assert_eq!(production_proofs.lookup(&task).unwrap(), None);
+
+        // nor do those of another label or default transcript
+        let state = state.with_protocol_label("acme/v1");
+        let labelled = state.proof_generation();
+        let state = state.with_transcript(TranscriptHash::Keccak256);
+        let keccak = state.proof_generation();
+        assert_eq!(keccak, state.proof_generation());
+        let generations = [unsalted, staging, production, labelled, keccak];
+        assert_eq!(generations.iter().collect::<BTreeSet<_>>().len(), 5);
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let state = ProverState::new(10).unwrap();
+        let task = |task_type, task_data: &str| Task {
+            id: "t".to_string(),
+            task_type,
+            task_data: task_data.to_string(),
+            dry_run: true,
+            ..Default::default()
+        };
+        let preimage = task(ProofType::Preimage, "[42]");
+        let k = state.preimage().k();
+        let state = state.with_max_k(k - 1);
+        let err = state.prove(&preimage).unwrap_err();
+        assert_eq!(err.stage, Stage::Witness);
+        assert_eq!(err.error.code(), "resource_limit_exceeded");
+        assert_eq!(
+            err.error,
+            crate::Error::limit(LimitError::KTooLarge { k, max: k - 1 })
+        );
+
+        let state = state.with_max_k(k).with_timeout(Duration::ZERO);
+        let err = state.prove(&preimage).unwrap_err();
+        assert_eq!(
+            err.error,
+            crate::Error::limit(LimitError::TimedOut {
+                timeout: Duration::ZERO
+            })
+        );
+
+        let state = state.with_timeout(Duration::from_secs(3600));
+        let cancel = CancelToken::new();
+        let chunk = task(ProofType::Chunk, "[1, 2]");
+        assert!(state.prove_with(&chunk, &cancel).is_ok());
+        cancel.cancel();
+        let err = state.prove_with(&chunk, &cancel).unwrap_err();
+        assert_eq!(err.stage, Stage::Prove);
+        assert_eq!(err.error, crate::Error::limit(LimitError::Cancelled));
+    }
+
+    /// A prover and the proof detail of a preimage task, proven once for every case.
+    fn preimage_proof() -> &'static (ProverState, ProofDetail) {
+        static PROOF: OnceLock<(ProverState, ProofDetail)> = OnceLock::new();
+        PROOF.get_or_init(|| {
+            let state = ProverState::new(10).unwrap();
+            let task = Task {
+                id: "t".to_string(),
+                task_type: ProofType::Preimage,
+                task_data: "[42]".to_string(),
+                ..Default::default()
+            };
+            let detail = state.prove(&task).unwrap().detail(&task);
+            (state, detail)
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        /// Corrupted and truncated proofs are rejected with an error, never a panic.
+        #[test]
+        fn test_corrupted_proof(
+            at in any::<prop::sample::Index>(),
+            mask in 1u8..,
+            truncate in any::<bool>(),
+        ) {
+            let (state, detail) = preimage_proof();
+            prop_assert_eq!(state.verify(detail), Ok(()));
+            let mut proof = BS64.decode(&detail.proof_data).unwrap();
+            let at = at.index(proof.len());
+            if truncate {
+                proof.truncate(at);
+            } else {
+                proof[at] ^= mask;
+            }
+            let corrupted = ProofDetail {
+                proof_data: BS64.encode(&proof),
+                ..detail.clone()
+            };
+            prop_assert!(state.verify(&corrupted).is_err());
+        }
+
+        /// So are proofs that are not base64, and instances that are not field elements.
+        #[test]
+        fn test_hostile_proof_detail(proof_data in ".{0,64}", instance in ".{0,80}") {
+            let (state, detail) = preimage_proof();
+            let hostile = ProofDetail {
+                proof_data,
+                ..detail.clone()
+            };
+            prop_assert!(state.verify(&hostile).is_err());
+            let hostile = ProofDetail {
+                instances: vec![instance],
+                ..detail.clone()
+            };
+            let _ = state.verify(&hostile);
+        }
+    }
+}
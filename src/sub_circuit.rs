@@ -0,0 +1,363 @@
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::{
+    circuit::Layouter,
+    plonk::{ConstraintSystem, Error},
+};
+use poseidon::Spec;
+
+use crate::{
+    main_gate::{AssignedValue, MainGate, MainGateConfig, RegionCtx},
+    poseidon_circuit::PoseidonChip,
+    specs::Domain,
+};
+
+/// What a [`synthesize_trace`] call assigned, for sizing the `k` of the embedding circuit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceSummary {
+    pub messages: usize,
+    /// Permutations over all messages, padding included
+    pub permutations: usize,
+    /// Rows of the region, [`PoseidonChip::num_rows_many`] of the message lengths
+    pub rows: usize,
+}
+
+/// Hashes `messages` under `domain` in one region of `layouter` and returns their digests,
+/// in order, with a summary of what they took.
+///
+/// This is the supported way for other circuits to embed the hasher: configure the main
+/// gate with [`MainGate::configure`], or through [`PoseidonSubCircuitConfig`], call this
+/// from `synthesize` and copy the digests wherever the circuit needs them. Each digest is
+/// that of [`crate::poseidon_hash::hash_with_domain`]. Messages the circuit assigned in its
+/// own cells go through [`crate::instructions::PoseidonInstructions`] instead.
+pub fn synthesize_trace<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    config: &MainGateConfig<T>,
+    spec: &Spec<F, T, RATE>,
+    domain: Domain,
+    layouter: &mut impl Layouter<F>,
+    messages: &[Vec<F>],
+) -> Result<(Vec<AssignedValue<F>>, TraceSummary), Error> {
+    let (hashes, rows) = layouter.assign_region(
+        || "poseidon hashes",
+        |region| {
+            let ctx = &mut RegionCtx::new(region, 0);
+            let chip = PoseidonChip::new(config.clone(), spec.clone()).with_domain(domain);
+            let hashes = chip.hash_many(ctx, messages)?;
+            Ok((hashes, ctx.offset()))
+        },
+    )?;
+    let summary = TraceSummary {
+        messages: messages.len(),
+        permutations: messages.iter().map(|msg| msg.len() / RATE + 1).sum(),
+        rows,
+    };
+    let digests = hashes.into_iter().map(|(_, digest)| digest).collect();
+    Ok((digests, summary))
+}
+
+/// Configuration of a circuit embedded into a larger super-circuit.
+pub trait SubCircuitConfig<F: PrimeField> {
+    /// Whatever the super-circuit shares with the sub-circuit at configure time
+    type ConfigArgs;
+
+    fn new(meta: &mut ConstraintSystem<F>, args: Self::ConfigArgs) -> Self;
+}
+
+/// A circuit that a super-circuit embeds next to others, in the style of the zkEVM
+/// `SubCircuit` trait.
+///
+/// The row budget contract: a super-circuit sizes its `k` from [`SubCircuit::min_num_rows`]
+/// plus [`SubCircuit::unusable_rows`], and [`SubCircuit::synthesize_sub`] never uses more rows
+/// than `min_num_rows` reported for the witness it was built from. When the witness does not
+/// fit into the budget it was given, synthesis fails instead of overflowing.
+pub trait SubCircuit<F: PrimeField> {
+    type Config: SubCircuitConfig<F> + Clone;
+    /// The data the circuit is built from, the analogue of the zkEVM `Block`
+    type Witness;
+
+    fn new_from_witness(witness: &Self::Witness) -> Self;
+
+    /// Rows at the end of the region that the proving system reserves for blinding.
+    fn unusable_rows() -> usize {
+        // blinding factors for a circuit querying every column at a single rotation, plus one
+        6
+    }
+
+    /// Returns `(rows used, rows required after padding)` for `witness`.
+    fn min_num_rows(witness: &Self::Witness) -> (usize, usize);
+
+    /// Public inputs exposed by this sub-circuit, one vector per instance column.
+    fn instance(&self) -> Vec<Vec<F>> {
+        vec![]
+    }
+
+    fn synthesize_sub(
+        &self,
+        config: &Self::Config,
+        layouter: &mut impl Layouter<F>,
+    ) -> Result<(), Error>;
+}
+
+#[derive(Clone, Debug)]
+pub struct PoseidonSubCircuitConfig<const T: usize> {
+    pub main_gate: MainGateConfig<T>,
+}
+
+impl<F: PrimeField, const T: usize> SubCircuitConfig<F> for PoseidonSubCircuitConfig<T> {
+    type ConfigArgs = ();
+
+    fn new(meta: &mut ConstraintSystem<F>, _args: ()) -> Self {
+        let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+        let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+        Self {
+            main_gate: MainGate::configure(meta, &mut adv_cols, &mut fix_cols),
+        }
+    }
+}
+
+/// Messages to hash and the budget the super-circuit grants for them.
+#[derive(Clone, Debug, Default)]
+pub struct PoseidonWitness<F: PrimeField> {
+    pub messages: Vec<Vec<F>>,
+    pub r_f: usize,
+    pub r_p: usize,
+    /// Rows the super-circuit reserves for the Poseidon sub-circuit
+    pub max_rows: usize,
+}
+
+/// Hashes every message of a [`PoseidonWitness`] inside one region.
+pub struct PoseidonSubCircuit<F: PrimeField, const T: usize, const RATE: usize> {
+    witness: PoseidonWitness<F>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    PoseidonSubCircuit<F, T, RATE>
+{
+    fn rows(witness: &PoseidonWitness<F>) -> usize {
+        let spec = Spec::<F, T, RATE>::new(witness.r_f, witness.r_p);
+        let lens = witness.messages.iter().map(Vec::len).collect::<Vec<_>>();
+        PoseidonChip::num_rows_many(&spec, &lens)
+    }
+
+    /// Assigns all messages and returns their digests, in order, for the super-circuit to
+    /// copy wherever it needs them.
+    pub fn assign(
+        &self,
+        config: &PoseidonSubCircuitConfig<T>,
+        layouter: &mut impl Layouter<F>,
+    ) -> Result<Vec<AssignedValue<F>>, Error> {
+        if Self::rows(&self.witness) > self.witness.max_rows {
+            return Err(Error::Synthesis);
+        }
+        let spec = Spec::<F, T, RATE>::new(self.witness.r_f, self.witness.r_p);
+        let messages = &self.witness.messages;
+        synthesize_trace(&config.main_gate, &spec, Domain::Pse, layouter, messages)
+            .map(|(digests, _)| digests)
+    }
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> SubCircuit<F>
+    for PoseidonSubCircuit<F, T, RATE>
+{
+    type Config = PoseidonSubCircuitConfig<T>;
+    type Witness = PoseidonWitness<F>;
+
+    fn new_from_witness(witness: &Self::Witness) -> Self {
+        Self {
+            witness: witness.clone(),
+        }
+    }
+
+    fn min_num_rows(witness: &Self::Witness) -> (usize, usize) {
+        let rows = Self::rows(witness);
+        (rows, rows.max(witness.max_rows))
+    }
+
+    fn synthesize_sub(
+        &self,
+        config: &Self::Config,
+        layouter: &mut impl Layouter<F>,
+    ) -> Result<(), Error> {
+        self.assign(config, layouter).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{
+        circuit::SimpleFloorPlanner,
+        dev::MockProver,
+        plonk::{Circuit, Column, Instance},
+    };
+    use halo2curves::pasta::Fp;
+
+    use super::*;
+
+    const T: usize = 3;
+    const RATE: usize = 2;
+    const R_F: usize = 4;
+    const R_P: usize = 3;
+    const K: u32 = 10;
+
+    type PoseidonSub = PoseidonSubCircuit<Fp, T, RATE>;
+
+    #[derive(Clone, Debug)]
+    struct SuperCircuitConfig {
+        poseidon: PoseidonSubCircuitConfig<T>,
+        instance: Column<Instance>,
+    }
+
+    /// A super-circuit exposing the digests of its Poseidon sub-circuit as public inputs.
+    struct SuperCircuit {
+        poseidon: PoseidonSub,
+    }
+
+    impl Circuit<Fp> for SuperCircuit {
+        type Config = SuperCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                poseidon: PoseidonSub::new_from_witness(&self.poseidon.witness),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            SuperCircuitConfig {
+                poseidon: PoseidonSubCircuitConfig::new(meta, ()),
+                instance,
+            }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let digests = self.poseidon.assign(&config.poseidon, &mut layouter)?;
+            for (row, digest) in digests.iter().enumerate() {
+                layouter.constrain_instance(digest.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn witness(max_rows: usize) -> PoseidonWitness<Fp> {
+        PoseidonWitness {
+            messages: vec![
+                (0..5).map(|i| Fp::from(i as u64)).collect(),
+                vec![Fp::from(42)],
+            ],
+            r_f: R_F,
+            r_p: R_P,
+            max_rows,
+        }
+    }
+
+    #[test]
+    fn test_embedded_sub_circuit() {
+        let spec = Spec::<Fp, T, RATE>::new(R_F, R_P);
+        let (used, padded) = PoseidonSub::min_num_rows(&witness(0));
+        // the second message copies the capacity row of the first
+        assert_eq!(
+            used,
+            PoseidonChip::num_rows(&spec, 5) + PoseidonChip::num_rows(&spec, 1) - 1
+        );
+        assert_eq!(padded, used);
+        assert!(used + PoseidonSub::unusable_rows() <= 1 << K);
+
+        // the digest of [0, 1, 2, 3, 4] under this spec, see the poseidon_circuit tests
+        let out_hash = Fp::from_str_vartime(
+            "13037709793114148810823325920380362524528554380279235267325741570708489436263",
+        )
+        .unwrap();
+        let mut hasher = poseidon::Poseidon::<Fp, T, RATE>::new(R_F, R_P);
+        hasher.update(&[Fp::from(42)]);
+        let second_hash = hasher.squeeze();
+
+        let circuit = SuperCircuit {
+            poseidon: PoseidonSub::new_from_witness(&witness(used)),
+        };
+        let prover = MockProver::run(K, &circuit, vec![vec![out_hash, second_hash]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_row_budget_exceeded() {
+        let (used, _) = PoseidonSub::min_num_rows(&witness(0));
+        let circuit = SuperCircuit {
+            poseidon: PoseidonSub::new_from_witness(&witness(used - 1)),
+        };
+        assert!(MockProver::run(K, &circuit, vec![vec![]]).is_err());
+    }
+
+    /// Exposes the digests of [`synthesize_trace`] under a user domain.
+    struct TraceCircuit {
+        messages: Vec<Vec<Fp>>,
+    }
+
+    impl Circuit<Fp> for TraceCircuit {
+        type Config = SuperCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                messages: self.messages.clone(),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            SuperCircuit::configure(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = Spec::<Fp, T, RATE>::new(R_F, R_P);
+            let main_gate = &config.poseidon.main_gate;
+            let (digests, summary) = synthesize_trace(
+                main_gate,
+                &spec,
+                Domain::User(3),
+                &mut layouter,
+                &self.messages,
+            )?;
+            let lens = self.messages.iter().map(Vec::len).collect::<Vec<_>>();
+            assert_eq!(
+                summary,
+                TraceSummary {
+                    messages: 3,
+                    permutations: 5,
+                    rows: PoseidonChip::num_rows_many(&spec, &lens),
+                }
+            );
+            for (row, digest) in digests.iter().enumerate() {
+                layouter.constrain_instance(digest.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_synthesize_trace() {
+        let spec = Spec::<Fp, T, RATE>::new(R_F, R_P);
+        let messages = vec![vec![Fp::from(1)], vec![], (0..4).map(Fp::from).collect()];
+        let digests = messages
+            .iter()
+            .map(|msg| crate::poseidon_hash::hash_with_domain(&spec, Domain::User(3), msg))
+            .collect::<Vec<_>>();
+        let circuit = TraceCircuit { messages };
+        let prover = MockProver::run(K, &circuit, vec![digests.clone()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        let pse = circuit
+            .messages
+            .iter()
+            .map(|msg| crate::poseidon_hash::hash(&spec, msg))
+            .collect();
+        let prover = MockProver::run(K, &circuit, vec![pse]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -0,0 +1,922 @@
+use std::{fmt, str::FromStr};
+
+use base64::{engine::general_purpose::STANDARD as BS64, Engine};
+use halo2curves::bn256::Fr;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    compression::ProofCompression,
+    error::Error,
+    instance_layout::InstanceLayout,
+    mem_stats::MemoryReport,
+    payload::{PayloadError, PayloadRef, PayloadSource},
+    prover::{encode_calldata, TranscriptHash},
+    schema::{self, SchemaError},
+    specs::SpecParams,
+    stage::Stage,
+};
+
+/// Largest JSON payload accepted by [`Task::from_json`].
+pub const MAX_TASK_BYTES: usize = 16 * 1024 * 1024;
+/// Largest accepted `uuid` / `id`.
+pub const MAX_ID_LEN: usize = 128;
+/// Largest accepted `hard_fork_name`.
+pub const MAX_HARD_FORK_NAME_LEN: usize = 64;
+/// Largest accepted `task_data`, leaving room for the rest of the envelope.
+pub const MAX_TASK_DATA_LEN: usize = MAX_TASK_BYTES - 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ProofType {
+    #[default]
+    Undefined,
+    Chunk,
+    Batch,
+    /// Knowledge of a Poseidon preimage, see [`crate::preimage`]
+    Preimage,
+    /// A committed value is in range, see [`crate::range_proof`]
+    Range,
+    /// A private leaf is in a public Merkle tree, see [`crate::membership`]
+    Membership,
+}
+
+impl ProofType {
+    /// Types named in configs, see [`ProofType::as_str`]
+    pub const ALL: [Self; 5] = [
+        Self::Chunk,
+        Self::Batch,
+        Self::Preimage,
+        Self::Range,
+        Self::Membership,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Undefined => "undefined",
+            Self::Chunk => "chunk",
+            Self::Batch => "batch",
+            Self::Preimage => "preimage",
+            Self::Range => "range",
+            Self::Membership => "membership",
+        }
+    }
+
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => ProofType::Chunk,
+            2 => ProofType::Batch,
+            3 => ProofType::Preimage,
+            4 => ProofType::Range,
+            5 => ProofType::Membership,
+            _ => ProofType::Undefined,
+        }
+    }
+}
+
+impl Serialize for ProofType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            ProofType::Undefined => serializer.serialize_i8(0),
+            ProofType::Chunk => serializer.serialize_i8(1),
+            ProofType::Batch => serializer.serialize_i8(2),
+            ProofType::Preimage => serializer.serialize_i8(3),
+            ProofType::Range => serializer.serialize_i8(4),
+            ProofType::Membership => serializer.serialize_i8(5),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ProofType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let v: u8 = u8::deserialize(deserializer)?;
+        Ok(ProofType::from_u8(v))
+    }
+}
+
+/// Represents the inputs to the Poseidon Circuit
+///
+/// This struct is designed to capture the necessary inputs for the
+/// Poseidon hash circuit. Tasks come from an untrusted queue, so every
+/// string field is bounded while it is deserialized.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Task {
+    /// The private_input vector, representing the hash input
+    ///
+    /// These inputs are part of the witness
+    #[serde(deserialize_with = "bounded_id")]
+    pub uuid: String,
+    #[serde(deserialize_with = "bounded_id")]
+    pub id: String,
+    #[serde(rename = "type", default)]
+    pub task_type: ProofType,
+    /// Inline payload; empty when it is sent as `task_data_ref`
+    #[serde(default, deserialize_with = "bounded_task_data")]
+    pub task_data: String,
+    /// Where to fetch `task_data` from when it is too large to send inline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_data_ref: Option<PayloadRef>,
+    #[serde(default, deserialize_with = "bounded_hard_fork_name")]
+    pub hard_fork_name: String,
+    /// Also return a proof for EVM verifiers, see [`ProofDetail::evm`]
+    #[serde(default)]
+    pub evm: bool,
+    /// Check the witness with `MockProver` instead of proving it; the answer has no proof,
+    /// and lists the [`ConstraintFailure`]s of a witness that does not satisfy the circuit
+    #[serde(default)]
+    pub dry_run: bool,
+    /// How the verifier expects the public values in instance columns
+    #[serde(default)]
+    pub instance_layout: InstanceLayout,
+    /// Transcript of the proof; the service's default, see
+    /// [`crate::state::ProverState::with_transcript`], when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<TranscriptHash>,
+    /// Where to POST the [`ProofDetail`] once the task is done, see [`crate::callback`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+    /// The batch this chunk belongs to, for services aggregating batches themselves, see
+    /// [`crate::batching`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<BatchRef>,
+    /// How chunk and batch tasks encode their message in `task_data`, see
+    /// [`crate::preprocess`]; the JSON of [`crate::task_data`] by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preprocessor: Option<String>,
+    /// Compression of the proof in the answer, see [`crate::compression`]; uncompressed when
+    /// unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<ProofCompression>,
+    /// Schema version the producer wrote, see [`crate::schema`]; parsed tasks carry the
+    /// current one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>,
+}
+
+/// Where a chunk task sits in its batch.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct BatchRef {
+    /// Id of the batch, and of the batch task aggregating it
+    #[serde(deserialize_with = "bounded_id")]
+    pub id: String,
+    /// Position of the chunk in the batch, from 0
+    pub index: usize,
+    /// Number of chunks in the batch; without it, the batch closes when its timer fires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+}
+
+/// Every field of a [`Task`] as sent on the wire.
+const TASK_FIELDS: &[&str] = &[
+    "uuid",
+    "id",
+    "type",
+    "task_data",
+    "task_data_ref",
+    "hard_fork_name",
+    "evm",
+    "dry_run",
+    "instance_layout",
+    "transcript",
+    "callback_url",
+    "batch",
+    "preprocessor",
+    "compression",
+    "schema_version",
+];
+/// Fields a strictly parsed task must carry; the others are opt-in. `task_data` may be
+/// replaced by `task_data_ref`.
+const REQUIRED_TASK_FIELDS: &[&str] = &["uuid", "id", "type", "task_data", "hard_fork_name"];
+
+/// How forgiving [`Task::from_json_with`] is about the set of fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Reject unknown fields and missing required ones instead of defaulting them
+    Strict,
+    /// Ignore unknown fields and default missing optional ones
+    #[default]
+    Lenient,
+}
+
+impl FromStr for ProofType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|proof_type| proof_type.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown proof type {s:?}, expected chunk, batch, preimage, range or \
+                     membership"
+                )
+            })
+    }
+}
+
+impl FromStr for ParseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            _ => Err(format!(
+                "unknown parse mode {s:?}, expected strict or lenient"
+            )),
+        }
+    }
+}
+
+impl Task {
+    /// Parses a task from raw queue bytes, rejecting oversized payloads before decoding.
+    pub fn from_json(bytes: &[u8]) -> Result<Self, TaskParseError> {
+        Self::from_json_with(bytes, ParseMode::Lenient)
+    }
+
+    /// Parses a task like [`Task::from_json`] in the given mode.
+    pub fn from_json_with(bytes: &[u8], mode: ParseMode) -> Result<Self, TaskParseError> {
+        if bytes.len() > MAX_TASK_BYTES {
+            return Err(TaskParseError::TooLarge {
+                len: bytes.len(),
+                max: MAX_TASK_BYTES,
+            });
+        }
+        Self::from_value(
+            serde_json::from_slice(bytes).map_err(TaskParseError::Json)?,
+            mode,
+        )
+    }
+
+    /// Builds a task from already decoded JSON, e.g. a request body, upgrading payloads of
+    /// older schema versions.
+    pub fn from_value(value: serde_json::Value, mode: ParseMode) -> Result<Self, TaskParseError> {
+        let value = schema::upgrade_task(value).map_err(TaskParseError::Schema)?;
+        if let (ParseMode::Strict, Some(fields)) = (mode, value.as_object()) {
+            if let Some(unknown) = fields
+                .keys()
+                .find(|key| !TASK_FIELDS.contains(&key.as_str()))
+            {
+                return Err(TaskParseError::UnknownField(unknown.clone()));
+            }
+            let has_ref = fields.contains_key("task_data_ref");
+            let present =
+                |field: &str| fields.contains_key(field) || (has_ref && field == "task_data");
+            if let Some(missing) = REQUIRED_TASK_FIELDS.iter().find(|field| !present(field)) {
+                return Err(TaskParseError::MissingField(missing));
+            }
+        }
+        serde_json::from_value(value).map_err(TaskParseError::Json)
+    }
+
+    /// Replaces a `task_data_ref` by the payload it points to, read from `source`; does
+    /// nothing for inline payloads.
+    pub fn resolve_task_data(
+        &mut self,
+        source: Option<&dyn PayloadSource>,
+    ) -> Result<(), PayloadError> {
+        let Some(reference) = &self.task_data_ref else {
+            return Ok(());
+        };
+        if !self.task_data.is_empty() {
+            return Err(PayloadError::Conflict);
+        }
+        self.task_data = reference.fetch(source.ok_or(PayloadError::NoSource)?)?;
+        self.task_data_ref = None;
+        Ok(())
+    }
+
+    /// `task_data` in [`canonical_json`] form, or as is when it is not JSON.
+    pub fn canonical_task_data(&self) -> String {
+        match serde_json::from_str::<serde_json::Value>(&self.task_data) {
+            Ok(value) => canonical_json(&value),
+            Err(_) => self.task_data.clone(),
+        }
+    }
+
+    /// Hex Blake2b-256 of everything that determines the proof, i.e. the task without its
+    /// `uuid` and `id`, with `task_data` canonicalized.
+    ///
+    /// Two tasks with the same digest get the same proof, whatever the key order or
+    /// whitespace of their payloads, so the digest keys deduplication and audit records.
+    pub fn task_digest(&self) -> String {
+        let mut payload = serde_json::json!({
+            "type": self.task_type,
+            "task_data": self.canonical_task_data(),
+            "hard_fork_name": self.hard_fork_name,
+            "evm": self.evm,
+            "instance_layout": self.instance_layout,
+        });
+        // only when set, so digests of tasks without one are those of older versions
+        if let Some(preprocessor) = &self.preprocessor {
+            payload["preprocessor"] = preprocessor.as_str().into();
+        }
+        if let Some(transcript) = self.transcript {
+            payload["transcript"] = transcript.as_str().into();
+        }
+        if self.dry_run {
+            payload["dry_run"] = true.into();
+        }
+        blake2b_simd::Params::new()
+            .hash_length(32)
+            .personal(b"poseidon-task\0\0\0")
+            .hash(canonical_json(&payload).as_bytes())
+            .to_hex()
+            .to_string()
+    }
+}
+
+/// Serializes `value` without whitespace and with the keys of every object sorted, so that
+/// equivalent documents serialize to the same bytes.
+///
+/// Numbers keep their textual form: `1` and `1.0` stay distinct.
+pub fn canonical_json(value: &serde_json::Value) -> String {
+    let mut out = String::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &serde_json::Value, out: &mut String) {
+    use serde_json::Value;
+    match value {
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            // do not rely on the map order: `preserve_order` may be enabled by any dependency
+            let mut fields = fields.iter().collect::<Vec<_>>();
+            fields.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (key, item)) in fields.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(item, out);
+            }
+            out.push('}');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct ProofDetail {
+    pub id: String,
+    #[serde(rename = "type", default)]
+    pub proof_type: ProofType,
+    /// Base64 proof in the transcript of `transcript`, for native verification
+    #[serde(with = "base64_bytes")]
+    pub proof_data: String,
+    /// Transcript of `proof_data`; Blake2b when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<TranscriptHash>,
+    /// Compression of `proof_data`; uncompressed when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<ProofCompression>,
+    /// Bytes of the uncompressed proof
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof_size: Option<usize>,
+    /// How long creating the proof took, without witness generation, keygen and the sanity
+    /// check
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proving_time_us: Option<u64>,
+    /// The circuit the proof is of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit: Option<CircuitInfo>,
+    pub error: String,
+    /// [`Error::code`] of `error`, for telling failures apart without parsing `error`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub error_code: String,
+    /// The stage `error` happened in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_stage: Option<Stage>,
+    /// What a dry run found wrong with the witness, see [`Task::dry_run`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraint_failures: Vec<ConstraintFailure>,
+    /// Public inputs of the proof, in the canonical encoding of [`crate::field_encoding`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub instances: Vec<String>,
+    /// How `instances` were placed in instance columns when proving
+    #[serde(default)]
+    pub instance_layout: InstanceLayout,
+    /// [`crate::prover::vk_hash`] of the key the proof verifies with; empty for the current
+    /// key of its proof type
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub vk_hash: String,
+    /// Label absorbed into the transcript, see
+    /// [`crate::prover::ProverContext::with_protocol_label`]; empty if none
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub protocol_label: String,
+    /// Present when the task asked for an EVM proof
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evm: Option<EvmProof>,
+    /// Peak RSS and per-phase heap usage, present when built with the `mem-stats` feature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<MemoryReport>,
+    /// The prover's own check of the proof, present when its sanity check is on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+    /// The build that produced the proof
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ProofMetadata>,
+    /// Set when the task was queued rather than proven: the `uuid` to poll for the proof
+    /// detail, see [`crate::jobs`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<String>,
+}
+
+impl ProofDetail {
+    /// This detail failed in `stage` with `error`.
+    pub fn with_error(self, stage: Stage, error: &Error) -> Self {
+        Self {
+            error: error.to_string(),
+            error_code: error.code().to_string(),
+            failed_stage: Some(stage),
+            ..self
+        }
+    }
+}
+
+/// The circuit of a proof, for clients weighing its cost.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct CircuitInfo {
+    /// Name of the circuit in the capability document, see [`crate::capabilities`]
+    pub name: String,
+    /// The circuit has `2^k` rows
+    pub k: u32,
+}
+
+/// The `halo2_proofs` this crate is built against: the PSE fork at the tag pinned in
+/// `Cargo.toml`, which has no release of its own.
+pub const HALO2_PROOFS_VERSION: &str = "pse-halo2@v2023_04_20";
+
+/// What a proof was produced with, for telling apart the builds of prover and verifier when
+/// a proof does not verify.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct ProofMetadata {
+    pub crate_version: String,
+    pub halo2_proofs: String,
+    /// [`SpecParams::id`] of the spec the circuit hashes with
+    pub spec_id: String,
+    /// [`crate::specs::SpecInfo::constants_hash`] of that spec
+    pub constants_hash: String,
+}
+
+impl ProofMetadata {
+    /// The metadata of proofs of this build hashing with `spec`.
+    pub fn current(spec: &SpecParams) -> Self {
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            halo2_proofs: HALO2_PROOFS_VERSION.to_string(),
+            spec_id: spec.id.to_string(),
+            constants_hash: spec.info().constants_hash,
+        }
+    }
+
+    /// The fields that differ from `current`, as `field: proof <value>, verifier <value>`.
+    pub fn mismatches(&self, current: &Self) -> Vec<String> {
+        [
+            ("crate_version", &self.crate_version, &current.crate_version),
+            ("halo2_proofs", &self.halo2_proofs, &current.halo2_proofs),
+            ("spec_id", &self.spec_id, &current.spec_id),
+            (
+                "constants_hash",
+                &self.constants_hash,
+                &current.constants_hash,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, proof, verifier)| proof != verifier)
+        .map(|(field, proof, verifier)| format!("{field}: proof {proof}, verifier {verifier}"))
+        .collect()
+    }
+}
+
+/// The prover verifying a proof it just created, before returning it.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verification {
+    pub verified: bool,
+    /// Time spent verifying, including the EVM proof if there is one
+    pub duration_us: u64,
+}
+
+/// A failure `MockProver` reports for the witness of a dry run.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct ConstraintFailure {
+    /// `constraint`, `poisoned`, `cell_not_assigned`, `instance_not_assigned`, `lookup`,
+    /// `permutation` or `other`
+    pub kind: String,
+    /// The constraint, gate, lookup or column at fault, as `MockProver` names it
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub gate: String,
+    /// The region the failure is in; empty outside of regions
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub region: String,
+    /// Offset of the failing row in `region`, or the absolute row outside of regions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row: Option<usize>,
+    /// The failure as `MockProver` prints it
+    pub message: String,
+}
+
+/// A proof for EVM verifiers, over the same circuit and instances as the native proof.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct EvmProof {
+    /// Hash of the transcript, always `keccak256`
+    pub transcript: String,
+    /// Base64 proof bytes
+    #[serde(with = "base64_bytes")]
+    pub proof: String,
+    /// `0x`-prefixed hex calldata: the instances as 32-byte words, then the proof
+    pub calldata: String,
+}
+
+impl EvmProof {
+    /// Encodes a proof made by [`crate::prover::ProverContext::prove_keccak`].
+    pub fn new(instances: &[&[Fr]], proof: &[u8]) -> Self {
+        let calldata: String = encode_calldata(instances, proof)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        Self {
+            transcript: "keccak256".to_string(),
+            proof: BS64.encode(proof),
+            calldata: format!("0x{calldata}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TaskParseError {
+    TooLarge { len: usize, max: usize },
+    Json(serde_json::Error),
+    UnknownField(String),
+    MissingField(&'static str),
+    Schema(SchemaError),
+}
+
+impl fmt::Display for TaskParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { len, max } => {
+                write!(f, "task payload of {len} bytes exceeds the limit of {max}")
+            }
+            Self::Json(err) => write!(f, "malformed task: {err}"),
+            Self::UnknownField(field) => write!(f, "unknown task field `{field}`"),
+            Self::MissingField(field) => write!(f, "missing task field `{field}`"),
+            Self::Schema(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for TaskParseError {}
+
+/// A Base64 string in human-readable formats and its raw bytes in binary ones, see
+/// [`crate::wire`].
+pub(crate) mod base64_bytes {
+    use std::fmt;
+
+    use base64::{engine::general_purpose::STANDARD as BS64, Engine};
+    use serde::{
+        de::{self, Visitor},
+        ser, Deserialize, Deserializer, Serializer,
+    };
+
+    pub fn serialize<S: Serializer>(b64: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            return serializer.serialize_str(b64);
+        }
+        let bytes = BS64.decode(b64).map_err(ser::Error::custom)?;
+        serializer.serialize_bytes(&bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        if deserializer.is_human_readable() {
+            return String::deserialize(deserializer);
+        }
+        deserializer.deserialize_byte_buf(Base64Visitor)
+    }
+
+    struct Base64Visitor;
+
+    impl<'de> Visitor<'de> for Base64Visitor {
+        type Value = String;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "a byte string")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+            Ok(BS64.encode(v))
+        }
+
+        /// Binary payloads written by older producers still carry the Base64 string.
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            Ok(v.to_owned())
+        }
+    }
+}
+
+fn bounded_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    deserializer.deserialize_string(BoundedString::<MAX_ID_LEN>)
+}
+
+fn bounded_hard_fork_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    deserializer.deserialize_string(BoundedString::<MAX_HARD_FORK_NAME_LEN>)
+}
+
+fn bounded_task_data<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    deserializer.deserialize_string(BoundedString::<MAX_TASK_DATA_LEN>)
+}
+
+/// Checks the length of a string before taking ownership of it.
+struct BoundedString<const MAX: usize>;
+
+impl<'de, const MAX: usize> Visitor<'de> for BoundedString<MAX> {
+    type Value = String;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a string of at most {MAX} bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        if v.len() > MAX {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+        Ok(v.to_owned())
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+        if v.len() > MAX {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+        Ok(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn proof_type() -> impl Strategy<Value = ProofType> {
+        (0u8..=ProofType::ALL.len() as u8).prop_map(ProofType::from_u8)
+    }
+
+    prop_compose! {
+        /// Tasks as producers send them, with every string within its bound.
+        fn any_task()(
+            (uuid, id, hard_fork_name) in (".{0,32}", ".{0,32}", ".{0,16}"),
+            task_type in proof_type(),
+            task_data in any::<String>(),
+            (evm, dry_run, column_major) in any::<(bool, bool, bool)>(),
+            transcript in prop::option::of(prop::sample::select(vec![
+                TranscriptHash::Blake2b,
+                TranscriptHash::Keccak256,
+                TranscriptHash::Poseidon,
+            ])),
+            preprocessor in prop::option::of("[a-z_]{1,8}"),
+            gzip in any::<bool>(),
+        ) -> Task {
+            Task {
+                uuid,
+                id,
+                task_type,
+                task_data,
+                hard_fork_name,
+                evm,
+                dry_run,
+                instance_layout: if column_major {
+                    InstanceLayout::ColumnMajor
+                } else {
+                    InstanceLayout::RowMajor
+                },
+                transcript,
+                preprocessor,
+                compression: gzip.then_some(ProofCompression::Gzip),
+                schema_version: Some(schema::SCHEMA_VERSION),
+                ..Default::default()
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_any_task_round_trip(task in any_task()) {
+            let encoded = serde_json::to_vec(&task).unwrap();
+            prop_assert_eq!(Task::from_json(&encoded).unwrap(), task.clone());
+            let strict = Task::from_json_with(&encoded, ParseMode::Strict).unwrap();
+            prop_assert_eq!(strict, task);
+        }
+
+        #[test]
+        fn test_any_proof_type_value(value in any::<u8>()) {
+            let json = format!(r#"{{"uuid":"","id":"","type":{value},"task_data":""}}"#);
+            let task_type = Task::from_json(json.as_bytes()).unwrap().task_type;
+            let encoded = serde_json::to_string(&task_type).unwrap();
+            prop_assert_eq!(serde_json::from_str::<ProofType>(&encoded).unwrap(), task_type);
+            if task_type != ProofType::Undefined {
+                prop_assert_eq!(encoded, value.to_string());
+            }
+        }
+
+        /// Whatever comes off the queue is a task or an error, never a panic.
+        #[test]
+        fn test_hostile_json(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+            if let Ok(task) = Task::from_json(&bytes) {
+                let encoded = serde_json::to_vec(&task).unwrap();
+                prop_assert_eq!(Task::from_json(&encoded).unwrap(), task);
+            }
+        }
+    }
+
+    #[test]
+    fn test_task_round_trip() {
+        let json = br#"{"uuid":"u-1","id":"7","type":1,"task_data":"[1,2,3]","hard_fork_name":"bernoulli"}"#;
+        let task = Task::from_json(json).unwrap();
+        assert_eq!(task.task_type, ProofType::Chunk);
+        assert_eq!(task.instance_layout, InstanceLayout::RowMajor);
+        let encoded = serde_json::to_vec(&task).unwrap();
+        assert_eq!(Task::from_json(&encoded).unwrap(), task);
+    }
+
+    #[test]
+    fn test_strict_parsing() {
+        let strict = |json: &str| Task::from_json_with(json.as_bytes(), ParseMode::Strict);
+        let full = r#"{"uuid":"u","id":"7","type":3,"task_data":"","hard_fork_name":"genesis"}"#;
+        assert_eq!(strict(full).unwrap().task_type, ProofType::Preimage);
+        assert!(matches!(
+            strict(r#"{"uuid":"u","id":"7","task_data":"","hard_fork_name":""}"#),
+            Err(TaskParseError::MissingField("type"))
+        ));
+        let typo = r#"{"uuid":"u","id":"7","typ":3,"type":3,"task_data":"","hard_fork_name":""}"#;
+        assert!(matches!(
+            strict(typo),
+            Err(TaskParseError::UnknownField(field)) if field == "typ"
+        ));
+        assert_eq!(
+            Task::from_json(typo.as_bytes()).unwrap().task_type,
+            ProofType::Preimage
+        );
+        assert_eq!("strict".parse(), Ok(ParseMode::Strict));
+        assert!("loose".parse::<ParseMode>().is_err());
+    }
+
+    #[test]
+    fn test_task_data_ref() {
+        let json = r#"{"uuid":"u","id":"7","type":3,"hard_fork_name":"",
+            "task_data_ref":{"uri":"file:///missing.json","len":2,"blake2b":"00"}}"#;
+        let mut task = Task::from_json_with(json.as_bytes(), ParseMode::Strict).unwrap();
+        assert!(task.task_data_ref.is_some());
+        assert!(matches!(
+            task.resolve_task_data(None),
+            Err(PayloadError::NoSource)
+        ));
+        task.task_data = "[]".to_string();
+        assert!(matches!(
+            task.resolve_task_data(None),
+            Err(PayloadError::Conflict)
+        ));
+        // inline payloads need no source
+        let mut inline = Task::default();
+        assert!(inline.resolve_task_data(None).is_ok());
+        assert!(!serde_json::to_string(&inline)
+            .unwrap()
+            .contains("task_data_ref"));
+    }
+
+    #[test]
+    fn test_task_digest() {
+        let task = |id: &str, task_data: &str| Task {
+            id: id.to_string(),
+            task_data: task_data.to_string(),
+            ..Default::default()
+        };
+        let a = task("1", r#"{"b": [1, {"y": 2, "x": "s"}], "a": null}"#);
+        assert_eq!(
+            a.canonical_task_data(),
+            r#"{"a":null,"b":[1,{"x":"s","y":2}]}"#
+        );
+        let b = task("2", r#"{"a":null,"b":[1,{"x":"s","y":2}]}"#);
+        assert_eq!(a.task_digest(), b.task_digest());
+        assert_eq!(a.task_digest().len(), 64);
+
+        let c = task("1", r#"{"a":null,"b":[{"x":"s","y":2},1]}"#);
+        assert_ne!(a.task_digest(), c.task_digest());
+        let evm = Task {
+            evm: true,
+            ..a.clone()
+        };
+        assert_ne!(a.task_digest(), evm.task_digest());
+        let hex = Task {
+            preprocessor: Some("hex".to_string()),
+            ..a.clone()
+        };
+        assert_ne!(a.task_digest(), hex.task_digest());
+        let poseidon = Task {
+            transcript: Some(TranscriptHash::Poseidon),
+            ..a.clone()
+        };
+        assert_ne!(a.task_digest(), poseidon.task_digest());
+        let dry_run = Task {
+            dry_run: true,
+            ..a.clone()
+        };
+        assert_ne!(a.task_digest(), dry_run.task_digest());
+        // not JSON: hashed as is
+        assert_eq!(task("1", "[1, 2").canonical_task_data(), "[1, 2");
+    }
+
+    #[test]
+    fn test_task_limits() {
+        let long_id = "a".repeat(MAX_ID_LEN + 1);
+        let json = format!(r#"{{"uuid":"{long_id}","id":"7","task_data":""}}"#);
+        assert!(matches!(
+            Task::from_json(json.as_bytes()),
+            Err(TaskParseError::Json(_))
+        ));
+
+        let huge = vec![b' '; MAX_TASK_BYTES + 1];
+        assert!(matches!(
+            Task::from_json(&huge),
+            Err(TaskParseError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_task_type_values() {
+        let parse = |ty: &str| {
+            Task::from_json(
+                format!(r#"{{"uuid":"","id":"","type":{ty},"task_data":""}}"#).as_bytes(),
+            )
+        };
+        assert_eq!(parse("2").unwrap().task_type, ProofType::Batch);
+        assert_eq!(parse("3").unwrap().task_type, ProofType::Preimage);
+        assert_eq!(parse("4").unwrap().task_type, ProofType::Range);
+        assert_eq!(parse("9").unwrap().task_type, ProofType::Undefined);
+        assert!(parse("-1").is_err());
+        assert!(parse("300").is_err());
+        assert!(parse("\"chunk\"").is_err());
+
+        // names are for configs only
+        for proof_type in ProofType::ALL {
+            assert_eq!(proof_type.as_str().parse(), Ok(proof_type));
+        }
+        assert!("undefined".parse::<ProofType>().is_err());
+    }
+
+    #[test]
+    fn test_evm_proof_encoding() {
+        let evm = EvmProof::new(&[&[Fr::from(1)]], &[0xab]);
+        assert_eq!(evm.transcript, "keccak256");
+        assert_eq!(evm.proof, "qw==");
+        assert_eq!(evm.calldata, format!("0x{}01ab", "00".repeat(31)));
+
+        let detail = ProofDetail::default();
+        assert!(!serde_json::to_string(&detail).unwrap().contains("evm"));
+    }
+
+    #[test]
+    fn test_proof_metadata() {
+        let current = ProofMetadata::current(&crate::specs::BN256_T4_R3);
+        assert_eq!(current.spec_id, "bn256-t4-r3");
+        assert_eq!(current.constants_hash.len(), 64);
+        // the tag is the one Cargo.toml pins
+        let (_, tag) = HALO2_PROOFS_VERSION.split_once('@').unwrap();
+        assert!(include_str!("../Cargo.toml").contains(&format!("tag = \"{tag}\" }}")));
+        assert!(current.mismatches(&current).is_empty());
+
+        let older = ProofMetadata {
+            crate_version: "0.0.1".to_string(),
+            constants_hash: "00".repeat(32),
+            ..current.clone()
+        };
+        let mismatches = older.mismatches(&current);
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(
+            mismatches[0],
+            format!(
+                "crate_version: proof 0.0.1, verifier {}",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert!(mismatches[1].starts_with("constants_hash: "));
+    }
+}
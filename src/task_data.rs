@@ -0,0 +1,296 @@
+//! The schema of [`crate::task::Task::task_data`].
+//!
+//! `task_data` holds field elements as JSON strings in any encoding of
+//! [`crate::field_encoding`], or as JSON integers. Chunk and batch tasks send the message to
+//! hash and optionally the digest they expect:
+//!
+//! ```json
+//! {"inputs": ["0x01", "2", "Aw=="], "digest": "0x2ce4...7749"}
+//! ```
+//!
+//! A bare array `["0x01", "2", "Aw=="]` is the same message without a digest. The other
+//! proof types send a bare array of their witness elements, see
+//! [`crate::state::ProverState::prove`].
+use std::fmt;
+
+use ff::PrimeField;
+use serde_json::Value;
+
+use crate::field_encoding::{parse_field, FieldParseErrorKind};
+
+/// The message of a chunk or batch task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashWitness<F> {
+    pub inputs: Vec<F>,
+    /// The digest the client expects, checked against the native hash before proving
+    pub digest: Option<F>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskDataError {
+    Json(String),
+    /// `task_data` is valid JSON of another shape
+    Malformed(String),
+    /// An element is not a field element in any accepted encoding
+    InvalidElement {
+        location: String,
+        kind: FieldParseErrorKind,
+    },
+    /// An element is a number not smaller than the field modulus
+    PubInputOutOfField {
+        location: String,
+        public_input: String,
+    },
+    /// The expected digest is not the hash of the inputs
+    DigestMismatch {
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for TaskDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(err) => write!(f, "task_data is not JSON: {err}"),
+            Self::Malformed(msg) => write!(f, "malformed task_data: {msg}"),
+            Self::InvalidElement { location, kind } => write!(f, "task_data {location}: {kind}"),
+            Self::PubInputOutOfField {
+                location,
+                public_input,
+            } => write!(
+                f,
+                "task_data {location} ({public_input:?}) is not smaller than the field modulus"
+            ),
+            Self::DigestMismatch { expected, actual } => write!(
+                f,
+                "the inputs hash to {actual}, not to the expected digest {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TaskDataError {}
+
+/// Longest element quoted back in errors.
+const MAX_REPORTED_LEN: usize = 80;
+
+impl<F: PrimeField> HashWitness<F> {
+    pub fn parse(task_data: &str) -> Result<Self, TaskDataError> {
+        match parse_json(task_data)? {
+            Value::Array(items) => Ok(Self {
+                inputs: parse_elements(&items, "inputs")?,
+                digest: None,
+            }),
+            Value::Object(mut object) => {
+                let inputs = match object.remove("inputs") {
+                    Some(Value::Array(items)) => parse_elements(&items, "inputs")?,
+                    Some(_) => return Err(malformed("inputs is not an array")),
+                    None => return Err(malformed("missing inputs")),
+                };
+                let digest = match object.remove("digest") {
+                    None | Some(Value::Null) => None,
+                    Some(digest) => Some(parse_element(&digest, "digest".to_string())?),
+                };
+                if let Some(field) = object.keys().next() {
+                    return Err(malformed(&format!("unknown field {field:?}")));
+                }
+                Ok(Self { inputs, digest })
+            }
+            _ => Err(malformed("expected an array or an object")),
+        }
+    }
+
+    /// Checks the expected digest, if any, against `actual`.
+    pub fn check_digest(&self, actual: F) -> Result<(), TaskDataError> {
+        match self.digest {
+            Some(expected) if expected != actual => Err(TaskDataError::DigestMismatch {
+                expected: crate::field_encoding::to_canonical(&expected),
+                actual: crate::field_encoding::to_canonical(&actual),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The elements of a bare `task_data` array.
+pub fn parse_array<F: PrimeField>(task_data: &str) -> Result<Vec<F>, TaskDataError> {
+    match parse_json(task_data)? {
+        Value::Array(items) => parse_elements(&items, "element"),
+        _ => Err(malformed("expected an array")),
+    }
+}
+
+fn parse_json(task_data: &str) -> Result<Value, TaskDataError> {
+    serde_json::from_str(task_data).map_err(|err| TaskDataError::Json(err.to_string()))
+}
+
+fn malformed(msg: &str) -> TaskDataError {
+    TaskDataError::Malformed(msg.to_string())
+}
+
+fn parse_elements<F: PrimeField>(items: &[Value], name: &str) -> Result<Vec<F>, TaskDataError> {
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| parse_element(item, format!("{name}[{i}]")))
+        .collect()
+}
+
+fn parse_element<F: PrimeField>(item: &Value, location: String) -> Result<F, TaskDataError> {
+    let text = match item {
+        Value::String(s) => s.clone(),
+        // serde_json keeps integers beyond u64 only with arbitrary precision, which is off
+        Value::Number(n) if n.is_u64() => n.to_string(),
+        _ => {
+            return Err(TaskDataError::Malformed(format!(
+                "{location} is neither a string nor a non-negative integer"
+            )))
+        }
+    };
+    parse_field(&text).map_err(|kind| match kind {
+        FieldParseErrorKind::OutOfField => TaskDataError::PubInputOutOfField {
+            location,
+            public_input: text.chars().take(MAX_REPORTED_LEN).collect(),
+        },
+        kind => TaskDataError::InvalidElement { location, kind },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::bn256::Fr;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::field_encoding::to_canonical;
+
+    proptest! {
+        /// Hostile payloads are rejected with an error, never a panic.
+        #[test]
+        fn test_any_task_data(task_data in any::<String>()) {
+            let _ = HashWitness::<Fr>::parse(&task_data);
+            let _ = parse_array::<Fr>(&task_data);
+        }
+
+        /// Numbers of the bit size of the field but over its modulus name the element.
+        #[test]
+        fn test_out_of_field_limbs(top in 0x31u8.., low in prop::array::uniform31(any::<u8>())) {
+            let digits: String = low.iter().map(|b| format!("{b:02x}")).collect();
+            let limb = format!("0x{top:02x}{digits}");
+            let task_data = format!(r#"{{"inputs": [1, "{limb}"]}}"#);
+            prop_assert_eq!(
+                HashWitness::<Fr>::parse(&task_data),
+                Err(TaskDataError::PubInputOutOfField {
+                    location: "inputs[1]".to_string(),
+                    public_input: limb,
+                })
+            );
+        }
+
+        /// Elements of any length are bounded before they are read, and quoted back cut.
+        #[test]
+        fn test_huge_elements(lead in 1u8..=9, len in 80usize..4096, hex in any::<bool>()) {
+            let element = match hex {
+                true => format!("0x{lead}{}", "f".repeat(len)),
+                false => format!("{lead}{}", "0".repeat(len)),
+            };
+            let err = parse_array::<Fr>(&format!(r#"["{element}"]"#)).unwrap_err();
+            let TaskDataError::PubInputOutOfField { public_input, .. } = err else {
+                panic!("{err:?}");
+            };
+            prop_assert_eq!(public_input.len(), MAX_REPORTED_LEN);
+        }
+
+        /// A payload cut short is not JSON, and an element cut short is another number or
+        /// an invalid one.
+        #[test]
+        fn test_truncated_task_data(
+            values in prop::collection::vec(any::<u64>(), 1..8),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let elements = values.iter().map(|v| to_canonical(&Fr::from(*v)));
+            let task_data = serde_json::to_string(&elements.collect::<Vec<_>>()).unwrap();
+            let cut = cut.index(task_data.len());
+            prop_assert!(matches!(
+                parse_array::<Fr>(&task_data[..cut]),
+                Err(TaskDataError::Json(_))
+            ));
+            let hex = to_canonical(&Fr::from(values[0]));
+            let short = &hex[..cut.min(hex.len())];
+            match parse_array::<Fr>(&format!(r#"["{short}"]"#)) {
+                Ok(parsed) => prop_assert!(parsed.len() == 1),
+                Err(err) => prop_assert!(
+                    matches!(err, TaskDataError::InvalidElement { .. }),
+                    "{:?}",
+                    err
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_hash_witness() {
+        let expected = vec![Fr::from(1), Fr::from(2), Fr::from(3)];
+        for task_data in [
+            r#"["0x01", "2", 3]"#,
+            r#"{"inputs": ["0x01", "2", 3]}"#,
+            r#"{"inputs": [1, 2, 3], "digest": null}"#,
+        ] {
+            let witness = HashWitness::<Fr>::parse(task_data).unwrap();
+            assert_eq!(witness.inputs, expected, "{task_data}");
+            assert_eq!(witness.digest, None);
+        }
+        let witness = HashWitness::<Fr>::parse(r#"{"inputs": [], "digest": "0x7"}"#).unwrap();
+        assert_eq!(witness.digest, Some(Fr::from(7)));
+        assert!(witness.check_digest(Fr::from(7)).is_ok());
+        assert!(matches!(
+            witness.check_digest(Fr::from(8)),
+            Err(TaskDataError::DigestMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_errors() {
+        let modulus = Fr::MODULUS;
+        assert_eq!(
+            HashWitness::<Fr>::parse(&format!(r#"{{"inputs": [1, "{modulus}"]}}"#)),
+            Err(TaskDataError::PubInputOutOfField {
+                location: "inputs[1]".to_string(),
+                public_input: modulus.to_string(),
+            })
+        );
+        assert!(matches!(
+            HashWitness::<Fr>::parse(&format!(r#"{{"inputs": [], "digest": "{modulus}"}}"#)),
+            Err(TaskDataError::PubInputOutOfField { location, .. }) if location == "digest"
+        ));
+        assert!(matches!(
+            HashWitness::<Fr>::parse(r#"["0xzz"]"#),
+            Err(TaskDataError::InvalidElement { .. })
+        ));
+        for task_data in [
+            "1",
+            r#"{"digest": "1"}"#,
+            r#"{"inputs": "1"}"#,
+            r#"{"inputs": [], "extra": 1}"#,
+            "[-1]",
+            "[1.5]",
+        ] {
+            assert!(
+                matches!(
+                    HashWitness::<Fr>::parse(task_data),
+                    Err(TaskDataError::Malformed(_))
+                ),
+                "{task_data}"
+            );
+        }
+        assert!(matches!(
+            HashWitness::<Fr>::parse("[1"),
+            Err(TaskDataError::Json(_))
+        ));
+        assert_eq!(
+            parse_array::<Fr>("[1, \"2\"]"),
+            Ok(vec![Fr::from(1), Fr::from(2)])
+        );
+        assert!(parse_array::<Fr>(r#"{"inputs": []}"#).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! Metrics of the prover service in the Prometheus text format, and the `tracing` spans of
+//! the stages a task goes through.
+//!
+//! [`Metrics::time`] runs a stage in a `stage` span and records how long it took in the
+//! latency histogram of the stage; [`crate::state::ProverState::prove`] opens a `task` span
+//! around them. Spans cost nothing until a subscriber is installed, which the service does
+//! when `RUST_LOG` is set. [`Metrics::render`] writes:
+//!
+//! | metric                               | type      | labels  |
+//! |--------------------------------------|-----------|---------|
+//! | `poseidon_prover_stage_seconds`      | histogram | `stage` |
+//! | `poseidon_prover_tasks_total`        | counter   |         |
+//! | `poseidon_prover_task_errors_total`  | counter   | `stage` |
+//! | `poseidon_prover_queue_depth`        | gauge     |         |
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::stage::Stage;
+
+/// Upper bounds of the latency buckets, in seconds: from a cached preimage proof to the
+/// keygen of a large circuit.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Observations per bucket of [`LATENCY_BUCKETS`], and past the last one
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS.len() + 1];
+        }
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Latencies, task and error counts and queue depth of one prover.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// By the position of the stage in [`Stage::ALL`]
+    latencies: Mutex<[Histogram; Stage::ALL.len()]>,
+    tasks: AtomicU64,
+    errors: [AtomicU64; Stage::ALL.len()],
+    queue_depth: AtomicU64,
+}
+
+fn index(stage: Stage) -> usize {
+    Stage::ALL
+        .iter()
+        .position(|s| *s == stage)
+        .expect("every stage is in ALL")
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f` as `stage`, in a `stage` span, and records how long it took.
+    pub fn time<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let _span = tracing::info_span!("stage", stage = stage.as_str()).entered();
+        let start = Instant::now();
+        let out = f();
+        self.observe(stage, start.elapsed());
+        out
+    }
+
+    pub fn observe(&self, stage: Stage, elapsed: Duration) {
+        self.latencies.lock().unwrap()[index(stage)].observe(elapsed.as_secs_f64());
+    }
+
+    /// Counts a finished task, and its error if it failed in `failed_stage`.
+    pub fn record_task(&self, failed_stage: Option<Stage>) {
+        self.tasks.fetch_add(1, Ordering::Relaxed);
+        if let Some(stage) = failed_stage {
+            self.errors[index(stage)].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Tasks waiting to be proven, see [`crate::jobs`].
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    /// Every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let latencies = self.latencies.lock().unwrap().clone();
+        out.push_str("# HELP poseidon_prover_stage_seconds Time spent in each stage of a task.\n");
+        out.push_str("# TYPE poseidon_prover_stage_seconds histogram\n");
+        for (stage, histogram) in Stage::ALL.iter().zip(&latencies) {
+            let mut cumulative = 0;
+            for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+                cumulative += histogram.buckets.get(i).copied().unwrap_or_default();
+                let _ = writeln!(
+                    out,
+                    "poseidon_prover_stage_seconds_bucket{{stage=\"{stage}\",le=\"{bound}\"}} \
+                     {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "poseidon_prover_stage_seconds_bucket{{stage=\"{stage}\",le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "poseidon_prover_stage_seconds_sum{{stage=\"{stage}\"}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "poseidon_prover_stage_seconds_count{{stage=\"{stage}\"}} {}",
+                histogram.count
+            );
+        }
+        out.push_str("# HELP poseidon_prover_tasks_total Tasks answered, proven or not.\n");
+        out.push_str("# TYPE poseidon_prover_tasks_total counter\n");
+        let _ = writeln!(
+            out,
+            "poseidon_prover_tasks_total {}",
+            self.tasks.load(Ordering::Relaxed)
+        );
+        out.push_str("# HELP poseidon_prover_task_errors_total Failed tasks by failed stage.\n");
+        out.push_str("# TYPE poseidon_prover_task_errors_total counter\n");
+        for (stage, errors) in Stage::ALL.iter().zip(&self.errors) {
+            let _ = writeln!(
+                out,
+                "poseidon_prover_task_errors_total{{stage=\"{stage}\"}} {}",
+                errors.load(Ordering::Relaxed)
+            );
+        }
+        out.push_str("# HELP poseidon_prover_queue_depth Tasks waiting for a worker.\n");
+        out.push_str("# TYPE poseidon_prover_queue_depth gauge\n");
+        let _ = writeln!(
+            out,
+            "poseidon_prover_queue_depth {}",
+            self.queue_depth.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        assert_eq!(metrics.time(Stage::Keygen, || 7), 7);
+        metrics.observe(Stage::Prove, Duration::from_millis(300));
+        metrics.observe(Stage::Prove, Duration::from_secs(600));
+        metrics.record_task(None);
+        metrics.record_task(Some(Stage::Witness));
+        metrics.set_queue_depth(3);
+
+        let text = metrics.render();
+        let value = |series: &str| {
+            text.lines()
+                .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+                .unwrap_or_else(|| panic!("no {series}"))
+                .to_string()
+        };
+        // buckets are cumulative, the last observation is past every bound
+        let prove = "poseidon_prover_stage_seconds_bucket{stage=\"prove\"";
+        assert_eq!(value(&format!("{prove},le=\"0.25\"}}")), "0");
+        assert_eq!(value(&format!("{prove},le=\"0.5\"}}")), "1");
+        assert_eq!(value(&format!("{prove},le=\"300\"}}")), "1");
+        assert_eq!(value(&format!("{prove},le=\"+Inf\"}}")), "2");
+        assert_eq!(
+            value("poseidon_prover_stage_seconds_count{stage=\"keygen\"}"),
+            "1"
+        );
+        assert_eq!(
+            value("poseidon_prover_stage_seconds_count{stage=\"verify\"}"),
+            "0"
+        );
+        assert_eq!(value("poseidon_prover_tasks_total"), "2");
+        assert_eq!(
+            value("poseidon_prover_task_errors_total{stage=\"witness\"}"),
+            "1"
+        );
+        assert_eq!(
+            value("poseidon_prover_task_errors_total{stage=\"prove\"}"),
+            "0"
+        );
+        assert_eq!(value("poseidon_prover_queue_depth"), "3");
+    }
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use ff::{FromUniformBytes, PrimeField};
 use halo2_proofs::{
     circuit::{Layouter, SimpleFloorPlanner},
@@ -6,37 +8,92 @@ use halo2_proofs::{
 use poseidon::Spec;
 
 use crate::{
+    limits::CancelToken,
     main_gate::{MainGate, MainGateConfig, RegionCtx},
     poseidon_circuit::PoseidonChip,
+    specs::{Domain, BN256_T4_R3},
+    trace_cache::TraceCache,
 };
 
-const T: usize = 4;
-const RATE: usize = 3;
-const R_F: usize = 8;
-const R_P: usize = 56;
-
 #[derive(Clone, Debug)]
-pub struct TestCircuitConfig {
+pub struct TestCircuitConfig<const T: usize> {
     pconfig: MainGateConfig<T>,
     instance: Column<Instance>,
 }
 
-pub struct TestCircuit<F: PrimeField> {
+/// Reference circuit exposing the Poseidon hash of private inputs as its only public input.
+///
+/// Generic over the field and the width and rate of the spec, so crates on other curves can
+/// reuse it in their own tests; the defaults are the spec the service proves with.
+pub struct TestCircuit<
+    F: PrimeField,
+    const T: usize = { BN256_T4_R3.width },
+    const RATE: usize = { BN256_T4_R3.rate },
+> {
     inputs: Vec<F>,
+    r_f: usize,
+    r_p: usize,
+    domain: Domain,
+    trace_cache: Option<Arc<TraceCache<F, T, RATE>>>,
+    cancel: Option<CancelToken>,
 }
 
 impl<F: PrimeField> TestCircuit<F> {
     pub fn new(inputs: Vec<F>) -> Self {
-        Self { inputs }
+        Self::with_spec(inputs, BN256_T4_R3.r_f, BN256_T4_R3.r_p)
+    }
+}
+
+impl<F: PrimeField, const T: usize, const RATE: usize> TestCircuit<F, T, RATE> {
+    /// Hashes `inputs` under the spec of width `T`, rate `RATE` and the given round numbers.
+    pub fn with_spec(inputs: Vec<F>, r_f: usize, r_p: usize) -> Self {
+        Self {
+            inputs,
+            r_f,
+            r_p,
+            domain: Domain::Pse,
+            trace_cache: None,
+            cancel: None,
+        }
+    }
+
+    /// Hashes under `domain` rather than [`Domain::Pse`]; the circuits of two domains have
+    /// different keys.
+    pub fn with_domain(mut self, domain: Domain) -> Self {
+        self.domain = domain;
+        self
+    }
+
+    /// Computes the witness through `cache`, see [`PoseidonChip::with_trace_cache`]; the
+    /// circuit and its keys stay the same.
+    pub fn with_trace_cache(mut self, cache: Arc<TraceCache<F, T, RATE>>) -> Self {
+        self.trace_cache = Some(cache);
+        self
+    }
+
+    /// Fails synthesis with [`Error::Synthesis`] once `cancel` stops its task, checked before
+    /// and after computing the witness, so a stopped task does not go on to prove.
+    pub fn with_cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    fn check_cancel(&self) -> Result<(), Error> {
+        match &self.cancel {
+            Some(cancel) => cancel.check().map_err(|_| Error::Synthesis),
+            None => Ok(()),
+        }
     }
 }
 
-impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for TestCircuit<F> {
-    type Config = TestCircuitConfig;
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> Circuit<F>
+    for TestCircuit<F, T, RATE>
+{
+    type Config = TestCircuitConfig<T>;
     type FloorPlanner = SimpleFloorPlanner;
 
     fn without_witnesses(&self) -> Self {
-        Self { inputs: Vec::new() }
+        Self::with_spec(Vec::new(), self.r_f, self.r_p).with_domain(self.domain)
     }
 
     fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
@@ -53,8 +110,12 @@ impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for TestCircuit<F> {
         config: Self::Config,
         mut layouter: impl Layouter<F>,
     ) -> Result<(), Error> {
-        let spec = Spec::<F, T, RATE>::new(R_F, R_P);
-        let mut pchip = PoseidonChip::new(config.pconfig, spec);
+        self.check_cancel()?;
+        let spec = Spec::<F, T, RATE>::new(self.r_f, self.r_p);
+        let mut pchip = PoseidonChip::new(config.pconfig, spec).with_domain(self.domain);
+        if let Some(cache) = &self.trace_cache {
+            pchip = pchip.with_trace_cache(cache.clone());
+        }
         pchip.update(self.inputs.clone());
         let output = layouter.assign_region(
             || "poseidon hash",
@@ -63,7 +124,177 @@ impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for TestCircuit<F> {
                 pchip.squeeze(ctx)
             },
         )?;
+        self.check_cancel()?;
         layouter.constrain_instance(output.cell(), config.instance, 0)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use halo2curves::{bn256::Fr, pasta::Fp};
+
+    use super::*;
+    use crate::{
+        limits::{LimitError, MessageLimits},
+        poseidon_hash::{hash, hash_with_domain, State},
+    };
+
+    const K: u32 = 10;
+
+    fn spec() -> Spec<Fr, 4, 3> {
+        Spec::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p)
+    }
+
+    fn message(len: u64) -> Vec<Fr> {
+        (0..len).map(Fr::from).collect()
+    }
+
+    /// Whether the circuit hashing `inputs` under `domain` accepts `digest`.
+    fn accepts(inputs: Vec<Fr>, domain: Domain, digest: Fr) -> bool {
+        let circuit = TestCircuit::new(inputs).with_domain(domain);
+        let prover = MockProver::run(K, &circuit, vec![vec![digest]]).unwrap();
+        prover.verify().is_ok()
+    }
+
+    #[test]
+    fn test_bn256_default_spec() {
+        let circuit = TestCircuit::new((0..5).map(Fr::from).collect());
+        let out_hash = Fr::from_str_vartime(
+            "20304616028358001435806807494046171997958789835068077254356069730773893150537",
+        )
+        .unwrap();
+        let prover = MockProver::run(K, &circuit, vec![vec![out_hash]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let domain = Domain::ConstantLength { len: 5, outputs: 1 };
+        let circuit = TestCircuit::new((0..5).map(Fr::from).collect()).with_domain(domain);
+        let prover = MockProver::run(K, &circuit, vec![vec![out_hash]]).unwrap();
+        assert!(prover.verify().is_err());
+        let spec = Spec::<Fr, 4, 3>::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p);
+        let digest = crate::primitives::Hash::new(spec)
+            .hash_with_domain(domain, &(0..5).map(Fr::from).collect::<Vec<_>>());
+        let prover = MockProver::run(K, &circuit, vec![vec![digest]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_pasta_spec() {
+        let circuit = TestCircuit::<Fp, 3, 2>::with_spec((0..5).map(Fp::from).collect(), 4, 3);
+        // see the poseidon_circuit tests
+        let out_hash = Fp::from_str_vartime(
+            "13037709793114148810823325920380362524528554380279235267325741570708489436263",
+        )
+        .unwrap();
+        let prover = MockProver::run(K, &circuit, vec![vec![out_hash]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_over_capacity() {
+        let limits = MessageLimits::for_k(&spec(), K);
+        let max = limits.max_hash_len;
+        assert!(accepts(
+            message(max as u64),
+            Domain::Pse,
+            hash(&spec(), &message(max as u64))
+        ));
+
+        // one element more is rejected with its length before synthesis
+        let inputs = message(max as u64 + 1);
+        assert_eq!(
+            limits.check([inputs.len()]),
+            Err(LimitError::HashTooLong {
+                index: 0,
+                len: max + 1,
+                max
+            })
+        );
+        assert_eq!(
+            limits.check([max, 1]),
+            Err(LimitError::TaskTooLong { len: max + 1, max })
+        );
+        // which synthesis would only report as running out of rows
+        let digest = hash(&spec(), &inputs);
+        let result = MockProver::run(K, &TestCircuit::new(inputs), vec![vec![digest]]);
+        assert!(matches!(result, Err(Error::NotEnoughRowsAvailable { .. })));
+    }
+
+    #[test]
+    fn test_cancel_token() {
+        let inputs = message(5);
+        let digest = hash(&spec(), &inputs);
+        let cancel = CancelToken::new();
+        let circuit = TestCircuit::new(inputs).with_cancel_token(cancel.clone());
+        let prover = MockProver::run(K, &circuit, vec![vec![digest]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        cancel.cancel();
+        let result = MockProver::run(K, &circuit, vec![vec![digest]]);
+        assert!(matches!(result, Err(Error::Synthesis)));
+    }
+
+    #[test]
+    fn test_malformed_padding() {
+        // the padding one is not a message element
+        let digest = hash(&spec(), &[Fr::from(7), Fr::ONE]);
+        assert!(!accepts(vec![Fr::from(7)], Domain::Pse, digest));
+        assert!(!accepts(
+            vec![Fr::from(7), Fr::ONE, Fr::ZERO],
+            Domain::Pse,
+            digest
+        ));
+
+        // a message filling the rate takes a permutation for its padding alone
+        let inputs = message(3);
+        let mut state =
+            State::<Fr, 4, 3>::new([Domain::Pse.capacity(), Fr::ZERO, Fr::ZERO, Fr::ZERO]);
+        state.permute_traced(&spec(), &inputs, |_, _, _| {});
+        let unpadded = state.words()[1];
+        assert_ne!(unpadded, hash(&spec(), &inputs));
+        assert!(!accepts(inputs.clone(), Domain::Pse, unpadded));
+        assert!(accepts(inputs.clone(), Domain::Pse, hash(&spec(), &inputs)));
+    }
+
+    #[test]
+    fn test_domain_collisions() {
+        let inputs = message(2);
+        let digest = |domain| hash_with_domain(&spec(), domain, &inputs);
+        let user = Domain::User(1);
+        assert!(accepts(inputs.clone(), user, digest(user)));
+        for other in [
+            Domain::User(2),
+            Domain::Pse,
+            Domain::Constant(1),
+            Domain::ConstantLength { len: 2, outputs: 1 },
+        ] {
+            assert_ne!(digest(other), digest(user), "{other:?}");
+            assert!(!accepts(inputs.clone(), user, digest(other)), "{other:?}");
+        }
+
+        // the one documented collision: PSE's capacity is that of one fixed-length output
+        let pse_like = Domain::ConstantLength { len: 1, outputs: 1 };
+        assert_eq!(Domain::Pse.capacity::<Fr>(), pse_like.capacity::<Fr>());
+        assert!(accepts(inputs.clone(), pse_like, digest(Domain::Pse)));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one element")]
+    fn test_domain_without_outputs() {
+        Domain::ConstantLength { len: 2, outputs: 0 }.capacity::<Fr>();
+    }
+
+    #[test]
+    fn test_instance_counts() {
+        let inputs = message(5);
+        let digest = hash(&spec(), &inputs);
+        let circuit = TestCircuit::new(inputs);
+        for instances in [vec![], vec![vec![]], vec![vec![digest], vec![digest]]] {
+            let result = MockProver::run(K, &circuit, instances.clone())
+                .map_err(|_| ())
+                .and_then(|prover| prover.verify().map_err(|_| ()));
+            assert!(result.is_err(), "{instances:?}");
+        }
+    }
+}
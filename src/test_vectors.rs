@@ -0,0 +1,299 @@
+//! Known-answer vectors of other Poseidon implementations over bn256, which the native
+//! permutation of this crate, the textbook one of `poseidon_circuit-native` and
+//! [`crate::poseidon_circuit::PoseidonChip`] all reproduce.
+//!
+//! [`REFERENCE`] holds the `poseidonperm_x5_254_*` vectors of the reference implementation
+//! of the Poseidon paper, [`CIRCOMLIB`] the digests of circomlib's `poseidon(inputs)`, which
+//! is the first element of the permutation of `[0, inputs..]` without padding, and [`PSE`]
+//! a digest of the PSE sponge, the one every hash of this crate uses. Scroll's zktrie hashes
+//! with the width 3 permutation of circomlib, taking its domain as the capacity, so the
+//! width 3 vectors cover it too. Matching digests need matching constants, which all of
+//! these derive from the Grain generator with the round numbers given here; the digest
+//! index and capacity of a hash are then what [`crate::specs::DigestIndex`] and
+//! [`crate::specs::Domain`] pick.
+use ff::PrimeField;
+
+use crate::field_encoding::parse_field;
+
+/// One permutation of a state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PermutationVector {
+    pub source: &'static str,
+    pub width: usize,
+    pub r_f: usize,
+    pub r_p: usize,
+    /// The state before the permutation, the capacity first
+    pub input: &'static [&'static str],
+    /// The state after it, or its first elements only when the source outputs no more
+    pub output: &'static [&'static str],
+}
+
+impl PermutationVector {
+    pub fn input<F: PrimeField>(&self) -> Vec<F> {
+        parse(self.input)
+    }
+
+    pub fn output<F: PrimeField>(&self) -> Vec<F> {
+        parse(self.output)
+    }
+}
+
+/// The digest of a message under a sponge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashVector {
+    pub source: &'static str,
+    pub width: usize,
+    pub r_f: usize,
+    pub r_p: usize,
+    pub message: &'static [&'static str],
+    pub digest: &'static str,
+}
+
+impl HashVector {
+    pub fn message<F: PrimeField>(&self) -> Vec<F> {
+        parse(self.message)
+    }
+
+    pub fn digest<F: PrimeField>(&self) -> F {
+        parse_field(self.digest).expect("vectors are valid elements")
+    }
+}
+
+fn parse<F: PrimeField>(elements: &[&str]) -> Vec<F> {
+    elements
+        .iter()
+        .map(|s| parse_field(s).expect("vectors are valid elements"))
+        .collect()
+}
+
+pub const REFERENCE: &[PermutationVector] = &[
+    PermutationVector {
+        source: "reference poseidonperm_x5_254_3",
+        width: 3,
+        r_f: 8,
+        r_p: 57,
+        input: &["0", "1", "2"],
+        output: &[
+            "0x115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a",
+            "0x0fca49b798923ab0239de1c9e7a4a9a2210312b6a2f616d18b5a87f9b628ae29",
+            "0x0e7ae82e40091e63cbd4f16a6d16310b3729d4b6e138fcf54110e2867045a30c",
+        ],
+    },
+    PermutationVector {
+        source: "reference poseidonperm_x5_254_5",
+        width: 5,
+        r_f: 8,
+        r_p: 60,
+        input: &["0", "1", "2", "3", "4"],
+        output: &[
+            "0x299c867db6c1fdd79dcefa40e4510b9837e60ebb1ce0663dbaa525df65250465",
+            "0x1148aaef609aa338b27dafd89bb98862d8bb2b429aceac47d86206154ffe053d",
+            "0x24febb87fed7462e23f6665ff9a0111f4044c38ee1672c1ac6b0637d34f24907",
+            "0x0eb08f6d809668a981c186beaf6110060707059576406b248e5d9cf6e78b3d3e",
+            "0x07748bc6877c9b82c8b98666ee9d0626ec7f5be4205f79ee8528ef1c4a376fc7",
+        ],
+    },
+];
+
+pub const CIRCOMLIB: &[PermutationVector] = &[
+    PermutationVector {
+        source: "circomlib poseidon([1])",
+        width: 2,
+        r_f: 8,
+        r_p: 56,
+        input: &["0", "1"],
+        output: &["0x29176100eaa962bdc1fe6c654d6a3c130e96a4d1168b33848b897dc502820133"],
+    },
+    PermutationVector {
+        source: "circomlib poseidon([1, 2])",
+        width: 3,
+        r_f: 8,
+        r_p: 57,
+        input: &["0", "1", "2"],
+        output: &["0x115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a"],
+    },
+    PermutationVector {
+        source: "circomlib poseidon([1, 2, 3])",
+        width: 4,
+        r_f: 8,
+        r_p: 56,
+        input: &["0", "1", "2", "3"],
+        output: &["0x0e7732d89e6939c0ff03d5e58dab6302f3230e269dc5b968f725df34ab36d732"],
+    },
+    PermutationVector {
+        source: "circomlib poseidon([1, 2, 3, 4])",
+        width: 5,
+        r_f: 8,
+        r_p: 60,
+        input: &["0", "1", "2", "3", "4"],
+        output: &["0x299c867db6c1fdd79dcefa40e4510b9837e60ebb1ce0663dbaa525df65250465"],
+    },
+];
+
+pub const PSE: &[HashVector] = &[HashVector {
+    source: "PSE poseidon, 5 elements",
+    width: 4,
+    r_f: 8,
+    r_p: 56,
+    message: &["0", "1", "2", "3", "4"],
+    digest: "0x2ce4016298e9e5fcaa94ccb686413e16add1bb813def8a3a0628aed46ea07749",
+}];
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Error, Instance},
+    };
+    use halo2curves::bn256::Fr;
+    use poseidon::Spec;
+
+    use super::*;
+    use crate::{
+        main_gate::{MainGate, MainGateConfig, RegionCtx},
+        poseidon_circuit::PoseidonChip,
+        poseidon_hash::{hash, State},
+        specs::Domain,
+        test_circuit::TestCircuit,
+    };
+
+    const K: u32 = 10;
+
+    /// Permutes `input` from a zero capacity with [`PoseidonChip::duplex`] and exposes the
+    /// first `outputs` elements of the state.
+    struct PermutationCircuit<const T: usize, const RATE: usize> {
+        r_f: usize,
+        r_p: usize,
+        input: Vec<Fr>,
+        outputs: usize,
+    }
+
+    impl<const T: usize, const RATE: usize> Circuit<Fr> for PermutationCircuit<T, RATE> {
+        type Config = (MainGateConfig<T>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                input: vec![Fr::ZERO; RATE],
+                ..*self
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let config = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            (config, instance)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let spec = Spec::<Fr, T, RATE>::new(self.r_f, self.r_p);
+            // the length tag of an empty message squeezing one element is zero
+            let zero = Domain::ConstantLength { len: 0, outputs: 1 };
+            let chip = PoseidonChip::new(config, spec).with_domain(zero);
+            let (state, _) = layouter.assign_region(
+                || "permutation",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    chip.duplex(ctx, self.input.clone(), None)
+                },
+            )?;
+            for (i, cell) in state.iter().take(self.outputs).enumerate() {
+                layouter.constrain_instance(cell.cell(), instance, i)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn check<const T: usize, const RATE: usize>(vector: &PermutationVector) {
+        let input: [Fr; T] = vector.input().try_into().unwrap();
+        let output = vector.output::<Fr>();
+        let source = vector.source;
+        assert_eq!(
+            input[0],
+            Fr::ZERO,
+            "{source}: the chip starts from a zero capacity"
+        );
+
+        // the optimized permutation, absorbing a chunk of zeros so nothing is padded
+        let spec = Spec::<Fr, T, RATE>::new(vector.r_f, vector.r_p);
+        let mut state = State::new(input);
+        state.permute(&spec, &[Fr::ZERO; RATE]);
+        assert_eq!(&state.words()[..output.len()], &output[..], "{source}");
+
+        // the textbook one
+        let native = poseidon_circuit_native::Spec::<Fr, T, RATE>::new(vector.r_f, vector.r_p);
+        let mut state = input;
+        native.permute(&mut state);
+        assert_eq!(&state[..output.len()], &output[..], "{source}");
+
+        let circuit = PermutationCircuit::<T, RATE> {
+            r_f: vector.r_f,
+            r_p: vector.r_p,
+            input: input[1..].to_vec(),
+            outputs: output.len(),
+        };
+        let prover = MockProver::run(K, &circuit, vec![output.clone()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()), "{source}");
+        let mut wrong = output;
+        wrong[0] += Fr::ONE;
+        let prover = MockProver::run(K, &circuit, vec![wrong]).unwrap();
+        assert!(prover.verify().is_err(), "{source}");
+    }
+
+    #[test]
+    fn test_permutation_vectors() {
+        for vector in REFERENCE.iter().chain(CIRCOMLIB) {
+            match vector.width {
+                2 => check::<2, 1>(vector),
+                3 => check::<3, 2>(vector),
+                4 => check::<4, 3>(vector),
+                5 => check::<5, 4>(vector),
+                width => panic!("no check for width {width}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_circomlib_is_reference() {
+        // circomlib's digests are the first elements of the reference permutations
+        for reference in REFERENCE {
+            let circomlib = CIRCOMLIB
+                .iter()
+                .find(|v| v.width == reference.width)
+                .unwrap();
+            assert_eq!(circomlib.input, reference.input);
+            assert_eq!(circomlib.output::<Fr>()[0], reference.output::<Fr>()[0]);
+        }
+    }
+
+    #[test]
+    fn test_sponge_vectors() {
+        for vector in PSE {
+            assert_eq!(vector.width, 4, "the test circuit is of width 4");
+            let spec = Spec::<Fr, 4, 3>::new(vector.r_f, vector.r_p);
+            let message = vector.message::<Fr>();
+            let digest = vector.digest::<Fr>();
+            assert_eq!(hash(&spec, &message), digest, "{}", vector.source);
+            let native = poseidon_circuit_native::Spec::<Fr, 4, 3>::new(vector.r_f, vector.r_p);
+            assert_eq!(
+                poseidon_circuit_native::hash(&native, &message),
+                digest,
+                "{}",
+                vector.source
+            );
+
+            let circuit = TestCircuit::<Fr, 4, 3>::with_spec(message, vector.r_f, vector.r_p);
+            let prover = MockProver::run(K, &circuit, vec![vec![digest]]).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "{}", vector.source);
+        }
+    }
+}
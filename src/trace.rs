@@ -0,0 +1,232 @@
+//! Per-round witnesses of the Poseidon permutation, for folding frontends.
+//!
+//! Nova-style frontends fold one permutation round per step and need the state before and
+//! after every round. [`hash_trace`] records them while hashing natively, following the
+//! same optimized decomposition as [`crate::poseidon_circuit::PoseidonChip`]: an absorbing
+//! step that adds the inputs and first round constants, then `r_f` full and `r_p` partial
+//! rounds whose linear layers are the pre-sparse and sparse matrices of the spec. The states
+//! between rounds are therefore those of the circuit, not of the textbook permutation.
+//!
+//! `snarkify trace` prints a [`HashTrace`] round by round, or as JSON, to compare against the
+//! intermediate states of another implementation of the same decomposition.
+use std::fmt;
+
+use ff::{FromUniformBytes, PrimeField};
+use poseidon::Spec;
+use serde_json::{json, Value};
+
+use crate::{field_encoding::to_canonical, poseidon_hash::State, specs::DigestIndex};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundKind {
+    /// Adds the absorbed inputs, the padding and the first round constants
+    Absorb,
+    Full,
+    Partial,
+}
+
+impl RoundKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Absorb => "absorb",
+            Self::Full => "full",
+            Self::Partial => "partial",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoundStep<F, const T: usize> {
+    pub kind: RoundKind,
+    pub before: [F; T],
+    pub after: [F; T],
+}
+
+/// One permutation of the sponge.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PermutationTrace<F, const T: usize> {
+    /// Elements absorbed by this permutation, at most `RATE`
+    pub inputs: Vec<F>,
+    /// `1 + r_f + r_p` steps, each starting from the state the previous one ended in
+    pub steps: Vec<RoundStep<F, T>>,
+}
+
+impl<F: Copy, const T: usize> PermutationTrace<F, T> {
+    pub fn initial_state(&self) -> [F; T] {
+        self.steps[0].before
+    }
+
+    pub fn final_state(&self) -> [F; T] {
+        self.steps[self.steps.len() - 1].after
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HashTrace<F, const T: usize> {
+    pub permutations: Vec<PermutationTrace<F, T>>,
+    pub digest: F,
+}
+
+impl<F: PrimeField, const T: usize> HashTrace<F, T> {
+    /// The final state element at `digest`; [`HashTrace::digest`] is the one at
+    /// [`DigestIndex::PSE`].
+    pub fn digest_at(&self, digest: DigestIndex) -> F {
+        self.permutations[self.permutations.len() - 1].final_state()[digest.0]
+    }
+
+    /// The trace with every element in the canonical encoding of [`crate::field_encoding`].
+    pub fn to_json(&self) -> Value {
+        let state = |words: &[F; T]| words.iter().map(to_canonical).collect::<Vec<_>>();
+        let permutations = self
+            .permutations
+            .iter()
+            .map(|perm| {
+                let steps = perm
+                    .steps
+                    .iter()
+                    .map(|step| {
+                        json!({
+                            "kind": step.kind.as_str(),
+                            "before": state(&step.before),
+                            "after": state(&step.after),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({
+                    "inputs": perm.inputs.iter().map(to_canonical).collect::<Vec<_>>(),
+                    "steps": steps,
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "permutations": permutations,
+            "digest": to_canonical(&self.digest),
+        })
+    }
+}
+
+/// One line per round with the state it ends in, numbered within its permutation.
+impl<F: PrimeField, const T: usize> fmt::Display for HashTrace<F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = |words: &[F; T]| words.iter().map(to_canonical).collect::<Vec<_>>().join(" ");
+        for (i, perm) in self.permutations.iter().enumerate() {
+            let inputs = perm.inputs.iter().map(to_canonical).collect::<Vec<_>>();
+            writeln!(f, "permutation {i}, absorbing [{}]", inputs.join(", "))?;
+            writeln!(
+                f,
+                "  {:<7} {:>3}  {}",
+                "initial",
+                "",
+                state(&perm.initial_state())
+            )?;
+            for (round, step) in perm.steps.iter().enumerate() {
+                writeln!(
+                    f,
+                    "  {:<7} {round:>3}  {}",
+                    step.kind.as_str(),
+                    state(&step.after)
+                )?;
+            }
+        }
+        writeln!(f, "digest {}", to_canonical(&self.digest))
+    }
+}
+
+/// Hashes `inputs` like [`crate::poseidon_hash::hash`] and records every round.
+pub fn hash_trace<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    inputs: &[F],
+) -> HashTrace<F, T> {
+    let mut state = State::<F, T, RATE>::new(poseidon::State::default().words());
+    let mut chunks = inputs.chunks(RATE).collect::<Vec<_>>();
+    if inputs.len().is_multiple_of(RATE) {
+        chunks.push(&[]);
+    }
+    let permutations = chunks
+        .into_iter()
+        .map(|chunk| {
+            let mut steps = Vec::with_capacity(1 + spec.r_f() + spec.constants().partial().len());
+            state.permute_traced(spec, chunk, |kind, before, after| {
+                steps.push(RoundStep {
+                    kind,
+                    before,
+                    after,
+                })
+            });
+            PermutationTrace {
+                inputs: chunk.to_vec(),
+                steps,
+            }
+        })
+        .collect();
+    HashTrace {
+        permutations,
+        digest: state.words()[DigestIndex::PSE.0],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::poseidon_hash::{hash, hash_to};
+
+    #[test]
+    fn test_hash_trace() {
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        let inputs = (0..5).map(|i| Fr::from(i as u64)).collect::<Vec<_>>();
+        let trace = hash_trace(&spec, &inputs);
+        assert_eq!(trace.digest, hash(&spec, &inputs));
+        assert_eq!(trace.digest_at(DigestIndex::PSE), trace.digest);
+        assert_eq!(
+            trace.digest_at(DigestIndex::CIRCOM),
+            hash_to(&spec, &inputs, DigestIndex::CIRCOM)
+        );
+        assert_eq!(trace.permutations.len(), 2);
+        assert_eq!(trace.permutations[1].inputs, inputs[3..]);
+
+        for perm in &trace.permutations {
+            assert_eq!(perm.steps.len(), 1 + 8 + 56);
+            assert_eq!(perm.steps[0].kind, RoundKind::Absorb);
+            assert_eq!(perm.steps[5].kind, RoundKind::Partial);
+            for pair in perm.steps.windows(2) {
+                assert_eq!(pair[0].after, pair[1].before);
+            }
+        }
+        assert_eq!(
+            trace.permutations[0].final_state(),
+            trace.permutations[1].initial_state()
+        );
+        // an exact multiple of the rate takes an extra permutation
+        assert_eq!(hash_trace(&spec, &inputs[..3]).permutations.len(), 2);
+    }
+
+    #[test]
+    fn test_trace_output() {
+        let spec = Spec::<Fr, 4, 3>::new(8, 56);
+        let trace = hash_trace(&spec, &[Fr::from(1), Fr::from(2)]);
+        let json = trace.to_json();
+        assert_eq!(json["digest"], to_canonical(&trace.digest));
+        assert_eq!(
+            json["permutations"][0]["steps"].as_array().unwrap().len(),
+            65
+        );
+        assert_eq!(json["permutations"][0]["steps"][1]["kind"], "full");
+        assert_eq!(
+            json["permutations"][0]["steps"][64]["after"][1],
+            to_canonical(&trace.digest)
+        );
+
+        let text = trace.to_string();
+        // a header, the initial state and 65 rounds, then the digest
+        assert_eq!(text.lines().count(), 1 + 1 + 65 + 1);
+        assert!(text
+            .lines()
+            .nth(2)
+            .unwrap()
+            .trim_start()
+            .starts_with("absorb"));
+        assert!(text.ends_with(&format!("digest {}\n", to_canonical(&trace.digest))));
+    }
+}
@@ -0,0 +1,242 @@
+//! Permutation traces of repeated message chunks, reused across tasks.
+//!
+//! Tasks sharing a prefix, such as identical headers, permute the same chunks from the same
+//! states. A [`TraceCache`] keeps the state after every round of a permutation, keyed by
+//! the state it starts from and the chunk it absorbs, so a chip built with
+//! [`PoseidonChip::with_trace_cache`] computes the witness of a shared prefix once and
+//! assigns it from the cache afterwards. The key is the whole starting state, so a chunk
+//! only hits after the same prefix, and a differing chunk misses for every one after it.
+//!
+//! Only computing the values is skipped: the rows, keys and proofs are the same with or
+//! without a cache.
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use ff::PrimeField;
+use poseidon::Spec;
+
+use crate::optimized_constants::OptimizedConstants;
+#[cfg(doc)]
+use crate::poseidon_circuit::PoseidonChip;
+
+/// Default number of permutations kept; one takes `(1 + r_f + r_p) * T` elements, 8 KiB
+/// for the spec of the service.
+pub const DEFAULT_TRACE_CACHE_ENTRIES: usize = 4096;
+
+/// How a [`TraceCache`] fared since it was built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+#[derive(Debug)]
+struct Entries<F, const T: usize> {
+    /// Trace and the tick it was last used at, by starting state and chunk
+    traces: HashMap<Vec<u8>, (Arc<Vec<[F; T]>>, u64)>,
+    tick: u64,
+}
+
+/// Permutation traces of one spec, least recently used first out.
+#[derive(Debug)]
+pub struct TraceCache<F, const T: usize, const RATE: usize> {
+    constants: Arc<OptimizedConstants<F, T, RATE>>,
+    capacity: usize,
+    entries: Mutex<Entries<F, T>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<F: PrimeField, const T: usize, const RATE: usize> TraceCache<F, T, RATE> {
+    /// Caches up to `capacity` permutations of the spec of `constants`.
+    pub fn new(constants: Arc<OptimizedConstants<F, T, RATE>>, capacity: usize) -> Self {
+        Self {
+            constants,
+            capacity: capacity.max(1),
+            entries: Mutex::new(Entries {
+                traces: HashMap::new(),
+                tick: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn for_spec(spec: &Spec<F, T, RATE>, capacity: usize) -> Self {
+        Self::new(Arc::new(OptimizedConstants::from_spec(spec)), capacity)
+    }
+
+    /// Whether the cache holds traces of the spec of `constants`.
+    pub fn serves(&self, constants: &Arc<OptimizedConstants<F, T, RATE>>) -> bool {
+        Arc::ptr_eq(&self.constants, constants) || *self.constants == **constants
+    }
+
+    /// The trace of permuting `state` with `inputs`, from `compute` on a miss.
+    ///
+    /// The lock is released while computing, so two threads missing the same chunk may
+    /// both compute it. A full cache evicts the least recently used trace, which takes a
+    /// scan of the cache; a miss computes a whole permutation anyway.
+    pub(crate) fn get_or_compute(
+        &self,
+        state: &[F; T],
+        inputs: &[F],
+        compute: impl FnOnce() -> Vec<[F; T]>,
+    ) -> Arc<Vec<[F; T]>> {
+        let key = state
+            .iter()
+            .chain(inputs)
+            .flat_map(|x| x.to_repr().as_ref().to_vec())
+            .collect::<Vec<_>>();
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.tick += 1;
+            let tick = entries.tick;
+            if let Some((trace, used)) = entries.traces.get_mut(&key) {
+                *used = tick;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return trace.clone();
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let trace = Arc::new(compute());
+        let mut entries = self.entries.lock().unwrap();
+        if entries.traces.len() >= self.capacity && !entries.traces.contains_key(&key) {
+            let oldest = entries
+                .traces
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.traces.remove(&oldest);
+            }
+        }
+        let tick = entries.tick;
+        entries.traces.insert(key, (trace.clone(), tick));
+        trace
+    }
+
+    pub fn stats(&self) -> TraceCacheStats {
+        TraceCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().traces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{dev::MockProver, plonk::ConstraintSystem};
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        main_gate::MainGate, poseidon_circuit::PoseidonChip, poseidon_hash::hash,
+        test_circuit::TestCircuit,
+    };
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+
+    fn spec() -> Spec<Fr, T, RATE> {
+        Spec::new(8, 56)
+    }
+
+    fn chip(cache: Option<Arc<TraceCache<Fr, T, RATE>>>) -> PoseidonChip<Fr, T, RATE> {
+        let mut cs = ConstraintSystem::<Fr>::default();
+        let mut adv_cols = [(); T + 2].map(|_| cs.advice_column()).into_iter();
+        let mut fix_cols = [(); 2 * T + 4].map(|_| cs.fixed_column()).into_iter();
+        let config = MainGate::<Fr, T>::configure(&mut cs, &mut adv_cols, &mut fix_cols);
+        let chip = PoseidonChip::new(config, spec());
+        match cache {
+            Some(cache) => chip.with_trace_cache(cache),
+            None => chip,
+        }
+    }
+
+    #[test]
+    fn test_shared_prefix() {
+        let cache = Arc::new(TraceCache::for_spec(&spec(), 16));
+        let cached = chip(Some(cache.clone()));
+        let header = (0..6).map(Fr::from).collect::<Vec<_>>();
+        let a = [&header[..], &[Fr::from(100)]].concat();
+        let b = [&header[..], &[Fr::from(200)]].concat();
+
+        assert_eq!(cached.trace(&a), chip(None).trace(&a));
+        // two chunks of header, then the chunk of the body
+        assert_eq!(
+            cache.stats(),
+            TraceCacheStats {
+                hits: 0,
+                misses: 3,
+                entries: 3
+            }
+        );
+        assert_eq!(cached.trace(&b), chip(None).trace(&b));
+        assert_eq!(
+            cache.stats(),
+            TraceCacheStats {
+                hits: 2,
+                misses: 4,
+                entries: 4
+            }
+        );
+
+        // the same chunk after another prefix is another permutation
+        let c = [&[Fr::from(7)], &header[1..], &[Fr::from(100)]].concat();
+        cached.trace(&c);
+        assert_eq!(cache.stats().hits, 2);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let cache = Arc::new(TraceCache::for_spec(&spec(), 2));
+        let cached = chip(Some(cache.clone()));
+        let messages = [[1u64], [2], [1], [3], [2]].map(|m| m.map(Fr::from).to_vec());
+        for message in &messages {
+            cached.trace(message);
+        }
+        // [2] was evicted by [3], [1] was kept by its second use
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 4, 2));
+    }
+
+    #[test]
+    #[should_panic(expected = "another spec")]
+    fn test_other_spec() {
+        let cache = Arc::new(TraceCache::for_spec(&Spec::new(8, 57), 2));
+        chip(Some(cache));
+    }
+
+    #[test]
+    fn test_circuit() {
+        let cache = Arc::new(TraceCache::for_spec(&spec(), 16));
+        let header = (0..6).map(Fr::from).collect::<Vec<_>>();
+        for last in [100, 200] {
+            let message = [&header[..], &[Fr::from(last)]].concat();
+            let digest = hash(&spec(), &message);
+            let circuit = TestCircuit::new(message).with_trace_cache(cache.clone());
+            let prover = MockProver::run(10, &circuit, vec![vec![digest]]).unwrap();
+            assert_eq!(prover.verify(), Ok(()));
+        }
+        assert!(cache.stats().hits >= 2);
+    }
+}
@@ -0,0 +1,242 @@
+//! Vector commitments with position openings, on a fixed-depth Poseidon Merkle tree.
+//!
+//! A vector of up to `2^depth` slots is committed to by the root of the tree whose leaf `i`
+//! is `Poseidon(v_i)`; slots past the end of the vector are empty, i.e. zero leaves, so no
+//! value opens there. An [`Opening`] of slot `i` is its value and a Merkle path of exactly
+//! `depth` siblings, the same size for every slot. [`VectorCommitmentChip`] checks an
+//! opening in-circuit against assigned value and index cells.
+use ff::{FromUniformBytes, PrimeField};
+use halo2_proofs::plonk::Error;
+use poseidon::Spec;
+
+use crate::{
+    main_gate::{AssignedValue, MainGateConfig, RegionCtx},
+    merkle::{MerkleChip, MerklePath, MerkleTree},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash,
+};
+
+/// The leaf committing to a slot value.
+pub fn slot_leaf<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    value: F,
+) -> F {
+    hash(spec, &[value])
+}
+
+#[derive(Clone, Debug)]
+pub struct VectorCommitment<F> {
+    values: Vec<F>,
+    tree: MerkleTree<F>,
+}
+
+/// Proof that a slot holds a value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Opening<F> {
+    pub value: F,
+    /// Path of the slot, whose index is the slot position
+    pub path: MerklePath<F>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> VectorCommitment<F> {
+    /// Commits to `values` in a tree of `depth` levels; panics if they do not fit.
+    pub fn commit<const T: usize, const RATE: usize>(
+        spec: &Spec<F, T, RATE>,
+        values: Vec<F>,
+        depth: usize,
+    ) -> Self {
+        let leaves = values.iter().map(|v| slot_leaf(spec, *v)).collect();
+        Self {
+            tree: MerkleTree::new(spec, leaves, depth),
+            values,
+        }
+    }
+
+    pub fn commitment(&self) -> F {
+        self.tree.root()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Opens slot `index`, which must be below [`VectorCommitment::len`].
+    pub fn open(&self, index: u64) -> Opening<F> {
+        Opening {
+            value: self.values[index as usize],
+            path: self.tree.path(index),
+        }
+    }
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> Opening<F> {
+    pub fn index(&self) -> u64 {
+        self.path.index
+    }
+
+    /// Checks the opening natively against a commitment.
+    pub fn verify<const T: usize, const RATE: usize>(
+        &self,
+        spec: &Spec<F, T, RATE>,
+        commitment: F,
+    ) -> bool {
+        self.path.root(spec, slot_leaf(spec, self.value)) == commitment
+    }
+}
+
+pub struct VectorCommitmentChip<F: PrimeField, const T: usize, const RATE: usize> {
+    config: MainGateConfig<T>,
+    spec: Spec<F, T, RATE>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    VectorCommitmentChip<F, T, RATE>
+{
+    pub fn new(config: MainGateConfig<T>, spec: Spec<F, T, RATE>) -> Self {
+        Self { config, spec }
+    }
+
+    /// Rows used by [`VectorCommitmentChip::open`] for a tree of `depth` levels.
+    pub fn num_rows(spec: &Spec<F, T, RATE>, depth: usize) -> usize {
+        PoseidonChip::num_rows(spec, 1) + MerkleChip::num_rows(spec, depth)
+    }
+
+    /// Constrains `value` to sit at slot `index` and returns the commitment cell, for the
+    /// caller to constrain to the expected commitment.
+    pub fn open(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        value: &AssignedValue<F>,
+        index: &AssignedValue<F>,
+        opening: &Opening<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        let mut pchip = PoseidonChip::new(self.config.clone(), self.spec.clone());
+        pchip.update(vec![opening.value]);
+        let (inputs, leaf) = pchip.squeeze_with_inputs(ctx)?;
+        ctx.constrain_equal(inputs[0].cell(), value.cell())?;
+
+        let merkle = MerkleChip::new(self.config.clone(), self.spec.clone());
+        merkle.compute_root(
+            ctx,
+            &leaf,
+            slot_leaf(&self.spec, opening.value),
+            index,
+            &opening.path,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner, Value},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+    };
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::main_gate::MainGate;
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+    const DEPTH: usize = 3;
+    const K: u32 = 11;
+
+    fn spec() -> Spec<Fr, T, RATE> {
+        Spec::new(8, 56)
+    }
+
+    /// Opens one slot and exposes `[commitment, value, index]`.
+    struct OpeningCircuit {
+        opening: Opening<Fr>,
+    }
+
+    impl Circuit<Fr> for OpeningCircuit {
+        type Config = (MainGateConfig<T>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                opening: Opening {
+                    value: Fr::ZERO,
+                    path: MerklePath {
+                        index: 0,
+                        siblings: vec![Fr::ZERO; DEPTH],
+                    },
+                },
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let config = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            (config, instance)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = VectorCommitmentChip::new(config.clone(), spec());
+            let cells = layouter.assign_region(
+                || "vector commitment opening",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    // free cells: every selector of the main gate is zero on this row
+                    let value = ctx.assign_advice(
+                        || "opening: value",
+                        config.state[0],
+                        Value::known(self.opening.value),
+                    )?;
+                    let index = ctx.assign_advice(
+                        || "opening: index",
+                        config.state[1],
+                        Value::known(Fr::from(self.opening.index())),
+                    )?;
+                    ctx.next();
+                    let commitment = chip.open(ctx, &value, &index, &self.opening)?;
+                    Ok([commitment, value, index])
+                },
+            )?;
+            for (row, cell) in cells.iter().enumerate() {
+                layouter.constrain_instance(cell.cell(), instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_vector_commitment() {
+        let spec = spec();
+        assert!(1 + VectorCommitmentChip::num_rows(&spec, DEPTH) + 6 <= 1 << K);
+        let values = (0..6).map(|i| Fr::from(100 + i)).collect::<Vec<_>>();
+        let vc = VectorCommitment::commit(&spec, values, DEPTH);
+        let opening = vc.open(5);
+        assert_eq!(opening.path.siblings.len(), DEPTH);
+        assert!(opening.verify(&spec, vc.commitment()));
+
+        let mut forged = opening.clone();
+        forged.value = Fr::from(7);
+        assert!(!forged.verify(&spec, vc.commitment()));
+
+        let instance = vec![vec![vc.commitment(), Fr::from(105), Fr::from(5)]];
+        let circuit = OpeningCircuit { opening };
+        let prover = MockProver::run(K, &circuit, instance.clone()).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // the value of another slot
+        let wrong = vec![vec![vc.commitment(), Fr::from(104), Fr::from(5)]];
+        let prover = MockProver::run(K, &circuit, wrong).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
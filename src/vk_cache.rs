@@ -0,0 +1,184 @@
+//! Verifying keys of past circuit versions, loaded on demand and kept in an LRU cache.
+//!
+//! Proofs made before a circuit or fork upgrade record the hash of their verifying key
+//! ([`crate::task::ProofDetail::vk_hash`]). The verifier reads such keys as `<vk hash>.vk`
+//! from a directory or any other [`ArtifactStore`], see [`VkStore`], and caches the deserialized keys so bulk
+//! re-verification of mixed historical proofs deserializes every key once.
+use std::{
+    collections::VecDeque,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use halo2_proofs::plonk::{Circuit, VerifyingKey};
+use halo2curves::bn256::{Fr, G1Affine};
+
+use crate::{
+    artifacts::{vk_key, ArtifactStore, LocalStore},
+    prover::{read_vk, vk_hash},
+};
+
+/// Default number of historical keys kept in memory.
+pub const DEFAULT_VK_CACHE_CAPACITY: usize = 16;
+
+/// A least recently used cache; small enough that a linear scan beats hashing.
+#[derive(Debug)]
+pub struct LruCache<V> {
+    capacity: usize,
+    /// Most recently used first
+    entries: Mutex<VecDeque<(String, Arc<V>)>>,
+}
+
+impl<V> LruCache<V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The value of `key`, calling `load` on a miss; failed loads are not cached.
+    ///
+    /// The lock is released while loading, so two threads missing the same key may both
+    /// load it.
+    pub fn get_or_load<E>(
+        &self,
+        key: &str,
+        load: impl FnOnce() -> Result<V, E>,
+    ) -> Result<Arc<V>, E> {
+        if let Some(value) = self.get(key) {
+            return Ok(value);
+        }
+        let value = Arc::new(load()?);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|(k, _)| k != key);
+        entries.push_front((key.to_string(), value.clone()));
+        entries.truncate(self.capacity);
+        Ok(value)
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<V>> {
+        let mut entries = self.entries.lock().unwrap();
+        let index = entries.iter().position(|(k, _)| k == key)?;
+        let entry = entries.remove(index)?;
+        let value = entry.1.clone();
+        entries.push_front(entry);
+        Some(value)
+    }
+
+    /// Whether `key` is cached, without making it the most recently used.
+    pub fn contains(&self, key: &str) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A directory of serialized historical verifying keys, with a cache in front.
+#[derive(Debug)]
+pub struct VkStore {
+    store: Arc<dyn ArtifactStore>,
+    cache: LruCache<VerifyingKey<G1Affine>>,
+}
+
+impl VkStore {
+    /// Reads `<vk hash>.vk` files from `dir`.
+    pub fn new(dir: impl Into<PathBuf>, capacity: usize) -> Self {
+        Self::from_store(Arc::new(LocalStore::new(dir)), capacity)
+    }
+
+    pub fn from_store(store: Arc<dyn ArtifactStore>, capacity: usize) -> Self {
+        Self {
+            store,
+            cache: LruCache::new(capacity),
+        }
+    }
+
+    /// The key whose [`vk_hash`] is `hash`, as a key of `ConcreteCircuit`'s shape.
+    pub fn get<ConcreteCircuit: Circuit<Fr>>(
+        &self,
+        hash: &str,
+    ) -> io::Result<Arc<VerifyingKey<G1Affine>>> {
+        // the hash names a file, so it must not name anything else
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("malformed vk hash {hash:?}"),
+            ));
+        }
+        let hash = hash.to_ascii_lowercase();
+        self.cache.get_or_load(&hash, || {
+            let bytes = self.store.get(&vk_key(&hash))?;
+            let vk = read_vk::<ConcreteCircuit>(&bytes)?;
+            if vk_hash(&vk) != hash {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{hash}.vk holds another key"),
+                ));
+            }
+            Ok(vk)
+        })
+    }
+
+    pub fn cached(&self) -> usize {
+        self.cache.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = LruCache::new(2);
+        let loads = Cell::new(0);
+        let get = |key: &str| {
+            *cache
+                .get_or_load::<()>(key, || {
+                    loads.set(loads.get() + 1);
+                    Ok(key.len())
+                })
+                .unwrap()
+        };
+        get("a");
+        get("bb");
+        get("a");
+        // evicts bb, the least recently used
+        get("ccc");
+        get("a");
+        assert_eq!(loads.get(), 3);
+        get("bb");
+        assert_eq!(loads.get(), 4);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("ccc").is_none());
+
+        assert!(cache.get_or_load("d", || Err("unreadable")).is_err());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_malformed_hash() {
+        let store = VkStore::new("/nonexistent", 4);
+        for hash in ["../secret", &"g".repeat(64), "00"] {
+            let err = store
+                .get::<crate::test_circuit::TestCircuit<Fr>>(hash)
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! Verifying keys in a versioned binary format, to provision verifiers outside the service
+//! without rebuilding the circuit.
+//!
+//! A [`VkExport`] holds the verifying key along with what a verifier needs besides it: the
+//! circuit it is of, and the digest of the params it was generated on, which the verifier
+//! must hold as well. Version 1 is, in order:
+//!
+//! | bytes     | field                                                                   |
+//! | --------- | ----------------------------------------------------------------------- |
+//! | 4         | magic `PCVK`                                                            |
+//! | 1         | format version, 1                                                       |
+//! | 1 + n     | length and ASCII name of the circuit, as in the capability document     |
+//! | 4         | `k`, little-endian                                                      |
+//! | 32        | [`params_digest`] of the params at `k`                                  |
+//! | 2 + n     | length, little-endian, and UTF-8 protocol label, empty if unlabelled    |
+//! | 4 + n     | length, little-endian, and the key in `SerdeFormat::RawBytes`           |
+//!
+//! The salt of a salted deployment is a secret and is never exported; proofs of such
+//! deployments only verify in the service.
+use std::{fmt, io};
+
+use halo2_proofs::{
+    plonk::{Circuit, VerifyingKey},
+    poly::{commitment::Params, kzg::commitment::ParamsKZG},
+    SerdeFormat,
+};
+use halo2curves::bn256::{Bn256, Fr, G1Affine};
+
+use crate::prover::{read_vk, vk_hash, ProverContext};
+
+pub const VK_MAGIC: [u8; 4] = *b"PCVK";
+/// Version of the format [`VkExport::encode`] writes.
+pub const VK_FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug)]
+pub enum VkFormatError {
+    /// The bytes do not start with [`VK_MAGIC`]
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    TrailingBytes(usize),
+    InvalidCircuitName,
+    InvalidLabel,
+    /// The key is not one of the circuit asked for
+    Key(io::Error),
+}
+
+impl fmt::Display for VkFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not an exported verifying key"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "verifying key format version {version} is not supported")
+            }
+            Self::Truncated => write!(f, "exported verifying key is truncated"),
+            Self::TrailingBytes(n) => write!(f, "{n} bytes after the exported verifying key"),
+            Self::InvalidCircuitName => write!(f, "circuit name is not ASCII"),
+            Self::InvalidLabel => write!(f, "protocol label is not UTF-8"),
+            Self::Key(err) => write!(f, "verifying key does not read back: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for VkFormatError {}
+
+/// Hex Blake2b-256 of `params` as written by `ParamsKZG::write`, naming the params a key
+/// was generated on.
+pub fn params_digest(params: &ParamsKZG<Bn256>) -> String {
+    let mut bytes = Vec::new();
+    params
+        .write(&mut bytes)
+        .expect("writing to a vector does not fail");
+    blake2b_simd::Params::new()
+        .hash_length(32)
+        .hash(&bytes)
+        .to_hex()
+        .to_string()
+}
+
+/// A verifying key with its circuit, size, params and protocol label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VkExport {
+    /// Name of the circuit, e.g. `preimage`
+    pub circuit: String,
+    pub k: u32,
+    /// See [`params_digest`]
+    pub params_digest: String,
+    /// Label absorbed into every transcript, see [`ProverContext::with_protocol_label`]
+    pub protocol_label: Option<String>,
+    /// The key in `SerdeFormat::RawBytes`
+    pub vk: Vec<u8>,
+}
+
+impl VkExport {
+    /// The key of `ctx`, exported as the key of `circuit`.
+    pub fn new<C: Circuit<Fr>>(circuit: &str, ctx: &ProverContext<C>) -> Self {
+        Self {
+            circuit: circuit.to_string(),
+            k: ctx.k(),
+            params_digest: params_digest(ctx.params()),
+            protocol_label: ctx.protocol_label().map(str::to_string),
+            vk: ctx.pk().get_vk().to_bytes(SerdeFormat::RawBytes),
+        }
+    }
+
+    /// The [`vk_hash`] of the key, as recorded in the proof details of its proofs.
+    pub fn vk_hash(&self) -> String {
+        blake2b_simd::Params::new()
+            .hash_length(32)
+            .hash(&self.vk)
+            .to_hex()
+            .to_string()
+    }
+
+    /// Writes the current version of the format.
+    ///
+    /// # Panics
+    ///
+    /// For circuit names of over 255 bytes or labels of over 65535.
+    pub fn encode(&self) -> Vec<u8> {
+        let label = self.protocol_label.as_deref().unwrap_or_default();
+        let mut out = Vec::with_capacity(48 + self.circuit.len() + label.len() + self.vk.len());
+        out.extend(VK_MAGIC);
+        out.push(VK_FORMAT_VERSION);
+        out.push(u8::try_from(self.circuit.len()).expect("circuit names fit 255 bytes"));
+        out.extend(self.circuit.as_bytes());
+        out.extend(self.k.to_le_bytes());
+        out.extend(digest_bytes(&self.params_digest));
+        out.extend(
+            u16::try_from(label.len())
+                .expect("labels fit 65535 bytes")
+                .to_le_bytes(),
+        );
+        out.extend(label.as_bytes());
+        out.extend((self.vk.len() as u32).to_le_bytes());
+        out.extend(&self.vk);
+        out
+    }
+
+    /// Reads any supported version of the format, without reading the key itself, which
+    /// needs the circuit, see [`VkExport::verifying_key`].
+    pub fn decode(bytes: &[u8]) -> Result<Self, VkFormatError> {
+        let mut reader = Reader(bytes);
+        if reader.take(4)? != VK_MAGIC {
+            return Err(VkFormatError::BadMagic);
+        }
+        match reader.take(1)?[0] {
+            1 => {}
+            version => return Err(VkFormatError::UnsupportedVersion(version)),
+        }
+        let len = reader.take(1)?[0] as usize;
+        let circuit = reader.take(len)?;
+        if !circuit.is_ascii() {
+            return Err(VkFormatError::InvalidCircuitName);
+        }
+        let circuit = String::from_utf8(circuit.to_vec()).expect("ASCII is UTF-8");
+        let k = u32::from_le_bytes(reader.take(4)?.try_into().expect("four bytes"));
+        let params_digest = hex(reader.take(32)?);
+        let len = u16::from_le_bytes(reader.take(2)?.try_into().expect("two bytes"));
+        let label = String::from_utf8(reader.take(len as usize)?.to_vec())
+            .map_err(|_| VkFormatError::InvalidLabel)?;
+        let len = u32::from_le_bytes(reader.take(4)?.try_into().expect("four bytes"));
+        let vk = reader.take(len as usize)?.to_vec();
+        if !reader.0.is_empty() {
+            return Err(VkFormatError::TrailingBytes(reader.0.len()));
+        }
+        Ok(Self {
+            circuit,
+            k,
+            params_digest,
+            protocol_label: (!label.is_empty()).then_some(label),
+            vk,
+        })
+    }
+
+    /// Reads the key as a key of `C`; a key of another circuit does not read back to the
+    /// same bytes, or is of another size, and is rejected.
+    pub fn verifying_key<C: Circuit<Fr>>(&self) -> Result<VerifyingKey<G1Affine>, VkFormatError> {
+        let vk = read_vk::<C>(&self.vk).map_err(VkFormatError::Key)?;
+        if vk.to_bytes(SerdeFormat::RawBytes) != self.vk || vk.get_domain().k() != self.k {
+            return Err(VkFormatError::Key(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("not a key of {}", self.circuit),
+            )));
+        }
+        debug_assert_eq!(vk_hash(&vk), self.vk_hash());
+        Ok(vk)
+    }
+}
+
+/// The bytes of a hex digest; [`params_digest`] always has 64 digits.
+fn digest_bytes(digest: &str) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    for (byte, pair) in bytes.iter_mut().zip(digest.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).expect("hex digits are ASCII");
+        *byte = u8::from_str_radix(pair, 16).expect("digests are hex");
+    }
+    bytes
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], VkFormatError> {
+        if self.0.len() < n {
+            return Err(VkFormatError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+
+    use super::*;
+    use crate::{
+        poseidon_hash::hash, preimage::PreimageCircuit, prover::verify_detached,
+        specs::BN256_T4_R3, test_circuit::TestCircuit,
+    };
+
+    #[test]
+    fn test_round_trip() {
+        let inputs = vec![Fr::ONE; 2];
+        let circuit = TestCircuit::new(inputs.clone());
+        let ctx = ProverContext::setup(8, &circuit)
+            .unwrap()
+            .with_protocol_label("vk-export");
+        let export = VkExport::new("test_circuit", &ctx);
+        assert_eq!(export.vk_hash(), ctx.vk_hash());
+        assert_eq!(export.params_digest.len(), 64);
+
+        let bytes = export.encode();
+        let decoded = VkExport::decode(&bytes).unwrap();
+        assert_eq!(decoded, export);
+        let vk = decoded.verifying_key::<TestCircuit<Fr>>().unwrap();
+        assert!(decoded.verifying_key::<PreimageCircuit<Fr>>().is_err());
+
+        // the imported key verifies proofs of the service's key
+        let spec = poseidon::Spec::<Fr, 4, 3>::new(BN256_T4_R3.r_f, BN256_T4_R3.r_p);
+        let digest = [hash(&spec, &inputs)];
+        let proof = ctx.prove(&circuit, &[&digest]).unwrap();
+        let label = decoded.protocol_label.as_deref();
+        assert!(verify_detached(ctx.params(), &vk, label, &proof, &[&digest]).is_ok());
+
+        let unlabelled = VkExport {
+            protocol_label: None,
+            ..export.clone()
+        };
+        assert_eq!(VkExport::decode(&unlabelled.encode()).unwrap(), unlabelled);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let export = VkExport {
+            circuit: "preimage".to_string(),
+            k: 10,
+            params_digest: "ab".repeat(32),
+            protocol_label: None,
+            vk: vec![1, 2, 3],
+        };
+        let bytes = export.encode();
+        assert_eq!(VkExport::decode(&bytes).unwrap(), export);
+
+        let mut other = bytes.clone();
+        other[0] = b'X';
+        assert!(matches!(
+            VkExport::decode(&other),
+            Err(VkFormatError::BadMagic)
+        ));
+        other = bytes.clone();
+        other[4] = 2;
+        assert!(matches!(
+            VkExport::decode(&other),
+            Err(VkFormatError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            VkExport::decode(&bytes[..bytes.len() - 1]),
+            Err(VkFormatError::Truncated)
+        ));
+        other = bytes.clone();
+        other.push(0);
+        assert!(matches!(
+            VkExport::decode(&other),
+            Err(VkFormatError::TrailingBytes(1))
+        ));
+        assert!(matches!(
+            export.verifying_key::<PreimageCircuit<Fr>>(),
+            Err(VkFormatError::Key(_))
+        ));
+    }
+}
@@ -0,0 +1,543 @@
+//! A verifiable random function over Grumpkin with the Poseidon sponge, verified in-circuit.
+//!
+//! The keys are those of [`crate::schnorr`]: a secret scalar `x` and `P = x * G`. The VRF of
+//! an input `m` hashes `(P, m)` to a point `H`, see [`hash_to_point`], and its output is the
+//! hash of `Γ = x * H`, which only the holder of `x` computes but anyone checks with the
+//! proof `(Γ, U, V, s)`: `U = k * G` and `V = k * H` for a random `k`, `s = k + c * x` and the
+//! challenge `c = Poseidon(P, H, Γ, U, V)`, so that `s * G = U + c * P` and
+//! `s * H = V + c * Γ` show that `Γ` is `H` to the same power as `P` is `G`. Every hash is
+//! the sponge of this crate, under a domain of its own.
+//!
+//! Proofs depend on `k`, outputs do not: a key has one output per input. [`VrfChip`]
+//! verifies an evaluation against the commitment [`commit_key`] to the public key rather
+//! than the key itself, so leader election and randomness beacon circuits expose who was
+//! elected without the key, and bind the output to a key registered earlier.
+use std::fmt;
+
+use ff::{Field, PrimeField};
+use halo2_proofs::{circuit::Value, plonk::Error};
+use halo2curves::bn256::{Fq, Fr};
+use poseidon::Spec;
+use rand_core::RngCore;
+
+use crate::{
+    main_gate::{AssignedValue, MainGateConfig, RegionCtx},
+    poseidon_circuit::PoseidonChip,
+    poseidon_hash::hash_with_domain,
+    range_chip::RangeChip,
+    schnorr::{
+        add, mul, to_scalar, AssignedPoint, Point, SchnorrChip, SigningKey, HALF_BITS, SCALAR_BITS,
+    },
+    specs::Domain,
+};
+
+/// `2^128 - 6`: commitments to public keys, below `ivc::CHAIN_DOMAIN`.
+pub const VRF_KEY_DOMAIN: Domain = Domain::Constant(u128::MAX - 5);
+
+/// `2^128 - 7`: the first candidate `x` of [`hash_to_point`].
+pub const VRF_POINT_DOMAIN: Domain = Domain::Constant(u128::MAX - 6);
+
+/// `2^128 - 8`: challenges of proofs.
+pub const VRF_CHALLENGE_DOMAIN: Domain = Domain::Constant(u128::MAX - 7);
+
+/// `2^128 - 9`: outputs.
+pub const VRF_OUTPUT_DOMAIN: Domain = Domain::Constant(u128::MAX - 8);
+
+/// Candidates [`hash_to_point`] tries; half of them are on the curve, so all of them miss
+/// for one input in about `2^32`.
+pub const MAX_TRIES: usize = 32;
+
+/// A quadratic non-residue: `n * y` is a square exactly when `y` is not.
+fn non_residue() -> Fr {
+    Fr::MULTIPLICATIVE_GENERATOR
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VrfError {
+    /// None of the [`MAX_TRIES`] candidates of the input is on the curve
+    NoPoint,
+}
+
+impl fmt::Display for VrfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoPoint => write!(f, "the input hashes to no point in {MAX_TRIES} tries"),
+        }
+    }
+}
+
+impl std::error::Error for VrfError {}
+
+/// Proof that an output is the VRF of an input under a public key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VrfProof {
+    pub gamma: Point,
+    pub u: Point,
+    pub v: Point,
+    pub s: Fq,
+}
+
+/// The commitment to `public_key` that [`VrfChip::verify`] exposes.
+pub fn commit_key<const T: usize, const RATE: usize>(
+    spec: &Spec<Fr, T, RATE>,
+    public_key: &Point,
+) -> Fr {
+    hash_with_domain(spec, VRF_KEY_DOMAIN, &[public_key.x, public_key.y])
+}
+
+/// The point `(h + i, y)` of even `y` for the least `i` below [`MAX_TRIES`] that is on the
+/// curve, where `h` hashes `public_key` and `input`; returns the point and `i`.
+pub fn hash_to_point<const T: usize, const RATE: usize>(
+    spec: &Spec<Fr, T, RATE>,
+    public_key: &Point,
+    input: Fr,
+) -> Result<(Point, usize), VrfError> {
+    let h = hash_with_domain(spec, VRF_POINT_DOMAIN, &[public_key.x, public_key.y, input]);
+    (0..MAX_TRIES)
+        .find_map(|i| Point::with_even_y(h + Fr::from(i as u64)).map(|point| (point, i)))
+        .ok_or(VrfError::NoPoint)
+}
+
+fn challenge<const T: usize, const RATE: usize>(
+    spec: &Spec<Fr, T, RATE>,
+    public_key: &Point,
+    h: &Point,
+    proof: &VrfProof,
+) -> Fr {
+    let points = [public_key, h, &proof.gamma, &proof.u, &proof.v];
+    let elements = points.iter().flat_map(|p| [p.x, p.y]).collect::<Vec<_>>();
+    hash_with_domain(spec, VRF_CHALLENGE_DOMAIN, &elements)
+}
+
+fn output_of<const T: usize, const RATE: usize>(spec: &Spec<Fr, T, RATE>, gamma: &Point) -> Fr {
+    hash_with_domain(spec, VRF_OUTPUT_DOMAIN, &[gamma.x])
+}
+
+/// The output of `key` on `input` and its proof, with a nonce drawn from `rng`, which must
+/// never repeat.
+pub fn evaluate<const T: usize, const RATE: usize>(
+    spec: &Spec<Fr, T, RATE>,
+    key: &SigningKey,
+    input: Fr,
+    rng: impl RngCore,
+) -> Result<(Fr, VrfProof), VrfError> {
+    let public_key = key.public_key();
+    let (h, _) = hash_to_point(spec, &public_key, input)?;
+    let gamma = mul(h, &key.0).expect("the key is not zero");
+    let k = Fq::random(rng);
+    let mut proof = VrfProof {
+        gamma,
+        u: mul(Point::generator(), &k).expect("the nonce is not zero"),
+        v: mul(h, &k).expect("the nonce is not zero"),
+        s: Fq::ZERO,
+    };
+    let c = challenge(spec, &public_key, &h, &proof);
+    proof.s = k + to_scalar(c) * key.0;
+    Ok((output_of(spec, &gamma), proof))
+}
+
+/// Checks natively that `output` is the VRF of `input` under `public_key`.
+pub fn verify<const T: usize, const RATE: usize>(
+    spec: &Spec<Fr, T, RATE>,
+    public_key: &Point,
+    input: Fr,
+    output: Fr,
+    proof: &VrfProof,
+) -> bool {
+    let Ok((h, _)) = hash_to_point(spec, public_key, input) else {
+        return false;
+    };
+    let c = to_scalar(challenge(spec, public_key, &h, proof));
+    let on_curve = [public_key, &proof.gamma, &proof.u, &proof.v]
+        .iter()
+        .all(|p| p.is_on_curve());
+    on_curve
+        && mul(Point::generator(), &proof.s) == add(Some(proof.u), mul(*public_key, &c))
+        && mul(h, &proof.s) == add(Some(proof.v), mul(proof.gamma, &c))
+        && output == output_of(spec, &proof.gamma)
+}
+
+/// The cells of a verified evaluation, for the caller to expose or constrain.
+#[derive(Clone, Debug)]
+pub struct AssignedEvaluation {
+    pub key_commitment: AssignedValue<Fr>,
+    pub input: AssignedValue<Fr>,
+    pub output: AssignedValue<Fr>,
+}
+
+/// Verifies VRF evaluations with the curve arithmetic of [`SchnorrChip`].
+pub struct VrfChip<const T: usize, const RATE: usize> {
+    ecc: SchnorrChip<T, RATE>,
+    config: MainGateConfig<T>,
+    spec: Spec<Fr, T, RATE>,
+}
+
+impl<const T: usize, const RATE: usize> VrfChip<T, RATE> {
+    pub fn new(config: MainGateConfig<T>, spec: Spec<Fr, T, RATE>) -> Self {
+        Self {
+            ecc: SchnorrChip::new(config.clone(), spec.clone()),
+            config,
+            spec,
+        }
+    }
+
+    /// Rows used by [`VrfChip::verify`].
+    pub fn num_rows(spec: &Spec<Fr, T, RATE>) -> usize {
+        // five points witnessed and checked on the curve, the generator and the offset,
+        // the commitment, the candidate, the challenge and the output, ten rows per
+        // candidate, two scalars decomposed, the challenge recomposed and checked to be
+        // canonical, four multiplications and two additions
+        5 * (2 + 3)
+            + 2 * 2
+            + PoseidonChip::num_rows(spec, 2)
+            + PoseidonChip::num_rows(spec, 3)
+            + PoseidonChip::num_rows(spec, 10)
+            + PoseidonChip::num_rows(spec, 1)
+            + 10 * MAX_TRIES
+            + 2 * (2 + 2 * RangeChip::<Fr, T>::num_rows(HALF_BITS))
+            + 1
+            + SchnorrChip::<T, RATE>::canonical_rows()
+            + 4 * SCALAR_BITS * (6 + 8 + 4)
+            + 2 * 8
+    }
+
+    /// Hashes `inputs` under `domain`, constraining the absorbed cells to equal `cells`;
+    /// returns the absorbed cells and the digest.
+    fn hash(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        domain: Domain,
+        inputs: Vec<Fr>,
+        cells: &[&AssignedValue<Fr>],
+    ) -> Result<(Vec<AssignedValue<Fr>>, AssignedValue<Fr>), Error> {
+        let mut pchip =
+            PoseidonChip::new(self.config.clone(), self.spec.clone()).with_domain(domain);
+        pchip.update(inputs);
+        let (absorbed, digest) = pchip.squeeze_with_inputs(ctx)?;
+        for (absorbed, cell) in absorbed.iter().zip(cells) {
+            ctx.constrain_equal(absorbed.cell(), cell.cell())?;
+        }
+        Ok((absorbed, digest))
+    }
+
+    /// Constrains `x` to be `h + i` for the least candidate `i` whose `x^3 - 17` is a
+    /// square: a flag per candidate, set for the candidates before `x` and then cleared,
+    /// witnesses a square root of `n * (x^3 - 17)` for every set flag, which exists only
+    /// for candidates off the curve.
+    fn first_candidate(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        h: &AssignedValue<Fr>,
+        tries: usize,
+        x: &AssignedValue<Fr>,
+    ) -> Result<(), Error> {
+        let ecc = &self.ecc;
+        let n = non_residue();
+        let mut acc = h.clone();
+        let mut prev: Option<AssignedValue<Fr>> = None;
+        for i in 0..MAX_TRIES {
+            let offset = Fr::from(i as u64);
+            let xi = ecc.gate(
+                ctx,
+                Fr::ZERO,
+                vec![(Fr::ONE, h.into())],
+                offset,
+                SchnorrChip::<T, RATE>::value(h) + Value::known(offset),
+            )?;
+            let xx = ecc.gate(
+                ctx,
+                Fr::ONE,
+                vec![(Fr::ZERO, (&xi).into()), (Fr::ZERO, (&xi).into())],
+                Fr::ZERO,
+                SchnorrChip::<T, RATE>::value(&xi).map(|x| x.square()),
+            )?;
+            let xxx = ecc.gate(
+                ctx,
+                Fr::ONE,
+                vec![(Fr::ZERO, (&xx).into()), (Fr::ZERO, (&xi).into())],
+                Fr::ZERO,
+                SchnorrChip::<T, RATE>::value(&xx) * SchnorrChip::<T, RATE>::value(&xi),
+            )?;
+
+            let set = i < tries;
+            let flag = ecc.witness(ctx, Value::known(Fr::from(set as u64)))?;
+            // flag * flag - flag = 0
+            ecc.assert_zero(
+                ctx,
+                Fr::ONE,
+                vec![(-Fr::ONE, (&flag).into()), (Fr::ZERO, (&flag).into())],
+                Fr::ZERO,
+            )?;
+            if let Some(prev) = &prev {
+                // a flag is only set after a set one: flag - flag * prev = 0
+                ecc.assert_zero(
+                    ctx,
+                    -Fr::ONE,
+                    vec![(Fr::ONE, (&flag).into()), (Fr::ZERO, prev.into())],
+                    Fr::ZERO,
+                )?;
+            }
+
+            // flag * xxx - 17 * flag, the curve equation at xi if the flag is set
+            let rhs = ecc.gate(
+                ctx,
+                Fr::ONE,
+                vec![
+                    (crate::schnorr::b(), (&flag).into()),
+                    (Fr::ZERO, (&xxx).into()),
+                ],
+                Fr::ZERO,
+                SchnorrChip::<T, RATE>::value(&flag)
+                    * (SchnorrChip::<T, RATE>::value(&xxx) + Value::known(crate::schnorr::b())),
+            )?;
+            let root = SchnorrChip::<T, RATE>::value(&rhs).map(|rhs| {
+                Option::from((rhs * n).sqrt()).expect("candidates before the point are off it")
+            });
+            let root = ecc.witness(ctx, root)?;
+            // root * root - n * rhs = 0
+            ecc.assert_zero(
+                ctx,
+                Fr::ONE,
+                vec![
+                    (Fr::ZERO, (&root).into()),
+                    (Fr::ZERO, (&root).into()),
+                    (-n, rhs.into()),
+                ],
+                Fr::ZERO,
+            )?;
+
+            acc = ecc.gate(
+                ctx,
+                Fr::ZERO,
+                vec![(Fr::ONE, (&acc).into()), (Fr::ONE, (&flag).into())],
+                Fr::ZERO,
+                SchnorrChip::<T, RATE>::value(&acc) + SchnorrChip::<T, RATE>::value(&flag),
+            )?;
+            prev = Some(flag);
+        }
+        ctx.constrain_equal(acc.cell(), x.cell())
+    }
+
+    /// Constrains `output` to be the VRF of `input` under `public_key`, as [`verify`]
+    /// checks.
+    ///
+    /// # Panics
+    ///
+    /// If `input` hashes to no point, which [`evaluate`] reports.
+    pub fn verify(
+        &self,
+        ctx: &mut RegionCtx<'_, Fr>,
+        public_key: &Point,
+        input: Fr,
+        proof: &VrfProof,
+    ) -> Result<AssignedEvaluation, Error> {
+        let ecc = &self.ecc;
+        let (h_val, tries) =
+            hash_to_point(&self.spec, public_key, input).expect("the input hashes to a point");
+        let pk = ecc.assign_point(ctx, public_key)?;
+        let h = ecc.assign_point(ctx, &h_val)?;
+        let gamma = ecc.assign_point(ctx, &proof.gamma)?;
+        let u = ecc.assign_point(ctx, &proof.u)?;
+        let v = ecc.assign_point(ctx, &proof.v)?;
+        let generator = ecc.constant_point(ctx, &Point::generator())?;
+        let offset = ecc.constant_point(ctx, &Point::offset())?;
+
+        let (_, key_commitment) = self.hash(
+            ctx,
+            VRF_KEY_DOMAIN,
+            vec![public_key.x, public_key.y],
+            &[&pk.x, &pk.y],
+        )?;
+        let (absorbed, first) = self.hash(
+            ctx,
+            VRF_POINT_DOMAIN,
+            vec![public_key.x, public_key.y, input],
+            &[&pk.x, &pk.y],
+        )?;
+        self.first_candidate(ctx, &first, tries, &h.x)?;
+
+        let points = [&pk, &h, &gamma, &u, &v];
+        let c_val = challenge(&self.spec, public_key, &h_val, proof);
+        let (_, c) = self.hash(
+            ctx,
+            VRF_CHALLENGE_DOMAIN,
+            [public_key, &h_val, &proof.gamma, &proof.u, &proof.v]
+                .iter()
+                .flat_map(|p| [p.x, p.y])
+                .collect(),
+            &points.iter().flat_map(|p| [&p.x, &p.y]).collect::<Vec<_>>(),
+        )?;
+
+        let (s_bits, _) = ecc.decompose(ctx, proof.s.to_repr())?;
+        let (c_bits, c_halves) = ecc.decompose(ctx, c_val.to_repr())?;
+        // lo + 2^127 * hi - c = 0, with lo + 2^127 * hi < r as for the Schnorr challenge
+        let [lo, hi] = &c_halves;
+        ecc.assert_zero(
+            ctx,
+            Fr::ZERO,
+            vec![
+                (Fr::ONE, lo.into()),
+                (Fr::from_u128(1 << HALF_BITS), hi.into()),
+                (-Fr::ONE, c.into()),
+            ],
+            Fr::ZERO,
+        )?;
+        ecc.assert_canonical(ctx, &c_halves)?;
+
+        // s * G = U + c * P and s * H = V + c * Γ, each side offset by the same multiple of
+        // the offset point
+        let equal = |ctx: &mut RegionCtx<'_, Fr>,
+                     base: &AssignedPoint,
+                     nonce: &AssignedPoint,
+                     power: &AssignedPoint|
+         -> Result<(), Error> {
+            let lhs = ecc.mul(ctx, &offset, &s_bits, base)?;
+            let rhs = ecc.mul(ctx, &offset, &c_bits, power)?;
+            let rhs = ecc.add(ctx, &rhs, nonce)?;
+            ctx.constrain_equal(lhs.x.cell(), rhs.x.cell())?;
+            ctx.constrain_equal(lhs.y.cell(), rhs.y.cell())
+        };
+        equal(ctx, &generator, &u, &pk)?;
+        equal(ctx, &h, &v, &gamma)?;
+
+        let (_, output) = self.hash(ctx, VRF_OUTPUT_DOMAIN, vec![proof.gamma.x], &[&gamma.x])?;
+        Ok(AssignedEvaluation {
+            key_commitment,
+            input: absorbed[2].clone(),
+            output,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+    };
+    use rand_core::OsRng;
+
+    use super::*;
+    use crate::main_gate::MainGate;
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+    const K: u32 = 15;
+
+    fn spec() -> Spec<Fr, T, RATE> {
+        Spec::new(8, 56)
+    }
+
+    /// Verifies a private evaluation and exposes `[key commitment, input, output]`.
+    struct VrfCircuit {
+        public_key: Point,
+        input: Fr,
+        proof: VrfProof,
+    }
+
+    impl Circuit<Fr> for VrfCircuit {
+        type Config = (MainGateConfig<T>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                public_key: self.public_key,
+                input: self.input,
+                proof: self.proof,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let config = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            (config, instance)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let chip = VrfChip::new(config, spec());
+            let verified = layouter.assign_region(
+                || "vrf",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    chip.verify(ctx, &self.public_key, self.input, &self.proof)
+                },
+            )?;
+            let public = [verified.key_commitment, verified.input, verified.output];
+            for (i, cell) in public.iter().enumerate() {
+                layouter.constrain_instance(cell.cell(), instance, i)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let spec = spec();
+        assert!(bool::from(non_residue().sqrt().is_none()));
+        let key = SigningKey::random(OsRng);
+        let public_key = key.public_key();
+        let input = Fr::from(42);
+        let (output, proof) = evaluate(&spec, &key, input, OsRng).unwrap();
+        assert!(verify(&spec, &public_key, input, output, &proof));
+
+        // another nonce proves the same output
+        let (again, other) = evaluate(&spec, &key, input, OsRng).unwrap();
+        assert_eq!(again, output);
+        assert_ne!(other, proof);
+        assert!(verify(&spec, &public_key, input, output, &other));
+
+        assert!(!verify(&spec, &public_key, input, output + Fr::ONE, &proof));
+        assert!(!verify(&spec, &public_key, input + Fr::ONE, output, &proof));
+        let other_key = SigningKey::random(OsRng).public_key();
+        assert!(!verify(&spec, &other_key, input, output, &proof));
+        let forged = VrfProof {
+            s: proof.s + Fq::ONE,
+            ..proof
+        };
+        assert!(!verify(&spec, &public_key, input, output, &forged));
+        let (other_output, _) = evaluate(&spec, &key, input + Fr::ONE, OsRng).unwrap();
+        assert_ne!(other_output, output);
+    }
+
+    #[test]
+    fn test_verify_in_circuit() {
+        let spec = spec();
+        assert!(VrfChip::num_rows(&spec) + 6 <= 1 << K);
+        let key = SigningKey::random(OsRng);
+        let public_key = key.public_key();
+        // an input whose point is not the first candidate, so flags are set
+        let input = (0..)
+            .map(Fr::from)
+            .find(|input| hash_to_point(&spec, &public_key, *input).unwrap().1 > 0)
+            .unwrap();
+        let (output, proof) = evaluate(&spec, &key, input, OsRng).unwrap();
+        let circuit = VrfCircuit {
+            public_key,
+            input,
+            proof,
+        };
+        let public = vec![commit_key(&spec, &public_key), input, output];
+        let prover = MockProver::run(K, &circuit, vec![public.clone()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // an evaluation under another key does not open the commitment
+        let other = SigningKey::random(OsRng);
+        let (other_output, proof) = evaluate(&spec, &other, input, OsRng).unwrap();
+        let circuit = VrfCircuit {
+            public_key: other.public_key(),
+            input,
+            proof,
+        };
+        let forged = vec![public[0], input, other_output];
+        let prover = MockProver::run(K, &circuit, vec![forged]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
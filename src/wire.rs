@@ -0,0 +1,242 @@
+//! Binary encodings of tasks and proof details, behind the `cbor` and `msgpack` features.
+//!
+//! JSON stays the default. In the binary formats the Base64 proof fields of
+//! [`crate::task::ProofDetail`] travel as raw byte strings, so a batch proof crossing the
+//! queue is not inflated by Base64; decoding turns them back into Base64, and the structs
+//! are the same whatever the format.
+//!
+//! The snarkify service itself speaks JSON through the SDK; these encodings are for queue
+//! producers and consumers that pick the format by configuration or by content type.
+use std::{fmt, str::FromStr};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::task::{ParseMode, Task, TaskParseError, MAX_TASK_BYTES};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    Cbor,
+    MessagePack,
+}
+
+impl WireFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Cbor => "application/cbor",
+            Self::MessagePack => "application/msgpack",
+        }
+    }
+
+    /// The format of a `Content-Type` or `Accept` value, ignoring parameters such as
+    /// `charset`.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        match essence.to_ascii_lowercase().as_str() {
+            "application/json" => Some(Self::Json),
+            "application/cbor" => Some(Self::Cbor),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Self::MessagePack)
+            }
+            _ => None,
+        }
+    }
+
+    /// Serializes `value`; structs are encoded as maps with field names in every format.
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, WireError> {
+        match self {
+            Self::Json => {
+                serde_json::to_vec(value).map_err(|err| WireError::Encode(err.to_string()))
+            }
+            #[cfg(feature = "cbor")]
+            Self::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::ser::into_writer(value, &mut bytes)
+                    .map_err(|err| WireError::Encode(err.to_string()))?;
+                Ok(bytes)
+            }
+            #[cfg(not(feature = "cbor"))]
+            Self::Cbor => Err(WireError::Disabled("cbor")),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => {
+                rmp_serde::to_vec_named(value).map_err(|err| WireError::Encode(err.to_string()))
+            }
+            #[cfg(not(feature = "msgpack"))]
+            Self::MessagePack => Err(WireError::Disabled("msgpack")),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, WireError> {
+        match self {
+            Self::Json => {
+                serde_json::from_slice(bytes).map_err(|err| WireError::Decode(err.to_string()))
+            }
+            #[cfg(feature = "cbor")]
+            Self::Cbor => {
+                ciborium::de::from_reader(bytes).map_err(|err| WireError::Decode(err.to_string()))
+            }
+            #[cfg(not(feature = "cbor"))]
+            Self::Cbor => Err(WireError::Disabled("cbor")),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => {
+                rmp_serde::from_slice(bytes).map_err(|err| WireError::Decode(err.to_string()))
+            }
+            #[cfg(not(feature = "msgpack"))]
+            Self::MessagePack => Err(WireError::Disabled("msgpack")),
+        }
+    }
+
+    /// Parses a task like [`Task::from_json_with`], from bytes in this format.
+    pub fn decode_task(&self, bytes: &[u8], mode: ParseMode) -> Result<Task, WireError> {
+        match self {
+            Self::Json => Task::from_json_with(bytes, mode).map_err(WireError::Task),
+            _ => {
+                if bytes.len() > MAX_TASK_BYTES {
+                    return Err(WireError::Task(TaskParseError::TooLarge {
+                        len: bytes.len(),
+                        max: MAX_TASK_BYTES,
+                    }));
+                }
+                // through a JSON value, for strict mode to see the field names
+                Task::from_value(self.decode(bytes)?, mode).map_err(WireError::Task)
+            }
+        }
+    }
+}
+
+impl FromStr for WireFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "cbor" => Ok(Self::Cbor),
+            "msgpack" => Ok(Self::MessagePack),
+            _ => Err(format!(
+                "unknown wire format {s:?}, expected json, cbor or msgpack"
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    Encode(String),
+    Decode(String),
+    Task(TaskParseError),
+    /// The binary was built without the feature of this format
+    Disabled(&'static str),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Encode(err) => write!(f, "cannot encode: {err}"),
+            Self::Decode(err) => write!(f, "cannot decode: {err}"),
+            Self::Task(err) => write!(f, "{err}"),
+            Self::Disabled(feature) => write!(f, "built without the `{feature}` feature"),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        schema::SCHEMA_VERSION,
+        task::{EvmProof, ProofDetail},
+    };
+
+    fn detail() -> ProofDetail {
+        ProofDetail {
+            id: "7".to_string(),
+            proof_data: "AAECAwQ=".to_string(),
+            instances: vec!["0x01".to_string()],
+            evm: Some(EvmProof {
+                transcript: "keccak256".to_string(),
+                proof: "qw==".to_string(),
+                calldata: "0xab".to_string(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_content_types() {
+        for format in [WireFormat::Json, WireFormat::Cbor, WireFormat::MessagePack] {
+            assert_eq!(
+                WireFormat::from_content_type(format.content_type()),
+                Some(format)
+            );
+        }
+        assert_eq!(
+            WireFormat::from_content_type("application/json; charset=utf-8"),
+            Some(WireFormat::Json)
+        );
+        assert_eq!(WireFormat::from_content_type("text/plain"), None);
+        assert_eq!("msgpack".parse(), Ok(WireFormat::MessagePack));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let bytes = WireFormat::Json.encode(&detail()).unwrap();
+        assert!(String::from_utf8_lossy(&bytes).contains("\"AAECAwQ=\""));
+        assert_eq!(
+            WireFormat::Json.decode::<ProofDetail>(&bytes).unwrap(),
+            detail()
+        );
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_cbor_round_trip() {
+        let bytes = WireFormat::Cbor.encode(&detail()).unwrap();
+        // the proof is a raw byte string, not its Base64
+        assert!(bytes.windows(5).any(|w| w == [0, 1, 2, 3, 4]));
+        assert_eq!(
+            WireFormat::Cbor.decode::<ProofDetail>(&bytes).unwrap(),
+            detail()
+        );
+
+        let task = Task {
+            id: "7".to_string(),
+            task_data: "[1,2]".to_string(),
+            // stamped on every parsed task
+            schema_version: Some(SCHEMA_VERSION),
+            ..Default::default()
+        };
+        let bytes = WireFormat::Cbor.encode(&task).unwrap();
+        assert_eq!(
+            WireFormat::Cbor
+                .decode_task(&bytes, ParseMode::Strict)
+                .unwrap(),
+            task
+        );
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_round_trip() {
+        let bytes = WireFormat::MessagePack.encode(&detail()).unwrap();
+        assert!(bytes.windows(5).any(|w| w == [0, 1, 2, 3, 4]));
+        assert_eq!(
+            WireFormat::MessagePack
+                .decode::<ProofDetail>(&bytes)
+                .unwrap(),
+            detail()
+        );
+    }
+
+    #[cfg(not(feature = "cbor"))]
+    #[test]
+    fn test_disabled_format() {
+        assert!(matches!(
+            WireFormat::Cbor.encode(&detail()),
+            Err(WireError::Disabled("cbor"))
+        ));
+    }
+}
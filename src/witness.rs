@@ -0,0 +1,129 @@
+use std::fmt;
+
+use ff::{FromUniformBytes, PrimeField};
+use poseidon::Spec;
+
+use crate::{field_encoding::to_canonical, poseidon_hash::hash};
+
+/// A message whose claimed digest differs from its native Poseidon hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestMismatch<F> {
+    /// Position of the message in the task
+    pub index: usize,
+    pub expected: F,
+    pub actual: F,
+}
+
+/// An input error found before synthesis, which would otherwise surface as an unsatisfied
+/// constraint deep inside the prover.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitnessError<F> {
+    DigestCountMismatch {
+        messages: usize,
+        digests: usize,
+    },
+    /// Every message whose expected digest is wrong, in order
+    DigestMismatch(Vec<DigestMismatch<F>>),
+}
+
+impl<F: PrimeField> fmt::Display for WitnessError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DigestCountMismatch { messages, digests } => write!(
+                f,
+                "{messages} messages but {digests} expected digests were provided"
+            ),
+            Self::DigestMismatch(mismatches) => {
+                write!(f, "expected digests do not match their messages:")?;
+                for m in mismatches {
+                    write!(
+                        f,
+                        " message {} hashes to {}, not {};",
+                        m.index,
+                        to_canonical(&m.actual),
+                        to_canonical(&m.expected)
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<F: PrimeField> std::error::Error for WitnessError<F> {}
+
+/// Recomputes every digest natively and reports all messages whose expected digest is wrong.
+///
+/// Meant to run before proving: a wrong digest otherwise only shows up as a failed
+/// constraint with no hint of which message caused it.
+pub fn check_digests<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>(
+    spec: &Spec<F, T, RATE>,
+    messages: &[Vec<F>],
+    expected: &[F],
+) -> Result<(), WitnessError<F>> {
+    if messages.len() != expected.len() {
+        return Err(WitnessError::DigestCountMismatch {
+            messages: messages.len(),
+            digests: expected.len(),
+        });
+    }
+    let mismatches = messages
+        .iter()
+        .zip(expected)
+        .enumerate()
+        .filter_map(|(index, (msg, expected))| {
+            let actual = hash(spec, msg);
+            (actual != *expected).then_some(DigestMismatch {
+                index,
+                expected: *expected,
+                actual,
+            })
+        })
+        .collect::<Vec<_>>();
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(WitnessError::DigestMismatch(mismatches))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+
+    const T: usize = 4;
+    const RATE: usize = 3;
+
+    #[test]
+    fn test_check_digests() {
+        let spec = Spec::<Fr, T, RATE>::new(8, 56);
+        let messages = (1..4u64)
+            .map(|n| (0..n).map(Fr::from).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let mut expected = messages
+            .iter()
+            .map(|msg| hash(&spec, msg))
+            .collect::<Vec<_>>();
+        assert_eq!(check_digests(&spec, &messages, &expected), Ok(()));
+
+        expected[1] += Fr::from(1);
+        match check_digests(&spec, &messages, &expected) {
+            Err(WitnessError::DigestMismatch(mismatches)) => {
+                assert_eq!(mismatches.len(), 1);
+                assert_eq!(mismatches[0].index, 1);
+                assert_eq!(mismatches[0].expected, expected[1]);
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        assert_eq!(
+            check_digests(&spec, &messages, &expected[..2]),
+            Err(WitnessError::DigestCountMismatch {
+                messages: 3,
+                digests: 2
+            })
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! How much computing the witness of many hashes on the rayon pool saves, to decide whether
+//! to build with `parallel-witness`.
+//!
+//! [`benchmark`] computes the [`PoseidonChip::trace`] of `hashes` messages of `len` elements
+//! with the bn256 spec of the service, once serially and once on the rayon pool, as
+//! [`PoseidonChip::hash_many`] does without and with the feature. The assignment that follows
+//! is the same either way and is not measured.
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use halo2_proofs::plonk::ConstraintSystem;
+use halo2curves::bn256::Fr;
+use serde::Serialize;
+
+use crate::{main_gate::MainGate, poseidon_circuit::PoseidonChip, specs::vetted_spec};
+
+/// Serial and parallel witness times of one batch.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct WitnessBenchmark {
+    pub hashes: usize,
+    /// Elements in every message
+    pub len: usize,
+    /// Threads of the rayon pool
+    pub threads: usize,
+    /// Whether this build computes the witness of `hash_many` in parallel
+    pub parallel_witness: bool,
+    pub serial: Duration,
+    pub parallel: Duration,
+}
+
+impl WitnessBenchmark {
+    /// How many times faster the parallel witness is.
+    pub fn speedup(&self) -> f64 {
+        self.serial.as_secs_f64() / self.parallel.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("benchmarks serialize")
+    }
+}
+
+impl fmt::Display for WitnessBenchmark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} hashes of {} elements on {} threads{}",
+            self.hashes,
+            self.len,
+            self.threads,
+            if self.parallel_witness {
+                ", parallel-witness enabled"
+            } else {
+                ""
+            }
+        )?;
+        writeln!(
+            f,
+            "serial: {:.1?}, parallel: {:.1?}, speedup: {:.2}x",
+            self.serial,
+            self.parallel,
+            self.speedup()
+        )
+    }
+}
+
+/// Measures the witness of `hashes` distinct messages of `len` elements.
+pub fn benchmark(hashes: usize, len: usize) -> WitnessBenchmark {
+    let spec = vetted_spec::<Fr, 4, 3>().expect("the spec of the service is vetted");
+    let mut cs = ConstraintSystem::<Fr>::default();
+    let mut advice = [(); 6].map(|_| cs.advice_column()).into_iter();
+    let mut fixed = [(); 12].map(|_| cs.fixed_column()).into_iter();
+    let config = MainGate::configure(&mut cs, &mut advice, &mut fixed);
+    let chip = PoseidonChip::new(config, spec);
+    let messages = (0..hashes)
+        .map(|i| (0..len).map(|j| Fr::from((i * len + j) as u64)).collect())
+        .collect::<Vec<Vec<_>>>();
+
+    let start = Instant::now();
+    let serial = chip.traces(&messages, false);
+    let serial_time = start.elapsed();
+    let start = Instant::now();
+    let parallel = chip.traces(&messages, true);
+    let parallel_time = start.elapsed();
+    debug_assert_eq!(serial, parallel);
+
+    WitnessBenchmark {
+        hashes,
+        len,
+        threads: rayon::current_num_threads(),
+        parallel_witness: cfg!(feature = "parallel-witness"),
+        serial: serial_time,
+        parallel: parallel_time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_benchmark() {
+        let result = benchmark(64, 5);
+        assert_eq!((result.hashes, result.len), (64, 5));
+        assert!(result.threads >= 1);
+        assert!(result.speedup() > 0.0);
+        assert!(result.to_string().contains("speedup"));
+        assert!(result.to_json().contains("\"parallel\""));
+    }
+}
@@ -0,0 +1,222 @@
+//! Native hashing and proof verification for browsers and Node, without the prover.
+//!
+//! Built with `wasm-pack build --release --target web` (or `--target nodejs`), the package
+//! exports:
+//!
+//! - `hash(inputs)` and `hashWithTag(tag, inputs)`, the digests the circuits of the service
+//!   compute, with the same spec and in the encoding of [`poseidon_circuit::field_encoding`],
+//!   for commitments made client-side;
+//! - `loadParams(bytes)` registers params written by `ParamsKZG::write`, downsized to the
+//!   `k` of the circuits to check, and returns their digest;
+//! - `verify(vkBytes, paramsDigest, proofB64, instances, transcript, protocolLabel,
+//!   deployment)` checks the Base64 `proof_data` and the `instances` of a
+//!   [`poseidon_circuit::task::ProofDetail`] against a verifying key written with
+//!   `SerdeFormat::RawBytes`, in the `transcript` and under the `protocol_label` of the
+//!   detail, and salted with the name of the deployment if it has one.
+//!
+//! The deployment name is a secret of the deployment: front-ends without it can only check
+//! the proofs of unsalted deployments.
+use std::{cell::RefCell, collections::HashMap};
+
+use base64::{engine::general_purpose::STANDARD as BS64, Engine};
+use poseidon_circuit::{
+    field_encoding::{parse_fields, to_canonical},
+    halo2_proofs::{
+        plonk::{Circuit, VerifyingKey},
+        poly::{commitment::Params, kzg::commitment::ParamsKZG},
+        SerdeFormat,
+    },
+    halo2curves::bn256::{Bn256, Fr, G1Affine},
+    membership::MembershipCircuit,
+    poseidon_hash::hash_with_domain,
+    preimage::PreimageCircuit,
+    prelude::Spec,
+    prover::{deployment_salt, read_vk, verify_detached_in, TranscriptHash},
+    range_proof::RangeProofCircuit,
+    specs::{vetted_spec, Domain},
+    test_circuit::TestCircuit,
+};
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    /// Params registered by [`load_params`], by digest; wasm runs on a single thread.
+    static PARAMS: RefCell<HashMap<String, ParamsKZG<Bn256>>> = RefCell::new(HashMap::new());
+    /// The spec of the service, built once since its constants take a while to derive.
+    static SPEC: Spec<Fr, 4, 3> = vetted_spec().expect("the spec of the service is vetted");
+}
+
+/// The digest of `inputs` as the circuits of the service hash them, in the PSE domain.
+#[wasm_bindgen]
+pub fn hash(inputs: Vec<String>) -> Result<String, JsError> {
+    hash_in(Domain::Pse, &inputs)
+}
+
+/// The digest of `inputs` in the application domain of `tag`, see `Domain::User`.
+#[wasm_bindgen(js_name = hashWithTag)]
+pub fn hash_with_tag(tag: u64, inputs: Vec<String>) -> Result<String, JsError> {
+    hash_in(Domain::User(tag), &inputs)
+}
+
+fn hash_in(domain: Domain, inputs: &[String]) -> Result<String, JsError> {
+    let inputs = parse_fields::<Fr, _>(inputs).map_err(|err| JsError::new(&err.to_string()))?;
+    Ok(SPEC.with(|spec| to_canonical(&hash_with_domain(spec, domain, &inputs))))
+}
+
+/// Registers params and returns their digest, the hex Blake2b-256 of `bytes`.
+#[wasm_bindgen(js_name = loadParams)]
+pub fn load_params(bytes: &[u8]) -> Result<String, JsError> {
+    let params = ParamsKZG::<Bn256>::read(&mut &bytes[..])
+        .map_err(|err| JsError::new(&format!("malformed params: {err}")))?;
+    let digest = blake2b_simd::Params::new()
+        .hash_length(32)
+        .hash(bytes)
+        .to_hex()
+        .to_string();
+    PARAMS.with(|params_by_digest| params_by_digest.borrow_mut().insert(digest.clone(), params));
+    Ok(digest)
+}
+
+/// Whether `proof_b64` proves `instances` under the verifying key `vk_bytes`, with the
+/// params registered as `params_digest`.
+///
+/// `transcript` is the `transcript` of the proof detail, Blake2b if unset, and
+/// `protocol_label` its `protocol_label`, none if unset or empty. `deployment` is the name
+/// the prover derived its salt from, see `DEPLOYMENT_SALT`; unset for unsalted deployments.
+///
+/// Malformed arguments are errors rather than `false`, so a front-end can tell a broken
+/// integration from a forged proof.
+#[wasm_bindgen]
+pub fn verify(
+    vk_bytes: &[u8],
+    params_digest: &str,
+    proof_b64: &str,
+    instances: Vec<String>,
+    transcript: Option<String>,
+    protocol_label: Option<String>,
+    deployment: Option<String>,
+) -> Result<bool, JsError> {
+    let hash = match transcript.as_deref() {
+        Some(name) => name
+            .parse::<TranscriptHash>()
+            .map_err(|err| JsError::new(&err))?,
+        None => TranscriptHash::Blake2b,
+    };
+    let label = protocol_label.as_deref().filter(|label| !label.is_empty());
+    let salt = deployment.as_deref().map(deployment_salt);
+    let proof = BS64
+        .decode(proof_b64)
+        .map_err(|err| JsError::new(&format!("proof is not base64: {err}")))?;
+    let instances =
+        parse_fields::<Fr, _>(&instances).map_err(|err| JsError::new(&err.to_string()))?;
+    let vk = read_any_vk(vk_bytes)
+        .ok_or_else(|| JsError::new("not a verifying key of any circuit of the service"))?;
+    let params =
+        PARAMS.with(|params_by_digest| params_by_digest.borrow().get(params_digest).cloned());
+    let mut params =
+        params.ok_or_else(|| JsError::new(&format!("no params registered as {params_digest}")))?;
+    let k = vk.get_domain().k();
+    if params.k() < k {
+        return Err(JsError::new(&format!(
+            "params of 2^{} rows, the key needs 2^{k}",
+            params.k()
+        )));
+    }
+    params.downsize(k);
+    // every circuit of the service has a single instance column
+    let instances = [instances.as_slice()];
+    Ok(verify_detached_in(hash, &params, &vk, label, salt, &proof, &instances).is_ok())
+}
+
+/// Reads `bytes` as the verifying key of each circuit the service proves, keeping the one
+/// that serializes back to exactly `bytes`.
+///
+/// The constraint system is part of the transcript, so a key read with the wrong circuit
+/// could not verify the proofs of the right one either; the round trip rejects it earlier.
+fn read_any_vk(bytes: &[u8]) -> Option<VerifyingKey<G1Affine>> {
+    fn read_as<C: Circuit<Fr>>(bytes: &[u8]) -> Option<VerifyingKey<G1Affine>> {
+        read_vk::<C>(bytes)
+            .ok()
+            .filter(|vk| vk.to_bytes(SerdeFormat::RawBytes) == bytes)
+    }
+    read_as::<TestCircuit<Fr>>(bytes)
+        .or_else(|| read_as::<PreimageCircuit<Fr>>(bytes))
+        .or_else(|| read_as::<RangeProofCircuit<Fr>>(bytes))
+        .or_else(|| read_as::<MembershipCircuit<Fr>>(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use poseidon_circuit::{
+        field_encoding::to_canonical,
+        halo2curves::bn256::Fr,
+        poseidon_hash::hash,
+        prover::{deployment_salt, ProverContext, TranscriptHash},
+        test_circuit::TestCircuit,
+    };
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    /// Registers the params of `ctx` and verifies `proof` of `digest` against its key.
+    fn check(
+        ctx: &ProverContext<TestCircuit<Fr>>,
+        proof: &[u8],
+        digest: Fr,
+        transcript: Option<&str>,
+        label: Option<&str>,
+        deployment: Option<&str>,
+    ) -> bool {
+        let mut params = Vec::new();
+        ctx.params().write(&mut params).unwrap();
+        let params_digest = load_params(&params).unwrap_or_else(|_| panic!("params load"));
+        let vk = ctx.pk().get_vk().to_bytes(SerdeFormat::RawBytes);
+        verify(
+            &vk,
+            &params_digest,
+            &BS64.encode(proof),
+            vec![to_canonical(&digest)],
+            transcript.map(str::to_string),
+            label.map(str::to_string),
+            deployment.map(str::to_string),
+        )
+        .unwrap_or_else(|_| panic!("arguments are well-formed"))
+    }
+
+    #[wasm_bindgen_test]
+    fn test_verify_labelled() {
+        let inputs = vec![Fr::from(1); 2];
+        let circuit = TestCircuit::new(inputs.clone());
+        let digest = SPEC.with(|spec| hash(spec, &inputs));
+        let ctx = ProverContext::setup(8, &circuit)
+            .unwrap()
+            .with_protocol_label("acme-rollup/v1");
+        let proof = ctx.prove(&circuit, &[&[digest]]).unwrap();
+        assert!(check(
+            &ctx,
+            &proof,
+            digest,
+            None,
+            Some("acme-rollup/v1"),
+            None
+        ));
+        assert!(!check(&ctx, &proof, digest, None, None, None));
+        assert!(!check(&ctx, &proof, digest, None, Some("other/v1"), None));
+
+        // a salted deployment proving in the Keccak256 transcript
+        let ctx = ctx.with_salt(deployment_salt("staging"));
+        let proof = ctx
+            .prove_in(TranscriptHash::Keccak256, &circuit, &[&[digest]])
+            .unwrap();
+        let label = Some("acme-rollup/v1");
+        assert!(check(
+            &ctx,
+            &proof,
+            digest,
+            Some("keccak256"),
+            label,
+            Some("staging")
+        ));
+        assert!(!check(&ctx, &proof, digest, Some("keccak256"), label, None));
+        assert!(!check(&ctx, &proof, digest, None, label, Some("staging")));
+    }
+}